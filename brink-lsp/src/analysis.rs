@@ -0,0 +1,885 @@
+//! Answers the requests about a document from the results of the compiler
+//! stages, which the query database keeps up to date as the documents are
+//! edited.
+
+use std::{
+    cell::Ref,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use brinkc::{
+    ast::{self, node_id::NodeId, Visibility},
+    frontend::{
+        parse_session::{Diagnostic, ParseSession, Severity},
+        token::{TokenClass, TokenKind, KEYWORDS},
+    },
+    query::{Database, TokenizedFile},
+    resolve::{DefId, DefKind, ModuleGraph, ModuleId, ModuleSource, Res, Resolutions},
+    source_file::{FileId, SourceFile, SourceSpan},
+    typeck::{
+        ty::{Ty, TyPrinter},
+        AdtKind, TypeckResults,
+    },
+};
+
+use crate::{
+    index::{Node, NodeKind, SyntaxIndex},
+    json::Value,
+    uri,
+};
+
+/// The token types of the semantic tokens, indexed by `SemanticToken`.
+pub const TOKEN_TYPES: &[&str] = &[
+    "keyword",
+    "variable",
+    "function",
+    "type",
+    "typeParameter",
+    "enumMember",
+    "namespace",
+    "property",
+    "number",
+    "string",
+    "operator",
+    "decorator",
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum SemanticToken {
+    Keyword,
+    Variable,
+    Function,
+    Type,
+    TypeParameter,
+    EnumMember,
+    Namespace,
+    Property,
+    Number,
+    String,
+    Operator,
+    Decorator,
+}
+
+// The kinds of the protocol's `SymbolKind` and `CompletionItemKind`.
+const SYMBOL_MODULE: u32 = 2;
+const SYMBOL_CLASS: u32 = 5;
+const SYMBOL_FIELD: u32 = 8;
+const SYMBOL_CONSTRUCTOR: u32 = 9;
+const SYMBOL_ENUM: u32 = 10;
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+const SYMBOL_ENUM_MEMBER: u32 = 22;
+const SYMBOL_STRUCT: u32 = 23;
+
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_CONSTRUCTOR: u32 = 4;
+const COMPLETION_FIELD: u32 = 5;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_CLASS: u32 = 7;
+const COMPLETION_MODULE: u32 = 9;
+const COMPLETION_KEYWORD: u32 = 14;
+
+/// The results of the compiler stages for the module graph rooted at a
+/// document.
+pub struct Analysis<'db> {
+    session: Ref<'db, ParseSession>,
+    graph: ModuleGraph,
+    resolutions: Resolutions,
+    results: Rc<TypeckResults>,
+    tokens: Option<Rc<TokenizedFile>>,
+    diagnostics: Vec<Diagnostic>,
+    index: SyntaxIndex,
+    /// The file module of the document in the graph.
+    document: usize,
+}
+
+impl<'db> Analysis<'db> {
+    /// Runs the stages for the document, failing if it cannot be loaded.
+    pub fn new(db: &'db Database, path: &Path) -> Result<Self, String> {
+        let graph = db.checked_graph()?;
+        let source = ModuleSource::File(path.to_path_buf());
+        let document = graph
+            .modules
+            .iter()
+            .position(|module| module.source == source)
+            .ok_or_else(|| format!("the file \"{}\" is not loaded", path.display()))?;
+
+        let mut diagnostics = db.graph_diagnostics();
+        diagnostics.extend(db.resolve_diagnostics());
+        let resolutions = db.resolutions();
+        let results = db.typeck();
+        // The type errors in a program with unresolved paths mostly repeat
+        // the resolution errors, as the compiler stops before checking it.
+        if diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity != Severity::Error)
+        {
+            diagnostics.extend(db.typeck_diagnostics());
+        }
+        let tokens = db.tokens(&source);
+
+        let index = SyntaxIndex::new(graph.modules.iter().map(|module| &*module.program));
+        Ok(Self {
+            session: db.session(),
+            graph,
+            resolutions,
+            results,
+            tokens,
+            diagnostics,
+            index,
+            document,
+        })
+    }
+
+    fn file(&self) -> &SourceFile {
+        self.session
+            .source_map
+            .file(self.graph.modules[self.document].file)
+    }
+
+    fn program(&self) -> &ast::Program {
+        &self.graph.modules[self.document].program
+    }
+
+    /// Gets the module of the document in the resolutions.
+    fn module(&self) -> Option<ModuleId> {
+        let file = self.graph.modules[self.document].file;
+        self.resolutions
+            .modules
+            .iter()
+            .position(|module| module.parent.is_none() && module.file == file)
+            .map(ModuleId::new)
+    }
+
+    /// Converts the protocol position in the document to a global byte
+    /// position. The characters are counted in UTF-16 code units.
+    pub fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let file = self.file();
+        let line_start = file
+            .source_code
+            .split_inclusive('\n')
+            .take(line)
+            .map(str::len)
+            .sum::<usize>();
+        let mut units = 0;
+        let mut offset = line_start;
+        for c in file.source_code[line_start..].chars() {
+            if units >= character || c == '\n' {
+                break;
+            }
+            units += c.len_utf16();
+            offset += c.len_utf8();
+        }
+        Some(file.start_pos + offset)
+    }
+
+    /// Converts the global byte position to a line and a character in its
+    /// file.
+    fn line_character(&self, pos: usize) -> (usize, usize) {
+        let file = self.session.source_map.lookup_file(pos);
+        let offset = pos - file.start_pos;
+        let line_start = file.source_code[..offset]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let (line, _) = file.line_column(pos);
+        let character = file.source_code[line_start..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        (line - 1, character)
+    }
+
+    fn position(&self, pos: usize) -> Value {
+        let (line, character) = self.line_character(pos);
+        Value::object(vec![
+            ("line", Value::from(line)),
+            ("character", Value::from(character)),
+        ])
+    }
+
+    fn range(&self, span: SourceSpan) -> Value {
+        Value::object(vec![
+            ("start", self.position(span.start)),
+            ("end", self.position(span.end)),
+        ])
+    }
+
+    /// Gets the location of the span, unless it is in a file embedded in
+    /// the compiler.
+    fn location(&self, span: SourceSpan) -> Option<Value> {
+        let file = self.session.source_map.lookup_file(span.start).id;
+        let path = self.file_path(file)?;
+        Some(Value::object(vec![
+            ("uri", Value::from(uri::from_path(&path))),
+            ("range", self.range(span)),
+        ]))
+    }
+
+    fn file_path(&self, file: FileId) -> Option<PathBuf> {
+        self.graph
+            .modules
+            .iter()
+            .find(|module| module.file == file)
+            .and_then(|module| match &module.source {
+                ModuleSource::File(path) => Some(path.clone()),
+                _ => None,
+            })
+    }
+
+    fn in_document(&self, span: SourceSpan) -> bool {
+        self.session.source_map.lookup_file(span.start).id == self.graph.modules[self.document].file
+    }
+
+    fn text(&self, span: SourceSpan) -> &str {
+        self.session.source_map.span_to_snippet(span)
+    }
+
+    /// Gets the diagnostics of the document.
+    pub fn diagnostics(&self) -> Value {
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| self.in_document(diagnostic.span))
+            .map(|diagnostic| {
+                let severity = match diagnostic.severity {
+                    Severity::Error => 1usize,
+                    Severity::Warning => 2,
+                };
+                Value::object(vec![
+                    ("range", self.range(diagnostic.span)),
+                    ("severity", Value::from(severity)),
+                    ("source", Value::from("brinkc")),
+                    ("message", Value::from(diagnostic.message.as_str())),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Value::from(diagnostics)
+    }
+
+    /// Finds what the path, binding or field at the position refers to.
+    fn target_at(&self, pos: usize) -> Option<(&Node, Res)> {
+        let node = self
+            .index
+            .node_at(pos, &[NodeKind::Path, NodeKind::Binding, NodeKind::Field])?;
+        Some((node, self.target(node)?))
+    }
+
+    fn target(&self, node: &Node) -> Option<Res> {
+        match node.kind {
+            NodeKind::Path => self.resolutions.paths.get(&node.id).copied(),
+            NodeKind::Field => self.resolutions.fields.get(&node.id).copied().map(Res::Def),
+            // The definitions and the modules are identified by the spans
+            // of their names, and the local bindings by their nodes.
+            NodeKind::Binding => {
+                let def = self
+                    .resolutions
+                    .defs
+                    .iter()
+                    .position(|def| def.span == node.span);
+                let module = self
+                    .resolutions
+                    .modules
+                    .iter()
+                    .position(|module| module.parent.is_some() && module.span == node.span);
+                Some(match (def, module) {
+                    (Some(def), _) => Res::Def(DefId::new(def)),
+                    (None, Some(module)) => Res::Module(ModuleId::new(module)),
+                    (None, None) => Res::Local(node.id),
+                })
+            }
+            NodeKind::Expr | NodeKind::Pattern => None,
+        }
+    }
+
+    /// Gets the span of the name introducing the target.
+    fn definition_span(&self, target: Res) -> Option<SourceSpan> {
+        match target {
+            Res::Def(def) => Some(self.resolutions.def(def).span),
+            Res::Module(module) => Some(self.resolutions.module(module).span),
+            Res::Local(node) | Res::TyParam(node) => self.index.node(node).map(|node| node.name),
+            Res::PrimTy(_) | Res::Builtin(_) => None,
+        }
+    }
+
+    pub fn hover(&self, pos: usize) -> Value {
+        let kinds = [
+            NodeKind::Path,
+            NodeKind::Binding,
+            NodeKind::Field,
+            NodeKind::Expr,
+            NodeKind::Pattern,
+        ];
+        let node = match self.index.node_at(pos, &kinds) {
+            Some(node) => node,
+            None => return Value::Null,
+        };
+        let description = match self.target(node) {
+            Some(target) => self.describe(node, target),
+            None => self.node_type(node.id),
+        };
+        match description {
+            Some(description) => Value::object(vec![
+                (
+                    "contents",
+                    Value::object(vec![
+                        ("kind", Value::from("markdown")),
+                        (
+                            "value",
+                            Value::from(format!("```brink\n{}\n```", description)),
+                        ),
+                    ]),
+                ),
+                ("range", self.range(node.span)),
+            ]),
+            None => Value::Null,
+        }
+    }
+
+    fn print(&self, ty: &Ty) -> String {
+        TyPrinter::new(&self.resolutions).print(ty)
+    }
+
+    fn node_type(&self, node: NodeId) -> Option<String> {
+        self.results.node_types.get(&node).map(|ty| self.print(ty))
+    }
+
+    /// Describes the target the way it is declared, e.g. `map : ('a -> 'b)
+    /// -> list 'a -> list 'b`.
+    fn describe(&self, node: &Node, target: Res) -> Option<String> {
+        let typed = |name: &str, ty: Option<String>| match ty {
+            Some(ty) => format!("{} : {}", name, ty),
+            None => name.to_string(),
+        };
+        Some(match target {
+            Res::Def(def) => {
+                let definition = self.resolutions.def(def);
+                match definition.kind {
+                    DefKind::Type => format!("type {}", definition.name),
+                    DefKind::Field(record) => typed(&definition.name, self.field_type(record, def)),
+                    DefKind::Value | DefKind::Constructor(_) | DefKind::Exception => {
+                        let scheme = self.results.def_schemes.get(&def);
+                        typed(
+                            &definition.name,
+                            scheme.map(|scheme| self.print(&scheme.ty)),
+                        )
+                    }
+                }
+            }
+            Res::Module(module) => format!("module {}", self.resolutions.module(module).name),
+            Res::Local(binding) => {
+                let ty = match self.results.local_schemes.get(&binding) {
+                    Some(scheme) => Some(self.print(&scheme.ty)),
+                    None => self.node_type(binding),
+                };
+                typed(self.text(node.name), ty)
+            }
+            Res::TyParam(_) => format!("type {}", self.text(node.name)),
+            Res::PrimTy(_) => format!("type {}", self.text(node.name)),
+            // The types of the builtins are only known where they are used.
+            Res::Builtin(builtin) => {
+                let expr = self
+                    .index
+                    .nodes()
+                    .iter()
+                    .find(|expr| expr.kind == NodeKind::Expr && expr.span == node.span);
+                typed(
+                    builtin.name(),
+                    expr.and_then(|expr| self.node_type(expr.id)),
+                )
+            }
+        })
+    }
+
+    fn field_type(&self, record: DefId, field: DefId) -> Option<String> {
+        match &self.results.adts.get(&record)?.kind {
+            AdtKind::Record(fields) => fields
+                .iter()
+                .find(|field_def| field_def.def == field)
+                .map(|field_def| self.print(&field_def.ty)),
+            AdtKind::Variant(_) => None,
+        }
+    }
+
+    pub fn definition(&self, pos: usize) -> Value {
+        self.target_at(pos)
+            .and_then(|(_, target)| self.definition_span(target))
+            .and_then(|span| self.location(span))
+            .into()
+    }
+
+    pub fn references(&self, pos: usize, include_declaration: bool) -> Value {
+        let target = match self.target_at(pos) {
+            Some((_, target)) => target,
+            None => return Value::Array(Vec::new()),
+        };
+        let same_node = |node| match target {
+            Res::Local(target) | Res::TyParam(target) => node == target,
+            _ => false,
+        };
+
+        let mut spans = Vec::new();
+        if include_declaration {
+            spans.extend(self.definition_span(target));
+        }
+        for (path, res) in &self.resolutions.paths {
+            let matches = match *res {
+                Res::Local(node) | Res::TyParam(node) => same_node(node),
+                res => res == target,
+            };
+            if matches {
+                spans.extend(self.index.node(*path).map(|node| node.name));
+            }
+        }
+        if let Res::Def(def) = target {
+            for (field, field_def) in &self.resolutions.fields {
+                if *field_def == def {
+                    spans.extend(self.index.node(*field).map(|node| node.name));
+                }
+            }
+        }
+        spans.sort_by_key(|span| span.start);
+        spans.dedup();
+        Value::from(
+            spans
+                .into_iter()
+                .filter_map(|span| self.location(span))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    pub fn document_symbols(&self) -> Value {
+        Value::from(self.symbols(&self.program().body))
+    }
+
+    fn symbols(&self, items: &[ast::Item]) -> Vec<Value> {
+        let mut symbols = Vec::new();
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    symbols.push(self.let_symbol(let_binding, item.span))
+                }
+                ast::ItemKind::LetRec(bindings) => {
+                    for let_binding in bindings {
+                        symbols.push(self.let_symbol(let_binding, let_binding.span));
+                    }
+                }
+                ast::ItemKind::Type(decl) => {
+                    let (kind, children) = match &decl.kind {
+                        ast::TypeDeclKind::Variant(constructors) => (
+                            SYMBOL_ENUM,
+                            constructors
+                                .iter()
+                                .map(|constructor| {
+                                    self.symbol(
+                                        &constructor.identifier,
+                                        SYMBOL_ENUM_MEMBER,
+                                        constructor.span,
+                                        None,
+                                        vec![],
+                                    )
+                                })
+                                .collect(),
+                        ),
+                        ast::TypeDeclKind::Record(fields) => (
+                            SYMBOL_STRUCT,
+                            fields
+                                .iter()
+                                .map(|field| {
+                                    self.symbol(
+                                        &field.identifier,
+                                        SYMBOL_FIELD,
+                                        field.span,
+                                        None,
+                                        vec![],
+                                    )
+                                })
+                                .collect(),
+                        ),
+                        ast::TypeDeclKind::Alias(_) => (SYMBOL_CLASS, vec![]),
+                    };
+                    symbols.push(self.symbol(&decl.identifier, kind, item.span, None, children));
+                }
+                ast::ItemKind::Module(decl) => {
+                    let children = self.symbols(&decl.body.items);
+                    symbols.push(self.symbol(
+                        &decl.identifier,
+                        SYMBOL_MODULE,
+                        item.span,
+                        None,
+                        children,
+                    ));
+                }
+                ast::ItemKind::Exception(constructor) => {
+                    symbols.push(self.symbol(
+                        &constructor.identifier,
+                        SYMBOL_CONSTRUCTOR,
+                        item.span,
+                        None,
+                        vec![],
+                    ));
+                }
+                ast::ItemKind::Open(_)
+                | ast::ItemKind::Import(_)
+                | ast::ItemKind::Fixity(_)
+                | ast::ItemKind::Expr(_) => {}
+            }
+        }
+        symbols
+    }
+
+    fn let_symbol(&self, let_binding: &ast::LetBinding, span: SourceSpan) -> Value {
+        let is_function = !let_binding.parameters.is_empty()
+            || matches!(
+                &let_binding.body,
+                ast::LetBody::Expr(ast::Expr {
+                    kind: ast::ExprKind::Lambda(..),
+                    ..
+                })
+            );
+        let kind = if is_function {
+            SYMBOL_FUNCTION
+        } else {
+            SYMBOL_VARIABLE
+        };
+        let detail = self
+            .resolutions
+            .def_of_node
+            .get(&let_binding.id)
+            .and_then(|def| self.results.def_schemes.get(def))
+            .map(|scheme| self.print(&scheme.ty));
+        self.symbol(&let_binding.identifier, kind, span, detail, vec![])
+    }
+
+    fn symbol(
+        &self,
+        identifier: &ast::Literal,
+        kind: u32,
+        span: SourceSpan,
+        detail: Option<String>,
+        children: Vec<Value>,
+    ) -> Value {
+        let mut members = vec![
+            ("name", Value::from(self.text(identifier.span))),
+            ("kind", Value::from(kind)),
+            ("range", self.range(span)),
+            ("selectionRange", self.range(identifier.span)),
+        ];
+        if let Some(detail) = detail {
+            members.push(("detail", Value::from(detail)));
+        }
+        if !children.is_empty() {
+            members.push(("children", Value::from(children)));
+        }
+        Value::object(members)
+    }
+
+    /// Completes the name before the position with the names in scope, or
+    /// with the members of the module it is qualified with.
+    pub fn completion(&self, pos: usize) -> Value {
+        let file = self.file();
+        let offset = pos - file.start_pos;
+        let before = &file.source_code[..offset];
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+        let prefix_start = before
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_name_char(*c))
+            .last()
+            .map_or(offset, |(i, _)| i);
+        let prefix = &before[prefix_start..];
+        let mut qualifier = Vec::new();
+        let mut rest = &before[..prefix_start];
+        while let Some(path) = rest.strip_suffix('.') {
+            let segment_start = path
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| is_name_char(*c))
+                .last()
+                .map(|(i, _)| i);
+            match segment_start {
+                Some(start) => {
+                    qualifier.insert(0, &path[start..]);
+                    rest = &path[..start];
+                }
+                None => break,
+            }
+        }
+
+        let mut items = Vec::new();
+        match self.module() {
+            Some(module) if !qualifier.is_empty() => {
+                if let Some(qualified) = self.find_module(module, &qualifier) {
+                    let is_local = self.resolutions.module(qualified).file == file.id;
+                    self.module_completions(qualified, !is_local, &mut items);
+                }
+            }
+            Some(module) => {
+                self.module_completions(module, false, &mut items);
+                if let Some(prelude) = self.resolutions.prelude {
+                    self.module_completions(prelude, true, &mut items);
+                }
+                self.local_completions(pos, &mut items);
+                for (keyword, kind) in KEYWORDS {
+                    if *kind != TokenKind::Underscore {
+                        items.push((keyword.to_string(), COMPLETION_KEYWORD, None));
+                    }
+                }
+            }
+            None => {}
+        }
+
+        items.retain(|(label, _, _)| label.starts_with(prefix));
+        items.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        items.dedup_by(|(a, _, _), (b, _, _)| a == b);
+        Value::from(
+            items
+                .into_iter()
+                .map(|(label, kind, detail)| {
+                    let mut members =
+                        vec![("label", Value::from(label)), ("kind", Value::from(kind))];
+                    if let Some(detail) = detail {
+                        members.push(("detail", Value::from(detail)));
+                    }
+                    Value::object(members)
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Finds the module the qualifier names, looking its first segment up
+    /// in the enclosing modules, then in the prelude and then among the
+    /// file modules, which the resolver keeps the imports of to itself.
+    fn find_module(&self, from: ModuleId, qualifier: &[&str]) -> Option<ModuleId> {
+        let mut scope = Some(from);
+        let mut module = None;
+        while let (Some(current), None) = (scope, module) {
+            let data = self.resolutions.module(current);
+            module = data.modules.get(qualifier[0]).copied();
+            scope = data.parent;
+        }
+        let prelude = self.resolutions.prelude;
+        let mut module = module
+            .or_else(|| {
+                self.resolutions
+                    .module(prelude?)
+                    .modules
+                    .get(qualifier[0])
+                    .copied()
+            })
+            .or_else(|| {
+                self.resolutions
+                    .modules
+                    .iter()
+                    .position(|module| module.parent.is_none() && module.name == qualifier[0])
+                    .map(ModuleId::new)
+            })?;
+        for segment in &qualifier[1..] {
+            module = *self.resolutions.module(module).modules.get(*segment)?;
+        }
+        Some(module)
+    }
+
+    fn module_completions(
+        &self,
+        module: ModuleId,
+        public_only: bool,
+        items: &mut Vec<(String, u32, Option<String>)>,
+    ) {
+        let data = self.resolutions.module(module);
+        let defs = data.values.values().chain(data.types.values());
+        for def in defs {
+            let definition = self.resolutions.def(*def);
+            if public_only && definition.visibility != Visibility::Public {
+                continue;
+            }
+            let scheme = self.results.def_schemes.get(def);
+            let kind = match definition.kind {
+                DefKind::Value => match scheme.map(|scheme| &scheme.ty) {
+                    Some(Ty::Function(..)) => COMPLETION_FUNCTION,
+                    _ => COMPLETION_VARIABLE,
+                },
+                DefKind::Constructor(_) | DefKind::Exception => COMPLETION_CONSTRUCTOR,
+                DefKind::Type => COMPLETION_CLASS,
+                DefKind::Field(_) => COMPLETION_FIELD,
+            };
+            let detail = scheme.map(|scheme| self.print(&scheme.ty));
+            items.push((definition.name.clone(), kind, detail));
+        }
+        for (name, nested) in &data.modules {
+            let nested = self.resolutions.module(*nested);
+            if !public_only || nested.visibility == Visibility::Public {
+                items.push((name.clone(), COMPLETION_MODULE, None));
+            }
+        }
+    }
+
+    /// Adds the local bindings introduced before the position in the
+    /// top-level item containing it.
+    fn local_completions(&self, pos: usize, items: &mut Vec<(String, u32, Option<String>)>) {
+        let item = match self
+            .program()
+            .body
+            .iter()
+            .find(|item| item.span.touches(pos))
+        {
+            Some(item) => item,
+            None => return,
+        };
+        for node in self.index.nodes() {
+            if node.kind != NodeKind::Binding
+                || node.span.start < item.span.start
+                || node.span.end > pos
+                || self.target(node) != Some(Res::Local(node.id))
+            {
+                continue;
+            }
+            let ty = self.results.node_types.get(&node.id);
+            let kind = match ty {
+                Some(Ty::Function(..)) => COMPLETION_FUNCTION,
+                _ => COMPLETION_VARIABLE,
+            };
+            items.push((
+                self.text(node.span).to_string(),
+                kind,
+                ty.map(|ty| self.print(ty)),
+            ));
+        }
+    }
+
+    /// Classifies the tokens of the document, encoded relative to each
+    /// other as the protocol requires.
+    pub fn semantic_tokens(&self) -> Value {
+        let tokens = match self
+            .tokens
+            .as_ref()
+            .and_then(|tokens| tokens.tokens.as_ref())
+        {
+            Some(tokens) => tokens,
+            None => return Value::object(vec![("data", Value::Array(Vec::new()))]),
+        };
+        let names = self
+            .index
+            .nodes()
+            .iter()
+            .filter(|node| {
+                matches!(
+                    node.kind,
+                    NodeKind::Path | NodeKind::Binding | NodeKind::Field
+                ) && self.in_document(node.span)
+            })
+            .map(|node| (node.name.start, node))
+            .collect::<HashMap<_, _>>();
+
+        let mut data = Vec::new();
+        let (mut previous_line, mut previous_character) = (0, 0);
+        for token in tokens.as_vec() {
+            let token_type = match classify(token.kind) {
+                Some(SemanticToken::Variable) => self.classify_name(token.span, &names),
+                token_type => token_type,
+            };
+            let token_type = match token_type {
+                Some(token_type) if !token.span.is_empty() => token_type,
+                _ => continue,
+            };
+            let (line, character) = self.line_character(token.span.start);
+            let text = self.text(token.span);
+            let length = text
+                .split('\n')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .map(char::len_utf16)
+                .sum::<usize>();
+            let delta_character = if line == previous_line {
+                character - previous_character
+            } else {
+                character
+            };
+            data.extend(vec![
+                Value::from(line - previous_line),
+                Value::from(delta_character),
+                Value::from(length),
+                Value::from(token_type as usize),
+                Value::from(0usize),
+            ]);
+            previous_line = line;
+            previous_character = character;
+        }
+        Value::object(vec![("data", Value::from(data))])
+    }
+
+    /// Classifies the identifier by what it refers to. The leading segments
+    /// of a qualified path are modules.
+    fn classify_name(
+        &self,
+        span: SourceSpan,
+        names: &HashMap<usize, &Node>,
+    ) -> Option<SemanticToken> {
+        let node = match names.get(&span.start) {
+            Some(node) if node.name == span => node,
+            _ => {
+                let in_path = self.index.nodes().iter().any(|node| {
+                    node.kind == NodeKind::Path
+                        && node.span.start <= span.start
+                        && span.end <= node.span.end
+                });
+                return Some(if in_path {
+                    SemanticToken::Namespace
+                } else {
+                    SemanticToken::Variable
+                });
+            }
+        };
+        let is_function = |ty: Option<&Ty>| matches!(ty, Some(Ty::Function(..)));
+        Some(match self.target(node)? {
+            Res::Def(def) => match self.resolutions.def(def).kind {
+                DefKind::Value => {
+                    let scheme = self.results.def_schemes.get(&def);
+                    if is_function(scheme.map(|scheme| &scheme.ty)) {
+                        SemanticToken::Function
+                    } else {
+                        SemanticToken::Variable
+                    }
+                }
+                DefKind::Constructor(_) | DefKind::Exception => SemanticToken::EnumMember,
+                DefKind::Type => SemanticToken::Type,
+                DefKind::Field(_) => SemanticToken::Property,
+            },
+            Res::Local(binding) => {
+                let scheme = self.results.local_schemes.get(&binding);
+                let ty = scheme
+                    .map(|scheme| &scheme.ty)
+                    .or_else(|| self.results.node_types.get(&binding));
+                if is_function(ty) {
+                    SemanticToken::Function
+                } else {
+                    SemanticToken::Variable
+                }
+            }
+            Res::Module(_) => SemanticToken::Namespace,
+            Res::PrimTy(_) => SemanticToken::Type,
+            Res::TyParam(_) => SemanticToken::TypeParameter,
+            Res::Builtin(builtin) if builtin.is_exception() => SemanticToken::EnumMember,
+            Res::Builtin(_) => SemanticToken::Function,
+        })
+    }
+}
+
+/// Classifies the token by its kind alone, as the lexer's highlighting
+/// does. The identifiers are variables until they are resolved.
+fn classify(kind: TokenKind) -> Option<SemanticToken> {
+    match kind.class()? {
+        TokenClass::Keyword | TokenClass::Constant => Some(SemanticToken::Keyword),
+        TokenClass::Identifier => Some(SemanticToken::Variable),
+        TokenClass::TypeVariable => Some(SemanticToken::TypeParameter),
+        TokenClass::Number => Some(SemanticToken::Number),
+        TokenClass::String => Some(SemanticToken::String),
+        TokenClass::Attribute => Some(SemanticToken::Decorator),
+        TokenClass::Operator => Some(SemanticToken::Operator),
+        TokenClass::Punctuation | TokenClass::Comment | TokenClass::Invalid => None,
+    }
+}
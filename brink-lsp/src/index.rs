@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use brinkc::{
+    ast::{self, node_id::NodeId},
+    source_file::SourceSpan,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NodeKind {
+    Expr,
+    Pattern,
+    /// A path to a value, a type or a module.
+    Path,
+    /// An identifier introducing a definition or a local binding.
+    Binding,
+    /// The name of a record field in an expression or a pattern.
+    Field,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Node {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    /// The span of the name the node refers to or introduces, which is the
+    /// last segment of a path.
+    pub name: SourceSpan,
+    pub kind: NodeKind,
+}
+
+/// The spans of the nodes the editor features point at, collected from the
+/// programs, as the side tables of the resolver and the type checker only
+/// hold the node ids.
+pub struct SyntaxIndex {
+    nodes: Vec<Node>,
+    by_id: HashMap<NodeId, usize>,
+}
+
+impl SyntaxIndex {
+    pub fn new<'a>(programs: impl IntoIterator<Item = &'a ast::Program>) -> Self {
+        let mut index = SyntaxIndex {
+            nodes: Vec::new(),
+            by_id: HashMap::new(),
+        };
+        for program in programs {
+            index.items(&program.body);
+        }
+        index
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.by_id.get(&id).map(|&index| &self.nodes[index])
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Finds the innermost node of one of the kinds at the global position.
+    /// Of the nodes with the same span, e.g. a path and the expression made
+    /// of it, the first kind wins.
+    pub fn node_at(&self, pos: usize, kinds: &[NodeKind]) -> Option<&Node> {
+        self.nodes
+            .iter()
+            .filter(|node| node.span.touches(pos))
+            .filter_map(|node| {
+                let priority = kinds.iter().position(|kind| *kind == node.kind)?;
+                Some((node, priority))
+            })
+            .min_by_key(|(node, priority)| (node.span.len(), *priority))
+            .map(|(node, _)| node)
+    }
+
+    fn add(&mut self, id: NodeId, span: SourceSpan, kind: NodeKind) {
+        self.add_named(id, span, span, kind);
+    }
+
+    fn add_named(&mut self, id: NodeId, span: SourceSpan, name: SourceSpan, kind: NodeKind) {
+        self.by_id.insert(id, self.nodes.len());
+        self.nodes.push(Node {
+            id,
+            span,
+            name,
+            kind,
+        });
+    }
+
+    fn literal(&mut self, literal: &ast::Literal, kind: NodeKind) {
+        self.add(literal.id, literal.span, kind);
+    }
+
+    fn path(&mut self, path: &ast::Path) {
+        let name = path
+            .segments
+            .last()
+            .map_or(path.span, |segment| segment.span);
+        self.add_named(path.id, path.span, name, NodeKind::Path);
+    }
+
+    fn items(&mut self, items: &[ast::Item]) {
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => self.let_binding(let_binding),
+                ast::ItemKind::LetRec(bindings) => {
+                    for let_binding in bindings {
+                        self.let_binding(let_binding);
+                    }
+                }
+                ast::ItemKind::Type(decl) => {
+                    self.literal(&decl.identifier, NodeKind::Binding);
+                    for parameter in &decl.parameters {
+                        self.literal(parameter, NodeKind::Binding);
+                    }
+                    match &decl.kind {
+                        ast::TypeDeclKind::Variant(constructors) => {
+                            for constructor in constructors {
+                                self.constructor(constructor);
+                            }
+                        }
+                        ast::TypeDeclKind::Record(fields) => {
+                            for field in fields {
+                                self.literal(&field.identifier, NodeKind::Binding);
+                                self.ty(&field.ty);
+                            }
+                        }
+                        ast::TypeDeclKind::Alias(ty) => self.ty(ty),
+                    }
+                }
+                ast::ItemKind::Module(decl) => {
+                    self.literal(&decl.identifier, NodeKind::Binding);
+                    self.items(&decl.body.items);
+                }
+                ast::ItemKind::Open(path) | ast::ItemKind::Import(path) => self.path(path),
+                ast::ItemKind::Exception(constructor) => self.constructor(constructor),
+                ast::ItemKind::Fixity(_) => {}
+                ast::ItemKind::Expr(expr) => self.expr(expr),
+            }
+        }
+    }
+
+    fn constructor(&mut self, constructor: &ast::Constructor) {
+        self.literal(&constructor.identifier, NodeKind::Binding);
+        for argument in &constructor.arguments {
+            self.ty(argument);
+        }
+    }
+
+    fn let_binding(&mut self, let_binding: &ast::LetBinding) {
+        self.literal(&let_binding.identifier, NodeKind::Binding);
+        for parameter in &let_binding.parameters {
+            self.pattern(parameter);
+        }
+        if let Some(ty) = &let_binding.return_ty {
+            self.ty(ty);
+        }
+        self.let_body(&let_binding.body);
+    }
+
+    fn let_body(&mut self, body: &ast::LetBody) {
+        match body {
+            ast::LetBody::Block(block) => self.items(&block.items),
+            ast::LetBody::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn ty(&mut self, ty: &ast::Ty) {
+        match &ty.kind {
+            ast::TyKind::Path(path, arguments) => {
+                self.path(path);
+                for argument in arguments {
+                    self.ty(argument);
+                }
+            }
+            ast::TyKind::Var(_) => {}
+            ast::TyKind::Arrow(parameter, result) => {
+                self.ty(parameter);
+                self.ty(result);
+            }
+            ast::TyKind::Tuple(elements) => {
+                for element in elements {
+                    self.ty(element);
+                }
+            }
+            ast::TyKind::Paren(inner) => self.ty(inner),
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[ast::Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn fields(&mut self, fields: &[ast::FieldExpr]) {
+        for field in fields {
+            self.literal(&field.identifier, NodeKind::Field);
+            self.expr(&field.expr);
+        }
+    }
+
+    fn arms(&mut self, arms: &[ast::MatchArm]) {
+        for arm in arms {
+            self.pattern(&arm.pattern);
+            self.let_body(&arm.body);
+        }
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) {
+        self.add(expr.id, expr.span, NodeKind::Expr);
+        match &expr.kind {
+            ast::ExprKind::Literal(_) => {}
+            ast::ExprKind::Path(path) => self.path(path),
+            ast::ExprKind::Application(function, arguments) => {
+                self.expr(function);
+                self.exprs(arguments);
+            }
+            ast::ExprKind::TailCall(inner)
+            | ast::ExprKind::Paren(inner)
+            | ast::ExprKind::Unary(_, inner) => self.expr(inner),
+            ast::ExprKind::Tuple(elements)
+            | ast::ExprKind::List(elements)
+            | ast::ExprKind::Array(elements) => self.exprs(elements),
+            ast::ExprKind::Record(fields) => self.fields(fields),
+            ast::ExprKind::RecordUpdate(record, fields) => {
+                self.expr(record);
+                self.fields(fields);
+            }
+            ast::ExprKind::FieldAccess(record, field) => {
+                self.expr(record);
+                self.literal(field, NodeKind::Field);
+            }
+            ast::ExprKind::Match(scrutinee, arms) => {
+                self.expr(scrutinee);
+                self.arms(arms);
+            }
+            ast::ExprKind::Lambda(parameters, body) => {
+                for parameter in parameters {
+                    self.pattern(parameter);
+                }
+                self.let_body(body);
+            }
+            ast::ExprKind::If(condition, then_branch, else_branch) => {
+                self.expr(condition);
+                self.let_body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.let_body(else_branch);
+                }
+            }
+            ast::ExprKind::While(condition, body) => {
+                self.expr(condition);
+                self.let_body(body);
+            }
+            ast::ExprKind::For(for_loop) => {
+                self.literal(&for_loop.binding, NodeKind::Binding);
+                self.expr(&for_loop.start);
+                self.expr(&for_loop.end);
+                self.let_body(&for_loop.body);
+            }
+            ast::ExprKind::Try(body, arms) => {
+                self.let_body(body);
+                self.arms(arms);
+            }
+            ast::ExprKind::Let(let_binding, body) => {
+                self.let_binding(let_binding);
+                self.let_body(body);
+            }
+            ast::ExprKind::Binary(_, left, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ast::ExprKind::Infix(operator, left, right) => {
+                self.path(operator);
+                self.expr(left);
+                self.expr(right);
+            }
+            ast::ExprKind::Section(operator, left, right) => {
+                if let ast::SectionOperator::User(path) = operator {
+                    self.path(path);
+                }
+                for operand in left.iter().chain(right) {
+                    self.expr(operand);
+                }
+            }
+            ast::ExprKind::Typed(inner, ty) => {
+                self.expr(inner);
+                self.ty(ty);
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    if let ast::InterpolationPart::Hole(hole) = part {
+                        self.expr(hole);
+                    }
+                }
+            }
+        }
+    }
+
+    fn pattern(&mut self, pattern: &ast::Pattern) {
+        self.add(pattern.id, pattern.span, NodeKind::Pattern);
+        match &pattern.kind {
+            ast::PatternKind::Wildcard | ast::PatternKind::Literal(_) => {}
+            ast::PatternKind::Binding(identifier) => self.literal(identifier, NodeKind::Binding),
+            ast::PatternKind::Constructor(path, arguments) => {
+                self.path(path);
+                for argument in arguments {
+                    self.pattern(argument);
+                }
+            }
+            ast::PatternKind::Record(fields) => {
+                for field in fields {
+                    match &field.pattern {
+                        Some(pattern) => {
+                            self.literal(&field.identifier, NodeKind::Field);
+                            self.pattern(pattern);
+                        }
+                        // The field binds the variable of the same name.
+                        None => self.literal(&field.identifier, NodeKind::Binding),
+                    }
+                }
+            }
+            ast::PatternKind::Or(patterns)
+            | ast::PatternKind::Tuple(patterns)
+            | ast::PatternKind::List(patterns)
+            | ast::PatternKind::Array(patterns) => {
+                for pattern in patterns {
+                    self.pattern(pattern);
+                }
+            }
+            ast::PatternKind::Paren(inner) => self.pattern(inner),
+            ast::PatternKind::Cons(head, tail) => {
+                self.pattern(head);
+                self.pattern(tail);
+            }
+            ast::PatternKind::Typed(inner, ty) => {
+                self.pattern(inner);
+                self.ty(ty);
+            }
+        }
+    }
+}
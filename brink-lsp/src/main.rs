@@ -0,0 +1,25 @@
+//! The language server of Brink. It speaks the Language Server Protocol over
+//! the standard input and output, checking the open documents with the
+//! compiler's query database as they are edited.
+
+mod analysis;
+mod index;
+mod json;
+mod server;
+mod transport;
+mod uri;
+
+use server::Server;
+
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let exit_code = match Server::new().run(&mut stdin.lock(), &mut stdout.lock()) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    };
+    std::process::exit(exit_code);
+}
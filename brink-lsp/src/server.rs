@@ -0,0 +1,546 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use brinkc::{frontend::parse_session::ParseSession, query::Database, source_file::SourceMap};
+
+use crate::{
+    analysis::{Analysis, TOKEN_TYPES},
+    json::Value,
+    transport, uri,
+};
+
+// The error codes of the protocol.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+/// An open document, checked as the root of its own module graph.
+struct Document {
+    uri: String,
+    text: String,
+    database: Database,
+}
+
+/// The state of the language server: the open documents and whether the
+/// client has asked it to shut down.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<PathBuf, Document>,
+    shutting_down: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the messages of the client until it exits, returning the exit
+    /// code: 0 if the client asked for a shutdown first.
+    pub fn run(&mut self, reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<i32> {
+        while let Some(message) = transport::read_message(reader)? {
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    let response = error_response(Value::Null, PARSE_ERROR, &e);
+                    transport::write_message(writer, &response)?;
+                    continue;
+                }
+            };
+            if message["method"].as_str() == Some("exit") {
+                return Ok(if self.shutting_down { 0 } else { 1 });
+            }
+            for outgoing in self.handle(&message) {
+                transport::write_message(writer, &outgoing)?;
+            }
+        }
+        Ok(1)
+    }
+
+    /// Handles a request or a notification of the client, returning the
+    /// response and the notifications to send.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, params),
+        };
+        if self.shutting_down && method != "shutdown" {
+            return vec![error_response(
+                id,
+                INVALID_REQUEST,
+                "the server is shutting down",
+            )];
+        }
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutting_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => self.at_position(params, |analysis, pos| analysis.hover(pos)),
+            "textDocument/definition" => {
+                self.at_position(params, |analysis, pos| analysis.definition(pos))
+            }
+            "textDocument/references" => {
+                let include_declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(false);
+                self.at_position(params, |analysis, pos| {
+                    analysis.references(pos, include_declaration)
+                })
+            }
+            "textDocument/completion" => {
+                self.at_position(params, |analysis, pos| analysis.completion(pos))
+            }
+            "textDocument/documentSymbol" => {
+                self.with_analysis(params, |analysis| analysis.document_symbols())
+            }
+            "textDocument/semanticTokens/full" => {
+                self.with_analysis(params, |analysis| analysis.semantic_tokens())
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        };
+        vec![match result {
+            Ok(result) => Value::object(vec![
+                ("jsonrpc", Value::from("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            Err((code, message)) => error_response(id, code, &message),
+        }]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let document = &params["textDocument"];
+        let path = document["uri"].as_str().and_then(uri::to_path);
+        match (method, path) {
+            ("textDocument/didOpen", Some(path)) => {
+                let text = document["text"].as_str().unwrap_or_default().to_string();
+                let mut database = Database::new(ParseSession::new(SourceMap::new()), path.clone());
+                for (open, document) in &self.documents {
+                    database.set_source_text(open.clone(), Some(document.text.clone()));
+                }
+                let uri = document["uri"].as_str().unwrap_or_default().to_string();
+                self.documents.insert(
+                    path.clone(),
+                    Document {
+                        uri,
+                        text: String::new(),
+                        database,
+                    },
+                );
+                self.set_text(path, Some(text));
+                self.publish_diagnostics()
+            }
+            ("textDocument/didChange", Some(path)) => {
+                // The documents are synchronized in full, so the last change
+                // holds the whole text.
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                match text {
+                    Some(text) if self.documents.contains_key(&path) => {
+                        self.set_text(path, Some(text.to_string()));
+                        self.publish_diagnostics()
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            ("textDocument/didClose", Some(path)) => match self.documents.remove(&path) {
+                Some(document) => {
+                    // The other documents see the file on disk again.
+                    let text = std::fs::read_to_string(&path).ok();
+                    self.set_text(path, text);
+                    let mut notifications = self.publish_diagnostics();
+                    notifications.push(diagnostics_notification(
+                        &document.uri,
+                        Value::Array(Vec::new()),
+                    ));
+                    notifications
+                }
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Sets the text of the file in the databases of all the documents.
+    fn set_text(&mut self, path: PathBuf, text: Option<String>) {
+        if let (Some(document), Some(text)) = (self.documents.get_mut(&path), &text) {
+            document.text = text.clone();
+        }
+        for document in self.documents.values_mut() {
+            document
+                .database
+                .set_source_text(path.clone(), text.clone());
+        }
+    }
+
+    /// Publishes the diagnostics of all the open documents, as a change in
+    /// one document may affect the others importing it.
+    fn publish_diagnostics(&self) -> Vec<Value> {
+        let mut paths = self.documents.keys().collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let document = &self.documents[path];
+                let diagnostics = match Analysis::new(&document.database, path) {
+                    Ok(analysis) => analysis.diagnostics(),
+                    Err(message) => Value::from(vec![Value::object(vec![
+                        ("range", zero_range()),
+                        ("severity", Value::from(1usize)),
+                        ("source", Value::from("brinkc")),
+                        ("message", Value::from(message)),
+                    ])]),
+                };
+                diagnostics_notification(&document.uri, diagnostics)
+            })
+            .collect()
+    }
+
+    fn with_analysis(
+        &self,
+        params: &Value,
+        f: impl FnOnce(&Analysis) -> Value,
+    ) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = uri::to_path(uri)
+            .and_then(|path| Some((self.documents.get(&path)?, path)))
+            .ok_or_else(|| {
+                (
+                    INVALID_PARAMS,
+                    format!("the document `{}` is not open", uri),
+                )
+            })?;
+        let (document, path) = document;
+        // A document which cannot be loaded has nothing to show.
+        Ok(match Analysis::new(&document.database, &path) {
+            Ok(analysis) => f(&analysis),
+            Err(_) => Value::Null,
+        })
+    }
+
+    fn at_position(
+        &self,
+        params: &Value,
+        f: impl FnOnce(&Analysis, usize) -> Value,
+    ) -> Result<Value, (i64, String)> {
+        let position = &params["position"];
+        self.with_analysis(params, |analysis| match analysis.offset(position) {
+            Some(pos) => f(analysis, pos),
+            None => Value::Null,
+        })
+    }
+}
+
+fn capabilities() -> Value {
+    let token_types = TOKEN_TYPES.iter().map(|name| Value::from(*name)).collect();
+    Value::object(vec![
+        (
+            "capabilities",
+            Value::object(vec![
+                // The documents are synchronized in full.
+                ("textDocumentSync", Value::from(1usize)),
+                ("hoverProvider", Value::from(true)),
+                ("definitionProvider", Value::from(true)),
+                ("referencesProvider", Value::from(true)),
+                ("documentSymbolProvider", Value::from(true)),
+                (
+                    "completionProvider",
+                    Value::object(vec![(
+                        "triggerCharacters",
+                        Value::from(vec![Value::from(".")]),
+                    )]),
+                ),
+                (
+                    "semanticTokensProvider",
+                    Value::object(vec![
+                        (
+                            "legend",
+                            Value::object(vec![
+                                ("tokenTypes", Value::Array(token_types)),
+                                ("tokenModifiers", Value::Array(Vec::new())),
+                            ]),
+                        ),
+                        ("full", Value::from(true)),
+                    ]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Value::object(vec![
+                ("name", Value::from("brink-lsp")),
+                ("version", Value::from(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::from("2.0")),
+        ("id", id),
+        (
+            "error",
+            Value::object(vec![
+                ("code", Value::from(code)),
+                ("message", Value::from(message)),
+            ]),
+        ),
+    ])
+}
+
+fn diagnostics_notification(uri: &str, diagnostics: Value) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::from("2.0")),
+        ("method", Value::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Value::object(vec![
+                ("uri", Value::from(uri)),
+                ("diagnostics", diagnostics),
+            ]),
+        ),
+    ])
+}
+
+fn zero_range() -> Value {
+    let zero = Value::object(vec![
+        ("line", Value::from(0usize)),
+        ("character", Value::from(0usize)),
+    ]);
+    Value::object(vec![("start", zero.clone()), ("end", zero)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+
+    const MAIN: &str = "file:///brink-lsp-tests/main.bk";
+
+    fn request(id: usize, method: &str, params: &str) -> Value {
+        Value::object(vec![
+            ("jsonrpc", Value::from("2.0")),
+            ("id", Value::from(id)),
+            ("method", Value::from(method)),
+            ("params", json::parse(params).unwrap()),
+        ])
+    }
+
+    fn notification(method: &str, params: &str) -> Value {
+        Value::object(vec![
+            ("jsonrpc", Value::from("2.0")),
+            ("method", Value::from(method)),
+            ("params", json::parse(params).unwrap()),
+        ])
+    }
+
+    fn did_open(text: &str) -> Value {
+        notification(
+            "textDocument/didOpen",
+            &format!(
+                r#"{{"textDocument": {{"uri": "{}", "languageId": "brink", "version": 1, "text": {}}}}}"#,
+                MAIN,
+                Value::from(text)
+            ),
+        )
+    }
+
+    fn at(id: usize, method: &str, line: usize, character: usize) -> Value {
+        request(
+            id,
+            method,
+            &format!(
+                r#"{{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}, "context": {{"includeDeclaration": true}}}}"#,
+                MAIN, line, character
+            ),
+        )
+    }
+
+    /// Runs the server over the framed messages, returning what it wrote and
+    /// its exit code.
+    fn serve(messages: &[Value]) -> (Vec<Value>, i32) {
+        let mut input = Vec::new();
+        for message in messages {
+            transport::write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        let exit_code = Server::new().run(&mut &input[..], &mut output).unwrap();
+        let mut reader = &output[..];
+        let mut outgoing = Vec::new();
+        while let Some(message) = transport::read_message(&mut reader).unwrap() {
+            outgoing.push(message.unwrap());
+        }
+        (outgoing, exit_code)
+    }
+
+    fn response(outgoing: &[Value], id: usize) -> &Value {
+        outgoing
+            .iter()
+            .find(|message| message["id"].as_u64() == Some(id as u64))
+            .map(|message| &message["result"])
+            .unwrap()
+    }
+
+    fn published(outgoing: &[Value]) -> Vec<&Value> {
+        outgoing
+            .iter()
+            .filter(|message| message["method"].as_str() == Some("textDocument/publishDiagnostics"))
+            .map(|message| &message["params"]["diagnostics"])
+            .collect()
+    }
+
+    #[test]
+    fn answers_requests_about_an_open_document() {
+        let text = "let double x = x * 2\nlet four = double 2\n";
+        let (outgoing, exit_code) = serve(&[
+            request(1, "initialize", "{}"),
+            did_open(text),
+            at(2, "textDocument/hover", 1, 12),
+            at(3, "textDocument/definition", 1, 12),
+            at(4, "textDocument/references", 0, 5),
+            request(
+                5,
+                "textDocument/documentSymbol",
+                &format!(r#"{{"textDocument": {{"uri": "{}"}}}}"#, MAIN),
+            ),
+            request(6, "shutdown", "null"),
+            notification("exit", "null"),
+        ]);
+
+        assert_eq!(exit_code, 0);
+        let capabilities = &response(&outgoing, 1)["capabilities"];
+        assert_eq!(capabilities["hoverProvider"].as_bool(), Some(true));
+        assert!(published(&outgoing)[0].as_array().unwrap().is_empty());
+
+        let hover = response(&outgoing, 2)["contents"]["value"]
+            .as_str()
+            .unwrap();
+        assert_eq!(hover, "```brink\ndouble : int -> int\n```");
+
+        let definition = response(&outgoing, 3);
+        assert_eq!(definition["uri"].as_str(), Some(MAIN));
+        assert_eq!(definition["range"]["start"]["line"].as_u64(), Some(0));
+        assert_eq!(definition["range"]["start"]["character"].as_u64(), Some(4));
+
+        let references = response(&outgoing, 4).as_array().unwrap();
+        let lines = references
+            .iter()
+            .map(|location| location["range"]["start"]["line"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [0, 1]);
+
+        let symbols = response(&outgoing, 5).as_array().unwrap();
+        let names = symbols
+            .iter()
+            .map(|symbol| symbol["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["double", "four"]);
+    }
+
+    #[test]
+    fn completes_qualified_names() {
+        let (outgoing, _) = serve(&[
+            did_open("import List\nlet xs = List.\n"),
+            at(1, "textDocument/completion", 1, 14),
+        ]);
+
+        let labels = response(&outgoing, 1)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(labels.contains(&"map"));
+        assert!(!labels.contains(&"let"));
+    }
+
+    #[test]
+    fn classifies_semantic_tokens() {
+        let (outgoing, _) = serve(&[
+            did_open("let n = 1\n"),
+            request(
+                1,
+                "textDocument/semanticTokens/full",
+                &format!(r#"{{"textDocument": {{"uri": "{}"}}}}"#, MAIN),
+            ),
+        ]);
+
+        let data = response(&outgoing, 1)["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_u64().unwrap())
+            .collect::<Vec<_>>();
+        // `let`, `n`, `=` and `1`, five numbers each.
+        assert_eq!(data.len() % 5, 0);
+        assert_eq!(&data[..3], [0, 0, 3]);
+        let keyword = TOKEN_TYPES.iter().position(|name| *name == "keyword");
+        assert_eq!(data.get(3).map(|&index| index as usize), keyword);
+    }
+
+    #[test]
+    fn republishes_diagnostics_on_change() {
+        let (outgoing, exit_code) = serve(&[
+            did_open("let n = 1\n"),
+            notification(
+                "textDocument/didChange",
+                &format!(
+                    r#"{{"textDocument": {{"uri": "{}", "version": 2}}, "contentChanges": [{{"text": "let n = 1 + true\n"}}]}}"#,
+                    MAIN
+                ),
+            ),
+            notification("exit", "null"),
+        ]);
+
+        assert_eq!(exit_code, 1);
+        let published = published(&outgoing);
+        assert_eq!(published.len(), 2);
+        assert!(published[0].as_array().unwrap().is_empty());
+        let diagnostics = published[1].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["severity"].as_u64(), Some(1));
+        assert_eq!(diagnostics[0]["range"]["start"]["line"].as_u64(), Some(0));
+    }
+
+    #[test]
+    fn rejects_unknown_methods() {
+        let (outgoing, _) = serve(&[request(1, "workspace/frobnicate", "{}")]);
+
+        assert_eq!(outgoing[0]["error"]["code"], Value::from(METHOD_NOT_FOUND));
+    }
+
+    #[test]
+    fn answers_malformed_messages_with_a_parse_error() {
+        let mut input = b"Content-Length: 9\r\n\r\n{\"id\": 1,".to_vec();
+        transport::write_message(&mut input, &request(2, "shutdown", "null")).unwrap();
+        transport::write_message(&mut input, &notification("exit", "null")).unwrap();
+        let mut output = Vec::new();
+        let exit_code = Server::new().run(&mut &input[..], &mut output).unwrap();
+        let mut reader = &output[..];
+        let error = transport::read_message(&mut reader)
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(error["id"], Value::Null);
+        assert_eq!(error["error"]["code"], Value::from(PARSE_ERROR));
+        assert_eq!(
+            transport::read_message(&mut reader)
+                .unwrap()
+                .unwrap()
+                .unwrap()["id"],
+            Value::from(2usize)
+        );
+        assert_eq!(exit_code, 0);
+    }
+}
@@ -0,0 +1,104 @@
+//! The base protocol: every message is a JSON value preceded by headers,
+//! of which only `Content-Length` is required.
+
+use std::io::{self, BufRead, Write};
+
+use crate::json::{self, Value};
+
+/// Reads the next message, or `None` at the end of the input. A content
+/// which is not JSON is an error of the message, after which the next one
+/// can still be read, while malformed headers end the input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Result<Value, String>>> {
+    let mut content_length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return match content_length {
+                None => Ok(None),
+                Some(_) => Err(invalid_data("unexpected end of the headers")),
+            };
+        }
+        let header = line.trim_end_matches(&['\r', '\n'][..]);
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                let length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| invalid_data("invalid `Content-Length`"))?;
+                content_length = Some(length);
+            }
+        }
+    }
+
+    let length = content_length.ok_or_else(|| invalid_data("missing `Content-Length`"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    Ok(Some(match String::from_utf8(content) {
+        Ok(content) => json::parse(&content),
+        Err(_) => Err("invalid UTF-8".to_string()),
+    }))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_written_messages() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &Value::from("héllo")).unwrap();
+        write_message(&mut buffer, &Value::from(2usize)).unwrap();
+        let mut reader = &buffer[..];
+
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(Ok(Value::from("héllo")))
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(Ok(Value::from(2usize)))
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn skips_other_headers() {
+        let input = "Content-Type: application/vscode-jsonrpc\r\nContent-Length: 4\r\n\r\nnull";
+
+        assert_eq!(
+            read_message(&mut input.as_bytes()).unwrap(),
+            Some(Ok(Value::Null))
+        );
+    }
+
+    #[test]
+    fn reads_past_malformed_content() {
+        let input = "Content-Length: 5\r\n\r\n{\"a\":Content-Length: 4\r\n\r\ntrue";
+        let mut reader = input.as_bytes();
+
+        assert!(matches!(read_message(&mut reader), Ok(Some(Err(_)))));
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(Ok(Value::from(true)))
+        );
+    }
+}
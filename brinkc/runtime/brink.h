@@ -0,0 +1,170 @@
+/*
+ * The interface of the runtime of the compiled Brink programs: the layout
+ * of the objects, the entry points of the runtime and the helpers of the
+ * C code generated by `--emit=c`.
+ *
+ * Every value is a tagged integer, with its lowest bit set, or a pointer to
+ * an object starting with a header word: the kind in the lowest 8 bits, the
+ * tag in the next 24 and the size in the highest 32. The size is the number
+ * of fields following the header, or the length in bytes of a string. The
+ * integers are shifted left by one and tagged, and the ones needing all 64
+ * bits are boxed. The values of the variant types are tagged unions, with
+ * their constructor as the tag. The unit and the booleans are static
+ * objects.
+ *
+ * The objects are managed by a precise garbage collector, which finds the
+ * values of the compiled functions in their frames on the shadow stack and
+ * may move the objects whenever one is allocated. The compiled code keeps
+ * the values in the slots of the frames rather than in other variables
+ * across the allocations, and stores into the objects allocated before the
+ * last allocation with `brink_write`, which remembers the old objects
+ * pointing to young ones.
+ *
+ * The constants shared with the compiler (the kinds, the tags of the
+ * built-in exceptions and their names) are defined before this file by
+ * the compiler.
+ */
+
+#include <stdint.h>
+
+typedef uint64_t value;
+
+#define IS_INT(v) ((v) & 1)
+#define TAG_INT(n) (((value)(n) << 1) | 1)
+
+#define HEADER(v) (*(uint64_t *)(v))
+#define KIND(v) (IS_INT(v) ? BRINK_KIND_INT : HEADER(v) & 0xff)
+#define TAG(v) ((HEADER(v) >> 8) & 0xffffff)
+#define SIZE(v) (HEADER(v) >> 32)
+#define FIELD(v, i) (((value *)(v))[(i) + 1])
+#define MAKE_HEADER(kind, tag, size) \
+    ((uint64_t)(kind) | ((uint64_t)(tag) << 8) | ((uint64_t)(size) << 32))
+#define STRING_BYTES(v) ((char *)(v) + 8)
+#define UNBOX(v) (IS_INT(v) ? (int64_t)(v) >> 1 : (int64_t)FIELD(v, 0))
+
+/* The closures hold their two entry points before the captured values. */
+#define CLOSURE_ENTRY 0
+#define CLOSURE_CAPTURES 2
+
+typedef value (*entry_fn)(value closure, value *arguments);
+
+/* A closure: the entry taking the arguments in an array, which the runtime
+   calls, the entry taking them as parameters after the closure, and the
+   captured values. */
+struct brink_closure {
+    uint64_t header;
+    entry_fn entry;
+    void (*direct)(void);
+    value captures[];
+};
+
+#define CLOSURE(v) ((struct brink_closure *)(v))
+/* Checks whether the function is a closure taking the number of
+   arguments, whose direct entry can be called. */
+#define IS_CLOSURE_OF(f, arity) \
+    ((HEADER(f) & 0xffffffff) == MAKE_HEADER(BRINK_KIND_CLOSURE, arity, 0))
+
+/* The names of the constructors, exceptions and records of the program,
+   indexed by their tags: the record entries are their field names separated
+   by spaces. Defined by the compiled program. */
+extern const char brink_def_names[];
+/* Evaluates the module-level bindings. Defined by the compiled program. */
+extern value brink_main(void);
+
+/* The static objects are preceded by the flags of the collector, which
+   mark them as static. */
+extern uint64_t brink_unit[2];
+extern uint64_t brink_false[2];
+extern uint64_t brink_true[2];
+/* Returned by the functions of the C code in place of their result to make
+   the tail call stored by `brink_tail_call`. */
+extern uint64_t brink_tail[2];
+
+/* A frame of the shadow stack, holding the values of a function. */
+struct brink_frame {
+    struct brink_frame *previous;
+    uint64_t count;
+    value *roots;
+};
+
+/* The innermost frame of the shadow stack. */
+extern struct brink_frame *brink_frames;
+/* The addresses of the globals of the program holding values, terminated
+   by a null pointer. Defined by the compiled program. */
+extern value *const brink_globals[];
+
+/* The raised exception, checked by the compiled code after the calls
+   which may raise one. */
+extern value brink_exn;
+/* The lowest address of the stack the compiled functions may be called
+   at. */
+extern uintptr_t brink_stack_limit;
+
+#define UNIT ((value)&brink_unit[1])
+#define BOOL(b) ((b) ? (value)&brink_true[1] : (value)&brink_false[1])
+#define IS_TRUE(v) ((v) == (value)&brink_true[1])
+#define BRINK_TAIL ((value)&brink_tail[1])
+
+/* Pushes the frame of the compiled function with the slots of its values,
+   which start as zeros the collector skips. */
+#define ENTER_FRAME(count)                                           \
+    value roots[count] = {0};                                        \
+    struct brink_frame frame = {brink_frames, count, roots};         \
+    brink_frames = &frame
+/* Pops the frame before the function returns the value. */
+#define LEAVE(v) brink_leave(frame.previous, (v))
+
+static inline value brink_leave(struct brink_frame *previous, value result) {
+    brink_frames = previous;
+    return result;
+}
+
+/* The integers wrap around on overflow. */
+#define INT_ADD(a, b) ((int64_t)((uint64_t)(a) + (uint64_t)(b)))
+#define INT_SUB(a, b) ((int64_t)((uint64_t)(a) - (uint64_t)(b)))
+#define INT_MUL(a, b) ((int64_t)((uint64_t)(a) * (uint64_t)(b)))
+#define INT_NEG(a) ((int64_t)(0 - (uint64_t)(a)))
+/* Divides by a divisor other than zero. */
+#define INT_DIV(a, b) ((b) == -1 ? INT_NEG(a) : (a) / (b))
+
+/* Raises `StackOverflow` from the function when the stack is almost
+   exhausted. */
+#define CHECK_STACK()                                     \
+    do {                                                  \
+        char here;                                        \
+        if ((uintptr_t)&here < brink_stack_limit) {       \
+            return brink_stack_overflow();                \
+        }                                                 \
+    } while (0)
+
+value brink_alloc(uint64_t header);
+value brink_box(int64_t n);
+value brink_write(value object, uint64_t index, value v);
+value brink_division_by_zero(void);
+value brink_stack_overflow(void);
+value brink_apply(value function, int64_t count, value *arguments);
+value brink_tail_call(value function, int64_t count, value *arguments);
+value brink_bounce(void);
+int64_t brink_equal(value a, value b);
+
+/* Makes the tail calls the called function returned to make, until one
+   returns its result. */
+static inline value brink_result(value result) {
+    return result == BRINK_TAIL ? brink_bounce() : result;
+}
+
+value brink_ref(value contents);
+value brink_compare(value a, value b);
+value brink_string_length(value string);
+value brink_string_get(value string, value index);
+value brink_string_sub(value string, value start, value length);
+value brink_string_concat(value a, value b);
+value brink_string_concat_list(value list);
+value brink_string_of_char_code(value code);
+value brink_string_of_int(value n);
+value brink_int_of_string(value string);
+value brink_print_string(value string);
+value brink_eprint_string(value string);
+value brink_read_line(value unit);
+value brink_read_file(value path);
+value brink_write_file(value path, value contents);
@@ -0,0 +1,1013 @@
+/*
+ * The runtime of the compiled Brink programs: the allocation, the generic
+ * application of the curried functions, the structural equality and
+ * ordering, the built-in functions and the entry point running the
+ * program. The layout of the values is described in `brink.h`, which is
+ * included before this file by the compiler.
+ *
+ * The exceptions do not unwind the stack: a raised exception is stored in
+ * `brink_exn` and the compiled code checks it after every call which may
+ * raise one, returning to its caller if no handler is active.
+ */
+
+#include <errno.h>
+#include <inttypes.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+uint64_t brink_unit[2] = {BRINK_GC_STATIC, MAKE_HEADER(BRINK_KIND_UNIT, 0, 0)};
+uint64_t brink_false[2] = {BRINK_GC_STATIC, MAKE_HEADER(BRINK_KIND_BOOL, 0, 0)};
+uint64_t brink_true[2] = {BRINK_GC_STATIC, MAKE_HEADER(BRINK_KIND_BOOL, 1, 0)};
+uint64_t brink_tail[2] = {BRINK_GC_STATIC, 0};
+
+value brink_exn;
+uintptr_t brink_stack_limit;
+struct brink_frame *brink_frames;
+
+/* ---- Garbage collection ---- */
+
+/* The objects are allocated in the nursery, and the ones a minor
+   collection finds alive are copied to the tenured generation. The tenured
+   objects are allocated with malloc, after a word of flags, and the major
+   collections mark the ones reachable from the roots and free the others.
+   A major collection follows a minor one when the tenured generation has
+   grown enough since the last one, so the nursery is empty when it marks.
+
+   The roots are the frames of the shadow stack, the globals and the raised
+   exception. The old objects which may point to the nursery are
+   remembered, and their fields are roots of the minor collections too. */
+
+#define NURSERY_SIZE ((size_t)1 << 20)
+/* The objects larger than this are allocated in the tenured generation. */
+#define LARGE_OBJECT_SIZE ((size_t)1 << 12)
+/* The least size of the tenured generation, in bytes, which starts a major
+   collection. */
+#define MAJOR_THRESHOLD ((size_t)8 << 20)
+
+#define GC_MARKED 1
+#define GC_REMEMBERED 2
+#define GC_FLAGS(v) (((uint64_t *)(v))[-1])
+/* The kind of the objects moved out of the nursery, whose header holds
+   their new address above the kind. */
+#define KIND_FORWARDED 0xff
+
+#define IN_NURSERY(v) \
+    (!IS_INT(v) && (char *)(v) >= nursery_start && (char *)(v) < nursery_end)
+
+static char *nursery_start;
+static char *nursery_next;
+static char *nursery_end;
+
+typedef struct {
+    value *items;
+    size_t length;
+    size_t capacity;
+} value_stack;
+
+static void push_value(value_stack *stack, value v) {
+    if (stack->length == stack->capacity) {
+        stack->capacity = stack->capacity ? stack->capacity * 2 : 256;
+        stack->items = realloc(stack->items, stack->capacity * sizeof(value));
+        if (stack->items == NULL) {
+            fputs("out of memory\n", stderr);
+            exit(3);
+        }
+    }
+    stack->items[stack->length++] = v;
+}
+
+/* All the tenured objects. */
+static value_stack tenured;
+/* The tenured objects which may point to the nursery. */
+static value_stack remembered;
+/* The objects whose fields are left to visit by the collection. */
+static value_stack gray;
+static size_t tenured_bytes;
+static size_t major_threshold = MAJOR_THRESHOLD;
+
+static int report_stats;
+static struct {
+    uint64_t minor;
+    uint64_t major;
+    uint64_t allocated;
+    uint64_t promoted;
+    uint64_t freed;
+    double seconds;
+} stats;
+
+/* Gets the size in bytes of the object of the header, with the header and
+   the zero byte terminating the strings. */
+static size_t object_bytes(uint64_t header) {
+    uint64_t size = header >> 32;
+    size_t bytes = (header & 0xff) == BRINK_KIND_STRING ? size + 1 : size * 8;
+    return (8 + bytes + 7) & ~(size_t)7;
+}
+
+/* Gets the fields of the object which hold values: all of them, but the
+   entries of the closures and the contents of the strings and the boxed
+   integers. */
+static void value_fields(value v, uint64_t *first, uint64_t *end) {
+    switch (HEADER(v) & 0xff) {
+    case BRINK_KIND_INT:
+    case BRINK_KIND_STRING:
+        *first = *end = 0;
+        break;
+    case BRINK_KIND_CLOSURE:
+        *first = CLOSURE_CAPTURES;
+        *end = SIZE(v);
+        break;
+    default:
+        *first = 0;
+        *end = SIZE(v);
+    }
+}
+
+static value tenured_alloc(size_t bytes) {
+    uint64_t *words = calloc(1, 8 + bytes);
+    if (words == NULL) {
+        fputs("out of memory\n", stderr);
+        exit(3);
+    }
+    value object = (value)(words + 1);
+    push_value(&tenured, object);
+    tenured_bytes += 8 + bytes;
+    return object;
+}
+
+/* Copies the object out of the nursery, unless it was already, returning
+   its new address. */
+static value promote(value v) {
+    if (!IN_NURSERY(v)) {
+        return v;
+    }
+    if ((HEADER(v) & 0xff) == KIND_FORWARDED) {
+        return HEADER(v) >> 8;
+    }
+    size_t bytes = object_bytes(HEADER(v));
+    value copy = tenured_alloc(bytes);
+    memcpy((void *)copy, (void *)v, bytes);
+    HEADER(v) = ((uint64_t)copy << 8) | KIND_FORWARDED;
+    push_value(&gray, copy);
+    stats.promoted += bytes;
+    return copy;
+}
+
+static void promote_fields(value v) {
+    uint64_t first, end;
+    value_fields(v, &first, &end);
+    for (uint64_t i = first; i < end; i++) {
+        FIELD(v, i) = promote(FIELD(v, i));
+    }
+}
+
+/* Applies the function to the addresses of all the roots. */
+static void visit_roots(void (*visit)(value *)) {
+    for (struct brink_frame *frame = brink_frames; frame != NULL; frame = frame->previous) {
+        for (uint64_t i = 0; i < frame->count; i++) {
+            visit(&frame->roots[i]);
+        }
+    }
+    for (value *const *global = brink_globals; *global != NULL; global++) {
+        visit(*global);
+    }
+    visit(&brink_exn);
+}
+
+static void promote_root(value *root) {
+    *root = promote(*root);
+}
+
+static void minor_collection(void) {
+    visit_roots(promote_root);
+    for (size_t i = 0; i < remembered.length; i++) {
+        GC_FLAGS(remembered.items[i]) &= ~(uint64_t)GC_REMEMBERED;
+        promote_fields(remembered.items[i]);
+    }
+    remembered.length = 0;
+    while (gray.length > 0) {
+        promote_fields(gray.items[--gray.length]);
+    }
+    nursery_next = nursery_start;
+    stats.minor++;
+}
+
+static void mark(value v) {
+    if (v == 0 || IS_INT(v) || (GC_FLAGS(v) & (GC_MARKED | BRINK_GC_STATIC))) {
+        return;
+    }
+    GC_FLAGS(v) |= GC_MARKED;
+    push_value(&gray, v);
+}
+
+static void mark_root(value *root) {
+    mark(*root);
+}
+
+static void major_collection(void) {
+    visit_roots(mark_root);
+    while (gray.length > 0) {
+        value v = gray.items[--gray.length];
+        uint64_t first, end;
+        value_fields(v, &first, &end);
+        for (uint64_t i = first; i < end; i++) {
+            mark(FIELD(v, i));
+        }
+    }
+    size_t kept = 0;
+    for (size_t i = 0; i < tenured.length; i++) {
+        value v = tenured.items[i];
+        if (GC_FLAGS(v) & GC_MARKED) {
+            GC_FLAGS(v) &= ~(uint64_t)GC_MARKED;
+            tenured.items[kept++] = v;
+        } else {
+            size_t bytes = 8 + object_bytes(HEADER(v));
+            tenured_bytes -= bytes;
+            stats.freed += bytes;
+            free(&GC_FLAGS(v));
+        }
+    }
+    tenured.length = kept;
+    major_threshold = 2 * tenured_bytes > MAJOR_THRESHOLD ? 2 * tenured_bytes : MAJOR_THRESHOLD;
+    stats.major++;
+}
+
+static double now(void) {
+    struct timespec time;
+    clock_gettime(CLOCK_MONOTONIC, &time);
+    return (double)time.tv_sec + (double)time.tv_nsec / 1e9;
+}
+
+static void collect(void) {
+    double start = now();
+    minor_collection();
+    if (tenured_bytes > major_threshold) {
+        major_collection();
+    }
+    stats.seconds += now() - start;
+}
+
+static void gc_init(void) {
+    nursery_start = malloc(NURSERY_SIZE);
+    if (nursery_start == NULL) {
+        fputs("out of memory\n", stderr);
+        exit(3);
+    }
+    nursery_next = nursery_start;
+    nursery_end = nursery_start + NURSERY_SIZE;
+    const char *setting = getenv("BRINK_GC_STATS");
+    report_stats = setting != NULL && strcmp(setting, "1") == 0;
+}
+
+/* Reports the statistics of the collector to the standard error, when
+   `BRINK_GC_STATS=1` is set. */
+static void print_gc_stats(void) {
+    if (!report_stats) {
+        return;
+    }
+    fprintf(stderr,
+            "gc: %" PRIu64 " minor collections, %" PRIu64 " major collections, %.3f ms\n"
+            "gc: %" PRIu64 " bytes allocated, %" PRIu64 " promoted, %" PRIu64
+            " freed, %zu tenured\n",
+            stats.minor, stats.major, stats.seconds * 1e3, stats.allocated, stats.promoted,
+            stats.freed, tenured_bytes);
+}
+
+/* Allocates the object of the header, whose fields are zeros until they
+   are initialized. The allocation may collect the garbage, moving the
+   objects of the nursery. */
+value brink_alloc(uint64_t header) {
+    size_t bytes = object_bytes(header);
+    stats.allocated += bytes;
+    value object;
+    if (bytes > LARGE_OBJECT_SIZE) {
+        if (tenured_bytes > major_threshold) {
+            collect();
+        }
+        /* The fields are initialized after the allocation, with values
+           which may be in the nursery. */
+        object = tenured_alloc(bytes);
+        GC_FLAGS(object) |= GC_REMEMBERED;
+        push_value(&remembered, object);
+    } else {
+        if ((size_t)(nursery_end - nursery_next) < bytes) {
+            collect();
+        }
+        object = (value)nursery_next;
+        nursery_next += bytes;
+        memset((void *)object, 0, bytes);
+    }
+    HEADER(object) = header;
+    return object;
+}
+
+/* Stores the value in the field of the object, remembering the object if
+   it is old and the value young. The assignments of the references are
+   the writes of their field 0. */
+value brink_write(value object, uint64_t index, value v) {
+    FIELD(object, index) = v;
+    if (IN_NURSERY(v) && !IN_NURSERY(object) && !(GC_FLAGS(object) & GC_REMEMBERED)) {
+        GC_FLAGS(object) |= GC_REMEMBERED;
+        push_value(&remembered, object);
+    }
+    return UNIT;
+}
+
+static void push_frame(struct brink_frame *frame, value *roots, uint64_t count) {
+    frame->previous = brink_frames;
+    frame->count = count;
+    frame->roots = roots;
+    brink_frames = frame;
+}
+
+static void pop_frame(struct brink_frame *frame) {
+    brink_frames = frame->previous;
+}
+
+/* Boxes the integer, for the values stored in the data structures: it is
+   tagged, unless it needs all 64 bits. */
+value brink_box(int64_t n) {
+    if (n >= -((int64_t)1 << 62) && n < ((int64_t)1 << 62)) {
+        return TAG_INT(n);
+    }
+    value object = brink_alloc(MAKE_HEADER(BRINK_KIND_INT, 0, 1));
+    FIELD(object, 0) = (value)n;
+    return object;
+}
+
+/* Allocates the string of the bytes, which are not in an object since it
+   may move. */
+static value new_string(const char *bytes, size_t length) {
+    value string = brink_alloc(MAKE_HEADER(BRINK_KIND_STRING, 0, length));
+    memcpy(STRING_BYTES(string), bytes, length);
+    STRING_BYTES(string)[length] = '\0';
+    return string;
+}
+
+/* ---- Exceptions ---- */
+
+static value raise_builtin(uint32_t tag, const char *message) {
+    if (message == NULL) {
+        brink_exn = brink_alloc(MAKE_HEADER(BRINK_KIND_VARIANT, tag, 0));
+    } else {
+        /* The message is the raised exception until the exception is
+           allocated, so it is a root. */
+        brink_exn = new_string(message, strlen(message));
+        value exception = brink_alloc(MAKE_HEADER(BRINK_KIND_VARIANT, tag, 1));
+        FIELD(exception, 0) = brink_exn;
+        brink_exn = exception;
+    }
+    return 0;
+}
+
+/* Raises `DivisionByZero`, for the compiled division. */
+value brink_division_by_zero(void) {
+    return raise_builtin(BRINK_EXN_DIVISION_BY_ZERO, NULL);
+}
+
+/* Raises `StackOverflow`, for the compiled functions called with too
+   little of the stack left. */
+value brink_stack_overflow(void) {
+    return raise_builtin(BRINK_EXN_STACK_OVERFLOW, NULL);
+}
+
+static value failure(const char *message) {
+    return raise_builtin(BRINK_EXN_FAILURE, message);
+}
+
+static value invalid_argument(const char *message) {
+    return raise_builtin(BRINK_EXN_INVALID_ARGUMENT, message);
+}
+
+/* Raises `Failure` with the message of the I/O error, the way the
+   interpreter formats it. */
+static value io_failure(value path) {
+    char message[1024];
+    int error = errno;
+    snprintf(message, sizeof message, "%s: %s (os error %d)", STRING_BYTES(path),
+             strerror(error), error);
+    return failure(message);
+}
+
+/* ---- Application ---- */
+
+/* Applies the function to the arguments one by one. The compiled code
+   calls the closures of the arity of the application directly, and falls
+   back to this for the partial applications and the applications to more
+   arguments than the function takes. The function and the arguments left
+   are roots, since the applications may move them. Returns `BRINK_TAIL` if
+   the last function applied returned it. */
+static value apply(value function, int64_t count, value *arguments) {
+    struct brink_frame function_frame;
+    struct brink_frame arguments_frame;
+    push_frame(&function_frame, &function, 1);
+    push_frame(&arguments_frame, arguments, count);
+    value result;
+    for (;;) {
+        if (KIND(function) == BRINK_KIND_PARTIAL) {
+            /* A partial application holds the closure and the arguments it
+               was given. */
+            uint64_t given = SIZE(function) - 1;
+            value all[given + count];
+            memcpy(all, &FIELD(function, 1), given * sizeof(value));
+            memcpy(all + given, arguments, count * sizeof(value));
+            result = apply(FIELD(function, 0), given + count, all);
+            break;
+        }
+        int64_t arity = TAG(function);
+        entry_fn entry = (entry_fn)FIELD(function, CLOSURE_ENTRY);
+        if (count == arity) {
+            result = entry(function, arguments);
+            break;
+        }
+        if (count < arity) {
+            result = brink_alloc(MAKE_HEADER(BRINK_KIND_PARTIAL, arity - count, count + 1));
+            FIELD(result, 0) = function;
+            memcpy(&FIELD(result, 1), arguments, count * sizeof(value));
+            break;
+        }
+        function = brink_result(entry(function, arguments));
+        if (brink_exn != 0) {
+            result = 0;
+            break;
+        }
+        arguments += arity;
+        count -= arity;
+        arguments_frame.roots = arguments;
+        arguments_frame.count = count;
+    }
+    pop_frame(&arguments_frame);
+    pop_frame(&function_frame);
+    return result;
+}
+
+value brink_apply(value function, int64_t count, value *arguments) {
+    return brink_result(apply(function, count, arguments));
+}
+
+/* The tail call a function of the C code returned `BRINK_TAIL` to make.
+   The C code has no guaranteed tail calls, so the functions return to the
+   trampoline of their nearest caller not in tail position, `brink_bounce`,
+   which makes the call. Nothing is allocated in between, so the values are
+   not roots. */
+static value tail_function;
+static int64_t tail_count;
+static value *tail_arguments;
+static int64_t tail_capacity;
+
+/* Stores the tail call of the function to the arguments, returning
+   `BRINK_TAIL`. */
+value brink_tail_call(value function, int64_t count, value *arguments) {
+    if (count > tail_capacity) {
+        tail_capacity = count;
+        tail_arguments = realloc(tail_arguments, count * sizeof(value));
+        if (tail_arguments == NULL) {
+            fputs("out of memory\n", stderr);
+            exit(3);
+        }
+    }
+    tail_function = function;
+    tail_count = count;
+    memcpy(tail_arguments, arguments, count * sizeof(value));
+    return BRINK_TAIL;
+}
+
+/* Makes the stored tail call and the ones it returns to make, in constant
+   space. */
+value brink_bounce(void) {
+    value result;
+    do {
+        int64_t count = tail_count;
+        value arguments[count];
+        memcpy(arguments, tail_arguments, count * sizeof(value));
+        result = apply(tail_function, count, arguments);
+    } while (result == BRINK_TAIL);
+    return result;
+}
+
+/* ---- Equality and ordering ---- */
+
+static int is_function(value v) {
+    return KIND(v) == BRINK_KIND_CLOSURE || KIND(v) == BRINK_KIND_PARTIAL;
+}
+
+/* Compares the values structurally. The functions are only equal to
+   themselves, and the references are equal if their contents are. */
+int64_t brink_equal(value a, value b) {
+    for (;;) {
+        if (a == b) {
+            return 1;
+        }
+        if (KIND(a) != KIND(b)) {
+            return 0;
+        }
+        switch (KIND(a)) {
+        case BRINK_KIND_INT:
+            return UNBOX(a) == UNBOX(b);
+        case BRINK_KIND_STRING:
+            return SIZE(a) == SIZE(b) && memcmp(STRING_BYTES(a), STRING_BYTES(b), SIZE(a)) == 0;
+        case BRINK_KIND_UNIT:
+        case BRINK_KIND_NIL:
+            return 1;
+        case BRINK_KIND_REF:
+            a = FIELD(a, 0);
+            b = FIELD(b, 0);
+            continue;
+        case BRINK_KIND_CLOSURE:
+        case BRINK_KIND_PARTIAL:
+            return 0;
+        default:
+            break;
+        }
+        /* The blocks of fields, the last of which is compared in the loop
+           so the lists are not compared recursively. */
+        if (HEADER(a) != HEADER(b)) {
+            return 0;
+        }
+        uint64_t size = SIZE(a);
+        if (size == 0) {
+            return 1;
+        }
+        for (uint64_t i = 0; i + 1 < size; i++) {
+            if (!brink_equal(FIELD(a, i), FIELD(b, i))) {
+                return 0;
+            }
+        }
+        a = FIELD(a, size - 1);
+        b = FIELD(b, size - 1);
+    }
+}
+
+#define INCOMPARABLE 2
+
+static int compare_ints(int64_t a, int64_t b) {
+    return (a > b) - (a < b);
+}
+
+/* Orders the values structurally, the way the interpreter does, or
+   returns `INCOMPARABLE` if they contain a function. */
+static int compare_values(value a, value b) {
+    for (;;) {
+        if (is_function(a) || is_function(b)) {
+            return INCOMPARABLE;
+        }
+        int kind = KIND(a);
+        if (kind != (int)KIND(b)) {
+            /* Only the lists have values of different kinds. */
+            return kind == BRINK_KIND_NIL ? -1 : 1;
+        }
+        switch (kind) {
+        case BRINK_KIND_INT:
+            return compare_ints(UNBOX(a), UNBOX(b));
+        case BRINK_KIND_BOOL:
+            return compare_ints(TAG(a), TAG(b));
+        case BRINK_KIND_STRING: {
+            uint64_t length = SIZE(a) < SIZE(b) ? SIZE(a) : SIZE(b);
+            int ordering = memcmp(STRING_BYTES(a), STRING_BYTES(b), length);
+            if (ordering != 0) {
+                return ordering < 0 ? -1 : 1;
+            }
+            return compare_ints(SIZE(a), SIZE(b));
+        }
+        case BRINK_KIND_UNIT:
+        case BRINK_KIND_NIL:
+            return 0;
+        case BRINK_KIND_REF:
+            a = FIELD(a, 0);
+            b = FIELD(b, 0);
+            continue;
+        case BRINK_KIND_CONS: {
+            int ordering = compare_values(FIELD(a, 0), FIELD(b, 0));
+            if (ordering != 0) {
+                return ordering;
+            }
+            a = FIELD(a, 1);
+            b = FIELD(b, 1);
+            continue;
+        }
+        case BRINK_KIND_VARIANT:
+            if (TAG(a) != TAG(b)) {
+                return TAG(a) < TAG(b) ? -1 : 1;
+            }
+            break;
+        default:
+            break;
+        }
+        uint64_t size = SIZE(a) < SIZE(b) ? SIZE(a) : SIZE(b);
+        for (uint64_t i = 0; i < size; i++) {
+            int ordering = compare_values(FIELD(a, i), FIELD(b, i));
+            if (ordering != 0) {
+                return ordering;
+            }
+        }
+        return compare_ints(SIZE(a), SIZE(b));
+    }
+}
+
+/* ---- Built-in functions ---- */
+
+value brink_ref(value contents) {
+    struct brink_frame frame;
+    push_frame(&frame, &contents, 1);
+    value ref = brink_alloc(MAKE_HEADER(BRINK_KIND_REF, 0, 1));
+    pop_frame(&frame);
+    FIELD(ref, 0) = contents;
+    return ref;
+}
+
+value brink_compare(value a, value b) {
+    int ordering = compare_values(a, b);
+    if (ordering == INCOMPARABLE) {
+        return invalid_argument("compare: functional value");
+    }
+    return brink_box(ordering);
+}
+
+value brink_string_length(value string) {
+    return brink_box((int64_t)SIZE(string));
+}
+
+value brink_string_get(value string, value index) {
+    int64_t i = UNBOX(index);
+    if (i < 0 || (uint64_t)i >= SIZE(string)) {
+        return invalid_argument("string_get");
+    }
+    return brink_box((unsigned char)STRING_BYTES(string)[i]);
+}
+
+/* Checks whether the byte index is at the start of a character. */
+static int is_char_boundary(value string, uint64_t index) {
+    return index == SIZE(string) ||
+           (index < SIZE(string) && ((unsigned char)STRING_BYTES(string)[index] & 0xc0) != 0x80);
+}
+
+value brink_string_sub(value string, value start, value length) {
+    int64_t from = UNBOX(start);
+    int64_t count = UNBOX(length);
+    if (from < 0 || count < 0 || (uint64_t)from > SIZE(string) ||
+        (uint64_t)count > SIZE(string) - (uint64_t)from ||
+        !is_char_boundary(string, from) || !is_char_boundary(string, from + count)) {
+        return invalid_argument("string_sub");
+    }
+    struct brink_frame frame;
+    push_frame(&frame, &string, 1);
+    value sub = brink_alloc(MAKE_HEADER(BRINK_KIND_STRING, 0, count));
+    pop_frame(&frame);
+    memcpy(STRING_BYTES(sub), STRING_BYTES(string) + from, count);
+    STRING_BYTES(sub)[count] = '\0';
+    return sub;
+}
+
+value brink_string_concat(value a, value b) {
+    value strings[2] = {a, b};
+    struct brink_frame frame;
+    push_frame(&frame, strings, 2);
+    value string = brink_alloc(MAKE_HEADER(BRINK_KIND_STRING, 0, SIZE(a) + SIZE(b)));
+    pop_frame(&frame);
+    a = strings[0];
+    b = strings[1];
+    memcpy(STRING_BYTES(string), STRING_BYTES(a), SIZE(a));
+    memcpy(STRING_BYTES(string) + SIZE(a), STRING_BYTES(b), SIZE(b));
+    STRING_BYTES(string)[SIZE(a) + SIZE(b)] = '\0';
+    return string;
+}
+
+/* Concatenates the strings of the list, summing their lengths first. */
+value brink_string_concat_list(value list) {
+    uint64_t length = 0;
+    for (value cell = list; KIND(cell) == BRINK_KIND_CONS; cell = FIELD(cell, 1)) {
+        length += SIZE(FIELD(cell, 0));
+    }
+    struct brink_frame frame;
+    push_frame(&frame, &list, 1);
+    value string = brink_alloc(MAKE_HEADER(BRINK_KIND_STRING, 0, length));
+    pop_frame(&frame);
+    char *bytes = STRING_BYTES(string);
+    for (value cell = list; KIND(cell) == BRINK_KIND_CONS; cell = FIELD(cell, 1)) {
+        value part = FIELD(cell, 0);
+        memcpy(bytes, STRING_BYTES(part), SIZE(part));
+        bytes += SIZE(part);
+    }
+    *bytes = '\0';
+    return string;
+}
+
+value brink_string_of_char_code(value code) {
+    int64_t c = UNBOX(code);
+    char bytes[4];
+    size_t length;
+    if (c < 0 || c > 0x10ffff || (c >= 0xd800 && c <= 0xdfff)) {
+        return invalid_argument("string_of_char_code");
+    } else if (c < 0x80) {
+        bytes[0] = (char)c;
+        length = 1;
+    } else if (c < 0x800) {
+        bytes[0] = (char)(0xc0 | (c >> 6));
+        bytes[1] = (char)(0x80 | (c & 0x3f));
+        length = 2;
+    } else if (c < 0x10000) {
+        bytes[0] = (char)(0xe0 | (c >> 12));
+        bytes[1] = (char)(0x80 | ((c >> 6) & 0x3f));
+        bytes[2] = (char)(0x80 | (c & 0x3f));
+        length = 3;
+    } else {
+        bytes[0] = (char)(0xf0 | (c >> 18));
+        bytes[1] = (char)(0x80 | ((c >> 12) & 0x3f));
+        bytes[2] = (char)(0x80 | ((c >> 6) & 0x3f));
+        bytes[3] = (char)(0x80 | (c & 0x3f));
+        length = 4;
+    }
+    return new_string(bytes, length);
+}
+
+value brink_string_of_int(value n) {
+    char bytes[24];
+    int length = snprintf(bytes, sizeof bytes, "%" PRId64, UNBOX(n));
+    return new_string(bytes, length);
+}
+
+/* Parses the decimal integer with an optional sign, like the interpreter
+   does, without any whitespace. */
+value brink_int_of_string(value string) {
+    const char *bytes = STRING_BYTES(string);
+    uint64_t length = SIZE(string);
+    uint64_t i = 0;
+    int negative = 0;
+    if (length > 0 && (bytes[0] == '+' || bytes[0] == '-')) {
+        negative = bytes[0] == '-';
+        i = 1;
+    }
+    if (i == length) {
+        return failure("int_of_string");
+    }
+    /* Accumulates the negated value, whose range includes the minimum. */
+    int64_t n = 0;
+    for (; i < length; i++) {
+        if (bytes[i] < '0' || bytes[i] > '9') {
+            return failure("int_of_string");
+        }
+        int digit = bytes[i] - '0';
+        if (n < (INT64_MIN + digit) / 10) {
+            return failure("int_of_string");
+        }
+        n = n * 10 - digit;
+    }
+    if (!negative) {
+        if (n == INT64_MIN) {
+            return failure("int_of_string");
+        }
+        n = -n;
+    }
+    return brink_box(n);
+}
+
+value brink_print_string(value string) {
+    fwrite(STRING_BYTES(string), 1, SIZE(string), stdout);
+    return UNIT;
+}
+
+value brink_eprint_string(value string) {
+    fflush(stdout);
+    fwrite(STRING_BYTES(string), 1, SIZE(string), stderr);
+    return UNIT;
+}
+
+value brink_read_line(value unit) {
+    (void)unit;
+    fflush(stdout);
+    char *line = NULL;
+    size_t capacity = 0;
+    errno = 0;
+    ssize_t length = getline(&line, &capacity, stdin);
+    if (length < 0) {
+        free(line);
+        if (errno != 0) {
+            char message[256];
+            snprintf(message, sizeof message, "%s (os error %d)", strerror(errno), errno);
+            return failure(message);
+        }
+        return raise_builtin(BRINK_EXN_END_OF_FILE, NULL);
+    }
+    while (length > 0 && (line[length - 1] == '\n' || line[length - 1] == '\r')) {
+        length--;
+    }
+    value string = new_string(line, length);
+    free(line);
+    return string;
+}
+
+value brink_read_file(value path) {
+    FILE *file = fopen(STRING_BYTES(path), "rb");
+    if (file == NULL) {
+        return io_failure(path);
+    }
+    size_t length = 0;
+    size_t capacity = 4096;
+    char *bytes = malloc(capacity);
+    size_t read;
+    while ((read = fread(bytes + length, 1, capacity - length, file)) > 0) {
+        length += read;
+        if (length == capacity) {
+            capacity *= 2;
+            bytes = realloc(bytes, capacity);
+        }
+    }
+    int failed = ferror(file);
+    fclose(file);
+    value contents = failed ? io_failure(path) : new_string(bytes, length);
+    free(bytes);
+    return contents;
+}
+
+value brink_write_file(value path, value contents) {
+    FILE *file = fopen(STRING_BYTES(path), "wb");
+    if (file == NULL) {
+        return io_failure(path);
+    }
+    size_t written = fwrite(STRING_BYTES(contents), 1, SIZE(contents), file);
+    if (fclose(file) != 0 || written != SIZE(contents)) {
+        return io_failure(path);
+    }
+    return UNIT;
+}
+
+/* ---- Uncaught exceptions ---- */
+
+typedef struct {
+    char *bytes;
+    size_t length;
+    size_t capacity;
+} buffer;
+
+static void push(buffer *output, const char *bytes, size_t length) {
+    if (output->length + length + 1 > output->capacity) {
+        output->capacity = (output->length + length + 1) * 2;
+        output->bytes = realloc(output->bytes, output->capacity);
+    }
+    memcpy(output->bytes + output->length, bytes, length);
+    output->length += length;
+    output->bytes[output->length] = '\0';
+}
+
+static void push_str(buffer *output, const char *s) {
+    push(output, s, strlen(s));
+}
+
+/* Gets the entry of the tag in the names of the program, or of the
+   built-in exceptions. */
+static const char *tag_name(uint64_t tag) {
+    if (tag >= BRINK_BUILTIN_TAG) {
+        return brink_builtin_names[tag - BRINK_BUILTIN_TAG];
+    }
+    const char *name = brink_def_names;
+    for (uint64_t i = 0; i < tag; i++) {
+        name += strlen(name) + 1;
+    }
+    return name;
+}
+
+static void write_value(buffer *output, value v, int nested);
+
+static void write_separated(buffer *output, value v, uint64_t first, const char *separator) {
+    for (uint64_t i = first; i < SIZE(v); i++) {
+        if (i > first) {
+            push_str(output, separator);
+        }
+        write_value(output, FIELD(v, i), 0);
+    }
+}
+
+/* Formats the value the way it would be written in the source code, like
+   the interpreter does. */
+static void write_value(buffer *output, value v, int nested) {
+    char number[24];
+    switch (KIND(v)) {
+    case BRINK_KIND_INT:
+        snprintf(number, sizeof number, nested && UNBOX(v) < 0 ? "(%" PRId64 ")" : "%" PRId64,
+                 UNBOX(v));
+        push_str(output, number);
+        break;
+    case BRINK_KIND_STRING:
+        push_str(output, "\"");
+        for (uint64_t i = 0; i < SIZE(v); i++) {
+            char c = STRING_BYTES(v)[i];
+            switch (c) {
+            case '\n': push_str(output, "\\n"); break;
+            case '\t': push_str(output, "\\t"); break;
+            case '\r': push_str(output, "\\r"); break;
+            case '\\': push_str(output, "\\\\"); break;
+            case '"': push_str(output, "\\\""); break;
+            default: push(output, &c, 1);
+            }
+        }
+        push_str(output, "\"");
+        break;
+    case BRINK_KIND_UNIT:
+        push_str(output, "()");
+        break;
+    case BRINK_KIND_BOOL:
+        push_str(output, TAG(v) ? "true" : "false");
+        break;
+    case BRINK_KIND_TUPLE:
+        push_str(output, "(");
+        write_separated(output, v, 0, ", ");
+        push_str(output, ")");
+        break;
+    case BRINK_KIND_RECORD: {
+        const char *names = tag_name(TAG(v));
+        push_str(output, "{ ");
+        for (uint64_t i = 0; i < SIZE(v); i++) {
+            if (i > 0) {
+                push_str(output, "; ");
+            }
+            size_t length = strcspn(names, " ");
+            push(output, names, length);
+            names += names[length] == ' ' ? length + 1 : length;
+            push_str(output, " = ");
+            write_value(output, FIELD(v, i), 0);
+        }
+        push_str(output, " }");
+        break;
+    }
+    case BRINK_KIND_VARIANT:
+        if (nested && SIZE(v) > 0) {
+            push_str(output, "(");
+        }
+        push_str(output, tag_name(TAG(v)));
+        for (uint64_t i = 0; i < SIZE(v); i++) {
+            push_str(output, " ");
+            write_value(output, FIELD(v, i), 1);
+        }
+        if (nested && SIZE(v) > 0) {
+            push_str(output, ")");
+        }
+        break;
+    case BRINK_KIND_NIL:
+    case BRINK_KIND_CONS:
+        push_str(output, "[");
+        for (value cell = v; KIND(cell) == BRINK_KIND_CONS; cell = FIELD(cell, 1)) {
+            if (cell != v) {
+                push_str(output, "; ");
+            }
+            write_value(output, FIELD(cell, 0), 0);
+        }
+        push_str(output, "]");
+        break;
+    case BRINK_KIND_ARRAY:
+        push_str(output, "[|");
+        write_separated(output, v, 0, "; ");
+        push_str(output, "|]");
+        break;
+    case BRINK_KIND_REF:
+        push_str(output, nested ? "(ref " : "ref ");
+        write_value(output, FIELD(v, 0), 1);
+        if (nested) {
+            push_str(output, ")");
+        }
+        break;
+    default:
+        push_str(output, "<fun>");
+    }
+}
+
+/* ---- Entry point ---- */
+
+#define STACK_SIZE ((size_t)1 << 30)
+/* The stack left for the runtime functions when the compiled ones report
+   the stack overflow. */
+#define STACK_RESERVE ((size_t)1 << 20)
+
+static void *run(void *unused) {
+    (void)unused;
+    char base;
+    brink_stack_limit = (uintptr_t)&base - STACK_SIZE + STACK_RESERVE;
+    gc_init();
+    brink_main();
+    fflush(stdout);
+    if (brink_exn != 0) {
+        buffer report = {NULL, 0, 0};
+        push_str(&report, "uncaught exception ");
+        write_value(&report, brink_exn, 0);
+        fprintf(stderr, "%s\n", report.bytes);
+        print_gc_stats();
+        exit(2);
+    }
+    print_gc_stats();
+    return NULL;
+}
+
+/* Runs the program on a thread with a deep stack, like the interpreter. */
+int main(void) {
+    pthread_attr_t attributes;
+    pthread_t thread;
+    pthread_attr_init(&attributes);
+    pthread_attr_setstacksize(&attributes, STACK_SIZE);
+    if (pthread_create(&thread, &attributes, run, NULL) != 0) {
+        fputs("failed to create the main thread\n", stderr);
+        return 3;
+    }
+    pthread_join(thread, NULL);
+    return 0;
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+
+use crate::{
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    source_file::SourceSpan,
+};
+
+use super::{Atom, Block, Function, GlobalValue, Program, Repr, Stmt, Value};
+
+/// An inconsistency in the program, which is a bug of the conversion or of
+/// the pass producing it.
+#[derive(Debug)]
+pub struct CheckError {
+    /// The global the error is in, e.g. `main/3`.
+    pub global: String,
+    pub span: SourceSpan,
+    pub message: String,
+}
+
+/// Checks that the program is well-formed: every variable is bound once and
+/// used in its scope, the representations of the operands are the ones the
+/// operations take, the known calls have the arity of the functions and the
+/// fields are read from the values known to be built by the constructor.
+/// Meant to run after each pass.
+pub fn check(program: &Program) -> Result<(), Vec<CheckError>> {
+    let mut checker = Checker {
+        program,
+        bound: vec![false; program.vars.len()],
+        in_scope: vec![false; program.vars.len()],
+        arities: HashMap::new(),
+        known_cons: HashMap::new(),
+        global: String::new(),
+        global_span: SourceSpan::new(0, 0),
+        errors: Vec::new(),
+    };
+    for global in &program.globals {
+        checker.bind(global.var);
+        if let GlobalValue::Function(function) = &global.value {
+            checker
+                .arities
+                .insert(global.var, function.parameters.len());
+        }
+    }
+    for global in &program.globals {
+        checker.global = format!("{}/{}", program.var(global.var).name, global.var.as_usize());
+        checker.global_span = program.var(global.var).span;
+        match &global.value {
+            GlobalValue::Function(function) => checker.function(function),
+            GlobalValue::Block(block) => checker.block(block, Some(Repr::Value)),
+        }
+    }
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+struct Checker<'a> {
+    program: &'a Program,
+    bound: Vec<bool>,
+    in_scope: Vec<bool>,
+    /// The numbers of the parameters of the functions the variables are
+    /// bound to.
+    arities: HashMap<VarId, usize>,
+    /// The constructors the values of the variables are known to be built
+    /// by, which are either tested by an enclosing switch or bound.
+    known_cons: HashMap<VarId, Con>,
+    global: String,
+    /// The span of the global, for the errors in the blocks without
+    /// bindings.
+    global_span: SourceSpan,
+    errors: Vec<CheckError>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, span: SourceSpan, message: impl Into<String>) {
+        self.errors.push(CheckError {
+            global: self.global.clone(),
+            span,
+            message: message.into(),
+        });
+    }
+
+    fn name(&self, var: VarId) -> String {
+        format!("{}/{}", self.program.var(var).name, var.as_usize())
+    }
+
+    fn bind(&mut self, var: VarId) {
+        if self.bound[var.as_usize()] {
+            let message = format!("`{}` is bound twice", self.name(var));
+            self.error(self.program.var(var).span, message);
+        }
+        self.bound[var.as_usize()] = true;
+        self.in_scope[var.as_usize()] = true;
+    }
+
+    fn unbind(&mut self, var: VarId) {
+        self.in_scope[var.as_usize()] = false;
+        self.arities.remove(&var);
+        self.known_cons.remove(&var);
+    }
+
+    fn function(&mut self, function: &Function) {
+        for parameter in &function.parameters {
+            self.bind(*parameter);
+            self.expect_var_repr(*parameter, Repr::Value);
+        }
+        self.block(&function.body, Some(Repr::Value));
+        for parameter in &function.parameters {
+            self.unbind(*parameter);
+        }
+    }
+
+    /// Checks the statements of the block and its result, which has to have
+    /// the representation if one is given, and takes the bindings out of
+    /// the scope.
+    fn block(&mut self, block: &Block, repr: Option<Repr>) {
+        let mut bound = Vec::new();
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(var, value) => {
+                    let span = self.program.var(*var).span;
+                    let repr = self.value(value, *var, span);
+                    self.bind(*var);
+                    bound.push(*var);
+                    if let Some(repr) = repr {
+                        self.expect_var_repr(*var, repr);
+                    }
+                    match value {
+                        Value::Lambda(function) => {
+                            self.arities.insert(*var, function.parameters.len());
+                        }
+                        Value::Construct(con, _) => {
+                            self.known_cons.insert(*var, *con);
+                        }
+                        _ => {}
+                    }
+                }
+                Stmt::LetRec(functions) => {
+                    for (var, function) in functions {
+                        self.bind(*var);
+                        bound.push(*var);
+                        self.expect_var_repr(*var, Repr::Value);
+                        self.arities.insert(*var, function.parameters.len());
+                    }
+                    for (_, function) in functions {
+                        self.function(function);
+                    }
+                }
+            }
+        }
+        let span = self.block_span(block);
+        match repr {
+            Some(repr) => self.expect_atom(&block.result, repr, span),
+            None => self.use_atom(&block.result, span),
+        }
+        for var in bound {
+            self.unbind(var);
+        }
+    }
+
+    fn block_span(&self, block: &Block) -> SourceSpan {
+        let var = match (&block.result, block.stmts.last()) {
+            (Atom::Var(var), _) => *var,
+            (_, Some(Stmt::Let(var, _))) => *var,
+            (_, Some(Stmt::LetRec(functions))) => functions[0].0,
+            (_, None) => return self.global_span,
+        };
+        self.program.var(var).span
+    }
+
+    /// Checks the value bound to the variable and gets its representation,
+    /// if it is not a join checked against the one of the variable.
+    fn value(&mut self, value: &Value, var: VarId, span: SourceSpan) -> Option<Repr> {
+        let repr = self.program.var(var).repr;
+        let repr = match value {
+            Value::Atom(atom) => {
+                self.use_atom(atom, span);
+                self.repr(atom)
+            }
+            Value::Lambda(function) => {
+                self.function(function);
+                Repr::Value
+            }
+            Value::Apply(function, arguments) => {
+                self.expect_atom(function, Repr::Value, span);
+                self.expect_atoms(arguments, Repr::Value, span);
+                Repr::Value
+            }
+            Value::Call(function, arguments) => {
+                self.use_atom(&Atom::Var(*function), span);
+                match self.arities.get(function) {
+                    Some(arity) if *arity == arguments.len() => {}
+                    Some(arity) => {
+                        let message = format!(
+                            "call of `{}` with {} arguments instead of {}",
+                            self.name(*function),
+                            arguments.len(),
+                            arity
+                        );
+                        self.error(span, message);
+                    }
+                    None => {
+                        let message =
+                            format!("`{}` is not known to be a function", self.name(*function));
+                        self.error(span, message);
+                    }
+                }
+                self.expect_atoms(arguments, Repr::Value, span);
+                Repr::Value
+            }
+            Value::Prim(op, arguments) => {
+                if arguments.len() != op.arity() {
+                    let message = format!(
+                        "`%{}` applied to {} arguments instead of {}",
+                        op.name(),
+                        arguments.len(),
+                        op.arity()
+                    );
+                    self.error(span, message);
+                }
+                match op {
+                    PrimOp::Add
+                    | PrimOp::Subtract
+                    | PrimOp::Multiply
+                    | PrimOp::Divide
+                    | PrimOp::Negate => {
+                        self.expect_atoms(arguments, Repr::Int, span);
+                        Repr::Int
+                    }
+                    PrimOp::Less | PrimOp::LessEqual | PrimOp::Greater | PrimOp::GreaterEqual => {
+                        self.expect_atoms(arguments, Repr::Int, span);
+                        Repr::Value
+                    }
+                    PrimOp::Equal | PrimOp::NotEqual => {
+                        for argument in arguments {
+                            self.use_atom(argument, span);
+                        }
+                        let reprs = arguments.iter().map(|a| self.repr(a)).collect::<Vec<_>>();
+                        if reprs.windows(2).any(|pair| pair[0] != pair[1]) {
+                            self.error(span, "comparison of different representations");
+                        }
+                        Repr::Value
+                    }
+                    _ => {
+                        self.expect_atoms(arguments, Repr::Value, span);
+                        Repr::Value
+                    }
+                }
+            }
+            Value::Box(atom) => {
+                self.expect_atom(atom, Repr::Int, span);
+                Repr::Value
+            }
+            Value::Unbox(atom) => {
+                self.expect_atom(atom, Repr::Value, span);
+                Repr::Int
+            }
+            Value::Construct(_, fields) => {
+                self.expect_atoms(fields, Repr::Value, span);
+                Repr::Value
+            }
+            Value::Field(atom, con, _) => {
+                self.expect_atom(atom, Repr::Value, span);
+                let known = match atom {
+                    Atom::Var(var) => self.known_cons.get(var) == Some(con),
+                    Atom::Literal(_) => false,
+                };
+                if !con.is_irrefutable() && !known {
+                    self.error(
+                        span,
+                        "read of a field of a value not known to be built by the constructor",
+                    );
+                }
+                Repr::Value
+            }
+            Value::If(condition, then_block, else_block) => {
+                self.expect_atom(condition, Repr::Value, span);
+                self.block(then_block, Some(repr));
+                self.block(else_block, Some(repr));
+                return None;
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.use_atom(scrutinee, span);
+                let scrutinee_repr = self.repr(scrutinee);
+                for (test, block) in cases {
+                    let known = match (test, scrutinee) {
+                        (Test::Con(con), Atom::Var(var)) => self.known_cons.insert(*var, *con),
+                        _ => None,
+                    };
+                    let test_repr = match test {
+                        Test::Literal(Literal::Int(_)) => scrutinee_repr,
+                        _ => Repr::Value,
+                    };
+                    if test_repr != scrutinee_repr {
+                        self.error(span, "switch on an unboxed integer tests a constructor");
+                    }
+                    self.block(block, Some(repr));
+                    if let (Test::Con(_), Atom::Var(var)) = (test, scrutinee) {
+                        match known {
+                            Some(known) => self.known_cons.insert(*var, known),
+                            None => self.known_cons.remove(var),
+                        };
+                    }
+                }
+                if let Some(default) = default {
+                    self.block(default, Some(repr));
+                }
+                return None;
+            }
+            Value::While(condition, body) => {
+                self.block(condition, Some(Repr::Value));
+                self.block(body, None);
+                Repr::Value
+            }
+            Value::For(counter, start, _, end, body) => {
+                self.expect_atom(start, Repr::Int, span);
+                self.expect_atom(end, Repr::Int, span);
+                self.bind(*counter);
+                self.expect_var_repr(*counter, Repr::Int);
+                self.block(body, None);
+                self.unbind(*counter);
+                Repr::Value
+            }
+            Value::Try(body, exception, handler) => {
+                self.block(body, Some(repr));
+                self.bind(*exception);
+                self.expect_var_repr(*exception, Repr::Value);
+                self.block(handler, Some(repr));
+                self.unbind(*exception);
+                return None;
+            }
+        };
+        Some(repr)
+    }
+
+    fn repr(&self, atom: &Atom) -> Repr {
+        self.program.atom_repr(atom)
+    }
+
+    fn use_atom(&mut self, atom: &Atom, span: SourceSpan) {
+        if let Atom::Var(var) = atom {
+            if !self.in_scope[var.as_usize()] {
+                let message = format!("`{}` is used out of its scope", self.name(*var));
+                self.error(span, message);
+            }
+        }
+    }
+
+    fn expect_atom(&mut self, atom: &Atom, expected: Repr, span: SourceSpan) {
+        self.use_atom(atom, span);
+        let found = self.repr(atom);
+        if found != expected {
+            let message = format!(
+                "mismatched representations: expected {:?}, found {:?}",
+                expected, found
+            );
+            self.error(span, message);
+        }
+    }
+
+    fn expect_atoms(&mut self, atoms: &[Atom], expected: Repr, span: SourceSpan) {
+        for atom in atoms {
+            self.expect_atom(atom, expected, span);
+        }
+    }
+
+    fn expect_var_repr(&mut self, var: VarId, expected: Repr) {
+        let found = self.program.var(var).repr;
+        if found != expected {
+            let message = format!(
+                "`{}` has the representation {:?} instead of {:?}",
+                self.name(var),
+                found,
+                expected
+            );
+            self.error(self.program.var(var).span, message);
+        }
+    }
+}
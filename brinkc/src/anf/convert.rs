@@ -0,0 +1,203 @@
+use std::rc::Rc;
+
+use crate::{
+    ir::{self, ExprKind, Literal, PrimOp},
+    resolve::PrimTy,
+    source_file::SourceSpan,
+    typeck::ty::Ty,
+};
+
+use super::{Atom, Block, Function, Global, GlobalValue, Program, Repr, Stmt, Value, VarInfo};
+
+/// Converts the core IR to A-normal form. The integers are kept in the
+/// uniform representation, boxed, and are only unboxed around the
+/// arithmetic operations; it is up to the optimizer to avoid the boxes.
+pub fn convert(program: &ir::Program) -> Program {
+    let mut converter = Converter {
+        program: Program {
+            vars: program
+                .vars
+                .iter()
+                .map(|var| VarInfo {
+                    name: var.name.clone(),
+                    ty: var.ty.clone(),
+                    repr: Repr::Value,
+                    span: var.span,
+                })
+                .collect(),
+            globals: Vec::new(),
+        },
+    };
+    for global in &program.globals {
+        let value = match &global.value.kind {
+            ExprKind::Lambda(parameters, body) => {
+                GlobalValue::Function(converter.function(parameters, body))
+            }
+            _ => GlobalValue::Block(converter.block(&global.value)),
+        };
+        converter.program.globals.push(Global {
+            var: global.var,
+            value,
+        });
+    }
+    converter.program
+}
+
+struct Converter {
+    program: Program,
+}
+
+impl Converter {
+    fn block(&mut self, expr: &ir::Expr) -> Block {
+        let mut stmts = Vec::new();
+        let result = self.atom(expr, &mut stmts);
+        Block { stmts, result }
+    }
+
+    fn function(&mut self, parameters: &[ir::VarId], body: &ir::Expr) -> Function {
+        Function {
+            parameters: parameters.to_vec(),
+            body: self.block(body),
+        }
+    }
+
+    /// Converts the expression to an operand, binding its value to a new
+    /// variable unless it is one already.
+    fn atom(&mut self, expr: &ir::Expr, stmts: &mut Vec<Stmt>) -> Atom {
+        match self.value(expr, stmts) {
+            Value::Atom(atom) => atom,
+            value => self.bind(value, expr.ty.clone(), Repr::Value, expr.span, stmts),
+        }
+    }
+
+    fn atoms(&mut self, exprs: &[ir::Expr], stmts: &mut Vec<Stmt>) -> Vec<Atom> {
+        exprs.iter().map(|expr| self.atom(expr, stmts)).collect()
+    }
+
+    /// Converts the expression to an unboxed integer operand.
+    fn int(&mut self, expr: &ir::Expr, stmts: &mut Vec<Stmt>) -> Atom {
+        if let ExprKind::Literal(Literal::Int(value)) = expr.kind {
+            return Atom::Literal(Literal::Int(value));
+        }
+        let atom = self.atom(expr, stmts);
+        self.bind(Value::Unbox(atom), int(), Repr::Int, expr.span, stmts)
+    }
+
+    fn bind(
+        &mut self,
+        value: Value,
+        ty: Ty,
+        repr: Repr,
+        span: SourceSpan,
+        stmts: &mut Vec<Stmt>,
+    ) -> Atom {
+        let var = self.program.new_var(Rc::from("_"), ty, repr, span);
+        stmts.push(Stmt::Let(var, value));
+        Atom::Var(var)
+    }
+
+    /// Converts the expression to the value of the uniform representation
+    /// it evaluates to, the statements computing its operands appended.
+    fn value(&mut self, expr: &ir::Expr, stmts: &mut Vec<Stmt>) -> Value {
+        match &expr.kind {
+            ExprKind::Var(var) => Value::Atom(Atom::Var(*var)),
+            ExprKind::Literal(Literal::Int(value)) => {
+                Value::Box(Atom::Literal(Literal::Int(*value)))
+            }
+            ExprKind::Literal(literal) => Value::Atom(Atom::Literal(literal.clone())),
+            ExprKind::Lambda(parameters, body) => Value::Lambda(self.function(parameters, body)),
+            ExprKind::Apply(function, arguments, _) => {
+                let function = self.atom(function, stmts);
+                Value::Apply(function, self.atoms(arguments, stmts))
+            }
+            ExprKind::Prim(op, arguments) => match op {
+                PrimOp::Add
+                | PrimOp::Subtract
+                | PrimOp::Multiply
+                | PrimOp::Divide
+                | PrimOp::Negate => {
+                    let arguments = arguments.iter().map(|a| self.int(a, stmts)).collect();
+                    let result = self.bind(
+                        Value::Prim(*op, arguments),
+                        int(),
+                        Repr::Int,
+                        expr.span,
+                        stmts,
+                    );
+                    Value::Box(result)
+                }
+                PrimOp::Less | PrimOp::LessEqual | PrimOp::Greater | PrimOp::GreaterEqual => {
+                    let arguments = arguments.iter().map(|a| self.int(a, stmts)).collect();
+                    Value::Prim(*op, arguments)
+                }
+                PrimOp::Equal | PrimOp::NotEqual if arguments[0].ty == int() => {
+                    let arguments = arguments.iter().map(|a| self.int(a, stmts)).collect();
+                    Value::Prim(*op, arguments)
+                }
+                _ => Value::Prim(*op, self.atoms(arguments, stmts)),
+            },
+            ExprKind::Let(var, value, body) => {
+                let value = self.value(value, stmts);
+                stmts.push(Stmt::Let(*var, value));
+                self.value(body, stmts)
+            }
+            ExprKind::LetRec(bindings, body) => {
+                let functions = bindings
+                    .iter()
+                    .map(|(var, value)| match &value.kind {
+                        ExprKind::Lambda(parameters, body) => {
+                            (*var, self.function(parameters, body))
+                        }
+                        _ => unreachable!("recursive binding of a value"),
+                    })
+                    .collect();
+                stmts.push(Stmt::LetRec(functions));
+                self.value(body, stmts)
+            }
+            ExprKind::Construct(con, fields) => Value::Construct(*con, self.atoms(fields, stmts)),
+            ExprKind::Field(value, con, index) => {
+                Value::Field(self.atom(value, stmts), *con, *index)
+            }
+            ExprKind::Switch(var, cases, default) => Value::Switch(
+                Atom::Var(*var),
+                cases
+                    .iter()
+                    .map(|(test, body)| (test.clone(), self.block(body)))
+                    .collect(),
+                default.as_ref().map(|default| self.block(default)),
+            ),
+            ExprKind::If(condition, then_branch, else_branch) => Value::If(
+                self.atom(condition, stmts),
+                self.block(then_branch),
+                self.block(else_branch),
+            ),
+            ExprKind::While(condition, body) => {
+                Value::While(self.block(condition), self.block(body))
+            }
+            ExprKind::For(var, start, direction, end, body) => {
+                let start = self.int(start, stmts);
+                let end = self.int(end, stmts);
+                // The loop counts in an unboxed variable, and the body gets
+                // the boxed one of the core IR.
+                let info = self.program.var(*var);
+                let counter = self
+                    .program
+                    .new_var(info.name.clone(), int(), Repr::Int, info.span);
+                let mut body_stmts = vec![Stmt::Let(*var, Value::Box(Atom::Var(counter)))];
+                let result = self.atom(body, &mut body_stmts);
+                let body = Block {
+                    stmts: body_stmts,
+                    result,
+                };
+                Value::For(counter, start, *direction, end, body)
+            }
+            ExprKind::Try(body, var, handler) => {
+                Value::Try(self.block(body), *var, self.block(handler))
+            }
+        }
+    }
+}
+
+fn int() -> Ty {
+    Ty::Prim(PrimTy::Int)
+}
@@ -0,0 +1,443 @@
+//! The mid-level intermediate representation in A-normal form: every
+//! operand is a variable or a literal, and every intermediate value is
+//! bound to a variable. The integers have an explicit representation, so
+//! the optimizer can keep them unboxed where the uniform representation is
+//! not needed.
+
+use std::rc::Rc;
+
+use crate::{
+    ast::ForDirection,
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    source_file::SourceSpan,
+    typeck::ty::Ty,
+};
+
+mod check;
+mod convert;
+mod pretty;
+
+pub use check::{check, CheckError};
+pub use convert::convert;
+pub use pretty::print_program;
+
+/// How a value is represented at runtime.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Repr {
+    /// The uniform representation of all the values, in which they are
+    /// passed to the functions and stored in the data structures. The
+    /// integers are boxed.
+    Value,
+    /// An unboxed machine integer.
+    Int,
+}
+
+#[derive(Clone, Debug)]
+pub struct VarInfo {
+    /// The name of the source variable, or `_` for the ones introduced by
+    /// the compiler.
+    pub name: Rc<str>,
+    /// The type of the value. The copies of the variables made by the
+    /// inlining keep the types of the inlined function, which may be more
+    /// general than the ones of the arguments.
+    pub ty: Ty,
+    pub repr: Repr,
+    /// The span of the binding, or of the computation bound to the
+    /// variable, e.g. the call which may raise an exception.
+    pub span: SourceSpan,
+}
+
+/// The program: the module-level bindings in the order they are
+/// evaluated. The variables are numbered like in the core IR they are
+/// converted from, and every variable is still bound exactly once.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub vars: Vec<VarInfo>,
+    pub globals: Vec<Global>,
+}
+
+impl Program {
+    pub fn var(&self, id: VarId) -> &VarInfo {
+        &self.vars[id.as_usize()]
+    }
+
+    pub fn new_var(&mut self, name: Rc<str>, ty: Ty, repr: Repr, span: SourceSpan) -> VarId {
+        let id = VarId::new(self.vars.len());
+        self.vars.push(VarInfo {
+            name,
+            ty,
+            repr,
+            span,
+        });
+        id
+    }
+
+    /// Creates a variable like the existing one, for a copy of its binding.
+    pub fn copy_var(&mut self, var: VarId) -> VarId {
+        let info = self.var(var).clone();
+        self.new_var(info.name, info.ty, info.repr, info.span)
+    }
+
+    pub fn atom_repr(&self, atom: &Atom) -> Repr {
+        match atom {
+            Atom::Var(var) => self.var(*var).repr,
+            Atom::Literal(Literal::Int(_)) => Repr::Int,
+            Atom::Literal(_) => Repr::Value,
+        }
+    }
+}
+
+/// A module-level binding. The functions of all the globals can refer to
+/// all the others.
+#[derive(Clone, Debug)]
+pub struct Global {
+    pub var: VarId,
+    pub value: GlobalValue,
+}
+
+#[derive(Clone, Debug)]
+pub enum GlobalValue {
+    Function(Function),
+    /// The value computed by the block when the program starts.
+    Block(Block),
+}
+
+/// A curried function of the parameters.
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub parameters: Vec<VarId>,
+    pub body: Block,
+}
+
+/// A sequence of bindings followed by the value of the block.
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub result: Atom,
+}
+
+impl Block {
+    /// Calls the function on every variable used in the block, including
+    /// the nested blocks and functions.
+    pub fn for_each_use(&self, f: &mut impl FnMut(VarId)) {
+        for stmt in &self.stmts {
+            stmt.for_each_use(f);
+        }
+        if let Atom::Var(var) = self.result {
+            f(var);
+        }
+    }
+
+    /// Counts the statements, including the nested ones, as a measure of
+    /// the size of the code.
+    pub fn size(&self) -> usize {
+        self.stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Let(_, value) => 1 + value.blocks().iter().map(|b| b.size()).sum::<usize>(),
+                Stmt::LetRec(functions) => functions.iter().map(|(_, f)| 1 + f.body.size()).sum(),
+            })
+            .sum()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Let(VarId, Value),
+    /// Mutually recursive functions.
+    LetRec(Vec<(VarId, Function)>),
+}
+
+impl Stmt {
+    pub fn for_each_use(&self, f: &mut impl FnMut(VarId)) {
+        match self {
+            Stmt::Let(_, value) => value.for_each_use(f),
+            Stmt::LetRec(functions) => {
+                for (_, function) in functions {
+                    function.body.for_each_use(f);
+                }
+            }
+        }
+    }
+}
+
+/// An operand: a variable or a constant. The integer literals are
+/// unboxed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Atom {
+    Var(VarId),
+    Literal(Literal),
+}
+
+/// A computation whose result is bound to a variable.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Atom(Atom),
+    Lambda(Function),
+    /// Applies the function to the arguments one by one; the function may
+    /// take fewer or more parameters than there are arguments.
+    Apply(Atom, Vec<Atom>),
+    /// Calls the function the variable is known to be bound to with exactly
+    /// the arguments it takes.
+    Call(VarId, Vec<Atom>),
+    /// A saturated call of the primitive operation. The arithmetic
+    /// operations and the ordering comparisons take unboxed integers, and
+    /// the arithmetic ones return them. The equalities compare two values
+    /// of the same representation, and the others take and return the
+    /// uniform representation.
+    Prim(PrimOp, Vec<Atom>),
+    /// Boxes the unboxed integer.
+    Box(Atom),
+    /// Gets the unboxed integer out of the box.
+    Unbox(Atom),
+    Construct(Con, Vec<Atom>),
+    /// Reads the field of the value, which is known to be built by the
+    /// constructor.
+    Field(Atom, Con, usize),
+    If(Atom, Block, Block),
+    /// Evaluates the first case whose test matches the value, or the
+    /// default. Without the default, the tests cover all the values of the
+    /// type.
+    Switch(Atom, Vec<(Test, Block)>, Option<Block>),
+    /// Evaluates the body while the condition block evaluates to `true`.
+    While(Block, Block),
+    /// Binds the variable to the unboxed integers from the start to the
+    /// end, both inclusive.
+    For(VarId, Atom, ForDirection, Atom, Block),
+    Try(Block, VarId, Block),
+}
+
+impl Value {
+    /// Calls the function on the operands of the value, not including the
+    /// ones of the nested blocks.
+    pub fn operands(&self, f: &mut impl FnMut(&Atom)) {
+        match self {
+            Value::Atom(atom) | Value::Box(atom) | Value::Unbox(atom) => f(atom),
+            Value::Field(atom, _, _) | Value::If(atom, _, _) | Value::Switch(atom, _, _) => f(atom),
+            Value::Apply(function, arguments) => {
+                f(function);
+                arguments.iter().for_each(f);
+            }
+            Value::Call(function, arguments) => {
+                f(&Atom::Var(*function));
+                arguments.iter().for_each(f);
+            }
+            Value::Prim(_, arguments) | Value::Construct(_, arguments) => {
+                arguments.iter().for_each(f)
+            }
+            Value::For(_, start, _, end, _) => {
+                f(start);
+                f(end);
+            }
+            Value::Lambda(_) | Value::While(..) | Value::Try(..) => {}
+        }
+    }
+
+    /// Calls the function on the operands of the value to replace them, not
+    /// including the ones of the nested blocks. The function called has to
+    /// stay a variable.
+    pub fn operands_mut(&mut self, f: &mut impl FnMut(&mut Atom)) {
+        match self {
+            Value::Atom(atom) | Value::Box(atom) | Value::Unbox(atom) => f(atom),
+            Value::Field(atom, _, _) | Value::If(atom, _, _) | Value::Switch(atom, _, _) => f(atom),
+            Value::Apply(function, arguments) => {
+                f(function);
+                arguments.iter_mut().for_each(f);
+            }
+            Value::Call(function, arguments) => {
+                let mut atom = Atom::Var(*function);
+                f(&mut atom);
+                match atom {
+                    Atom::Var(var) => *function = var,
+                    Atom::Literal(_) => unreachable!("call of a literal"),
+                }
+                arguments.iter_mut().for_each(f);
+            }
+            Value::Prim(_, arguments) | Value::Construct(_, arguments) => {
+                arguments.iter_mut().for_each(f)
+            }
+            Value::For(_, start, _, end, _) => {
+                f(start);
+                f(end);
+            }
+            Value::Lambda(_) | Value::While(..) | Value::Try(..) => {}
+        }
+    }
+
+    /// Gets the blocks nested in the value, in the order they appear.
+    pub fn blocks(&self) -> Vec<&Block> {
+        match self {
+            Value::Lambda(function) => vec![&function.body],
+            Value::If(_, then_block, else_block) => vec![then_block, else_block],
+            Value::Switch(_, cases, default) => cases
+                .iter()
+                .map(|(_, block)| block)
+                .chain(default.iter())
+                .collect(),
+            Value::While(condition, body) => vec![condition, body],
+            Value::For(.., body) => vec![body],
+            Value::Try(body, _, handler) => vec![body, handler],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn blocks_mut(&mut self) -> Vec<&mut Block> {
+        match self {
+            Value::Lambda(function) => vec![&mut function.body],
+            Value::If(_, then_block, else_block) => vec![then_block, else_block],
+            Value::Switch(_, cases, default) => cases
+                .iter_mut()
+                .map(|(_, block)| block)
+                .chain(default.iter_mut())
+                .collect(),
+            Value::While(condition, body) => vec![condition, body],
+            Value::For(.., body) => vec![body],
+            Value::Try(body, _, handler) => vec![body, handler],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn for_each_use(&self, f: &mut impl FnMut(VarId)) {
+        self.operands(&mut |atom| {
+            if let Atom::Var(var) = atom {
+                f(*var);
+            }
+        });
+        for block in self.blocks() {
+            block.for_each_use(f);
+        }
+    }
+
+    /// Checks whether the value is the join of several blocks, whose
+    /// results become the value.
+    pub fn is_join(&self) -> bool {
+        matches!(self, Value::If(..) | Value::Switch(..) | Value::Try(..))
+    }
+
+    /// Gets the blocks whose results become the value of a join.
+    pub fn join_blocks_mut(&mut self) -> Vec<&mut Block> {
+        match self {
+            Value::If(..) | Value::Switch(..) | Value::Try(..) => self.blocks_mut(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Counts the uses of every variable in the program.
+pub fn use_counts(program: &Program) -> Vec<usize> {
+    let mut counts = vec![0; program.vars.len()];
+    for global in &program.globals {
+        let mut count = |var: VarId| counts[var.as_usize()] += 1;
+        match &global.value {
+            GlobalValue::Function(function) => function.body.for_each_use(&mut count),
+            GlobalValue::Block(block) => block.for_each_use(&mut count),
+        }
+    }
+    counts
+}
+
+/// Copies the function, binding fresh variables in the copy, so it can be
+/// inlined without binding any variable twice. The parameters are replaced
+/// with the arguments instead.
+pub fn copy_body(program: &mut Program, function: &Function, arguments: &[Atom]) -> Block {
+    let mut copier = Copier {
+        program,
+        renamed: function
+            .parameters
+            .iter()
+            .copied()
+            .zip(arguments.iter().cloned())
+            .collect(),
+    };
+    copier.block(&function.body)
+}
+
+struct Copier<'a> {
+    program: &'a mut Program,
+    renamed: std::collections::HashMap<VarId, Atom>,
+}
+
+impl<'a> Copier<'a> {
+    fn bind(&mut self, var: VarId) -> VarId {
+        let copy = self.program.copy_var(var);
+        self.renamed.insert(var, Atom::Var(copy));
+        copy
+    }
+
+    fn atom(&self, atom: &mut Atom) {
+        if let Atom::Var(var) = atom {
+            if let Some(renamed) = self.renamed.get(var) {
+                *atom = renamed.clone();
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) -> Block {
+        let stmts = block
+            .stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Let(var, value) => {
+                    let value = self.value(value);
+                    Stmt::Let(self.bind(*var), value)
+                }
+                Stmt::LetRec(functions) => {
+                    let vars = functions
+                        .iter()
+                        .map(|(var, _)| self.bind(*var))
+                        .collect::<Vec<_>>();
+                    let functions = functions
+                        .iter()
+                        .zip(vars)
+                        .map(|((_, function), var)| (var, self.function(function)))
+                        .collect();
+                    Stmt::LetRec(functions)
+                }
+            })
+            .collect();
+        let mut result = block.result.clone();
+        self.atom(&mut result);
+        Block { stmts, result }
+    }
+
+    fn function(&mut self, function: &Function) -> Function {
+        Function {
+            parameters: function
+                .parameters
+                .iter()
+                .map(|parameter| self.bind(*parameter))
+                .collect(),
+            body: self.block(&function.body),
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        let mut value = match value {
+            Value::Lambda(function) => Value::Lambda(self.function(function)),
+            Value::For(var, start, direction, end, body) => {
+                let var = self.bind(*var);
+                Value::For(
+                    var,
+                    start.clone(),
+                    *direction,
+                    end.clone(),
+                    self.block(body),
+                )
+            }
+            Value::Try(body, var, handler) => {
+                let body = self.block(body);
+                let var = self.bind(*var);
+                Value::Try(body, var, self.block(handler))
+            }
+            value => {
+                let mut value = value.clone();
+                for block in value.blocks_mut() {
+                    *block = self.block(block);
+                }
+                value
+            }
+        };
+        value.operands_mut(&mut |atom| self.atom(atom));
+        value
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{
+    ast::ForDirection,
+    ir::{Con, Test, VarId},
+    resolve::Resolutions,
+    typeck::{ty::TyPrinter, TypeckResults},
+};
+
+use super::{Atom, Block, Function, GlobalValue, Program, Repr, Stmt, Value};
+
+/// Formats the program for reading, like the core IR. The unboxed integers
+/// have the type `#int`.
+pub fn print_program(
+    program: &Program,
+    resolutions: &Resolutions,
+    typeck_results: &TypeckResults,
+) -> String {
+    let mut printer = Printer {
+        program,
+        resolutions,
+        typeck_results,
+        ty_printer: TyPrinter::new(resolutions),
+        output: String::new(),
+    };
+    for (i, global) in program.globals.iter().enumerate() {
+        if i > 0 {
+            printer.output.push_str("\n\n");
+        }
+        printer.ty_printer = TyPrinter::new(resolutions);
+        printer.output.push_str("let ");
+        printer.binder(global.var);
+        printer.output.push_str(" =");
+        printer.newline(2);
+        match &global.value {
+            GlobalValue::Function(function) => printer.function(function, 2),
+            GlobalValue::Block(block) => printer.block(block, 2),
+        }
+    }
+    printer.output.push('\n');
+    printer.output
+}
+
+struct Printer<'a> {
+    program: &'a Program,
+    resolutions: &'a Resolutions,
+    typeck_results: &'a TypeckResults,
+    ty_printer: TyPrinter<'a>,
+    output: String,
+}
+
+impl<'a> Printer<'a> {
+    fn var(&mut self, var: VarId) {
+        let name = &self.program.var(var).name;
+        self.output
+            .push_str(&format!("{}/{}", name, var.as_usize()));
+    }
+
+    fn binder(&mut self, var: VarId) {
+        self.var(var);
+        self.output.push_str(" : ");
+        let info = self.program.var(var);
+        match info.repr {
+            Repr::Value => {
+                let ty = self.ty_printer.print(&info.ty);
+                self.output.push_str(&ty);
+            }
+            Repr::Int => self.output.push_str("#int"),
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.output.push('\n');
+        self.output.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    /// Prints the statements and the result of the block on separate lines,
+    /// starting at the current position.
+    fn block(&mut self, block: &Block, indent: usize) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(var, value) => {
+                    self.output.push_str("let ");
+                    self.binder(*var);
+                    self.output.push_str(" =");
+                    if value.blocks().is_empty() {
+                        self.output.push(' ');
+                    } else {
+                        self.newline(indent + 2);
+                    }
+                    self.value(value, indent + 2);
+                }
+                Stmt::LetRec(functions) => {
+                    for (i, (var, function)) in functions.iter().enumerate() {
+                        if i > 0 {
+                            self.newline(indent);
+                        }
+                        self.output
+                            .push_str(if i == 0 { "let rec " } else { "and " });
+                        self.binder(*var);
+                        self.output.push_str(" =");
+                        self.newline(indent + 2);
+                        self.function(function, indent + 2);
+                    }
+                }
+            }
+            self.newline(indent);
+        }
+        self.atom(&block.result);
+    }
+
+    /// Prints the block on the following lines at the indentation.
+    fn nested_block(&mut self, block: &Block, indent: usize) {
+        self.newline(indent);
+        self.block(block, indent);
+    }
+
+    fn function(&mut self, function: &Function, indent: usize) {
+        self.output.push_str("fun");
+        for parameter in &function.parameters {
+            self.output.push_str(" (");
+            self.binder(*parameter);
+            self.output.push(')');
+        }
+        self.output.push_str(" ->");
+        if function.body.stmts.is_empty() {
+            self.output.push(' ');
+            self.atom(&function.body.result);
+        } else {
+            self.nested_block(&function.body, indent + 2);
+        }
+    }
+
+    /// Prints the value at the current position. The values with nested
+    /// blocks continue at the indentation.
+    fn value(&mut self, value: &Value, indent: usize) {
+        match value {
+            Value::Atom(atom) => self.atom(atom),
+            Value::Lambda(function) => self.function(function, indent),
+            Value::Apply(function, arguments) => {
+                self.atom(function);
+                for argument in arguments {
+                    self.output.push(' ');
+                    self.atom(argument);
+                }
+            }
+            Value::Call(function, arguments) => {
+                self.output.push_str("call ");
+                self.var(*function);
+                self.arguments(arguments);
+            }
+            Value::Prim(op, arguments) => {
+                self.output.push('%');
+                self.output.push_str(op.name());
+                self.arguments(arguments);
+            }
+            Value::Box(atom) => {
+                self.output.push_str("%box");
+                self.arguments(std::slice::from_ref(atom));
+            }
+            Value::Unbox(atom) => {
+                self.output.push_str("%unbox");
+                self.arguments(std::slice::from_ref(atom));
+            }
+            Value::Construct(con, fields) => self.construct(*con, fields),
+            Value::Field(atom, con, index) => {
+                self.atom(atom);
+                self.output.push('.');
+                let field = con.field_name(*index, self.resolutions, self.typeck_results);
+                self.output.push_str(&field);
+            }
+            Value::If(condition, then_block, else_block) => {
+                self.output.push_str("if ");
+                self.atom(condition);
+                self.output.push_str(" then");
+                self.nested_block(then_block, indent + 2);
+                self.newline(indent);
+                self.output.push_str("else");
+                self.nested_block(else_block, indent + 2);
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.output.push_str("switch ");
+                self.atom(scrutinee);
+                for (test, block) in cases {
+                    self.newline(indent);
+                    self.output.push_str("| ");
+                    match test {
+                        Test::Con(con) => self.output.push_str(&con.name(self.resolutions)),
+                        Test::Literal(literal) => self.output.push_str(&literal.to_string()),
+                    }
+                    self.output.push_str(" ->");
+                    self.nested_block(block, indent + 2);
+                }
+                if let Some(default) = default {
+                    self.newline(indent);
+                    self.output.push_str("| _ ->");
+                    self.nested_block(default, indent + 2);
+                }
+            }
+            Value::While(condition, body) => {
+                self.output.push_str("while");
+                self.nested_block(condition, indent + 2);
+                self.newline(indent);
+                self.output.push_str("do");
+                self.nested_block(body, indent + 2);
+            }
+            Value::For(counter, start, direction, end, body) => {
+                self.output.push_str("for ");
+                self.binder(*counter);
+                self.output.push_str(" = ");
+                self.atom(start);
+                self.output.push_str(match direction {
+                    ForDirection::Up => " to ",
+                    ForDirection::Down => " downto ",
+                });
+                self.atom(end);
+                self.output.push_str(" do");
+                self.nested_block(body, indent + 2);
+            }
+            Value::Try(body, exception, handler) => {
+                self.output.push_str("try");
+                self.nested_block(body, indent + 2);
+                self.newline(indent);
+                self.output.push_str("with ");
+                self.binder(*exception);
+                self.output.push_str(" ->");
+                self.nested_block(handler, indent + 2);
+            }
+        }
+    }
+
+    fn construct(&mut self, con: Con, fields: &[Atom]) {
+        match con {
+            Con::Tuple(_) => self.arguments(fields),
+            Con::Record(_) => {
+                self.output.push_str("{ ");
+                self.separated(fields, "; ");
+                self.output.push_str(" }");
+            }
+            Con::Constructor(_) | Con::Exception(_) => {
+                self.output.push_str(&con.name(self.resolutions));
+                if !fields.is_empty() {
+                    self.arguments(fields);
+                }
+            }
+            Con::Nil => self.output.push_str("[]"),
+            Con::Cons => {
+                self.output.push('(');
+                self.separated(fields, " :: ");
+                self.output.push(')');
+            }
+            Con::Array(_) => {
+                self.output.push_str("[|");
+                self.separated(fields, "; ");
+                self.output.push_str("|]");
+            }
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Atom]) {
+        self.output.push('(');
+        self.separated(arguments, ", ");
+        self.output.push(')');
+    }
+
+    fn separated(&mut self, atoms: &[Atom], separator: &str) {
+        for (i, atom) in atoms.iter().enumerate() {
+            if i > 0 {
+                self.output.push_str(separator);
+            }
+            self.atom(atom);
+        }
+    }
+
+    fn atom(&mut self, atom: &Atom) {
+        match atom {
+            Atom::Var(var) => self.var(*var),
+            Atom::Literal(literal) => self.output.push_str(&literal.to_string()),
+        }
+    }
+}
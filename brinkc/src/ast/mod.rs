@@ -1,15 +1,23 @@
 use node_id::NodeId;
 
-use crate::source_file::SourceSpan;
+use crate::source_file::{FileId, SourceSpan};
 
 pub mod node_id;
 
+/// The items of a single source file, which is also a module.
 #[derive(Debug)]
 pub struct Program {
     pub id: NodeId,
+    pub file: FileId,
     pub body: Vec<Item>,
 }
 
+/// A sequence of items on consecutive lines of the same indentation. The
+/// items are evaluated in order, and the value of a block is the value of
+/// its last item if it is an expression, and `()` otherwise.
+///
+/// Within an item, the operands and the function arguments are evaluated
+/// from left to right, before the function is called.
 #[derive(Debug)]
 pub struct Block {
     pub id: NodeId,
@@ -17,24 +25,87 @@ pub struct Block {
     pub items: Vec<Item>,
 }
 
+impl Block {
+    /// Gets the expression giving the value of the block, if there is one.
+    pub fn result(&self) -> Option<&Expr> {
+        match self.items.last().map(|item| &item.kind) {
+            Some(ItemKind::Expr(expr)) => Some(expr),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Item {
     pub id: NodeId,
+    /// The id of the first node of the item. The nodes of an item have
+    /// consecutive ids, ending with the id of the item itself.
+    pub first_id: NodeId,
     pub span: SourceSpan,
+    pub visibility: Visibility,
     pub kind: ItemKind,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Visibility {
+    Private,
+    Public,
+}
+
 #[derive(Debug)]
 pub enum ItemKind {
     LetBinding(LetBinding),
+    /// A group of mutually recursive bindings, e.g. `let rec f x = … and g
+    /// y = …`. Every binding of the group is recursive and visible in the
+    /// bodies of the others, and a group has at least two bindings.
+    LetRec(Vec<LetBinding>),
+    Type(TypeDecl),
+    Module(ModuleDecl),
+    Open(Path),
+    Import(Path),
+    /// An exception declaration, e.g. `exception NotFound int`. The
+    /// exceptions are the constructors of the `exn` type.
+    Exception(Constructor),
+    /// A fixity declaration, e.g. `infixl 1 |>`.
+    Fixity(FixityDecl),
     Expr(Expr),
 }
 
+/// Declares the precedence and the associativity of the user-defined
+/// operators. The declaration applies from its line to the end of the file,
+/// and the other files using the operators must declare the same fixity.
+#[derive(Debug)]
+pub struct FixityDecl {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub associativity: Associativity,
+    /// The precedence from 0 (the loosest) to 9 (the tightest).
+    pub precedence: Literal,
+    pub operators: Vec<Literal>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Associativity {
+    /// Groups to the left, `infixl`.
+    Left,
+    /// Groups to the right, `infixr`.
+    Right,
+    /// Cannot be chained without parentheses, `infix`.
+    None,
+}
+
 #[derive(Debug)]
 pub struct LetBinding {
     pub id: NodeId,
     pub span: SourceSpan,
+    /// Whether the binding is visible in its own body, i.e. `let rec`.
+    pub is_recursive: bool,
+    /// The name of the binding. An operator is defined in parentheses, e.g.
+    /// `let (|>) x f = f x`, and the identifier spans the operator only.
     pub identifier: Literal,
+    pub parameters: Vec<Pattern>,
+    /// The annotated type of the body, e.g. `int` in `let f x : int = x`.
+    pub return_ty: Option<Ty>,
     pub body: LetBody,
 }
 
@@ -44,6 +115,79 @@ pub enum LetBody {
     Expr(Expr),
 }
 
+/// A type declaration, e.g. `type Option a = None | Some a`.
+#[derive(Debug)]
+pub struct TypeDecl {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub identifier: Literal,
+    pub parameters: Vec<Literal>,
+    pub kind: TypeDeclKind,
+}
+
+#[derive(Debug)]
+pub enum TypeDeclKind {
+    /// A sum type, e.g. `None | Some a`.
+    Variant(Vec<Constructor>),
+    /// A record type, e.g. `{ x: int; y: int }`.
+    Record(Vec<FieldDecl>),
+    /// Another name for an existing type.
+    Alias(Ty),
+}
+
+#[derive(Debug)]
+pub struct Constructor {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub identifier: Literal,
+    pub arguments: Vec<Ty>,
+}
+
+#[derive(Debug)]
+pub struct FieldDecl {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub identifier: Literal,
+    pub ty: Ty,
+}
+
+#[derive(Debug)]
+pub struct Ty {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub kind: TyKind,
+}
+
+#[derive(Debug)]
+pub enum TyKind {
+    /// A possibly applied type constructor, e.g. `int` or `Option a`.
+    Path(Path, Vec<Ty>),
+    /// A type variable, e.g. `'a`.
+    Var(Literal),
+    /// A function type, e.g. `int -> int`.
+    Arrow(Box<Ty>, Box<Ty>),
+    /// A tuple type, e.g. `int * bool`.
+    Tuple(Vec<Ty>),
+    Paren(Box<Ty>),
+}
+
+/// A module nested in a source file, e.g. `module Inner = ...`.
+#[derive(Debug)]
+pub struct ModuleDecl {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub identifier: Literal,
+    pub body: Block,
+}
+
+/// A possibly qualified name, e.g. `x` or `List.map`.
+#[derive(Debug)]
+pub struct Path {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub segments: Vec<Literal>,
+}
+
 #[derive(Debug)]
 pub struct Expr {
     pub id: NodeId,
@@ -54,6 +198,179 @@ pub struct Expr {
 #[derive(Debug)]
 pub enum ExprKind {
     Literal(Literal),
+    Path(Path),
+    Application(Box<Expr>, Vec<Expr>),
+    /// An application annotated with `@tailcall`, which has to be in tail
+    /// position, e.g. `@tailcall loop (n - 1)`.
+    TailCall(Box<Expr>),
+    Paren(Box<Expr>),
+    /// A tuple of at least two elements, e.g. `(1, true)`.
+    Tuple(Vec<Expr>),
+    /// A list literal, e.g. `[1; 2; 3]`.
+    List(Vec<Expr>),
+    /// An array literal, e.g. `[| 1; 2; 3 |]`.
+    Array(Vec<Expr>),
+    /// Record construction, e.g. `{ x = 1; y = 2 }`.
+    Record(Vec<FieldExpr>),
+    /// Functional record update, e.g. `{ p with x = 1 }`.
+    RecordUpdate(Box<Expr>, Vec<FieldExpr>),
+    FieldAccess(Box<Expr>, Literal),
+    Match(Box<Expr>, Vec<MatchArm>),
+    /// An anonymous function, e.g. `fun x y -> x + y`.
+    Lambda(Vec<Pattern>, Box<LetBody>),
+    /// A conditional, e.g. `if c then a else b`. The `elif` branches are
+    /// nested conditionals in the `else` branches.
+    If(Box<Expr>, Box<LetBody>, Option<Box<LetBody>>),
+    /// Repeats the body while the condition holds, e.g. `while c do f ()`.
+    While(Box<Expr>, Box<LetBody>),
+    For(Box<ForLoop>),
+    /// Evaluates the body, handling the exceptions matching the arms, e.g.
+    /// `try f x with | NotFound -> 0`.
+    Try(Box<LetBody>, Vec<MatchArm>),
+    /// A let binding scoped to the body, e.g. `let x = 1 in x + 1`.
+    Let(Box<LetBinding>, Box<LetBody>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// An application of a user-defined operator, e.g. `x |> f`, whose
+    /// path names the operator.
+    Infix(Path, Box<Expr>, Box<Expr>),
+    /// An operator used as a function, with at most one operand given,
+    /// e.g. `(+)`, `(+ 1)` or `(xs @)`. An operator without operands in
+    /// parentheses is a path unless it is built in.
+    Section(SectionOperator, Option<Box<Expr>>, Option<Box<Expr>>),
+    Unary(UnaryOp, Box<Expr>),
+    /// An expression with the annotated type, e.g. `(x : int)`.
+    Typed(Box<Expr>, Ty),
+    /// An interpolated string, e.g. `$"x = {x}"`. The text segments and
+    /// the holes alternate, starting and ending with a text segment.
+    Interpolation(Vec<InterpolationPart>),
+}
+
+#[derive(Debug)]
+pub enum InterpolationPart {
+    /// A segment of the text, spanning its source code without the quotes
+    /// and the braces delimiting it.
+    Text(SourceSpan),
+    /// An interpolated expression, e.g. `x` in `{x}`.
+    Hole(Expr),
+}
+
+/// A loop over a range of integers, e.g. `for i = 1 to n do f i`. Both
+/// bounds are inclusive and evaluated once, before the loop.
+#[derive(Debug)]
+pub struct ForLoop {
+    pub binding: Literal,
+    pub start: Expr,
+    pub direction: ForDirection,
+    pub end: Expr,
+    pub body: LetBody,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ForDirection {
+    /// Counts up, `to`.
+    Up,
+    /// Counts down, `downto`.
+    Down,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BinaryOp {
+    pub span: SourceSpan,
+    pub kind: BinaryOpKind,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BinaryOpKind {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    /// Prepends an element to a list, `::`.
+    Cons,
+    /// Stores the value in a reference, `:=`.
+    Assign,
+}
+
+#[derive(Debug)]
+pub enum SectionOperator {
+    Builtin(BinaryOp),
+    User(Path),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct UnaryOp {
+    pub span: SourceSpan,
+    pub kind: UnaryOpKind,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UnaryOpKind {
+    Negate,
+    /// Reads the value of a reference, `!`.
+    Deref,
+}
+
+#[derive(Debug)]
+pub struct FieldExpr {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub identifier: Literal,
+    pub expr: Expr,
+}
+
+#[derive(Debug)]
+pub struct MatchArm {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub pattern: Pattern,
+    pub body: LetBody,
+}
+
+#[derive(Debug)]
+pub struct Pattern {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub kind: PatternKind,
+}
+
+#[derive(Debug)]
+pub enum PatternKind {
+    Wildcard,
+    /// Binds the matched value to a new local variable.
+    Binding(Literal),
+    Literal(Literal),
+    /// A constructor applied to the patterns of its arguments.
+    Constructor(Path, Vec<Pattern>),
+    /// A record pattern; the fields without a pattern bind the variables
+    /// of the same names.
+    Record(Vec<FieldPattern>),
+    Or(Vec<Pattern>),
+    Paren(Box<Pattern>),
+    Tuple(Vec<Pattern>),
+    /// Matches the lists of the same length as the pattern.
+    List(Vec<Pattern>),
+    /// Matches the arrays of the same length as the pattern.
+    Array(Vec<Pattern>),
+    /// Matches the head and the tail of a non-empty list, e.g. `x :: xs`.
+    Cons(Box<Pattern>, Box<Pattern>),
+    /// A pattern with the annotated type, e.g. `(x: int)`.
+    Typed(Box<Pattern>, Ty),
+}
+
+#[derive(Debug)]
+pub struct FieldPattern {
+    pub id: NodeId,
+    pub span: SourceSpan,
+    pub identifier: Literal,
+    pub pattern: Option<Pattern>,
 }
 
 #[derive(Debug)]
@@ -66,5 +383,10 @@ pub struct Literal {
 #[derive(Debug)]
 pub enum LiteralKind {
     Identifier,
+    TypeVariable,
     Integer,
+    /// A string literal; its span includes the quotes.
+    String,
+    Bool,
+    Unit,
 }
@@ -1,9 +1,8 @@
 use std::fmt;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct NodeId(usize);
 
-#[allow(dead_code)]
 impl NodeId {
     pub fn from_usize(id: usize) -> Self {
         Self(id)
@@ -20,6 +19,7 @@ impl fmt::Display for NodeId {
     }
 }
 
+#[derive(Default)]
 pub struct NodeIdGenerator {
     next_id: usize,
 }
@@ -29,6 +29,11 @@ impl NodeIdGenerator {
         Self { next_id: 0 }
     }
 
+    /// Gets the id the next node will have.
+    pub fn peek(&self) -> NodeId {
+        NodeId(self.next_id)
+    }
+
     pub fn next_id(&mut self) -> NodeId {
         let id = self.next_id;
         self.next_id += 1;
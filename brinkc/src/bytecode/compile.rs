@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+
+use crate::{
+    anf::{Atom, Block, GlobalValue, Program, Stmt, Value},
+    ast::ForDirection,
+    codegen::{builtin_may_raise, tag_names, Closures},
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    resolve::Resolutions,
+    source_file::SourceMap,
+};
+
+use super::{Constant, Function, Instr, Module};
+
+/// The name of the function evaluating the globals in the stack traces.
+const MAIN_NAME: &str = "<main>";
+
+/// Compiles the program to the bytecode of the virtual machine.
+///
+/// Every function becomes an entry of the function table, whose closures
+/// capture the variables found by the closure conversion. The local
+/// variables of a function are numbered from its parameters, and the
+/// calls in tail position reuse the frame of the caller, so the recursion
+/// in tail position runs in constant space.
+pub fn compile(program: &Program, resolutions: &Resolutions, source_map: &SourceMap) -> Module {
+    let mut compiler = Compiler {
+        program,
+        source_map,
+        closures: Closures::new(program),
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        constants: HashMap::new(),
+        locations: HashMap::new(),
+        module: Module {
+            constants: Vec::new(),
+            functions: Vec::new(),
+            globals: Vec::new(),
+            main: 0,
+            tag_names: tag_names(resolutions),
+            locations: Vec::new(),
+        },
+        state: State::default(),
+    };
+    // The function table starts with the main function, followed by the
+    // other ones in the order they are bound.
+    compiler.module.functions.push(placeholder());
+    for (i, global) in program.globals.iter().enumerate() {
+        compiler.globals.insert(global.var, i as u32);
+        compiler
+            .module
+            .globals
+            .push(program.var(global.var).name.to_string());
+        match &global.value {
+            GlobalValue::Function(function) => {
+                compiler.number_function(global.var);
+                compiler.number_functions(&function.body);
+            }
+            GlobalValue::Block(block) => compiler.number_functions(block),
+        }
+    }
+
+    compiler.compile_main();
+    for global in &program.globals {
+        if let GlobalValue::Function(function) = &global.value {
+            compiler.function(global.var, function, Vec::new());
+        }
+    }
+    compiler.module
+}
+
+fn placeholder() -> Function {
+    Function {
+        name: String::new(),
+        arity: 0,
+        captures: 0,
+        locals: 0,
+        code: Vec::new(),
+        lines: Vec::new(),
+    }
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    source_map: &'a SourceMap,
+    closures: Closures,
+    /// The functions bound to the variables, in the function table.
+    functions: HashMap<VarId, u32>,
+    /// The slots of the globals.
+    globals: HashMap<VarId, u32>,
+    constants: HashMap<Constant, u32>,
+    locations: HashMap<String, u32>,
+    module: Module,
+    /// The function being compiled.
+    state: State,
+}
+
+/// The code of the function being compiled and the slots of its variables.
+#[derive(Default)]
+struct State {
+    code: Vec<Instr>,
+    lines: Vec<(u32, u32)>,
+    locals: HashMap<VarId, u32>,
+    captures: HashMap<VarId, u32>,
+    /// The variable the function is bound to, which refers to the running
+    /// closure instead of being captured.
+    callee: Option<VarId>,
+}
+
+impl<'a> Compiler<'a> {
+    fn number_function(&mut self, var: VarId) {
+        self.functions
+            .insert(var, self.module.functions.len() as u32);
+        self.module.functions.push(placeholder());
+    }
+
+    /// Numbers the functions bound in the block, including the nested ones.
+    fn number_functions(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(var, Value::Lambda(function)) => {
+                    self.number_function(*var);
+                    self.number_functions(&function.body);
+                }
+                Stmt::Let(_, value) => {
+                    for block in value.blocks() {
+                        self.number_functions(block);
+                    }
+                }
+                Stmt::LetRec(functions) => {
+                    for (var, function) in functions {
+                        self.number_function(*var);
+                        self.number_functions(&function.body);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compiles the main function, which creates the closures of the global
+    /// functions and then evaluates the other globals in order.
+    fn compile_main(&mut self) {
+        let program = self.program;
+        for global in &program.globals {
+            if let GlobalValue::Function(_) = global.value {
+                self.emit(Instr::Closure(self.functions[&global.var]));
+                self.emit(Instr::SetGlobal(self.globals[&global.var]));
+            }
+        }
+        for global in &program.globals {
+            if let GlobalValue::Block(block) = &global.value {
+                self.block(block, false);
+                self.emit(Instr::SetGlobal(self.globals[&global.var]));
+            }
+        }
+        self.emit(Instr::Unit);
+        self.emit(Instr::Return);
+        let state = std::mem::take(&mut self.state);
+        self.module.main = 0;
+        self.module.functions[0] = Function {
+            name: MAIN_NAME.to_string(),
+            arity: 0,
+            captures: 0,
+            locals: state.locals.len() as u32,
+            code: state.code,
+            lines: state.lines,
+        };
+    }
+
+    /// Compiles the function bound to the variable, whose closures capture
+    /// the variables.
+    fn function(&mut self, var: VarId, function: &crate::anf::Function, captures: Vec<VarId>) {
+        let mut state = State {
+            callee: Some(var),
+            ..State::default()
+        };
+        for (i, parameter) in function.parameters.iter().enumerate() {
+            state.locals.insert(*parameter, i as u32);
+        }
+        for (i, capture) in captures.iter().enumerate() {
+            state.captures.insert(*capture, i as u32);
+        }
+        let enclosing = std::mem::replace(&mut self.state, state);
+        self.block(&function.body, true);
+        let state = std::mem::replace(&mut self.state, enclosing);
+
+        let name = match &*self.program.var(var).name {
+            "_" => "<fun>".to_string(),
+            name => name.to_string(),
+        };
+        self.module.functions[self.functions[&var] as usize] = Function {
+            name,
+            arity: function.parameters.len() as u32,
+            captures: captures.len() as u32,
+            locals: state.locals.len() as u32,
+            code: state.code,
+            lines: state.lines,
+        };
+    }
+
+    /// Gets the variables captured by the closures of the local function,
+    /// without the function itself.
+    fn captures(&self, var: VarId) -> Vec<VarId> {
+        self.closures
+            .captures(var)
+            .iter()
+            .copied()
+            .filter(|capture| *capture != var)
+            .collect()
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.state.code.push(instr);
+        self.state.code.len() - 1
+    }
+
+    /// Sets the target of the jump to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.state.code.len() as u32;
+        match &mut self.state.code[at] {
+            Instr::Jump(to)
+            | Instr::JumpIfFalse(to)
+            | Instr::JumpIfTrue(to)
+            | Instr::PushHandler(to) => *to = target,
+            instr => unreachable!("patching {:?}", instr),
+        }
+    }
+
+    /// Reports the next instruction, which may raise an exception, at the
+    /// location of the value bound to the variable.
+    fn locate(&mut self, var: VarId) {
+        let location = self.source_map.span_to_location(self.program.var(var).span);
+        let next = self.module.locations.len() as u32;
+        let module = &mut self.module;
+        let id = *self
+            .locations
+            .entry(location)
+            .or_insert_with_key(|location| {
+                module.locations.push(location.clone());
+                next
+            });
+        self.state.lines.push((self.state.code.len() as u32, id));
+    }
+
+    fn constant(&mut self, constant: Constant) -> u32 {
+        let next = self.module.constants.len() as u32;
+        let constants = &mut self.module.constants;
+        *self
+            .constants
+            .entry(constant)
+            .or_insert_with_key(|constant| {
+                constants.push(constant.clone());
+                next
+            })
+    }
+
+    fn slot(&mut self, var: VarId) -> u32 {
+        let next = self.state.locals.len() as u32;
+        *self.state.locals.entry(var).or_insert(next)
+    }
+
+    fn load(&mut self, var: VarId) {
+        let instr = if let Some(slot) = self.state.locals.get(&var) {
+            Instr::Local(*slot)
+        } else if let Some(index) = self.state.captures.get(&var) {
+            Instr::Capture(*index)
+        } else if self.state.callee == Some(var) {
+            Instr::Callee
+        } else if let Some(slot) = self.globals.get(&var) {
+            Instr::Global(*slot)
+        } else {
+            unreachable!("unbound variable {:?}", var)
+        };
+        self.emit(instr);
+    }
+
+    fn atom(&mut self, atom: &Atom) {
+        match atom {
+            Atom::Var(var) => self.load(*var),
+            Atom::Literal(literal) => self.literal(literal),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        let instr = match literal {
+            Literal::Int(value) => Instr::Const(self.constant(Constant::Int(*value))),
+            Literal::String(value) => {
+                Instr::Const(self.constant(Constant::String(value.to_string())))
+            }
+            Literal::Bool(value) => Instr::Bool(*value),
+            Literal::Unit => Instr::Unit,
+        };
+        self.emit(instr);
+    }
+
+    /// Compiles the block, which leaves its value on the stack, or returns
+    /// it from the function if the block is in tail position.
+    fn block(&mut self, block: &Block, tail: bool) {
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Let(var, value) => {
+                    let last = i + 1 == block.stmts.len();
+                    if tail && last && matches!(block.result, Atom::Var(result) if result == *var) {
+                        self.value(*var, value, true);
+                        return;
+                    }
+                    self.value(*var, value, false);
+                    let slot = self.slot(*var);
+                    self.emit(Instr::SetLocal(slot));
+                }
+                Stmt::LetRec(functions) => self.let_rec(functions),
+            }
+        }
+        self.atom(&block.result);
+        if tail {
+            self.emit(Instr::Return);
+        }
+    }
+
+    /// Creates the closures of the recursive functions before storing the
+    /// captured values, since they may capture each other.
+    fn let_rec(&mut self, functions: &[(VarId, crate::anf::Function)]) {
+        for (var, _) in functions {
+            self.emit(Instr::RecClosure(self.functions[var]));
+            let slot = self.slot(*var);
+            self.emit(Instr::SetLocal(slot));
+        }
+        for (var, function) in functions {
+            let captures = self.captures(*var);
+            for capture in &captures {
+                self.load(*capture);
+            }
+            self.emit(Instr::InitClosure(self.state.locals[var]));
+            self.function(*var, function, captures);
+        }
+    }
+
+    /// Compiles the value bound to the variable, which is returned from the
+    /// function if it is in tail position.
+    fn value(&mut self, var: VarId, value: &Value, tail: bool) {
+        match value {
+            Value::Atom(atom) | Value::Box(atom) | Value::Unbox(atom) => self.atom(atom),
+            Value::Lambda(function) => {
+                let captures = self.captures(var);
+                for capture in &captures {
+                    self.load(*capture);
+                }
+                self.emit(Instr::Closure(self.functions[&var]));
+                self.function(var, function, captures);
+            }
+            Value::Apply(function, arguments) => {
+                self.atom(function);
+                for argument in arguments {
+                    self.atom(argument);
+                }
+                self.locate(var);
+                let count = arguments.len() as u32;
+                if tail {
+                    self.emit(Instr::TailApply(count));
+                    return;
+                }
+                self.emit(Instr::Apply(count));
+            }
+            Value::Call(function, arguments) => {
+                self.load(*function);
+                for argument in arguments {
+                    self.atom(argument);
+                }
+                self.locate(var);
+                let function = self.functions[function];
+                if tail {
+                    self.emit(Instr::TailCall(function));
+                    return;
+                }
+                self.emit(Instr::Call(function));
+            }
+            Value::Prim(op, arguments) => {
+                for argument in arguments {
+                    self.atom(argument);
+                }
+                let may_raise = match op {
+                    PrimOp::Divide => true,
+                    PrimOp::Builtin(builtin) => builtin_may_raise(*builtin),
+                    _ => false,
+                };
+                if may_raise {
+                    self.locate(var);
+                }
+                self.emit(Instr::Prim(*op));
+            }
+            Value::Construct(con, fields) => {
+                for field in fields {
+                    self.atom(field);
+                }
+                self.emit(Instr::Construct(*con, fields.len() as u32));
+            }
+            Value::Field(atom, _, index) => {
+                self.atom(atom);
+                self.emit(Instr::Field(*index as u32));
+            }
+            Value::If(condition, then_block, else_block) => {
+                self.atom(condition);
+                let else_jump = self.emit(Instr::JumpIfFalse(0));
+                self.block(then_block, tail);
+                let end_jump = (!tail).then(|| self.emit(Instr::Jump(0)));
+                self.patch(else_jump);
+                self.block(else_block, tail);
+                if let Some(end_jump) = end_jump {
+                    self.patch(end_jump);
+                }
+                return;
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.switch(scrutinee, cases, default, tail);
+                return;
+            }
+            Value::While(condition, body) => {
+                let start = self.state.code.len() as u32;
+                self.block(condition, false);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.block(body, false);
+                self.emit(Instr::Pop);
+                self.emit(Instr::Jump(start));
+                self.patch(exit);
+                self.emit(Instr::Unit);
+            }
+            Value::For(counter, start, direction, end, body) => {
+                let (comparison, step) = match direction {
+                    ForDirection::Up => (PrimOp::LessEqual, PrimOp::Add),
+                    ForDirection::Down => (PrimOp::GreaterEqual, PrimOp::Subtract),
+                };
+                let counter = self.slot(*counter);
+                self.atom(start);
+                self.emit(Instr::SetLocal(counter));
+                self.emit(Instr::Local(counter));
+                self.atom(end);
+                self.emit(Instr::Prim(comparison));
+                let skip = self.emit(Instr::JumpIfFalse(0));
+                let body_start = self.state.code.len() as u32;
+                self.block(body, false);
+                self.emit(Instr::Pop);
+                // The counter is compared with the end before it is
+                // stepped, so it does not overflow past it.
+                self.emit(Instr::Local(counter));
+                self.atom(end);
+                self.emit(Instr::Prim(PrimOp::Equal));
+                let exit = self.emit(Instr::JumpIfTrue(0));
+                self.emit(Instr::Local(counter));
+                self.literal(&Literal::Int(1));
+                self.emit(Instr::Prim(step));
+                self.emit(Instr::SetLocal(counter));
+                self.emit(Instr::Jump(body_start));
+                self.patch(skip);
+                self.patch(exit);
+                self.emit(Instr::Unit);
+            }
+            Value::Try(body, exception, handler) => {
+                // The calls in the body return to it, since the handler
+                // has to be removed after them.
+                let handler_jump = self.emit(Instr::PushHandler(0));
+                self.block(body, false);
+                self.emit(Instr::PopHandler);
+                let end_jump = if tail {
+                    self.emit(Instr::Return);
+                    None
+                } else {
+                    Some(self.emit(Instr::Jump(0)))
+                };
+                self.patch(handler_jump);
+                let slot = self.slot(*exception);
+                self.emit(Instr::SetLocal(slot));
+                self.block(handler, tail);
+                if let Some(end_jump) = end_jump {
+                    self.patch(end_jump);
+                }
+                return;
+            }
+        }
+        if tail {
+            self.emit(Instr::Return);
+        }
+    }
+
+    fn switch(
+        &mut self,
+        scrutinee: &Atom,
+        cases: &[(Test, Block)],
+        default: &Option<Block>,
+        tail: bool,
+    ) {
+        // The cases with their tests, up to the first one which always
+        // matches.
+        let mut arms = Vec::new();
+        for (test, block) in cases {
+            let test = match test {
+                Test::Con(Con::Tuple(_)) | Test::Con(Con::Record(_)) => None,
+                Test::Literal(Literal::Unit) => None,
+                test => Some(test),
+            };
+            let always = test.is_none();
+            arms.push((test, block));
+            if always {
+                break;
+            }
+        }
+        if !matches!(arms.last(), Some((None, _))) {
+            match default {
+                Some(block) => arms.push((None, block)),
+                // The last case matches the values the others do not.
+                None => {
+                    if let Some(last) = arms.last_mut() {
+                        last.0 = None;
+                    }
+                }
+            }
+        }
+
+        if arms.is_empty() {
+            // A switch without cases on a value which cannot exist.
+            self.emit(Instr::Unit);
+            if tail {
+                self.emit(Instr::Return);
+            }
+            return;
+        }
+        let mut end_jumps = Vec::new();
+        for (test, block) in arms {
+            let next_jump = test.map(|test| {
+                self.atom(scrutinee);
+                match test {
+                    Test::Con(con) => {
+                        self.emit(Instr::IsCon(*con));
+                        self.emit(Instr::JumpIfFalse(0))
+                    }
+                    Test::Literal(Literal::Bool(true)) => self.emit(Instr::JumpIfFalse(0)),
+                    Test::Literal(Literal::Bool(false)) => self.emit(Instr::JumpIfTrue(0)),
+                    Test::Literal(literal) => {
+                        self.literal(literal);
+                        self.emit(Instr::Prim(PrimOp::Equal));
+                        self.emit(Instr::JumpIfFalse(0))
+                    }
+                }
+            });
+            self.block(block, tail);
+            if !tail && next_jump.is_some() {
+                end_jumps.push(self.emit(Instr::Jump(0)));
+            }
+            if let Some(next_jump) = next_jump {
+                self.patch(next_jump);
+            }
+        }
+        for end_jump in end_jumps {
+            self.patch(end_jump);
+        }
+    }
+}
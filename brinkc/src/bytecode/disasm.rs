@@ -0,0 +1,123 @@
+use std::fmt::Write;
+
+use crate::ir::Con;
+
+use super::{Constant, Instr, Module};
+
+/// Prints the module for `brinkc disasm`: the constant pool, the globals
+/// and the code of every function, with the constants, the functions and
+/// the globals the operands refer to and the locations of the
+/// instructions in the comments.
+pub fn disassemble(module: &Module) -> String {
+    let mut output = String::new();
+    output.push_str("constants:\n");
+    for (i, constant) in module.constants.iter().enumerate() {
+        writeln!(output, "  {:4}  {}", i, constant_text(constant)).unwrap();
+    }
+    output.push_str("globals:\n");
+    for (i, name) in module.globals.iter().enumerate() {
+        writeln!(output, "  {:4}  {}", i, name).unwrap();
+    }
+    for (i, function) in module.functions.iter().enumerate() {
+        writeln!(
+            output,
+            "\nfunction {} {}{} (arity {}, captures {}, locals {}):",
+            i,
+            function.name,
+            if i as u32 == module.main {
+                " [main]"
+            } else {
+                ""
+            },
+            function.arity,
+            function.captures,
+            function.locals
+        )
+        .unwrap();
+        for (index, instr) in function.code.iter().enumerate() {
+            let mut line = format!("  {:04}  {}", index, instr.name());
+            let operands = operands(module, *instr);
+            if !operands.is_empty() {
+                line.push(' ');
+                line.push_str(&operands);
+            }
+            let mut comments = Vec::new();
+            if let Some(comment) = comment(module, *instr) {
+                comments.push(comment);
+            }
+            if let Some(location) = function.location(index) {
+                comments.push(module.locations[location as usize].clone());
+            }
+            if !comments.is_empty() {
+                line = format!("{:<32}; {}", line, comments.join(", "));
+            }
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+fn constant_text(constant: &Constant) -> String {
+    match constant {
+        Constant::Int(value) => format!("int {}", value),
+        Constant::String(value) => format!("string {:?}", value),
+    }
+}
+
+fn operands(module: &Module, instr: Instr) -> String {
+    match instr {
+        Instr::Const(operand)
+        | Instr::Local(operand)
+        | Instr::SetLocal(operand)
+        | Instr::Capture(operand)
+        | Instr::Global(operand)
+        | Instr::SetGlobal(operand)
+        | Instr::Closure(operand)
+        | Instr::RecClosure(operand)
+        | Instr::InitClosure(operand)
+        | Instr::Apply(operand)
+        | Instr::TailApply(operand)
+        | Instr::Call(operand)
+        | Instr::TailCall(operand)
+        | Instr::Field(operand) => operand.to_string(),
+        Instr::Jump(target)
+        | Instr::JumpIfFalse(target)
+        | Instr::JumpIfTrue(target)
+        | Instr::PushHandler(target) => format!("{:04}", target),
+        Instr::Bool(value) => value.to_string(),
+        Instr::Prim(op) => op.name().to_string(),
+        Instr::Construct(con, count) => format!("{} {}", con_name(module, con), count),
+        Instr::IsCon(con) => con_name(module, con),
+        Instr::Unit | Instr::Callee | Instr::Pop | Instr::Return | Instr::PopHandler => {
+            String::new()
+        }
+    }
+}
+
+/// Describes what the operand refers to.
+fn comment(module: &Module, instr: Instr) -> Option<String> {
+    match instr {
+        Instr::Const(constant) => Some(constant_text(&module.constants[constant as usize])),
+        Instr::Global(global) | Instr::SetGlobal(global) => {
+            Some(module.globals[global as usize].clone())
+        }
+        Instr::Closure(function)
+        | Instr::RecClosure(function)
+        | Instr::Call(function)
+        | Instr::TailCall(function) => Some(module.functions[function as usize].name.clone()),
+        _ => None,
+    }
+}
+
+fn con_name(module: &Module, con: Con) -> String {
+    match con {
+        Con::Tuple(n) => format!("({})", ",".repeat(n.saturating_sub(1))),
+        Con::Record(def) => format!("{{{}}}", module.tag_names[def.as_usize()]),
+        Con::Constructor(def) => module.tag_names[def.as_usize()].clone(),
+        Con::Exception(builtin) => builtin.name().to_string(),
+        Con::Nil => "[]".to_string(),
+        Con::Cons => "::".to_string(),
+        Con::Array(n) => format!("[|{}|]", n),
+    }
+}
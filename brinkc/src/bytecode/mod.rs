@@ -0,0 +1,159 @@
+//! The bytecode of the virtual machine: a constant pool, a table of the
+//! functions with the number of the values their closures capture, and the
+//! instructions of a stack machine. The bytecode is compiled from the
+//! mid-level IR, can be printed by the disassembler, and is serialized to
+//! `.bkc` files so it can be cached.
+
+use crate::ir::{Con, PrimOp};
+
+mod compile;
+mod disasm;
+mod serialize;
+
+pub use compile::compile;
+pub use disasm::disassemble;
+pub use serialize::{deserialize, serialize};
+
+/// A compiled program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+    /// The names of the global variables, indexed by their slots.
+    pub globals: Vec<String>,
+    /// The function evaluating the globals, called when the program starts.
+    pub main: u32,
+    /// The names of the constructors, exceptions and records, indexed by
+    /// their definitions: the record entries are their field names
+    /// separated by spaces. They are used to show the values.
+    pub tag_names: Vec<String>,
+    /// The source locations the instructions are reported at, e.g.
+    /// `main.bk:3:17`.
+    pub locations: Vec<String>,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    /// The name shown in the stack traces.
+    pub name: String,
+    pub arity: u32,
+    /// The number of the values captured by the closures of the function.
+    pub captures: u32,
+    /// The number of the local variables, including the parameters, which
+    /// are the first ones.
+    pub locals: u32,
+    pub code: Vec<Instr>,
+    /// The locations of the instructions which may raise an exception,
+    /// ordered by the instruction index: the index and the location.
+    pub lines: Vec<(u32, u32)>,
+}
+
+impl Function {
+    /// Gets the location the instruction is reported at.
+    pub fn location(&self, index: usize) -> Option<u32> {
+        let position = self
+            .lines
+            .binary_search_by_key(&(index as u32), |(index, _)| *index)
+            .ok()?;
+        Some(self.lines[position].1)
+    }
+}
+
+/// An instruction of the stack machine. The operands are popped from the
+/// stack, the last one first, and the result is pushed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Instr {
+    /// Pushes the constant.
+    Const(u32),
+    Unit,
+    Bool(bool),
+    /// Pushes the local variable.
+    Local(u32),
+    /// Pops the value into the local variable.
+    SetLocal(u32),
+    /// Pushes the value captured by the closure of the running function.
+    Capture(u32),
+    /// Pushes the closure of the running function, which the recursive
+    /// functions call instead of capturing it.
+    Callee,
+    Global(u32),
+    SetGlobal(u32),
+    /// Pops the captured values and pushes a closure of the function.
+    Closure(u32),
+    /// Pushes a closure of the function whose captured values are stored
+    /// later, for the recursive functions capturing each other.
+    RecClosure(u32),
+    /// Pops the captured values into the closure in the local variable,
+    /// created by `RecClosure`.
+    InitClosure(u32),
+    Pop,
+    /// Applies the function to the arguments, both popped.
+    Apply(u32),
+    /// Applies the function to the arguments, returning the result from the
+    /// running function and reusing its frame.
+    TailApply(u32),
+    /// Calls the function known to be the popped closure with all of its
+    /// arguments.
+    Call(u32),
+    TailCall(u32),
+    Return,
+    Jump(u32),
+    /// Pops the boolean and jumps if it is false.
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    /// Applies the primitive operation to its operands. The integers are
+    /// not boxed in the machine, so the boxing of the mid-level IR is
+    /// implicit.
+    Prim(PrimOp),
+    /// Pops the fields and pushes the value built by the constructor.
+    Construct(Con, u32),
+    /// Pops the value and pushes its field.
+    Field(u32),
+    /// Pops the value and pushes whether it was built by the constructor.
+    IsCon(Con),
+    /// Handles the exceptions raised by the following instructions at the
+    /// target, which gets the exception on the stack.
+    PushHandler(u32),
+    PopHandler,
+}
+
+impl Instr {
+    /// Gets the name of the instruction in the disassembly and the errors.
+    pub fn name(self) -> &'static str {
+        match self {
+            Instr::Const(_) => "const",
+            Instr::Unit => "unit",
+            Instr::Bool(_) => "bool",
+            Instr::Local(_) => "local",
+            Instr::SetLocal(_) => "set_local",
+            Instr::Capture(_) => "capture",
+            Instr::Callee => "callee",
+            Instr::Global(_) => "global",
+            Instr::SetGlobal(_) => "set_global",
+            Instr::Closure(_) => "closure",
+            Instr::RecClosure(_) => "rec_closure",
+            Instr::InitClosure(_) => "init_closure",
+            Instr::Pop => "pop",
+            Instr::Apply(_) => "apply",
+            Instr::TailApply(_) => "tail_apply",
+            Instr::Call(_) => "call",
+            Instr::TailCall(_) => "tail_call",
+            Instr::Return => "return",
+            Instr::Jump(_) => "jump",
+            Instr::JumpIfFalse(_) => "jump_if_false",
+            Instr::JumpIfTrue(_) => "jump_if_true",
+            Instr::Prim(_) => "prim",
+            Instr::Construct(..) => "construct",
+            Instr::Field(_) => "field",
+            Instr::IsCon(_) => "is_con",
+            Instr::PushHandler(_) => "push_handler",
+            Instr::PopHandler => "pop_handler",
+        }
+    }
+}
@@ -0,0 +1,718 @@
+//! The `.bkc` files: the magic bytes and the format version, followed by
+//! the module. The integers are stored in little-endian order with fixed
+//! widths, the strings as their byte lengths followed by the bytes, and the
+//! instructions as their opcodes followed by their operands.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    ir::{Con, PrimOp},
+    resolve::{Builtin, DefId},
+};
+
+use super::{Constant, Function, Instr, Module};
+
+const MAGIC: &[u8; 4] = b"BKC\0";
+/// The version of the format, changed whenever the instructions are.
+const VERSION: u32 = 2;
+
+/// Serializes the module to the contents of a `.bkc` file.
+pub fn serialize(module: &Module) -> Vec<u8> {
+    let mut writer = Writer { bytes: Vec::new() };
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u32(VERSION);
+    writer.u32(module.constants.len() as u32);
+    for constant in &module.constants {
+        match constant {
+            Constant::Int(value) => {
+                writer.u8(0);
+                writer.bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Constant::String(value) => {
+                writer.u8(1);
+                writer.string(value);
+            }
+        }
+    }
+    for strings in &[&module.globals, &module.tag_names, &module.locations] {
+        writer.u32(strings.len() as u32);
+        for string in strings.iter() {
+            writer.string(string);
+        }
+    }
+    writer.u32(module.main);
+    writer.u32(module.functions.len() as u32);
+    for function in &module.functions {
+        writer.string(&function.name);
+        writer.u32(function.arity);
+        writer.u32(function.captures);
+        writer.u32(function.locals);
+        writer.u32(function.code.len() as u32);
+        for instr in &function.code {
+            writer.instr(*instr);
+        }
+        writer.u32(function.lines.len() as u32);
+        for (index, location) in &function.lines {
+            writer.u32(*index);
+            writer.u32(*location);
+        }
+    }
+    writer.bytes
+}
+
+/// Deserializes the contents of a `.bkc` file, checking that the operands
+/// of the instructions are in the bounds of the module and that the
+/// instructions find their operands on the stack. The counts in the file,
+/// including the numbers of the local variables, cannot exceed its length,
+/// so a corrupted file cannot make the machine allocate without bound.
+pub fn deserialize(bytes: &[u8]) -> Result<Module, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("not a brink bytecode file".to_string());
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(format!(
+            "unsupported bytecode version {}, expected {}",
+            version, VERSION
+        ));
+    }
+    let mut module = Module {
+        constants: Vec::new(),
+        functions: Vec::new(),
+        globals: Vec::new(),
+        main: 0,
+        tag_names: Vec::new(),
+        locations: Vec::new(),
+    };
+    for _ in 0..reader.count()? {
+        let constant = match reader.u8()? {
+            0 => Constant::Int(i64::from_le_bytes(reader.array()?)),
+            1 => Constant::String(reader.string()?),
+            kind => return Err(format!("invalid constant kind {}", kind)),
+        };
+        module.constants.push(constant);
+    }
+    for strings in [
+        &mut module.globals,
+        &mut module.tag_names,
+        &mut module.locations,
+    ] {
+        for _ in 0..reader.count()? {
+            strings.push(reader.string()?);
+        }
+    }
+    module.main = reader.u32()?;
+    for _ in 0..reader.count()? {
+        let name = reader.string()?;
+        let arity = reader.count()?;
+        let captures = reader.count()?;
+        let locals = reader.count()?;
+        let mut code = Vec::new();
+        for _ in 0..reader.count()? {
+            code.push(reader.instr()?);
+        }
+        let mut lines = Vec::new();
+        for _ in 0..reader.count()? {
+            lines.push((reader.u32()?, reader.u32()?));
+        }
+        module.functions.push(Function {
+            name,
+            arity,
+            captures,
+            locals,
+            code,
+            lines,
+        });
+    }
+    if reader.position != bytes.len() {
+        return Err("trailing bytes after the module".to_string());
+    }
+    check(&module)?;
+    Ok(module)
+}
+
+/// Checks the operands of the instructions, that the code of every
+/// function ends with an instruction leaving it, and that the instructions
+/// find their operands on the stack.
+fn check(module: &Module) -> Result<(), String> {
+    let functions = module.functions.len();
+    // The main function is called without arguments or captured values.
+    match module.functions.get(module.main as usize) {
+        Some(main) if main.arity == 0 && main.captures == 0 => {}
+        _ => return Err(format!("invalid main function {}", module.main)),
+    }
+    for function in &module.functions {
+        let error = |index: usize, instr: Instr| {
+            format!(
+                "invalid operand of `{}` at {} in `{}`",
+                instr.name(),
+                index,
+                function.name
+            )
+        };
+        if function.locals < function.arity {
+            return Err(format!("missing parameters in `{}`", function.name));
+        }
+        match function.code.last() {
+            Some(Instr::Return)
+            | Some(Instr::TailApply(_))
+            | Some(Instr::TailCall(_))
+            | Some(Instr::Jump(_)) => {}
+            _ => return Err(format!("unterminated code in `{}`", function.name)),
+        }
+        for (index, instr) in function.code.iter().enumerate() {
+            let valid = match *instr {
+                Instr::Const(constant) => (constant as usize) < module.constants.len(),
+                Instr::Local(slot) | Instr::SetLocal(slot) | Instr::InitClosure(slot) => {
+                    slot < function.locals
+                }
+                Instr::Capture(capture) => capture < function.captures,
+                Instr::Global(global) | Instr::SetGlobal(global) => {
+                    (global as usize) < module.globals.len()
+                }
+                Instr::Closure(callee)
+                | Instr::RecClosure(callee)
+                | Instr::Call(callee)
+                | Instr::TailCall(callee) => (callee as usize) < functions,
+                Instr::Jump(target)
+                | Instr::JumpIfFalse(target)
+                | Instr::JumpIfTrue(target)
+                | Instr::PushHandler(target) => (target as usize) < function.code.len(),
+                Instr::Construct(con, count) if !con_takes(con, count as usize) => false,
+                Instr::Construct(con, _) | Instr::IsCon(con) => match con {
+                    Con::Record(def) | Con::Constructor(def) => {
+                        def.as_usize() < module.tag_names.len()
+                    }
+                    _ => true,
+                },
+                _ => true,
+            };
+            if !valid {
+                return Err(error(index, *instr));
+            }
+        }
+        let mut previous = None;
+        for (index, location) in &function.lines {
+            if previous.is_some_and(|previous| previous >= *index)
+                || *location as usize >= module.locations.len()
+            {
+                return Err(format!("invalid line table in `{}`", function.name));
+            }
+            previous = Some(*index);
+        }
+        check_stack(module, function)?;
+    }
+    Ok(())
+}
+
+/// Whether the constructor can build a value of the fields, whose number
+/// the lists, the tuples and the arrays determine.
+fn con_takes(con: Con, count: usize) -> bool {
+    match con {
+        Con::Nil => count == 0,
+        Con::Cons => count == 2,
+        Con::Tuple(n) | Con::Array(n) => count == n,
+        Con::Record(_) | Con::Constructor(_) | Con::Exception(_) => true,
+    }
+}
+
+/// The values on the stack before an instruction, as the closures created
+/// by `RecClosure`, whose captured values `InitClosure` pops, and unknown
+/// values. Only the local variables holding such closures are recorded.
+#[derive(Clone, PartialEq)]
+struct StackState {
+    stack: Vec<Option<u32>>,
+    locals: BTreeMap<u32, u32>,
+    handlers: usize,
+}
+
+impl StackState {
+    /// Merges the state of another path to the instruction, which has to
+    /// have the same depths, returning whether the state changed.
+    fn merge(&mut self, other: &StackState) -> Option<bool> {
+        if self.stack.len() != other.stack.len() || self.handlers != other.handlers {
+            return None;
+        }
+        let merged = StackState {
+            stack: self
+                .stack
+                .iter()
+                .zip(&other.stack)
+                .map(|(value, other)| if value == other { *value } else { None })
+                .collect(),
+            locals: self
+                .locals
+                .iter()
+                .filter(|(slot, callee)| other.locals.get(slot) == Some(callee))
+                .map(|(slot, callee)| (*slot, *callee))
+                .collect(),
+            handlers: self.handlers,
+        };
+        let changed = merged != *self;
+        *self = merged;
+        Some(changed)
+    }
+}
+
+/// Checks that every instruction finds its operands on the stack above the
+/// local variables, with the same depth whichever jump reaches it, and that
+/// the exception handlers are removed before the function returns, so the
+/// machine can run the code without checking the stack.
+fn check_stack(module: &Module, function: &Function) -> Result<(), String> {
+    let mut states = vec![None; function.code.len()];
+    states[0] = Some(StackState {
+        stack: Vec::new(),
+        locals: BTreeMap::new(),
+        handlers: 0,
+    });
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+        let instr = function.code[index];
+        let error = |message: &str| {
+            format!(
+                "{} by `{}` at {} in `{}`",
+                message,
+                instr.name(),
+                index,
+                function.name
+            )
+        };
+        let mut state: StackState = states[index].clone().unwrap();
+        let captures = |callee: u32| module.functions[callee as usize].captures as usize;
+        let arity = |callee: u32| module.functions[callee as usize].arity as usize;
+        let pops = match instr {
+            Instr::SetLocal(_) | Instr::SetGlobal(_) | Instr::Pop => 1,
+            Instr::Closure(callee) => captures(callee),
+            Instr::InitClosure(slot) => match state.locals.get(&slot).copied() {
+                Some(callee) => captures(callee),
+                None => return Err(error("uninitializable closure")),
+            },
+            Instr::Apply(count) | Instr::TailApply(count) => count as usize + 1,
+            Instr::Call(callee) | Instr::TailCall(callee) => arity(callee) + 1,
+            Instr::Return
+            | Instr::JumpIfFalse(_)
+            | Instr::JumpIfTrue(_)
+            | Instr::Field(_)
+            | Instr::IsCon(_) => 1,
+            Instr::Prim(op) => op.arity(),
+            Instr::Construct(_, count) => count as usize,
+            _ => 0,
+        };
+        if state.stack.len() < pops {
+            return Err(error("stack underflow"));
+        }
+        let popped = state.stack.split_off(state.stack.len() - pops);
+        let mut successors = vec![index + 1];
+        match instr {
+            Instr::Local(slot) => state.stack.push(state.locals.get(&slot).copied()),
+            Instr::SetLocal(slot) => match popped[0] {
+                Some(callee) => {
+                    state.locals.insert(slot, callee);
+                }
+                None => {
+                    state.locals.remove(&slot);
+                }
+            },
+            Instr::RecClosure(callee) => state.stack.push(Some(callee)),
+            Instr::InitClosure(slot) => {
+                state.locals.remove(&slot);
+            }
+            Instr::SetGlobal(_)
+            | Instr::Pop
+            | Instr::Jump(_)
+            | Instr::JumpIfFalse(_)
+            | Instr::JumpIfTrue(_)
+            | Instr::PushHandler(_)
+            | Instr::PopHandler => {}
+            _ => state.stack.push(None),
+        }
+        match instr {
+            Instr::Return | Instr::TailApply(_) | Instr::TailCall(_) => {
+                if state.handlers > 0 {
+                    return Err(error("unremoved exception handler"));
+                }
+                successors.clear();
+            }
+            Instr::Jump(target) => successors = vec![target as usize],
+            Instr::JumpIfFalse(target) | Instr::JumpIfTrue(target) => {
+                successors.push(target as usize)
+            }
+            Instr::PushHandler(target) => {
+                // The handler gets the exception on the stack as it was
+                // when the handler was pushed.
+                let mut handler = state.clone();
+                handler.stack.push(None);
+                merge_state(&mut states, &mut pending, target as usize, handler)
+                    .map_err(|()| error("inconsistent stack"))?;
+                state.handlers += 1;
+            }
+            Instr::PopHandler if state.handlers == 0 => {
+                return Err(error("missing exception handler"))
+            }
+            Instr::PopHandler => state.handlers -= 1,
+            _ => {}
+        }
+        for successor in successors {
+            merge_state(&mut states, &mut pending, successor, state.clone())
+                .map_err(|()| error("inconsistent stack"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Records a path to the instruction, to check it again if its state
+/// changed.
+fn merge_state(
+    states: &mut [Option<StackState>],
+    pending: &mut Vec<usize>,
+    index: usize,
+    state: StackState,
+) -> Result<(), ()> {
+    let changed = match &mut states[index] {
+        Some(existing) => existing.merge(&state).ok_or(())?,
+        slot @ None => {
+            *slot = Some(state);
+            true
+        }
+    };
+    if changed {
+        pending.push(index);
+    }
+    Ok(())
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn instr(&mut self, instr: Instr) {
+        self.u8(opcode(instr));
+        match instr {
+            Instr::Const(operand)
+            | Instr::Local(operand)
+            | Instr::SetLocal(operand)
+            | Instr::Capture(operand)
+            | Instr::Global(operand)
+            | Instr::SetGlobal(operand)
+            | Instr::Closure(operand)
+            | Instr::RecClosure(operand)
+            | Instr::InitClosure(operand)
+            | Instr::Apply(operand)
+            | Instr::TailApply(operand)
+            | Instr::Call(operand)
+            | Instr::TailCall(operand)
+            | Instr::Jump(operand)
+            | Instr::JumpIfFalse(operand)
+            | Instr::JumpIfTrue(operand)
+            | Instr::Field(operand)
+            | Instr::PushHandler(operand) => self.u32(operand),
+            Instr::Bool(value) => self.u8(value as u8),
+            Instr::Prim(op) => match op {
+                PrimOp::Builtin(builtin) => {
+                    self.u8(PRIM_OPS.len() as u8);
+                    self.u8(builtin as u8);
+                }
+                op => self.u8(PRIM_OPS.iter().position(|o| *o == op).unwrap() as u8),
+            },
+            Instr::Construct(con, count) => {
+                self.con(con);
+                self.u32(count);
+            }
+            Instr::IsCon(con) => self.con(con),
+            Instr::Unit | Instr::Callee | Instr::Pop | Instr::Return | Instr::PopHandler => {}
+        }
+    }
+
+    fn con(&mut self, con: Con) {
+        let (kind, payload) = match con {
+            Con::Tuple(n) => (0, n as u32),
+            Con::Record(def) => (1, def.as_usize() as u32),
+            Con::Constructor(def) => (2, def.as_usize() as u32),
+            Con::Exception(builtin) => (3, builtin as u32),
+            Con::Nil => (4, 0),
+            Con::Cons => (5, 0),
+            Con::Array(n) => (6, n as u32),
+        };
+        self.u8(kind);
+        self.u32(payload);
+    }
+}
+
+/// The primitive operations other than the builtins, numbered by their
+/// positions. The builtins follow them, with the builtin as the operand.
+const PRIM_OPS: &[PrimOp] = &[
+    PrimOp::Add,
+    PrimOp::Subtract,
+    PrimOp::Multiply,
+    PrimOp::Divide,
+    PrimOp::Negate,
+    PrimOp::Less,
+    PrimOp::LessEqual,
+    PrimOp::Greater,
+    PrimOp::GreaterEqual,
+    PrimOp::Equal,
+    PrimOp::NotEqual,
+    PrimOp::Deref,
+    PrimOp::Assign,
+];
+
+fn opcode(instr: Instr) -> u8 {
+    match instr {
+        Instr::Const(_) => 0,
+        Instr::Unit => 1,
+        Instr::Bool(_) => 2,
+        Instr::Local(_) => 3,
+        Instr::SetLocal(_) => 4,
+        Instr::Capture(_) => 5,
+        Instr::Callee => 6,
+        Instr::Global(_) => 7,
+        Instr::SetGlobal(_) => 8,
+        Instr::Closure(_) => 9,
+        Instr::RecClosure(_) => 10,
+        Instr::InitClosure(_) => 11,
+        Instr::Pop => 12,
+        Instr::Apply(_) => 13,
+        Instr::TailApply(_) => 14,
+        Instr::Call(_) => 15,
+        Instr::TailCall(_) => 16,
+        Instr::Return => 17,
+        Instr::Jump(_) => 18,
+        Instr::JumpIfFalse(_) => 19,
+        Instr::JumpIfTrue(_) => 20,
+        Instr::Prim(_) => 21,
+        Instr::Construct(..) => 22,
+        Instr::Field(_) => 23,
+        Instr::IsCon(_) => 24,
+        Instr::PushHandler(_) => 25,
+        Instr::PopHandler => 26,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("unexpected end of the bytecode file")?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    /// Reads a count, of elements or of local variables, which cannot
+    /// exceed the length of the file.
+    fn count(&mut self) -> Result<u32, String> {
+        let count = self.u32()?;
+        if count as usize > self.bytes.len() {
+            return Err(format!("invalid count {} in the bytecode file", count));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| "invalid UTF-8 in the bytecode file".to_string())
+    }
+
+    fn builtin(&mut self) -> Result<Builtin, String> {
+        let index = self.u8()?;
+        Builtin::from_index(index as usize).ok_or_else(|| format!("invalid builtin {}", index))
+    }
+
+    fn instr(&mut self) -> Result<Instr, String> {
+        let opcode = self.u8()?;
+        Ok(match opcode {
+            0 => Instr::Const(self.u32()?),
+            1 => Instr::Unit,
+            2 => Instr::Bool(self.u8()? != 0),
+            3 => Instr::Local(self.u32()?),
+            4 => Instr::SetLocal(self.u32()?),
+            5 => Instr::Capture(self.u32()?),
+            6 => Instr::Callee,
+            7 => Instr::Global(self.u32()?),
+            8 => Instr::SetGlobal(self.u32()?),
+            9 => Instr::Closure(self.u32()?),
+            10 => Instr::RecClosure(self.u32()?),
+            11 => Instr::InitClosure(self.u32()?),
+            12 => Instr::Pop,
+            13 => Instr::Apply(self.count()?),
+            14 => Instr::TailApply(self.count()?),
+            15 => Instr::Call(self.u32()?),
+            16 => Instr::TailCall(self.u32()?),
+            17 => Instr::Return,
+            18 => Instr::Jump(self.u32()?),
+            19 => Instr::JumpIfFalse(self.u32()?),
+            20 => Instr::JumpIfTrue(self.u32()?),
+            21 => {
+                let code = self.u8()? as usize;
+                Instr::Prim(match PRIM_OPS.get(code) {
+                    Some(op) => *op,
+                    None if code == PRIM_OPS.len() => PrimOp::Builtin(self.builtin()?),
+                    None => return Err(format!("invalid primitive operation {}", code)),
+                })
+            }
+            22 => {
+                let con = self.con()?;
+                Instr::Construct(con, self.count()?)
+            }
+            23 => Instr::Field(self.u32()?),
+            24 => Instr::IsCon(self.con()?),
+            25 => Instr::PushHandler(self.u32()?),
+            26 => Instr::PopHandler,
+            opcode => return Err(format!("invalid opcode {}", opcode)),
+        })
+    }
+
+    fn con(&mut self) -> Result<Con, String> {
+        let kind = self.u8()?;
+        let payload = self.count()? as usize;
+        Ok(match kind {
+            0 => Con::Tuple(payload),
+            1 => Con::Record(DefId::new(payload)),
+            2 => Con::Constructor(DefId::new(payload)),
+            3 => Con::Exception(
+                Builtin::from_index(payload)
+                    .ok_or_else(|| format!("invalid builtin {}", payload))?,
+            ),
+            4 => Con::Nil,
+            5 => Con::Cons,
+            6 => Con::Array(payload),
+            kind => return Err(format!("invalid constructor kind {}", kind)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module() -> Module {
+        Module {
+            constants: vec![Constant::Int(-7), Constant::String("é\n".to_string())],
+            functions: vec![Function {
+                name: "<main>".to_string(),
+                arity: 0,
+                captures: 0,
+                locals: 1,
+                code: vec![
+                    Instr::Const(0),
+                    Instr::Const(1),
+                    Instr::Construct(Con::Exception(Builtin::Failure), 1),
+                    Instr::Prim(PrimOp::Builtin(Builtin::Raise)),
+                    Instr::Prim(PrimOp::Divide),
+                    Instr::IsCon(Con::Constructor(DefId::new(0))),
+                    Instr::Return,
+                ],
+                lines: vec![(3, 0)],
+            }],
+            globals: vec!["x".to_string()],
+            main: 0,
+            tag_names: vec!["Some".to_string()],
+            locations: vec!["main.bk:1:9".to_string()],
+        }
+    }
+
+    #[test]
+    fn round_trips_modules() {
+        let module = module();
+        assert_eq!(Ok(module.clone()), deserialize(&serialize(&module)));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let bytes = serialize(&module());
+        assert!(deserialize(b"BKX\0")
+            .unwrap_err()
+            .contains("not a brink bytecode"));
+        assert!(deserialize(&bytes[..bytes.len() - 1])
+            .unwrap_err()
+            .contains("unexpected end"));
+
+        let mut module = module();
+        module.functions[0].code[0] = Instr::Const(2);
+        assert!(deserialize(&serialize(&module))
+            .unwrap_err()
+            .contains("invalid operand of `const` at 0"));
+        module.functions[0].code.pop();
+        module.functions[0].code[0] = Instr::Const(0);
+        assert!(deserialize(&serialize(&module))
+            .unwrap_err()
+            .contains("unterminated code"));
+    }
+
+    #[test]
+    fn rejects_oversized_counts() {
+        let mut invalid = module();
+        invalid.functions[0].locals = u32::MAX;
+        assert!(deserialize(&serialize(&invalid))
+            .unwrap_err()
+            .contains("invalid count 4294967295"));
+
+        let mut invalid = module();
+        invalid.functions[0].code[2] = Instr::Construct(Con::Cons, 1);
+        assert!(deserialize(&serialize(&invalid))
+            .unwrap_err()
+            .contains("invalid operand of `construct` at 2"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_stacks() {
+        let mut invalid = module();
+        invalid.functions[0].code[1] = Instr::Pop;
+        assert!(deserialize(&serialize(&invalid))
+            .unwrap_err()
+            .contains("stack underflow by `construct` at 2"));
+
+        let mut invalid = module();
+        invalid.functions[0].code.splice(
+            0..0,
+            [Instr::Bool(true), Instr::JumpIfFalse(3), Instr::Unit],
+        );
+        assert!(deserialize(&serialize(&invalid))
+            .unwrap_err()
+            .contains("inconsistent stack"));
+
+        let mut invalid = module();
+        invalid.functions[0].code.insert(0, Instr::PushHandler(8));
+        invalid.functions[0].code.push(Instr::Return);
+        assert!(deserialize(&serialize(&invalid))
+            .unwrap_err()
+            .contains("unremoved exception handler by `return` at 7"));
+    }
+}
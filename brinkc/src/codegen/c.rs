@@ -0,0 +1,1158 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+    rc::Rc,
+};
+
+use crate::{
+    anf::{Atom, Block, Function, GlobalValue, Program, Repr, Stmt, Value},
+    ast::ForDirection,
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    resolve::{Builtin, Resolutions},
+};
+
+use super::{
+    builtin_may_raise, builtin_symbol, con_kind, def_names, runtime_constants,
+    screaming_snake_case, Closures, Frame, RUNTIME, RUNTIME_HEADER,
+};
+
+/// Generates a single C file of the program and the runtime, which any C
+/// compiler builds into the executable, e.g. `cc -O2 main.c -lpthread`.
+///
+/// The values have the layout of the native backend: the closures are
+/// `struct brink_closure`s of their entry points and captured values, and
+/// the values of the variant types are tagged with their constructors. The
+/// functions are compiled to C functions taking the closure and the
+/// arguments, and the exceptions to the checks of `brink_exn` after the
+/// calls which may raise one. The values of each function are kept in the
+/// `roots` of its frame on the shadow stack, where the garbage collector
+/// finds them. C has no portable way to require the tail calls, so the
+/// calls of a function to itself in tail position jump back to its start,
+/// and its other tail calls return `BRINK_TAIL` to the trampoline of the
+/// caller, which makes them; either way they run in constant space.
+pub fn emit_c(program: &Program, resolutions: &Resolutions) -> String {
+    let mut emitter = Emitter {
+        program,
+        resolutions,
+        closures: Closures::new(program),
+        global_functions: HashSet::new(),
+        functions: Vec::new(),
+        strings: HashMap::new(),
+        string_definitions: String::new(),
+        tags: BTreeMap::new(),
+        output: String::new(),
+        indent: 0,
+        labels: 0,
+        handlers: Vec::new(),
+        frame: Frame::default(),
+        current: None,
+        restarts: false,
+    };
+    for global in &program.globals {
+        match &global.value {
+            GlobalValue::Function(function) => {
+                emitter.global_functions.insert(global.var);
+                emitter.functions.push((global.var, function));
+                collect_functions(&function.body, &mut emitter.functions);
+            }
+            GlobalValue::Block(block) => collect_functions(block, &mut emitter.functions),
+        }
+    }
+
+    for (var, function) in emitter.functions.clone() {
+        emitter.function(var, function);
+    }
+    emitter.main();
+    let code = std::mem::take(&mut emitter.output);
+
+    let mut source =
+        String::from("/* Generated by brinkc. The runtime of the program follows its code. */\n\n");
+    source.push_str(&runtime_constants());
+    source.push_str(RUNTIME_HEADER);
+    source.push_str("\n/* ---- Program ---- */\n\n");
+    for (tag, name) in &emitter.tags {
+        writeln!(source, "#define {} {}", name, tag).unwrap();
+    }
+    if !emitter.tags.is_empty() {
+        source.push('\n');
+    }
+    source.push_str(&emitter.string_definitions);
+    if !emitter.string_definitions.is_empty() {
+        source.push('\n');
+    }
+    for (var, function) in &emitter.functions {
+        let name = emitter.name(*var);
+        writeln!(
+            source,
+            "static value {}_fn({});",
+            name,
+            parameters(function.parameters.len())
+        )
+        .unwrap();
+        writeln!(
+            source,
+            "static value {}_entry(value self, value *arguments);",
+            name
+        )
+        .unwrap();
+    }
+    source.push('\n');
+    for global in &program.globals {
+        let name = emitter.name(global.var);
+        match &global.value {
+            GlobalValue::Function(function) => writeln!(
+                source,
+                "static struct {{ uint64_t gc; uint64_t header; entry_fn entry; \
+                 void (*direct)(void); }} {name}_closure = \
+                 {{BRINK_GC_STATIC, MAKE_HEADER(BRINK_KIND_CLOSURE, {}, CLOSURE_CAPTURES), \
+                 {name}_entry, (void (*)(void)){name}_fn}};",
+                function.parameters.len(),
+                name = name
+            ),
+            GlobalValue::Block(_) => writeln!(
+                source,
+                "static {} {};",
+                c_type(program.var(global.var).repr),
+                name
+            ),
+        }
+        .unwrap();
+    }
+    source.push_str("value *const brink_globals[] = {");
+    for global in &program.globals {
+        if matches!(global.value, GlobalValue::Block(_))
+            && program.var(global.var).repr == Repr::Value
+        {
+            write!(source, "&{}, ", emitter.name(global.var)).unwrap();
+        }
+    }
+    source.push_str("0};\n\n");
+    source.push_str(&code);
+
+    source.push_str("const char brink_def_names[] =");
+    let names = def_names(resolutions);
+    for name in names.split(|byte| *byte == 0).take(resolutions.defs.len()) {
+        write!(source, "\n    \"{}\\000\"", escape(name)).unwrap();
+    }
+    source.push_str(";\n\n/* ---- Runtime ---- */\n\n");
+    source.push_str(RUNTIME);
+    source
+}
+
+/// Adds the local functions bound in the block, including the nested ones,
+/// to the list.
+pub(super) fn collect_functions<'a>(block: &'a Block, functions: &mut Vec<(VarId, &'a Function)>) {
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Let(var, Value::Lambda(function)) => {
+                functions.push((*var, function));
+                collect_functions(&function.body, functions);
+            }
+            Stmt::Let(_, value) => {
+                for block in value.blocks() {
+                    collect_functions(block, functions);
+                }
+            }
+            Stmt::LetRec(members) => {
+                for (var, function) in members {
+                    functions.push((*var, function));
+                    collect_functions(&function.body, functions);
+                }
+            }
+        }
+    }
+}
+
+fn c_type(repr: Repr) -> &'static str {
+    match repr {
+        Repr::Value => "value",
+        Repr::Int => "int64_t",
+    }
+}
+
+/// Gets the parameter types of the direct entry of a function of the
+/// arity, which takes the closure before the arguments.
+fn parameters(arity: usize) -> String {
+    vec!["value"; 1 + arity].join(", ")
+}
+
+/// Makes a C identifier of the name, e.g. `op_7c_3e` of `|>`.
+fn sanitize(name: &str) -> String {
+    let mut output = String::new();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        output.push_str("op");
+    }
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            output.push(c);
+        } else {
+            write!(output, "_{:x}", c as u32).unwrap();
+        }
+    }
+    output
+}
+
+/// Escapes the bytes for a C string literal. The bytes other than the
+/// printable ASCII characters are written in octal, so they cannot merge
+/// with the following digits.
+fn escape(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for byte in bytes {
+        match byte {
+            b'"' => output.push_str("\\\""),
+            b'\\' => output.push_str("\\\\"),
+            b'?' => output.push_str("\\?"),
+            b'\n' => output.push_str("\\n"),
+            b'\t' => output.push_str("\\t"),
+            b' '..=b'~' => output.push(*byte as char),
+            _ => write!(output, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    output
+}
+
+struct Emitter<'a> {
+    program: &'a Program,
+    resolutions: &'a Resolutions,
+    closures: Closures,
+    global_functions: HashSet<VarId>,
+    /// All the functions of the program, each compiled to a C function.
+    functions: Vec<(VarId, &'a Function)>,
+    /// The indices of the static objects of the string literals.
+    strings: HashMap<Rc<str>, usize>,
+    string_definitions: String,
+    /// The names of the tags of the constructors and the records used.
+    tags: BTreeMap<usize, String>,
+    output: String,
+    indent: usize,
+    /// The number of the labels of the current function.
+    labels: usize,
+    /// The labels of the handlers of the enclosing `try`s, the innermost
+    /// last.
+    handlers: Vec<String>,
+    /// The frame of the current function.
+    frame: Frame,
+    /// The current function, unless the globals are evaluated.
+    current: Option<(VarId, &'a Function)>,
+    /// Whether the current function tail calls itself, jumping back to its
+    /// start.
+    restarts: bool,
+}
+
+impl<'a> Emitter<'a> {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.output.push_str("    ");
+        }
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    /// Gets the name of the C variable of the Brink one, unique in the
+    /// program, e.g. `fibonacci_12`.
+    fn name(&self, var: VarId) -> String {
+        let name = &self.program.var(var).name;
+        let name = if &**name == "_" {
+            "t".to_string()
+        } else {
+            sanitize(name)
+        };
+        format!("{}_{}", name, var.as_usize())
+    }
+
+    /// Gets the C expression of the variable: its slot in the frame if it
+    /// is a value of the current function, or its C variable.
+    fn var(&self, var: VarId) -> String {
+        match self.frame.slot(var) {
+            Some(slot) => format!("roots[{}]", slot),
+            None => self.name(var),
+        }
+    }
+
+    /// Gets the target of the assignment defining the variable, which
+    /// declares its C variable unless it has a slot in the frame.
+    fn target(&self, var: VarId) -> String {
+        match self.frame.slot(var) {
+            Some(slot) => format!("roots[{}]", slot),
+            None => format!("{} {}", c_type(self.program.var(var).repr), self.name(var)),
+        }
+    }
+
+    /// Declares the C variable of the variable, unless it has a slot in the
+    /// frame, before the branches assigning it.
+    fn declare(&mut self, var: VarId) {
+        if self.frame.slot(var).is_none() {
+            let line = format!("{};", self.target(var));
+            self.line(&line);
+        }
+    }
+
+    /// Gets the statement returning the value, which pops the frame first.
+    fn return_value(&self, value: &str) -> String {
+        if self.frame.is_empty() {
+            format!("return {};", value)
+        } else {
+            format!("return LEAVE({});", value)
+        }
+    }
+
+    fn label(&mut self, prefix: &str) -> String {
+        self.labels += 1;
+        format!("{}_{}", prefix, self.labels)
+    }
+
+    fn atom(&mut self, atom: &Atom) -> String {
+        match atom {
+            Atom::Var(var) if self.global_functions.contains(var) => {
+                format!("(value)&{}_closure.header", self.name(*var))
+            }
+            Atom::Var(var) => self.var(*var),
+            Atom::Literal(Literal::Int(i64::MIN)) => "INT64_MIN".to_string(),
+            Atom::Literal(Literal::Int(n)) => n.to_string(),
+            Atom::Literal(Literal::Bool(b)) => format!("BOOL({})", *b as u8),
+            Atom::Literal(Literal::Unit) => "UNIT".to_string(),
+            Atom::Literal(Literal::String(string)) => {
+                format!("(value)&{}.header", self.string(string))
+            }
+        }
+    }
+
+    fn atoms(&mut self, atoms: &[Atom]) -> Vec<String> {
+        atoms.iter().map(|atom| self.atom(atom)).collect()
+    }
+
+    /// Gets the name of the static object of the string literal.
+    fn string(&mut self, string: &Rc<str>) -> String {
+        let next = self.strings.len();
+        let index = *self.strings.entry(Rc::clone(string)).or_insert(next);
+        if index == next {
+            writeln!(
+                self.string_definitions,
+                "static struct {{ uint64_t gc; uint64_t header; char bytes[{}]; }} string_{} = \
+                 {{BRINK_GC_STATIC, MAKE_HEADER(BRINK_KIND_STRING, 0, {}), \"{}\"}};",
+                string.len() + 1,
+                index,
+                string.len(),
+                escape(string.as_bytes())
+            )
+            .unwrap();
+        }
+        format!("string_{}", index)
+    }
+
+    /// Gets the name of the tag of the values built by the constructor.
+    fn tag(&mut self, con: Con) -> String {
+        let def = match con {
+            Con::Record(def) | Con::Constructor(def) => def,
+            Con::Exception(builtin) => {
+                return format!("BRINK_EXN_{}", screaming_snake_case(builtin.name()))
+            }
+            _ => return con_kind(con).1.to_string(),
+        };
+        let name = format!(
+            "TAG_{}_{}",
+            sanitize(&self.resolutions.def(def).name),
+            def.as_usize()
+        );
+        self.tags.insert(def.as_usize(), name.clone());
+        name
+    }
+
+    /// Gets the statement passing the raised exception to the innermost
+    /// handler, or returning it to the caller.
+    fn raise(&self) -> String {
+        match self.handlers.last() {
+            Some(handler) => format!("goto {};", handler),
+            None => self.return_value("0"),
+        }
+    }
+
+    fn check_exception(&mut self) {
+        let raise = self.raise();
+        self.line(&format!("if (brink_exn != 0) {}", raise));
+    }
+
+    fn function(&mut self, var: VarId, function: &'a Function) {
+        let name = self.name(var);
+        self.labels = 0;
+        let mut parameters = String::from("value self");
+        for parameter in &function.parameters {
+            write!(parameters, ", value {}", self.name(*parameter)).unwrap();
+        }
+        self.line(&format!("static value {}_fn({}) {{", name, parameters));
+        self.indent += 1;
+        self.line("CHECK_STACK();");
+        let captures = self.closures.captures(var).to_vec();
+        self.frame = Frame::of_function(self.program, function, &captures);
+        if !self.frame.is_empty() {
+            self.line(&format!("ENTER_FRAME({});", self.frame.len()));
+        }
+        for parameter in &function.parameters {
+            if let Some(slot) = self.frame.slot(*parameter) {
+                let line = format!("roots[{}] = {};", slot, self.name(*parameter));
+                self.line(&line);
+            }
+        }
+        // The captures are loaded before anything is allocated, since the
+        // closure is not in the frame.
+        for (i, capture) in captures.into_iter().enumerate() {
+            let field = format!("CLOSURE(self)->captures[{}]", i);
+            let line = match self.program.var(capture).repr {
+                Repr::Value => format!("{} = {};", self.target(capture), field),
+                Repr::Int => format!("{} = UNBOX({});", self.target(capture), field),
+            };
+            self.line(&line);
+        }
+        // The tail calls of the function to itself jump to the start of
+        // the body, which is labeled if there are any.
+        let start = self.output.len();
+        self.current = Some((var, function));
+        self.restarts = false;
+        self.tail_block(&function.body);
+        if self.restarts {
+            self.output.insert_str(start, "    start:;\n");
+        }
+        self.current = None;
+        self.indent -= 1;
+        self.line("}");
+        self.line("");
+
+        let arguments = (0..function.parameters.len())
+            .map(|i| format!(", arguments[{}]", i))
+            .collect::<String>();
+        self.line(&format!(
+            "static value {}_entry(value self, value *arguments) {{",
+            name
+        ));
+        self.line(&format!("    return {}_fn(self{});", name, arguments));
+        self.line("}");
+        self.line("");
+    }
+
+    /// Emits `brink_main`, which evaluates the globals other than the
+    /// functions in order.
+    fn main(&mut self) {
+        self.labels = 0;
+        self.line("value brink_main(void) {");
+        self.indent += 1;
+        let blocks = self
+            .program
+            .globals
+            .iter()
+            .filter_map(|global| match &global.value {
+                GlobalValue::Block(block) => Some(block),
+                GlobalValue::Function(_) => None,
+            });
+        self.frame = Frame::of_blocks(self.program, blocks);
+        if !self.frame.is_empty() {
+            self.line(&format!("ENTER_FRAME({});", self.frame.len()));
+        }
+        for global in &self.program.globals {
+            if let GlobalValue::Block(block) = &global.value {
+                self.line("{");
+                self.indent += 1;
+                let result = self.block(block);
+                let line = format!("{} = {};", self.name(global.var), result);
+                self.line(&line);
+                self.indent -= 1;
+                self.line("}");
+            }
+        }
+        let line = self.return_value("0");
+        self.line(&line);
+        self.indent -= 1;
+        self.line("}");
+        self.line("");
+    }
+
+    /// Emits the statements of the block, returning its result.
+    fn block(&mut self, block: &'a Block) -> String {
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+        self.atom(&block.result)
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::Let(var, value) => self.value(*var, value),
+            Stmt::LetRec(functions) => {
+                // The closures are allocated before their captured values
+                // are stored, since they capture each other.
+                for (var, function) in functions {
+                    self.alloc_closure(*var, function);
+                }
+                for (var, _) in functions {
+                    self.store_captures(*var, functions.len() > 1);
+                }
+            }
+        }
+    }
+
+    /// Emits the block returning its result from the function. The block
+    /// evaluating to the value of its last statement computes the value in
+    /// tail position.
+    fn tail_block(&mut self, block: &'a Block) {
+        if let Some((Stmt::Let(var, value), stmts)) = block.stmts.split_last() {
+            if block.result == Atom::Var(*var) {
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+                return self.tail_value(*var, value);
+            }
+        }
+        let result = self.block(block);
+        let line = self.return_value(&result);
+        self.line(&line);
+    }
+
+    /// Computes the value in tail position, returning it from the function.
+    /// The calls are tail calls, and so are the ones the branches end with.
+    fn tail_value(&mut self, var: VarId, value: &'a Value) {
+        match value {
+            Value::Call(function, arguments) | Value::Apply(Atom::Var(function), arguments)
+                if self.calls_itself(*function, arguments) =>
+            {
+                self.restart(arguments)
+            }
+            Value::Call(function, arguments) => {
+                let function = self.atom(&Atom::Var(*function));
+                self.tail_call(function, arguments);
+            }
+            Value::Apply(function, arguments) => {
+                let function = self.atom(function);
+                self.tail_call(function, arguments);
+            }
+            Value::If(condition, then_block, else_block) => {
+                let condition = self.atom(condition);
+                self.line(&format!("if (IS_TRUE({})) {{", condition));
+                self.indent += 1;
+                self.tail_block(then_block);
+                self.indent -= 1;
+                self.line("} else {");
+                self.indent += 1;
+                self.tail_block(else_block);
+                self.indent -= 1;
+                self.line("}");
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.switch(var, scrutinee, cases, default, true)
+            }
+            _ => {
+                self.value(var, value);
+                let result = self.var(var);
+                let line = self.return_value(&result);
+                self.line(&line);
+            }
+        }
+    }
+
+    /// Checks whether the call of the function to the arguments is a call
+    /// of the current function to all of its parameters.
+    fn calls_itself(&self, function: VarId, arguments: &[Atom]) -> bool {
+        match self.current {
+            Some((current, current_function)) => {
+                current == function && current_function.parameters.len() == arguments.len()
+            }
+            None => false,
+        }
+    }
+
+    /// Tail calls the current function, assigning the arguments to its
+    /// parameters and jumping back to its start. The closure is the same,
+    /// so are its captured values.
+    fn restart(&mut self, arguments: &[Atom]) {
+        let (_, function) = self.current.unwrap();
+        let arguments = self.atoms(arguments);
+        self.restarts = true;
+        // The arguments are evaluated before any parameter is assigned,
+        // since they may read the parameters.
+        self.line("{");
+        for (i, argument) in arguments.iter().enumerate() {
+            self.line(&format!("    value argument_{} = {};", i, argument));
+        }
+        for (i, parameter) in function.parameters.iter().enumerate() {
+            let line = format!("    {} = argument_{};", self.var(*parameter), i);
+            self.line(&line);
+        }
+        self.line("    goto start;");
+        self.line("}");
+    }
+
+    /// Returns the tail call of the function to the arguments to the
+    /// trampoline of the caller, after popping the frame.
+    fn tail_call(&mut self, function: String, arguments: &[Atom]) {
+        let arguments = self.atoms(arguments);
+        let call = format!(
+            "brink_tail_call({}, {}, (value[]){{{}}})",
+            function,
+            arguments.len(),
+            arguments.join(", ")
+        );
+        let line = self.return_value(&call);
+        self.line(&line);
+    }
+
+    /// Emits the block assigning its result to the variable.
+    fn join_block(&mut self, block: &'a Block, var: &str) {
+        self.indent += 1;
+        let result = self.block(block);
+        self.line(&format!("{} = {};", var, result));
+        self.indent -= 1;
+    }
+
+    fn alloc_closure(&mut self, var: VarId, function: &Function) {
+        let name = self.name(var);
+        let closure = self.var(var);
+        let captures = self.closures.captures(var).len();
+        self.line(&format!(
+            "{} = brink_alloc(MAKE_HEADER(BRINK_KIND_CLOSURE, {}, CLOSURE_CAPTURES + {}));",
+            self.target(var),
+            function.parameters.len(),
+            captures
+        ));
+        self.line(&format!("CLOSURE({})->entry = {}_entry;", closure, name));
+        self.line(&format!(
+            "CLOSURE({})->direct = (void (*)(void)){}_fn;",
+            closure, name
+        ));
+    }
+
+    /// Stores the captured values in the closure. The unboxed integers are
+    /// boxed, so all the fields are values. The closure may have been moved
+    /// out of the nursery by the allocations since its own, the other
+    /// closures of a `let rec` or the boxes, in which case the stores go
+    /// through the write barrier.
+    fn store_captures(&mut self, var: VarId, mut moved: bool) {
+        let closure = self.var(var);
+        for (i, capture) in self.closures.captures(var).to_vec().into_iter().enumerate() {
+            let line = match self.program.var(capture).repr {
+                Repr::Value if !moved => {
+                    format!(
+                        "CLOSURE({})->captures[{}] = {};",
+                        closure,
+                        i,
+                        self.var(capture)
+                    )
+                }
+                Repr::Value => format!(
+                    "brink_write({}, CLOSURE_CAPTURES + {}, {});",
+                    closure,
+                    i,
+                    self.var(capture)
+                ),
+                Repr::Int => {
+                    moved = true;
+                    format!(
+                        "{{ value boxed = brink_box({}); brink_write({}, CLOSURE_CAPTURES + {}, boxed); }}",
+                        self.var(capture),
+                        closure,
+                        i
+                    )
+                }
+            };
+            self.line(&line);
+        }
+    }
+
+    fn value(&mut self, var: VarId, value: &'a Value) {
+        let name = self.var(var);
+        let target = self.target(var);
+        let define = |emitter: &mut Self, expression: String| {
+            emitter.line(&format!("{} = {};", target, expression));
+        };
+        match value {
+            Value::Atom(atom) => {
+                let atom = self.atom(atom);
+                define(self, atom);
+            }
+            Value::Lambda(function) => {
+                self.alloc_closure(var, function);
+                self.store_captures(var, false);
+            }
+            Value::Apply(function, arguments) => {
+                let function = self.atom(function);
+                let arguments = self.atoms(arguments);
+                let count = arguments.len();
+                let direct = format!(
+                    "((value (*)({}))CLOSURE({})->direct)",
+                    parameters(count),
+                    function
+                );
+                define(
+                    self,
+                    format!(
+                        "brink_result(IS_CLOSURE_OF({f}, {n})\n{indent}    ? {direct}({f}, {args})\n{indent}    : brink_apply({f}, {n}, (value[]){{{args}}}))",
+                        f = function,
+                        n = count,
+                        direct = direct,
+                        args = arguments.join(", "),
+                        indent = "    ".repeat(self.indent)
+                    ),
+                );
+                self.check_exception();
+            }
+            Value::Call(function, arguments) => {
+                let mut values = vec![self.atom(&Atom::Var(*function))];
+                values.extend(self.atoms(arguments));
+                let call = format!(
+                    "brink_result({}_fn({}))",
+                    self.name(*function),
+                    values.join(", ")
+                );
+                define(self, call);
+                self.check_exception();
+            }
+            Value::Prim(op, arguments) => self.prim(&target, *op, arguments),
+            Value::Box(atom) => {
+                let atom = self.atom(atom);
+                define(self, format!("brink_box({})", atom));
+            }
+            Value::Unbox(atom) => {
+                let atom = self.atom(atom);
+                define(self, format!("UNBOX({})", atom));
+            }
+            Value::Construct(con, fields) => {
+                let fields = self.atoms(fields);
+                let (kind, _) = con_kind(*con);
+                let tag = self.tag(*con);
+                define(
+                    self,
+                    format!(
+                        "brink_alloc(MAKE_HEADER(BRINK_KIND_{}, {}, {}))",
+                        kind.name(),
+                        tag,
+                        fields.len()
+                    ),
+                );
+                for (i, field) in fields.into_iter().enumerate() {
+                    self.line(&format!("FIELD({}, {}) = {};", name, i, field));
+                }
+            }
+            Value::Field(atom, _, index) => {
+                let atom = self.atom(atom);
+                define(self, format!("FIELD({}, {})", atom, index));
+            }
+            Value::If(condition, then_block, else_block) => {
+                let condition = self.atom(condition);
+                self.declare(var);
+                self.line(&format!("if (IS_TRUE({})) {{", condition));
+                self.join_block(then_block, &name);
+                self.line("} else {");
+                self.join_block(else_block, &name);
+                self.line("}");
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.switch(var, scrutinee, cases, default, false)
+            }
+            Value::While(condition, body) => {
+                self.line("for (;;) {");
+                self.indent += 1;
+                let condition = self.block(condition);
+                self.line(&format!("if (!IS_TRUE({})) break;", condition));
+                self.block(body);
+                self.indent -= 1;
+                self.line("}");
+                define(self, "UNIT".to_string());
+            }
+            Value::For(counter, start, direction, end, body) => {
+                let start = self.atom(start);
+                let end = self.atom(end);
+                let counter = self.name(*counter);
+                let (comparison, step) = match direction {
+                    ForDirection::Up => ("<=", "++"),
+                    ForDirection::Down => (">=", "--"),
+                };
+                self.line(&format!("if ({} {} {}) {{", start, comparison, end));
+                self.indent += 1;
+                self.line(&format!("int64_t {} = {};", counter, start));
+                self.line("for (;;) {");
+                self.indent += 1;
+                self.block(body);
+                // The counter is compared with the end before it is
+                // stepped, so it does not overflow past it.
+                self.line(&format!("if ({} == {}) break;", counter, end));
+                self.line(&format!("{}{};", counter, step));
+                self.indent -= 1;
+                self.line("}");
+                self.indent -= 1;
+                self.line("}");
+                define(self, "UNIT".to_string());
+            }
+            Value::Try(body, exception, handler) => {
+                let handler_label = self.label("handler");
+                let end_label = self.label("try_end");
+                self.declare(var);
+                self.line("{");
+                self.handlers.push(handler_label.clone());
+                self.join_block(body, &name);
+                self.handlers.pop();
+                self.line(&format!("    goto {};", end_label));
+                self.line("}");
+                self.line(&format!("{}:;", handler_label));
+                self.line("{");
+                let exception = self.target(*exception);
+                self.line(&format!("    {} = brink_exn;", exception));
+                self.line("    brink_exn = 0;");
+                self.join_block(handler, &name);
+                self.line("}");
+                self.line(&format!("{}:;", end_label));
+            }
+        }
+    }
+
+    fn prim(&mut self, target: &str, op: PrimOp, arguments: &[Atom]) {
+        let int_operands = self.program.atom_repr(&arguments[0]) == Repr::Int;
+        let values = self.atoms(arguments);
+        let expression = match op {
+            PrimOp::Add => format!("INT_ADD({}, {})", values[0], values[1]),
+            PrimOp::Subtract => format!("INT_SUB({}, {})", values[0], values[1]),
+            PrimOp::Multiply => format!("INT_MUL({}, {})", values[0], values[1]),
+            PrimOp::Negate => format!("INT_NEG({})", values[0]),
+            PrimOp::Divide => {
+                let raise = self.raise();
+                match arguments[1] {
+                    Atom::Literal(Literal::Int(0)) => {
+                        self.line("brink_division_by_zero();");
+                        self.line(&raise);
+                        "0".to_string()
+                    }
+                    Atom::Literal(_) => format!("INT_DIV({}, {})", values[0], values[1]),
+                    Atom::Var(_) => {
+                        self.line(&format!(
+                            "if ({} == 0) {{ brink_division_by_zero(); {} }}",
+                            values[1], raise
+                        ));
+                        format!("INT_DIV({}, {})", values[0], values[1])
+                    }
+                }
+            }
+            PrimOp::Less | PrimOp::LessEqual | PrimOp::Greater | PrimOp::GreaterEqual => {
+                let operator = match op {
+                    PrimOp::Less => "<",
+                    PrimOp::LessEqual => "<=",
+                    PrimOp::Greater => ">",
+                    _ => ">=",
+                };
+                format!("BOOL({} {} {})", values[0], operator, values[1])
+            }
+            PrimOp::Equal if int_operands => format!("BOOL({} == {})", values[0], values[1]),
+            PrimOp::NotEqual if int_operands => format!("BOOL({} != {})", values[0], values[1]),
+            PrimOp::Equal => format!("BOOL(brink_equal({}, {}))", values[0], values[1]),
+            PrimOp::NotEqual => format!("BOOL(!brink_equal({}, {}))", values[0], values[1]),
+            PrimOp::Deref => format!("FIELD({}, 0)", values[0]),
+            PrimOp::Assign => format!("brink_write({}, 0, {})", values[0], values[1]),
+            PrimOp::Builtin(Builtin::Raise) => {
+                let raise = self.raise();
+                self.line(&format!("brink_exn = {};", values[0]));
+                self.line(&raise);
+                "0".to_string()
+            }
+            PrimOp::Builtin(builtin) => {
+                let call = format!("{}({})", builtin_symbol(builtin), values.join(", "));
+                self.line(&format!("{} = {};", target, call));
+                if builtin_may_raise(builtin) {
+                    self.check_exception();
+                }
+                return;
+            }
+        };
+        self.line(&format!("{} = {};", target, expression));
+    }
+
+    /// Emits the switch assigning the result of the case taken to the
+    /// variable, or returning it from the function in tail position.
+    fn switch(
+        &mut self,
+        var: VarId,
+        scrutinee: &Atom,
+        cases: &'a [(Test, Block)],
+        default: &'a Option<Block>,
+        tail: bool,
+    ) {
+        let repr = self.program.atom_repr(scrutinee);
+        let value = self.atom(scrutinee);
+        // The cases with their conditions, up to the first one which
+        // always matches.
+        let mut arms = Vec::new();
+        for (test, block) in cases {
+            let condition = match test {
+                Test::Con(Con::Tuple(_)) | Test::Con(Con::Record(_)) => None,
+                Test::Con(Con::Array(n)) => Some(format!("SIZE({}) == {}", value, n)),
+                Test::Con(con @ Con::Nil) | Test::Con(con @ Con::Cons) => Some(format!(
+                    "KIND({}) == BRINK_KIND_{}",
+                    value,
+                    con_kind(*con).0.name()
+                )),
+                Test::Con(con) => Some(format!("TAG({}) == {}", value, self.tag(*con))),
+                Test::Literal(Literal::Int(n)) => Some(match repr {
+                    Repr::Int => format!("{} == {}", value, n),
+                    Repr::Value => format!("UNBOX({}) == {}", value, n),
+                }),
+                Test::Literal(Literal::Bool(true)) => Some(format!("IS_TRUE({})", value)),
+                Test::Literal(Literal::Bool(false)) => Some(format!("!IS_TRUE({})", value)),
+                Test::Literal(literal @ Literal::String(_)) => {
+                    let string = self.atom(&Atom::Literal(literal.clone()));
+                    Some(format!("brink_equal({}, {})", value, string))
+                }
+                Test::Literal(Literal::Unit) => None,
+            };
+            let always = condition.is_none();
+            arms.push((condition, block));
+            if always {
+                break;
+            }
+        }
+        if !matches!(arms.last(), Some((None, _))) {
+            match default {
+                Some(block) => arms.push((None, block)),
+                // The last case matches the values the others do not.
+                None => {
+                    if let Some(last) = arms.last_mut() {
+                        last.0 = None;
+                    }
+                }
+            }
+        }
+
+        if arms.is_empty() {
+            // A switch without cases on a value which cannot exist.
+            let line = match tail {
+                true => self.return_value("0"),
+                false => format!("{} = 0;", self.target(var)),
+            };
+            self.line(&line);
+            return;
+        }
+        if !tail {
+            self.declare(var);
+        }
+        let name = self.var(var);
+        for (i, (condition, block)) in arms.into_iter().enumerate() {
+            let line = match (i, condition) {
+                (0, Some(condition)) => format!("if ({}) {{", condition),
+                (0, None) => "{".to_string(),
+                (_, Some(condition)) => format!("}} else if ({}) {{", condition),
+                (_, None) => "} else {".to_string(),
+            };
+            self.line(&line);
+            if tail {
+                self.indent += 1;
+                self.tail_block(block);
+                self.indent -= 1;
+            } else {
+                self.join_block(block, &name);
+            }
+        }
+        self.line("}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        process::Command,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::test_util;
+
+    /// The number of the programs compiled, which names their directories.
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Emits the C code of the program at the optimization level, builds it
+    /// with the C compiler and runs it, returning its standard output and
+    /// the exception it did not handle. The exception is checked against
+    /// the one the interpreter reports.
+    fn build_and_run(source: &str, opt_level: u8) -> (String, Option<String>) {
+        let (stdout, exception, _) = build_and_run_with_env(source, opt_level, &[]);
+        (stdout, exception)
+    }
+
+    /// Builds and runs the program like `build_and_run`, in the environment
+    /// with the variables, also returning its standard error.
+    fn build_and_run_with_env(
+        source: &str,
+        opt_level: u8,
+        env: &[(&str, &str)],
+    ) -> (String, Option<String>, String) {
+        build_and_execute(source, opt_level, env, true)
+    }
+
+    /// Builds and runs the program, checking the exception it did not
+    /// handle against the interpreter if `compare` is set.
+    fn build_and_execute(
+        source: &str,
+        opt_level: u8,
+        env: &[(&str, &str)],
+        compare: bool,
+    ) -> (String, Option<String>, String) {
+        let checked = test_util::check(source);
+        let code = emit_c(&checked.optimize(opt_level), &checked.resolutions);
+
+        let directory = std::env::temp_dir().join(format!(
+            "brink-c-test-{}-{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("main.c");
+        std::fs::write(&path, code).unwrap();
+        let executable = directory.join("main");
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let status = Command::new(compiler)
+            .arg("-o")
+            .arg(&executable)
+            .arg(&path)
+            .arg("-lpthread")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(&executable)
+            .envs(env.iter().copied())
+            .output()
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let exception = stderr
+            .lines()
+            .find_map(|line| line.strip_prefix("uncaught exception "))
+            .map(str::to_string);
+        if compare {
+            assert_eq!(checked.uncaught_exception(), exception);
+        }
+        (String::from_utf8(output.stdout).unwrap(), exception, stderr)
+    }
+
+    fn run_output(source: &str) -> String {
+        let outputs = [0, 2]
+            .iter()
+            .map(|level| build_and_run(source, *level))
+            .collect::<Vec<_>>();
+        assert_eq!(outputs[0], outputs[1]);
+        let (stdout, exception) = outputs[0].clone();
+        assert_eq!(None, exception);
+        stdout
+    }
+
+    #[test]
+    fn escapes_names_and_strings() {
+        assert_eq!("op_7c_3e", sanitize("|>"));
+        assert_eq!("x_27", sanitize("x'"));
+        assert_eq!(
+            "a\\\"\\?\\n\\001\\303\\251",
+            escape("a\"?\n\u{1}é".as_bytes())
+        );
+    }
+
+    #[test]
+    fn runs_functions_and_closures() {
+        let source = "let rec fibonacci n = if n < 2 then n else fibonacci (n - 1) + fibonacci (n - 2)\n\
+                      infixl 1 |>\nlet (|>) x f = f x\n\
+                      let add x y = x + y\nlet inc = add 1\n\
+                      let make n =\n  let rec count k = if k = 0 then [] else (k + n) :: count (k - 1)\n  count\n\
+                      let twice f x = f (f x)\n\
+                      let a = print_string (string_of_int (fibonacci 20))\n\
+                      let b = print_string (sprintf \" %d %d %d\" (inc 2) (twice inc 5) (2 |> add 10))\n\
+                      let c = print_string (sprintf \" %b\\n\" (make 10 3 = [13; 12; 11]))\n";
+        assert_eq!("6765 3 7 12 true\n", run_output(source));
+    }
+
+    #[test]
+    fn runs_loops_matches_and_exceptions() {
+        let source = "type Shape = Circle int | Square int int | Empty\n\
+                      let area s =\n  match s\n  | Circle r -> 3 * r * r\n  | Square w h -> w * h\n  | Empty -> 0\n\
+                      let name s =\n  match s\n  | \"a\" -> 1\n  | _ -> 2\n\
+                      let total =\n  let sum = ref 0\n  for i = 1 to 4 do\n    sum := !sum + i\n  for i = 2 downto 1 do\n    sum := !sum * i\n  let n = ref 3\n  while !n > 0 do\n    n := !n - 1\n  !sum + !n\n\
+                      exception Invalid int\n\
+                      let r = try 1 / 0 with | DivisionByZero -> -1\n\
+                      let s = try raise (Invalid 4) with | Invalid n -> n\n\
+                      let rec deep n = 1 + deep (n + 1)\n\
+                      let o = try deep 0 with | StackOverflow -> 0\n\
+                      let p = print_string (sprintf \"%d %d %d %d %d %d\" (area (Circle 2) + area (Square 2 3) + area Empty) (name \"a\" + name \"b\") total r s o)\n";
+        assert_eq!("18 3 20 -1 4 0", run_output(source));
+    }
+
+    #[test]
+    fn reports_uncaught_exceptions_like_the_interpreter() {
+        let sources = [
+            "exception Invalid int\nlet check x = if x < 0 then raise (Invalid x) else x\nlet y = check (0 - 2)\n",
+            "type P = { x: int; y: string }\nexception Bad P\nlet y = raise (Bad { x = 1; y = \"a\" })\n",
+            "let f x =\n  match x\n  | 0 -> 1\nlet y = f 1\n",
+            "let y = int_of_string \"1x\"\n",
+            "let y = compare (fun x -> x) (fun x -> x)\n",
+        ];
+        for source in sources.iter() {
+            for level in [0, 2].iter() {
+                assert!(build_and_run(source, *level).1.is_some());
+            }
+        }
+    }
+
+    /// Runs tail calls far deeper than the stack holds frames, without the
+    /// optimizations of the C compiler: the calls of the functions to
+    /// themselves, to other functions and to unknown closures. The
+    /// interpreter would take long to run them.
+    #[test]
+    fn runs_tail_calls_in_constant_space() {
+        let source = "let rec loop n acc = if n = 0 then acc else loop (n - 1) (acc + 1)\n\
+                      let rec count n = match n\n  | 0 -> 0\n  | n -> count (n - 1)\n\
+                      let apply f x = f x\n\
+                      let rec bounce n = if n = 0 then 0 else apply bounce (n - 1)\n\
+                      let rec even n =\n  let odd m = if m = 0 then false else even (m - 1)\n  n = 0 || odd (n - 1)\n\
+                      let p = print_string (sprintf \"%d %d %d %b\" (loop 10000000 0) (count 10000000) (bounce 10000000) (even 10000001))\n";
+        for level in [0, 2].iter() {
+            let (stdout, exception, _) = build_and_execute(source, *level, &[], false);
+            assert_eq!(("10000000 0 0 false", None), (&*stdout, exception));
+        }
+    }
+
+    #[test]
+    fn runs_mutually_recursive_groups_in_constant_space() {
+        let source = test_util::printing(&test_util::mutually_recursive_groups(10000000), "r");
+        for level in [0, 2].iter() {
+            let (stdout, exception, _) = build_and_execute(&source, *level, &[], false);
+            assert_eq!(("true true 10000000", None), (&*stdout, exception));
+        }
+    }
+
+    /// Runs the tests of the standard library modules compiled to C,
+    /// printing the `result` they bind.
+    #[test]
+    fn runs_stdlib_tests() {
+        let (io, path) = test_util::io_test("brink_c_io_test.txt");
+        for (name, source) in test_util::STDLIB_TESTS
+            .iter()
+            .copied()
+            .chain(Some(("io", &*io)))
+        {
+            let source = test_util::printing(source, "result");
+            assert_eq!("ok", run_output(&source), "stdlib test {}", name);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Runs a program allocating much more than the nursery holds, keeping
+    /// some of the lists alive across the collections and storing young
+    /// lists in old references, and checks the statistics of the collector.
+    #[test]
+    fn collects_garbage() {
+        let source = "let rec build n acc = if n = 0 then acc else build (n - 1) ((n, string_of_int n) :: acc)\n\
+                      let rec total xs acc =\n  match xs\n  | [] -> acc\n  | (n, s) :: rest -> total rest (acc + n + string_length s)\n\
+                      let shift n =\n  let h = n * 1000000000000000000\n  fun x -> x + h\n\
+                      let latest = ref []\n\
+                      let kept = ref []\n\
+                      let sum =\n  let sum = ref 0\n  for i = 1 to 60 do\n    latest := build 5000 []\n    kept := !latest :: !kept\n    if i / 10 * 10 = i then kept := []\n    sum := !sum + total !latest 0 + (shift 5 i - 5000000000000000000)\n  !sum\n\
+                      let p = print_string (string_of_int sum)\n";
+        for level in [0, 2].iter() {
+            let (stdout, exception, stderr) =
+                build_and_run_with_env(source, *level, &[("BRINK_GC_STATS", "1")]);
+            assert_eq!(("751285410", None), (&*stdout, exception));
+            let lines = stderr.lines().collect::<Vec<_>>();
+            assert_eq!(2, lines.len(), "{}", stderr);
+            assert!(lines[0].starts_with("gc: ") && !lines[0].contains(" 0 major"));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{
+    anf::{Block, Function, GlobalValue, Program, Stmt, Value},
+    ir::VarId,
+};
+
+/// The variables captured by the local functions, which are stored in
+/// their closures. The global functions capture nothing, since the globals
+/// are not stored in the closures.
+#[derive(Debug, Default)]
+pub struct Closures {
+    /// Keyed by the variables the functions are bound to.
+    captures: HashMap<VarId, Vec<VarId>>,
+}
+
+impl Closures {
+    pub fn new(program: &Program) -> Closures {
+        let mut finder = Finder {
+            globals: program.globals.iter().map(|global| global.var).collect(),
+            closures: Closures::default(),
+        };
+        for global in &program.globals {
+            match &global.value {
+                GlobalValue::Function(function) => finder.block(&function.body),
+                GlobalValue::Block(block) => finder.block(block),
+            }
+        }
+        finder.closures
+    }
+
+    /// Gets the variables captured by the local function bound to the
+    /// variable, in the order of their fields.
+    pub fn captures(&self, function: VarId) -> &[VarId] {
+        self.captures.get(&function).map_or(&[], Vec::as_slice)
+    }
+}
+
+struct Finder {
+    globals: HashSet<VarId>,
+    closures: Closures,
+}
+
+impl Finder {
+    fn block(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(var, Value::Lambda(function)) => self.function(*var, function),
+                Stmt::Let(_, value) => {
+                    for block in value.blocks() {
+                        self.block(block);
+                    }
+                }
+                Stmt::LetRec(functions) => {
+                    for (var, function) in functions {
+                        self.function(*var, function);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds the variables used in the function and bound outside of it,
+    /// which are the ones bound in the enclosing functions since every
+    /// variable is bound once.
+    fn function(&mut self, var: VarId, function: &Function) {
+        let mut bound = function.parameters.iter().copied().collect::<HashSet<_>>();
+        bind_all(&function.body, &mut bound);
+        let mut captures = BTreeSet::new();
+        function.body.for_each_use(&mut |used| {
+            if !bound.contains(&used) && !self.globals.contains(&used) {
+                captures.insert(used);
+            }
+        });
+        self.closures
+            .captures
+            .insert(var, captures.into_iter().collect());
+        self.block(&function.body);
+    }
+}
+
+/// Adds the variables bound in the block, including the nested blocks and
+/// functions, to the set.
+fn bind_all(block: &Block, bound: &mut HashSet<VarId>) {
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Let(var, value) => {
+                bound.insert(*var);
+                match value {
+                    Value::Lambda(function) => bound.extend(function.parameters.iter().copied()),
+                    Value::For(counter, ..) => {
+                        bound.insert(*counter);
+                    }
+                    Value::Try(_, exception, _) => {
+                        bound.insert(*exception);
+                    }
+                    _ => {}
+                }
+                for block in value.blocks() {
+                    bind_all(block, bound);
+                }
+            }
+            Stmt::LetRec(functions) => {
+                for (var, function) in functions {
+                    bound.insert(*var);
+                    bound.extend(function.parameters.iter().copied());
+                    bind_all(&function.body, bound);
+                }
+            }
+        }
+    }
+}
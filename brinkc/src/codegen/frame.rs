@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use crate::{
+    anf::{Block, Function, Program, Repr, Stmt, Value},
+    ir::VarId,
+};
+
+/// The frame of a compiled function on the shadow stack, whose slots hold
+/// the variables of the function which are values, so the garbage
+/// collector finds them and updates them when it moves their objects. The
+/// integers are not in the frame, and neither are the variables of the
+/// nested functions, which have frames of their own.
+#[derive(Debug, Default)]
+pub struct Frame {
+    slots: HashMap<VarId, usize>,
+}
+
+impl Frame {
+    /// Gets the frame of the function, holding its parameters, the values
+    /// it captures and the variables bound in its body.
+    pub fn of_function(program: &Program, function: &Function, captures: &[VarId]) -> Frame {
+        let mut frame = Frame::default();
+        for var in function.parameters.iter().chain(captures) {
+            frame.add(program, *var);
+        }
+        frame.block(program, &function.body);
+        frame
+    }
+
+    /// Gets the frame of `brink_main`, holding the variables bound in the
+    /// globals other than the functions.
+    pub fn of_blocks<'a>(program: &Program, blocks: impl IntoIterator<Item = &'a Block>) -> Frame {
+        let mut frame = Frame::default();
+        for block in blocks {
+            frame.block(program, block);
+        }
+        frame
+    }
+
+    fn add(&mut self, program: &Program, var: VarId) {
+        if program.var(var).repr == Repr::Value && !self.slots.contains_key(&var) {
+            let slot = self.slots.len();
+            self.slots.insert(var, slot);
+        }
+    }
+
+    fn block(&mut self, program: &Program, block: &Block) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(var, value) => {
+                    self.add(program, *var);
+                    if let Value::Try(_, exception, _) = value {
+                        self.add(program, *exception);
+                    }
+                    if !matches!(value, Value::Lambda(_)) {
+                        for block in value.blocks() {
+                            self.block(program, block);
+                        }
+                    }
+                }
+                Stmt::LetRec(functions) => {
+                    for (var, _) in functions {
+                        self.add(program, *var);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gets the slot of the variable, if it is a value of the function.
+    pub fn slot(&self, var: VarId) -> Option<usize> {
+        self.slots.get(&var).copied()
+    }
+
+    /// Gets the number of the slots.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
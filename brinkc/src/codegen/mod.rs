@@ -0,0 +1,281 @@
+//! The code generation of the mid-level IR: the layout of the values the
+//! compiled code shares with the runtime, the closure conversion, the
+//! frames of the shadow stack, the C backend of `--emit=c`, the WebAssembly
+//! backend of `--target=wasm32` and the linking of the executables. The
+//! native backend built on Cranelift is enabled by the `cranelift` cargo
+//! feature.
+
+use std::fmt::Write;
+#[cfg(feature = "cranelift")]
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    ir::Con,
+    resolve::{Builtin, DefKind, Resolutions},
+};
+
+mod c;
+mod closure;
+mod frame;
+#[cfg(feature = "cranelift")]
+mod native;
+mod wasm;
+
+pub use c::emit_c;
+pub use closure::Closures;
+pub use frame::Frame;
+#[cfg(feature = "cranelift")]
+pub use native::compile;
+pub use wasm::emit_wasm;
+
+/// The interface of the runtime, included before the compiled C code and
+/// the runtime itself.
+const RUNTIME_HEADER: &str = include_str!("../../runtime/brink.h");
+/// The source of the runtime, without the header and the constants defined
+/// by `runtime_constants`.
+const RUNTIME: &str = include_str!("../../runtime/runtime.c");
+
+/// The kinds of the objects, stored in the lowest byte of their headers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// A boxed integer, one needing all 64 bits. The others are tagged.
+    Int,
+    /// The bytes of a string, with the length as the size.
+    String,
+    Unit,
+    /// A boolean, with the value as the tag.
+    Bool,
+    Tuple,
+    /// A record, with the record type as the tag.
+    Record,
+    /// A value of a variant type or an exception, with the constructor as
+    /// the tag.
+    Variant,
+    Nil,
+    Cons,
+    Array,
+    Ref,
+    /// A function, with the arity as the tag. The fields are the entry
+    /// taking the arguments in an array, the entry taking them as
+    /// parameters, and the captured values.
+    Closure,
+    /// A partial application, with the number of the missing arguments as
+    /// the tag. The fields are the closure and the given arguments.
+    Partial,
+}
+
+const KINDS: &[Kind] = &[
+    Kind::Int,
+    Kind::String,
+    Kind::Unit,
+    Kind::Bool,
+    Kind::Tuple,
+    Kind::Record,
+    Kind::Variant,
+    Kind::Nil,
+    Kind::Cons,
+    Kind::Array,
+    Kind::Ref,
+    Kind::Closure,
+    Kind::Partial,
+];
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Int => "INT",
+            Kind::String => "STRING",
+            Kind::Unit => "UNIT",
+            Kind::Bool => "BOOL",
+            Kind::Tuple => "TUPLE",
+            Kind::Record => "RECORD",
+            Kind::Variant => "VARIANT",
+            Kind::Nil => "NIL",
+            Kind::Cons => "CONS",
+            Kind::Array => "ARRAY",
+            Kind::Ref => "REF",
+            Kind::Closure => "CLOSURE",
+            Kind::Partial => "PARTIAL",
+        }
+    }
+}
+
+/// The tag of the first built-in exception. The tags of the constructors
+/// and the exceptions of the program are their definitions, which are
+/// ordered before the built-in exceptions like in the interpreter.
+pub const BUILTIN_TAG: u32 = 1 << 23;
+
+/// The index of the first captured value among the fields of a closure.
+pub const CLOSURE_CAPTURES: usize = 2;
+
+/// The flags of the garbage collector preceding the static objects, which
+/// are never collected. The other objects outside the nursery are preceded
+/// by their flags too.
+pub const GC_STATIC: u64 = 4;
+
+/// Builds the header of an object.
+pub fn header(kind: Kind, tag: u32, size: usize) -> u64 {
+    kind as u64 | (u64::from(tag) << 8) | ((size as u64) << 32)
+}
+
+/// Gets the kind and the tag of the values built by the constructor.
+pub fn con_kind(con: Con) -> (Kind, u32) {
+    match con {
+        Con::Tuple(_) => (Kind::Tuple, 0),
+        Con::Record(def) => (Kind::Record, def.as_usize() as u32),
+        Con::Constructor(def) => (Kind::Variant, def.as_usize() as u32),
+        Con::Exception(builtin) => (Kind::Variant, BUILTIN_TAG + builtin as u32),
+        Con::Nil => (Kind::Nil, 0),
+        Con::Cons => (Kind::Cons, 0),
+        Con::Array(_) => (Kind::Array, 0),
+    }
+}
+
+/// Gets the symbol of the runtime function implementing the builtin.
+pub fn builtin_symbol(builtin: Builtin) -> String {
+    format!("brink_{}", builtin.name())
+}
+
+/// Checks whether the runtime function of the builtin may raise an
+/// exception.
+pub fn builtin_may_raise(builtin: Builtin) -> bool {
+    matches!(
+        builtin,
+        Builtin::Raise
+            | Builtin::Compare
+            | Builtin::StringGet
+            | Builtin::StringSub
+            | Builtin::StringOfCharCode
+            | Builtin::IntOfString
+            | Builtin::ReadLine
+            | Builtin::ReadFile
+            | Builtin::WriteFile
+    )
+}
+
+/// Gets the names of the tags of the program, which are shown in the
+/// values: the name of every definition in order, or the names of the
+/// fields of a record type, separated by spaces.
+pub fn tag_names(resolutions: &Resolutions) -> Vec<String> {
+    let mut fields = vec![Vec::new(); resolutions.defs.len()];
+    for def in &resolutions.defs {
+        if let DefKind::Field(owner) = def.kind {
+            fields[owner.as_usize()].push(&*def.name);
+        }
+    }
+    resolutions
+        .defs
+        .iter()
+        .zip(fields)
+        .map(|(def, fields)| {
+            if fields.is_empty() {
+                def.name.to_string()
+            } else {
+                fields.join(" ")
+            }
+        })
+        .collect()
+}
+
+/// Builds the names of the tags of the program for the runtime, which
+/// reports the uncaught exceptions. The names are terminated by zero bytes.
+pub fn def_names(resolutions: &Resolutions) -> Vec<u8> {
+    let mut names = Vec::new();
+    for name in tag_names(resolutions) {
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    names
+}
+
+/// Gets the source of the runtime with its header and the constants it
+/// shares with the compiler.
+#[cfg(feature = "cranelift")]
+pub fn runtime_source() -> String {
+    let mut source = runtime_constants();
+    source.push_str(RUNTIME_HEADER);
+    source.push('\n');
+    source.push_str(RUNTIME);
+    source
+}
+
+/// Defines the constants the runtime shares with the compiler: the kinds of
+/// the objects, the tags of the built-in exceptions and their names.
+fn runtime_constants() -> String {
+    const EXCEPTIONS: &[Builtin] = &[
+        Builtin::DivisionByZero,
+        Builtin::MatchFailure,
+        Builtin::StackOverflow,
+        Builtin::Failure,
+        Builtin::InvalidArgument,
+        Builtin::NotFound,
+        Builtin::EndOfFile,
+    ];
+    let mut source = String::new();
+    for kind in KINDS {
+        writeln!(source, "#define BRINK_KIND_{} {}", kind.name(), *kind as u8).unwrap();
+    }
+    writeln!(source, "#define BRINK_BUILTIN_TAG {}", BUILTIN_TAG).unwrap();
+    writeln!(source, "#define BRINK_GC_STATIC {}", GC_STATIC).unwrap();
+    for exception in EXCEPTIONS {
+        let (_, tag) = con_kind(Con::Exception(*exception));
+        writeln!(
+            source,
+            "#define BRINK_EXN_{} {}",
+            screaming_snake_case(exception.name()),
+            tag
+        )
+        .unwrap();
+    }
+    source.push_str("static const char *const brink_builtin_names[] = {\n");
+    for exception in EXCEPTIONS {
+        writeln!(
+            source,
+            "    [{}] = \"{}\",",
+            *exception as u32,
+            exception.name()
+        )
+        .unwrap();
+    }
+    source.push_str("};\n\n");
+    source
+}
+
+fn screaming_snake_case(name: &str) -> String {
+    let mut output = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            output.push('_');
+        }
+        output.push(c.to_ascii_uppercase());
+    }
+    output
+}
+
+/// Compiles the runtime and links it with the object files into the
+/// executable, with the C compiler of the `CC` environment variable or
+/// `cc`, which drives the system linker.
+#[cfg(feature = "cranelift")]
+pub fn link(objects: &[PathBuf], output: &Path) -> Result<(), String> {
+    let directory = objects[0].parent().unwrap_or_else(|| Path::new("."));
+    let runtime = directory.join("brink_runtime.c");
+    std::fs::write(&runtime, runtime_source())
+        .map_err(|e| format!("could not write `{}`: {}", runtime.display(), e))?;
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&compiler)
+        .arg("-O2")
+        .arg("-o")
+        .arg(output)
+        .args(objects)
+        .arg(&runtime)
+        .arg("-lpthread")
+        .status()
+        .map_err(|e| format!("could not run the linker `{}`: {}", compiler, e))?;
+    if !status.success() {
+        return Err(format!("linking with `{}` failed: {}", compiler, status));
+    }
+    Ok(())
+}
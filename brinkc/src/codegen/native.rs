@@ -0,0 +1,1464 @@
+use std::{collections::HashMap, rc::Rc};
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::IntCC, types::I64, AbiParam, Block as ClifBlock, FuncRef, GlobalValue,
+        InstBuilder, MemFlags, SigRef, Signature, StackSlot, StackSlotData, StackSlotKind,
+        Value as ClifValue,
+    },
+    isa::CallConv,
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use crate::{
+    anf::{Atom, Block, Function, GlobalValue as AnfGlobal, Program, Repr, Stmt, Value},
+    ast::ForDirection,
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    resolve::{Builtin, Resolutions},
+};
+
+use super::{
+    builtin_may_raise, builtin_symbol, con_kind, def_names, header, Closures, Frame, Kind,
+    CLOSURE_CAPTURES, GC_STATIC,
+};
+
+/// Compiles the program to an object file for the host, defining
+/// `brink_main` and the names the runtime reports the exceptions with.
+///
+/// Every function is compiled to a direct entry, taking the closure and the
+/// arguments as parameters, and to an entry taking the closure and an array
+/// of the arguments, which the runtime calls. The applications of unknown
+/// functions call the direct entry when the function is a closure of their
+/// arity, and the runtime otherwise; the known calls always call it. The
+/// direct entries use the tail calling convention, so the calls of them in
+/// tail position reuse the frame of the caller. The frame of each function
+/// on the shadow stack is a stack slot, holding the values of the function
+/// across the calls which may collect the garbage.
+pub fn compile(
+    program: &Program,
+    resolutions: &Resolutions,
+    optimize: bool,
+) -> Result<Vec<u8>, String> {
+    let mut flags = settings::builder();
+    let set = |flags: &mut settings::Builder, name, value| {
+        flags
+            .set(name, value)
+            .map_err(|e| format!("invalid code generation setting: {}", e))
+    };
+    set(&mut flags, "is_pic", "true")?;
+    // Required by the tail calls.
+    set(&mut flags, "preserve_frame_pointers", "true")?;
+    set(
+        &mut flags,
+        "opt_level",
+        if optimize { "speed" } else { "none" },
+    )?;
+    let isa = cranelift_native::builder()
+        .map_err(|e| format!("unsupported host: {}", e))?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| format!("unsupported host: {}", e))?;
+    let builder = ObjectBuilder::new(isa, "brink", cranelift_module::default_libcall_names())
+        .map_err(|e| e.to_string())?;
+
+    let mut compiler = Compiler {
+        program,
+        closures: Closures::new(program),
+        module: ObjectModule::new(builder),
+        functions: HashMap::new(),
+        static_closures: HashMap::new(),
+        slots: HashMap::new(),
+        strings: HashMap::new(),
+        runtime_functions: HashMap::new(),
+        runtime_data: HashMap::new(),
+        pending: Vec::new(),
+    };
+    compiler.compile_program(resolutions)?;
+    let product = compiler.module.finish();
+    product.emit().map_err(|e| e.to_string())
+}
+
+/// The entries of a compiled function.
+#[derive(Copy, Clone)]
+struct Entries {
+    direct: FuncId,
+    array: FuncId,
+    arity: usize,
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    closures: Closures,
+    module: ObjectModule,
+    /// Keyed by the variables the functions are bound to.
+    functions: HashMap<VarId, Entries>,
+    /// The closures of the global functions, which capture nothing.
+    static_closures: HashMap<VarId, DataId>,
+    /// The values of the other globals, stored when the program starts.
+    slots: HashMap<VarId, DataId>,
+    strings: HashMap<Rc<str>, DataId>,
+    runtime_functions: HashMap<String, FuncId>,
+    runtime_data: HashMap<&'static str, DataId>,
+    /// The local functions declared and not compiled yet.
+    pending: Vec<(VarId, &'a Function)>,
+}
+
+type CompileResult<T> = Result<T, String>;
+
+impl<'a> Compiler<'a> {
+    fn compile_program(&mut self, resolutions: &Resolutions) -> CompileResult<()> {
+        let program = self.program;
+        for global in &program.globals {
+            match &global.value {
+                AnfGlobal::Function(function) => {
+                    let entries = self.declare_function(global.var, function)?;
+                    let data = self.declare_data(
+                        &format!("{}.closure", self.symbol(global.var)),
+                        Linkage::Local,
+                        true,
+                    )?;
+                    let mut description = DataDescription::new();
+                    let mut bytes = GC_STATIC.to_ne_bytes().to_vec();
+                    bytes.extend_from_slice(
+                        &header(Kind::Closure, entries.arity as u32, CLOSURE_CAPTURES)
+                            .to_ne_bytes(),
+                    );
+                    bytes.resize(8 * (2 + CLOSURE_CAPTURES), 0);
+                    description.define(bytes.into_boxed_slice());
+                    description.set_align(8);
+                    let array = self
+                        .module
+                        .declare_func_in_data(entries.array, &mut description);
+                    let direct = self
+                        .module
+                        .declare_func_in_data(entries.direct, &mut description);
+                    description.write_function_addr(16, array);
+                    description.write_function_addr(24, direct);
+                    self.module
+                        .define_data(data, &description)
+                        .map_err(|e| e.to_string())?;
+                    self.static_closures.insert(global.var, data);
+                }
+                AnfGlobal::Block(_) => {
+                    let data = self.declare_data(&self.symbol(global.var), Linkage::Local, true)?;
+                    let mut description = DataDescription::new();
+                    description.define_zeroinit(8);
+                    description.set_align(8);
+                    self.module
+                        .define_data(data, &description)
+                        .map_err(|e| e.to_string())?;
+                    self.slots.insert(global.var, data);
+                }
+            }
+        }
+
+        for global in &program.globals {
+            if let AnfGlobal::Function(function) = &global.value {
+                self.compile_function(global.var, function)?;
+            }
+        }
+        self.compile_main()?;
+        while let Some((var, function)) = self.pending.pop() {
+            self.compile_function(var, function)?;
+        }
+
+        // The addresses of the globals holding values, for the collector.
+        let globals = self.declare_data("brink_globals", Linkage::Export, false)?;
+        let mut description = DataDescription::new();
+        let slots = program
+            .globals
+            .iter()
+            .filter(|global| program.var(global.var).repr == Repr::Value)
+            .filter_map(|global| self.slots.get(&global.var).copied())
+            .collect::<Vec<_>>();
+        // The addresses are relocations, which zero-initialized data
+        // cannot hold.
+        description.define(vec![0; 8 * (slots.len() + 1)].into_boxed_slice());
+        description.set_align(8);
+        for (i, slot) in slots.into_iter().enumerate() {
+            let slot = self.module.declare_data_in_data(slot, &mut description);
+            description.write_data_addr(8 * i as u32, slot, 0);
+        }
+        self.module
+            .define_data(globals, &description)
+            .map_err(|e| e.to_string())?;
+
+        let names = self.declare_data("brink_def_names", Linkage::Export, false)?;
+        let mut description = DataDescription::new();
+        description.define(def_names(resolutions).into_boxed_slice());
+        self.module
+            .define_data(names, &description)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Gets the symbol of the function or the global bound to the variable,
+    /// unique in the program.
+    fn symbol(&self, var: VarId) -> String {
+        let mut symbol = String::from("brink.");
+        for c in self.program.var(var).name.chars() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                symbol.push(c);
+            } else {
+                symbol.push_str(&format!("${:x}", c as u32));
+            }
+        }
+        format!("{}.{}", symbol, var.as_usize())
+    }
+
+    fn signature(&self, parameters: usize) -> Signature {
+        let mut signature = self.module.make_signature();
+        signature
+            .params
+            .extend(std::iter::repeat_n(AbiParam::new(I64), parameters));
+        signature.returns.push(AbiParam::new(I64));
+        signature
+    }
+
+    /// Gets the signature of the direct entries, which can be tail called.
+    fn direct_signature(&self, parameters: usize) -> Signature {
+        let mut signature = self.signature(parameters);
+        signature.call_conv = CallConv::Tail;
+        signature
+    }
+
+    fn declare_data(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        writable: bool,
+    ) -> CompileResult<DataId> {
+        self.module
+            .declare_data(name, linkage, writable, false)
+            .map_err(|e| e.to_string())
+    }
+
+    fn declare_function(&mut self, var: VarId, function: &Function) -> CompileResult<Entries> {
+        let arity = function.parameters.len();
+        let symbol = self.symbol(var);
+        let direct = self
+            .module
+            .declare_function(&symbol, Linkage::Local, &self.direct_signature(1 + arity))
+            .map_err(|e| e.to_string())?;
+        let array = self
+            .module
+            .declare_function(
+                &format!("{}.entry", symbol),
+                Linkage::Local,
+                &self.signature(2),
+            )
+            .map_err(|e| e.to_string())?;
+        let entries = Entries {
+            direct,
+            array,
+            arity,
+        };
+        self.functions.insert(var, entries);
+        Ok(entries)
+    }
+
+    fn runtime_function(&mut self, name: &str, parameters: usize) -> CompileResult<FuncId> {
+        if let Some(id) = self.runtime_functions.get(name) {
+            return Ok(*id);
+        }
+        let id = self
+            .module
+            .declare_function(name, Linkage::Import, &self.signature(parameters))
+            .map_err(|e| e.to_string())?;
+        self.runtime_functions.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    fn runtime_data(&mut self, name: &'static str) -> CompileResult<DataId> {
+        if let Some(id) = self.runtime_data.get(name) {
+            return Ok(*id);
+        }
+        let id = self.declare_data(name, Linkage::Import, true)?;
+        self.runtime_data.insert(name, id);
+        Ok(id)
+    }
+
+    /// Gets the static object of the string literal.
+    fn string(&mut self, string: &Rc<str>) -> CompileResult<DataId> {
+        if let Some(id) = self.strings.get(string) {
+            return Ok(*id);
+        }
+        let id = self.declare_data(
+            &format!("brink.string.{}", self.strings.len()),
+            Linkage::Local,
+            false,
+        )?;
+        let mut bytes = GC_STATIC.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&header(Kind::String, 0, string.len()).to_ne_bytes());
+        bytes.extend_from_slice(string.as_bytes());
+        bytes.push(0);
+        let mut description = DataDescription::new();
+        description.define(bytes.into_boxed_slice());
+        description.set_align(8);
+        self.module
+            .define_data(id, &description)
+            .map_err(|e| e.to_string())?;
+        self.strings.insert(Rc::clone(string), id);
+        Ok(id)
+    }
+
+    fn compile_function(&mut self, var: VarId, function: &'a Function) -> CompileResult<()> {
+        let entries = self.functions[&var];
+        let captures = self.closures.captures(var).to_vec();
+
+        let mut context = self.module.make_context();
+        context.func.signature = self.direct_signature(1 + entries.arity);
+        let mut builder_context = FunctionBuilderContext::new();
+        let frame = Frame::of_function(self.program, function, &captures);
+        let mut translator = Translator::new(self, &mut context.func, &mut builder_context);
+        let closure = translator.builder.block_params(translator.entry)[0];
+        let parameters = translator.builder.block_params(translator.entry)[1..].to_vec();
+        translator.enter_frame(frame)?;
+        translator.check_stack()?;
+        // The captures are loaded before anything is allocated, since the
+        // closure is not in the frame.
+        for (i, capture) in captures.iter().enumerate() {
+            let offset = 8 * (1 + CLOSURE_CAPTURES + i) as i32;
+            let mut value = translator.load(closure, offset);
+            if translator.compiler.program.var(*capture).repr == Repr::Int {
+                value = translator.unbox(value);
+            }
+            translator.define(*capture, value);
+        }
+        for (parameter, value) in function.parameters.iter().zip(parameters) {
+            translator.define(*parameter, value);
+        }
+        translator.block_to(&function.body, Dest::Return)?;
+        translator.finish()?;
+        self.module
+            .define_function(entries.direct, &mut context)
+            .map_err(|e| format!("{:?}", e))?;
+
+        // The entry taking the arguments in an array calls the direct one.
+        let mut context = self.module.make_context();
+        context.func.signature = self.signature(2);
+        let mut translator = Translator::new(self, &mut context.func, &mut builder_context);
+        let closure = translator.builder.block_params(translator.entry)[0];
+        let array = translator.builder.block_params(translator.entry)[1];
+        let mut arguments = vec![closure];
+        for i in 0..entries.arity {
+            arguments.push(translator.load(array, 8 * i as i32));
+        }
+        let direct = translator.func_ref(entries.direct);
+        let call = translator.builder.ins().call(direct, &arguments);
+        let result = translator.builder.inst_results(call)[0];
+        translator.return_value(result)?;
+        translator.finish()?;
+        self.module
+            .define_function(entries.array, &mut context)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    /// Compiles `brink_main`, which evaluates the globals other than the
+    /// functions in order and stores their values.
+    fn compile_main(&mut self) -> CompileResult<()> {
+        let main = self
+            .module
+            .declare_function("brink_main", Linkage::Export, &self.signature(0))
+            .map_err(|e| e.to_string())?;
+        let mut context = self.module.make_context();
+        context.func.signature = self.signature(0);
+        let mut builder_context = FunctionBuilderContext::new();
+        let program = self.program;
+        let frame = Frame::of_blocks(
+            program,
+            program
+                .globals
+                .iter()
+                .filter_map(|global| match &global.value {
+                    AnfGlobal::Block(block) => Some(block),
+                    AnfGlobal::Function(_) => None,
+                }),
+        );
+        let mut translator = Translator::new(self, &mut context.func, &mut builder_context);
+        translator.enter_frame(frame)?;
+        for global in &program.globals {
+            if let AnfGlobal::Block(block) = &global.value {
+                let value = translator.block(block)?;
+                let slot = translator.compiler.slots[&global.var];
+                let address = translator.data_address(slot);
+                translator
+                    .builder
+                    .ins()
+                    .store(MemFlags::trusted(), value, address, 0);
+            }
+        }
+        let result = translator.builder.ins().iconst(I64, 0);
+        translator.return_value(result)?;
+        translator.finish()?;
+        self.module
+            .define_function(main, &mut context)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Translates the body of a function.
+struct Translator<'c, 'a, 'f> {
+    compiler: &'c mut Compiler<'a>,
+    builder: FunctionBuilder<'f>,
+    entry: ClifBlock,
+    variables: HashMap<VarId, Variable>,
+    /// The handlers of the enclosing `try`s, the innermost last.
+    handlers: Vec<ClifBlock>,
+    /// The block returning to the caller with the exception raised.
+    unwind: ClifBlock,
+    func_refs: HashMap<FuncId, FuncRef>,
+    data_refs: HashMap<DataId, GlobalValue>,
+    /// The slots of the values of the function in its frame.
+    frame: Frame,
+    /// The frame, holding the previous frame, the number of the roots, the
+    /// address of the roots and the roots.
+    frame_slot: Option<StackSlot>,
+}
+
+/// The offset of the first root in the frame.
+const FRAME_ROOTS: i32 = 24;
+
+/// Where the value of a block goes.
+#[derive(Copy, Clone)]
+enum Dest {
+    /// The join block, taking the value as its parameter.
+    Join(ClifBlock),
+    /// The caller of the function, so the call the block ends with is a
+    /// tail call.
+    Return,
+}
+
+impl<'c, 'a, 'f> Translator<'c, 'a, 'f> {
+    fn new(
+        compiler: &'c mut Compiler<'a>,
+        function: &'f mut cranelift_codegen::ir::Function,
+        builder_context: &'f mut FunctionBuilderContext,
+    ) -> Self {
+        let mut builder = FunctionBuilder::new(function, builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let unwind = builder.create_block();
+        builder.set_cold_block(unwind);
+        Translator {
+            compiler,
+            builder,
+            entry,
+            variables: HashMap::new(),
+            handlers: Vec::new(),
+            unwind,
+            func_refs: HashMap::new(),
+            data_refs: HashMap::new(),
+            frame: Frame::default(),
+            frame_slot: None,
+        }
+    }
+
+    /// Pushes the frame of the function on the shadow stack, with its roots
+    /// zeroed.
+    fn enter_frame(&mut self, frame: Frame) -> CompileResult<()> {
+        self.frame = frame;
+        if self.frame.is_empty() {
+            return Ok(());
+        }
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            FRAME_ROOTS as u32 + 8 * self.frame.len() as u32,
+            3,
+        ));
+        self.frame_slot = Some(slot);
+        let frames = self.runtime_address("brink_frames")?;
+        let previous = self.load(frames, 0);
+        self.builder.ins().stack_store(previous, slot, 0);
+        let count = self.builder.ins().iconst(I64, self.frame.len() as i64);
+        self.builder.ins().stack_store(count, slot, 8);
+        let roots = self.builder.ins().stack_addr(I64, slot, FRAME_ROOTS);
+        self.builder.ins().stack_store(roots, slot, 16);
+        let zero = self.builder.ins().iconst(I64, 0);
+        for i in 0..self.frame.len() {
+            self.builder
+                .ins()
+                .stack_store(zero, slot, FRAME_ROOTS + 8 * i as i32);
+        }
+        let frame = self.builder.ins().stack_addr(I64, slot, 0);
+        self.store(frame, frames, 0);
+        Ok(())
+    }
+
+    /// Pops the frame of the function, before it returns.
+    fn leave_frame(&mut self) -> CompileResult<()> {
+        if let Some(slot) = self.frame_slot {
+            let previous = self.builder.ins().stack_load(I64, slot, 0);
+            let frames = self.runtime_address("brink_frames")?;
+            self.store(previous, frames, 0);
+        }
+        Ok(())
+    }
+
+    /// Returns the result from the function.
+    fn return_value(&mut self, result: ClifValue) -> CompileResult<()> {
+        self.leave_frame()?;
+        self.builder.ins().return_(&[result]);
+        Ok(())
+    }
+
+    /// Returns zero from the unwinding block, finishing the function.
+    fn finish(mut self) -> CompileResult<()> {
+        self.builder.switch_to_block(self.unwind);
+        self.leave_frame()?;
+        let zero = self.builder.ins().iconst(I64, 0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+        Ok(())
+    }
+
+    fn func_ref(&mut self, id: FuncId) -> FuncRef {
+        if let Some(func_ref) = self.func_refs.get(&id) {
+            return *func_ref;
+        }
+        let func_ref = self
+            .compiler
+            .module
+            .declare_func_in_func(id, self.builder.func);
+        self.func_refs.insert(id, func_ref);
+        func_ref
+    }
+
+    fn data_address(&mut self, id: DataId) -> ClifValue {
+        let global = match self.data_refs.get(&id) {
+            Some(global) => *global,
+            None => {
+                let global = self
+                    .compiler
+                    .module
+                    .declare_data_in_func(id, self.builder.func);
+                self.data_refs.insert(id, global);
+                global
+            }
+        };
+        self.builder.ins().symbol_value(I64, global)
+    }
+
+    fn runtime_address(&mut self, name: &'static str) -> CompileResult<ClifValue> {
+        let id = self.compiler.runtime_data(name)?;
+        Ok(self.data_address(id))
+    }
+
+    /// Gets the static object of the data, which follows the flags of the
+    /// collector.
+    fn static_object(&mut self, id: DataId) -> ClifValue {
+        let address = self.data_address(id);
+        self.builder.ins().iadd_imm(address, 8)
+    }
+
+    fn runtime_object(&mut self, name: &'static str) -> CompileResult<ClifValue> {
+        let id = self.compiler.runtime_data(name)?;
+        Ok(self.static_object(id))
+    }
+
+    fn call_runtime(&mut self, name: &str, arguments: &[ClifValue]) -> CompileResult<ClifValue> {
+        let id = self.compiler.runtime_function(name, arguments.len())?;
+        let func_ref = self.func_ref(id);
+        let call = self.builder.ins().call(func_ref, arguments);
+        Ok(self.builder.inst_results(call)[0])
+    }
+
+    fn load(&mut self, address: ClifValue, offset: i32) -> ClifValue {
+        self.builder
+            .ins()
+            .load(I64, MemFlags::trusted(), address, offset)
+    }
+
+    fn store(&mut self, value: ClifValue, address: ClifValue, offset: i32) {
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, address, offset);
+    }
+
+    fn define(&mut self, var: VarId, value: ClifValue) {
+        if let (Some(slot), Some(frame)) = (self.frame.slot(var), self.frame_slot) {
+            self.builder
+                .ins()
+                .stack_store(value, frame, FRAME_ROOTS + 8 * slot as i32);
+            return;
+        }
+        let next = self.variables.len();
+        let builder = &mut self.builder;
+        let variable = *self.variables.entry(var).or_insert_with(|| {
+            let variable = Variable::from_u32(next as u32);
+            builder.declare_var(variable, I64);
+            variable
+        });
+        self.builder.def_var(variable, value);
+    }
+
+    fn var(&mut self, var: VarId) -> ClifValue {
+        if let Some(closure) = self.compiler.static_closures.get(&var) {
+            return self.static_object(*closure);
+        }
+        if let (Some(slot), Some(frame)) = (self.frame.slot(var), self.frame_slot) {
+            return self
+                .builder
+                .ins()
+                .stack_load(I64, frame, FRAME_ROOTS + 8 * slot as i32);
+        }
+        if let Some(slot) = self.compiler.slots.get(&var) {
+            let address = self.data_address(*slot);
+            return self.load(address, 0);
+        }
+        self.builder.use_var(self.variables[&var])
+    }
+
+    fn atom(&mut self, atom: &Atom) -> CompileResult<ClifValue> {
+        Ok(match atom {
+            Atom::Var(var) => self.var(*var),
+            Atom::Literal(Literal::Int(n)) => self.builder.ins().iconst(I64, *n),
+            Atom::Literal(Literal::Bool(b)) => self.bool_object(*b)?,
+            Atom::Literal(Literal::Unit) => self.runtime_object("brink_unit")?,
+            Atom::Literal(Literal::String(string)) => {
+                let id = self.compiler.string(string)?;
+                self.static_object(id)
+            }
+        })
+    }
+
+    fn atoms(&mut self, atoms: &[Atom]) -> CompileResult<Vec<ClifValue>> {
+        atoms.iter().map(|atom| self.atom(atom)).collect()
+    }
+
+    fn bool_object(&mut self, b: bool) -> CompileResult<ClifValue> {
+        self.runtime_object(if b { "brink_true" } else { "brink_false" })
+    }
+
+    /// Converts the condition to a boolean object.
+    fn bool_of_condition(&mut self, condition: ClifValue) -> CompileResult<ClifValue> {
+        let true_object = self.bool_object(true)?;
+        let false_object = self.bool_object(false)?;
+        Ok(self
+            .builder
+            .ins()
+            .select(condition, true_object, false_object))
+    }
+
+    /// Tests whether the boolean object is `true`.
+    fn is_true(&mut self, value: ClifValue) -> CompileResult<ClifValue> {
+        let true_object = self.bool_object(true)?;
+        Ok(self.builder.ins().icmp(IntCC::Equal, value, true_object))
+    }
+
+    fn alloc(&mut self, header: u64) -> CompileResult<ClifValue> {
+        let header = self.builder.ins().iconst(I64, header as i64);
+        self.call_runtime("brink_alloc", &[header])
+    }
+
+    /// Gets the block the raised exceptions are passed to.
+    fn raise_target(&self) -> ClifBlock {
+        self.handlers.last().copied().unwrap_or(self.unwind)
+    }
+
+    /// Branches to the handler if the call just made raised an exception.
+    fn check_exception(&mut self) -> CompileResult<()> {
+        let address = self.runtime_address("brink_exn")?;
+        let exception = self.load(address, 0);
+        let next = self.builder.create_block();
+        let target = self.raise_target();
+        self.builder.ins().brif(exception, target, &[], next, &[]);
+        self.builder.switch_to_block(next);
+        Ok(())
+    }
+
+    /// Branches to the handler, continuing in an unreachable block.
+    fn raise(&mut self) {
+        let target = self.raise_target();
+        self.builder.ins().jump(target, &[]);
+        let next = self.builder.create_block();
+        self.builder.switch_to_block(next);
+    }
+
+    /// Raises `StackOverflow` when the stack is almost exhausted, so the
+    /// recursion too deep is reported like in the interpreter.
+    fn check_stack(&mut self) -> CompileResult<()> {
+        let pointer = self.builder.ins().get_stack_pointer(I64);
+        let address = self.runtime_address("brink_stack_limit")?;
+        let limit = self.load(address, 0);
+        let overflow = self
+            .builder
+            .ins()
+            .icmp(IntCC::UnsignedLessThan, pointer, limit);
+        let overflow_block = self.builder.create_block();
+        self.builder.set_cold_block(overflow_block);
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(overflow, overflow_block, &[], next, &[]);
+        self.builder.switch_to_block(overflow_block);
+        self.call_runtime("brink_stack_overflow", &[])?;
+        let unwind = self.unwind;
+        self.builder.ins().jump(unwind, &[]);
+        self.builder.switch_to_block(next);
+        Ok(())
+    }
+
+    fn block(&mut self, block: &'a Block) -> CompileResult<ClifValue> {
+        for stmt in &block.stmts {
+            self.stmt(stmt)?;
+        }
+        self.atom(&block.result)
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> CompileResult<()> {
+        match stmt {
+            Stmt::Let(var, value) => {
+                let value = self.value(*var, value)?;
+                self.define(*var, value);
+            }
+            Stmt::LetRec(functions) => {
+                // The closures are allocated before their captured values
+                // are stored, since they capture each other.
+                for (var, function) in functions {
+                    let closure = self.alloc_closure(*var, function)?;
+                    self.define(*var, closure);
+                }
+                for (var, _) in functions {
+                    self.store_captures(*var, functions.len() > 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates the block and passes its result to the destination. The
+    /// block returned from the function evaluating to the value of its last
+    /// statement computes the value in tail position.
+    fn block_to(&mut self, block: &'a Block, dest: Dest) -> CompileResult<()> {
+        if let (Dest::Return, Some((Stmt::Let(var, value), stmts))) =
+            (dest, block.stmts.split_last())
+        {
+            if block.result == Atom::Var(*var) {
+                for stmt in stmts {
+                    self.stmt(stmt)?;
+                }
+                return self.tail_value(*var, value);
+            }
+        }
+        let result = self.block(block)?;
+        self.pass(result, dest)
+    }
+
+    /// Evaluates the block and jumps to the join with its result.
+    fn join_block(&mut self, block: &'a Block, join: ClifBlock) -> CompileResult<()> {
+        self.block_to(block, Dest::Join(join))
+    }
+
+    fn pass(&mut self, result: ClifValue, dest: Dest) -> CompileResult<()> {
+        match dest {
+            Dest::Join(join) => {
+                self.builder.ins().jump(join, &[result]);
+                Ok(())
+            }
+            Dest::Return => self.return_value(result),
+        }
+    }
+
+    fn new_join(&mut self) -> (ClifBlock, ClifValue) {
+        let join = self.builder.create_block();
+        let result = self.builder.append_block_param(join, I64);
+        (join, result)
+    }
+
+    fn alloc_closure(&mut self, var: VarId, function: &'a Function) -> CompileResult<ClifValue> {
+        let entries = self.compiler.declare_function(var, function)?;
+        self.compiler.pending.push((var, function));
+        let captures = self.compiler.closures.captures(var).len();
+        let closure = self.alloc(header(
+            Kind::Closure,
+            entries.arity as u32,
+            CLOSURE_CAPTURES + captures,
+        ))?;
+        let array = self.func_ref(entries.array);
+        let array = self.builder.ins().func_addr(I64, array);
+        self.store(array, closure, 8);
+        let direct = self.func_ref(entries.direct);
+        let direct = self.builder.ins().func_addr(I64, direct);
+        self.store(direct, closure, 16);
+        Ok(closure)
+    }
+
+    /// Stores the captured values in the closure, bound to the variable.
+    /// The unboxed integers are boxed, so all the fields are values. The
+    /// closure may have been moved out of the nursery by the allocations
+    /// since its own, the other closures of a `let rec` or the boxes, in
+    /// which case the stores go through the write barrier.
+    fn store_captures(&mut self, var: VarId, mut moved: bool) -> CompileResult<()> {
+        let captures = self.compiler.closures.captures(var).to_vec();
+        for (i, capture) in captures.into_iter().enumerate() {
+            let mut value = self.var(capture);
+            if self.compiler.program.var(capture).repr == Repr::Int {
+                value = self.box_int(value)?;
+                moved = true;
+            }
+            let closure = self.var(var);
+            if moved {
+                let field = self
+                    .builder
+                    .ins()
+                    .iconst(I64, (CLOSURE_CAPTURES + i) as i64);
+                self.call_runtime("brink_write", &[closure, field, value])?;
+            } else {
+                self.store(value, closure, 8 * (1 + CLOSURE_CAPTURES + i) as i32);
+            }
+        }
+        Ok(())
+    }
+
+    /// Boxes the integer: it is tagged if it fits in 63 bits, and the
+    /// runtime allocates it otherwise.
+    fn box_int(&mut self, value: ClifValue) -> CompileResult<ClifValue> {
+        let shifted = self.builder.ins().ishl_imm(value, 1);
+        let tagged = self.builder.ins().bor_imm(shifted, 1);
+        let restored = self.builder.ins().sshr_imm(shifted, 1);
+        let fits = self.builder.ins().icmp(IntCC::Equal, restored, value);
+        let large = self.builder.create_block();
+        self.builder.set_cold_block(large);
+        let (join, result) = self.new_join();
+        self.builder.ins().brif(fits, join, &[tagged], large, &[]);
+        self.builder.switch_to_block(large);
+        let boxed = self.call_runtime("brink_box", &[value])?;
+        self.builder.ins().jump(join, &[boxed]);
+        self.builder.switch_to_block(join);
+        Ok(result)
+    }
+
+    /// Gets the integer of the value, tagged or boxed.
+    fn unbox(&mut self, value: ClifValue) -> ClifValue {
+        let tag = self.builder.ins().band_imm(value, 1);
+        let tagged = self.builder.create_block();
+        let boxed = self.builder.create_block();
+        let (join, result) = self.new_join();
+        self.builder.ins().brif(tag, tagged, &[], boxed, &[]);
+        self.builder.switch_to_block(tagged);
+        let shifted = self.builder.ins().sshr_imm(value, 1);
+        self.builder.ins().jump(join, &[shifted]);
+        self.builder.switch_to_block(boxed);
+        let loaded = self.load(value, 8);
+        self.builder.ins().jump(join, &[loaded]);
+        self.builder.switch_to_block(join);
+        result
+    }
+
+    fn value(&mut self, var: VarId, value: &'a Value) -> CompileResult<ClifValue> {
+        match value {
+            Value::Atom(atom) => self.atom(atom),
+            Value::Lambda(function) => {
+                let closure = self.alloc_closure(var, function)?;
+                self.define(var, closure);
+                self.store_captures(var, false)?;
+                Ok(self.var(var))
+            }
+            Value::Apply(function, arguments) => {
+                let function = self.atom(function)?;
+                let arguments = self.atoms(arguments)?;
+                self.apply(function, &arguments)
+            }
+            Value::Call(function, arguments) => {
+                let (direct, values) = self.call_operands(*function, arguments)?;
+                let call = self.builder.ins().call(direct, &values);
+                let result = self.builder.inst_results(call)[0];
+                self.check_exception()?;
+                Ok(result)
+            }
+            Value::If(..) | Value::Switch(..) | Value::Try(..) => {
+                let (join, result) = self.new_join();
+                self.branch(value, Dest::Join(join))?;
+                self.builder.switch_to_block(join);
+                Ok(result)
+            }
+            Value::Prim(op, arguments) => self.prim(*op, arguments),
+            Value::Box(atom) => {
+                let value = self.atom(atom)?;
+                self.box_int(value)
+            }
+            Value::Unbox(atom) => {
+                let boxed = self.atom(atom)?;
+                Ok(self.unbox(boxed))
+            }
+            Value::Construct(con, fields) => {
+                // The fields are loaded after the allocation, which may
+                // move them.
+                let (kind, tag) = con_kind(*con);
+                let object = self.alloc(header(kind, tag, fields.len()))?;
+                let fields = self.atoms(fields)?;
+                for (i, field) in fields.into_iter().enumerate() {
+                    self.store(field, object, 8 * (1 + i) as i32);
+                }
+                Ok(object)
+            }
+            Value::Field(atom, _, index) => {
+                let object = self.atom(atom)?;
+                Ok(self.load(object, 8 * (1 + *index) as i32))
+            }
+            Value::While(condition, body) => {
+                let header = self.builder.create_block();
+                let body_label = self.builder.create_block();
+                let exit = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                let condition = self.block(condition)?;
+                let condition = self.is_true(condition)?;
+                self.builder
+                    .ins()
+                    .brif(condition, body_label, &[], exit, &[]);
+                self.builder.switch_to_block(body_label);
+                self.block(body)?;
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(exit);
+                self.runtime_object("brink_unit")
+            }
+            Value::For(counter, start, direction, end, body) => {
+                let start = self.atom(start)?;
+                let end = self.atom(end)?;
+                self.define(*counter, start);
+                let (skip_cc, step) = match direction {
+                    ForDirection::Up => (IntCC::SignedGreaterThan, 1),
+                    ForDirection::Down => (IntCC::SignedLessThan, -1),
+                };
+                let body_label = self.builder.create_block();
+                let next = self.builder.create_block();
+                let exit = self.builder.create_block();
+                let skip = self.builder.ins().icmp(skip_cc, start, end);
+                self.builder.ins().brif(skip, exit, &[], body_label, &[]);
+                self.builder.switch_to_block(body_label);
+                self.block(body)?;
+                // The counter is compared with the end before it is
+                // stepped, so it does not overflow past it.
+                let current = self.var(*counter);
+                let done = self.builder.ins().icmp(IntCC::Equal, current, end);
+                self.builder.ins().brif(done, exit, &[], next, &[]);
+                self.builder.switch_to_block(next);
+                let stepped = self.builder.ins().iadd_imm(current, step);
+                self.define(*counter, stepped);
+                self.builder.ins().jump(body_label, &[]);
+                self.builder.switch_to_block(exit);
+                self.runtime_object("brink_unit")
+            }
+        }
+    }
+
+    /// Computes the value in tail position, returning it from the function.
+    /// The calls are tail calls, except for the applications the runtime
+    /// makes, and so are the ones the branches end with.
+    fn tail_value(&mut self, var: VarId, value: &'a Value) -> CompileResult<()> {
+        match value {
+            Value::Apply(function, arguments) => {
+                let function = self.atom(function)?;
+                let arguments = self.atoms(arguments)?;
+                self.tail_apply(function, &arguments)
+            }
+            Value::Call(function, arguments) => {
+                let (direct, values) = self.call_operands(*function, arguments)?;
+                self.leave_frame()?;
+                self.builder.ins().return_call(direct, &values);
+                Ok(())
+            }
+            Value::If(..) | Value::Switch(..) | Value::Try(..) => self.branch(value, Dest::Return),
+            _ => {
+                let result = self.value(var, value)?;
+                self.return_value(result)
+            }
+        }
+    }
+
+    /// Gets the direct entry of the known function and the values it is
+    /// called with.
+    fn call_operands(
+        &mut self,
+        function: VarId,
+        arguments: &[Atom],
+    ) -> CompileResult<(FuncRef, Vec<ClifValue>)> {
+        let entries = self.compiler.functions[&function];
+        let mut values = vec![self.var(function)];
+        values.extend(self.atoms(arguments)?);
+        Ok((self.func_ref(entries.direct), values))
+    }
+
+    /// Compiles the conditional value, passing the result of the branch
+    /// taken to the destination.
+    fn branch(&mut self, value: &'a Value, dest: Dest) -> CompileResult<()> {
+        match value {
+            Value::If(condition, then_block, else_block) => {
+                let condition = self.atom(condition)?;
+                let condition = self.is_true(condition)?;
+                let then_label = self.builder.create_block();
+                let else_label = self.builder.create_block();
+                self.builder
+                    .ins()
+                    .brif(condition, then_label, &[], else_label, &[]);
+                self.builder.switch_to_block(then_label);
+                self.block_to(then_block, dest)?;
+                self.builder.switch_to_block(else_label);
+                self.block_to(else_block, dest)
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.switch(scrutinee, cases, default, dest)
+            }
+            Value::Try(body, exception, handler) => {
+                // The body is not in tail position, as the handler is
+                // removed after it returns.
+                let (join, result) = match dest {
+                    Dest::Join(join) => (join, None),
+                    Dest::Return => {
+                        let (join, result) = self.new_join();
+                        (join, Some(result))
+                    }
+                };
+                let handler_label = self.builder.create_block();
+                self.builder.set_cold_block(handler_label);
+                self.handlers.push(handler_label);
+                self.join_block(body, join)?;
+                self.handlers.pop();
+                self.builder.switch_to_block(handler_label);
+                let address = self.runtime_address("brink_exn")?;
+                let raised = self.load(address, 0);
+                let zero = self.builder.ins().iconst(I64, 0);
+                self.store(zero, address, 0);
+                self.define(*exception, raised);
+                self.block_to(handler, dest)?;
+                if let Some(result) = result {
+                    self.builder.switch_to_block(join);
+                    self.return_value(result)?;
+                }
+                Ok(())
+            }
+            value => unreachable!("branching on {:?}", value),
+        }
+    }
+
+    /// Applies the function to the arguments, calling its direct entry if
+    /// it is a closure taking as many arguments, and the runtime otherwise.
+    fn apply(&mut self, function: ClifValue, arguments: &[ClifValue]) -> CompileResult<ClifValue> {
+        let (slow, signature, direct, values) = self.branch_on_arity(function, arguments);
+        let (join, result) = self.new_join();
+        let call = self.builder.ins().call_indirect(signature, direct, &values);
+        let fast_result = self.builder.inst_results(call)[0];
+        self.builder.ins().jump(join, &[fast_result]);
+
+        self.builder.switch_to_block(slow);
+        let slow_result = self.apply_in_runtime(function, arguments)?;
+        self.builder.ins().jump(join, &[slow_result]);
+
+        self.builder.switch_to_block(join);
+        self.check_exception()?;
+        Ok(result)
+    }
+
+    /// Applies the function to the arguments in tail position, tail calling
+    /// its direct entry if it is a closure taking as many arguments.
+    fn tail_apply(&mut self, function: ClifValue, arguments: &[ClifValue]) -> CompileResult<()> {
+        let (slow, signature, direct, values) = self.branch_on_arity(function, arguments);
+        self.leave_frame()?;
+        self.builder
+            .ins()
+            .return_call_indirect(signature, direct, &values);
+
+        self.builder.switch_to_block(slow);
+        let result = self.apply_in_runtime(function, arguments)?;
+        self.return_value(result)
+    }
+
+    /// Branches on whether the function is a closure taking as many
+    /// arguments, continuing in the block calling its direct entry. Returns
+    /// the block applying the function in the runtime otherwise, and the
+    /// signature, the address and the arguments of the direct call.
+    fn branch_on_arity(
+        &mut self,
+        function: ClifValue,
+        arguments: &[ClifValue],
+    ) -> (ClifBlock, SigRef, ClifValue, Vec<ClifValue>) {
+        let count = arguments.len();
+        let header = self.load(function, 0);
+        let kind_and_tag = self.builder.ins().band_imm(header, 0xffff_ffff);
+        let expected = super::header(Kind::Closure, count as u32, 0) as i64;
+        let exact = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, kind_and_tag, expected);
+        let fast = self.builder.create_block();
+        let slow = self.builder.create_block();
+        self.builder.ins().brif(exact, fast, &[], slow, &[]);
+
+        self.builder.switch_to_block(fast);
+        let direct = self.load(function, 16);
+        let signature = self.compiler.direct_signature(1 + count);
+        let signature = self.builder.import_signature(signature);
+        let mut values = vec![function];
+        values.extend_from_slice(arguments);
+        (slow, signature, direct, values)
+    }
+
+    /// Applies the function to the arguments in the runtime, which handles
+    /// the partial applications and the applications of the results.
+    fn apply_in_runtime(
+        &mut self,
+        function: ClifValue,
+        arguments: &[ClifValue],
+    ) -> CompileResult<ClifValue> {
+        let count = arguments.len();
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            8 * count as u32,
+            3,
+        ));
+        for (i, argument) in arguments.iter().enumerate() {
+            self.builder
+                .ins()
+                .stack_store(*argument, slot, 8 * i as i32);
+        }
+        let array = self.builder.ins().stack_addr(I64, slot, 0);
+        let count = self.builder.ins().iconst(I64, count as i64);
+        self.call_runtime("brink_apply", &[function, count, array])
+    }
+
+    fn prim(&mut self, op: PrimOp, arguments: &[Atom]) -> CompileResult<ClifValue> {
+        let reprs = arguments
+            .iter()
+            .map(|argument| self.compiler.program.atom_repr(argument))
+            .collect::<Vec<_>>();
+        let values = self.atoms(arguments)?;
+        Ok(match op {
+            PrimOp::Add => self.builder.ins().iadd(values[0], values[1]),
+            PrimOp::Subtract => self.builder.ins().isub(values[0], values[1]),
+            PrimOp::Multiply => self.builder.ins().imul(values[0], values[1]),
+            PrimOp::Negate => self.builder.ins().ineg(values[0]),
+            PrimOp::Divide => self.divide(values[0], values[1])?,
+            PrimOp::Less | PrimOp::LessEqual | PrimOp::Greater | PrimOp::GreaterEqual => {
+                let cc = match op {
+                    PrimOp::Less => IntCC::SignedLessThan,
+                    PrimOp::LessEqual => IntCC::SignedLessThanOrEqual,
+                    PrimOp::Greater => IntCC::SignedGreaterThan,
+                    _ => IntCC::SignedGreaterThanOrEqual,
+                };
+                let condition = self.builder.ins().icmp(cc, values[0], values[1]);
+                self.bool_of_condition(condition)?
+            }
+            PrimOp::Equal | PrimOp::NotEqual => {
+                let cc = if op == PrimOp::Equal {
+                    IntCC::Equal
+                } else {
+                    IntCC::NotEqual
+                };
+                let condition = if reprs[0] == Repr::Int {
+                    self.builder.ins().icmp(cc, values[0], values[1])
+                } else {
+                    let equal = self.call_runtime("brink_equal", &values)?;
+                    self.builder.ins().icmp_imm(cc, equal, 1)
+                };
+                self.bool_of_condition(condition)?
+            }
+            PrimOp::Deref => self.load(values[0], 8),
+            PrimOp::Assign => {
+                let field = self.builder.ins().iconst(I64, 0);
+                self.call_runtime("brink_write", &[values[0], field, values[1]])?
+            }
+            PrimOp::Builtin(Builtin::Raise) => {
+                let address = self.runtime_address("brink_exn")?;
+                self.store(values[0], address, 0);
+                self.raise();
+                self.builder.ins().iconst(I64, 0)
+            }
+            PrimOp::Builtin(builtin) => {
+                let result = self.call_runtime(&builtin_symbol(builtin), &values)?;
+                if builtin_may_raise(builtin) {
+                    self.check_exception()?;
+                }
+                result
+            }
+        })
+    }
+
+    /// Divides the integers, raising `DivisionByZero` for the zero divisor.
+    /// The division of the minimum by -1 wraps around like in the
+    /// interpreter, instead of trapping.
+    fn divide(&mut self, dividend: ClifValue, divisor: ClifValue) -> CompileResult<ClifValue> {
+        let zero = self.builder.create_block();
+        self.builder.set_cold_block(zero);
+        let next = self.builder.create_block();
+        self.builder.ins().brif(divisor, next, &[], zero, &[]);
+        self.builder.switch_to_block(zero);
+        self.call_runtime("brink_division_by_zero", &[])?;
+        let target = self.raise_target();
+        self.builder.ins().jump(target, &[]);
+        self.builder.switch_to_block(next);
+        let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, divisor, -1);
+        let one = self.builder.ins().iconst(I64, 1);
+        let safe_divisor = self.builder.ins().select(minus_one, one, divisor);
+        let quotient = self.builder.ins().sdiv(dividend, safe_divisor);
+        let negated = self.builder.ins().ineg(dividend);
+        Ok(self.builder.ins().select(minus_one, negated, quotient))
+    }
+
+    /// Tests the value against the cases, passing the result of the block
+    /// matched to the destination.
+    fn switch(
+        &mut self,
+        scrutinee: &Atom,
+        cases: &'a [(Test, Block)],
+        default: &'a Option<Block>,
+        dest: Dest,
+    ) -> CompileResult<()> {
+        let repr = self.compiler.program.atom_repr(scrutinee);
+        let value = self.atom(scrutinee)?;
+        // The header and the unboxed integer are read once, before the
+        // tests.
+        let header = if cases.iter().any(|(test, _)| matches!(test, Test::Con(_))) {
+            Some(self.load(value, 0))
+        } else {
+            None
+        };
+        let int = match repr {
+            Repr::Int => value,
+            Repr::Value
+                if cases
+                    .iter()
+                    .any(|(test, _)| matches!(test, Test::Literal(Literal::Int(_)))) =>
+            {
+                self.unbox(value)
+            }
+            Repr::Value => value,
+        };
+        for (i, (test, block)) in cases.iter().enumerate() {
+            if i + 1 == cases.len() && default.is_none() {
+                self.block_to(block, dest)?;
+                break;
+            }
+            let matched = match test {
+                Test::Con(con) => {
+                    let header = header.unwrap();
+                    let (kind, tag) = con_kind(*con);
+                    match con {
+                        Con::Tuple(_) | Con::Record(_) => self.builder.ins().iconst(I64, 1),
+                        Con::Array(n) => self.builder.ins().icmp_imm(
+                            IntCC::Equal,
+                            header,
+                            super::header(kind, tag, *n) as i64,
+                        ),
+                        Con::Nil | Con::Cons => {
+                            let kind_byte = self.builder.ins().band_imm(header, 0xff);
+                            self.builder
+                                .ins()
+                                .icmp_imm(IntCC::Equal, kind_byte, kind as i64)
+                        }
+                        Con::Constructor(_) | Con::Exception(_) => {
+                            let kind_and_tag = self.builder.ins().band_imm(header, 0xffff_ffff);
+                            self.builder.ins().icmp_imm(
+                                IntCC::Equal,
+                                kind_and_tag,
+                                super::header(kind, tag, 0) as i64,
+                            )
+                        }
+                    }
+                }
+                Test::Literal(Literal::Int(n)) => {
+                    self.builder.ins().icmp_imm(IntCC::Equal, int, *n)
+                }
+                Test::Literal(Literal::Bool(b)) => {
+                    let object = self.bool_object(*b)?;
+                    self.builder.ins().icmp(IntCC::Equal, value, object)
+                }
+                Test::Literal(literal @ Literal::String(_)) => {
+                    let string = self.atom(&Atom::Literal(literal.clone()))?;
+                    let equal = self.call_runtime("brink_equal", &[value, string])?;
+                    self.builder.ins().icmp_imm(IntCC::Equal, equal, 1)
+                }
+                Test::Literal(Literal::Unit) => self.builder.ins().iconst(I64, 1),
+            };
+            let case = self.builder.create_block();
+            let next = self.builder.create_block();
+            self.builder.ins().brif(matched, case, &[], next, &[]);
+            self.builder.switch_to_block(case);
+            self.block_to(block, dest)?;
+            self.builder.switch_to_block(next);
+        }
+        match default {
+            Some(block) => self.block_to(block, dest),
+            None if cases.is_empty() => {
+                // A switch without cases on a value which cannot exist.
+                let zero = self.builder.ins().iconst(I64, 0);
+                self.pass(zero, dest)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        process::Command,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::test_util;
+
+    /// The number of the executables built, which names their directories.
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Builds the program at the optimization level and runs the
+    /// executable, returning its standard output and the first line of its
+    /// standard error, which reports the uncaught exception.
+    fn build_and_run(source: &str, opt_level: u8) -> (String, String) {
+        let (stdout, stderr) = build_and_run_with_env(source, opt_level, &[]);
+        (stdout, stderr.lines().next().unwrap_or("").to_string())
+    }
+
+    /// Builds and runs the program like `build_and_run`, in the environment
+    /// with the variables, returning its whole standard error.
+    fn build_and_run_with_env(
+        source: &str,
+        opt_level: u8,
+        env: &[(&str, &str)],
+    ) -> (String, String) {
+        let checked = test_util::check(source);
+        let object = compile(
+            &checked.optimize(opt_level),
+            &checked.resolutions,
+            opt_level > 0,
+        )
+        .unwrap();
+
+        let directory = std::env::temp_dir().join(format!(
+            "brink-native-test-{}-{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("main.o");
+        std::fs::write(&path, object).unwrap();
+        let executable = directory.join("main");
+        super::super::link(&[path], &executable).unwrap();
+        let output = Command::new(&executable)
+            .envs(env.iter().copied())
+            .output()
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&directory);
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    }
+
+    fn run_output(source: &str) -> String {
+        let outputs = [0, 2]
+            .iter()
+            .map(|level| build_and_run(source, *level))
+            .collect::<Vec<_>>();
+        assert_eq!(outputs[0], outputs[1]);
+        let (stdout, stderr) = outputs[0].clone();
+        assert_eq!("", stderr);
+        stdout
+    }
+
+    #[test]
+    fn runs_functions_and_closures() {
+        let source = "let rec fibonacci n = if n < 2 then n else fibonacci (n - 1) + fibonacci (n - 2)\n\
+                      let add x y = x + y\nlet inc = add 1\n\
+                      let make n =\n  let rec count k = if k = 0 then [] else (k + n) :: count (k - 1)\n  count\n\
+                      let twice f x = f (f x)\n\
+                      let a = print_string (string_of_int (fibonacci 20))\n\
+                      let b = print_string (sprintf \" %d %d %d\" (inc 2) (twice inc 5) (twice (add 10) 1))\n\
+                      let c = print_string (sprintf \" %b\" (make 10 3 = [13; 12; 11]))\n";
+        assert_eq!("6765 3 7 21 true", run_output(source));
+    }
+
+    #[test]
+    fn runs_loops_matches_and_exceptions() {
+        let source = "type Shape = Circle int | Square int int | Empty\n\
+                      let area s =\n  match s\n  | Circle r -> 3 * r * r\n  | Square w h -> w * h\n  | Empty -> 0\n\
+                      let total =\n  let sum = ref 0\n  for i = 1 to 4 do\n    sum := !sum + i\n  let n = ref 3\n  while !n > 0 do\n    n := !n - 1\n  !sum + !n\n\
+                      exception Invalid int\n\
+                      let r = try 1 / 0 with | DivisionByZero -> -1\n\
+                      let s = try raise (Invalid 4) with | Invalid n -> n\n\
+                      let rec deep n = 1 + deep (n + 1)\n\
+                      let o = try deep 0 with | StackOverflow -> 0\n\
+                      let p = print_string (sprintf \"%d %d %d %d %d\" (area (Circle 2) + area (Square 2 3) + area Empty) total r s o)\n";
+        assert_eq!("18 10 -1 4 0", run_output(source));
+    }
+
+    #[test]
+    fn runs_tail_calls_in_constant_space() {
+        let source = "let rec sum i acc = if i = 0 then acc else @tailcall sum (i - 1) (acc + i)\n\
+                      let rec count n = match n\n  | 0 -> 0\n  | n -> count (n - 1)\n\
+                      let apply f x = f x\n\
+                      let rec bounce n = if n = 0 then 0 else apply bounce (n - 1)\n\
+                      let rec even n =\n  let odd m = if m = 0 then false else even (m - 1)\n  n = 0 || odd (n - 1)\n\
+                      exception Done int\n\
+                      let rec retry n = try (if n > 0 then raise (Done n) else 0) with | Done k -> retry (k - 1)\n\
+                      let rec find n = if n = 1000000 then raise (Done n) else find (n + 1)\n\
+                      let r = try find 0 with | Done n -> n\n\
+                      let p = print_string (sprintf \"%d %d %d %b %d %d\" (sum 1000000 0) (count 1000000) (bounce 1000000) (even 1000001) (retry 1000000) r)\n";
+        assert_eq!("500000500000 0 0 false 0 1000000", run_output(source));
+    }
+
+    #[test]
+    fn runs_mutually_recursive_groups_in_constant_space() {
+        let source = test_util::printing(&test_util::mutually_recursive_groups(1000000), "r");
+        assert_eq!("true true 1000000", run_output(&source));
+    }
+
+    #[test]
+    fn reports_uncaught_exceptions() {
+        let source = "exception Invalid int\n\
+                      let check x = if x < 0 then raise (Invalid x) else x\n\
+                      let p = print_string \"before\"\nlet y = check (0 - 2)\n";
+        for level in [0, 2].iter() {
+            assert_eq!(
+                (
+                    "before".to_string(),
+                    "uncaught exception Invalid (-2)".to_string()
+                ),
+                build_and_run(source, *level)
+            );
+        }
+    }
+
+    /// Runs the tests of the standard library modules natively, printing
+    /// the `result` they bind.
+    #[test]
+    fn runs_stdlib_tests() {
+        let (io, path) = test_util::io_test("brink_native_io_test.txt");
+        for (name, source) in test_util::STDLIB_TESTS
+            .iter()
+            .copied()
+            .chain(Some(("io", &*io)))
+        {
+            let source = test_util::printing(source, "result");
+            assert_eq!("ok", run_output(&source), "stdlib test {}", name);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Runs a program allocating much more than the nursery holds, with
+    /// globals and old references pointing to young lists, and checks that
+    /// the collector reports both kinds of collections.
+    #[test]
+    fn collects_garbage() {
+        let source = "let rec build n acc = if n = 0 then acc else build (n - 1) ((n, string_of_int n) :: acc)\n\
+                      let rec total xs acc =\n  match xs\n  | [] -> acc\n  | (n, s) :: rest -> total rest (acc + n + string_length s)\n\
+                      let shift n =\n  let h = n * 1000000000000000000\n  fun x -> x + h\n\
+                      let latest = ref []\n\
+                      let kept = ref []\n\
+                      let sum =\n  let sum = ref 0\n  for i = 1 to 60 do\n    latest := build 5000 []\n    kept := !latest :: !kept\n    if i / 10 * 10 = i then kept := []\n    sum := !sum + total !latest 0 + (shift 5 i - 5000000000000000000)\n  !sum\n\
+                      let p = print_string (string_of_int sum)\n";
+        for level in [0, 2].iter() {
+            let (stdout, stderr) =
+                build_and_run_with_env(source, *level, &[("BRINK_GC_STATS", "1")]);
+            assert_eq!("751285410", stdout);
+            let lines = stderr.lines().collect::<Vec<_>>();
+            assert_eq!(2, lines.len(), "{}", stderr);
+            assert!(lines[0].starts_with("gc: ") && !lines[0].contains(" 0 major"));
+        }
+    }
+}
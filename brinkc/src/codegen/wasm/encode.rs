@@ -0,0 +1,424 @@
+//! The binary format of the WebAssembly modules: the instructions the
+//! backend and the runtime use, and the sections of the module.
+
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+/// The result of a structured instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlockType {
+    Empty,
+    Value(ValType),
+}
+
+/// The instructions, with the indices of their operands. The memory
+/// instructions take the static offset of the address, and the branches
+/// the depth of their label.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    /// Calls the function of the table at the index on the stack, with the
+    /// type of the index.
+    CallIndirect(u32),
+    /// The calls of the tail-call proposal, which replace the frame of the
+    /// caller.
+    ReturnCall(u32),
+    ReturnCallIndirect(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load(u32),
+    I64Load(u32),
+    I32Load8U(u32),
+    I32Store(u32),
+    I64Store(u32),
+    I32Store8(u32),
+    MemorySize,
+    MemoryGrow,
+    /// Copies the bytes of the count from the source to the destination
+    /// address, from the bulk memory operations.
+    MemoryCopy,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32LtU,
+    I32GtU,
+    I32GeU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64GeS,
+    I32Add,
+    I32Sub,
+    I32And,
+    I32Or,
+    I32Shl,
+    I32ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64RemS,
+    I64Or,
+    I64Shl,
+    I64ShrU,
+    I32WrapI64,
+    I64ExtendI32S,
+    I64ExtendI32U,
+}
+
+impl Instr {
+    fn encode(self, bytes: &mut Vec<u8>) {
+        let memory = |bytes: &mut Vec<u8>, opcode: u8, align: u32, offset: u32| {
+            bytes.push(opcode);
+            unsigned(bytes, u64::from(align));
+            unsigned(bytes, u64::from(offset));
+        };
+        match self {
+            Instr::Unreachable => bytes.push(0x00),
+            Instr::Block(ty) | Instr::Loop(ty) | Instr::If(ty) => {
+                bytes.push(match self {
+                    Instr::Block(_) => 0x02,
+                    Instr::Loop(_) => 0x03,
+                    _ => 0x04,
+                });
+                bytes.push(match ty {
+                    BlockType::Empty => 0x40,
+                    BlockType::Value(ty) => ty.code(),
+                });
+            }
+            Instr::Else => bytes.push(0x05),
+            Instr::End => bytes.push(0x0b),
+            Instr::Br(depth) => with_index(bytes, 0x0c, depth),
+            Instr::BrIf(depth) => with_index(bytes, 0x0d, depth),
+            Instr::Return => bytes.push(0x0f),
+            Instr::Call(function) => with_index(bytes, 0x10, function),
+            Instr::CallIndirect(ty) => {
+                with_index(bytes, 0x11, ty);
+                bytes.push(0);
+            }
+            Instr::ReturnCall(function) => with_index(bytes, 0x12, function),
+            Instr::ReturnCallIndirect(ty) => {
+                with_index(bytes, 0x13, ty);
+                bytes.push(0);
+            }
+            Instr::Drop => bytes.push(0x1a),
+            Instr::Select => bytes.push(0x1b),
+            Instr::LocalGet(local) => with_index(bytes, 0x20, local),
+            Instr::LocalSet(local) => with_index(bytes, 0x21, local),
+            Instr::LocalTee(local) => with_index(bytes, 0x22, local),
+            Instr::GlobalGet(global) => with_index(bytes, 0x23, global),
+            Instr::GlobalSet(global) => with_index(bytes, 0x24, global),
+            Instr::I32Load(offset) => memory(bytes, 0x28, 2, offset),
+            Instr::I64Load(offset) => memory(bytes, 0x29, 3, offset),
+            Instr::I32Load8U(offset) => memory(bytes, 0x2d, 0, offset),
+            Instr::I32Store(offset) => memory(bytes, 0x36, 2, offset),
+            Instr::I64Store(offset) => memory(bytes, 0x37, 3, offset),
+            Instr::I32Store8(offset) => memory(bytes, 0x3a, 0, offset),
+            Instr::MemorySize => bytes.extend_from_slice(&[0x3f, 0]),
+            Instr::MemoryGrow => bytes.extend_from_slice(&[0x40, 0]),
+            Instr::MemoryCopy => bytes.extend_from_slice(&[0xfc, 10, 0, 0]),
+            Instr::I32Const(value) => {
+                bytes.push(0x41);
+                signed(bytes, i64::from(value));
+            }
+            Instr::I64Const(value) => {
+                bytes.push(0x42);
+                signed(bytes, value);
+            }
+            _ => bytes.push(self.opcode()),
+        }
+    }
+
+    /// Gets the opcode of the numeric instructions, which have no
+    /// operands.
+    fn opcode(self) -> u8 {
+        match self {
+            Instr::I32Eqz => 0x45,
+            Instr::I32Eq => 0x46,
+            Instr::I32Ne => 0x47,
+            Instr::I32LtS => 0x48,
+            Instr::I32LtU => 0x49,
+            Instr::I32GtU => 0x4b,
+            Instr::I32GeU => 0x4f,
+            Instr::I64Eqz => 0x50,
+            Instr::I64Eq => 0x51,
+            Instr::I64Ne => 0x52,
+            Instr::I64LtS => 0x53,
+            Instr::I64LtU => 0x54,
+            Instr::I64GtS => 0x55,
+            Instr::I64GtU => 0x56,
+            Instr::I64LeS => 0x57,
+            Instr::I64GeS => 0x59,
+            Instr::I32Add => 0x6a,
+            Instr::I32Sub => 0x6b,
+            Instr::I32And => 0x71,
+            Instr::I32Or => 0x72,
+            Instr::I32Shl => 0x74,
+            Instr::I32ShrU => 0x76,
+            Instr::I64Add => 0x7c,
+            Instr::I64Sub => 0x7d,
+            Instr::I64Mul => 0x7e,
+            Instr::I64DivS => 0x7f,
+            Instr::I64RemS => 0x81,
+            Instr::I64Or => 0x84,
+            Instr::I64Shl => 0x86,
+            Instr::I64ShrU => 0x88,
+            Instr::I32WrapI64 => 0xa7,
+            Instr::I64ExtendI32S => 0xac,
+            Instr::I64ExtendI32U => 0xad,
+            _ => unreachable!("`{:?}` has operands", self),
+        }
+    }
+}
+
+fn with_index(bytes: &mut Vec<u8>, opcode: u8, index: u32) {
+    bytes.push(opcode);
+    unsigned(bytes, u64::from(index));
+}
+
+/// Writes the integer in the unsigned LEB128 encoding.
+fn unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Writes the integer in the signed LEB128 encoding.
+fn signed(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn name(bytes: &mut Vec<u8>, name: &str) {
+    unsigned(bytes, name.len() as u64);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+/// A function defined by the module: its type, the types of its locals
+/// after the parameters, and its code without the final `end`.
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub ty: u32,
+    pub locals: Vec<ValType>,
+    pub code: Vec<Instr>,
+}
+
+/// The WebAssembly module, built section by section. The functions are
+/// numbered after the imported ones, and the table holds every function
+/// which is called indirectly.
+#[derive(Debug, Default)]
+pub struct Module {
+    types: Vec<FuncType>,
+    type_indices: HashMap<FuncType, u32>,
+    /// The imported functions, with their module, name and type.
+    pub imports: Vec<(String, String, u32)>,
+    pub functions: Vec<Function>,
+    /// The initial values of the globals, which are mutable `i32`s.
+    pub globals: Vec<i32>,
+    /// The functions of the table, in the order of their entries.
+    pub table: Vec<u32>,
+    /// The initial number of the pages of the memory.
+    pub memory_pages: u32,
+    /// The exported functions, by their names.
+    pub exports: Vec<(String, u32)>,
+    /// The contents of the memory at the start of the data.
+    pub data: Vec<u8>,
+    pub data_start: u32,
+}
+
+impl Module {
+    /// Gets the index of the function type, adding it if it is new.
+    pub fn func_type(&mut self, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let ty = FuncType { params, results };
+        if let Some(index) = self.type_indices.get(&ty) {
+            return *index;
+        }
+        let index = self.types.len() as u32;
+        self.types.push(ty.clone());
+        self.type_indices.insert(ty, index);
+        index
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = b"\0asm".to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+
+        section(&mut bytes, 1, &self.types, |bytes, ty| {
+            bytes.push(0x60);
+            for types in [&ty.params, &ty.results].iter() {
+                unsigned(bytes, types.len() as u64);
+                bytes.extend(types.iter().map(|ty| ty.code()));
+            }
+        });
+        section(
+            &mut bytes,
+            2,
+            &self.imports,
+            |bytes, (module, field, ty)| {
+                name(bytes, module);
+                name(bytes, field);
+                bytes.push(0x00);
+                unsigned(bytes, u64::from(*ty));
+            },
+        );
+        section(&mut bytes, 3, &self.functions, |bytes, function| {
+            unsigned(bytes, u64::from(function.ty));
+        });
+        section(&mut bytes, 4, &[self.table.len() as u64], |bytes, size| {
+            bytes.extend_from_slice(&[0x70, 0x00]);
+            unsigned(bytes, *size);
+        });
+        section(&mut bytes, 5, &[self.memory_pages], |bytes, pages| {
+            bytes.push(0x00);
+            unsigned(bytes, u64::from(*pages));
+        });
+        section(&mut bytes, 6, &self.globals, |bytes, value| {
+            bytes.extend_from_slice(&[ValType::I32.code(), 0x01]);
+            Instr::I32Const(*value).encode(bytes);
+            Instr::End.encode(bytes);
+        });
+        let mut exports = self
+            .exports
+            .iter()
+            .map(|(field, function)| (field.as_str(), 0x00, *function))
+            .collect::<Vec<_>>();
+        exports.push(("memory", 0x02, 0));
+        section(&mut bytes, 7, &exports, |bytes, (field, kind, index)| {
+            name(bytes, field);
+            bytes.push(*kind);
+            unsigned(bytes, u64::from(*index));
+        });
+        section(&mut bytes, 9, &[&self.table], |bytes, functions| {
+            bytes.push(0x00);
+            Instr::I32Const(0).encode(bytes);
+            Instr::End.encode(bytes);
+            unsigned(bytes, functions.len() as u64);
+            for function in functions.iter() {
+                unsigned(bytes, u64::from(*function));
+            }
+        });
+        section(&mut bytes, 10, &self.functions, |bytes, function| {
+            let mut body = Vec::new();
+            // The locals are declared in runs of the same type.
+            let mut runs: Vec<(u32, ValType)> = Vec::new();
+            for ty in &function.locals {
+                match runs.last_mut() {
+                    Some((count, last)) if last == ty => *count += 1,
+                    _ => runs.push((1, *ty)),
+                }
+            }
+            unsigned(&mut body, runs.len() as u64);
+            for (count, ty) in runs {
+                unsigned(&mut body, u64::from(count));
+                body.push(ty.code());
+            }
+            for instr in &function.code {
+                instr.encode(&mut body);
+            }
+            Instr::End.encode(&mut body);
+            unsigned(bytes, body.len() as u64);
+            bytes.extend_from_slice(&body);
+        });
+        section(&mut bytes, 11, &[&self.data], |bytes, data| {
+            bytes.push(0x00);
+            Instr::I32Const(self.data_start as i32).encode(bytes);
+            Instr::End.encode(bytes);
+            unsigned(bytes, data.len() as u64);
+            bytes.extend_from_slice(data);
+        });
+        bytes
+    }
+}
+
+/// Writes the section of the entries, prefixed with their count, unless
+/// there are none.
+fn section<T>(bytes: &mut Vec<u8>, id: u8, entries: &[T], entry: impl Fn(&mut Vec<u8>, &T)) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut contents = Vec::new();
+    unsigned(&mut contents, entries.len() as u64);
+    for e in entries {
+        entry(&mut contents, e);
+    }
+    bytes.push(id);
+    unsigned(bytes, contents.len() as u64);
+    bytes.extend_from_slice(&contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_leb128() {
+        let mut bytes = Vec::new();
+        unsigned(&mut bytes, 624_485);
+        assert_eq!(vec![0xe5, 0x8e, 0x26], bytes);
+        bytes.clear();
+        signed(&mut bytes, -123_456);
+        assert_eq!(vec![0xc0, 0xbb, 0x78], bytes);
+        bytes.clear();
+        signed(&mut bytes, 64);
+        assert_eq!(vec![0xc0, 0x00], bytes);
+        bytes.clear();
+        signed(&mut bytes, i64::MIN);
+        assert_eq!(10, bytes.len());
+    }
+}
@@ -0,0 +1,1159 @@
+//! The WebAssembly backend of `brinkc build --target=wasm32`, which writes
+//! a `.wasm` module of the program and its runtime.
+//!
+//! The values are the addresses of the objects in the linear memory, laid
+//! out like the ones of the C runtime with 32-bit fields: the header of 8
+//! bytes is followed by the fields of 4 bytes, the bytes of a string or the
+//! integer of a box. The unit, the booleans, the string literals and the
+//! closures of the global functions are static objects of the data
+//! segment, and the other objects are allocated after it by a bump
+//! allocator which grows the memory. The closures hold the indices of
+//! their two entries in the function table, which the applications call
+//! indirectly, and the calls in tail position use the tail-call proposal.
+//!
+//! The module imports `brink.write(fd, address, length)` to write to the
+//! standard output or error, and `brink.read_byte()`, which returns the
+//! next byte of the standard input or -1 at its end. It exports its memory
+//! and `main`, which runs the program and returns the exit status: 0, or 2
+//! after it reports an uncaught exception to the standard error.
+
+use std::collections::HashMap;
+
+use crate::{
+    anf::{Atom, Block, Function, GlobalValue, Program, Repr, Stmt, Value},
+    ast::ForDirection,
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    resolve::{Builtin, Resolutions},
+};
+
+use super::{
+    builtin_may_raise, c::collect_functions, con_kind, header, tag_names, Closures, Kind,
+    BUILTIN_TAG, CLOSURE_CAPTURES,
+};
+
+mod encode;
+mod runtime;
+
+use encode::{BlockType, Instr, Module, ValType};
+use runtime::{Runtime, RUNTIME_FUNCTIONS};
+
+/// The imported functions, numbered before the runtime ones.
+const WRITE: u32 = 0;
+const READ_BYTE: u32 = 1;
+const IMPORTS: u32 = 2;
+
+/// The raised exception, checked after the calls which may raise one.
+const EXN: u32 = 0;
+/// The number of the nested calls of the compiled functions.
+const DEPTH: u32 = 1;
+/// The address of the free memory of the allocator.
+const HEAP: u32 = 2;
+/// The globals of the runtime, before the ones of the program.
+const RUNTIME_GLOBALS: u32 = 3;
+
+/// The address of the data segment. The address zero is never an object,
+/// so it is the absence of an exception.
+const DATA_START: u32 = 8;
+/// The addresses of the static objects at the start of the data.
+const UNIT: u32 = 8;
+const BOOL_FALSE: u32 = 16;
+const BOOL_TRUE: u32 = 24;
+
+/// The maximum number of nested calls, after which `StackOverflow` is
+/// raised, like in the interpreter. The engines limit the depth of the
+/// calls themselves, and trap instead.
+const MAX_CALL_DEPTH: i32 = 10_000;
+
+const PAGE_SIZE: u32 = 1 << 16;
+
+/// Compiles the program to a WebAssembly module. Every function is
+/// compiled to a function taking the closure and the arguments, called
+/// directly by the known calls and the applications of the closures of its
+/// arity, and an entry taking the arguments in an array, which the runtime
+/// calls.
+pub fn emit_wasm(program: &Program, resolutions: &Resolutions) -> Vec<u8> {
+    let mut module = Module::default();
+    let write = module.func_type(vec![ValType::I32; 3], vec![]);
+    let read_byte = module.func_type(vec![], vec![ValType::I32]);
+    module
+        .imports
+        .push(("brink".to_string(), "write".to_string(), write));
+    module
+        .imports
+        .push(("brink".to_string(), "read_byte".to_string(), read_byte));
+
+    let mut data = Data::new();
+    let names = tag_names(resolutions);
+    let tags = tag_table(&mut data, &names);
+    runtime::define(&mut module, &mut data, tags, names.len() as u32);
+
+    let mut emitter = Emitter {
+        program,
+        module,
+        data,
+        closures: Closures::new(program),
+        functions: HashMap::new(),
+        static_closures: HashMap::new(),
+        globals: HashMap::new(),
+        state: State::default(),
+    };
+    let mut functions = Vec::new();
+    for global in &program.globals {
+        match &global.value {
+            GlobalValue::Function(function) => {
+                functions.push((global.var, function));
+                collect_functions(&function.body, &mut functions);
+            }
+            GlobalValue::Block(block) => {
+                let index = RUNTIME_GLOBALS + emitter.globals.len() as u32;
+                emitter.globals.insert(global.var, index);
+                collect_functions(block, &mut functions);
+            }
+        }
+    }
+    for (i, (var, _)) in functions.iter().enumerate() {
+        emitter.functions.insert(*var, i as u32);
+        let first = IMPORTS + RUNTIME_FUNCTIONS + 2 * i as u32;
+        emitter.module.table.extend_from_slice(&[first, first + 1]);
+    }
+    for global in &program.globals {
+        if let GlobalValue::Function(function) = &global.value {
+            let number = emitter.functions[&global.var];
+            let closure = emitter.data.object(
+                header(
+                    Kind::Closure,
+                    function.parameters.len() as u32,
+                    CLOSURE_CAPTURES,
+                ),
+                &[2 * number + 1, 2 * number],
+            );
+            emitter.static_closures.insert(global.var, closure);
+        }
+    }
+
+    for (var, function) in functions.iter().copied() {
+        emitter.function(var, function);
+    }
+    emitter.main();
+
+    let Emitter {
+        mut module,
+        data,
+        globals,
+        ..
+    } = emitter;
+    let heap = (data.end() + 7) & !7;
+    module.globals = vec![0; RUNTIME_GLOBALS as usize + globals.len()];
+    module.globals[HEAP as usize] = heap as i32;
+    module.memory_pages = heap / PAGE_SIZE + 1;
+    module.data = data.bytes;
+    module.data_start = DATA_START;
+    module.encode()
+}
+
+/// Adds the table of the names of the tags to the data: for every
+/// definition, the array of its name or of the names of its fields, then
+/// the same for every builtin.
+fn tag_table(data: &mut Data, names: &[String]) -> u32 {
+    let mut entries = Vec::new();
+    for name in names {
+        let strings = name
+            .split(' ')
+            .map(|name| data.string(name.as_bytes()))
+            .collect::<Vec<_>>();
+        entries.push(data.words(&strings));
+    }
+    let mut index = 0;
+    while let Some(builtin) = Builtin::from_index(index) {
+        let string = data.string(builtin.name().as_bytes());
+        entries.push(data.words(&[string]));
+        index += 1;
+    }
+    data.words(&entries)
+}
+
+/// The contents of the data segment, starting with the unit and the
+/// booleans. The strings are static objects, shared by their uses.
+pub struct Data {
+    bytes: Vec<u8>,
+    strings: HashMap<Vec<u8>, u32>,
+}
+
+impl Data {
+    fn new() -> Data {
+        let mut data = Data {
+            bytes: Vec::new(),
+            strings: HashMap::new(),
+        };
+        let unit = data.object(header(Kind::Unit, 0, 0), &[]);
+        let bool_false = data.object(header(Kind::Bool, 0, 0), &[]);
+        let bool_true = data.object(header(Kind::Bool, 1, 0), &[]);
+        assert_eq!((UNIT, BOOL_FALSE, BOOL_TRUE), (unit, bool_false, bool_true));
+        data
+    }
+
+    fn end(&self) -> u32 {
+        DATA_START + self.bytes.len() as u32
+    }
+
+    fn align(&mut self) {
+        let end = (self.bytes.len() + 7) & !7;
+        self.bytes.resize(end, 0);
+    }
+
+    /// Adds the object of the header and the fields, returning its
+    /// address.
+    fn object(&mut self, header: u64, fields: &[u32]) -> u32 {
+        self.align();
+        let address = self.end();
+        self.bytes.extend_from_slice(&header.to_le_bytes());
+        for field in fields {
+            self.bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        address
+    }
+
+    /// Gets the address of the static string of the bytes.
+    pub fn string(&mut self, bytes: &[u8]) -> u32 {
+        if let Some(address) = self.strings.get(bytes) {
+            return *address;
+        }
+        let address = self.object(header(Kind::String, 0, bytes.len()), &[]);
+        self.bytes.extend_from_slice(bytes);
+        self.strings.insert(bytes.to_vec(), address);
+        address
+    }
+
+    /// Adds the array of the words, without a header.
+    fn words(&mut self, words: &[u32]) -> u32 {
+        self.align();
+        let address = self.end();
+        for word in words {
+            self.bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        address
+    }
+}
+
+fn val_type(repr: Repr) -> ValType {
+    match repr {
+        Repr::Value => ValType::I32,
+        Repr::Int => ValType::I64,
+    }
+}
+
+struct Emitter<'a> {
+    program: &'a Program,
+    module: Module,
+    data: Data,
+    closures: Closures,
+    /// The numbers of the functions: the function of the number `n` is
+    /// compiled to the entries `2 * n` of the direct calls and `2 * n + 1`
+    /// taking the arguments in an array, in the table and after the
+    /// runtime functions.
+    functions: HashMap<VarId, u32>,
+    /// The addresses of the closures of the global functions.
+    static_closures: HashMap<VarId, u32>,
+    /// The WebAssembly globals of the other globals.
+    globals: HashMap<VarId, u32>,
+    state: State,
+}
+
+/// The state of the function being compiled.
+#[derive(Default)]
+struct State {
+    params: u32,
+    locals: Vec<ValType>,
+    /// The locals of the variables.
+    vars: HashMap<VarId, u32>,
+    /// A local for the objects being initialized.
+    scratch: Option<u32>,
+    code: Vec<Instr>,
+    /// The number of the enclosing labels.
+    depth: u32,
+    /// The depths of the labels of the handlers of the enclosing `try`s,
+    /// the innermost last.
+    handlers: Vec<u32>,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(&mut self, instr: Instr) {
+        self.state.code.push(instr);
+    }
+
+    fn emit_all(&mut self, instrs: &[Instr]) {
+        self.state.code.extend_from_slice(instrs);
+    }
+
+    /// Emits the structured instruction, which opens a label.
+    fn open(&mut self, instr: Instr) {
+        self.emit(instr);
+        self.state.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.emit(Instr::End);
+        self.state.depth -= 1;
+    }
+
+    fn new_local(&mut self, ty: ValType) -> u32 {
+        self.state.locals.push(ty);
+        self.state.params + self.state.locals.len() as u32 - 1
+    }
+
+    fn local(&mut self, var: VarId) -> u32 {
+        if let Some(local) = self.state.vars.get(&var) {
+            return *local;
+        }
+        let local = self.new_local(val_type(self.program.var(var).repr));
+        self.state.vars.insert(var, local);
+        local
+    }
+
+    fn scratch(&mut self) -> u32 {
+        match self.state.scratch {
+            Some(local) => local,
+            None => {
+                let local = self.new_local(ValType::I32);
+                self.state.scratch = Some(local);
+                local
+            }
+        }
+    }
+
+    /// Gets the type of the direct entries of the functions of the arity,
+    /// which take the closure before the arguments.
+    fn direct_type(&mut self, arity: usize) -> u32 {
+        self.module
+            .func_type(vec![ValType::I32; 1 + arity], vec![ValType::I32])
+    }
+
+    fn direct_function(&self, var: VarId) -> u32 {
+        IMPORTS + RUNTIME_FUNCTIONS + 2 * self.functions[&var]
+    }
+
+    fn atom(&mut self, atom: &Atom) {
+        let instr = match atom {
+            Atom::Var(var) => match (self.static_closures.get(var), self.globals.get(var)) {
+                (Some(closure), _) => Instr::I32Const(*closure as i32),
+                (_, Some(global)) => Instr::GlobalGet(*global),
+                _ => Instr::LocalGet(self.local(*var)),
+            },
+            Atom::Literal(Literal::Int(n)) => Instr::I64Const(*n),
+            Atom::Literal(Literal::Bool(b)) => {
+                Instr::I32Const(if *b { BOOL_TRUE } else { BOOL_FALSE } as i32)
+            }
+            Atom::Literal(Literal::Unit) => Instr::I32Const(UNIT as i32),
+            Atom::Literal(Literal::String(string)) => {
+                Instr::I32Const(self.data.string(string.as_bytes()) as i32)
+            }
+        };
+        self.emit(instr);
+    }
+
+    /// Decrements the depth of the calls before the function returns.
+    fn leave(&mut self) {
+        self.emit_all(&[
+            Instr::GlobalGet(DEPTH),
+            Instr::I32Const(1),
+            Instr::I32Sub,
+            Instr::GlobalSet(DEPTH),
+        ]);
+    }
+
+    /// Returns the value on the stack from the function.
+    fn ret(&mut self) {
+        self.leave();
+        self.emit(Instr::Return);
+    }
+
+    /// Passes the raised exception to the innermost handler, or returns it
+    /// to the caller.
+    fn raise(&mut self) {
+        match self.state.handlers.last() {
+            Some(handler) => {
+                let depth = self.state.depth - handler;
+                self.emit(Instr::Br(depth));
+            }
+            None => {
+                self.leave();
+                self.emit_all(&[Instr::I32Const(0), Instr::Return]);
+            }
+        }
+    }
+
+    fn check_exception(&mut self) {
+        self.emit(Instr::GlobalGet(EXN));
+        self.open(Instr::If(BlockType::Empty));
+        self.raise();
+        self.close();
+    }
+
+    /// Raises the built-in exception without an argument.
+    fn raise_builtin(&mut self, exception: Builtin) {
+        let (_, tag) = con_kind(Con::Exception(exception));
+        self.emit_all(&[
+            Instr::I32Const(tag as i32),
+            Instr::I32Const(0),
+            Instr::Call(Runtime::RaiseBuiltin.index()),
+            Instr::Drop,
+        ]);
+        self.raise();
+    }
+
+    /// Compiles the function to its direct entry, which raises
+    /// `StackOverflow` when the calls are nested too deeply, and its entry
+    /// taking the arguments in an array.
+    fn function(&mut self, var: VarId, function: &Function) {
+        let arity = function.parameters.len();
+        self.state = State {
+            params: 1 + arity as u32,
+            ..State::default()
+        };
+        for (i, parameter) in function.parameters.iter().enumerate() {
+            self.state.vars.insert(*parameter, 1 + i as u32);
+        }
+        let (_, stack_overflow) = con_kind(Con::Exception(Builtin::StackOverflow));
+        self.emit_all(&[
+            Instr::GlobalGet(DEPTH),
+            Instr::I32Const(MAX_CALL_DEPTH),
+            Instr::I32GeU,
+        ]);
+        self.open(Instr::If(BlockType::Empty));
+        self.emit_all(&[
+            Instr::I32Const(stack_overflow as i32),
+            Instr::I32Const(0),
+            Instr::ReturnCall(Runtime::RaiseBuiltin.index()),
+        ]);
+        self.close();
+        self.emit_all(&[
+            Instr::GlobalGet(DEPTH),
+            Instr::I32Const(1),
+            Instr::I32Add,
+            Instr::GlobalSet(DEPTH),
+        ]);
+        for (i, capture) in self.closures.captures(var).to_vec().into_iter().enumerate() {
+            let offset = 8 + 4 * (CLOSURE_CAPTURES + i) as u32;
+            self.emit_all(&[Instr::LocalGet(0), Instr::I32Load(offset)]);
+            if self.program.var(capture).repr == Repr::Int {
+                self.emit(Instr::I64Load(8));
+            }
+            let local = self.local(capture);
+            self.emit(Instr::LocalSet(local));
+        }
+        self.block(&function.body, true);
+        let direct_type = self.direct_type(arity);
+        let state = std::mem::take(&mut self.state);
+        self.module.functions.push(encode::Function {
+            ty: direct_type,
+            locals: state.locals,
+            code: state.code,
+        });
+
+        let mut code = vec![Instr::LocalGet(0)];
+        for i in 0..arity {
+            code.extend_from_slice(&[Instr::LocalGet(1), Instr::I32Load(4 * i as u32)]);
+        }
+        code.push(Instr::ReturnCall(self.direct_function(var)));
+        let entry_type = self
+            .module
+            .func_type(vec![ValType::I32; 2], vec![ValType::I32]);
+        self.module.functions.push(encode::Function {
+            ty: entry_type,
+            locals: Vec::new(),
+            code,
+        });
+    }
+
+    /// Compiles `main`, which evaluates the globals other than the
+    /// functions in order, and reports the exception they do not handle.
+    fn main(&mut self) {
+        self.state = State::default();
+        self.open(Instr::Block(BlockType::Empty));
+        self.state.handlers.push(self.state.depth);
+        for global in &self.program.globals {
+            if let GlobalValue::Block(block) = &global.value {
+                self.block(block, false);
+                let global = self.globals[&global.var];
+                self.emit(Instr::GlobalSet(global));
+            }
+        }
+        self.emit_all(&[Instr::I32Const(0), Instr::Return]);
+        self.close();
+        self.emit_all(&[
+            Instr::Call(Runtime::ReportUncaught.index()),
+            Instr::I32Const(2),
+        ]);
+        let ty = self.module.func_type(vec![], vec![ValType::I32]);
+        let state = std::mem::take(&mut self.state);
+        let index = IMPORTS + self.module.functions.len() as u32;
+        self.module.functions.push(encode::Function {
+            ty,
+            locals: state.locals,
+            code: state.code,
+        });
+        self.module.exports.push(("main".to_string(), index));
+    }
+
+    /// Compiles the block, which leaves its value on the stack, or returns
+    /// it from the function if the block is in tail position.
+    fn block(&mut self, block: &Block, tail: bool) {
+        for (i, stmt) in block.stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Let(var, value) => {
+                    let last = i + 1 == block.stmts.len();
+                    if tail && last && matches!(block.result, Atom::Var(result) if result == *var) {
+                        self.value(*var, value, true);
+                        return;
+                    }
+                    self.value(*var, value, false);
+                    let local = self.local(*var);
+                    self.emit(Instr::LocalSet(local));
+                }
+                Stmt::LetRec(functions) => {
+                    // The closures are allocated before their captured
+                    // values are stored, since they capture each other.
+                    for (var, function) in functions {
+                        self.alloc_closure(*var, function);
+                    }
+                    for (var, _) in functions {
+                        self.store_captures(*var);
+                    }
+                }
+            }
+        }
+        self.atom(&block.result);
+        if tail {
+            self.ret();
+        }
+    }
+
+    fn alloc_closure(&mut self, var: VarId, function: &Function) {
+        let captures = self.closures.captures(var).len();
+        let number = self.functions[&var];
+        let local = self.local(var);
+        self.emit_all(&[
+            Instr::I64Const(header(
+                Kind::Closure,
+                function.parameters.len() as u32,
+                CLOSURE_CAPTURES + captures,
+            ) as i64),
+            Instr::Call(Runtime::Alloc.index()),
+            Instr::LocalTee(local),
+            Instr::I32Const(2 * number as i32 + 1),
+            Instr::I32Store(8),
+            Instr::LocalGet(local),
+            Instr::I32Const(2 * number as i32),
+            Instr::I32Store(12),
+        ]);
+    }
+
+    /// Stores the captured values in the closure. The unboxed integers are
+    /// boxed, so all the fields are values.
+    fn store_captures(&mut self, var: VarId) {
+        let local = self.local(var);
+        for (i, capture) in self.closures.captures(var).to_vec().into_iter().enumerate() {
+            self.emit(Instr::LocalGet(local));
+            self.atom(&Atom::Var(capture));
+            if self.program.var(capture).repr == Repr::Int {
+                self.emit(Instr::Call(Runtime::Box.index()));
+            }
+            self.emit(Instr::I32Store(8 + 4 * (CLOSURE_CAPTURES + i) as u32));
+        }
+    }
+
+    /// Compiles the value bound to the variable, which is returned from the
+    /// function if it is in tail position.
+    fn value(&mut self, var: VarId, value: &Value, tail: bool) {
+        let ty = BlockType::Value(val_type(self.program.var(var).repr));
+        match value {
+            Value::Atom(atom) => self.atom(atom),
+            Value::Lambda(function) => {
+                self.alloc_closure(var, function);
+                self.store_captures(var);
+                let local = self.local(var);
+                self.emit(Instr::LocalGet(local));
+            }
+            Value::Apply(function, arguments) => {
+                self.apply(function, arguments, tail);
+                if tail {
+                    return;
+                }
+                self.check_exception();
+            }
+            Value::Call(function, arguments) => {
+                self.atom(&Atom::Var(*function));
+                for argument in arguments {
+                    self.atom(argument);
+                }
+                let function = self.direct_function(*function);
+                if tail {
+                    self.leave();
+                    self.emit(Instr::ReturnCall(function));
+                    return;
+                }
+                self.emit(Instr::Call(function));
+                self.check_exception();
+            }
+            Value::Prim(op, arguments) => self.prim(*op, arguments),
+            Value::Box(atom) => {
+                self.atom(atom);
+                self.emit(Instr::Call(Runtime::Box.index()));
+            }
+            Value::Unbox(atom) => {
+                self.atom(atom);
+                self.emit(Instr::I64Load(8));
+            }
+            Value::Construct(con, fields) => {
+                let (kind, tag) = con_kind(*con);
+                self.emit_all(&[
+                    Instr::I64Const(header(kind, tag, fields.len()) as i64),
+                    Instr::Call(Runtime::Alloc.index()),
+                ]);
+                if !fields.is_empty() {
+                    let object = self.scratch();
+                    self.emit(Instr::LocalSet(object));
+                    for (i, field) in fields.iter().enumerate() {
+                        self.emit(Instr::LocalGet(object));
+                        self.atom(field);
+                        self.emit(Instr::I32Store(8 + 4 * i as u32));
+                    }
+                    self.emit(Instr::LocalGet(object));
+                }
+            }
+            Value::Field(atom, _, index) => {
+                self.atom(atom);
+                self.emit(Instr::I32Load(8 + 4 * *index as u32));
+            }
+            Value::If(condition, then_block, else_block) => {
+                self.atom(condition);
+                self.emit_all(&[Instr::I32Const(BOOL_TRUE as i32), Instr::I32Eq]);
+                self.open(Instr::If(ty));
+                self.block(then_block, tail);
+                self.emit(Instr::Else);
+                self.block(else_block, tail);
+                self.close();
+                return;
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                self.switch(ty, scrutinee, cases, default, tail);
+                return;
+            }
+            Value::While(condition, body) => {
+                self.open(Instr::Block(BlockType::Empty));
+                self.open(Instr::Loop(BlockType::Empty));
+                self.block(condition, false);
+                self.emit_all(&[
+                    Instr::I32Const(BOOL_TRUE as i32),
+                    Instr::I32Ne,
+                    Instr::BrIf(1),
+                ]);
+                self.block(body, false);
+                self.emit_all(&[Instr::Drop, Instr::Br(0)]);
+                self.close();
+                self.close();
+                self.emit(Instr::I32Const(UNIT as i32));
+            }
+            Value::For(counter, start, direction, end, body) => {
+                let (comparison, step) = match direction {
+                    ForDirection::Up => (Instr::I64LeS, Instr::I64Add),
+                    ForDirection::Down => (Instr::I64GeS, Instr::I64Sub),
+                };
+                let counter = self.local(*counter);
+                self.atom(start);
+                self.atom(end);
+                self.emit(comparison);
+                self.open(Instr::If(BlockType::Empty));
+                self.atom(start);
+                self.emit(Instr::LocalSet(counter));
+                self.open(Instr::Block(BlockType::Empty));
+                self.open(Instr::Loop(BlockType::Empty));
+                self.block(body, false);
+                // The counter is compared with the end before it is
+                // stepped, so it does not overflow past it.
+                self.emit_all(&[Instr::Drop, Instr::LocalGet(counter)]);
+                self.atom(end);
+                self.emit_all(&[
+                    Instr::I64Eq,
+                    Instr::BrIf(1),
+                    Instr::LocalGet(counter),
+                    Instr::I64Const(1),
+                    step,
+                    Instr::LocalSet(counter),
+                    Instr::Br(0),
+                ]);
+                self.close();
+                self.close();
+                self.close();
+                self.emit(Instr::I32Const(UNIT as i32));
+            }
+            Value::Try(body, exception, handler) => {
+                // The calls in the body return to it, since the handler
+                // has to be removed after them.
+                self.open(Instr::Block(ty));
+                self.open(Instr::Block(BlockType::Empty));
+                self.state.handlers.push(self.state.depth);
+                self.block(body, false);
+                self.state.handlers.pop();
+                if tail {
+                    self.ret();
+                } else {
+                    self.emit(Instr::Br(1));
+                }
+                self.close();
+                let exception = self.local(*exception);
+                self.emit_all(&[
+                    Instr::GlobalGet(EXN),
+                    Instr::LocalSet(exception),
+                    Instr::I32Const(0),
+                    Instr::GlobalSet(EXN),
+                ]);
+                self.block(handler, tail);
+                self.close();
+                return;
+            }
+        }
+        if tail {
+            self.ret();
+        }
+    }
+
+    /// Applies the function, calling the direct entry of the closures of
+    /// the arity of the application and the runtime otherwise.
+    fn apply(&mut self, function: &Atom, arguments: &[Atom], tail: bool) {
+        let count = arguments.len();
+        let direct_type = self.direct_type(count);
+        self.atom(function);
+        self.emit_all(&[
+            Instr::I32Load(0),
+            Instr::I32Const(Kind::Closure as i32 | (count as i32) << 8),
+            Instr::I32Eq,
+        ]);
+        self.open(Instr::If(BlockType::Value(ValType::I32)));
+        self.atom(function);
+        for argument in arguments {
+            self.atom(argument);
+        }
+        self.atom(function);
+        self.emit(Instr::I32Load(12));
+        if tail {
+            self.leave();
+            self.emit(Instr::ReturnCallIndirect(direct_type));
+        } else {
+            self.emit(Instr::CallIndirect(direct_type));
+        }
+        self.emit(Instr::Else);
+        let array = self.scratch();
+        self.emit_all(&[
+            Instr::I32Const(4 * count as i32),
+            Instr::Call(Runtime::AllocBytes.index()),
+            Instr::LocalSet(array),
+        ]);
+        for (i, argument) in arguments.iter().enumerate() {
+            self.emit(Instr::LocalGet(array));
+            self.atom(argument);
+            self.emit(Instr::I32Store(4 * i as u32));
+        }
+        self.atom(function);
+        self.emit_all(&[Instr::I32Const(count as i32), Instr::LocalGet(array)]);
+        if tail {
+            self.leave();
+            self.emit(Instr::ReturnCall(Runtime::Apply.index()));
+        } else {
+            self.emit(Instr::Call(Runtime::Apply.index()));
+        }
+        self.close();
+    }
+
+    fn prim(&mut self, op: PrimOp, arguments: &[Atom]) {
+        let int_operands = self.program.atom_repr(&arguments[0]) == Repr::Int;
+        let boolean = |emitter: &mut Self, comparison: &[Instr]| {
+            emitter.emit_all(&[
+                Instr::I32Const(BOOL_TRUE as i32),
+                Instr::I32Const(BOOL_FALSE as i32),
+            ]);
+            emitter.atom(&arguments[0]);
+            emitter.atom(&arguments[1]);
+            emitter.emit_all(comparison);
+            emitter.emit(Instr::Select);
+        };
+        match op {
+            PrimOp::Add | PrimOp::Subtract | PrimOp::Multiply => {
+                self.atom(&arguments[0]);
+                self.atom(&arguments[1]);
+                self.emit(match op {
+                    PrimOp::Add => Instr::I64Add,
+                    PrimOp::Subtract => Instr::I64Sub,
+                    _ => Instr::I64Mul,
+                });
+            }
+            PrimOp::Negate => {
+                self.emit(Instr::I64Const(0));
+                self.atom(&arguments[0]);
+                self.emit(Instr::I64Sub);
+            }
+            PrimOp::Divide => self.divide(&arguments[0], &arguments[1]),
+            PrimOp::Less => boolean(self, &[Instr::I64LtS]),
+            PrimOp::LessEqual => boolean(self, &[Instr::I64LeS]),
+            PrimOp::Greater => boolean(self, &[Instr::I64GtS]),
+            PrimOp::GreaterEqual => boolean(self, &[Instr::I64GeS]),
+            PrimOp::Equal if int_operands => boolean(self, &[Instr::I64Eq]),
+            PrimOp::NotEqual if int_operands => boolean(self, &[Instr::I64Ne]),
+            PrimOp::Equal => boolean(self, &[Instr::Call(Runtime::Equal.index())]),
+            PrimOp::NotEqual => {
+                boolean(self, &[Instr::Call(Runtime::Equal.index()), Instr::I32Eqz])
+            }
+            PrimOp::Deref => {
+                self.atom(&arguments[0]);
+                self.emit(Instr::I32Load(8));
+            }
+            PrimOp::Assign => {
+                self.atom(&arguments[0]);
+                self.atom(&arguments[1]);
+                self.emit_all(&[Instr::I32Store(8), Instr::I32Const(UNIT as i32)]);
+            }
+            PrimOp::Builtin(Builtin::Raise) => {
+                self.atom(&arguments[0]);
+                self.emit(Instr::GlobalSet(EXN));
+                self.raise();
+            }
+            PrimOp::Builtin(builtin) => {
+                for argument in arguments {
+                    self.atom(argument);
+                }
+                self.emit(Instr::Call(Runtime::builtin(builtin).index()));
+                if builtin_may_raise(builtin) {
+                    self.check_exception();
+                }
+            }
+        }
+    }
+
+    /// Divides the integers, raising `DivisionByZero` for the divisor zero.
+    /// The minimum divided by -1 wraps around instead of trapping.
+    fn divide(&mut self, dividend: &Atom, divisor: &Atom) {
+        let negate = |emitter: &mut Self| {
+            emitter.emit(Instr::I64Const(0));
+            emitter.atom(dividend);
+            emitter.emit(Instr::I64Sub);
+        };
+        let divide = |emitter: &mut Self| {
+            emitter.atom(dividend);
+            emitter.atom(divisor);
+            emitter.emit(Instr::I64DivS);
+        };
+        match divisor {
+            Atom::Literal(Literal::Int(0)) => self.raise_builtin(Builtin::DivisionByZero),
+            Atom::Literal(Literal::Int(-1)) => negate(self),
+            Atom::Literal(_) => divide(self),
+            Atom::Var(_) => {
+                self.atom(divisor);
+                self.emit(Instr::I64Eqz);
+                self.open(Instr::If(BlockType::Empty));
+                self.raise_builtin(Builtin::DivisionByZero);
+                self.close();
+                self.atom(divisor);
+                self.emit_all(&[Instr::I64Const(-1), Instr::I64Eq]);
+                self.open(Instr::If(BlockType::Value(ValType::I64)));
+                negate(self);
+                self.emit(Instr::Else);
+                divide(self);
+                self.close();
+            }
+        }
+    }
+
+    fn switch(
+        &mut self,
+        ty: BlockType,
+        scrutinee: &Atom,
+        cases: &[(Test, Block)],
+        default: &Option<Block>,
+        tail: bool,
+    ) {
+        // The cases with their tests, up to the first one which always
+        // matches.
+        let mut arms = Vec::new();
+        for (test, block) in cases {
+            let test = match test {
+                Test::Con(Con::Tuple(_)) | Test::Con(Con::Record(_)) => None,
+                Test::Literal(Literal::Unit) => None,
+                test => Some(test),
+            };
+            let always = test.is_none();
+            arms.push((test, block));
+            if always {
+                break;
+            }
+        }
+        if !matches!(arms.last(), Some((None, _))) {
+            match default {
+                Some(block) => arms.push((None, block)),
+                // The last case matches the values the others do not.
+                None => {
+                    if let Some(last) = arms.last_mut() {
+                        last.0 = None;
+                    }
+                }
+            }
+        }
+
+        if arms.is_empty() {
+            // A switch without cases on a value which cannot exist.
+            self.emit(match ty {
+                BlockType::Value(ValType::I64) => Instr::I64Const(0),
+                _ => Instr::I32Const(0),
+            });
+            if tail {
+                self.ret();
+            }
+            return;
+        }
+        let mut opened = 0;
+        for (test, block) in arms {
+            if let Some(test) = test {
+                self.test(scrutinee, test);
+                self.open(Instr::If(ty));
+                self.block(block, tail);
+                self.emit(Instr::Else);
+                opened += 1;
+            } else {
+                self.block(block, tail);
+            }
+        }
+        for _ in 0..opened {
+            self.close();
+        }
+    }
+
+    /// Pushes whether the value matches the test.
+    fn test(&mut self, scrutinee: &Atom, test: &Test) {
+        self.atom(scrutinee);
+        match test {
+            Test::Con(Con::Array(n)) => {
+                self.emit_all(&[Instr::I32Load(4), Instr::I32Const(*n as i32), Instr::I32Eq])
+            }
+            Test::Con(con @ Con::Nil) | Test::Con(con @ Con::Cons) => self.emit_all(&[
+                Instr::I32Load8U(0),
+                Instr::I32Const(con_kind(*con).0 as i32),
+                Instr::I32Eq,
+            ]),
+            Test::Con(con) => self.emit_all(&[
+                Instr::I32Load(0),
+                Instr::I32Const(8),
+                Instr::I32ShrU,
+                Instr::I32Const(con_kind(*con).1 as i32),
+                Instr::I32Eq,
+            ]),
+            Test::Literal(Literal::Int(n)) => {
+                if self.program.atom_repr(scrutinee) == Repr::Value {
+                    self.emit(Instr::I64Load(8));
+                }
+                self.emit_all(&[Instr::I64Const(*n), Instr::I64Eq]);
+            }
+            Test::Literal(Literal::Bool(b)) => self.emit_all(&[
+                Instr::I32Const(BOOL_TRUE as i32),
+                if *b { Instr::I32Eq } else { Instr::I32Ne },
+            ]),
+            Test::Literal(literal) => {
+                self.atom(&Atom::Literal(literal.clone()));
+                self.emit(Instr::Call(Runtime::Equal.index()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmi::{Caller, Config, Engine, Linker, Module, StackLimits, Store};
+    use wasmparser::{Validator, WasmFeatures};
+
+    use super::*;
+    use crate::test_util;
+
+    /// The standard input and the outputs of the module being run.
+    #[derive(Default)]
+    struct Host {
+        stdin: Vec<u8>,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    }
+
+    /// Validates the module, runs its `main` with the interpreter of
+    /// `wasmi` and returns its standard output and the exception it did not
+    /// handle.
+    fn run_module(bytes: &[u8], stdin: &str) -> (String, Option<String>) {
+        Validator::new_with_features(WasmFeatures {
+            tail_call: true,
+            ..WasmFeatures::default()
+        })
+        .validate_all(bytes)
+        .unwrap();
+
+        let mut config = Config::default();
+        config.wasm_tail_call(true);
+        // The depth of the calls is limited by the module itself.
+        config.set_stack_limits(StackLimits::new(1 << 10, 1 << 24, 1 << 20).unwrap());
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes).unwrap();
+        let host = Host {
+            stdin: stdin.bytes().rev().collect(),
+            ..Host::default()
+        };
+        let mut store = Store::new(&engine, host);
+        let mut linker = Linker::<Host>::new(&engine);
+        linker
+            .func_wrap(
+                "brink",
+                "write",
+                |mut caller: Caller<'_, Host>, fd: i32, address: i32, length: i32| {
+                    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+                    let start = address as usize;
+                    let bytes = memory.data(&caller)[start..start + length as usize].to_vec();
+                    let host = caller.data_mut();
+                    match fd {
+                        1 => host.stdout.extend(bytes),
+                        _ => host.stderr.extend(bytes),
+                    }
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap("brink", "read_byte", |mut caller: Caller<'_, Host>| {
+                caller.data_mut().stdin.pop().map_or(-1, i32::from)
+            })
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let main = instance.get_typed_func::<(), i32>(&store, "main").unwrap();
+        let status = main.call(&mut store, ()).unwrap();
+
+        let host = store.into_data();
+        let stderr = String::from_utf8(host.stderr).unwrap();
+        let exception = stderr.lines().next().map(|line| {
+            line.strip_prefix("uncaught exception ")
+                .unwrap()
+                .to_string()
+        });
+        assert_eq!(status == 2, exception.is_some());
+        (String::from_utf8(host.stdout).unwrap(), exception)
+    }
+
+    /// Compiles the program to WebAssembly at the optimization level.
+    fn compile(source: &str, opt_level: u8) -> Vec<u8> {
+        let checked = test_util::check(source);
+        emit_wasm(&checked.optimize(opt_level), &checked.resolutions)
+    }
+
+    /// Compiles the program at the optimization level to WebAssembly and
+    /// runs it. The exception it did not handle is checked against the one
+    /// the interpreter reports.
+    fn build_and_run(source: &str, opt_level: u8) -> (String, Option<String>) {
+        let (stdout, exception) = run_module(&compile(source, opt_level), "");
+        assert_eq!(test_util::check(source).uncaught_exception(), exception);
+        (stdout, exception)
+    }
+
+    fn run_output(source: &str) -> String {
+        let outputs = [0, 2]
+            .iter()
+            .map(|level| build_and_run(source, *level))
+            .collect::<Vec<_>>();
+        assert_eq!(outputs[0], outputs[1]);
+        let (stdout, exception) = outputs[0].clone();
+        assert_eq!(None, exception);
+        stdout
+    }
+
+    #[test]
+    fn runs_functions_and_closures() {
+        let source = "let rec fibonacci n = if n < 2 then n else fibonacci (n - 1) + fibonacci (n - 2)\n\
+                      infixl 1 |>\nlet (|>) x f = f x\n\
+                      let add x y = x + y\nlet inc = add 1\n\
+                      let make n =\n  let rec count k = if k = 0 then [] else (k + n) :: count (k - 1)\n  count\n\
+                      let twice f x = f (f x)\n\
+                      let a = print_string (string_of_int (fibonacci 20))\n\
+                      let b = print_string (sprintf \" %d %d %d\" (inc 2) (twice inc 5) (2 |> add 10))\n\
+                      let c = print_string (sprintf \" %b\\n\" (make 10 3 = [13; 12; 11]))\n";
+        assert_eq!("6765 3 7 12 true\n", run_output(source));
+    }
+
+    #[test]
+    fn runs_loops_matches_and_exceptions() {
+        let source = "type Shape = Circle int | Square int int | Empty\n\
+                      let area s =\n  match s\n  | Circle r -> 3 * r * r\n  | Square w h -> w * h\n  | Empty -> 0\n\
+                      let name s =\n  match s\n  | \"a\" -> 1\n  | _ -> 2\n\
+                      let total =\n  let sum = ref 0\n  for i = 1 to 4 do\n    sum := !sum + i\n  for i = 2 downto 1 do\n    sum := !sum * i\n  let n = ref 3\n  while !n > 0 do\n    n := !n - 1\n  !sum + !n\n\
+                      exception Invalid int\n\
+                      let r = try 1 / 0 with | DivisionByZero -> -1\n\
+                      let s = try raise (Invalid 4) with | Invalid n -> n\n\
+                      let rec deep n = 1 + deep (n + 1)\n\
+                      let o = try deep 0 with | StackOverflow -> 0\n\
+                      let p = print_string (sprintf \"%d %d %d %d %d %d\" (area (Circle 2) + area (Square 2 3) + area Empty) (name \"a\" + name \"b\") total r s o)\n";
+        assert_eq!("18 3 20 -1 4 0", run_output(source));
+    }
+
+    /// Runs loops of tail calls deeper than the limit of the nested calls.
+    #[test]
+    fn runs_tail_calls_in_constant_space() {
+        let source = "let rec count n acc = if n = 0 then acc else count (n - 1) (acc + 1)\n\
+                      let rec loop f n = if n = 0 then f 0 else (fun k -> loop f k) (n - 1)\n\
+                      let p = print_string (sprintf \"%d %d\" (count 100000 0) (loop (fun x -> x + 1) 100000))\n";
+        for level in [0, 2].iter() {
+            let output = run_module(&compile(source, *level), "");
+            assert_eq!(("100000 1".to_string(), None), output);
+        }
+    }
+
+    /// Runs the recursive groups deeper than the limit of the nested calls.
+    #[test]
+    fn runs_mutually_recursive_groups_in_constant_space() {
+        let source = test_util::printing(&test_util::mutually_recursive_groups(100000), "r");
+        for level in [0, 2].iter() {
+            let output = run_module(&compile(&source, *level), "");
+            assert_eq!(("true true 100000".to_string(), None), output);
+        }
+    }
+
+    #[test]
+    fn reports_uncaught_exceptions_like_the_interpreter() {
+        let sources = [
+            "exception Invalid int\nlet check x = if x < 0 then raise (Invalid x) else x\nlet y = check (0 - 2)\n",
+            "type P = { x: int; y: string }\nexception Bad P\nlet y = raise (Bad { x = 1; y = \"a\" })\n",
+            "let f x =\n  match x\n  | 0 -> 1\nlet y = f 1\n",
+            "let y = int_of_string \"1x\"\n",
+            "let y = compare (fun x -> x) (fun x -> x)\n",
+        ];
+        for source in sources.iter() {
+            for level in [0, 2].iter() {
+                assert!(build_and_run(source, *level).1.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn reads_the_standard_input() {
+        let source = "let a = read_line ()\nlet b = read_line ()\n\
+                      let p = print_string (sprintf \"%s|%s|%d\" b a (string_length a))\n\
+                      let c = read_line ()\n";
+        let output = run_module(&compile(source, 2), "first\nsecond\n");
+        assert_eq!(
+            ("second|first|5".to_string(), Some("EndOfFile".to_string())),
+            output
+        );
+    }
+
+    /// Runs the tests of the standard library modules compiled to
+    /// WebAssembly, printing the `result` they bind. The files cannot be
+    /// read or written, so the tests of `io.bk` are not run.
+    #[test]
+    fn runs_stdlib_tests() {
+        for (name, source) in test_util::STDLIB_TESTS.iter() {
+            let source = test_util::printing(source, "result");
+            assert_eq!("ok", run_output(&source), "stdlib test {}", name);
+        }
+    }
+}
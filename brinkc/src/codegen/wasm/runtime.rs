@@ -0,0 +1,1706 @@
+//! The runtime of the WebAssembly modules, assembled into every module
+//! before the compiled functions: the allocation, the generic
+//! application, the structural equality and ordering, the built-in
+//! functions and the report of the uncaught exceptions. It follows
+//! `runtime/runtime.c`, with the values laid out as described in the
+//! parent module.
+
+use crate::{
+    codegen::{con_kind, header, Kind},
+    ir::Con,
+    resolve::Builtin,
+};
+
+use super::{
+    encode::{
+        BlockType::Empty,
+        Function, Instr,
+        Instr::*,
+        Module,
+        ValType::{self, I32, I64},
+    },
+    Data, BUILTIN_TAG, EXN, HEAP, IMPORTS, READ_BYTE, UNIT, WRITE,
+};
+
+/// The functions of the runtime, numbered after the imported ones in this
+/// order.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Runtime {
+    /// Grows the memory to hold the bytes up to the address.
+    Reserve,
+    /// Allocates the bytes on the heap, aligned to eight bytes.
+    AllocBytes,
+    /// Allocates the object of the header, whose fields are left to be
+    /// initialized.
+    Alloc,
+    Box,
+    /// Allocates the string of the length, whose bytes are left to be
+    /// initialized.
+    NewString,
+    /// Raises the built-in exception of the tag, with the string as its
+    /// argument unless it is zero.
+    RaiseBuiltin,
+    Apply,
+    Equal,
+    CompareBytes,
+    /// Orders the values, or returns `INCOMPARABLE` if they contain a
+    /// function.
+    CompareValues,
+    CharBoundary,
+    FormatInt,
+    /// Writes the bytes of the string to the file descriptor.
+    WriteString,
+    /// Writes the value to the standard error, the way the interpreter
+    /// shows it.
+    WriteValue,
+    ReportUncaught,
+    Ref,
+    Compare,
+    StringLength,
+    StringGet,
+    StringSub,
+    StringConcat,
+    StringConcatList,
+    StringOfCharCode,
+    StringOfInt,
+    IntOfString,
+    PrintString,
+    EprintString,
+    ReadLine,
+    ReadFile,
+    WriteFile,
+}
+
+const FUNCTIONS: &[Runtime] = &[
+    Runtime::Reserve,
+    Runtime::AllocBytes,
+    Runtime::Alloc,
+    Runtime::Box,
+    Runtime::NewString,
+    Runtime::RaiseBuiltin,
+    Runtime::Apply,
+    Runtime::Equal,
+    Runtime::CompareBytes,
+    Runtime::CompareValues,
+    Runtime::CharBoundary,
+    Runtime::FormatInt,
+    Runtime::WriteString,
+    Runtime::WriteValue,
+    Runtime::ReportUncaught,
+    Runtime::Ref,
+    Runtime::Compare,
+    Runtime::StringLength,
+    Runtime::StringGet,
+    Runtime::StringSub,
+    Runtime::StringConcat,
+    Runtime::StringConcatList,
+    Runtime::StringOfCharCode,
+    Runtime::StringOfInt,
+    Runtime::IntOfString,
+    Runtime::PrintString,
+    Runtime::EprintString,
+    Runtime::ReadLine,
+    Runtime::ReadFile,
+    Runtime::WriteFile,
+];
+
+/// The number of the runtime functions, after which the compiled ones are
+/// numbered.
+pub const RUNTIME_FUNCTIONS: u32 = FUNCTIONS.len() as u32;
+
+/// The result of `CompareValues` for the values containing functions.
+const INCOMPARABLE: i32 = 2;
+
+impl Runtime {
+    pub fn index(self) -> u32 {
+        IMPORTS + self as u32
+    }
+
+    /// Gets the runtime function implementing the builtin.
+    pub fn builtin(builtin: Builtin) -> Runtime {
+        match builtin {
+            Builtin::Ref => Runtime::Ref,
+            Builtin::Compare => Runtime::Compare,
+            Builtin::StringLength => Runtime::StringLength,
+            Builtin::StringGet => Runtime::StringGet,
+            Builtin::StringSub => Runtime::StringSub,
+            Builtin::StringConcat => Runtime::StringConcat,
+            Builtin::StringConcatList => Runtime::StringConcatList,
+            Builtin::StringOfCharCode => Runtime::StringOfCharCode,
+            Builtin::StringOfInt => Runtime::StringOfInt,
+            Builtin::IntOfString => Runtime::IntOfString,
+            Builtin::PrintString => Runtime::PrintString,
+            Builtin::EprintString => Runtime::EprintString,
+            Builtin::ReadLine => Runtime::ReadLine,
+            Builtin::ReadFile => Runtime::ReadFile,
+            Builtin::WriteFile => Runtime::WriteFile,
+            _ => unreachable!("`{}` has no runtime function", builtin.name()),
+        }
+    }
+
+    fn signature(self) -> (Vec<ValType>, Vec<ValType>) {
+        match self {
+            Runtime::Reserve => (vec![I32], vec![]),
+            Runtime::Alloc | Runtime::Box | Runtime::FormatInt => (vec![I64], vec![I32]),
+            Runtime::WriteString | Runtime::WriteValue => (vec![I32, I32], vec![]),
+            Runtime::ReportUncaught => (vec![], vec![]),
+            Runtime::AllocBytes
+            | Runtime::NewString
+            | Runtime::Ref
+            | Runtime::StringLength
+            | Runtime::StringConcatList
+            | Runtime::StringOfCharCode
+            | Runtime::StringOfInt
+            | Runtime::IntOfString
+            | Runtime::PrintString
+            | Runtime::EprintString
+            | Runtime::ReadLine
+            | Runtime::ReadFile => (vec![I32], vec![I32]),
+            Runtime::Apply | Runtime::CompareBytes | Runtime::StringSub => {
+                (vec![I32, I32, I32], vec![I32])
+            }
+            Runtime::RaiseBuiltin
+            | Runtime::Equal
+            | Runtime::CompareValues
+            | Runtime::CharBoundary
+            | Runtime::Compare
+            | Runtime::StringGet
+            | Runtime::StringConcat
+            | Runtime::WriteFile => (vec![I32, I32], vec![I32]),
+        }
+    }
+}
+
+/// Adds the runtime functions to the module. The static strings they use
+/// are added to the data, and `tags` is the address of the table of the
+/// names of the tags, of the `defs` definitions of the program followed by
+/// the builtins.
+pub fn define(module: &mut Module, data: &mut Data, tags: u32, defs: u32) {
+    let entry_type = module.func_type(vec![I32, I32], vec![I32]);
+    for function in FUNCTIONS {
+        let (params, results) = function.signature();
+        let mut builder = Builder {
+            data: &mut *data,
+            params: params.len(),
+            locals: Vec::new(),
+            code: Vec::new(),
+        };
+        match function {
+            Runtime::Reserve => reserve(&mut builder),
+            Runtime::AllocBytes => alloc_bytes(&mut builder),
+            Runtime::Alloc => alloc(&mut builder),
+            Runtime::Box => box_int(&mut builder),
+            Runtime::NewString => new_string(&mut builder),
+            Runtime::RaiseBuiltin => raise_builtin(&mut builder),
+            Runtime::Apply => apply(&mut builder, entry_type),
+            Runtime::Equal => equal(&mut builder),
+            Runtime::CompareBytes => compare_bytes(&mut builder),
+            Runtime::CompareValues => compare_values(&mut builder),
+            Runtime::CharBoundary => char_boundary(&mut builder),
+            Runtime::FormatInt => format_int(&mut builder),
+            Runtime::WriteString => write_string(&mut builder),
+            Runtime::WriteValue => write_value(&mut builder, tags, defs),
+            Runtime::ReportUncaught => report_uncaught(&mut builder),
+            Runtime::Ref => reference(&mut builder),
+            Runtime::Compare => compare(&mut builder),
+            Runtime::StringLength => string_length(&mut builder),
+            Runtime::StringGet => string_get(&mut builder),
+            Runtime::StringSub => string_sub(&mut builder),
+            Runtime::StringConcat => string_concat(&mut builder),
+            Runtime::StringConcatList => string_concat_list(&mut builder),
+            Runtime::StringOfCharCode => string_of_char_code(&mut builder),
+            Runtime::StringOfInt => string_of_int(&mut builder),
+            Runtime::IntOfString => int_of_string(&mut builder),
+            Runtime::PrintString => print_string(&mut builder, 1),
+            Runtime::EprintString => print_string(&mut builder, 2),
+            Runtime::ReadLine => read_line(&mut builder),
+            Runtime::ReadFile | Runtime::WriteFile => {
+                let builtin = match function {
+                    Runtime::ReadFile => Builtin::ReadFile,
+                    _ => Builtin::WriteFile,
+                };
+                let message = format!("{}: not supported on wasm32", builtin.name());
+                builder.raise(Builtin::Failure, Some(&message));
+            }
+        }
+        let Builder { locals, code, .. } = builder;
+        let ty = module.func_type(params, results);
+        module.functions.push(Function { ty, locals, code });
+    }
+}
+
+struct Builder<'a> {
+    data: &'a mut Data,
+    params: usize,
+    locals: Vec<ValType>,
+    code: Vec<Instr>,
+}
+
+impl Builder<'_> {
+    fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        (self.params + self.locals.len() - 1) as u32
+    }
+
+    fn emit(&mut self, instrs: &[Instr]) {
+        self.code.extend_from_slice(instrs);
+    }
+
+    fn call(&mut self, function: Runtime) {
+        self.emit(&[Call(function.index())]);
+    }
+
+    /// Writes the text to the standard error.
+    fn text(&mut self, text: &str) {
+        let string = self.data.string(text.as_bytes());
+        self.emit(&[I32Const(2), I32Const(string as i32)]);
+        self.call(Runtime::WriteString);
+    }
+
+    /// Returns the built-in exception raised, with the message.
+    fn raise(&mut self, exception: Builtin, message: Option<&str>) {
+        let message = message.map_or(0, |message| self.data.string(message.as_bytes()));
+        let (_, tag) = con_kind(Con::Exception(exception));
+        self.emit(&[
+            I32Const(tag as i32),
+            I32Const(message as i32),
+            ReturnCall(Runtime::RaiseBuiltin.index()),
+        ]);
+    }
+
+    /// Pushes the kind of the object in the local.
+    fn kind(&mut self, object: u32) {
+        self.emit(&[LocalGet(object), I32Load8U(0)]);
+    }
+
+    /// Pushes whether the kind of the object in the local is the kind.
+    fn is_kind(&mut self, object: u32, kind: Kind) {
+        self.kind(object);
+        self.emit(&[I32Const(kind as i32), I32Eq]);
+    }
+
+    fn tag(&mut self, object: u32) {
+        self.emit(&[LocalGet(object), I32Load(0), I32Const(8), I32ShrU]);
+    }
+
+    fn size(&mut self, object: u32) {
+        self.emit(&[LocalGet(object), I32Load(4)]);
+    }
+
+    /// Pushes the address of the field of the object at the index in the
+    /// local, minus the offset of the fields.
+    fn field_address(&mut self, object: u32, index: u32) {
+        self.emit(&[
+            LocalGet(object),
+            LocalGet(index),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+        ]);
+    }
+
+    /// Returns the constant if the condition on the stack holds.
+    fn return_if(&mut self, value: i32) {
+        self.emit(&[If(Empty), I32Const(value), Return, End]);
+    }
+
+    /// Pushes the order of the two unsigned integers the instructions push:
+    /// -1, 0 or 1.
+    fn order_unsigned(&mut self, a: &[Instr], b: &[Instr]) {
+        self.emit(a);
+        self.emit(b);
+        self.emit(&[I32GtU]);
+        self.emit(a);
+        self.emit(b);
+        self.emit(&[I32LtU, I32Sub]);
+    }
+}
+
+fn reserve(b: &mut Builder) {
+    let end = 0;
+    b.emit(&[
+        LocalGet(end),
+        MemorySize,
+        I32Const(16),
+        I32Shl,
+        I32GtU,
+        If(Empty),
+        // The missing bytes, rounded up to the pages.
+        LocalGet(end),
+        MemorySize,
+        I32Const(16),
+        I32Shl,
+        I32Sub,
+        I32Const(0xffff),
+        I32Add,
+        I32Const(16),
+        I32ShrU,
+        MemoryGrow,
+        I32Const(-1),
+        I32Eq,
+        If(Empty),
+        Unreachable,
+        End,
+        End,
+    ]);
+}
+
+fn alloc_bytes(b: &mut Builder) {
+    let size = 0;
+    let object = b.local(I32);
+    let end = b.local(I32);
+    b.emit(&[
+        GlobalGet(HEAP),
+        LocalTee(object),
+        LocalGet(size),
+        I32Add,
+        I32Const(7),
+        I32Add,
+        I32Const(-8),
+        I32And,
+        LocalTee(end),
+    ]);
+    b.call(Runtime::Reserve);
+    b.emit(&[LocalGet(end), GlobalSet(HEAP), LocalGet(object)]);
+}
+
+fn alloc(b: &mut Builder) {
+    let header = 0;
+    let kind = b.local(I32);
+    let size = b.local(I32);
+    let object = b.local(I32);
+    b.emit(&[
+        LocalGet(header),
+        I32WrapI64,
+        I32Const(0xff),
+        I32And,
+        LocalSet(kind),
+        LocalGet(header),
+        I64Const(32),
+        I64ShrU,
+        I32WrapI64,
+        LocalTee(size),
+        // The fields of four bytes, the bytes of the strings or the integer
+        // of eight bytes.
+        LocalGet(size),
+        I32Const(2),
+        I32Shl,
+        LocalGet(kind),
+        I32Const(Kind::String as i32),
+        I32Eq,
+        Select,
+        I32Const(8),
+        LocalGet(kind),
+        I32Const(Kind::Int as i32),
+        I32Ne,
+        Select,
+        I32Const(8),
+        I32Add,
+    ]);
+    b.call(Runtime::AllocBytes);
+    b.emit(&[
+        LocalTee(object),
+        LocalGet(header),
+        I64Store(0),
+        LocalGet(object),
+    ]);
+}
+
+fn box_int(b: &mut Builder) {
+    let n = 0;
+    let object = b.local(I32);
+    b.emit(&[I64Const(header(Kind::Int, 0, 1) as i64)]);
+    b.call(Runtime::Alloc);
+    b.emit(&[LocalTee(object), LocalGet(n), I64Store(8), LocalGet(object)]);
+}
+
+fn new_string(b: &mut Builder) {
+    let length = 0;
+    b.emit(&[
+        LocalGet(length),
+        I64ExtendI32U,
+        I64Const(32),
+        I64Shl,
+        I64Const(Kind::String as i64),
+        I64Or,
+    ]);
+    b.call(Runtime::Alloc);
+}
+
+fn raise_builtin(b: &mut Builder) {
+    let (tag, message) = (0, 1);
+    let exception = b.local(I32);
+    b.emit(&[
+        LocalGet(tag),
+        I32Const(8),
+        I32Shl,
+        I32Const(Kind::Variant as i32),
+        I32Or,
+        I64ExtendI32U,
+        I64Const(1 << 32),
+        I64Const(0),
+        LocalGet(message),
+        Select,
+        I64Or,
+    ]);
+    b.call(Runtime::Alloc);
+    b.emit(&[
+        LocalSet(exception),
+        LocalGet(message),
+        If(Empty),
+        LocalGet(exception),
+        LocalGet(message),
+        I32Store(8),
+        End,
+        LocalGet(exception),
+        GlobalSet(EXN),
+        I32Const(0),
+    ]);
+}
+
+/// Applies the function to the arguments in the array one by one. The
+/// compiled code calls the closures of the arity of the application
+/// directly, and falls back to this for the partial applications and the
+/// applications to more arguments than the function takes.
+fn apply(b: &mut Builder, entry_type: u32) {
+    let (function, count, arguments) = (0, 1, 2);
+    let arity = b.local(I32);
+    let given = b.local(I32);
+    let all = b.local(I32);
+    let partial = b.local(I32);
+    b.emit(&[Loop(Empty)]);
+    // A partial application holds the closure and the arguments it was
+    // given.
+    b.is_kind(function, Kind::Partial);
+    b.emit(&[If(Empty)]);
+    b.size(function);
+    b.emit(&[
+        I32Const(1),
+        I32Sub,
+        LocalTee(given),
+        LocalGet(count),
+        I32Add,
+        I32Const(2),
+        I32Shl,
+    ]);
+    b.call(Runtime::AllocBytes);
+    b.emit(&[
+        LocalTee(all),
+        LocalGet(function),
+        I32Const(12),
+        I32Add,
+        LocalGet(given),
+        I32Const(2),
+        I32Shl,
+        MemoryCopy,
+        LocalGet(all),
+        LocalGet(given),
+        I32Const(2),
+        I32Shl,
+        I32Add,
+        LocalGet(arguments),
+        LocalGet(count),
+        I32Const(2),
+        I32Shl,
+        MemoryCopy,
+        LocalGet(all),
+        LocalSet(arguments),
+        LocalGet(given),
+        LocalGet(count),
+        I32Add,
+        LocalSet(count),
+        LocalGet(function),
+        I32Load(8),
+        LocalSet(function),
+        Br(1),
+        End,
+    ]);
+    b.tag(function);
+    b.emit(&[
+        LocalTee(arity),
+        LocalGet(count),
+        I32Eq,
+        If(Empty),
+        LocalGet(function),
+        LocalGet(arguments),
+        LocalGet(function),
+        I32Load(8),
+        ReturnCallIndirect(entry_type),
+        End,
+        LocalGet(count),
+        LocalGet(arity),
+        I32LtU,
+        If(Empty),
+        // The missing arguments as the tag, and the closure before the
+        // given ones.
+        LocalGet(arity),
+        LocalGet(count),
+        I32Sub,
+        I32Const(8),
+        I32Shl,
+        I32Const(Kind::Partial as i32),
+        I32Or,
+        I64ExtendI32U,
+        LocalGet(count),
+        I32Const(1),
+        I32Add,
+        I64ExtendI32U,
+        I64Const(32),
+        I64Shl,
+        I64Or,
+    ]);
+    b.call(Runtime::Alloc);
+    b.emit(&[
+        LocalTee(partial),
+        LocalGet(function),
+        I32Store(8),
+        LocalGet(partial),
+        I32Const(12),
+        I32Add,
+        LocalGet(arguments),
+        LocalGet(count),
+        I32Const(2),
+        I32Shl,
+        MemoryCopy,
+        LocalGet(partial),
+        Return,
+        End,
+        // The function returned by the application of the function to
+        // its arguments is applied to the rest.
+        LocalGet(function),
+        LocalGet(arguments),
+        LocalGet(function),
+        I32Load(8),
+        CallIndirect(entry_type),
+        LocalSet(function),
+        GlobalGet(EXN),
+    ]);
+    b.return_if(0);
+    b.emit(&[
+        LocalGet(arguments),
+        LocalGet(arity),
+        I32Const(2),
+        I32Shl,
+        I32Add,
+        LocalSet(arguments),
+        LocalGet(count),
+        LocalGet(arity),
+        I32Sub,
+        LocalSet(count),
+        Br(0),
+        End,
+        Unreachable,
+    ]);
+}
+
+/// Compares the values structurally. The functions are only equal to
+/// themselves, and the references are equal if their contents are.
+fn equal(b: &mut Builder) {
+    let (a, other) = (0, 1);
+    let kind = b.local(I32);
+    let size = b.local(I32);
+    let i = b.local(I32);
+    b.emit(&[Loop(Empty), LocalGet(a), LocalGet(other), I32Eq]);
+    b.return_if(1);
+    b.kind(a);
+    b.emit(&[LocalTee(kind)]);
+    b.kind(other);
+    b.emit(&[I32Ne]);
+    b.return_if(0);
+    b.emit(&[
+        LocalGet(kind),
+        I32Const(Kind::Int as i32),
+        I32Eq,
+        If(Empty),
+        LocalGet(a),
+        I64Load(8),
+        LocalGet(other),
+        I64Load(8),
+        I64Eq,
+        Return,
+        End,
+        LocalGet(kind),
+        I32Const(Kind::String as i32),
+        I32Eq,
+        If(Empty),
+    ]);
+    b.size(a);
+    b.size(other);
+    b.emit(&[I32Ne]);
+    b.return_if(0);
+    b.emit(&[
+        LocalGet(a),
+        I32Const(8),
+        I32Add,
+        LocalGet(other),
+        I32Const(8),
+        I32Add,
+    ]);
+    b.size(a);
+    b.call(Runtime::CompareBytes);
+    b.emit(&[
+        I32Eqz,
+        Return,
+        End,
+        LocalGet(kind),
+        I32Const(Kind::Unit as i32),
+        I32Eq,
+        LocalGet(kind),
+        I32Const(Kind::Nil as i32),
+        I32Eq,
+        I32Or,
+    ]);
+    b.return_if(1);
+    b.emit(&[
+        LocalGet(kind),
+        I32Const(Kind::Ref as i32),
+        I32Eq,
+        If(Empty),
+        LocalGet(a),
+        I32Load(8),
+        LocalSet(a),
+        LocalGet(other),
+        I32Load(8),
+        LocalSet(other),
+        Br(1),
+        End,
+        LocalGet(kind),
+        I32Const(Kind::Closure as i32),
+        I32Eq,
+        LocalGet(kind),
+        I32Const(Kind::Partial as i32),
+        I32Eq,
+        I32Or,
+    ]);
+    b.return_if(0);
+    // The blocks of fields, the last of which is compared in the loop so
+    // the lists are not compared recursively.
+    b.emit(&[LocalGet(a), I64Load(0), LocalGet(other), I64Load(0), I64Ne]);
+    b.return_if(0);
+    b.size(a);
+    b.emit(&[LocalTee(size), I32Eqz]);
+    b.return_if(1);
+    b.emit(&[
+        I32Const(0),
+        LocalSet(i),
+        Block(Empty),
+        Loop(Empty),
+        LocalGet(i),
+        I32Const(1),
+        I32Add,
+        LocalGet(size),
+        I32GeU,
+        BrIf(1),
+    ]);
+    b.field_address(a, i);
+    b.emit(&[I32Load(8)]);
+    b.field_address(other, i);
+    b.emit(&[I32Load(8)]);
+    b.call(Runtime::Equal);
+    b.emit(&[I32Eqz]);
+    b.return_if(0);
+    b.emit(&[
+        LocalGet(i),
+        I32Const(1),
+        I32Add,
+        LocalSet(i),
+        Br(0),
+        End,
+        End,
+    ]);
+    b.field_address(a, size);
+    b.emit(&[I32Load(4), LocalSet(a)]);
+    b.field_address(other, size);
+    b.emit(&[I32Load(4), LocalSet(other), Br(0), End, Unreachable]);
+}
+
+/// Orders the bytes at the two addresses, returning -1, 0 or 1.
+fn compare_bytes(b: &mut Builder) {
+    let (a, other, count) = (0, 1, 2);
+    let i = b.local(I32);
+    let x = b.local(I32);
+    let y = b.local(I32);
+    b.emit(&[
+        Block(Empty),
+        Loop(Empty),
+        LocalGet(i),
+        LocalGet(count),
+        I32GeU,
+        BrIf(1),
+        LocalGet(a),
+        LocalGet(i),
+        I32Add,
+        I32Load8U(0),
+        LocalTee(x),
+        LocalGet(other),
+        LocalGet(i),
+        I32Add,
+        I32Load8U(0),
+        LocalTee(y),
+        I32Ne,
+        If(Empty),
+        I32Const(-1),
+        I32Const(1),
+        LocalGet(x),
+        LocalGet(y),
+        I32LtU,
+        Select,
+        Return,
+        End,
+        LocalGet(i),
+        I32Const(1),
+        I32Add,
+        LocalSet(i),
+        Br(0),
+        End,
+        End,
+        I32Const(0),
+    ]);
+}
+
+/// Orders the values structurally, the way the interpreter does, or
+/// returns `INCOMPARABLE` if they contain a function.
+fn compare_values(b: &mut Builder) {
+    let (a, other) = (0, 1);
+    let kind = b.local(I32);
+    let size = b.local(I32);
+    let i = b.local(I32);
+    let ordering = b.local(I32);
+    let is_kind = |b: &mut Builder, kind_local: u32, kind: Kind| {
+        b.emit(&[LocalGet(kind_local), I32Const(kind as i32), I32Eq]);
+    };
+    b.emit(&[Loop(Empty)]);
+    for value in [a, other].iter() {
+        b.is_kind(*value, Kind::Closure);
+        b.is_kind(*value, Kind::Partial);
+        b.emit(&[I32Or]);
+    }
+    b.emit(&[I32Or]);
+    b.return_if(INCOMPARABLE);
+    b.kind(a);
+    b.emit(&[LocalTee(kind)]);
+    b.kind(other);
+    // Only the lists have values of different kinds.
+    b.emit(&[I32Ne, If(Empty), I32Const(-1), I32Const(1)]);
+    is_kind(b, kind, Kind::Nil);
+    b.emit(&[Select, Return, End]);
+    is_kind(b, kind, Kind::Int);
+    b.emit(&[
+        If(Empty),
+        LocalGet(a),
+        I64Load(8),
+        LocalGet(other),
+        I64Load(8),
+        I64GtS,
+        LocalGet(a),
+        I64Load(8),
+        LocalGet(other),
+        I64Load(8),
+        I64LtS,
+        I32Sub,
+        Return,
+        End,
+    ]);
+    is_kind(b, kind, Kind::Bool);
+    b.emit(&[If(Empty)]);
+    b.order_unsigned(
+        &[LocalGet(a), I32Load(0), I32Const(8), I32ShrU],
+        &[LocalGet(other), I32Load(0), I32Const(8), I32ShrU],
+    );
+    b.emit(&[Return, End]);
+    is_kind(b, kind, Kind::String);
+    b.emit(&[
+        If(Empty),
+        LocalGet(a),
+        I32Const(8),
+        I32Add,
+        LocalGet(other),
+        I32Const(8),
+        I32Add,
+    ]);
+    b.size(a);
+    b.size(other);
+    b.size(a);
+    b.size(other);
+    b.emit(&[I32LtU, Select]);
+    b.call(Runtime::CompareBytes);
+    b.emit(&[
+        LocalTee(ordering),
+        If(Empty),
+        LocalGet(ordering),
+        Return,
+        End,
+    ]);
+    b.order_unsigned(&[LocalGet(a), I32Load(4)], &[LocalGet(other), I32Load(4)]);
+    b.emit(&[Return, End]);
+    is_kind(b, kind, Kind::Unit);
+    is_kind(b, kind, Kind::Nil);
+    b.emit(&[I32Or]);
+    b.return_if(0);
+    is_kind(b, kind, Kind::Ref);
+    b.emit(&[
+        If(Empty),
+        LocalGet(a),
+        I32Load(8),
+        LocalSet(a),
+        LocalGet(other),
+        I32Load(8),
+        LocalSet(other),
+        Br(1),
+        End,
+    ]);
+    is_kind(b, kind, Kind::Cons);
+    b.emit(&[
+        If(Empty),
+        LocalGet(a),
+        I32Load(8),
+        LocalGet(other),
+        I32Load(8),
+    ]);
+    b.call(Runtime::CompareValues);
+    b.emit(&[
+        LocalTee(ordering),
+        If(Empty),
+        LocalGet(ordering),
+        Return,
+        End,
+        LocalGet(a),
+        I32Load(12),
+        LocalSet(a),
+        LocalGet(other),
+        I32Load(12),
+        LocalSet(other),
+        Br(1),
+        End,
+    ]);
+    is_kind(b, kind, Kind::Variant);
+    b.emit(&[If(Empty)]);
+    b.tag(a);
+    b.tag(other);
+    b.emit(&[I32Ne, If(Empty), I32Const(-1), I32Const(1)]);
+    b.tag(a);
+    b.tag(other);
+    b.emit(&[I32LtU, Select, Return, End, End]);
+    b.size(a);
+    b.size(other);
+    b.size(a);
+    b.size(other);
+    b.emit(&[
+        I32LtU,
+        Select,
+        LocalSet(size),
+        Block(Empty),
+        Loop(Empty),
+        LocalGet(i),
+        LocalGet(size),
+        I32GeU,
+        BrIf(1),
+    ]);
+    b.field_address(a, i);
+    b.emit(&[I32Load(8)]);
+    b.field_address(other, i);
+    b.emit(&[I32Load(8)]);
+    b.call(Runtime::CompareValues);
+    b.emit(&[
+        LocalTee(ordering),
+        If(Empty),
+        LocalGet(ordering),
+        Return,
+        End,
+        LocalGet(i),
+        I32Const(1),
+        I32Add,
+        LocalSet(i),
+        Br(0),
+        End,
+        End,
+    ]);
+    b.order_unsigned(&[LocalGet(a), I32Load(4)], &[LocalGet(other), I32Load(4)]);
+    b.emit(&[Return, End, Unreachable]);
+}
+
+/// Checks whether the byte index, at most the length of the string, is at
+/// the start of a character.
+fn char_boundary(b: &mut Builder) {
+    let (string, index) = (0, 1);
+    b.emit(&[LocalGet(index)]);
+    b.size(string);
+    b.emit(&[I32Eq]);
+    b.return_if(1);
+    b.emit(&[
+        LocalGet(string),
+        LocalGet(index),
+        I32Add,
+        I32Load8U(8),
+        I32Const(0xc0),
+        I32And,
+        I32Const(0x80),
+        I32Ne,
+    ]);
+}
+
+/// Formats the integer in decimal. The digits are taken from the negated
+/// value, whose range includes the minimum.
+fn format_int(b: &mut Builder) {
+    let n = 0;
+    let negated = b.local(I64);
+    let length = b.local(I32);
+    let string = b.local(I32);
+    let set_negated = |b: &mut Builder| {
+        b.emit(&[
+            I64Const(0),
+            LocalGet(n),
+            I64Sub,
+            LocalGet(n),
+            LocalGet(n),
+            I64Const(0),
+            I64GeS,
+            Select,
+            LocalSet(negated),
+        ]);
+    };
+    set_negated(b);
+    b.emit(&[
+        LocalGet(n),
+        I64Const(0),
+        I64LtS,
+        LocalSet(length),
+        Loop(Empty),
+        LocalGet(length),
+        I32Const(1),
+        I32Add,
+        LocalSet(length),
+        LocalGet(negated),
+        I64Const(10),
+        I64DivS,
+        LocalTee(negated),
+        I64Eqz,
+        I32Eqz,
+        BrIf(0),
+        End,
+        LocalGet(length),
+    ]);
+    b.call(Runtime::NewString);
+    b.emit(&[LocalSet(string)]);
+    set_negated(b);
+    b.emit(&[
+        Loop(Empty),
+        LocalGet(length),
+        I32Const(1),
+        I32Sub,
+        LocalTee(length),
+        LocalGet(string),
+        I32Add,
+        I32Const(b'0' as i32),
+        LocalGet(negated),
+        I64Const(10),
+        I64RemS,
+        I32WrapI64,
+        I32Sub,
+        I32Store8(8),
+        LocalGet(negated),
+        I64Const(10),
+        I64DivS,
+        LocalTee(negated),
+        I64Eqz,
+        I32Eqz,
+        BrIf(0),
+        End,
+        LocalGet(n),
+        I64Const(0),
+        I64LtS,
+        If(Empty),
+        LocalGet(string),
+        I32Const(b'-' as i32),
+        I32Store8(8),
+        End,
+        LocalGet(string),
+    ]);
+}
+
+fn write_string(b: &mut Builder) {
+    let (fd, string) = (0, 1);
+    b.emit(&[LocalGet(fd), LocalGet(string), I32Const(8), I32Add]);
+    b.size(string);
+    b.emit(&[Call(WRITE)]);
+}
+
+/// Formats the value the way it would be written in the source code, like
+/// the interpreter does.
+fn write_value(b: &mut Builder, tags: u32, defs: u32) {
+    let (value, nested) = (0, 1);
+    let kind = b.local(I32);
+    let i = b.local(I32);
+    let size = b.local(I32);
+    let names = b.local(I32);
+    let cell = b.local(I32);
+    let byte = b.local(I32);
+    let when_kind = |b: &mut Builder, kinds: &[Kind]| {
+        for (i, kind_) in kinds.iter().enumerate() {
+            b.emit(&[LocalGet(kind), I32Const(*kind_ as i32), I32Eq]);
+            if i > 0 {
+                b.emit(&[I32Or]);
+            }
+        }
+        b.emit(&[If(Empty)]);
+    };
+    // Writes the fields from the first one, each written by the function.
+    let fields = |b: &mut Builder, separator: &str, each: &dyn Fn(&mut Builder)| {
+        b.size(value);
+        b.emit(&[
+            LocalSet(size),
+            I32Const(0),
+            LocalSet(i),
+            Block(Empty),
+            Loop(Empty),
+            LocalGet(i),
+            LocalGet(size),
+            I32GeU,
+            BrIf(1),
+        ]);
+        if !separator.is_empty() {
+            b.emit(&[LocalGet(i), If(Empty)]);
+            b.text(separator);
+            b.emit(&[End]);
+        }
+        each(b);
+        b.emit(&[
+            LocalGet(i),
+            I32Const(1),
+            I32Add,
+            LocalSet(i),
+            Br(0),
+            End,
+            End,
+        ]);
+    };
+    let field = |b: &mut Builder, nested: i32| {
+        b.field_address(value, i);
+        b.emit(&[I32Load(8), I32Const(nested)]);
+        b.call(Runtime::WriteValue);
+    };
+
+    b.kind(value);
+    b.emit(&[LocalSet(kind)]);
+
+    when_kind(b, &[Kind::Int]);
+    b.emit(&[
+        LocalGet(nested),
+        LocalGet(value),
+        I64Load(8),
+        I64Const(0),
+        I64LtS,
+        I32And,
+        LocalTee(byte),
+        If(Empty),
+    ]);
+    b.text("(");
+    b.emit(&[End, I32Const(2), LocalGet(value), I64Load(8)]);
+    b.call(Runtime::FormatInt);
+    b.call(Runtime::WriteString);
+    b.emit(&[LocalGet(byte), If(Empty)]);
+    b.text(")");
+    b.emit(&[End, Return, End]);
+
+    when_kind(b, &[Kind::String]);
+    b.text("\"");
+    fields(b, "", &|b| {
+        b.emit(&[
+            LocalGet(value),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(8),
+            LocalSet(byte),
+        ]);
+        let escapes = [
+            (b'\n', "\\n"),
+            (b'\t', "\\t"),
+            (b'\r', "\\r"),
+            (b'\\', "\\\\"),
+            (b'"', "\\\""),
+        ];
+        for (c, escaped) in escapes.iter() {
+            b.emit(&[LocalGet(byte), I32Const(*c as i32), I32Eq, If(Empty)]);
+            b.text(escaped);
+            b.emit(&[Else]);
+        }
+        b.emit(&[
+            I32Const(2),
+            LocalGet(value),
+            LocalGet(i),
+            I32Add,
+            I32Const(8),
+            I32Add,
+            I32Const(1),
+            Call(WRITE),
+        ]);
+        for _ in escapes.iter() {
+            b.emit(&[End]);
+        }
+    });
+    b.text("\"");
+    b.emit(&[Return, End]);
+
+    when_kind(b, &[Kind::Unit]);
+    b.text("()");
+    b.emit(&[Return, End]);
+
+    when_kind(b, &[Kind::Bool]);
+    b.tag(value);
+    b.emit(&[If(Empty)]);
+    b.text("true");
+    b.emit(&[Else]);
+    b.text("false");
+    b.emit(&[End, Return, End]);
+
+    when_kind(b, &[Kind::Tuple]);
+    b.text("(");
+    fields(b, ", ", &|b| field(b, 0));
+    b.text(")");
+    b.emit(&[Return, End]);
+
+    when_kind(b, &[Kind::Array]);
+    b.text("[|");
+    fields(b, "; ", &|b| field(b, 0));
+    b.text("|]");
+    b.emit(&[Return, End]);
+
+    // The names of a tag are the ones of its fields for the records. The
+    // tags of the builtins follow the definitions in the table.
+    let names_of_tag = |b: &mut Builder| {
+        b.tag(value);
+        b.emit(&[
+            LocalTee(names),
+            LocalGet(names),
+            I32Const(BUILTIN_TAG as i32 - defs as i32),
+            I32Sub,
+            LocalGet(names),
+            I32Const(BUILTIN_TAG as i32),
+            I32LtU,
+            Select,
+            I32Const(2),
+            I32Shl,
+            I32Load(tags),
+            LocalSet(names),
+        ]);
+    };
+    when_kind(b, &[Kind::Record]);
+    names_of_tag(b);
+    b.text("{ ");
+    fields(b, "; ", &|b| {
+        b.emit(&[I32Const(2)]);
+        b.field_address(names, i);
+        b.emit(&[I32Load(0)]);
+        b.call(Runtime::WriteString);
+        b.text(" = ");
+        field(b, 0);
+    });
+    b.text(" }");
+    b.emit(&[Return, End]);
+
+    when_kind(b, &[Kind::Variant]);
+    names_of_tag(b);
+    b.emit(&[LocalGet(nested)]);
+    b.size(value);
+    b.emit(&[I32Const(0), I32Ne, I32And, LocalTee(byte), If(Empty)]);
+    b.text("(");
+    b.emit(&[End, I32Const(2), LocalGet(names), I32Load(0)]);
+    b.call(Runtime::WriteString);
+    fields(b, "", &|b| {
+        b.text(" ");
+        field(b, 1);
+    });
+    b.emit(&[LocalGet(byte), If(Empty)]);
+    b.text(")");
+    b.emit(&[End, Return, End]);
+
+    when_kind(b, &[Kind::Nil, Kind::Cons]);
+    b.text("[");
+    b.emit(&[LocalGet(value), LocalSet(cell), Block(Empty), Loop(Empty)]);
+    b.is_kind(cell, Kind::Cons);
+    b.emit(&[
+        I32Eqz,
+        BrIf(1),
+        LocalGet(cell),
+        LocalGet(value),
+        I32Ne,
+        If(Empty),
+    ]);
+    b.text("; ");
+    b.emit(&[End, LocalGet(cell), I32Load(8), I32Const(0)]);
+    b.call(Runtime::WriteValue);
+    b.emit(&[LocalGet(cell), I32Load(12), LocalSet(cell), Br(0), End, End]);
+    b.text("]");
+    b.emit(&[Return, End]);
+
+    when_kind(b, &[Kind::Ref]);
+    b.emit(&[LocalGet(nested), If(Empty)]);
+    b.text("(");
+    b.emit(&[End]);
+    b.text("ref ");
+    b.emit(&[LocalGet(value), I32Load(8), I32Const(1)]);
+    b.call(Runtime::WriteValue);
+    b.emit(&[LocalGet(nested), If(Empty)]);
+    b.text(")");
+    b.emit(&[End, Return, End]);
+
+    b.text("<fun>");
+}
+
+fn report_uncaught(b: &mut Builder) {
+    b.text("uncaught exception ");
+    b.emit(&[GlobalGet(EXN), I32Const(0)]);
+    b.call(Runtime::WriteValue);
+    b.text("\n");
+}
+
+fn reference(b: &mut Builder) {
+    let contents = 0;
+    let reference = b.local(I32);
+    b.emit(&[I64Const(header(Kind::Ref, 0, 1) as i64)]);
+    b.call(Runtime::Alloc);
+    b.emit(&[
+        LocalTee(reference),
+        LocalGet(contents),
+        I32Store(8),
+        LocalGet(reference),
+    ]);
+}
+
+fn compare(b: &mut Builder) {
+    let ordering = b.local(I32);
+    b.emit(&[LocalGet(0), LocalGet(1)]);
+    b.call(Runtime::CompareValues);
+    b.emit(&[LocalTee(ordering), I32Const(INCOMPARABLE), I32Eq, If(Empty)]);
+    b.raise(Builtin::InvalidArgument, Some("compare: functional value"));
+    b.emit(&[End, LocalGet(ordering), I64ExtendI32S]);
+    b.call(Runtime::Box);
+}
+
+fn string_length(b: &mut Builder) {
+    b.size(0);
+    b.emit(&[I64ExtendI32U]);
+    b.call(Runtime::Box);
+}
+
+fn string_get(b: &mut Builder) {
+    let (string, index) = (0, 1);
+    b.emit(&[LocalGet(index), I64Load(8)]);
+    b.size(string);
+    b.emit(&[I64ExtendI32U, I64LtU, I32Eqz, If(Empty)]);
+    b.raise(Builtin::InvalidArgument, Some("string_get"));
+    b.emit(&[
+        End,
+        LocalGet(string),
+        LocalGet(index),
+        I64Load(8),
+        I32WrapI64,
+        I32Add,
+        I32Load8U(8),
+        I64ExtendI32U,
+    ]);
+    b.call(Runtime::Box);
+}
+
+fn string_sub(b: &mut Builder) {
+    let (string, start, length) = (0, 1, 2);
+    let from = b.local(I32);
+    let count = b.local(I32);
+    let substring = b.local(I32);
+    // The negative integers are out of the range as unsigned ones.
+    b.emit(&[LocalGet(start), I64Load(8)]);
+    b.size(string);
+    b.emit(&[I64ExtendI32U, I64GtU, LocalGet(length), I64Load(8)]);
+    b.size(string);
+    b.emit(&[
+        I64ExtendI32U,
+        LocalGet(start),
+        I64Load(8),
+        I64Sub,
+        I64GtU,
+        I32Or,
+        If(Empty),
+    ]);
+    b.raise(Builtin::InvalidArgument, Some("string_sub"));
+    b.emit(&[
+        End,
+        LocalGet(start),
+        I64Load(8),
+        I32WrapI64,
+        LocalSet(from),
+        LocalGet(length),
+        I64Load(8),
+        I32WrapI64,
+        LocalSet(count),
+        LocalGet(string),
+        LocalGet(from),
+    ]);
+    b.call(Runtime::CharBoundary);
+    b.emit(&[LocalGet(string), LocalGet(from), LocalGet(count), I32Add]);
+    b.call(Runtime::CharBoundary);
+    b.emit(&[I32And, I32Eqz, If(Empty)]);
+    b.raise(Builtin::InvalidArgument, Some("string_sub"));
+    b.emit(&[End, LocalGet(count)]);
+    b.call(Runtime::NewString);
+    b.emit(&[
+        LocalTee(substring),
+        I32Const(8),
+        I32Add,
+        LocalGet(string),
+        LocalGet(from),
+        I32Add,
+        I32Const(8),
+        I32Add,
+        LocalGet(count),
+        MemoryCopy,
+        LocalGet(substring),
+    ]);
+}
+
+fn string_concat(b: &mut Builder) {
+    let (a, other) = (0, 1);
+    let string = b.local(I32);
+    b.size(a);
+    b.size(other);
+    b.emit(&[I32Add]);
+    b.call(Runtime::NewString);
+    b.emit(&[
+        LocalTee(string),
+        I32Const(8),
+        I32Add,
+        LocalGet(a),
+        I32Const(8),
+        I32Add,
+    ]);
+    b.size(a);
+    b.emit(&[MemoryCopy, LocalGet(string), I32Const(8), I32Add]);
+    b.size(a);
+    b.emit(&[I32Add, LocalGet(other), I32Const(8), I32Add]);
+    b.size(other);
+    b.emit(&[MemoryCopy, LocalGet(string)]);
+}
+
+/// Concatenates the strings of the list, summing their lengths first.
+fn string_concat_list(b: &mut Builder) {
+    let list = 0;
+    let cell = b.local(I32);
+    let length = b.local(I32);
+    let string = b.local(I32);
+    let end = b.local(I32);
+    b.emit(&[LocalGet(list), LocalSet(cell), Block(Empty), Loop(Empty)]);
+    b.is_kind(cell, Kind::Cons);
+    b.emit(&[
+        I32Eqz,
+        BrIf(1),
+        LocalGet(length),
+        LocalGet(cell),
+        I32Load(8),
+        I32Load(4),
+        I32Add,
+        LocalSet(length),
+        LocalGet(cell),
+        I32Load(12),
+        LocalSet(cell),
+        Br(0),
+        End,
+        End,
+        LocalGet(length),
+    ]);
+    b.call(Runtime::NewString);
+    b.emit(&[
+        LocalTee(string),
+        I32Const(8),
+        I32Add,
+        LocalSet(end),
+        LocalGet(list),
+        LocalSet(cell),
+        Block(Empty),
+        Loop(Empty),
+    ]);
+    b.is_kind(cell, Kind::Cons);
+    b.emit(&[
+        I32Eqz,
+        BrIf(1),
+        LocalGet(end),
+        LocalGet(cell),
+        I32Load(8),
+        I32Const(8),
+        I32Add,
+        LocalGet(cell),
+        I32Load(8),
+        I32Load(4),
+        MemoryCopy,
+        LocalGet(end),
+        LocalGet(cell),
+        I32Load(8),
+        I32Load(4),
+        I32Add,
+        LocalSet(end),
+        LocalGet(cell),
+        I32Load(12),
+        LocalSet(cell),
+        Br(0),
+        End,
+        End,
+        LocalGet(string),
+    ]);
+}
+
+/// Encodes the character in UTF-8.
+fn string_of_char_code(b: &mut Builder) {
+    let code = 0;
+    let c = b.local(I32);
+    let string = b.local(I32);
+    b.emit(&[
+        LocalGet(code),
+        I64Load(8),
+        I64Const(0x10ffff),
+        I64GtU,
+        LocalGet(code),
+        I64Load(8),
+        I64Const(0xd800),
+        I64Sub,
+        I64Const(0x800),
+        I64LtU,
+        I32Or,
+        If(Empty),
+    ]);
+    b.raise(Builtin::InvalidArgument, Some("string_of_char_code"));
+    b.emit(&[End, LocalGet(code), I64Load(8), I32WrapI64, LocalSet(c)]);
+    let limits = [0x80, 0x800, 0x10000, 0x110000];
+    let prefixes = [0x00, 0xc0, 0xe0, 0xf0];
+    for (length, (limit, prefix)) in limits.iter().zip(prefixes.iter()).enumerate() {
+        let length = length as u32 + 1;
+        b.emit(&[
+            LocalGet(c),
+            I32Const(*limit),
+            I32LtU,
+            If(Empty),
+            I32Const(length as i32),
+        ]);
+        b.call(Runtime::NewString);
+        b.emit(&[
+            LocalTee(string),
+            LocalGet(c),
+            I32Const(6 * (length as i32 - 1)),
+            I32ShrU,
+            I32Const(*prefix),
+            I32Or,
+            I32Store8(8),
+        ]);
+        for k in 1..length {
+            b.emit(&[
+                LocalGet(string),
+                LocalGet(c),
+                I32Const(6 * (length - 1 - k) as i32),
+                I32ShrU,
+                I32Const(0x3f),
+                I32And,
+                I32Const(0x80),
+                I32Or,
+                I32Store8(8 + k),
+            ]);
+        }
+        b.emit(&[LocalGet(string), Return, End]);
+    }
+    b.emit(&[Unreachable]);
+}
+
+fn string_of_int(b: &mut Builder) {
+    b.emit(&[LocalGet(0), I64Load(8)]);
+    b.call(Runtime::FormatInt);
+}
+
+/// Parses the decimal integer with an optional sign, like the interpreter
+/// does, without any whitespace.
+fn int_of_string(b: &mut Builder) {
+    let string = 0;
+    let length = b.local(I32);
+    let i = b.local(I32);
+    let negative = b.local(I32);
+    let n = b.local(I64);
+    let digit = b.local(I32);
+    let fail = |b: &mut Builder| b.raise(Builtin::Failure, Some("int_of_string"));
+    b.size(string);
+    b.emit(&[
+        LocalTee(length),
+        If(Empty),
+        LocalGet(string),
+        I32Load8U(8),
+        LocalTee(digit),
+        I32Const(b'-' as i32),
+        I32Eq,
+        LocalTee(negative),
+        LocalGet(digit),
+        I32Const(b'+' as i32),
+        I32Eq,
+        I32Or,
+        LocalSet(i),
+        End,
+        LocalGet(i),
+        LocalGet(length),
+        I32Eq,
+        If(Empty),
+    ]);
+    fail(b);
+    // Accumulates the negated value, whose range includes the minimum.
+    b.emit(&[
+        End,
+        Block(Empty),
+        Loop(Empty),
+        LocalGet(i),
+        LocalGet(length),
+        I32GeU,
+        BrIf(1),
+        LocalGet(string),
+        LocalGet(i),
+        I32Add,
+        I32Load8U(8),
+        I32Const(b'0' as i32),
+        I32Sub,
+        LocalTee(digit),
+        I32Const(9),
+        I32GtU,
+        LocalGet(n),
+        I64Const(i64::MIN),
+        LocalGet(digit),
+        I64ExtendI32U,
+        I64Add,
+        I64Const(10),
+        I64DivS,
+        I64LtS,
+        I32Or,
+        If(Empty),
+    ]);
+    fail(b);
+    b.emit(&[
+        End,
+        LocalGet(n),
+        I64Const(10),
+        I64Mul,
+        LocalGet(digit),
+        I64ExtendI32U,
+        I64Sub,
+        LocalSet(n),
+        LocalGet(i),
+        I32Const(1),
+        I32Add,
+        LocalSet(i),
+        Br(0),
+        End,
+        End,
+        LocalGet(negative),
+        I32Eqz,
+        If(Empty),
+        LocalGet(n),
+        I64Const(i64::MIN),
+        I64Eq,
+        If(Empty),
+    ]);
+    fail(b);
+    b.emit(&[
+        End,
+        I64Const(0),
+        LocalGet(n),
+        I64Sub,
+        LocalSet(n),
+        End,
+        LocalGet(n),
+    ]);
+    b.call(Runtime::Box);
+}
+
+fn print_string(b: &mut Builder, fd: i32) {
+    b.emit(&[I32Const(fd), LocalGet(0)]);
+    b.call(Runtime::WriteString);
+    b.emit(&[I32Const(UNIT as i32)]);
+}
+
+/// Reads the line byte by byte from the host into the free memory after
+/// the heap, where the string is then allocated.
+fn read_line(b: &mut Builder) {
+    let start = b.local(I32);
+    let length = b.local(I32);
+    let byte = b.local(I32);
+    let read = b.local(I32);
+    let line_end = |b: &mut Builder| {
+        b.emit(&[
+            LocalGet(byte),
+            I32Const(b'\n' as i32),
+            I32Eq,
+            LocalGet(byte),
+            I32Const(b'\r' as i32),
+            I32Eq,
+            I32Or,
+        ]);
+    };
+    b.emit(&[
+        GlobalGet(HEAP),
+        LocalSet(start),
+        Block(Empty),
+        Loop(Empty),
+        Call(READ_BYTE),
+        LocalTee(byte),
+        I32Const(0),
+        I32LtS,
+        BrIf(1),
+        I32Const(1),
+        LocalSet(read),
+        LocalGet(byte),
+        I32Const(b'\n' as i32),
+        I32Eq,
+        BrIf(1),
+        LocalGet(start),
+        LocalGet(length),
+        I32Add,
+        I32Const(9),
+        I32Add,
+    ]);
+    b.call(Runtime::Reserve);
+    b.emit(&[
+        LocalGet(start),
+        LocalGet(length),
+        I32Add,
+        LocalGet(byte),
+        I32Store8(8),
+        LocalGet(length),
+        I32Const(1),
+        I32Add,
+        LocalSet(length),
+        Br(0),
+        End,
+        End,
+        LocalGet(read),
+        I32Eqz,
+        If(Empty),
+    ]);
+    b.raise(Builtin::EndOfFile, None);
+    b.emit(&[
+        End,
+        Block(Empty),
+        Loop(Empty),
+        LocalGet(length),
+        I32Eqz,
+        BrIf(1),
+        LocalGet(start),
+        LocalGet(length),
+        I32Add,
+        I32Load8U(7),
+        LocalSet(byte),
+    ]);
+    line_end(b);
+    b.emit(&[
+        I32Eqz,
+        BrIf(1),
+        LocalGet(length),
+        I32Const(1),
+        I32Sub,
+        LocalSet(length),
+        Br(0),
+        End,
+        End,
+        LocalGet(length),
+    ]);
+    // Nothing was allocated since, so the string starts at the bytes.
+    b.call(Runtime::NewString);
+}
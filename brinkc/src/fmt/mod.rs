@@ -0,0 +1,349 @@
+//! The formatter of `brinkc fmt`, printing the programs in the canonical
+//! layout: two spaces of indentation, the code broken over lines only when
+//! it does not fit in the width, and the comments kept where they were.
+
+use crate::{
+    ast,
+    frontend::{self, lexer::Lexer, parse_session::ParseSession, token::TokenKind},
+    source_file::{FileId, SourceMap, SourceSpan},
+};
+
+mod printer;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// The width of the lines, from `--width`, which only the code that
+    /// cannot be broken exceeds.
+    pub width: usize,
+    /// The number of spaces per indentation level, from `--indent`.
+    pub indent: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: 100,
+            indent: 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    /// The file does not parse; the errors are reported to the session.
+    Syntax,
+    /// The formatted code would not mean the same as the file, which is a
+    /// bug of the formatter.
+    Unstable(String),
+}
+
+/// Formats the file loaded into the session. The fixities of the prelude
+/// have to be known to the session, to parse the operators as the compiler
+/// does.
+pub fn format(
+    session: &mut ParseSession,
+    file: FileId,
+    config: &Config,
+) -> Result<String, FormatError> {
+    let error_count = session.error_count();
+    let program = frontend::parse_file(session, file);
+    let program = match program {
+        Some(program) if session.error_count() == error_count => program,
+        _ => return Err(FormatError::Syntax),
+    };
+    let comments = Lexer::comments(session.source_map.file(file));
+    let formatted = printer::print_program(&program, &session.source_map, &comments, config);
+    verify(session, &program, formatted.clone()).map_err(FormatError::Unstable)?;
+    Ok(formatted)
+}
+
+/// Checks that the formatted code parses to the same program, with the
+/// same tokens and comments, so formatting never changes what a file means.
+fn verify(
+    session: &mut ParseSession,
+    program: &ast::Program,
+    formatted: String,
+) -> Result<(), String> {
+    let path = format!(
+        "{} (formatted)",
+        session.source_map.file(program.file).file_path
+    );
+    let file = session.source_map.add_file(path, formatted);
+    session.begin_capture();
+    let reparsed = frontend::parse_file(session, file);
+    let diagnostics = session.end_capture();
+    if let Some(diagnostic) = diagnostics.first() {
+        return Err(format!(
+            "{} at {}",
+            diagnostic.message,
+            session.source_map.span_to_location(diagnostic.span)
+        ));
+    }
+    let reparsed = reparsed.ok_or("the indentation is malformed")?;
+
+    let source_map = &session.source_map;
+    let (expected, found) = (tokens(source_map, program.file), tokens(source_map, file));
+    if let Some(i) = (0..expected.len().max(found.len())).find(|&i| {
+        expected.get(i).map(|token| (token.0, token.1))
+            != found.get(i).map(|token| (token.0, token.1))
+    }) {
+        let text = |tokens: &[(TokenKind, &str, SourceSpan)]| {
+            tokens
+                .get(i)
+                .map_or("the end of the file", |token| token.1)
+                .to_string()
+        };
+        let location = found.get(i).map_or_else(
+            || "the end of the file".to_string(),
+            |token| source_map.span_to_location(token.2),
+        );
+        return Err(format!(
+            "found `{}` instead of `{}` at {}",
+            text(&found),
+            text(&expected),
+            location
+        ));
+    }
+
+    // The comments stay after the same token, and on a line of their own
+    // or at the end of a line of code.
+    let comments = |file, tokens: &[(TokenKind, &str, SourceSpan)]| {
+        Lexer::comments(source_map.file(file))
+            .into_iter()
+            .map(|span| {
+                let preceding = tokens
+                    .iter()
+                    .take_while(|token| token.2.start < span.start)
+                    .count();
+                let text = source_map.span_to_snippet(span).trim_end();
+                ((text, preceding, follows_code(source_map, span)), span)
+            })
+            .collect::<Vec<_>>()
+    };
+    let (expected, found) = (comments(program.file, &expected), comments(file, &found));
+    if let Some(i) = (0..expected.len().max(found.len())).find(|&i| {
+        expected.get(i).map(|comment| comment.0) != found.get(i).map(|comment| comment.0)
+    }) {
+        return Err(match (expected.get(i), found.get(i)) {
+            (Some(((text, ..), _)), Some((_, span))) => format!(
+                "the comment `{}` moved to {}",
+                text,
+                source_map.span_to_location(*span)
+            ),
+            _ => "the comments differ".to_string(),
+        });
+    }
+    if shape(program) != shape(&reparsed) {
+        return Err("the syntax trees differ".to_string());
+    }
+    Ok(())
+}
+
+/// Whether the comment follows code on its line, rather than being on a
+/// line of its own.
+fn follows_code(source_map: &SourceMap, comment: SourceSpan) -> bool {
+    let file = source_map.lookup_file(comment.start);
+    let (line, column) = file.line_column(comment.start);
+    file.line_text(line)
+        .chars()
+        .take(column - 1)
+        .any(|c| !c.is_whitespace())
+}
+
+/// Gets the tokens of the file without the layout, with their text and
+/// position. The leading bar of a variant type is optional.
+fn tokens(source_map: &SourceMap, file: FileId) -> Vec<(TokenKind, &str, SourceSpan)> {
+    let mut tokens: Vec<(TokenKind, &str, SourceSpan)> = Vec::new();
+    for token in Lexer::tokenize(source_map.file(file)).as_vec() {
+        let is_layout = matches!(
+            token.kind,
+            TokenKind::NewLine
+                | TokenKind::Indent
+                | TokenKind::Dedent
+                | TokenKind::Semicolon
+                | TokenKind::EndOfFile
+        );
+        let is_leading_bar = token.kind == TokenKind::Bar
+            && tokens.last().is_some_and(|last| last.0 == TokenKind::Equal);
+        if !is_layout && !is_leading_bar {
+            tokens.push((
+                token.kind,
+                source_map.span_to_snippet(token.span),
+                token.span,
+            ));
+        }
+    }
+    tokens
+}
+
+/// Prints the syntax tree without its node ids and positions, the only
+/// numbers in it, as the literals are spans of the source code.
+fn shape(program: &ast::Program) -> String {
+    format!("{:?}", program.body)
+        .chars()
+        .filter(|c| !c.is_ascii_digit())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        frontend::{self, parse_session::ParseSession},
+        resolve::ModuleSource,
+        source_file::SourceMap,
+    };
+
+    use super::{format, verify, Config, FormatError};
+
+    fn session() -> ParseSession {
+        let mut session = ParseSession::new(SourceMap::new());
+        let prelude = ModuleSource::Prelude.embedded_source().unwrap();
+        let file = session
+            .source_map
+            .add_file("prelude.bk".to_string(), prelude.to_string());
+        let _ = frontend::parse_prelude(&mut session, file);
+        session
+    }
+
+    fn format_with(source: &str, config: &Config) -> String {
+        let mut session = session();
+        let file = session
+            .source_map
+            .add_file("test.bk".to_string(), source.to_string());
+        match format(&mut session, file, config) {
+            Ok(formatted) => formatted,
+            Err(e) => panic!("could not format {:?}: {:?}", source, e),
+        }
+    }
+
+    fn format_source(source: &str) -> String {
+        format_with(source, &Config::default())
+    }
+
+    #[test]
+    fn normalizes_indentation_and_spacing() {
+        assert_eq!(
+            format_source(
+                "let  f x=\n    let y =x+1\n    match y\n    |0 -> [ 1 ;2]\n    | _ -> []\n"
+            ),
+            "let f x =\n  let y = x + 1\n  match y\n  | 0 -> [1; 2]\n  | _ -> []\n"
+        );
+        assert_eq!(
+            format_source("type T = A|B int\ntype R = {x:int;y:int}\nlet r = {x=1;y=2}\n"),
+            "type T = A | B int\ntype R = { x: int; y: int }\nlet r = { x = 1; y = 2 }\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let source =
+            "# header\n\nlet x = 1 # one\n\n\n# about y\nlet y =\n  # inside\n  x\n# end\n";
+        assert_eq!(
+            format_source(source),
+            "# header\n\nlet x = 1 # one\n\n# about y\nlet y =\n  # inside\n  x\n# end\n"
+        );
+    }
+
+    #[test]
+    fn breaks_the_code_too_wide() {
+        let config = Config {
+            width: 20,
+            indent: 4,
+        };
+        assert_eq!(
+            format_with("let xs = [1111; 2222; 3333]\nlet f x =\n  x\n", &config),
+            "let xs = [\n    1111\n    2222\n    3333\n]\nlet f x =\n    x\n"
+        );
+        assert_eq!(
+            format_with("let x = if a then bbbbbbb else c\n", &config),
+            "let x = if a then bbbbbbb\nelse c\n"
+        );
+    }
+
+    #[test]
+    fn keeps_elif_and_operators() {
+        let source = "let (|>>) x f = f x\nlet g x = if x < 0 then -1 elif x = 0 then 0 else 1\nlet h = (+ 1)\nlet k = (|>>)\n";
+        assert_eq!(format_source(source), source);
+    }
+
+    #[test]
+    fn puts_the_bindings_of_a_group_on_their_lines() {
+        assert_eq!(
+            format_source("let rec f x=g x and g x=\n  f x\n"),
+            "let rec f x = g x\nand g x =\n  f x\n"
+        );
+        let source = "let r =\n  let rec f x = g x # f\n\n  # about g\n  and g x = f x\n  f 1\n";
+        assert_eq!(format_source(source), source);
+    }
+
+    #[test]
+    fn keeps_comments_at_the_end_of_their_line() {
+        let source = "let f x =   # trailing\n  let g y = # g\n    y\n  g x*2 # end\n\nlet h x =\n  match x\n  | _ -> # any\n    try # t\n      x\n    with # w\n    | E -> 0\n";
+        assert_eq!(
+            format_source(source),
+            "let f x = # trailing\n  let g y = # g\n    y\n  g x * 2 # end\n\nlet h x =\n  match x\n  | _ -> # any\n    try # t\n      x\n    with # w\n    | E -> 0\n"
+        );
+    }
+
+    #[test]
+    fn breaks_a_match_within_brackets() {
+        let config = Config {
+            width: 30,
+            indent: 2,
+        };
+        assert_eq!(
+            format_with(
+                "let y = f (fun v -> match v | Some n -> n | None -> 0) x\n",
+                &config
+            ),
+            "let y = f (fun v -> match v\n  | Some n -> n\n  | None -> 0) x\n"
+        );
+    }
+
+    #[test]
+    fn rejects_moved_comments() {
+        let mut session = session();
+        let file = session
+            .source_map
+            .add_file("test.bk".to_string(), "let x = 1 # one\n".to_string());
+        let program = frontend::parse_file(&mut session, file).unwrap();
+        assert_eq!(
+            verify(&mut session, &program, "let x = 1\n# one\n".to_string()),
+            Err("the comment `# one` moved to test.bk (formatted):2:1".to_string())
+        );
+    }
+
+    #[test]
+    fn formats_the_library_idempotently() {
+        for (name, source) in [
+            ("io.bk", include_str!("../../lib/io.bk")),
+            ("list.bk", include_str!("../../lib/list.bk")),
+            ("map.bk", include_str!("../../lib/map.bk")),
+            ("option.bk", include_str!("../../lib/option.bk")),
+            ("prelude.bk", include_str!("../../lib/prelude.bk")),
+            ("result.bk", include_str!("../../lib/result.bk")),
+            ("set.bk", include_str!("../../lib/set.bk")),
+            ("string.bk", include_str!("../../lib/string.bk")),
+            ("tests/list.bk", include_str!("../../lib/tests/list.bk")),
+            ("tests/map.bk", include_str!("../../lib/tests/map.bk")),
+            ("tests/string.bk", include_str!("../../lib/tests/string.bk")),
+        ] {
+            let formatted = format_source(source);
+            assert_eq!(formatted, source, "{}", name);
+            assert_eq!(format_source(&formatted), formatted, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let mut session = session();
+        let file = session
+            .source_map
+            .add_file("test.bk".to_string(), "let x =\n".to_string());
+        assert!(matches!(
+            format(&mut session, file, &Config::default()),
+            Err(FormatError::Syntax)
+        ));
+        assert!(session.has_errors());
+    }
+}
@@ -0,0 +1,915 @@
+use crate::{
+    ast,
+    frontend::token,
+    source_file::{SourceMap, SourceSpan},
+};
+
+use super::{follows_code, Config};
+
+/// Prints the program in the canonical layout, keeping its comments and at
+/// most one blank line between the items.
+pub fn print_program(
+    program: &ast::Program,
+    source_map: &SourceMap,
+    comments: &[SourceSpan],
+    config: &Config,
+) -> String {
+    let mut printer = Printer {
+        source_map,
+        config,
+        comments,
+        next_comment: 0,
+        last_end: source_map.file(program.file).start_pos,
+        output: String::new(),
+        indent: 0,
+        flat: false,
+        broken: false,
+        in_brackets: false,
+    };
+    printer.items(&program.body);
+    printer.trailing_comment(usize::MAX);
+    while let Some(&comment) = printer.comments.get(printer.next_comment) {
+        if !printer.output.is_empty() {
+            let blank = printer.blank_line_before(comment.start);
+            printer.newline(blank);
+        }
+        printer.comment(comment);
+    }
+    printer.trim_line_end();
+    if !printer.output.is_empty() {
+        printer.output.push('\n');
+    }
+    printer.output
+}
+
+struct Printer<'a> {
+    source_map: &'a SourceMap,
+    config: &'a Config,
+    /// The spans of the comments of the file, in order.
+    comments: &'a [SourceSpan],
+    /// The index of the first comment not printed yet.
+    next_comment: usize,
+    /// The end of the source code printed last, from which the comments
+    /// and the blank lines are found.
+    last_end: usize,
+    output: String,
+    indent: usize,
+    /// Whether the code is printed on a single line.
+    flat: bool,
+    /// Whether the code printed flat needed a line break.
+    broken: bool,
+    /// Whether the code is within brackets on the line, where a match can
+    /// stay on one line.
+    in_brackets: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn items(&mut self, items: &[ast::Item]) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.break_before(item.span.start);
+            }
+            self.leading_comments(item.span.start);
+            self.item(item);
+            self.advance_to(item.span);
+        }
+    }
+
+    fn item(&mut self, item: &ast::Item) {
+        if item.visibility == ast::Visibility::Public {
+            self.push("pub ");
+        }
+        match &item.kind {
+            ast::ItemKind::LetBinding(binding) => {
+                self.push("let ");
+                self.let_binding(binding);
+            }
+            // The bindings after the first are recursive without `rec`.
+            ast::ItemKind::LetRec(bindings) => {
+                for (i, binding) in bindings.iter().enumerate() {
+                    if i == 0 {
+                        self.push("let ");
+                        self.let_binding(binding);
+                        continue;
+                    }
+                    self.advance_to(bindings[i - 1].span);
+                    self.break_before(binding.span.start);
+                    self.leading_comments(binding.span.start);
+                    self.push("and ");
+                    self.let_definition(binding);
+                }
+            }
+            ast::ItemKind::Type(decl) => self.type_decl(decl),
+            ast::ItemKind::Module(decl) => {
+                self.push("module ");
+                self.literal(&decl.identifier);
+                self.push(" =");
+                self.block(&decl.body);
+            }
+            ast::ItemKind::Open(path) => {
+                self.push("open ");
+                self.path(path);
+            }
+            ast::ItemKind::Import(path) => {
+                self.push("import ");
+                self.path(path);
+            }
+            ast::ItemKind::Exception(constructor) => {
+                self.push("exception ");
+                self.constructor(constructor);
+            }
+            ast::ItemKind::Fixity(decl) => {
+                self.push(match decl.associativity {
+                    ast::Associativity::Left => "infixl ",
+                    ast::Associativity::Right => "infixr ",
+                    ast::Associativity::None => "infix ",
+                });
+                self.literal(&decl.precedence);
+                for operator in &decl.operators {
+                    self.push(" ");
+                    self.literal(operator);
+                }
+            }
+            ast::ItemKind::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn let_binding(&mut self, binding: &ast::LetBinding) {
+        if binding.is_recursive {
+            self.push("rec ");
+        }
+        self.let_definition(binding);
+    }
+
+    /// Prints the binding after its `rec` keyword.
+    fn let_definition(&mut self, binding: &ast::LetBinding) {
+        self.name(&binding.identifier);
+        for parameter in &binding.parameters {
+            self.push(" ");
+            self.pattern(parameter);
+        }
+        if let Some(ty) = &binding.return_ty {
+            self.push(" : ");
+            self.ty(ty);
+        }
+        self.push(" =");
+        self.let_body(&binding.body);
+    }
+
+    /// Prints the body after its keyword, which ends the line before a
+    /// block.
+    fn let_body(&mut self, body: &ast::LetBody) {
+        match body {
+            ast::LetBody::Block(block) => self.block(block),
+            ast::LetBody::Expr(expr) => {
+                self.push(" ");
+                self.expr(expr);
+            }
+        }
+    }
+
+    fn block(&mut self, block: &ast::Block) {
+        if self.flat {
+            self.broken = true;
+            return;
+        }
+        let in_brackets = std::mem::replace(&mut self.in_brackets, false);
+        self.indent += 1;
+        self.line_break(block.span.start);
+        self.items(&block.items);
+        self.indent -= 1;
+        self.in_brackets = in_brackets;
+    }
+
+    fn type_decl(&mut self, decl: &ast::TypeDecl) {
+        self.push("type ");
+        self.literal(&decl.identifier);
+        for parameter in &decl.parameters {
+            self.push(" ");
+            self.literal(parameter);
+        }
+        self.push(" =");
+        match &decl.kind {
+            ast::TypeDeclKind::Alias(ty) => {
+                self.push(" ");
+                self.ty(ty);
+            }
+            ast::TypeDeclKind::Record(fields) => {
+                self.push(" ");
+                self.group(decl.span, |printer| {
+                    printer.bracketed(
+                        "{",
+                        "}",
+                        decl.span,
+                        fields,
+                        |field| field.span,
+                        |printer, field| {
+                            printer.literal(&field.identifier);
+                            printer.push(": ");
+                            printer.ty(&field.ty);
+                        },
+                    )
+                });
+            }
+            ast::TypeDeclKind::Variant(constructors) => {
+                self.group(decl.span, |printer| printer.constructors(constructors))
+            }
+        }
+    }
+
+    /// Prints the constructors of a variant type, with a leading bar when
+    /// there is a single one, as `type T = A` declares an alias.
+    fn constructors(&mut self, constructors: &[ast::Constructor]) {
+        if self.flat {
+            if constructors.len() == 1 {
+                self.push(" |");
+            }
+            for (i, constructor) in constructors.iter().enumerate() {
+                if i > 0 {
+                    self.push(" |");
+                }
+                self.push(" ");
+                self.constructor(constructor);
+            }
+            return;
+        }
+        self.indent += 1;
+        for constructor in constructors {
+            self.line_break(constructor.span.start);
+            self.leading_comments(constructor.span.start);
+            self.push("| ");
+            self.constructor(constructor);
+            self.advance_to(constructor.span);
+        }
+        self.indent -= 1;
+    }
+
+    fn constructor(&mut self, constructor: &ast::Constructor) {
+        self.literal(&constructor.identifier);
+        for argument in &constructor.arguments {
+            self.push(" ");
+            self.ty(argument);
+        }
+    }
+
+    fn ty(&mut self, ty: &ast::Ty) {
+        match &ty.kind {
+            ast::TyKind::Path(path, arguments) => {
+                self.path(path);
+                for argument in arguments {
+                    self.push(" ");
+                    self.ty(argument);
+                }
+            }
+            ast::TyKind::Var(literal) => self.literal(literal),
+            ast::TyKind::Arrow(parameter, result) => {
+                self.ty(parameter);
+                self.push(" -> ");
+                self.ty(result);
+            }
+            ast::TyKind::Tuple(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.push(" * ");
+                    }
+                    self.ty(element);
+                }
+            }
+            ast::TyKind::Paren(inner) => {
+                self.push("(");
+                self.ty(inner);
+                self.push(")");
+            }
+        }
+    }
+
+    /// Prints the pattern, which always fits on a line.
+    fn pattern(&mut self, pattern: &ast::Pattern) {
+        match &pattern.kind {
+            ast::PatternKind::Wildcard => self.push("_"),
+            ast::PatternKind::Binding(literal) | ast::PatternKind::Literal(literal) => {
+                self.literal(literal)
+            }
+            ast::PatternKind::Constructor(path, arguments) => {
+                self.path(path);
+                for argument in arguments {
+                    self.push(" ");
+                    self.pattern(argument);
+                }
+            }
+            ast::PatternKind::Record(fields) => {
+                self.push("{ ");
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        self.push("; ");
+                    }
+                    self.literal(&field.identifier);
+                    if let Some(pattern) = &field.pattern {
+                        self.push(" = ");
+                        self.pattern(pattern);
+                    }
+                }
+                self.push(" }");
+            }
+            ast::PatternKind::Or(alternatives) => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        self.push(" | ");
+                    }
+                    self.pattern(alternative);
+                }
+            }
+            ast::PatternKind::Paren(inner) => {
+                self.push("(");
+                self.pattern(inner);
+                self.push(")");
+            }
+            ast::PatternKind::Tuple(elements) => {
+                self.push("(");
+                self.patterns(elements, ", ");
+                self.push(")");
+            }
+            ast::PatternKind::List(elements) => {
+                self.push("[");
+                self.patterns(elements, "; ");
+                self.push("]");
+            }
+            ast::PatternKind::Array(elements) => {
+                self.push("[|");
+                self.patterns(elements, "; ");
+                self.push("|]");
+            }
+            ast::PatternKind::Cons(head, tail) => {
+                self.pattern(head);
+                self.push(" :: ");
+                self.pattern(tail);
+            }
+            ast::PatternKind::Typed(inner, ty) => {
+                self.push("(");
+                self.pattern(inner);
+                self.push(": ");
+                self.ty(ty);
+                self.push(")");
+            }
+        }
+    }
+
+    fn patterns(&mut self, patterns: &[ast::Pattern], separator: &str) {
+        for (i, pattern) in patterns.iter().enumerate() {
+            if i > 0 {
+                self.push(separator);
+            }
+            self.pattern(pattern);
+        }
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) {
+        self.group(expr.span, |printer| printer.expr_kind(expr));
+    }
+
+    fn expr_kind(&mut self, expr: &ast::Expr) {
+        let in_brackets = self.in_brackets;
+        self.in_brackets |= matches!(
+            expr.kind,
+            ast::ExprKind::Paren(_)
+                | ast::ExprKind::Tuple(_)
+                | ast::ExprKind::List(_)
+                | ast::ExprKind::Array(_)
+                | ast::ExprKind::Record(_)
+                | ast::ExprKind::RecordUpdate(..)
+                | ast::ExprKind::Section(..)
+                | ast::ExprKind::Typed(..)
+        );
+        self.expr_kind_in(expr);
+        self.in_brackets = in_brackets;
+    }
+
+    fn expr_kind_in(&mut self, expr: &ast::Expr) {
+        match &expr.kind {
+            ast::ExprKind::Literal(literal) => self.literal(literal),
+            ast::ExprKind::Path(path) => match path.segments.as_slice() {
+                [operator] if self.is_operator(operator) => {
+                    self.push("(");
+                    self.literal(operator);
+                    self.push(")");
+                }
+                _ => self.path(path),
+            },
+            ast::ExprKind::Application(function, arguments) => {
+                self.expr(function);
+                for argument in arguments {
+                    self.push(" ");
+                    self.expr(argument);
+                }
+            }
+            ast::ExprKind::TailCall(call) => {
+                self.push("@tailcall ");
+                self.expr(call);
+            }
+            ast::ExprKind::Paren(inner) => {
+                self.push("(");
+                self.expr(inner);
+                self.push(")");
+            }
+            ast::ExprKind::Tuple(elements) => self.bracketed(
+                "(",
+                ")",
+                expr.span,
+                elements,
+                |element| element.span,
+                Self::expr,
+            ),
+            ast::ExprKind::List(elements) => self.bracketed(
+                "[",
+                "]",
+                expr.span,
+                elements,
+                |element| element.span,
+                Self::expr,
+            ),
+            ast::ExprKind::Array(elements) => self.bracketed(
+                "[|",
+                "|]",
+                expr.span,
+                elements,
+                |element| element.span,
+                Self::expr,
+            ),
+            ast::ExprKind::Record(fields) => {
+                self.bracketed("{", "}", expr.span, fields, |field| field.span, Self::field)
+            }
+            ast::ExprKind::RecordUpdate(record, fields) => {
+                self.push("{ ");
+                self.expr(record);
+                self.push(" with");
+                if self.flat {
+                    self.push(" ");
+                    self.joined(fields, "; ", Self::field);
+                    self.push(" }");
+                } else {
+                    self.elements(expr.span, fields, "; ", |field| field.span, Self::field);
+                    self.push("}");
+                }
+            }
+            ast::ExprKind::FieldAccess(record, field) => {
+                self.expr(record);
+                self.push(".");
+                self.literal(field);
+            }
+            ast::ExprKind::Match(scrutinee, arms) => {
+                self.push("match ");
+                self.expr(scrutinee);
+                self.arms(arms);
+            }
+            ast::ExprKind::Lambda(parameters, body) => {
+                self.push("fun");
+                for parameter in parameters {
+                    self.push(" ");
+                    self.pattern(parameter);
+                }
+                self.push(" ->");
+                self.let_body(body);
+            }
+            ast::ExprKind::If(..) => self.if_expr(expr),
+            ast::ExprKind::While(condition, body) => {
+                self.push("while ");
+                self.expr(condition);
+                self.push(" do");
+                self.let_body(body);
+            }
+            ast::ExprKind::For(for_loop) => {
+                self.push("for ");
+                self.literal(&for_loop.binding);
+                self.push(" = ");
+                self.expr(&for_loop.start);
+                self.push(match for_loop.direction {
+                    ast::ForDirection::Up => " to ",
+                    ast::ForDirection::Down => " downto ",
+                });
+                self.expr(&for_loop.end);
+                self.push(" do");
+                self.let_body(&for_loop.body);
+            }
+            ast::ExprKind::Try(body, arms) => {
+                self.push("try");
+                self.let_body(body);
+                if matches!(**body, ast::LetBody::Block(_)) {
+                    self.line_break(self.code_after(body_span(body).end));
+                    self.push("with");
+                } else {
+                    self.push(" with");
+                }
+                self.arms(arms);
+            }
+            ast::ExprKind::Let(binding, body) => {
+                self.push("let ");
+                self.let_binding(binding);
+                self.push(" in");
+                match &**body {
+                    ast::LetBody::Block(block) => self.block(block),
+                    ast::LetBody::Expr(body) if self.flat => {
+                        self.push(" ");
+                        self.expr(body);
+                    }
+                    ast::LetBody::Expr(body) => {
+                        self.line_break(body.span.start);
+                        self.expr(body);
+                    }
+                }
+            }
+            ast::ExprKind::Binary(operator, left, right) => {
+                self.expr(left);
+                self.push(" ");
+                self.push_span(operator.span);
+                self.push(" ");
+                self.expr(right);
+            }
+            ast::ExprKind::Infix(path, left, right) => {
+                self.expr(left);
+                self.push(" ");
+                self.path(path);
+                self.push(" ");
+                self.expr(right);
+            }
+            ast::ExprKind::Section(operator, left, right) => {
+                self.push("(");
+                if let Some(left) = left {
+                    self.expr(left);
+                    self.push(" ");
+                }
+                match operator {
+                    ast::SectionOperator::Builtin(operator) => self.push_span(operator.span),
+                    ast::SectionOperator::User(path) => self.path(path),
+                }
+                if let Some(right) = right {
+                    self.push(" ");
+                    self.expr(right);
+                }
+                self.push(")");
+            }
+            ast::ExprKind::Unary(operator, operand) => {
+                self.push_span(operator.span);
+                // `--` and `!!` would be lexed as single operators.
+                if matches!(operand.kind, ast::ExprKind::Unary(..)) {
+                    self.push(" ");
+                }
+                self.expr(operand);
+            }
+            ast::ExprKind::Typed(inner, ty) => {
+                self.push("(");
+                self.expr(inner);
+                self.push(" : ");
+                self.ty(ty);
+                self.push(")");
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                // The holes are within a string token, on its line.
+                let flat = std::mem::replace(&mut self.flat, true);
+                self.push("$\"");
+                for part in parts {
+                    match part {
+                        ast::InterpolationPart::Text(span) => self.push_span(*span),
+                        ast::InterpolationPart::Hole(expr) => {
+                            self.push("{");
+                            self.expr(expr);
+                            self.push("}");
+                        }
+                    }
+                }
+                self.push("\"");
+                self.flat = flat;
+            }
+        }
+    }
+
+    /// Prints the conditional with its `elif` branches, which are nested
+    /// conditionals spanning from the `elif` keyword.
+    fn if_expr(&mut self, mut expr: &ast::Expr) {
+        let mut keyword = "if ";
+        while let ast::ExprKind::If(condition, then_branch, else_branch) = &expr.kind {
+            self.push(keyword);
+            self.expr(condition);
+            self.push(" then");
+            self.let_body(then_branch);
+            let Some(else_branch) = else_branch else {
+                return;
+            };
+            if self.flat {
+                self.push(" ");
+            } else {
+                self.line_break(self.code_after(body_span(then_branch).end));
+            }
+            match &**else_branch {
+                ast::LetBody::Expr(nested)
+                    if matches!(nested.kind, ast::ExprKind::If(..))
+                        && self.snippet(nested.span).starts_with("elif") =>
+                {
+                    keyword = "elif ";
+                    expr = nested;
+                }
+                body => {
+                    self.push("else");
+                    self.let_body(body);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Prints the arms of a match or a try, each on its own line unless
+    /// they are within brackets and fit. The arms broken within brackets
+    /// are indented, as they continue the line of the bracket.
+    fn arms(&mut self, arms: &[ast::MatchArm]) {
+        let indent = usize::from(!self.flat && self.in_brackets);
+        self.indent += indent;
+        for arm in arms {
+            if self.flat {
+                self.broken |= !self.in_brackets;
+                self.push(" ");
+            } else {
+                self.line_break(arm.span.start);
+                self.leading_comments(arm.span.start);
+            }
+            self.push("| ");
+            self.pattern(&arm.pattern);
+            self.push(" ->");
+            self.let_body(&arm.body);
+            self.advance_to(arm.span);
+        }
+        self.indent -= indent;
+    }
+
+    fn field(&mut self, field: &ast::FieldExpr) {
+        self.literal(&field.identifier);
+        self.push(" = ");
+        self.expr(&field.expr);
+    }
+
+    /// Prints the elements in the brackets, on one line if they fit, and
+    /// otherwise each on its own line. Only the tuples separate them with
+    /// commas.
+    fn bracketed<T>(
+        &mut self,
+        open: &str,
+        close: &str,
+        span: SourceSpan,
+        elements: &[T],
+        span_of: impl Fn(&T) -> SourceSpan,
+        print: impl Fn(&mut Self, &T),
+    ) {
+        self.push(open);
+        if elements.is_empty() {
+            self.push(close);
+            return;
+        }
+        let padding = if open == "{" { " " } else { "" };
+        let separator = if open == "(" { ", " } else { "; " };
+        if self.flat {
+            self.push(padding);
+            self.joined(elements, separator, print);
+            self.push(padding);
+        } else {
+            self.elements(span, elements, separator, span_of, print);
+        }
+        self.push(close);
+    }
+
+    fn joined<T>(&mut self, elements: &[T], separator: &str, print: impl Fn(&mut Self, &T)) {
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 {
+                self.push(separator);
+            }
+            print(self, element);
+        }
+    }
+
+    /// Prints the elements one per line, indented, and ends the line before
+    /// the closing bracket. The commas of the tuples stay, while the line
+    /// breaks replace the semicolons.
+    fn elements<T>(
+        &mut self,
+        span: SourceSpan,
+        elements: &[T],
+        separator: &str,
+        span_of: impl Fn(&T) -> SourceSpan,
+        print: impl Fn(&mut Self, &T),
+    ) {
+        let separator = separator.trim_end().trim_end_matches(';');
+        self.indent += 1;
+        for (i, element) in elements.iter().enumerate() {
+            self.line_break(span_of(element).start);
+            self.leading_comments(span_of(element).start);
+            print(self, element);
+            if i + 1 < elements.len() {
+                self.push(separator);
+            }
+            self.advance_to(span_of(element));
+        }
+        self.trailing_comment(span.end);
+        while let Some(&comment) = self.comments.get(self.next_comment) {
+            if comment.start >= span.end {
+                break;
+            }
+            self.newline(false);
+            self.comment(comment);
+        }
+        self.indent -= 1;
+        self.line_break(span.end);
+    }
+
+    /// Prints the code on one line if it fits, and otherwise with the line
+    /// breaks of its layout.
+    fn group(&mut self, span: SourceSpan, print: impl Fn(&mut Self)) {
+        if self.flat || !self.try_flat(span, &print) {
+            print(self);
+        }
+    }
+
+    /// Prints the code on one line, or nothing if it has to span several
+    /// lines, would overflow the width, or has comments.
+    fn try_flat(&mut self, span: SourceSpan, print: impl Fn(&mut Self)) -> bool {
+        if self.comments[self.next_comment..]
+            .iter()
+            .any(|comment| span.start <= comment.start && comment.start < span.end)
+        {
+            return false;
+        }
+        let start = self.output.len();
+        self.flat = true;
+        self.broken = false;
+        print(self);
+        self.flat = false;
+        if !self.broken && self.column() <= self.config.width {
+            return true;
+        }
+        self.output.truncate(start);
+        false
+    }
+
+    fn leading_comments(&mut self, pos: usize) {
+        while let Some(&comment) = self.comments.get(self.next_comment) {
+            if comment.start >= pos {
+                break;
+            }
+            self.comment(comment);
+            let blank = self.blank_line_before(pos);
+            self.newline(blank);
+        }
+    }
+
+    /// Prints the comment ending a line of code in the source, if there is
+    /// one before the code at the position, which is printed next.
+    fn trailing_comment(&mut self, next: usize) {
+        if let Some(&comment) = self.comments.get(self.next_comment) {
+            if comment.start < next && follows_code(self.source_map, comment) {
+                self.push(" ");
+                self.comment(comment);
+            }
+        }
+    }
+
+    /// Finds the code following the position in the source, after the
+    /// layout and the comments, e.g. the keyword between two nodes.
+    fn code_after(&self, mut pos: usize) -> usize {
+        let file = self.source_map.lookup_file(pos);
+        let mut comments = self.comments[self.next_comment..].iter();
+        loop {
+            let rest = file.read_span(SourceSpan::new(pos, file.end_pos()));
+            pos += rest.len() - rest.trim_start().len();
+            match comments.find(|comment| comment.end > pos) {
+                Some(comment) if comment.start == pos => pos = comment.end,
+                _ => return pos,
+            }
+        }
+    }
+
+    fn comment(&mut self, comment: SourceSpan) {
+        self.output.push_str(self.snippet(comment).trim_end());
+        self.next_comment += 1;
+        self.last_end = comment.end;
+    }
+
+    /// Whether a blank line separates the code printed last from the code
+    /// or the comment before the position in the source.
+    fn blank_line_before(&self, pos: usize) -> bool {
+        let next = match self.comments.get(self.next_comment) {
+            Some(comment) if comment.start < pos => comment.start,
+            _ => pos,
+        };
+        if next < self.last_end {
+            return false;
+        }
+        let between = self.snippet(SourceSpan::new(self.last_end, next));
+        between[between.trim_end().len()..].matches('\n').count() > 1
+    }
+
+    /// Records the end of the node printed, without its trailing layout and
+    /// the comments ending its last line, which are printed after it.
+    fn advance_to(&mut self, span: SourceSpan) {
+        let mut end = span.end;
+        loop {
+            end = span.start
+                + self
+                    .snippet(SourceSpan::new(span.start, end))
+                    .trim_end()
+                    .len();
+            match self.comments[self.next_comment..]
+                .iter()
+                .find(|comment| comment.start < end && end <= comment.end)
+            {
+                Some(comment) if comment.start >= span.start => end = comment.start,
+                _ => break,
+            }
+        }
+        self.last_end = self.last_end.max(end);
+    }
+
+    /// Ends the line before the code at the position, with the comment
+    /// ending the line in the source.
+    fn line_break(&mut self, next: usize) {
+        if !self.flat {
+            self.trailing_comment(next);
+        }
+        self.newline(false);
+    }
+
+    /// Ends the line before the code at the position, keeping a blank line
+    /// if the source has one.
+    fn break_before(&mut self, pos: usize) {
+        self.trailing_comment(pos);
+        let blank = self.blank_line_before(pos);
+        self.newline(blank);
+    }
+
+    fn newline(&mut self, blank: bool) {
+        if self.flat {
+            self.broken = true;
+            return;
+        }
+        self.trim_line_end();
+        self.output.push('\n');
+        if blank {
+            self.output.push('\n');
+        }
+        let indent = self.indent * self.config.indent;
+        self.output.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    fn trim_line_end(&mut self) {
+        let len = self.output.trim_end_matches(' ').len();
+        self.output.truncate(len);
+    }
+
+    fn column(&self) -> usize {
+        let line_start = self.output.rfind('\n').map_or(0, |i| i + 1);
+        self.output[line_start..].chars().count()
+    }
+
+    /// Prints the name of a binding, in parentheses if it is an operator.
+    fn name(&mut self, identifier: &ast::Literal) {
+        if self.is_operator(identifier) {
+            self.push("(");
+            self.literal(identifier);
+            self.push(")");
+        } else {
+            self.literal(identifier);
+        }
+    }
+
+    fn is_operator(&self, literal: &ast::Literal) -> bool {
+        self.snippet(literal.span)
+            .starts_with(token::is_operator_start)
+    }
+
+    fn path(&mut self, path: &ast::Path) {
+        for (i, segment) in path.segments.iter().enumerate() {
+            if i > 0 {
+                self.push(".");
+            }
+            self.literal(segment);
+        }
+    }
+
+    fn literal(&mut self, literal: &ast::Literal) {
+        self.push_span(literal.span);
+    }
+
+    fn push_span(&mut self, span: SourceSpan) {
+        self.output.push_str(self.source_map.span_to_snippet(span));
+    }
+
+    fn push(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+
+    fn snippet(&self, span: SourceSpan) -> &'a str {
+        self.source_map.span_to_snippet(span)
+    }
+}
+
+fn body_span(body: &ast::LetBody) -> SourceSpan {
+    match body {
+        ast::LetBody::Block(block) => block.span,
+        ast::LetBody::Expr(expr) => expr.span,
+    }
+}
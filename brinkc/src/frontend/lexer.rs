@@ -1,6 +1,6 @@
 use std::{iter::Peekable, str::CharIndices};
 
-use crate::source_file::{IndentKind, SourceFile};
+use crate::source_file::{IndentKind, SourceFile, SourceSpan};
 
 use super::{
     token::{self, Token, TokenKind},
@@ -10,41 +10,98 @@ use super::{
 macro_rules! tokenize_operator {
     ($lexer:ident, $start:ident, $c:ident, $short_lexeme:literal, $short_kind:ident) => {
         if $c == $short_lexeme {
-            $lexer
-                .tokens
-                .push(Token::with_length(TokenKind::$short_kind, $start, 1));
+            $lexer.add_token(TokenKind::$short_kind, $start, 1);
+            continue;
+        }
+    };
+    (
+        $lexer:ident, $start:ident, $c:ident,
+        $short_lexeme:literal, $short_kind:ident
+        $(, $long_lexeme:literal, $long_kind:ident)+
+    ) => {
+        if $c == $short_lexeme {
+            $(
+                if $lexer
+                    .source_code
+                    .next_if(|(_, c)| *c == $long_lexeme)
+                    .is_some()
+                {
+                    $lexer.add_token(TokenKind::$long_kind, $start, 2);
+                    continue;
+                }
+            )+
+            $lexer.add_token(TokenKind::$short_kind, $start, 1);
             continue;
         }
     };
 }
+
 /// Holds the lexer state during parsing.
 pub struct Lexer<'a> {
     source_code: Peekable<CharIndices<'a>>,
     tokens: Vec<Token>,
     indent_kind: IndentKind,
     current_indent: u8,
+    /// The numbers of the braces opened inside of each interpolated string
+    /// hole being tokenized, innermost last. The hole ends with a closing
+    /// brace when the number is zero.
+    interpolation_holes: Vec<usize>,
+    /// The line comments, which are not tokens, so the parser never sees
+    /// them.
+    comments: Vec<SourceSpan>,
+    /// Global position of the source code in the `SourceMap`.
+    start_pos: usize,
 }
 
 impl<'a> Lexer<'a> {
     /// Turns the given source code into a sequence of tokens.
     pub fn tokenize(source_file: &'a SourceFile) -> Tokens {
-        Tokens::new(Self::tokenize_source_code(
+        Tokens::new(Self::tokenize_source_code_at(
             &source_file.source_code,
             source_file.indent_kind,
+            source_file.start_pos,
         ))
     }
 
     pub fn tokenize_source_code(source_code: &'a str, indent_kind: IndentKind) -> Vec<Token> {
+        Self::tokenize_source_code_at(source_code, indent_kind, 0)
+    }
+
+    /// Tokenizes the source code, offsetting all the spans by its global
+    /// position.
+    pub fn tokenize_source_code_at(
+        source_code: &'a str,
+        indent_kind: IndentKind,
+        start_pos: usize,
+    ) -> Vec<Token> {
+        Self::run_at(source_code, indent_kind, start_pos).tokens
+    }
+
+    /// Finds the spans of the line comments in the source file, from the
+    /// `#` to the end of the line.
+    pub fn comments(source_file: &'a SourceFile) -> Vec<SourceSpan> {
+        Self::run_at(
+            &source_file.source_code,
+            source_file.indent_kind,
+            source_file.start_pos,
+        )
+        .comments
+    }
+
+    fn run_at(source_code: &'a str, indent_kind: IndentKind, start_pos: usize) -> Self {
         let mut lexer = Self {
             source_code: source_code.char_indices().peekable(),
             indent_kind,
             tokens: Vec::new(),
             current_indent: 0,
+            interpolation_holes: Vec::new(),
+            comments: Vec::new(),
+            start_pos,
         };
 
         lexer.run(source_code.len());
 
-        lexer.tokens
+        lexer
     }
 
     fn run(&mut self, source_code_length: usize) {
@@ -58,17 +115,79 @@ impl<'a> Lexer<'a> {
                 continue;
             }
 
+            if c == '#' {
+                self.skip_comment(start);
+                continue;
+            }
+
             if c.is_ascii_digit() {
                 self.tokenize_integer(start);
                 continue;
             }
 
-            if c.is_alphabetic() {
+            if c.is_alphabetic() || c == '_' {
                 self.tokenize_identifier(start, c);
                 continue;
             }
 
-            tokenize_operator!(self, start, c, '=', Equal);
+            if c == '\'' {
+                self.tokenize_type_variable(start);
+                continue;
+            }
+
+            if c == '"' {
+                self.tokenize_string(start);
+                continue;
+            }
+
+            if c == '$' && self.source_code.next_if(|(_, c)| *c == '"').is_some() {
+                self.tokenize_interpolated_text(start, true);
+                continue;
+            }
+
+            if c == '@'
+                && !self.follows_operand()
+                && self
+                    .source_code
+                    .peek()
+                    .is_some_and(|(_, c)| c.is_alphabetic())
+            {
+                self.tokenize_attribute(start);
+                continue;
+            }
+
+            if c == '{' {
+                if let Some(braces) = self.interpolation_holes.last_mut() {
+                    *braces += 1;
+                }
+            }
+
+            if c == '}' {
+                match self.interpolation_holes.last_mut() {
+                    Some(0) => {
+                        self.interpolation_holes.pop();
+                        self.tokenize_interpolated_text(start, false);
+                        continue;
+                    }
+                    Some(braces) => *braces -= 1,
+                    None => {}
+                }
+            }
+
+            if token::is_operator_start(c) {
+                self.tokenize_symbolic_operator(start, c);
+                continue;
+            }
+
+            tokenize_operator!(self, start, c, '.', Dot);
+            tokenize_operator!(self, start, c, ',', Comma);
+            tokenize_operator!(self, start, c, ';', Semicolon);
+            tokenize_operator!(self, start, c, '(', LeftParen);
+            tokenize_operator!(self, start, c, ')', RightParen);
+            tokenize_operator!(self, start, c, '{', LeftBrace);
+            tokenize_operator!(self, start, c, '}', RightBrace);
+            tokenize_operator!(self, start, c, '[', LeftBracket, '|', LeftBracketBar);
+            tokenize_operator!(self, start, c, ']', RightBracket);
 
             self.add_token(TokenKind::Invalid, start, 1);
         }
@@ -77,6 +196,9 @@ impl<'a> Lexer<'a> {
     }
 
     fn tokenize_newline(&mut self, start: usize) {
+        // The interpolated strings cannot span multiple lines, so the holes
+        // left open are reported by the parser.
+        self.interpolation_holes.clear();
         if let IndentKind::Spaces(spaces_per_indent) = self.indent_kind {
             self.tokenize_newline_using_spaces(start, spaces_per_indent);
         } else {
@@ -173,7 +295,10 @@ impl<'a> Lexer<'a> {
 
     fn tokenize_identifier(&mut self, start: usize, first_char: char) {
         let mut identifier = String::from(first_char);
-        while let Some((_, c)) = self.source_code.next_if(|(_, c)| c.is_alphanumeric()) {
+        while let Some((_, c)) = self
+            .source_code
+            .next_if(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '\'')
+        {
             identifier.push(c);
         }
 
@@ -184,14 +309,166 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Tokenizes the longest sequence of the operator characters, except
+    /// for the bar closing an array, e.g. in `[|1|]`.
+    fn tokenize_symbolic_operator(&mut self, start: usize, first_char: char) {
+        let mut operator = String::from(first_char);
+        while let Some((_, c)) = self
+            .source_code
+            .next_if(|(_, c)| token::is_operator_part(*c))
+        {
+            operator.push(c);
+        }
+
+        let closes_array =
+            operator.ends_with('|') && matches!(self.source_code.peek(), Some((_, ']')));
+        if closes_array {
+            operator.pop();
+        }
+        if !operator.is_empty() {
+            let kind = token::get_operator_kind(&operator).unwrap_or(TokenKind::Operator);
+            self.add_token(kind, start, operator.len());
+        }
+        if closes_array {
+            let _ = self.source_code.next();
+            self.add_token(TokenKind::BarRightBracket, start + operator.len(), 2);
+        }
+    }
+
+    /// Tokenizes a string literal, which cannot span multiple lines. The
+    /// escape sequences are checked by the parser.
+    fn tokenize_string(&mut self, start: usize) {
+        let mut end = start + 1;
+        let mut escaped = false;
+        while let Some((i, c)) = self.source_code.next_if(|(_, c)| *c != '\n') {
+            end = i + c.len_utf8();
+            if c == '"' && !escaped {
+                self.add_token(TokenKind::String, start, end - start);
+                return;
+            }
+            escaped = c == '\\' && !escaped;
+        }
+        self.add_token(TokenKind::UnterminatedString, start, end - start);
+    }
+
+    /// Tokenizes the text of an interpolated string following either its
+    /// opening `$"` or the `}` closing a hole, up to the `{` opening the
+    /// next hole or the closing quote. The doubled braces are a part of the
+    /// text.
+    fn tokenize_interpolated_text(&mut self, start: usize, is_first: bool) {
+        let mut end = self.source_code.peek().map_or(start + 1, |(i, _)| *i);
+        let mut escaped = false;
+        while let Some((i, c)) = self.source_code.next_if(|(_, c)| *c != '\n') {
+            end = i + c.len_utf8();
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            let kind = match c {
+                '"' if is_first => TokenKind::InterpolatedString,
+                '"' => TokenKind::InterpolationEnd,
+                '{' if self.source_code.next_if(|(_, c)| *c == '{').is_some() => {
+                    end += 1;
+                    continue;
+                }
+                '{' if is_first => TokenKind::InterpolationStart,
+                '{' => TokenKind::InterpolationMiddle,
+                '}' => {
+                    if self.source_code.next_if(|(_, c)| *c == '}').is_some() {
+                        end += 1;
+                    }
+                    continue;
+                }
+                c => {
+                    escaped = c == '\\';
+                    continue;
+                }
+            };
+            if c == '{' {
+                self.interpolation_holes.push(0);
+            }
+            self.add_token(kind, start, end - start);
+            return;
+        }
+        self.add_token(TokenKind::UnterminatedString, start, end - start);
+    }
+
+    fn tokenize_type_variable(&mut self, start: usize) {
+        let mut length = 1;
+        while let Some((_, c)) = self
+            .source_code
+            .next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+        {
+            length += c.len_utf8();
+        }
+
+        if length == 1 {
+            self.add_token(TokenKind::Invalid, start, length);
+        } else {
+            self.add_token(TokenKind::TypeVariable, start, length);
+        }
+    }
+
+    /// Tokenizes an attribute, the at sign directly followed by a name where
+    /// an expression starts, so the operators starting with it, e.g. `@`,
+    /// need a space before a name on their right only in that position.
+    fn tokenize_attribute(&mut self, start: usize) {
+        let mut length = 1;
+        while let Some((_, c)) = self
+            .source_code
+            .next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+        {
+            length += c.len_utf8();
+        }
+        self.add_token(TokenKind::Attribute, start, length);
+    }
+
+    /// Skips a comment up to the end of its line. A line holding only a
+    /// comment is as good as blank, so it does not affect the layout.
+    fn skip_comment(&mut self, start: usize) {
+        let mut end = start + 1;
+        while let Some((i, c)) = self.source_code.next_if(|(_, c)| *c != '\n') {
+            end = i + c.len_utf8();
+        }
+        self.comments.push(SourceSpan::new(
+            self.start_pos + start,
+            self.start_pos + end,
+        ));
+    }
+
+    /// Whether the last token ends an operand, so an at sign after it is
+    /// an operator, e.g. in `xs@ys`.
+    fn follows_operand(&self) -> bool {
+        self.tokens.last().is_some_and(|token| {
+            matches!(
+                token.kind,
+                TokenKind::Identifier
+                    | TokenKind::TypeVariable
+                    | TokenKind::Integer
+                    | TokenKind::String
+                    | TokenKind::InterpolatedString
+                    | TokenKind::InterpolationEnd
+                    | TokenKind::True
+                    | TokenKind::False
+                    | TokenKind::Underscore
+                    | TokenKind::RightParen
+                    | TokenKind::RightBrace
+                    | TokenKind::RightBracket
+                    | TokenKind::BarRightBracket
+            )
+        })
+    }
+
     fn add_token(&mut self, kind: TokenKind, start: usize, length: usize) {
-        self.tokens.push(Token::with_length(kind, start, length));
+        self.tokens
+            .push(Token::with_length(kind, self.start_pos + start, length));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::source_file::SourceSpan;
 
     #[test]
     fn ignores_whitespace() {
@@ -327,11 +604,239 @@ mod tests {
 
         assert_eq!(TokenKind::Equal, result.kind)
     }
+
+    #[test]
+    fn tokenizes_long_operator() {
+        let input = "- ->";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+
+        assert_eq!(TokenKind::Minus, result[0].kind);
+        assert_eq!(TokenKind::Arrow, result[1].kind);
+        assert_eq!(2, result[1].span.len());
+    }
+
+    #[test]
+    fn tokenizes_one_of_long_operators() {
+        let input = "< <= <>";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+
+        assert_eq!(TokenKind::Less, result[0].kind);
+        assert_eq!(TokenKind::LessEqual, result[1].kind);
+        assert_eq!(TokenKind::NotEqual, result[2].kind);
+    }
+
+    #[test]
+    fn tokenizes_array_brackets() {
+        use TokenKind::*;
+        let input = "[| [] |] ::";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                LeftBracketBar,
+                LeftBracket,
+                RightBracket,
+                BarRightBracket,
+                ColonColon,
+                EndOfFile
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn tokenizes_reference_operators() {
+        use TokenKind::*;
+        let input = "r := !r : int";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![Identifier, ColonEqual, Bang, Identifier, Colon, Identifier, EndOfFile],
+            kinds
+        );
+    }
+
+    #[test]
+    fn tokenizes_longest_operator() {
+        use TokenKind::*;
+        let input = "x |> f >>= g <= [|1|] +. ::";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Identifier,
+                Operator,
+                Identifier,
+                Operator,
+                Identifier,
+                LessEqual,
+                LeftBracketBar,
+                Integer,
+                BarRightBracket,
+                Operator,
+                ColonColon,
+                EndOfFile
+            ],
+            kinds
+        );
+        assert_eq!(3, result[3].span.len());
+    }
+
+    #[test]
+    fn tokenizes_attribute() {
+        use TokenKind::*;
+        let input = "@tailcall f (xs @ ys) @@ zs";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Attribute, Identifier, LeftParen, Identifier, Operator, Identifier, RightParen,
+                Operator, Identifier, EndOfFile
+            ],
+            kinds
+        );
+        assert_eq!(9, result[0].span.len());
+
+        // After an operand, the at sign is an operator.
+        let input = "xs@ys [1]@[2] (@tailcall g x)";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Identifier,
+                Operator,
+                Identifier,
+                LeftBracket,
+                Integer,
+                RightBracket,
+                Operator,
+                LeftBracket,
+                Integer,
+                RightBracket,
+                LeftParen,
+                Attribute,
+                Identifier,
+                Identifier,
+                RightParen,
+                EndOfFile
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn tokenizes_string() {
+        use TokenKind::*;
+        let input = r#"f "a \"b\" c" "ą" "open"#;
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![Identifier, String, String, UnterminatedString, EndOfFile],
+            kinds
+        );
+        assert_eq!(11, result[1].span.len());
+        assert_eq!(4, result[2].span.len());
+    }
+
+    #[test]
+    fn tokenizes_interpolated_string() {
+        use TokenKind::*;
+        let input = r#"$"a {x} {{b}} { f { y = 1 }.y }." $"c" $"{"#;
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                InterpolationStart,
+                Identifier,
+                InterpolationMiddle,
+                Identifier,
+                LeftBrace,
+                Identifier,
+                Equal,
+                Integer,
+                RightBrace,
+                Dot,
+                Identifier,
+                InterpolationEnd,
+                InterpolatedString,
+                InterpolationStart,
+                EndOfFile
+            ],
+            kinds
+        );
+        assert_eq!(SourceSpan::new(0, 5), result[0].span);
+        assert_eq!(SourceSpan::new(6, 15), result[2].span);
+        assert_eq!(SourceSpan::new(30, 33), result[11].span);
+    }
+
+    #[test]
+    fn tokenizes_type_variable() {
+        let input = "'a";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab)[0];
+
+        assert_eq!(TokenKind::TypeVariable, result.kind);
+        assert_eq!(2, result.span.len());
+    }
+
+    #[test]
+    fn tokenizes_qualified_path() {
+        let input = "List.map";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+
+        assert_eq!(TokenKind::Identifier, result[0].kind);
+        assert_eq!(TokenKind::Dot, result[1].kind);
+        assert_eq!(TokenKind::Identifier, result[2].kind);
+    }
+
+    #[test]
+    fn tokenizes_keyword() {
+        let input = "pub module Inner_1";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Tab);
+
+        assert_eq!(TokenKind::Pub, result[0].kind);
+        assert_eq!(TokenKind::Module, result[1].kind);
+        assert_eq!(TokenKind::Identifier, result[2].kind);
+        assert_eq!(7, result[2].span.len());
+    }
     #[test]
     fn tokenizes_invalid() {
-        let input = "@";
+        let input = "`";
         let result = Lexer::tokenize_source_code(input, IndentKind::Tab)[0];
 
         assert_eq!(TokenKind::Invalid, result.kind);
     }
+
+    #[test]
+    fn skips_comments() {
+        use TokenKind::*;
+        let input = "let x = 1 # one\n# \"not a string\nf \"#\"";
+        let result = Lexer::tokenize_source_code(input, IndentKind::Spaces(2));
+        let kinds = result.iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![Let, Identifier, Equal, Integer, NewLine, NewLine, Identifier, String, EndOfFile],
+            kinds
+        );
+        let comments = Lexer::run_at(input, IndentKind::Spaces(2), 0).comments;
+        assert_eq!(
+            vec![SourceSpan::new(10, 15), SourceSpan::new(16, 31)],
+            comments
+        );
+    }
+
+    #[test]
+    fn offsets_spans_by_global_position() {
+        let input = "let x";
+        let result = Lexer::tokenize_source_code_at(input, IndentKind::Tab, 100);
+
+        assert_eq!(SourceSpan::new(104, 105), result[1].span);
+        assert_eq!(SourceSpan::new(105, 105), result[2].span);
+    }
 }
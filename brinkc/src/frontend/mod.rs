@@ -1,4 +1,7 @@
-use crate::source_file::IndentKind;
+use crate::{
+    ast,
+    source_file::{FileId, IndentKind},
+};
 
 pub mod lexer;
 pub mod parse_session;
@@ -6,13 +9,43 @@ pub mod parser;
 pub mod token;
 pub mod tokens;
 
-use self::{parse_session::ParseSession, token::TokenKind, tokens::Tokens};
+use self::{
+    lexer::Lexer, parse_session::ParseSession, parser::Parser, token::TokenKind, tokens::Tokens,
+};
+
+/// Runs the whole frontend on a file loaded into the session's source map.
+/// Returns `None` if the indentation of the file is malformed.
+pub fn parse_file(session: &mut ParseSession, file: FileId) -> Option<ast::Program> {
+    let tokens = tokenize_file(session, file)?;
+    Some(Parser::parse(session, file, tokens))
+}
+
+/// Runs the whole frontend on the prelude, whose fixity declarations apply
+/// to the files parsed after it.
+pub fn parse_prelude(session: &mut ParseSession, file: FileId) -> Option<ast::Program> {
+    let tokens = tokenize_file(session, file)?;
+    Some(Parser::parse_prelude(session, file, tokens))
+}
+
+/// Lexes the file and checks its indentation. Returns `None` if the
+/// indentation is malformed, as the layout tokens cannot be trusted then.
+pub fn tokenize_file(session: &mut ParseSession, file: FileId) -> Option<Tokens> {
+    let tokens = Lexer::tokenize(session.source_map.file(file));
+
+    let error_count = session.error_count();
+    find_mixed_and_invalid_indentations(session, &tokens);
+    if session.error_count() != error_count {
+        return None;
+    }
+    Some(tokens)
+}
 
 pub fn find_mixed_and_invalid_indentations(session: &mut ParseSession, tokens: &Tokens) {
     for token in tokens.as_vec() {
+        let indent_kind = session.source_map.lookup_file(token.span.start).indent_kind;
         match token.kind {
             TokenKind::MixedIndentation => {
-                if let IndentKind::Spaces(spaces_per_indent) = session.source_file.indent_kind {
+                if let IndentKind::Spaces(spaces_per_indent) = indent_kind {
                     session.error(
                         token.span,
                         format!(
@@ -29,7 +62,7 @@ pub fn find_mixed_and_invalid_indentations(session: &mut ParseSession, tokens: &
             }
 
             TokenKind::InvalidIndentation => {
-                if let IndentKind::Spaces(spaces_per_indent) = session.source_file.indent_kind {
+                if let IndentKind::Spaces(spaces_per_indent) = indent_kind {
                     session.error(
                         token.span,
                         format!(
@@ -1,41 +1,124 @@
-use crate::source_file::{SourceFile, SourceSpan};
+use std::collections::HashMap;
+
+use crate::{
+    ast::node_id::NodeIdGenerator,
+    source_file::{SourceMap, SourceSpan},
+};
+
+use super::parser::Fixity;
+
+/// A diagnostic captured instead of being reported, see
+/// `ParseSession::begin_capture`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: SourceSpan,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
 
 pub struct ParseSession {
-    pub source_file: SourceFile,
+    pub source_map: SourceMap,
+    /// Shared by all the parsed files, so the node ids are unique across
+    /// the whole compilation.
+    pub node_id_generator: NodeIdGenerator,
+    /// The fixities of the operators declared in the prelude, which apply
+    /// to every file.
+    pub prelude_fixities: HashMap<String, Fixity>,
     error_count: u32,
     warning_count: u32,
+    /// The diagnostics being captured, innermost capture last, with the
+    /// counts to restore when the capture ends.
+    captures: Vec<(Vec<Diagnostic>, u32, u32)>,
 }
 
 impl ParseSession {
-    pub fn new(source_file: SourceFile) -> Self {
+    pub fn new(source_map: SourceMap) -> Self {
         Self {
-            source_file,
+            source_map,
+            node_id_generator: NodeIdGenerator::new(),
+            prelude_fixities: HashMap::new(),
             error_count: 0,
             warning_count: 0,
+            captures: Vec::new(),
         }
     }
 
     pub fn error<S: AsRef<str>>(&mut self, span: SourceSpan, message: S) {
-        self.error_count += 1;
-
-        eprintln!("error: {}", message.as_ref());
-        eprintln!(
-            " -> {}:{}-{}",
-            self.source_file.file_path, span.start, span.end
-        );
-        println!();
+        self.emit(Diagnostic {
+            severity: Severity::Error,
+            span,
+            message: message.as_ref().to_string(),
+        });
     }
 
-    #[allow(dead_code)]
     pub fn warning<S: AsRef<str>>(&mut self, span: SourceSpan, message: S) {
-        self.error_count += 1;
+        self.emit(Diagnostic {
+            severity: Severity::Warning,
+            span,
+            message: message.as_ref().to_string(),
+        });
+    }
+
+    /// Reports the diagnostic, or adds it to the innermost capture.
+    pub fn emit(&mut self, diagnostic: Diagnostic) {
+        match diagnostic.severity {
+            Severity::Error => self.error_count += 1,
+            Severity::Warning => self.warning_count += 1,
+        }
+        match self.captures.last_mut() {
+            Some((captured, _, _)) => captured.push(diagnostic),
+            None => self.report(diagnostic.severity, diagnostic.span, &diagnostic.message),
+        }
+    }
+
+    /// Starts capturing the diagnostics rather than reporting them, so they
+    /// can be reported later, e.g. by a memoized query. The captures nest.
+    pub fn begin_capture(&mut self) {
+        self.captures
+            .push((Vec::new(), self.error_count, self.warning_count));
+    }
+
+    /// Ends the innermost capture, returning its diagnostics. They are not
+    /// counted until they are emitted.
+    pub fn end_capture(&mut self) -> Vec<Diagnostic> {
+        let (captured, error_count, warning_count) = self.captures.pop().unwrap();
+        self.error_count = error_count;
+        self.warning_count = warning_count;
+        captured
+    }
+
+    fn report(&self, severity: Severity, span: SourceSpan, message: &str) {
+        let position = self.source_map.lookup_position(span.start);
+        let line = self.source_map.file(position.file).line_text(position.line);
+        let marker_length = span
+            .len()
+            .min(line.chars().count().saturating_sub(position.column - 1))
+            .max(1);
 
-        eprintln!("warning: {}", message.as_ref());
+        eprintln!("{}: {}", severity.as_str(), message);
+        eprintln!(" -> {}", self.source_map.span_to_location(span));
+        eprintln!("  | {}", line);
         eprintln!(
-            " -> {}:{}-{}",
-            self.source_file.file_path, span.start, span.end
+            "  | {}{}",
+            " ".repeat(position.column - 1),
+            "^".repeat(marker_length)
         );
-        println!();
+        eprintln!();
     }
 
     pub fn error_count(&self) -> u32 {
@@ -1,153 +1,1570 @@
+use std::{collections::HashMap, fmt};
+
 use crate::{
-    ast::{self, node_id::NodeIdGenerator},
-    source_file::SourceSpan,
+    ast::{self, node_id::NodeId},
+    source_file::{FileId, SourceMap, SourceSpan},
 };
 
 use super::{
     parse_session::ParseSession,
-    token::{Token, TokenKind},
+    token::{self, Token, TokenKind},
     tokens::Tokens,
 };
 
-#[allow(dead_code)]
 pub struct Parser<'a> {
-    session: &'a ParseSession,
+    session: &'a mut ParseSession,
     tokens: Tokens,
-    id_generator: NodeIdGenerator,
+    /// The fixities of the user-defined operators declared so far. The
+    /// other user-defined operators have the `DEFAULT_FIXITY`.
+    fixities: HashMap<String, Fixity>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn parse(session: &'a ParseSession, tokens: Tokens) -> ast::Program {
+    /// Parses the file, starting with the fixities declared in the prelude.
+    pub fn parse(session: &'a mut ParseSession, file: FileId, tokens: Tokens) -> ast::Program {
+        let fixities = session.prelude_fixities.clone();
+        Self::parse_with_fixities(session, file, tokens, fixities).0
+    }
+
+    /// Parses the prelude, whose fixity declarations apply to all the files
+    /// parsed after it.
+    pub fn parse_prelude(session: &mut ParseSession, file: FileId, tokens: Tokens) -> ast::Program {
+        let (program, fixities) =
+            Parser::parse_with_fixities(session, file, tokens, HashMap::new());
+        session.prelude_fixities = fixities;
+        program
+    }
+
+    fn parse_with_fixities(
+        session: &'a mut ParseSession,
+        file: FileId,
+        tokens: Tokens,
+        fixities: HashMap<String, Fixity>,
+    ) -> (ast::Program, HashMap<String, Fixity>) {
         let mut parser = Self {
             session,
             tokens,
-            id_generator: NodeIdGenerator::new(),
+            fixities,
         };
-        parser.parse_program()
+        let program = parser.parse_program(file);
+        (program, parser.fixities)
     }
 
-    fn parse_program(&mut self) -> ast::Program {
-        let mut body = Vec::new();
-        while !self.tokens.at_end() {
-            body.push(self.parse_item().unwrap());
-        }
+    fn parse_program(&mut self, file: FileId) -> ast::Program {
+        let body = self.parse_items();
         ast::Program {
-            id: self.id_generator.next_id(),
+            id: self.next_id(),
+            file,
             body,
         }
     }
 
     fn parse_block(&mut self) -> Result<ast::Block, ParseError> {
         let start = self.expect(TokenKind::Indent)?.span.start;
-        let mut items = Vec::new();
-        while !self.tokens.at_end() && !self.tokens.check(TokenKind::Dedent) {
-            items.push(self.parse_item()?);
-        }
+        let items = self.parse_items();
         let _ = self.tokens.consume(TokenKind::Dedent);
         let span = SourceSpan::new(start, self.tokens.previous().span.end);
         Ok(ast::Block {
-            id: self.id_generator.next_id(),
+            id: self.next_id(),
             span,
             items,
         })
     }
 
+    /// Parses a sequence of items, each starting on its own line, until the
+    /// end of the current block. Erroneous items are reported and skipped.
+    fn parse_items(&mut self) -> Vec<ast::Item> {
+        let mut items = Vec::new();
+        while !self.tokens.at_end() && !self.tokens.check(TokenKind::Dedent) {
+            match self.parse_item().and_then(|item| {
+                self.expect_item_end()?;
+                Ok(item)
+            }) {
+                Ok(item) => items.push(item),
+                Err(error) => {
+                    self.session.error(error.span, error.message);
+                    self.recover_to_next_item();
+                }
+            }
+        }
+        items
+    }
+
     fn parse_item(&mut self) -> Result<ast::Item, ParseError> {
-        if self.tokens.consume(TokenKind::Let).is_some() {
+        let start = self.tokens.peek().span.start;
+        let first_id = self.session.node_id_generator.peek();
+        let visibility = if self.tokens.consume(TokenKind::Pub).is_some() {
+            ast::Visibility::Public
+        } else {
+            ast::Visibility::Private
+        };
+
+        let kind = if self.tokens.consume(TokenKind::Let).is_some() {
             let let_binding = self.parse_let_binding()?;
-            let span = let_binding.span.clone();
-            Ok(ast::Item {
-                id: self.id_generator.next_id(),
-                span,
-                kind: ast::ItemKind::LetBinding(let_binding),
-            })
+            if self.continues_let_rec(&let_binding) {
+                ast::ItemKind::LetRec(self.parse_let_rec_group(let_binding)?)
+            } else if self.tokens.check(TokenKind::In) {
+                if visibility == ast::Visibility::Public {
+                    return Err(ParseError {
+                        span: self.tokens.peek().span,
+                        message: "let expressions cannot be public".to_string(),
+                    });
+                }
+                ast::ItemKind::Expr(self.parse_let_expr_tail(let_binding)?)
+            } else {
+                ast::ItemKind::LetBinding(let_binding)
+            }
+        } else if self.tokens.consume(TokenKind::Type).is_some() {
+            ast::ItemKind::Type(self.parse_type_decl()?)
+        } else if self.tokens.consume(TokenKind::Module).is_some() {
+            ast::ItemKind::Module(self.parse_module_decl()?)
+        } else if self.tokens.consume(TokenKind::Open).is_some() {
+            ast::ItemKind::Open(self.parse_path()?)
+        } else if self.tokens.consume(TokenKind::Import).is_some() {
+            ast::ItemKind::Import(self.parse_path()?)
+        } else if self.tokens.consume(TokenKind::Exception).is_some() {
+            let ty = self.parse_ty()?;
+            ast::ItemKind::Exception(self.ty_to_constructor(ty)?)
+        } else if let Some(associativity) = fixity_associativity(self.tokens.peek().kind) {
+            if visibility == ast::Visibility::Public {
+                return Err(ParseError {
+                    span: self.tokens.peek().span,
+                    message: "fixity declarations cannot be public".to_string(),
+                });
+            }
+            let _ = self.tokens.advance();
+            ast::ItemKind::Fixity(self.parse_fixity_decl(associativity)?)
+        } else if visibility == ast::Visibility::Public {
+            return Err(self.expected(r#"a declaration after "pub""#));
         } else {
-            let expr = self.parse_expr()?;
-            let span = expr.span.clone();
-            Ok(ast::Item {
-                id: self.id_generator.next_id(),
-                span,
-                kind: ast::ItemKind::Expr(expr),
-            })
+            ast::ItemKind::Expr(self.parse_expr()?)
+        };
+
+        let span = SourceSpan::new(start, self.tokens.previous().span.end);
+        Ok(ast::Item {
+            id: self.next_id(),
+            first_id,
+            span,
+            visibility,
+            kind,
+        })
+    }
+
+    /// Items are separated with newlines. An item ending with a block is
+    /// already terminated by the dedent closing that block.
+    fn expect_item_end(&mut self) -> Result<(), ParseError> {
+        if self.tokens.consume(TokenKind::NewLine).is_some()
+            || self.tokens.previous().kind == TokenKind::Dedent
+            || self.tokens.check(TokenKind::Dedent)
+            || self.tokens.at_end()
+        {
+            Ok(())
+        } else {
+            Err(self.expected("end of line"))
+        }
+    }
+
+    /// Skips the tokens up to the beginning of the next item on the same
+    /// indentation level.
+    fn recover_to_next_item(&mut self) {
+        let mut depth = 0;
+        while !self.tokens.at_end() {
+            match self.tokens.peek().kind {
+                TokenKind::Indent => depth += 1,
+                TokenKind::Dedent if depth == 0 => return,
+                TokenKind::Dedent => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let _ = self.tokens.advance();
+                        return;
+                    }
+                }
+                TokenKind::NewLine if depth == 0 => {
+                    let _ = self.tokens.advance();
+                    return;
+                }
+                _ => {}
+            }
+            let _ = self.tokens.advance();
         }
     }
 
     fn parse_let_binding(&mut self) -> Result<ast::LetBinding, ParseError> {
         let start = self.tokens.previous().span.start;
-        let identifier = self.expect_identifier()?;
+        let is_recursive = self.tokens.consume(TokenKind::Rec).is_some();
+        let identifier = self.parse_binding_name()?;
+        let mut parameters = Vec::new();
+        while self.starts_primary_pattern() {
+            parameters.push(self.parse_primary_pattern()?);
+        }
+        let return_ty = if self.tokens.consume(TokenKind::Colon).is_some() {
+            Some(self.parse_ty()?)
+        } else {
+            None
+        };
         let _ = self.expect(TokenKind::Equal)?;
         let body = self.parse_let_binding_body()?;
         let span = SourceSpan::new(start, self.tokens.previous().span.end);
         Ok(ast::LetBinding {
-            id: self.id_generator.next_id(),
+            id: self.next_id(),
             span,
+            is_recursive,
             identifier,
+            parameters,
+            return_ty,
             body,
         })
     }
 
+    /// Checks whether the recursive binding is followed by `and`, on its
+    /// line or at the start of the next one.
+    fn continues_let_rec(&mut self, let_binding: &ast::LetBinding) -> bool {
+        let_binding.is_recursive
+            && (self.tokens.check(TokenKind::And)
+                || (self.tokens.check(TokenKind::NewLine)
+                    && self.tokens.peek_second().kind == TokenKind::And))
+    }
+
+    /// Parses the bindings following the first one of a recursive group,
+    /// each introduced by `and`. The bindings of a group are all recursive.
+    fn parse_let_rec_group(
+        &mut self,
+        first: ast::LetBinding,
+    ) -> Result<Vec<ast::LetBinding>, ParseError> {
+        let mut bindings = vec![first];
+        while self.continues_let_rec(bindings.last().unwrap()) {
+            let _ = self.tokens.consume(TokenKind::NewLine);
+            let _ = self.expect(TokenKind::And)?;
+            if let Some(token) = self.tokens.consume(TokenKind::Rec) {
+                return Err(ParseError {
+                    span: token.span,
+                    message: "the bindings after `and` are recursive without `rec`".to_string(),
+                });
+            }
+            let mut let_binding = self.parse_let_binding()?;
+            let_binding.is_recursive = true;
+            bindings.push(let_binding);
+        }
+        Ok(bindings)
+    }
+
+    /// Parses the name of a let binding, which is either an identifier or
+    /// a user-defined operator in parentheses, e.g. `(|>)`.
+    fn parse_binding_name(&mut self) -> Result<ast::Literal, ParseError> {
+        if self.tokens.consume(TokenKind::LeftParen).is_none() {
+            return self.expect_identifier();
+        }
+        let token = self.expect_user_operator()?;
+        let _ = self.expect(TokenKind::RightParen)?;
+        Ok(self.literal(token, ast::LiteralKind::Identifier))
+    }
+
+    /// Parses a fixity declaration after its keyword, and applies it to the
+    /// rest of the file.
+    fn parse_fixity_decl(
+        &mut self,
+        associativity: ast::Associativity,
+    ) -> Result<ast::FixityDecl, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let token = self.tokens.peek();
+        let precedence = if token.kind == TokenKind::Integer {
+            self.session
+                .source_map
+                .span_to_snippet(token.span)
+                .parse::<u8>()
+                .ok()
+                .filter(|precedence| *precedence <= MAX_PRECEDENCE)
+        } else {
+            return Err(self.expected("a precedence"));
+        };
+        let precedence = precedence.ok_or_else(|| ParseError {
+            span: token.span,
+            message: format!("the precedence must be between 0 and {}", MAX_PRECEDENCE),
+        })?;
+        let _ = self.tokens.advance();
+        let precedence_literal = self.literal(token, ast::LiteralKind::Integer);
+
+        let mut operators = vec![self.expect_user_operator()?];
+        while !self.tokens.check(TokenKind::NewLine) && !self.tokens.at_end() {
+            operators.push(self.expect_user_operator()?);
+        }
+        let fixity = Fixity {
+            precedence,
+            associativity,
+        };
+        let operators = operators
+            .into_iter()
+            .map(|token| {
+                let name = self.session.source_map.span_to_snippet(token.span);
+                self.fixities.insert(name.to_string(), fixity);
+                self.literal(token, ast::LiteralKind::Identifier)
+            })
+            .collect();
+
+        Ok(ast::FixityDecl {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            associativity,
+            precedence: precedence_literal,
+            operators,
+        })
+    }
+
+    /// Parses a user-defined operator. The built-in operators cannot be
+    /// redefined, nor can their fixity change.
+    fn expect_user_operator(&mut self) -> Result<Token, ParseError> {
+        let token = self.tokens.peek();
+        if token.kind == TokenKind::Operator {
+            Ok(self.tokens.advance())
+        } else if binary_operator(token.kind).is_some() {
+            Err(ParseError {
+                span: token.span,
+                message: format!(
+                    "expected a user-defined operator, but `{}` is built in",
+                    self.session.source_map.span_to_snippet(token.span)
+                ),
+            })
+        } else {
+            Err(self.expected("an operator"))
+        }
+    }
+
     fn parse_let_binding_body(&mut self) -> Result<ast::LetBody, ParseError> {
         if self.tokens.check(TokenKind::Indent) {
             Ok(ast::LetBody::Block(self.parse_block()?))
         } else {
-            let expr = self.parse_expr()?;
-            let _ = self.expect(TokenKind::NewLine)?;
-            Ok(ast::LetBody::Expr(expr))
+            Ok(ast::LetBody::Expr(self.parse_expr()?))
         }
     }
 
-    fn parse_expr(&mut self) -> Result<ast::Expr, ParseError> {
-        self.parse_primary_expr()
+    /// Parses the `in` keyword and the body of a let expression. The body may
+    /// be an indented block, or continue on the next line.
+    fn parse_let_expr_tail(
+        &mut self,
+        let_binding: ast::LetBinding,
+    ) -> Result<ast::Expr, ParseError> {
+        let _ = self.expect(TokenKind::In)?;
+        if !self.tokens.check(TokenKind::Indent) {
+            let _ = self.tokens.consume(TokenKind::NewLine);
+        }
+        let body = self.parse_let_binding_body()?;
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(let_binding.span.start, self.tokens.previous().span.end),
+            kind: ast::ExprKind::Let(Box::new(let_binding), Box::new(body)),
+        })
     }
 
-    fn parse_primary_expr(&mut self) -> Result<ast::Expr, ParseError> {
-        if self.tokens.check(TokenKind::Integer) {
-            let token = self.tokens.consume(TokenKind::Integer).unwrap();
-            Ok(ast::Expr {
-                id: self.id_generator.next_id(),
+    /// Parses a type declaration. The right-hand side is a variant type if
+    /// it contains or starts with a bar, so a single-constructor variant type
+    /// must be written as `type Wrapper = | Wrap int`.
+    fn parse_type_decl(&mut self) -> Result<ast::TypeDecl, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let identifier = self.expect_capitalized_identifier("a type name")?;
+        let mut parameters = Vec::new();
+        loop {
+            if self.tokens.check(TokenKind::Identifier) {
+                parameters.push(self.expect_identifier()?);
+            } else if let Some(token) = self.tokens.consume(TokenKind::TypeVariable) {
+                parameters.push(self.literal(token, ast::LiteralKind::TypeVariable));
+            } else {
+                break;
+            }
+        }
+        let _ = self.expect(TokenKind::Equal)?;
+
+        let indented = self.tokens.consume(TokenKind::Indent).is_some();
+        let kind = if self.tokens.check(TokenKind::LeftBrace) {
+            ast::TypeDeclKind::Record(self.parse_record_type()?)
+        } else if self.tokens.check(TokenKind::Bar) {
+            ast::TypeDeclKind::Variant(self.parse_constructors(Vec::new())?)
+        } else {
+            let ty = self.parse_ty()?;
+            if self.check_bar(indented) {
+                let first = self.ty_to_constructor(ty)?;
+                ast::TypeDeclKind::Variant(self.parse_constructors(vec![first])?)
+            } else {
+                ast::TypeDeclKind::Alias(ty)
+            }
+        };
+        if indented {
+            let _ = self.tokens.consume(TokenKind::NewLine);
+            let _ = self.expect(TokenKind::Dedent)?;
+        }
+
+        let span = SourceSpan::new(start, self.tokens.previous().span.end);
+        Ok(ast::TypeDecl {
+            id: self.next_id(),
+            span,
+            identifier,
+            parameters,
+            kind,
+        })
+    }
+
+    /// Checks whether the next token is a bar, possibly on the next line if
+    /// the declaration is indented.
+    fn check_bar(&mut self, indented: bool) -> bool {
+        self.tokens.check(TokenKind::Bar)
+            || (indented
+                && self.tokens.check(TokenKind::NewLine)
+                && self.tokens.peek_second().kind == TokenKind::Bar)
+    }
+
+    fn parse_constructors(
+        &mut self,
+        mut constructors: Vec<ast::Constructor>,
+    ) -> Result<Vec<ast::Constructor>, ParseError> {
+        loop {
+            let _ = self.tokens.consume(TokenKind::NewLine);
+            if self.tokens.consume(TokenKind::Bar).is_none() {
+                return Ok(constructors);
+            }
+            let ty = self.parse_ty()?;
+            constructors.push(self.ty_to_constructor(ty)?);
+            if !self.check_bar(true) {
+                return Ok(constructors);
+            }
+        }
+    }
+
+    /// Reinterprets the type application as a constructor declaration, as
+    /// their syntax is the same.
+    fn ty_to_constructor(&mut self, ty: ast::Ty) -> Result<ast::Constructor, ParseError> {
+        match ty.kind {
+            ast::TyKind::Path(mut path, arguments)
+                if path.segments.len() == 1 && self.is_capitalized(path.segments[0].span) =>
+            {
+                Ok(ast::Constructor {
+                    id: self.next_id(),
+                    span: ty.span,
+                    identifier: path.segments.pop().unwrap(),
+                    arguments,
+                })
+            }
+            _ => Err(ParseError {
+                span: ty.span,
+                message: "expected a constructor".to_string(),
+            }),
+        }
+    }
+
+    fn parse_record_type(&mut self) -> Result<Vec<ast::FieldDecl>, ParseError> {
+        self.parse_record_fields(|parser| {
+            let identifier = parser.expect_identifier()?;
+            let _ = parser.expect(TokenKind::Colon)?;
+            let ty = parser.parse_ty()?;
+            Ok(ast::FieldDecl {
+                id: parser.next_id(),
+                span: SourceSpan::new(identifier.span.start, parser.tokens.previous().span.end),
+                identifier,
+                ty,
+            })
+        })
+    }
+
+    /// Parses the braced fields separated with semicolons or newlines.
+    fn parse_record_fields<T>(
+        &mut self,
+        mut parse_field: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let _ = self.expect(TokenKind::LeftBrace)?;
+        self.parse_record_fields_tail(&mut parse_field)
+    }
+
+    fn parse_record_fields_tail<T>(
+        &mut self,
+        parse_field: &mut impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let fields = self.parse_sequence_tail(TokenKind::RightBrace, parse_field)?;
+        if fields.is_empty() {
+            return Err(ParseError {
+                span: self.tokens.previous().span,
+                message: "records must have at least one field".to_string(),
+            });
+        }
+        Ok(fields)
+    }
+
+    /// Parses the elements separated by semicolons or newlines, up to the
+    /// closing bracket. The literals may span many lines, since the
+    /// indentation inside of brackets is insignificant.
+    fn parse_sequence_tail<T>(
+        &mut self,
+        close: TokenKind,
+        parse_element: &mut impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut elements = Vec::new();
+        loop {
+            while self.tokens.consume(TokenKind::NewLine).is_some()
+                || self.tokens.consume(TokenKind::Semicolon).is_some()
+            {}
+            if self.tokens.consume(close).is_some() {
+                break;
+            }
+            if !elements.is_empty() && !self.previous_is_separator() {
+                return Err(self.expected(&format!(r#""Semicolon" or "{:?}""#, close)));
+            }
+            elements.push(parse_element(self)?);
+        }
+        Ok(elements)
+    }
+
+    /// Parses the list or array elements following the opening bracket.
+    fn parse_brackets<T>(
+        &mut self,
+        mut parse_element: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let close = match self.tokens.advance().kind {
+            TokenKind::LeftBracket => TokenKind::RightBracket,
+            TokenKind::LeftBracketBar => TokenKind::BarRightBracket,
+            _ => unreachable!(),
+        };
+        self.parse_sequence_tail(close, &mut parse_element)
+    }
+
+    /// Parses the elements of a parenthesized tuple following the first one.
+    fn parse_tuple_tail<T>(
+        &mut self,
+        first: T,
+        mut parse_element: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut elements = vec![first];
+        while self.tokens.consume(TokenKind::Comma).is_some() {
+            self.skip_newlines();
+            elements.push(parse_element(self)?);
+            self.skip_newlines();
+        }
+        Ok(elements)
+    }
+
+    /// Skips the newlines, which are insignificant in some places inside of
+    /// brackets.
+    fn skip_newlines(&mut self) {
+        while self.tokens.consume(TokenKind::NewLine).is_some() {}
+    }
+
+    fn previous_is_separator(&mut self) -> bool {
+        matches!(
+            self.tokens.previous().kind,
+            TokenKind::NewLine | TokenKind::Semicolon
+        )
+    }
+
+    /// Parses a type. The arrows are right associative and bind looser than
+    /// the tuple stars, which bind looser than the type applications.
+    fn parse_ty(&mut self) -> Result<ast::Ty, ParseError> {
+        let ty = self.parse_tuple_ty()?;
+        if self.tokens.consume(TokenKind::Arrow).is_none() {
+            return Ok(ty);
+        }
+        let result = self.parse_ty()?;
+        Ok(ast::Ty {
+            id: self.next_id(),
+            span: ty.span.to(result.span),
+            kind: ast::TyKind::Arrow(Box::new(ty), Box::new(result)),
+        })
+    }
+
+    fn parse_tuple_ty(&mut self) -> Result<ast::Ty, ParseError> {
+        let first = self.parse_applied_ty()?;
+        if !self.tokens.check(TokenKind::Star) {
+            return Ok(first);
+        }
+        let mut elements = vec![first];
+        while self.tokens.consume(TokenKind::Star).is_some() {
+            elements.push(self.parse_applied_ty()?);
+        }
+        Ok(ast::Ty {
+            id: self.next_id(),
+            span: SourceSpan::new(elements[0].span.start, self.tokens.previous().span.end),
+            kind: ast::TyKind::Tuple(elements),
+        })
+    }
+
+    fn parse_applied_ty(&mut self) -> Result<ast::Ty, ParseError> {
+        if !self.tokens.check(TokenKind::Identifier) {
+            return self.parse_primary_ty();
+        }
+        let path = self.parse_path()?;
+        let mut arguments = Vec::new();
+        while self.starts_primary_ty() {
+            arguments.push(self.parse_primary_ty()?);
+        }
+        let span = SourceSpan::new(path.span.start, self.tokens.previous().span.end);
+        Ok(ast::Ty {
+            id: self.next_id(),
+            span,
+            kind: ast::TyKind::Path(path, arguments),
+        })
+    }
+
+    fn starts_primary_ty(&mut self) -> bool {
+        matches!(
+            self.tokens.peek().kind,
+            TokenKind::Identifier | TokenKind::TypeVariable | TokenKind::LeftParen
+        )
+    }
+
+    fn parse_primary_ty(&mut self) -> Result<ast::Ty, ParseError> {
+        if self.tokens.check(TokenKind::Identifier) {
+            let path = self.parse_path()?;
+            Ok(ast::Ty {
+                id: self.next_id(),
+                span: path.span,
+                kind: ast::TyKind::Path(path, Vec::new()),
+            })
+        } else if let Some(token) = self.tokens.consume(TokenKind::TypeVariable) {
+            Ok(ast::Ty {
+                id: self.next_id(),
                 span: token.span,
-                kind: ast::ExprKind::Literal(ast::Literal {
-                    id: self.id_generator.next_id(),
+                kind: ast::TyKind::Var(self.literal(token, ast::LiteralKind::TypeVariable)),
+            })
+        } else if let Some(token) = self.tokens.consume(TokenKind::LeftParen) {
+            let ty = self.parse_ty()?;
+            let _ = self.expect(TokenKind::RightParen)?;
+            Ok(ast::Ty {
+                id: self.next_id(),
+                span: SourceSpan::new(token.span.start, self.tokens.previous().span.end),
+                kind: ast::TyKind::Paren(Box::new(ty)),
+            })
+        } else {
+            Err(self.expected("a type"))
+        }
+    }
+
+    fn parse_module_decl(&mut self) -> Result<ast::ModuleDecl, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let identifier = self.expect_capitalized_identifier("a module name")?;
+        let _ = self.expect(TokenKind::Equal)?;
+        let body = self.parse_block()?;
+        let span = SourceSpan::new(start, self.tokens.previous().span.end);
+        Ok(ast::ModuleDecl {
+            id: self.next_id(),
+            span,
+            identifier,
+            body,
+        })
+    }
+
+    fn parse_path(&mut self) -> Result<ast::Path, ParseError> {
+        let mut segments = vec![self.expect_identifier()?];
+        while self.tokens.consume(TokenKind::Dot).is_some() {
+            segments.push(self.expect_identifier()?);
+        }
+        let span = SourceSpan::new(segments[0].span.start, self.tokens.previous().span.end);
+        Ok(ast::Path {
+            id: self.next_id(),
+            span,
+            segments,
+        })
+    }
+
+    fn parse_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        if self.tokens.consume(TokenKind::Match).is_some() {
+            self.parse_match_expr()
+        } else if self.tokens.consume(TokenKind::Fun).is_some() {
+            self.parse_lambda_expr()
+        } else if self.tokens.consume(TokenKind::If).is_some() {
+            self.parse_if_expr()
+        } else if self.tokens.consume(TokenKind::Try).is_some() {
+            let start = self.tokens.previous().span.start;
+            let body = self.parse_let_binding_body()?;
+            if !self.continues_with(TokenKind::With) {
+                return Err(self.expected(r#""With""#));
+            }
+            let _ = self.tokens.advance();
+            let arms = self.parse_match_arms()?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: SourceSpan::new(start, self.tokens.previous().span.end),
+                kind: ast::ExprKind::Try(Box::new(body), arms),
+            })
+        } else if self.tokens.consume(TokenKind::While).is_some() {
+            let start = self.tokens.previous().span.start;
+            let condition = self.parse_expr()?;
+            let _ = self.expect(TokenKind::Do)?;
+            let body = self.parse_let_binding_body()?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: SourceSpan::new(start, self.tokens.previous().span.end),
+                kind: ast::ExprKind::While(Box::new(condition), Box::new(body)),
+            })
+        } else if self.tokens.consume(TokenKind::For).is_some() {
+            self.parse_for_expr()
+        } else if self.tokens.consume(TokenKind::Let).is_some() {
+            let let_binding = self.parse_let_binding()?;
+            self.parse_let_expr_tail(let_binding)
+        } else {
+            self.parse_binary_expr(0)
+        }
+    }
+
+    /// Parses a conditional. The `else` and `elif` keywords may start the
+    /// line following the `then` branch.
+    fn parse_if_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let condition = self.parse_expr()?;
+        let _ = self.expect(TokenKind::Then)?;
+        let then_branch = self.parse_let_binding_body()?;
+
+        let else_branch = if self.continues_with(TokenKind::Elif) {
+            let _ = self.tokens.advance();
+            Some(ast::LetBody::Expr(self.parse_if_expr()?))
+        } else if self.continues_with(TokenKind::Else) {
+            let _ = self.tokens.advance();
+            if self.tokens.consume(TokenKind::If).is_some() {
+                Some(ast::LetBody::Expr(self.parse_if_expr()?))
+            } else {
+                Some(self.parse_let_binding_body()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            kind: ast::ExprKind::If(
+                Box::new(condition),
+                Box::new(then_branch),
+                else_branch.map(Box::new),
+            ),
+        })
+    }
+
+    fn parse_for_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let binding = self.expect_identifier()?;
+        let _ = self.expect(TokenKind::Equal)?;
+        let start_expr = self.parse_expr()?;
+        let direction = if self.tokens.consume(TokenKind::To).is_some() {
+            ast::ForDirection::Up
+        } else if self.tokens.consume(TokenKind::Downto).is_some() {
+            ast::ForDirection::Down
+        } else {
+            return Err(self.expected(r#""To" or "Downto""#));
+        };
+        let end = self.parse_expr()?;
+        let _ = self.expect(TokenKind::Do)?;
+        let body = self.parse_let_binding_body()?;
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            kind: ast::ExprKind::For(Box::new(ast::ForLoop {
+                binding,
+                start: start_expr,
+                direction,
+                end,
+                body,
+            })),
+        })
+    }
+
+    /// Checks whether the expression continues with the keyword, either on
+    /// the same or the next line. The newline before the keyword is skipped.
+    fn continues_with(&mut self, kind: TokenKind) -> bool {
+        if self.tokens.check(TokenKind::NewLine) && self.tokens.peek_second().kind == kind {
+            let _ = self.tokens.advance();
+        }
+        self.tokens.check(kind)
+    }
+
+    fn parse_lambda_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let mut parameters = vec![self.parse_primary_pattern()?];
+        while self.starts_primary_pattern() {
+            parameters.push(self.parse_primary_pattern()?);
+        }
+        let _ = self.expect(TokenKind::Arrow)?;
+        let body = self.parse_let_binding_body()?;
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            kind: ast::ExprKind::Lambda(parameters, Box::new(body)),
+        })
+    }
+
+    /// Parses a chain of binary operators using precedence climbing. An
+    /// operator followed by the closing parenthesis is left to the left
+    /// section, e.g. `(xs @)`.
+    fn parse_binary_expr(&mut self, min_precedence: u8) -> Result<ast::Expr, ParseError> {
+        let mut lhs = self.parse_unary_expr()?;
+        let mut previous: Option<Fixity> = None;
+        loop {
+            let token = self.tokens.peek();
+            let (operator, fixity) = match self.infix_operator(token) {
+                Some(operator)
+                    if operator.1.precedence >= min_precedence
+                        && self.tokens.peek_second().kind != TokenKind::RightParen =>
+                {
+                    operator
+                }
+                _ => return Ok(lhs),
+            };
+            if let Some(previous) = previous.filter(|previous| {
+                previous.precedence == fixity.precedence
+                    && (previous.associativity == ast::Associativity::None
+                        || fixity.associativity == ast::Associativity::None)
+            }) {
+                return Err(ParseError {
                     span: token.span,
-                    kind: ast::LiteralKind::Integer,
-                }),
+                    message: format!(
+                        "non-associative operators of precedence {} cannot be chained without parentheses",
+                        previous.precedence
+                    ),
+                });
+            }
+            previous = Some(fixity);
+
+            let _ = self.tokens.advance();
+            let next_precedence = match fixity.associativity {
+                ast::Associativity::Left | ast::Associativity::None => fixity.precedence + 1,
+                ast::Associativity::Right => fixity.precedence,
+            };
+            let rhs = self.parse_binary_expr(next_precedence)?;
+            let span = lhs.span.to(rhs.span);
+            let kind = match operator {
+                InfixOperator::Builtin(kind) => ast::ExprKind::Binary(
+                    ast::BinaryOp {
+                        span: token.span,
+                        kind,
+                    },
+                    Box::new(lhs),
+                    Box::new(rhs),
+                ),
+                InfixOperator::User => {
+                    ast::ExprKind::Infix(self.operator_path(token), Box::new(lhs), Box::new(rhs))
+                }
+            };
+            lhs = ast::Expr {
+                id: self.next_id(),
+                span,
+                kind,
+            };
+        }
+    }
+
+    /// Gets the binary operator starting with the token, and its fixity.
+    fn infix_operator(&self, token: Token) -> Option<(InfixOperator, Fixity)> {
+        if let Some((kind, fixity)) = binary_operator(token.kind) {
+            Some((InfixOperator::Builtin(kind), fixity))
+        } else if token.kind == TokenKind::Operator {
+            let name = self.session.source_map.span_to_snippet(token.span);
+            let fixity = self.fixities.get(name).copied().unwrap_or(DEFAULT_FIXITY);
+            Some((InfixOperator::User, fixity))
+        } else {
+            None
+        }
+    }
+
+    /// Makes the path naming the user-defined operator.
+    fn operator_path(&mut self, token: Token) -> ast::Path {
+        ast::Path {
+            id: self.next_id(),
+            span: token.span,
+            segments: vec![self.literal(token, ast::LiteralKind::Identifier)],
+        }
+    }
+
+    fn parse_unary_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        if let Some(token) = self.tokens.consume(TokenKind::Minus) {
+            let operand = self.parse_unary_expr()?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: token.span.to(operand.span),
+                kind: ast::ExprKind::Unary(
+                    ast::UnaryOp {
+                        span: token.span,
+                        kind: ast::UnaryOpKind::Negate,
+                    },
+                    Box::new(operand),
+                ),
             })
+        } else if matches!(
+            self.tokens.peek().kind,
+            TokenKind::Match
+                | TokenKind::Try
+                | TokenKind::Fun
+                | TokenKind::Let
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::For
+        ) {
+            // These extend as far to the right as possible, so they can be
+            // the last operand without parentheses, e.g. `x + match y ...`.
+            self.parse_expr()
         } else {
-            let current = self.tokens.peek();
-            Err(ParseError {
-                span: current.span,
-                message: format!("expected an expression, but found {:?}", self.tokens.peek()),
+            self.parse_application_expr()
+        }
+    }
+
+    fn parse_match_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        let start = self.tokens.previous().span.start;
+        let scrutinee = self.parse_expr()?;
+        let arms = self.parse_match_arms()?;
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            kind: ast::ExprKind::Match(Box::new(scrutinee), arms),
+        })
+    }
+
+    /// Parses the arms of a match or try expression. The arms either start
+    /// on the line of the scrutinee, on the following lines with the same
+    /// indentation as the keyword, or in an indented block.
+    fn parse_match_arms(&mut self) -> Result<Vec<ast::MatchArm>, ParseError> {
+        let indented = self.tokens.check(TokenKind::Indent)
+            && self.tokens.peek_second().kind == TokenKind::Bar;
+        if indented {
+            let _ = self.tokens.consume(TokenKind::Indent);
+        }
+
+        let mut arms = Vec::new();
+        loop {
+            if self.tokens.check(TokenKind::NewLine)
+                && self.tokens.peek_second().kind == TokenKind::Bar
+            {
+                let _ = self.tokens.consume(TokenKind::NewLine);
+            }
+            if !self.tokens.check(TokenKind::Bar) {
+                break;
+            }
+            arms.push(self.parse_match_arm()?);
+        }
+        if arms.is_empty() {
+            return Err(self.expected(r#"a match arm starting with "Bar""#));
+        }
+        if indented {
+            let _ = self.tokens.consume(TokenKind::NewLine);
+            let _ = self.expect(TokenKind::Dedent)?;
+        }
+        Ok(arms)
+    }
+
+    fn parse_match_arm(&mut self) -> Result<ast::MatchArm, ParseError> {
+        let start = self.expect(TokenKind::Bar)?.span.start;
+        let pattern = self.parse_pattern()?;
+        let _ = self.expect(TokenKind::Arrow)?;
+        let body = self.parse_let_binding_body()?;
+        Ok(ast::MatchArm {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            pattern,
+            body,
+        })
+    }
+
+    fn parse_pattern(&mut self) -> Result<ast::Pattern, ParseError> {
+        let first = self.parse_cons_pattern()?;
+        if !self.tokens.check(TokenKind::Bar) {
+            return Ok(first);
+        }
+        let mut alternatives = vec![first];
+        while self.tokens.consume(TokenKind::Bar).is_some() {
+            alternatives.push(self.parse_cons_pattern()?);
+        }
+        Ok(ast::Pattern {
+            id: self.next_id(),
+            span: SourceSpan::new(alternatives[0].span.start, self.tokens.previous().span.end),
+            kind: ast::PatternKind::Or(alternatives),
+        })
+    }
+
+    fn parse_cons_pattern(&mut self) -> Result<ast::Pattern, ParseError> {
+        let head = self.parse_constructor_pattern()?;
+        if self.tokens.consume(TokenKind::ColonColon).is_none() {
+            return Ok(head);
+        }
+        let tail = self.parse_cons_pattern()?;
+        Ok(ast::Pattern {
+            id: self.next_id(),
+            span: head.span.to(tail.span),
+            kind: ast::PatternKind::Cons(Box::new(head), Box::new(tail)),
+        })
+    }
+
+    fn parse_constructor_pattern(&mut self) -> Result<ast::Pattern, ParseError> {
+        if !self.check_capitalized() {
+            return self.parse_primary_pattern();
+        }
+        let path = self.parse_path()?;
+        let mut arguments = Vec::new();
+        while self.starts_primary_pattern() {
+            arguments.push(self.parse_primary_pattern()?);
+        }
+        Ok(ast::Pattern {
+            id: self.next_id(),
+            span: SourceSpan::new(path.span.start, self.tokens.previous().span.end),
+            kind: ast::PatternKind::Constructor(path, arguments),
+        })
+    }
+
+    fn starts_primary_pattern(&mut self) -> bool {
+        matches!(
+            self.tokens.peek().kind,
+            TokenKind::Underscore
+                | TokenKind::Identifier
+                | TokenKind::Integer
+                | TokenKind::String
+                | TokenKind::UnterminatedString
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::LeftParen
+                | TokenKind::LeftBrace
+                | TokenKind::LeftBracket
+                | TokenKind::LeftBracketBar
+        )
+    }
+
+    fn parse_primary_pattern(&mut self) -> Result<ast::Pattern, ParseError> {
+        let start = self.tokens.peek().span.start;
+        let kind = if self.tokens.consume(TokenKind::Underscore).is_some() {
+            ast::PatternKind::Wildcard
+        } else if self.check_capitalized() {
+            ast::PatternKind::Constructor(self.parse_path()?, Vec::new())
+        } else if self.tokens.check(TokenKind::Identifier) {
+            ast::PatternKind::Binding(self.expect_identifier()?)
+        } else if let Some(literal) = self.parse_literal() {
+            ast::PatternKind::Literal(literal)
+        } else if self.tokens.consume(TokenKind::LeftParen).is_some() {
+            self.skip_newlines();
+            let pattern = self.parse_pattern()?;
+            self.skip_newlines();
+            let kind = if self.tokens.consume(TokenKind::Colon).is_some() {
+                ast::PatternKind::Typed(Box::new(pattern), self.parse_ty()?)
+            } else if self.tokens.check(TokenKind::Comma) {
+                ast::PatternKind::Tuple(self.parse_tuple_tail(pattern, Self::parse_pattern)?)
+            } else {
+                ast::PatternKind::Paren(Box::new(pattern))
+            };
+            self.skip_newlines();
+            let _ = self.expect(TokenKind::RightParen)?;
+            kind
+        } else if self.tokens.check(TokenKind::LeftBracket) {
+            ast::PatternKind::List(self.parse_brackets(Self::parse_pattern)?)
+        } else if self.tokens.check(TokenKind::LeftBracketBar) {
+            ast::PatternKind::Array(self.parse_brackets(Self::parse_pattern)?)
+        } else if self.tokens.check(TokenKind::LeftBrace) {
+            ast::PatternKind::Record(self.parse_record_fields(|parser| {
+                let identifier = parser.expect_identifier()?;
+                let pattern = if parser.tokens.consume(TokenKind::Equal).is_some() {
+                    Some(parser.parse_pattern()?)
+                } else {
+                    None
+                };
+                Ok(ast::FieldPattern {
+                    id: parser.next_id(),
+                    span: SourceSpan::new(identifier.span.start, parser.tokens.previous().span.end),
+                    identifier,
+                    pattern,
+                })
+            })?)
+        } else {
+            return Err(self.expected("a pattern"));
+        };
+        Ok(ast::Pattern {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            kind,
+        })
+    }
+
+    fn parse_application_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        if let Some(token) = self.tokens.consume(TokenKind::Attribute) {
+            return self.parse_attributed_expr(token);
+        }
+        let callee = self.parse_postfix_expr()?;
+        let mut arguments = Vec::new();
+        while self.starts_primary_expr() {
+            arguments.push(self.parse_postfix_expr()?);
+        }
+        if arguments.is_empty() {
+            return Ok(callee);
+        }
+        let span = SourceSpan::new(callee.span.start, self.tokens.previous().span.end);
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span,
+            kind: ast::ExprKind::Application(Box::new(callee), arguments),
+        })
+    }
+
+    /// Parses the application annotated with the attribute. The only one
+    /// is `@tailcall`, asserting the call is in tail position.
+    fn parse_attributed_expr(&mut self, attribute: Token) -> Result<ast::Expr, ParseError> {
+        let name = self.session.source_map.span_to_snippet(attribute.span);
+        if name != "@tailcall" {
+            return Err(ParseError {
+                span: attribute.span,
+                message: format!(r#"unknown attribute "{}""#, name),
+            });
+        }
+        let call = self.parse_application_expr()?;
+        if !matches!(call.kind, ast::ExprKind::Application(..)) {
+            return Err(ParseError {
+                span: call.span,
+                message: r#"expected a function application after "@tailcall""#.to_string(),
+            });
+        }
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: attribute.span.to(call.span),
+            kind: ast::ExprKind::TailCall(Box::new(call)),
+        })
+    }
+
+    fn starts_primary_expr(&mut self) -> bool {
+        matches!(
+            self.tokens.peek().kind,
+            TokenKind::Integer
+                | TokenKind::String
+                | TokenKind::UnterminatedString
+                | TokenKind::InterpolatedString
+                | TokenKind::InterpolationStart
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::Identifier
+                | TokenKind::LeftParen
+                | TokenKind::LeftBrace
+                | TokenKind::LeftBracket
+                | TokenKind::LeftBracketBar
+                | TokenKind::Bang
+        )
+    }
+
+    /// Parses a primary expression followed by field accesses.
+    fn parse_postfix_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        let mut expr = self.parse_primary_expr()?;
+        while self.tokens.check(TokenKind::Dot)
+            && self.tokens.peek_second().kind == TokenKind::Identifier
+        {
+            let _ = self.tokens.consume(TokenKind::Dot);
+            let field = self.expect_identifier()?;
+            expr = ast::Expr {
+                id: self.next_id(),
+                span: SourceSpan::new(expr.span.start, field.span.end),
+                kind: ast::ExprKind::FieldAccess(Box::new(expr), field),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        if let Some(token) = self.tokens.consume(TokenKind::Bang) {
+            // The dereference binds tighter than the application, so
+            // `f !r` passes the value of `r` to `f`.
+            let operand = self.parse_postfix_expr()?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: token.span.to(operand.span),
+                kind: ast::ExprKind::Unary(
+                    ast::UnaryOp {
+                        span: token.span,
+                        kind: ast::UnaryOpKind::Deref,
+                    },
+                    Box::new(operand),
+                ),
+            })
+        } else if let Some(literal) = self.parse_literal() {
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: literal.span,
+                kind: ast::ExprKind::Literal(literal),
             })
+        } else if self.tokens.check(TokenKind::InterpolatedString)
+            || self.tokens.check(TokenKind::InterpolationStart)
+        {
+            self.parse_interpolation()
+        } else if self.tokens.check(TokenKind::Identifier) {
+            let path = self.parse_expr_path()?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: path.span,
+                kind: ast::ExprKind::Path(path),
+            })
+        } else if let Some(token) = self.tokens.consume(TokenKind::LeftParen) {
+            self.skip_newlines();
+            if let Some(kind) = self.parse_right_section()? {
+                let _ = self.expect(TokenKind::RightParen)?;
+                return Ok(ast::Expr {
+                    id: self.next_id(),
+                    span: SourceSpan::new(token.span.start, self.tokens.previous().span.end),
+                    kind,
+                });
+            }
+            let expr = self.parse_expr()?;
+            self.skip_newlines();
+            let kind = if let Some(operator) = self.section_operator() {
+                ast::ExprKind::Section(operator, Some(Box::new(expr)), None)
+            } else if self.tokens.consume(TokenKind::Colon).is_some() {
+                ast::ExprKind::Typed(Box::new(expr), self.parse_ty()?)
+            } else if self.tokens.check(TokenKind::Comma) {
+                ast::ExprKind::Tuple(self.parse_tuple_tail(expr, Self::parse_expr)?)
+            } else {
+                ast::ExprKind::Paren(Box::new(expr))
+            };
+            self.skip_newlines();
+            let _ = self.expect(TokenKind::RightParen)?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: SourceSpan::new(token.span.start, self.tokens.previous().span.end),
+                kind,
+            })
+        } else if self.tokens.check(TokenKind::LeftBrace) {
+            self.parse_record_expr()
+        } else if self.tokens.check(TokenKind::LeftBracket)
+            || self.tokens.check(TokenKind::LeftBracketBar)
+        {
+            let start = self.tokens.peek().span.start;
+            let is_array = self.tokens.check(TokenKind::LeftBracketBar);
+            let elements = self.parse_brackets(Self::parse_expr)?;
+            Ok(ast::Expr {
+                id: self.next_id(),
+                span: SourceSpan::new(start, self.tokens.previous().span.end),
+                kind: if is_array {
+                    ast::ExprKind::Array(elements)
+                } else {
+                    ast::ExprKind::List(elements)
+                },
+            })
+        } else {
+            Err(self.expected("an expression"))
+        }
+    }
+
+    /// Parses a section starting with the operator, e.g. `(+ 1)` or `(+)`,
+    /// after the opening parenthesis. The operator alone is a path if it is
+    /// user-defined. A minus followed by an operand is a negation.
+    fn parse_right_section(&mut self) -> Result<Option<ast::ExprKind>, ParseError> {
+        let token = self.tokens.peek();
+        if self.infix_operator(token).is_none()
+            || (token.kind == TokenKind::Minus
+                && self.tokens.peek_second().kind != TokenKind::RightParen)
+        {
+            return Ok(None);
+        }
+        let operator = self.section_operator().unwrap();
+        if self.tokens.check(TokenKind::RightParen) {
+            return Ok(Some(match operator {
+                ast::SectionOperator::User(path) => ast::ExprKind::Path(path),
+                operator => ast::ExprKind::Section(operator, None, None),
+            }));
+        }
+        let operand = self.parse_expr()?;
+        self.skip_newlines();
+        Ok(Some(ast::ExprKind::Section(
+            operator,
+            None,
+            Some(Box::new(operand)),
+        )))
+    }
+
+    /// Parses the operator of a section, if there is one.
+    fn section_operator(&mut self) -> Option<ast::SectionOperator> {
+        let token = self.tokens.peek();
+        let (operator, _) = self.infix_operator(token)?;
+        let _ = self.tokens.advance();
+        Some(match operator {
+            InfixOperator::Builtin(kind) => ast::SectionOperator::Builtin(ast::BinaryOp {
+                span: token.span,
+                kind,
+            }),
+            InfixOperator::User => ast::SectionOperator::User(self.operator_path(token)),
+        })
+    }
+
+    /// Parses an integer, a string, a boolean or the unit literal, if there
+    /// is one. The malformed string literals are reported, but still parsed.
+    /// Parses an interpolated string, whose tokens are the text segments
+    /// around the holes, e.g. `$"a {`, `}, {` and `}."` in `$"a {x}, {y}."`.
+    fn parse_interpolation(&mut self) -> Result<ast::Expr, ParseError> {
+        let first = self.tokens.advance();
+        let mut parts = vec![self.interpolated_text(first)];
+        if first.kind == TokenKind::InterpolationStart {
+            loop {
+                parts.push(ast::InterpolationPart::Hole(self.parse_expr()?));
+                let token = self.tokens.peek();
+                match token.kind {
+                    TokenKind::InterpolationMiddle | TokenKind::InterpolationEnd => {
+                        let _ = self.tokens.advance();
+                        parts.push(self.interpolated_text(token));
+                        if token.kind == TokenKind::InterpolationEnd {
+                            break;
+                        }
+                    }
+                    TokenKind::UnterminatedString => {
+                        return Err(ParseError {
+                            span: token.span,
+                            message: "unterminated string literal".to_string(),
+                        })
+                    }
+                    _ => return Err(self.expected("`}` closing the interpolated expression")),
+                }
+            }
         }
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(first.span.start, self.tokens.previous().span.end),
+            kind: ast::ExprKind::Interpolation(parts),
+        })
+    }
+
+    /// Gets the text segment of the interpolated string token, without the
+    /// delimiters, checking its escape sequences.
+    fn interpolated_text(&mut self, token: Token) -> ast::InterpolationPart {
+        let start = match token.kind {
+            TokenKind::InterpolatedString | TokenKind::InterpolationStart => 2,
+            _ => 1,
+        };
+        let span = SourceSpan::new(token.span.start + start, token.span.end - 1);
+        let text = self.session.source_map.span_to_snippet(span);
+        self.check_escapes(span, token::unescape_interpolated_text(text));
+        ast::InterpolationPart::Text(span)
+    }
+
+    /// Reports the unknown escape sequence found in the text spanned.
+    fn check_escapes(&mut self, span: SourceSpan, unescaped: Result<String, usize>) {
+        if let Err(offset) = unescaped {
+            let span = SourceSpan::from_length(span.start + offset, 2);
+            self.session.error(span, "unknown escape sequence");
+        }
+    }
+
+    fn parse_literal(&mut self) -> Option<ast::Literal> {
+        let token = self.tokens.peek();
+        let kind = match token.kind {
+            TokenKind::Integer => ast::LiteralKind::Integer,
+            TokenKind::String => {
+                let span = SourceSpan::new(token.span.start + 1, token.span.end - 1);
+                let contents = self.session.source_map.span_to_snippet(span);
+                self.check_escapes(span, token::unescape_string(contents));
+                ast::LiteralKind::String
+            }
+            TokenKind::UnterminatedString => {
+                self.session
+                    .error(token.span, "unterminated string literal");
+                ast::LiteralKind::String
+            }
+            TokenKind::True | TokenKind::False => ast::LiteralKind::Bool,
+            TokenKind::LeftParen if self.tokens.peek_second().kind == TokenKind::RightParen => {
+                let _ = self.tokens.advance();
+                let end = self.tokens.advance().span.end;
+                return Some(ast::Literal {
+                    id: self.next_id(),
+                    span: SourceSpan::new(token.span.start, end),
+                    kind: ast::LiteralKind::Unit,
+                });
+            }
+            _ => return None,
+        };
+        let _ = self.tokens.advance();
+        Some(self.literal(token, kind))
+    }
+
+    /// Parses a path in an expression. The path continues only through the
+    /// capitalized module names, so `List.map` is a path, while `p.x` is an
+    /// access of the field `x` of `p`.
+    fn parse_expr_path(&mut self) -> Result<ast::Path, ParseError> {
+        let mut segments = vec![self.expect_identifier()?];
+        while self.is_capitalized(segments.last().unwrap().span)
+            && self.tokens.check(TokenKind::Dot)
+            && self.tokens.peek_second().kind == TokenKind::Identifier
+        {
+            let _ = self.tokens.consume(TokenKind::Dot);
+            segments.push(self.expect_identifier()?);
+        }
+        let span = SourceSpan::new(segments[0].span.start, self.tokens.previous().span.end);
+        Ok(ast::Path {
+            id: self.next_id(),
+            span,
+            segments,
+        })
+    }
+
+    fn parse_record_expr(&mut self) -> Result<ast::Expr, ParseError> {
+        let start = self.tokens.peek().span.start;
+        let mut parse_field = |parser: &mut Self| {
+            let identifier = parser.expect_identifier()?;
+            let _ = parser.expect(TokenKind::Equal)?;
+            let expr = parser.parse_expr()?;
+            Ok(ast::FieldExpr {
+                id: parser.next_id(),
+                span: SourceSpan::new(identifier.span.start, parser.tokens.previous().span.end),
+                identifier,
+                expr,
+            })
+        };
+
+        // A record update starts with an arbitrary expression followed by
+        // `with`, while a record construction starts with `field =`.
+        let _ = self.expect(TokenKind::LeftBrace)?;
+        while self.tokens.consume(TokenKind::NewLine).is_some() {}
+        let kind = if self.tokens.check(TokenKind::Identifier)
+            && self.tokens.peek_second().kind == TokenKind::Equal
+        {
+            ast::ExprKind::Record(self.parse_record_fields_tail(&mut parse_field)?)
+        } else {
+            let record = self.parse_expr()?;
+            let _ = self.expect(TokenKind::With)?;
+            let fields = self.parse_record_fields_tail(&mut parse_field)?;
+            ast::ExprKind::RecordUpdate(Box::new(record), fields)
+        };
+
+        Ok(ast::Expr {
+            id: self.next_id(),
+            span: SourceSpan::new(start, self.tokens.previous().span.end),
+            kind,
+        })
     }
 
     fn expect(&mut self, kind: TokenKind) -> Result<Token, ParseError> {
         if let Some(token) = self.tokens.consume(kind) {
             Ok(token)
         } else {
-            let current = self.tokens.peek();
-            Err(ParseError {
-                span: current.span,
-                message: format!(r#"expected "{:?}", but found "{:?}""#, kind, current.kind),
-            })
+            Err(self.expected(&format!(r#""{:?}""#, kind)))
         }
     }
 
     fn expect_identifier(&mut self) -> Result<ast::Literal, ParseError> {
         if let Some(token) = self.tokens.consume(TokenKind::Identifier) {
             Ok(ast::Literal {
-                id: self.id_generator.next_id(),
+                id: self.next_id(),
                 span: token.span,
                 kind: ast::LiteralKind::Identifier,
             })
         } else {
-            let current = self.tokens.peek();
-            Err(ParseError {
-                span: current.span,
-                message: format!(r#"expected {{identifier}}, but found "{:?}""#, current.kind),
-            })
+            Err(self.expected("{identifier}"))
+        }
+    }
+
+    fn expect_capitalized_identifier(&mut self, what: &str) -> Result<ast::Literal, ParseError> {
+        if self.check_capitalized() {
+            self.expect_identifier()
+        } else {
+            Err(self.expected(&format!("{} starting with an uppercase letter", what)))
         }
     }
+
+    /// Checks whether the next token is a capitalized identifier, which names
+    /// a module, a type or a constructor.
+    fn check_capitalized(&mut self) -> bool {
+        let token = self.tokens.peek();
+        token.kind == TokenKind::Identifier && self.is_capitalized(token.span)
+    }
+
+    fn is_capitalized(&self, span: SourceSpan) -> bool {
+        self.session
+            .source_map
+            .span_to_snippet(span)
+            .starts_with(char::is_uppercase)
+    }
+
+    fn expected(&mut self, what: &str) -> ParseError {
+        let current = self.tokens.peek();
+        ParseError {
+            span: current.span,
+            message: format!(r#"expected {}, but found "{:?}""#, what, current.kind),
+        }
+    }
+
+    fn literal(&mut self, token: Token, kind: ast::LiteralKind) -> ast::Literal {
+        ast::Literal {
+            id: self.next_id(),
+            span: token.span,
+            kind,
+        }
+    }
+
+    fn next_id(&mut self) -> NodeId {
+        self.session.node_id_generator.next_id()
+    }
+}
+
+/// The precedence and the associativity of a binary operator.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Fixity {
+    precedence: u8,
+    associativity: ast::Associativity,
+}
+
+impl Fixity {
+    /// Gets the fixity given by the declaration, which the parser checked.
+    pub fn of_decl(decl: &ast::FixityDecl, source_map: &SourceMap) -> Fixity {
+        Fixity {
+            precedence: source_map
+                .span_to_snippet(decl.precedence.span)
+                .parse()
+                .unwrap_or(MAX_PRECEDENCE),
+            associativity: decl.associativity,
+        }
+    }
+}
+
+impl fmt::Display for Fixity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keyword = match self.associativity {
+            ast::Associativity::Left => "infixl",
+            ast::Associativity::Right => "infixr",
+            ast::Associativity::None => "infix",
+        };
+        write!(f, "{} {}", keyword, self.precedence)
+    }
+}
+
+const MAX_PRECEDENCE: u8 = 9;
+
+/// The fixity of the user-defined operators without a fixity declaration.
+pub const DEFAULT_FIXITY: Fixity = Fixity {
+    precedence: MAX_PRECEDENCE,
+    associativity: ast::Associativity::Left,
+};
+
+enum InfixOperator {
+    Builtin(ast::BinaryOpKind),
+    User,
+}
+
+/// Gets the kind and the fixity of the built-in binary operator.
+fn binary_operator(kind: TokenKind) -> Option<(ast::BinaryOpKind, Fixity)> {
+    use ast::{
+        Associativity::{Left, Right},
+        BinaryOpKind::*,
+    };
+    let (kind, precedence, associativity) = match kind {
+        TokenKind::ColonEqual => (Assign, 0, Right),
+        TokenKind::BarBar => (Or, 2, Right),
+        TokenKind::AndAnd => (And, 3, Right),
+        TokenKind::Equal => (Equal, 4, Left),
+        TokenKind::NotEqual => (NotEqual, 4, Left),
+        TokenKind::Less => (Less, 4, Left),
+        TokenKind::LessEqual => (LessEqual, 4, Left),
+        TokenKind::Greater => (Greater, 4, Left),
+        TokenKind::GreaterEqual => (GreaterEqual, 4, Left),
+        TokenKind::ColonColon => (Cons, 5, Right),
+        TokenKind::Plus => (Add, 6, Left),
+        TokenKind::Minus => (Subtract, 6, Left),
+        TokenKind::Star => (Multiply, 7, Left),
+        TokenKind::Slash => (Divide, 7, Left),
+        _ => return None,
+    };
+    Some((
+        kind,
+        Fixity {
+            precedence,
+            associativity,
+        },
+    ))
+}
+
+/// Gets the associativity declared by the fixity keyword.
+fn fixity_associativity(kind: TokenKind) -> Option<ast::Associativity> {
+    match kind {
+        TokenKind::Infixl => Some(ast::Associativity::Left),
+        TokenKind::Infixr => Some(ast::Associativity::Right),
+        TokenKind::Infix => Some(ast::Associativity::None),
+        _ => None,
+    }
 }
 
 #[derive(Debug)]
@@ -26,22 +26,379 @@ pub enum TokenKind {
     Dedent,
 
     Identifier,
+    /// A type variable, e.g. `'a`.
+    TypeVariable,
     Integer,
+    /// A string literal, e.g. `"a\n"`, including the quotes.
+    String,
+    /// An interpolated string without holes, e.g. `$"a"`.
+    InterpolatedString,
+    /// The text of an interpolated string before its first hole, e.g.
+    /// `$"x = {`.
+    InterpolationStart,
+    /// The text of an interpolated string between two holes, e.g. `}, {`.
+    InterpolationMiddle,
+    /// The text of an interpolated string after its last hole, e.g. `}."`.
+    InterpolationEnd,
+    /// An attribute annotating the following expression, e.g. `@tailcall`.
+    Attribute,
+    True,
+    False,
 
+    And,
+    Do,
+    Downto,
+    Elif,
+    Else,
+    Exception,
+    For,
+    Fun,
+    If,
+    Import,
+    In,
+    Infix,
+    Infixl,
+    Infixr,
     Let,
+    Match,
+    Module,
+    Open,
+    Pub,
+    Rec,
+    Then,
+    To,
+    Try,
+    Type,
+    While,
+    With,
 
     Equal,
+    Dot,
+    Colon,
+    ColonColon,
+    ColonEqual,
+    Comma,
+    Semicolon,
+    Bar,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    NotEqual,
+    AndAnd,
+    BarBar,
+    Arrow,
+    Bang,
+    /// A user-defined symbolic operator, e.g. `|>`.
+    Operator,
+    Underscore,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    /// The opening bracket of an array, `[|`.
+    LeftBracketBar,
+    /// The closing bracket of an array, `|]`.
+    BarRightBracket,
 
     // Pseudo-tokens.
     Invalid,
+    /// A string literal missing the closing quote on its line.
+    UnterminatedString,
     MixedIndentation,
     InvalidIndentation,
     EndOfFile,
 }
 
+impl TokenKind {
+    /// Gets the highlighting class of the tokens of the kind, or `None` for
+    /// the layout and the indentation pseudo-tokens, which have no text.
+    pub fn class(self) -> Option<TokenClass> {
+        match self {
+            TokenKind::Identifier => Some(TokenClass::Identifier),
+            TokenKind::TypeVariable => Some(TokenClass::TypeVariable),
+            TokenKind::Integer => Some(TokenClass::Number),
+            TokenKind::String
+            | TokenKind::InterpolatedString
+            | TokenKind::InterpolationStart
+            | TokenKind::InterpolationMiddle
+            | TokenKind::InterpolationEnd
+            | TokenKind::UnterminatedString => Some(TokenClass::String),
+            TokenKind::Attribute => Some(TokenClass::Attribute),
+            TokenKind::True | TokenKind::False => Some(TokenClass::Constant),
+            TokenKind::And
+            | TokenKind::Do
+            | TokenKind::Downto
+            | TokenKind::Elif
+            | TokenKind::Else
+            | TokenKind::Exception
+            | TokenKind::For
+            | TokenKind::Fun
+            | TokenKind::If
+            | TokenKind::Import
+            | TokenKind::In
+            | TokenKind::Infix
+            | TokenKind::Infixl
+            | TokenKind::Infixr
+            | TokenKind::Let
+            | TokenKind::Match
+            | TokenKind::Module
+            | TokenKind::Open
+            | TokenKind::Pub
+            | TokenKind::Rec
+            | TokenKind::Then
+            | TokenKind::To
+            | TokenKind::Try
+            | TokenKind::Type
+            | TokenKind::While
+            | TokenKind::With => Some(TokenClass::Keyword),
+            TokenKind::Equal
+            | TokenKind::ColonColon
+            | TokenKind::ColonEqual
+            | TokenKind::Bar
+            | TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Star
+            | TokenKind::Slash
+            | TokenKind::Less
+            | TokenKind::LessEqual
+            | TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::NotEqual
+            | TokenKind::AndAnd
+            | TokenKind::BarBar
+            | TokenKind::Arrow
+            | TokenKind::Bang
+            | TokenKind::Operator => Some(TokenClass::Operator),
+            TokenKind::Dot
+            | TokenKind::Colon
+            | TokenKind::Comma
+            | TokenKind::Semicolon
+            | TokenKind::Underscore
+            | TokenKind::LeftParen
+            | TokenKind::RightParen
+            | TokenKind::LeftBrace
+            | TokenKind::RightBrace
+            | TokenKind::LeftBracket
+            | TokenKind::RightBracket
+            | TokenKind::LeftBracketBar
+            | TokenKind::BarRightBracket => Some(TokenClass::Punctuation),
+            TokenKind::Invalid => Some(TokenClass::Invalid),
+            TokenKind::NewLine
+            | TokenKind::Indent
+            | TokenKind::Dedent
+            | TokenKind::MixedIndentation
+            | TokenKind::InvalidIndentation
+            | TokenKind::EndOfFile => None,
+        }
+    }
+}
+
+/// The highlighting classes of the source code, shared by `brinkc
+/// highlight`, the exported editor grammar and the language server.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TokenClass {
+    Keyword,
+    /// The boolean literals.
+    Constant,
+    Identifier,
+    TypeVariable,
+    Number,
+    /// The string literals, including the text of the interpolated ones.
+    String,
+    Attribute,
+    Operator,
+    Punctuation,
+    /// A line comment, which the lexer skips rather than tokenizes.
+    Comment,
+    Invalid,
+}
+
+impl TokenClass {
+    pub const ALL: [TokenClass; 11] = [
+        TokenClass::Keyword,
+        TokenClass::Constant,
+        TokenClass::Identifier,
+        TokenClass::TypeVariable,
+        TokenClass::Number,
+        TokenClass::String,
+        TokenClass::Attribute,
+        TokenClass::Operator,
+        TokenClass::Punctuation,
+        TokenClass::Comment,
+        TokenClass::Invalid,
+    ];
+
+    /// Gets the name of the class, used as the CSS class of the tokens.
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword",
+            TokenClass::Constant => "constant",
+            TokenClass::Identifier => "identifier",
+            TokenClass::TypeVariable => "type-variable",
+            TokenClass::Number => "number",
+            TokenClass::String => "string",
+            TokenClass::Attribute => "attribute",
+            TokenClass::Operator => "operator",
+            TokenClass::Punctuation => "punctuation",
+            TokenClass::Comment => "comment",
+            TokenClass::Invalid => "invalid",
+        }
+    }
+
+    /// Gets the TextMate scope of the class, without the `.brink` suffix.
+    pub fn scope(self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword.control",
+            TokenClass::Constant => "constant.language.boolean",
+            TokenClass::Identifier => "variable.other",
+            TokenClass::TypeVariable => "variable.parameter.type",
+            TokenClass::Number => "constant.numeric.integer",
+            TokenClass::String => "string.quoted.double",
+            TokenClass::Attribute => "entity.other.attribute-name",
+            TokenClass::Operator => "keyword.operator",
+            TokenClass::Punctuation => "punctuation",
+            TokenClass::Comment => "comment.line.number-sign",
+            TokenClass::Invalid => "invalid.illegal",
+        }
+    }
+}
+
+/// The reserved words, with the kinds of their tokens.
+pub const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("and", TokenKind::And),
+    ("do", TokenKind::Do),
+    ("downto", TokenKind::Downto),
+    ("elif", TokenKind::Elif),
+    ("else", TokenKind::Else),
+    ("exception", TokenKind::Exception),
+    ("for", TokenKind::For),
+    ("fun", TokenKind::Fun),
+    ("if", TokenKind::If),
+    ("import", TokenKind::Import),
+    ("in", TokenKind::In),
+    ("infix", TokenKind::Infix),
+    ("infixl", TokenKind::Infixl),
+    ("infixr", TokenKind::Infixr),
+    ("let", TokenKind::Let),
+    ("match", TokenKind::Match),
+    ("module", TokenKind::Module),
+    ("open", TokenKind::Open),
+    ("pub", TokenKind::Pub),
+    ("rec", TokenKind::Rec),
+    ("then", TokenKind::Then),
+    ("true", TokenKind::True),
+    ("false", TokenKind::False),
+    ("to", TokenKind::To),
+    ("try", TokenKind::Try),
+    ("type", TokenKind::Type),
+    ("while", TokenKind::While),
+    ("with", TokenKind::With),
+    ("_", TokenKind::Underscore),
+];
+
 pub fn get_keyword_kind(identifier: &str) -> Option<TokenKind> {
-    match identifier {
-        "let" => Some(TokenKind::Let),
+    KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == identifier)
+        .map(|(_, kind)| *kind)
+}
+
+/// Checks whether the character can start a symbolic operator.
+pub fn is_operator_start(c: char) -> bool {
+    matches!(
+        c,
+        '!' | '$'
+            | '%'
+            | '&'
+            | '*'
+            | '+'
+            | '-'
+            | '/'
+            | ':'
+            | '<'
+            | '='
+            | '>'
+            | '?'
+            | '@'
+            | '^'
+            | '|'
+            | '~'
+    )
+}
+
+/// Checks whether the character can continue a symbolic operator. Unlike
+/// at the start, a dot is allowed, so `List.map` is still a path.
+pub fn is_operator_part(c: char) -> bool {
+    is_operator_start(c) || c == '.'
+}
+
+/// Gets the kind of the built-in operator or punctuation spelled as the
+/// symbolic operator. Other operators are user-defined.
+pub fn get_operator_kind(operator: &str) -> Option<TokenKind> {
+    match operator {
+        "=" => Some(TokenKind::Equal),
+        ":" => Some(TokenKind::Colon),
+        "::" => Some(TokenKind::ColonColon),
+        ":=" => Some(TokenKind::ColonEqual),
+        "!" => Some(TokenKind::Bang),
+        "|" => Some(TokenKind::Bar),
+        "+" => Some(TokenKind::Plus),
+        "-" => Some(TokenKind::Minus),
+        "*" => Some(TokenKind::Star),
+        "/" => Some(TokenKind::Slash),
+        "<" => Some(TokenKind::Less),
+        "<=" => Some(TokenKind::LessEqual),
+        ">" => Some(TokenKind::Greater),
+        ">=" => Some(TokenKind::GreaterEqual),
+        "<>" => Some(TokenKind::NotEqual),
+        "&&" => Some(TokenKind::AndAnd),
+        "||" => Some(TokenKind::BarBar),
+        "->" => Some(TokenKind::Arrow),
         _ => None,
     }
 }
+
+/// Replaces the escape sequences in the contents of a string literal, i.e.
+/// without the quotes. On an unknown escape sequence, returns its byte
+/// offset.
+pub fn unescape_string(contents: &str) -> Result<String, usize> {
+    unescape(contents, false)
+}
+
+/// Replaces the escape sequences in a text segment of an interpolated
+/// string, where the braces are additionally escaped by doubling them.
+pub fn unescape_interpolated_text(contents: &str) -> Result<String, usize> {
+    unescape(contents, true)
+}
+
+fn unescape(contents: &str, doubled_braces: bool) -> Result<String, usize> {
+    let mut result = String::with_capacity(contents.len());
+    let mut chars = contents.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if doubled_braces && (c == '{' || c == '}') {
+            let _ = chars.next_if(|(_, next)| *next == c);
+            result.push(c);
+            continue;
+        }
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next().map(|(_, c)| c) {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            _ => return Err(i),
+        }
+    }
+    Ok(result)
+}
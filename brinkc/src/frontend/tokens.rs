@@ -1,5 +1,6 @@
 use super::token::{Token, TokenKind};
 
+#[derive(Clone)]
 pub struct Tokens {
     tokens: Vec<Token>,
     position: usize,
@@ -8,7 +9,7 @@ pub struct Tokens {
 impl Tokens {
     pub fn new(tokens: Vec<Token>) -> Self {
         Self {
-            tokens,
+            tokens: normalize_layout(tokens),
             position: 0,
         }
     }
@@ -27,13 +28,17 @@ impl Tokens {
         self.tokens[self.position]
     }
 
+    /// Gets the unconsumed token following the first one without advancing.
+    pub fn peek_second(&mut self) -> Token {
+        self.tokens[(self.position + 1).min(self.tokens.len() - 1)]
+    }
+
     /// Gets the recently advanced token.
     pub fn previous(&mut self) -> Token {
         self.tokens[self.position - 1]
     }
 
     pub fn check(&mut self, kind: TokenKind) -> bool {
-        println!("checking {:?}", kind);
         self.tokens[self.position].kind == kind
     }
 
@@ -45,4 +50,256 @@ impl Tokens {
             None
         }
     }
+
+    /// Consumes the first unconsumed token, whatever it is. The end of file
+    /// is never consumed.
+    pub fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position];
+        if token.kind != TokenKind::EndOfFile {
+            self.position += 1;
+        }
+        token
+    }
+}
+
+/// Collapses the layout tokens produced by the lexer for consecutive lines
+/// into the tokens describing the change of indentation between the lines
+/// containing actual code, so blank lines neither end nor start blocks.
+///
+/// Inside of brackets the indentation is insignificant, so the code can be
+/// freely split across lines; the line breaks are kept as single newlines.
+/// The exception is a block opened by a token such as `->` at the end of a
+/// line, e.g. the indented body of a lambda passed as an argument. Such a
+/// block lasts until a less indented line or the closing bracket.
+///
+/// The indentation is balanced at the end of file, and the last line of code
+/// is always terminated by either a newline or a dedent.
+fn normalize_layout(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(tokens.len());
+    // Indentation level of the current line, as tracked by the lexer.
+    let mut line_level: i32 = 0;
+    // Indentation level of the line containing the last emitted token.
+    let mut previous_line_level = 0;
+    // The innermost context last. The outermost one is the file itself,
+    // whose base is the indentation of the first line of code.
+    let mut contexts = vec![Context::Layout {
+        base: None,
+        level: 0,
+    }];
+    let mut pending_layout = None;
+
+    for token in tokens {
+        match token.kind {
+            TokenKind::Indent => {
+                line_level += 1;
+                pending_layout.get_or_insert(token);
+            }
+            TokenKind::Dedent => {
+                line_level -= 1;
+                pending_layout.get_or_insert(token);
+            }
+            TokenKind::NewLine => {
+                pending_layout.get_or_insert(token);
+            }
+            TokenKind::EndOfFile => {
+                while contexts.len() > 1 {
+                    close_context(contexts.pop().unwrap(), token, &mut result);
+                }
+                let last = result.last().map(|t| t.kind);
+                if let Some(Context::Layout { level, .. }) = contexts.pop() {
+                    if level > 0 {
+                        push_layout(&mut result, TokenKind::Dedent, level, token);
+                    } else if last.is_some() && last != Some(TokenKind::NewLine) {
+                        push_layout(&mut result, TokenKind::NewLine, 1, token);
+                    }
+                }
+                result.push(token);
+                return result;
+            }
+            _ => {
+                if let Some(layout) = pending_layout.take() {
+                    // Close the blocks nested in brackets which end before
+                    // this line.
+                    while let Some(Context::Layout {
+                        base: Some(base), ..
+                    }) = contexts.last()
+                    {
+                        if contexts.len() == 1 || line_level >= *base {
+                            break;
+                        }
+                        close_context(contexts.pop().unwrap(), layout, &mut result);
+                    }
+
+                    match contexts.last_mut().unwrap() {
+                        Context::Layout { base, level } => {
+                            let base = *base.get_or_insert(line_level);
+                            if !result.is_empty() {
+                                let delta = (line_level - base).max(0) - *level;
+                                let kind = if delta > 0 {
+                                    TokenKind::Indent
+                                } else if delta < 0 {
+                                    TokenKind::Dedent
+                                } else {
+                                    TokenKind::NewLine
+                                };
+                                push_layout(&mut result, kind, delta.abs().max(1), layout);
+                                *level += delta;
+                            }
+                        }
+                        Context::Bracket => {
+                            let opens_block = result.last().is_some_and(|t| opens_block(t.kind));
+                            if opens_block && line_level > previous_line_level {
+                                contexts.push(Context::Layout {
+                                    base: Some(line_level),
+                                    level: 0,
+                                });
+                                push_layout(&mut result, TokenKind::Indent, 1, layout);
+                            } else {
+                                push_layout(&mut result, TokenKind::NewLine, 1, layout);
+                            }
+                        }
+                    }
+                } else if contexts.len() == 1 {
+                    if let Context::Layout { base, .. } = &mut contexts[0] {
+                        base.get_or_insert(line_level);
+                    }
+                }
+
+                match token.kind {
+                    TokenKind::LeftParen
+                    | TokenKind::LeftBrace
+                    | TokenKind::LeftBracket
+                    | TokenKind::LeftBracketBar => contexts.push(Context::Bracket),
+                    TokenKind::RightParen
+                    | TokenKind::RightBrace
+                    | TokenKind::RightBracket
+                    | TokenKind::BarRightBracket
+                        if contexts.contains(&Context::Bracket) =>
+                    {
+                        // The blocks opened inside of the brackets end with
+                        // them.
+                        while let Some(context) = contexts.pop() {
+                            if context == Context::Bracket {
+                                break;
+                            }
+                            close_context(context, token, &mut result);
+                        }
+                    }
+                    _ => {}
+                }
+                previous_line_level = line_level;
+                result.push(token);
+            }
+        }
+    }
+
+    result
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Context {
+    /// The indentation is significant. The base is the indentation level of
+    /// the first line in the context, and the level is the number of the
+    /// indents emitted since.
+    Layout {
+        base: Option<i32>,
+        level: i32,
+    },
+    Bracket,
+}
+
+/// Emits the dedents closing the block opened inside of brackets, including
+/// the indent which opened it.
+fn close_context(context: Context, at: Token, result: &mut Vec<Token>) {
+    if let Context::Layout { level, .. } = context {
+        push_layout(result, TokenKind::Dedent, level + 1, at);
+    }
+}
+
+fn push_layout(result: &mut Vec<Token>, kind: TokenKind, count: i32, at: Token) {
+    for _ in 0..count {
+        result.push(Token { kind, ..at });
+    }
+}
+
+/// Checks whether the token can be followed by an indented block.
+fn opens_block(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Arrow
+            | TokenKind::In
+            | TokenKind::Then
+            | TokenKind::Else
+            | TokenKind::Do
+            | TokenKind::Try
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frontend::lexer::Lexer, source_file::IndentKind};
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        let tokens = Tokens::new(Lexer::tokenize_source_code(input, IndentKind::Spaces(2)));
+        tokens.as_vec().iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn ignores_blank_lines() {
+        use TokenKind::*;
+        let input = "let x =\n  1\n\n  2\n\nx\n";
+
+        assert_eq!(
+            vec![
+                Let, Identifier, Equal, Indent, Integer, NewLine, Integer, Dedent, Identifier,
+                NewLine, EndOfFile
+            ],
+            kinds(input)
+        );
+    }
+
+    #[test]
+    fn balances_indentation_at_end_of_file() {
+        use TokenKind::*;
+        let input = "\n\nx =\n  y =\n    1";
+
+        assert_eq!(
+            vec![
+                Identifier, Equal, Indent, Identifier, Equal, Indent, Integer, Dedent, Dedent,
+                EndOfFile
+            ],
+            kinds(input)
+        );
+    }
+
+    #[test]
+    fn opens_block_inside_brackets() {
+        use TokenKind::*;
+        let input = "f (fun x ->\n    x\n    x)\ng (fun y ->\n  y\n)\nz";
+
+        assert_eq!(
+            vec![
+                Identifier, LeftParen, Fun, Identifier, Arrow, Indent, Identifier, NewLine,
+                Identifier, Dedent, RightParen, NewLine, Identifier, LeftParen, Fun, Identifier,
+                Arrow, Indent, Identifier, Dedent, NewLine, RightParen, NewLine, Identifier,
+                NewLine, EndOfFile
+            ],
+            kinds(input)
+        );
+    }
+
+    #[test]
+    fn ignores_indentation_inside_brackets() {
+        use TokenKind::*;
+        let input = "x = {\n    y\n  }\nz";
+
+        assert_eq!(
+            vec![
+                Identifier, Equal, LeftBrace, NewLine, Identifier, NewLine, RightBrace, NewLine,
+                Identifier, NewLine, EndOfFile
+            ],
+            kinds(input)
+        );
+    }
 }
@@ -0,0 +1,278 @@
+use crate::frontend::token::{self, TokenClass, KEYWORDS};
+
+/// The characters continuing an identifier, as the lexer reads them.
+const IDENTIFIER_PART: &str = "[[:alnum:]_']";
+
+/// Generates the TextMate grammar of the language, as the JSON of a
+/// `.tmLanguage.json` file. The keywords and the operator characters come
+/// from the lexer's tables, and the scopes from the token classes.
+pub fn textmate_grammar() -> String {
+    let operator_start = char_class(token::is_operator_start);
+    let operator_part = char_class(token::is_operator_part);
+    let mut patterns = vec![
+        rule(TokenClass::Comment, "#.*$"),
+        interpolated_string(),
+        format!(
+            "{{ \"name\": {}, \"begin\": \"\\\"\", \"end\": \"\\\"|$\", \"patterns\": [{{ \"include\": \"#escape\" }}] }}",
+            scope(TokenClass::String)
+        ),
+        // After an operand, the at sign is an operator, e.g. in `xs@ys`.
+        rule(
+            TokenClass::Attribute,
+            "(?<![[:alnum:]_'\\)\\]}])@[[:alpha:]][[:alnum:]_]*",
+        ),
+        rule(TokenClass::TypeVariable, "'[[:alnum:]_]+"),
+    ];
+    for class in TokenClass::ALL {
+        let words = KEYWORDS
+            .iter()
+            .filter(|(_, kind)| kind.class() == Some(class))
+            .map(|(keyword, _)| *keyword)
+            .collect::<Vec<_>>();
+        if !words.is_empty() {
+            patterns.push(rule(
+                class,
+                &format!(
+                    "(?<!{part})(?:{})(?!{part})",
+                    words.join("|"),
+                    part = IDENTIFIER_PART
+                ),
+            ));
+        }
+    }
+    patterns.push(rule(TokenClass::Identifier, "[[:alpha:]_][[:alnum:]_']*"));
+    patterns.push(rule(TokenClass::Number, "[0-9]+"));
+    patterns.push(rule(
+        TokenClass::Punctuation,
+        "\\[\\||\\|\\]|[.,;()\\[\\]{}]",
+    ));
+    // The built-in operators which are punctuation, e.g. `:`.
+    for c in (' '..='~').filter(|c| token::is_operator_start(*c)) {
+        let class = token::get_operator_kind(&c.to_string()).and_then(|kind| kind.class());
+        if class == Some(TokenClass::Punctuation) {
+            patterns.push(rule(
+                TokenClass::Punctuation,
+                &format!("\\{}(?!{})", c, operator_part),
+            ));
+        }
+    }
+    // A bar before a bracket closes an array rather than ends an operator.
+    patterns.push(rule(
+        TokenClass::Operator,
+        &format!(
+            "(?!\\|\\]){0}(?:(?!\\|\\]){1})*",
+            operator_start, operator_part
+        ),
+    ));
+
+    let escape = format!(
+        "{{ \"name\": \"constant.character.escape.brink\", \"match\": {} }}",
+        json_string("\\\\[ntr\\\\\"]")
+    );
+    format!(
+        "{{\n  \"$schema\": \"https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json\",\n  \
+         \"name\": \"Brink\",\n  \"scopeName\": \"source.brink\",\n  \"fileTypes\": [\"bk\"],\n  \
+         \"patterns\": [\n    {}\n  ],\n  \"repository\": {{\n    \"escape\": {}\n  }}\n}}\n",
+        patterns.join(",\n    "),
+        escape
+    )
+}
+
+/// The interpolated strings, whose holes are expressions and whose braces
+/// are escaped by doubling them.
+fn interpolated_string() -> String {
+    let punctuation = |position| {
+        format!(
+            "{{ \"0\": {{ \"name\": \"punctuation.section.interpolation.{}.brink\" }} }}",
+            position
+        )
+    };
+    format!(
+        "{{ \"name\": {}, \"begin\": \"\\\\$\\\"\", \"end\": \"\\\"|$\", \"patterns\": [\
+         {{ \"include\": \"#escape\" }}, \
+         {{ \"name\": \"constant.character.escape.brink\", \"match\": {} }}, \
+         {{ \"name\": \"meta.interpolation.brink\", \"begin\": \"\\\\{{\", \"end\": \"\\\\}}\", \
+         \"beginCaptures\": {}, \"endCaptures\": {}, \"patterns\": [{{ \"include\": \"$self\" }}] }}] }}",
+        scope(TokenClass::String),
+        json_string("\\{\\{|\\}\\}"),
+        punctuation("begin"),
+        punctuation("end")
+    )
+}
+
+fn rule(class: TokenClass, regex: &str) -> String {
+    format!(
+        "{{ \"name\": {}, \"match\": {} }}",
+        scope(class),
+        json_string(regex)
+    )
+}
+
+fn scope(class: TokenClass) -> String {
+    json_string(&format!("{}.brink", class.scope()))
+}
+
+/// Builds the bracket expression of the printable ASCII characters
+/// satisfying the predicate of the lexer.
+fn char_class(predicate: fn(char) -> bool) -> String {
+    let chars = (' '..='~')
+        .filter(|c| predicate(*c))
+        .map(|c| format!("\\{}", c))
+        .collect::<String>();
+    format!("[{}]", chars)
+}
+
+fn json_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, iter::Peekable, str::Chars};
+
+    use super::textmate_grammar;
+
+    #[derive(Debug)]
+    enum Json {
+        String(String),
+        Array(Vec<Json>),
+        Object(BTreeMap<String, Json>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> Option<&Json> {
+            match self {
+                Json::Object(members) => members.get(key),
+                _ => None,
+            }
+        }
+    }
+
+    /// Parses the JSON of the grammar, which only has strings, arrays and
+    /// objects, and fails on anything else.
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected `{}` after the value", c)),
+        }
+    }
+
+    fn parse_value(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+        skip_whitespace(chars);
+        match chars.next() {
+            Some('"') => parse_string(chars).map(Json::String),
+            Some('[') => {
+                let mut elements = Vec::new();
+                skip_whitespace(chars);
+                if chars.next_if_eq(&']').is_none() {
+                    loop {
+                        elements.push(parse_value(chars)?);
+                        skip_whitespace(chars);
+                        match chars.next() {
+                            Some(',') => {}
+                            Some(']') => break,
+                            c => return Err(format!("expected `,` or `]`, found {:?}", c)),
+                        }
+                    }
+                }
+                Ok(Json::Array(elements))
+            }
+            Some('{') => {
+                let mut members = BTreeMap::new();
+                skip_whitespace(chars);
+                if chars.next_if_eq(&'}').is_none() {
+                    loop {
+                        skip_whitespace(chars);
+                        if chars.next() != Some('"') {
+                            return Err("expected a key".to_string());
+                        }
+                        let key = parse_string(chars)?;
+                        skip_whitespace(chars);
+                        if chars.next() != Some(':') {
+                            return Err(format!("expected `:` after `{}`", key));
+                        }
+                        if members.insert(key.clone(), parse_value(chars)?).is_some() {
+                            return Err(format!("duplicate key `{}`", key));
+                        }
+                        skip_whitespace(chars);
+                        match chars.next() {
+                            Some(',') => {}
+                            Some('}') => break,
+                            c => return Err(format!("expected `,` or `}}`, found {:?}", c)),
+                        }
+                    }
+                }
+                Ok(Json::Object(members))
+            }
+            c => Err(format!("expected a value, found {:?}", c)),
+        }
+    }
+
+    fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match chars.next() {
+                    Some(c @ ('"' | '\\' | '/')) => string.push(c),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    c => return Err(format!("invalid escape {:?}", c)),
+                },
+                Some(c) if c < ' ' => return Err(format!("control character {:?}", c)),
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn skip_whitespace(chars: &mut Peekable<Chars>) {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    #[test]
+    fn generates_valid_json() {
+        let grammar = parse(&textmate_grammar()).unwrap();
+        assert!(matches!(
+            grammar.get("scopeName"),
+            Some(Json::String(scope)) if scope == "source.brink"
+        ));
+        let Some(Json::Array(patterns)) = grammar.get("patterns") else {
+            panic!("the grammar has no patterns");
+        };
+        for pattern in patterns {
+            assert!(
+                matches!(pattern.get("name"), Some(Json::String(_))),
+                "{:?}",
+                pattern
+            );
+            assert!(
+                pattern.get("match").is_some() || pattern.get("begin").is_some(),
+                "{:?}",
+                pattern
+            );
+        }
+        assert!(grammar
+            .get("repository")
+            .and_then(|repository| repository.get("escape"))
+            .is_some());
+    }
+
+    #[test]
+    fn generates_the_keywords_and_operators() {
+        let grammar = textmate_grammar();
+        assert!(grammar.contains("\"scopeName\": \"source.brink\""));
+        assert!(grammar.contains(
+            "{ \"name\": \"constant.language.boolean.brink\", \
+             \"match\": \"(?<![[:alnum:]_'])(?:true|false)(?![[:alnum:]_'])\" }"
+        ));
+        assert!(grammar.contains("(?:and|do|downto|elif|else|exception|for|fun|if|import|in|"));
+        assert!(grammar
+            .contains("{ \"name\": \"punctuation.brink\", \"match\": \"\\\\:(?![\\\\!\\\\$\\\\%"));
+        assert!(grammar
+            .contains("{ \"name\": \"keyword.operator.brink\", \"match\": \"(?!\\\\|\\\\])[\\\\!"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Syntax highlighting with the compiler's own lexer. `brinkc highlight`
+//! renders a file as HTML or for the terminal, and `brinkc grammar` exports
+//! the lexical grammar, so the editors classify the code as the lexer does.
+
+use crate::{
+    frontend::{lexer::Lexer, token::TokenClass},
+    source_file::{SourceFile, SourceSpan},
+};
+
+mod grammar;
+
+pub use grammar::textmate_grammar;
+
+/// The colors of the classes in the HTML pages. The identifiers and the
+/// punctuation keep the color of the text.
+const STYLESHEET: &str = "\
+.brink { background: #fafafa; color: #383a42; padding: 1em; }
+.bk-keyword { color: #a626a4; }
+.bk-constant, .bk-number { color: #986801; }
+.bk-type-variable { color: #c18401; }
+.bk-string { color: #50a14f; }
+.bk-attribute { color: #4078f2; }
+.bk-operator { color: #0184bc; }
+.bk-comment { color: #a0a1a7; font-style: italic; }
+.bk-invalid { color: #e45649; text-decoration: underline wavy; }
+";
+
+/// Classifies the tokens and the comments of the file, in order. The
+/// whitespace and the layout are not classified.
+pub fn classify(source_file: &SourceFile) -> Vec<(SourceSpan, TokenClass)> {
+    let tokens = Lexer::tokenize(source_file);
+    let mut spans = tokens
+        .as_vec()
+        .iter()
+        .filter(|token| !token.span.is_empty())
+        .filter_map(|token| Some((token.span, token.kind.class()?)))
+        .chain(
+            Lexer::comments(source_file)
+                .into_iter()
+                .map(|span| (span, TokenClass::Comment)),
+        )
+        .collect::<Vec<_>>();
+    spans.sort_by_key(|(span, _)| span.start);
+    spans
+}
+
+/// Splits the whole source code of the file into the classified tokens and
+/// the text between them.
+fn segments(source_file: &SourceFile) -> Vec<(Option<TokenClass>, &str)> {
+    let mut segments = Vec::new();
+    let mut last_end = source_file.start_pos;
+    for (span, class) in classify(source_file) {
+        if span.start > last_end {
+            segments.push((
+                None,
+                source_file.read_span(SourceSpan::new(last_end, span.start)),
+            ));
+        }
+        segments.push((Some(class), source_file.read_span(span)));
+        last_end = span.end;
+    }
+    let end = source_file.end_pos();
+    if end > last_end {
+        segments.push((None, source_file.read_span(SourceSpan::new(last_end, end))));
+    }
+    segments
+}
+
+/// Renders the file as a standalone HTML page, with the tokens in spans of
+/// the `bk-` classes.
+pub fn to_html(source_file: &SourceFile) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\n{}</style>\n</head>\n<body>\n<pre class=\"brink\"><code>",
+        escape_html(&source_file.file_path),
+        STYLESHEET
+    );
+    for (class, text) in segments(source_file) {
+        match class {
+            Some(class) => html.push_str(&format!(
+                "<span class=\"bk-{}\">{}</span>",
+                class.name(),
+                escape_html(text)
+            )),
+            None => html.push_str(&escape_html(text)),
+        }
+    }
+    html.push_str("</code></pre>\n</body>\n</html>\n");
+    html
+}
+
+/// Renders the file with the ANSI colors of the terminals.
+pub fn to_ansi(source_file: &SourceFile) -> String {
+    let mut output = String::new();
+    for (class, text) in segments(source_file) {
+        match class.and_then(ansi_color) {
+            Some(color) => output.push_str(&format!("\x1b[{}m{}\x1b[0m", color, text)),
+            None => output.push_str(text),
+        }
+    }
+    output
+}
+
+fn ansi_color(class: TokenClass) -> Option<&'static str> {
+    match class {
+        TokenClass::Keyword => Some("35"),
+        TokenClass::Constant | TokenClass::Number | TokenClass::TypeVariable => Some("33"),
+        TokenClass::String => Some("32"),
+        TokenClass::Attribute => Some("34"),
+        TokenClass::Operator => Some("36"),
+        TokenClass::Comment => Some("2;3"),
+        TokenClass::Invalid => Some("31;4"),
+        TokenClass::Identifier | TokenClass::Punctuation => None,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{frontend::token::TokenClass, source_file::SourceMap};
+
+    use super::{classify, to_html};
+
+    #[test]
+    fn classifies_tokens_and_comments() {
+        let mut source_map = SourceMap::new();
+        let file = source_map.add_file(
+            "test.bk".to_string(),
+            "let x = \"#\" # note\n@tailcall f 'a true |> [|x|]\n".to_string(),
+        );
+        let source_file = source_map.file(file);
+        let classes = classify(source_file)
+            .into_iter()
+            .map(|(span, class)| (source_file.read_span(span), class))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            classes,
+            [
+                ("let", TokenClass::Keyword),
+                ("x", TokenClass::Identifier),
+                ("=", TokenClass::Operator),
+                ("\"#\"", TokenClass::String),
+                ("# note", TokenClass::Comment),
+                ("@tailcall", TokenClass::Attribute),
+                ("f", TokenClass::Identifier),
+                ("'a", TokenClass::TypeVariable),
+                ("true", TokenClass::Constant),
+                ("|>", TokenClass::Operator),
+                ("[|", TokenClass::Punctuation),
+                ("x", TokenClass::Identifier),
+                ("|]", TokenClass::Punctuation),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_escaped_html() {
+        let mut source_map = SourceMap::new();
+        let file = source_map.add_file("a<b>.bk".to_string(), "x < \"&\"\n".to_string());
+        let html = to_html(source_map.file(file));
+        assert!(html.contains("<title>a&lt;b&gt;.bk</title>"));
+        assert!(html.contains(
+            "<code><span class=\"bk-identifier\">x</span> \
+             <span class=\"bk-operator\">&lt;</span> \
+             <span class=\"bk-string\">&quot;&amp;&quot;</span>\n</code>"
+        ));
+    }
+}
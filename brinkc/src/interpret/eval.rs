@@ -0,0 +1,1079 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{self, node_id::NodeId},
+    frontend::token,
+    resolve::{Builtin, DefId, DefKind, ModuleGraph, Res, Resolutions},
+    source_file::{SourceMap, SourceSpan},
+    typeck::{
+        format::{self, FormatPiece},
+        ty::Ty,
+        AdtKind, TypeckResults,
+    },
+    INTERPRETER_STACK_SIZE, MAX_CALL_DEPTH,
+};
+
+use super::{
+    value::{Closure, Function, FunctionKind, Tag, Value},
+    TraceFrame, UncaughtException,
+};
+
+/// Evaluates the type checked program by walking its syntax tree. The file
+/// modules are evaluated in the dependency order, and the items of each
+/// module from top to bottom. Returns the values of the module-level
+/// bindings. It runs on a thread with a stack of `INTERPRETER_STACK_SIZE`,
+/// as the calls nest on it.
+pub fn interpret<'a>(
+    source_map: &'a SourceMap,
+    graph: &'a ModuleGraph,
+    resolutions: &'a Resolutions,
+    typeck_results: &'a TypeckResults,
+) -> Result<HashMap<DefId, Value<'a>>, UncaughtException> {
+    let mut interpreter = Interpreter {
+        source_map,
+        resolutions,
+        typeck_results,
+        globals: HashMap::new(),
+        frames: Vec::new(),
+        stack_limit: stack_position().saturating_sub(INTERPRETER_STACK_SIZE - STACK_RESERVE),
+    };
+
+    for module in &graph.modules {
+        let start = source_map.file(module.file).start_pos;
+        interpreter.frames.push(Frame {
+            function: module.name.as_str().into(),
+            call_span: SourceSpan::new(start, start),
+            locals: HashMap::new(),
+        });
+        let result = interpreter.eval_module_items(&module.program.body);
+        interpreter.frames.pop();
+        if let Err(unwind) = result {
+            return Err(UncaughtException {
+                exception: unwind.exception.display(resolutions),
+                trace: unwind.trace,
+            });
+        }
+    }
+
+    Ok(interpreter.globals)
+}
+
+/// The part of the stack left for the evaluation of the calls started
+/// before `StackOverflow` is raised, and for the callers of `interpret`.
+const STACK_RESERVE: usize = 64 << 20;
+
+/// Gets the address of the top of the stack, which grows down.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// An exception propagating up the stack, with the trace captured when it
+/// was raised.
+struct Unwind<'a> {
+    exception: Value<'a>,
+    trace: Vec<TraceFrame>,
+}
+
+type EvalResult<'a> = Result<Value<'a>, Unwind<'a>>;
+
+/// What an expression in tail position evaluates to: a value, or the call
+/// whose result is the value. The call is made by the caller of the
+/// enclosing function after its frame is popped, so the tail calls run in
+/// constant space.
+enum Tail<'a> {
+    Value(Value<'a>),
+    Call(Value<'a>, Vec<Value<'a>>, SourceSpan),
+}
+
+type TailResult<'a> = Result<Tail<'a>, Unwind<'a>>;
+
+struct Frame<'a> {
+    function: Rc<str>,
+    /// Span of the call which created the frame.
+    call_span: SourceSpan,
+    /// The values of the local bindings, by the node of the bound
+    /// identifier. The node ids are unique, so a single map holds all the
+    /// scopes of the function.
+    locals: HashMap<NodeId, Value<'a>>,
+}
+
+struct Interpreter<'a> {
+    source_map: &'a SourceMap,
+    resolutions: &'a Resolutions,
+    typeck_results: &'a TypeckResults,
+    /// The values of the module-level bindings.
+    globals: HashMap<DefId, Value<'a>>,
+    /// The active calls, innermost last. The first frame evaluates the
+    /// top-level code of the current module.
+    frames: Vec<Frame<'a>>,
+    /// The address of the stack below which no call starts, so the calls
+    /// raise `StackOverflow` before they exhaust the stack, however large
+    /// the frames of the build are.
+    stack_limit: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    fn eval_module_items(&mut self, items: &'a [ast::Item]) -> Result<(), Unwind<'a>> {
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    let value = self.eval_let_binding(let_binding)?;
+                    if let Some(def) = self.resolutions.def_of_node.get(&let_binding.id) {
+                        self.globals.insert(*def, value);
+                    }
+                }
+                // The module-level functions refer to each other through the
+                // globals, which are looked up when they are called.
+                ast::ItemKind::LetRec(bindings) => {
+                    for let_binding in bindings {
+                        let value = self.eval_let_binding(let_binding)?;
+                        if let Some(def) = self.resolutions.def_of_node.get(&let_binding.id) {
+                            self.globals.insert(*def, value);
+                        }
+                    }
+                }
+                ast::ItemKind::Module(module) => self.eval_module_items(&module.body.items)?,
+                ast::ItemKind::Expr(expr) => {
+                    let _ = self.eval_expr(expr)?;
+                }
+                ast::ItemKind::Type(_)
+                | ast::ItemKind::Open(_)
+                | ast::ItemKind::Import(_)
+                | ast::ItemKind::Exception(_)
+                | ast::ItemKind::Fixity(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates the value bound by the let binding. The functions, and the
+    /// bindings of lambdas, become closures named after the binding.
+    fn eval_let_binding(&mut self, let_binding: &'a ast::LetBinding) -> EvalResult<'a> {
+        match self.let_closure(let_binding, &[]) {
+            Some(closure) => Ok(function_value(closure)),
+            None => self.eval_let_body(&let_binding.body),
+        }
+    }
+
+    /// Creates the closure of the let-bound function, or of the lambda it
+    /// binds, which does not capture the recursive bindings of the group.
+    fn let_closure(
+        &self,
+        let_binding: &'a ast::LetBinding,
+        group: &[NodeId],
+    ) -> Option<Closure<'a>> {
+        let name = self.text(let_binding.identifier.span).into();
+        if !let_binding.parameters.is_empty() {
+            return Some(self.closure(
+                let_binding.id,
+                name,
+                &let_binding.parameters,
+                &let_binding.body,
+                group,
+            ));
+        }
+        match &let_binding.body {
+            ast::LetBody::Expr(ast::Expr {
+                id,
+                kind: ast::ExprKind::Lambda(parameters, body),
+                ..
+            }) => Some(self.closure(*id, name, parameters, body, group)),
+            _ => None,
+        }
+    }
+
+    fn eval_let_body(&mut self, body: &'a ast::LetBody) -> EvalResult<'a> {
+        let tail = self.eval_tail_let_body(body)?;
+        self.finish_tail(tail)
+    }
+
+    fn eval_tail_let_body(&mut self, body: &'a ast::LetBody) -> TailResult<'a> {
+        match body {
+            ast::LetBody::Block(block) => self.eval_tail_block(block),
+            ast::LetBody::Expr(expr) => self.eval_tail_expr(expr),
+        }
+    }
+
+    fn eval_tail_block(&mut self, block: &'a ast::Block) -> TailResult<'a> {
+        let mut result = Tail::Value(Value::Unit);
+        for (i, item) in block.items.iter().enumerate() {
+            result = match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    self.eval_local_let_binding(let_binding)?;
+                    Tail::Value(Value::Unit)
+                }
+                ast::ItemKind::LetRec(bindings) => {
+                    self.eval_local_let_rec(bindings);
+                    Tail::Value(Value::Unit)
+                }
+                ast::ItemKind::Expr(expr) if i == block.items.len() - 1 => {
+                    self.eval_tail_expr(expr)?
+                }
+                ast::ItemKind::Expr(expr) => Tail::Value(self.eval_expr(expr)?),
+                // Rejected by the resolver.
+                _ => Tail::Value(Value::Unit),
+            };
+        }
+        Ok(result)
+    }
+
+    fn eval_local_let_binding(
+        &mut self,
+        let_binding: &'a ast::LetBinding,
+    ) -> Result<(), Unwind<'a>> {
+        if let_binding.is_recursive {
+            self.eval_local_let_rec(std::slice::from_ref(let_binding));
+            return Ok(());
+        }
+        let value = self.eval_let_binding(let_binding)?;
+        self.bind(let_binding.identifier.id, value);
+        Ok(())
+    }
+
+    /// Binds the recursive local functions. The functions of a group do not
+    /// capture each other, as they are bound again on every call.
+    fn eval_local_let_rec(&mut self, bindings: &'a [ast::LetBinding]) {
+        let nodes = bindings
+            .iter()
+            .map(|let_binding| let_binding.identifier.id)
+            .collect::<Vec<_>>();
+        // The resolver only accepts the recursive bindings of functions.
+        let recursive = bindings
+            .iter()
+            .filter_map(|let_binding| {
+                let closure = self.let_closure(let_binding, &nodes)?;
+                Some((let_binding.identifier.id, Rc::new(closure)))
+            })
+            .collect::<Rc<[_]>>();
+        for (node, closure) in recursive.iter() {
+            let value = recursive_function(closure, &recursive);
+            self.bind(*node, value);
+        }
+    }
+
+    /// Creates the closure, capturing the current values of its free
+    /// variables except for the recursive bindings of its group.
+    fn closure(
+        &self,
+        node: NodeId,
+        name: Rc<str>,
+        parameters: &'a [ast::Pattern],
+        body: &'a ast::LetBody,
+        group: &[NodeId],
+    ) -> Closure<'a> {
+        let captured = self
+            .resolutions
+            .free_variables
+            .get(&node)
+            .map_or(&[][..], |v| &v[..])
+            .iter()
+            .filter(|variable| !group.contains(variable))
+            .map(|variable| (*variable, self.local(*variable)))
+            .collect();
+        Closure {
+            name,
+            parameters,
+            body,
+            captured,
+            recursive: Rc::new([]),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &'a ast::Expr) -> EvalResult<'a> {
+        match &expr.kind {
+            ast::ExprKind::Literal(literal) => Ok(self.literal_value(literal)),
+            ast::ExprKind::Path(path) => Ok(self.path_value(path)),
+            ast::ExprKind::Application(..)
+            | ast::ExprKind::TailCall(_)
+            | ast::ExprKind::Paren(_)
+            | ast::ExprKind::Typed(..)
+            | ast::ExprKind::Match(..)
+            | ast::ExprKind::Try(..)
+            | ast::ExprKind::If(..)
+            | ast::ExprKind::Let(..)
+            | ast::ExprKind::Infix(..) => {
+                let tail = self.eval_tail_expr(expr)?;
+                self.finish_tail(tail)
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                let mut result = String::new();
+                for part in parts {
+                    match part {
+                        ast::InterpolationPart::Text(span) => {
+                            let text = token::unescape_interpolated_text(self.text(*span));
+                            result.push_str(&text.unwrap_or_default());
+                        }
+                        ast::InterpolationPart::Hole(hole) => {
+                            result.push_str(&self.eval_expr(hole)?.format_plain())
+                        }
+                    }
+                }
+                Ok(Value::String(result.into()))
+            }
+            ast::ExprKind::Tuple(elements) => Ok(Value::Tuple(Rc::new(self.eval_exprs(elements)?))),
+            ast::ExprKind::List(elements) => Ok(Value::list(self.eval_exprs(elements)?)),
+            ast::ExprKind::Array(elements) => Ok(Value::Array(Rc::new(self.eval_exprs(elements)?))),
+            ast::ExprKind::Record(fields) => {
+                let owner = self.field_owner(&fields[0].identifier);
+                let count = match &self.typeck_results.adts[&owner].kind {
+                    AdtKind::Record(field_defs) => field_defs.len(),
+                    AdtKind::Variant(_) => 0,
+                };
+                let mut values = vec![Value::Unit; count];
+                self.eval_fields(fields, &mut values)?;
+                Ok(Value::Record(owner, Rc::new(values)))
+            }
+            ast::ExprKind::RecordUpdate(record, fields) => match self.eval_expr(record)? {
+                Value::Record(owner, values) => {
+                    let mut values = values.as_ref().clone();
+                    self.eval_fields(fields, &mut values)?;
+                    Ok(Value::Record(owner, Rc::new(values)))
+                }
+                value => unreachable!("record update of {:?}", value),
+            },
+            ast::ExprKind::FieldAccess(record, field) => match self.eval_expr(record)? {
+                Value::Record(_, values) => Ok(values[self.field_index(field)].clone()),
+                value => unreachable!("field access on {:?}", value),
+            },
+            ast::ExprKind::Lambda(parameters, body) => {
+                let closure = self.closure(expr.id, "<fun>".into(), parameters, body, &[]);
+                Ok(function_value(closure))
+            }
+            ast::ExprKind::While(condition, body) => {
+                while self.eval_bool(condition)? {
+                    let _ = self.eval_let_body(body)?;
+                }
+                Ok(Value::Unit)
+            }
+            ast::ExprKind::For(for_loop) => {
+                let start = self.eval_int(&for_loop.start)?;
+                let end = self.eval_int(&for_loop.end)?;
+                let mut i = start;
+                loop {
+                    let done = match for_loop.direction {
+                        ast::ForDirection::Up => i > end,
+                        ast::ForDirection::Down => i < end,
+                    };
+                    if done {
+                        break;
+                    }
+                    self.bind(for_loop.binding.id, Value::Int(i));
+                    let _ = self.eval_let_body(&for_loop.body)?;
+                    i = match for_loop.direction {
+                        ast::ForDirection::Up => i + 1,
+                        ast::ForDirection::Down => i - 1,
+                    };
+                }
+                Ok(Value::Unit)
+            }
+            ast::ExprKind::Binary(op, lhs, rhs) => self.eval_binary(expr.span, *op, lhs, rhs),
+            ast::ExprKind::Section(operator, lhs, rhs) => {
+                let operator = match operator {
+                    ast::SectionOperator::Builtin(op) => Value::Function(Rc::new(Function {
+                        kind: FunctionKind::Operator(op.kind),
+                        arity: 2,
+                        arguments: Vec::new(),
+                    })),
+                    ast::SectionOperator::User(path) => self.path_value(path),
+                };
+                match (lhs, rhs) {
+                    (Some(lhs), _) => {
+                        let lhs = self.eval_expr(lhs)?;
+                        self.apply(operator, vec![lhs], expr.span)
+                    }
+                    (None, Some(rhs)) => {
+                        let rhs = self.eval_expr(rhs)?;
+                        Ok(Value::Function(Rc::new(Function {
+                            kind: FunctionKind::Flipped(operator),
+                            arity: 2,
+                            arguments: vec![rhs],
+                        })))
+                    }
+                    (None, None) => Ok(operator),
+                }
+            }
+            ast::ExprKind::Unary(op, operand) => {
+                let value = self.eval_expr(operand)?;
+                Ok(match (op.kind, value) {
+                    (ast::UnaryOpKind::Negate, Value::Int(value)) => {
+                        Value::Int(value.wrapping_neg())
+                    }
+                    (ast::UnaryOpKind::Deref, Value::Ref(cell)) => cell.borrow().clone(),
+                    (_, value) => unreachable!("unary operator applied to {:?}", value),
+                })
+            }
+        }
+    }
+
+    /// Evaluates the expression in tail position, leaving the call it ends
+    /// with, if any, to the caller.
+    fn eval_tail_expr(&mut self, expr: &'a ast::Expr) -> TailResult<'a> {
+        match &expr.kind {
+            ast::ExprKind::Application(callee, arguments) => {
+                let function = self.eval_expr(callee)?;
+                let arguments = self.eval_exprs(arguments)?;
+                Ok(Tail::Call(function, arguments, expr.span))
+            }
+            ast::ExprKind::Infix(operator, lhs, rhs) => {
+                let function = self.path_value(operator);
+                let arguments = vec![self.eval_expr(lhs)?, self.eval_expr(rhs)?];
+                Ok(Tail::Call(function, arguments, expr.span))
+            }
+            ast::ExprKind::TailCall(inner)
+            | ast::ExprKind::Paren(inner)
+            | ast::ExprKind::Typed(inner, _) => self.eval_tail_expr(inner),
+            ast::ExprKind::Match(scrutinee, arms) => {
+                let value = self.eval_expr(scrutinee)?;
+                match self.eval_match_arms(&value, arms) {
+                    Some(result) => result,
+                    None => Err(self.raise_builtin(Builtin::MatchFailure, expr.span)),
+                }
+            }
+            // The body is not in tail position, as the exceptions raised by
+            // the calls it ends with are handled too.
+            ast::ExprKind::Try(body, arms) => match self.eval_let_body(body) {
+                Ok(value) => Ok(Tail::Value(value)),
+                // The frames of the calls the exception was raised in are
+                // already popped.
+                Err(unwind) => match self.eval_match_arms(&unwind.exception, arms) {
+                    Some(result) => result,
+                    None => Err(unwind),
+                },
+            },
+            ast::ExprKind::If(condition, then_branch, else_branch) => {
+                if self.eval_bool(condition)? {
+                    self.eval_tail_let_body(then_branch)
+                } else {
+                    match else_branch {
+                        Some(else_branch) => self.eval_tail_let_body(else_branch),
+                        None => Ok(Tail::Value(Value::Unit)),
+                    }
+                }
+            }
+            ast::ExprKind::Let(let_binding, body) => {
+                self.eval_local_let_binding(let_binding)?;
+                self.eval_tail_let_body(body)
+            }
+            _ => Ok(Tail::Value(self.eval_expr(expr)?)),
+        }
+    }
+
+    /// Makes the call the expression in tail position ended with, if any.
+    fn finish_tail(&mut self, tail: Tail<'a>) -> EvalResult<'a> {
+        match tail {
+            Tail::Value(value) => Ok(value),
+            Tail::Call(function, arguments, span) => self.apply(function, arguments, span),
+        }
+    }
+
+    fn eval_exprs(&mut self, exprs: &'a [ast::Expr]) -> Result<Vec<Value<'a>>, Unwind<'a>> {
+        exprs.iter().map(|expr| self.eval_expr(expr)).collect()
+    }
+
+    fn eval_bool(&mut self, expr: &'a ast::Expr) -> Result<bool, Unwind<'a>> {
+        match self.eval_expr(expr)? {
+            Value::Bool(value) => Ok(value),
+            value => unreachable!("expected a bool, found {:?}", value),
+        }
+    }
+
+    fn eval_int(&mut self, expr: &'a ast::Expr) -> Result<i64, Unwind<'a>> {
+        match self.eval_expr(expr)? {
+            Value::Int(value) => Ok(value),
+            value => unreachable!("expected an int, found {:?}", value),
+        }
+    }
+
+    fn eval_binary(
+        &mut self,
+        span: SourceSpan,
+        op: ast::BinaryOp,
+        lhs: &'a ast::Expr,
+        rhs: &'a ast::Expr,
+    ) -> EvalResult<'a> {
+        use ast::BinaryOpKind::*;
+        // The logical operators evaluate the right operand only if needed.
+        match op.kind {
+            And => return Ok(Value::Bool(self.eval_bool(lhs)? && self.eval_bool(rhs)?)),
+            Or => return Ok(Value::Bool(self.eval_bool(lhs)? || self.eval_bool(rhs)?)),
+            _ => {}
+        }
+
+        let lhs = self.eval_expr(lhs)?;
+        let rhs = self.eval_expr(rhs)?;
+        self.binary_value(span, op.kind, lhs, rhs)
+    }
+
+    /// Applies the binary operator to the values of the operands.
+    fn binary_value(
+        &mut self,
+        span: SourceSpan,
+        kind: ast::BinaryOpKind,
+        lhs: Value<'a>,
+        rhs: Value<'a>,
+    ) -> EvalResult<'a> {
+        use ast::BinaryOpKind::*;
+        let value = match (kind, lhs, rhs) {
+            (Equal, lhs, rhs) => Value::Bool(lhs.equals(&rhs)),
+            (NotEqual, lhs, rhs) => Value::Bool(!lhs.equals(&rhs)),
+            (Cons, head, tail) => Value::Cons(Rc::new((head, tail))),
+            (Assign, Value::Ref(cell), value) => {
+                *cell.borrow_mut() = value;
+                Value::Unit
+            }
+            (Divide, Value::Int(_), Value::Int(0)) => {
+                return Err(self.raise_builtin(Builtin::DivisionByZero, span))
+            }
+            (And, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs && rhs),
+            (Or, Value::Bool(lhs), Value::Bool(rhs)) => Value::Bool(lhs || rhs),
+            (kind, Value::Int(lhs), Value::Int(rhs)) => match kind {
+                Add => Value::Int(lhs.wrapping_add(rhs)),
+                Subtract => Value::Int(lhs.wrapping_sub(rhs)),
+                Multiply => Value::Int(lhs.wrapping_mul(rhs)),
+                Divide => Value::Int(lhs.wrapping_div(rhs)),
+                Less => Value::Bool(lhs < rhs),
+                LessEqual => Value::Bool(lhs <= rhs),
+                Greater => Value::Bool(lhs > rhs),
+                GreaterEqual => Value::Bool(lhs >= rhs),
+                kind => unreachable!("{:?} applied to integers", kind),
+            },
+            (kind, lhs, rhs) => unreachable!("{:?} applied to {:?} and {:?}", kind, lhs, rhs),
+        };
+        Ok(value)
+    }
+
+    /// Evaluates the body of the first arm matching the value in tail
+    /// position, if any.
+    fn eval_match_arms(
+        &mut self,
+        value: &Value<'a>,
+        arms: &'a [ast::MatchArm],
+    ) -> Option<TailResult<'a>> {
+        arms.iter()
+            .find(|arm| self.match_pattern(&arm.pattern, value))
+            .map(|arm| self.eval_tail_let_body(&arm.body))
+    }
+
+    /// Evaluates the field expressions in order, storing the values at the
+    /// field indices.
+    fn eval_fields(
+        &mut self,
+        fields: &'a [ast::FieldExpr],
+        values: &mut [Value<'a>],
+    ) -> Result<(), Unwind<'a>> {
+        for field in fields {
+            let value = self.eval_expr(&field.expr)?;
+            values[self.field_index(&field.identifier)] = value;
+        }
+        Ok(())
+    }
+
+    /// Applies the function to the arguments. The arguments beyond the
+    /// function arity are applied to its result.
+    fn apply(
+        &mut self,
+        function: Value<'a>,
+        arguments: Vec<Value<'a>>,
+        span: SourceSpan,
+    ) -> EvalResult<'a> {
+        let mut result = function;
+        let mut arguments = arguments.into_iter().peekable();
+        while arguments.peek().is_some() {
+            let function = match &result {
+                Value::Function(function) => function.clone(),
+                value => unreachable!("application of {:?}", value),
+            };
+            let mut collected = function.arguments.clone();
+            let missing = function.arity - collected.len();
+            collected.extend(arguments.by_ref().take(missing));
+            result = if collected.len() < function.arity {
+                Value::Function(Rc::new(Function {
+                    arguments: collected,
+                    ..function.as_ref().clone()
+                }))
+            } else {
+                self.call(&function.kind, collected, span)?
+            };
+        }
+        Ok(result)
+    }
+
+    fn call(
+        &mut self,
+        kind: &FunctionKind<'a>,
+        arguments: Vec<Value<'a>>,
+        span: SourceSpan,
+    ) -> EvalResult<'a> {
+        let closure = match kind {
+            FunctionKind::Closure(closure) => closure,
+            FunctionKind::Constructor(tag) => return Ok(Value::Variant(*tag, Rc::new(arguments))),
+            FunctionKind::Builtin(builtin) => return self.call_builtin(*builtin, arguments, span),
+            FunctionKind::Operator(kind) => {
+                let mut arguments = arguments.into_iter();
+                let lhs = arguments.next().unwrap();
+                return self.binary_value(span, *kind, lhs, arguments.next().unwrap());
+            }
+            FunctionKind::Flipped(function) => {
+                let arguments = arguments.into_iter().rev().collect();
+                return self.apply(function.clone(), arguments, span);
+            }
+            FunctionKind::Format(format) => return Ok(sprintf(format, arguments)),
+        };
+
+        if self.frames.len() >= MAX_CALL_DEPTH || stack_position() < self.stack_limit {
+            return Err(self.raise_builtin(Builtin::StackOverflow, span));
+        }
+        let (mut closure, mut arguments, mut span) = (closure.clone(), arguments, span);
+        loop {
+            let mut locals = closure.captured.iter().cloned().collect::<HashMap<_, _>>();
+            for (node, function) in closure.recursive.iter() {
+                locals.insert(*node, recursive_function(function, &closure.recursive));
+            }
+            self.frames.push(Frame {
+                function: closure.name.clone(),
+                call_span: span,
+                locals,
+            });
+            let result = self.eval_closure_body(&closure, arguments);
+            let (function, tail_arguments, tail_span) = match result {
+                Ok(Tail::Call(function, arguments, span)) => (function, arguments, span),
+                Ok(Tail::Value(value)) => {
+                    self.frames.pop();
+                    return Ok(value);
+                }
+                Err(unwind) => {
+                    self.frames.pop();
+                    return Err(unwind);
+                }
+            };
+            // The saturated calls of the closures in tail position replace
+            // the frame of the call, the others are applied as usual.
+            if let Value::Function(function) = &function {
+                let saturated = function.arguments.len() + tail_arguments.len() == function.arity;
+                if let (FunctionKind::Closure(tail_closure), true) = (&function.kind, saturated) {
+                    closure = tail_closure.clone();
+                    arguments = function.arguments.clone();
+                    arguments.extend(tail_arguments);
+                    span = tail_span;
+                    self.frames.pop();
+                    continue;
+                }
+            }
+            // The other functions run in the frame of the call, which is in
+            // the traces of the exceptions they raise.
+            let result = self.apply(function, tail_arguments, tail_span);
+            self.frames.pop();
+            return result;
+        }
+    }
+
+    fn call_builtin(
+        &mut self,
+        builtin: Builtin,
+        arguments: Vec<Value<'a>>,
+        span: SourceSpan,
+    ) -> EvalResult<'a> {
+        let mut arguments = arguments.into_iter();
+        let mut next = || arguments.next().unwrap();
+        let string = |value: Value<'a>| match value {
+            Value::String(value) => value,
+            value => unreachable!("expected a string, found {:?}", value),
+        };
+        let int = |value: Value<'a>| match value {
+            Value::Int(value) => value,
+            value => unreachable!("expected an int, found {:?}", value),
+        };
+        let new_string = |value: &str| Value::String(value.into());
+
+        let value = match builtin {
+            Builtin::Ref => Value::Ref(Rc::new(RefCell::new(next()))),
+            Builtin::Raise => return Err(self.raise(next(), span)),
+            Builtin::Compare => match next().compare(&next()) {
+                Some(ordering) => Value::Int(ordering as i64),
+                None => return Err(self.invalid_argument("compare: functional value", span)),
+            },
+            Builtin::StringLength => Value::Int(string(next()).len() as i64),
+            Builtin::StringGet => {
+                let value = string(next());
+                let index = int(next());
+                match usize::try_from(index)
+                    .ok()
+                    .and_then(|i| value.as_bytes().get(i))
+                {
+                    Some(byte) => Value::Int(i64::from(*byte)),
+                    None => return Err(self.invalid_argument("string_get", span)),
+                }
+            }
+            Builtin::StringSub => {
+                let value = string(next());
+                let start = int(next());
+                let length = int(next());
+                let range = usize::try_from(start)
+                    .ok()
+                    .zip(usize::try_from(length).ok())
+                    .and_then(|(start, length)| value.get(start..start.checked_add(length)?));
+                match range {
+                    Some(sub) => new_string(sub),
+                    None => return Err(self.invalid_argument("string_sub", span)),
+                }
+            }
+            Builtin::StringConcat => {
+                let mut value = string(next()).to_string();
+                value.push_str(&string(next()));
+                new_string(&value)
+            }
+            Builtin::StringConcatList => {
+                let mut value = String::new();
+                let mut list = next();
+                while let Value::Cons(cell) = list {
+                    value.push_str(&string(cell.0.clone()));
+                    list = cell.1.clone();
+                }
+                new_string(&value)
+            }
+            Builtin::StringOfCharCode => {
+                match u32::try_from(int(next())).ok().and_then(char::from_u32) {
+                    Some(c) => new_string(c.encode_utf8(&mut [0; 4])),
+                    None => return Err(self.invalid_argument("string_of_char_code", span)),
+                }
+            }
+            Builtin::StringOfInt => new_string(&int(next()).to_string()),
+            Builtin::Sprintf => {
+                let format = string(next());
+                match format::parse_format(&format) {
+                    Ok(pieces) => {
+                        let arity = pieces
+                            .iter()
+                            .filter(|piece| matches!(piece, FormatPiece::Conversion(_)))
+                            .count();
+                        if arity == 0 {
+                            return Ok(sprintf(&format, Vec::new()));
+                        }
+                        Value::Function(Rc::new(Function {
+                            kind: FunctionKind::Format(format.clone()),
+                            arity,
+                            arguments: Vec::new(),
+                        }))
+                    }
+                    // The formats are checked by the type checker.
+                    Err(error) => unreachable!("invalid format: {}", error.message),
+                }
+            }
+            Builtin::IntOfString => match string(next()).parse() {
+                Ok(value) => Value::Int(value),
+                Err(_) => return Err(self.failure("int_of_string".to_string(), span)),
+            },
+            Builtin::PrintString => {
+                print!("{}", string(next()));
+                let _ = io::stdout().flush();
+                Value::Unit
+            }
+            Builtin::EprintString => {
+                eprint!("{}", string(next()));
+                Value::Unit
+            }
+            Builtin::ReadLine => {
+                let mut line = String::new();
+                match io::stdin().lock().read_line(&mut line) {
+                    Ok(0) => return Err(self.raise_builtin(Builtin::EndOfFile, span)),
+                    Ok(_) => {
+                        let end = line.trim_end_matches(&['\n', '\r'][..]).len();
+                        line.truncate(end);
+                        new_string(&line)
+                    }
+                    Err(error) => return Err(self.failure(error.to_string(), span)),
+                }
+            }
+            Builtin::ReadFile => {
+                let path = string(next());
+                match std::fs::read_to_string(&*path) {
+                    Ok(contents) => new_string(&contents),
+                    Err(error) => return Err(self.failure(format!("{}: {}", path, error), span)),
+                }
+            }
+            Builtin::WriteFile => {
+                let path = string(next());
+                match std::fs::write(&*path, &*string(next())) {
+                    Ok(()) => Value::Unit,
+                    Err(error) => return Err(self.failure(format!("{}: {}", path, error), span)),
+                }
+            }
+            Builtin::DivisionByZero
+            | Builtin::MatchFailure
+            | Builtin::StackOverflow
+            | Builtin::Failure
+            | Builtin::InvalidArgument
+            | Builtin::NotFound
+            | Builtin::EndOfFile => unreachable!("call of exception {:?}", builtin),
+        };
+        Ok(value)
+    }
+
+    fn eval_closure_body(
+        &mut self,
+        closure: &Closure<'a>,
+        arguments: Vec<Value<'a>>,
+    ) -> TailResult<'a> {
+        for (parameter, argument) in closure.parameters.iter().zip(&arguments) {
+            if !self.match_pattern(parameter, argument) {
+                return Err(self.raise_builtin(Builtin::MatchFailure, parameter.span));
+            }
+        }
+        self.eval_tail_let_body(closure.body)
+    }
+
+    /// Creates the unwinding exception, capturing the stack trace.
+    fn raise(&self, exception: Value<'a>, span: SourceSpan) -> Unwind<'a> {
+        let mut trace = Vec::new();
+        let mut span = span;
+        for frame in self.frames.iter().rev() {
+            trace.push(TraceFrame {
+                function: frame.function.clone(),
+                span,
+            });
+            span = frame.call_span;
+        }
+        Unwind { exception, trace }
+    }
+
+    fn raise_builtin(&self, builtin: Builtin, span: SourceSpan) -> Unwind<'a> {
+        self.raise(
+            Value::Variant(Tag::Builtin(builtin), Rc::new(Vec::new())),
+            span,
+        )
+    }
+
+    fn failure(&self, message: String, span: SourceSpan) -> Unwind<'a> {
+        self.raise(
+            Value::Variant(
+                Tag::Builtin(Builtin::Failure),
+                Rc::new(vec![Value::String(message.into())]),
+            ),
+            span,
+        )
+    }
+
+    fn invalid_argument(&self, message: &str, span: SourceSpan) -> Unwind<'a> {
+        self.raise(
+            Value::Variant(
+                Tag::Builtin(Builtin::InvalidArgument),
+                Rc::new(vec![Value::String(message.into())]),
+            ),
+            span,
+        )
+    }
+
+    /// Matches the value against the pattern, binding the variables in the
+    /// current frame.
+    fn match_pattern(&mut self, pattern: &'a ast::Pattern, value: &Value<'a>) -> bool {
+        match (&pattern.kind, value) {
+            (ast::PatternKind::Wildcard, _) => true,
+            (ast::PatternKind::Binding(identifier), value) => {
+                self.bind(identifier.id, value.clone());
+                true
+            }
+            (ast::PatternKind::Literal(literal), value) => {
+                self.literal_value(literal).equals(value)
+            }
+            (ast::PatternKind::Constructor(path, arguments), Value::Variant(tag, values)) => {
+                let expected = match self.resolutions.paths.get(&path.id) {
+                    Some(Res::Def(def)) => Tag::Def(*def),
+                    Some(Res::Builtin(builtin)) => Tag::Builtin(*builtin),
+                    _ => return false,
+                };
+                *tag == expected && self.match_patterns(arguments, values)
+            }
+            (ast::PatternKind::Record(fields), Value::Record(_, values)) => {
+                fields.iter().all(|field| {
+                    let value = &values[self.field_index(&field.identifier)];
+                    match &field.pattern {
+                        Some(pattern) => self.match_pattern(pattern, value),
+                        None => {
+                            self.bind(field.identifier.id, value.clone());
+                            true
+                        }
+                    }
+                })
+            }
+            (ast::PatternKind::Or(alternatives), value) => alternatives
+                .iter()
+                .any(|alternative| self.match_pattern(alternative, value)),
+            (ast::PatternKind::Paren(pattern), value)
+            | (ast::PatternKind::Typed(pattern, _), value) => self.match_pattern(pattern, value),
+            (ast::PatternKind::Tuple(elements), Value::Tuple(values))
+            | (ast::PatternKind::Array(elements), Value::Array(values)) => {
+                self.match_patterns(elements, values)
+            }
+            (ast::PatternKind::List(elements), value) => {
+                let mut current = value;
+                for element in elements {
+                    match current {
+                        Value::Cons(cell) => {
+                            if !self.match_pattern(element, &cell.0) {
+                                return false;
+                            }
+                            current = &cell.1;
+                        }
+                        _ => return false,
+                    }
+                }
+                matches!(current, Value::Nil)
+            }
+            (ast::PatternKind::Cons(head, tail), Value::Cons(cell)) => {
+                self.match_pattern(head, &cell.0) && self.match_pattern(tail, &cell.1)
+            }
+            _ => false,
+        }
+    }
+
+    fn match_patterns(&mut self, patterns: &'a [ast::Pattern], values: &[Value<'a>]) -> bool {
+        patterns.len() == values.len()
+            && patterns
+                .iter()
+                .zip(values)
+                .all(|(pattern, value)| self.match_pattern(pattern, value))
+    }
+
+    /// Binds the value to the local variable. The variables of the non-first
+    /// alternatives of or-patterns are bound as the ones in the first.
+    fn bind(&mut self, node: NodeId, value: Value<'a>) {
+        let node = *self.resolutions.or_bindings.get(&node).unwrap_or(&node);
+        self.frames.last_mut().unwrap().locals.insert(node, value);
+    }
+
+    fn local(&self, node: NodeId) -> Value<'a> {
+        self.frames.last().unwrap().locals[&node].clone()
+    }
+
+    fn path_value(&self, path: &ast::Path) -> Value<'a> {
+        match self.resolutions.paths[&path.id] {
+            Res::Local(node) => self.local(node),
+            Res::Def(def) => match self.resolutions.def(def).kind {
+                DefKind::Constructor(_) | DefKind::Exception => self.constructor_value(def),
+                // The resolver only lets the functions refer to the
+                // bindings which are not yet defined.
+                _ => self.globals[&def].clone(),
+            },
+            Res::Builtin(builtin) if builtin.arity() == 0 => {
+                Value::Variant(Tag::Builtin(builtin), Rc::new(Vec::new()))
+            }
+            Res::Builtin(builtin) => Value::Function(Rc::new(Function {
+                kind: if builtin.is_exception() {
+                    FunctionKind::Constructor(Tag::Builtin(builtin))
+                } else {
+                    FunctionKind::Builtin(builtin)
+                },
+                arity: builtin.arity(),
+                arguments: Vec::new(),
+            })),
+            res => unreachable!("value path resolved to {:?}", res),
+        }
+    }
+
+    /// Gets the constructor as a value: a variant if it has no arguments,
+    /// and a function otherwise.
+    fn constructor_value(&self, def: DefId) -> Value<'a> {
+        let mut ty = &self.typeck_results.def_schemes[&def].ty;
+        let mut arity = 0;
+        while let Ty::Function(_, result) = ty {
+            arity += 1;
+            ty = result;
+        }
+        if arity == 0 {
+            return Value::Variant(Tag::Def(def), Rc::new(Vec::new()));
+        }
+        Value::Function(Rc::new(Function {
+            kind: FunctionKind::Constructor(Tag::Def(def)),
+            arity,
+            arguments: Vec::new(),
+        }))
+    }
+
+    fn literal_value(&self, literal: &ast::Literal) -> Value<'a> {
+        match literal.kind {
+            // The literals out of range saturate.
+            ast::LiteralKind::Integer => {
+                Value::Int(self.text(literal.span).parse().unwrap_or(i64::MAX))
+            }
+            ast::LiteralKind::Bool => Value::Bool(self.text(literal.span) == "true"),
+            ast::LiteralKind::Unit => Value::Unit,
+            // The invalid literals are reported by the parser, so they are
+            // never evaluated.
+            ast::LiteralKind::String => {
+                let text = self.text(literal.span);
+                let contents = &text[1..text.len() - 1];
+                Value::String(token::unescape_string(contents).unwrap_or_default().into())
+            }
+            ast::LiteralKind::Identifier | ast::LiteralKind::TypeVariable => {
+                unreachable!("identifier evaluated as a literal")
+            }
+        }
+    }
+
+    fn field_owner(&self, field: &ast::Literal) -> DefId {
+        match self
+            .resolutions
+            .def(self.resolutions.fields[&field.id])
+            .kind
+        {
+            DefKind::Field(owner) => owner,
+            kind => unreachable!("field resolved to {:?}", kind),
+        }
+    }
+
+    /// Gets the index of the field in the record values.
+    fn field_index(&self, field: &ast::Literal) -> usize {
+        let def = self.resolutions.fields[&field.id];
+        match &self.typeck_results.adts[&self.field_owner(field)].kind {
+            AdtKind::Record(fields) => fields.iter().position(|f| f.def == def).unwrap(),
+            AdtKind::Variant(_) => unreachable!("field of a variant type"),
+        }
+    }
+
+    fn text(&self, span: SourceSpan) -> &'a str {
+        self.source_map.span_to_snippet(span)
+    }
+}
+
+/// Wraps the closure in a function value taking all of its parameters.
+fn function_value(closure: Closure<'_>) -> Value<'_> {
+    Value::Function(Rc::new(Function {
+        arity: closure.parameters.len(),
+        kind: FunctionKind::Closure(Rc::new(closure)),
+        arguments: Vec::new(),
+    }))
+}
+
+/// Creates the value of a recursive local function, which binds the
+/// functions of its group again when it is called.
+fn recursive_function<'a>(
+    closure: &Closure<'a>,
+    recursive: &Rc<[(NodeId, Rc<Closure<'a>>)]>,
+) -> Value<'a> {
+    function_value(Closure {
+        recursive: recursive.clone(),
+        ..closure.clone()
+    })
+}
+
+/// Formats the arguments of the conversions in the valid format.
+fn sprintf<'a>(format: &str, arguments: Vec<Value<'a>>) -> Value<'a> {
+    let mut arguments = arguments.into_iter();
+    let mut result = String::new();
+    for piece in format::parse_format(format).unwrap_or_default() {
+        match piece {
+            FormatPiece::Text(text) => result.push_str(text),
+            FormatPiece::Conversion(_) => {
+                result.push_str(&arguments.next().unwrap().format_plain())
+            }
+        }
+    }
+    Value::String(result.into())
+}
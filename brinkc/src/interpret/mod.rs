@@ -0,0 +1,228 @@
+use std::rc::Rc;
+
+use crate::source_file::{SourceMap, SourceSpan};
+
+mod eval;
+pub mod value;
+
+pub use eval::interpret;
+
+/// An exception which was raised and not handled by the program.
+#[derive(Debug)]
+pub struct UncaughtException {
+    /// The exception value, e.g. `NotFound 1`.
+    pub exception: String,
+    pub trace: Vec<TraceFrame>,
+}
+
+/// A function active when the exception was raised, with the point it was
+/// executing: the `raise` in the innermost function, and the calls in the
+/// others. The top-level code of a module is shown as the module name.
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    pub function: Rc<str>,
+    pub span: SourceSpan,
+}
+
+impl UncaughtException {
+    /// Formats the exception with the stack trace, innermost call first.
+    pub fn report(&self, source_map: &SourceMap) -> String {
+        let mut report = format!("uncaught exception {}", self.exception);
+        for frame in &self.trace {
+            report.push_str(&format!(
+                "\n  at {} ({})",
+                frame.function,
+                source_map.span_to_location(frame.span)
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Runs the program and formats the final value of the definition, or
+    /// the report of the uncaught exception. Like the compiler, it runs the
+    /// interpreter on a deep stack.
+    fn run(source: &str, name: &str) -> String {
+        let test_util::Checked {
+            session,
+            graph,
+            resolutions,
+            results,
+        } = test_util::check(source);
+        test_util::on_deep_stack(|| {
+            match interpret(&session.source_map, &graph, &resolutions, &results) {
+                Ok(globals) => {
+                    let (_, value) = globals
+                        .iter()
+                        .find(|(def, _)| resolutions.def(**def).name == name)
+                        .unwrap();
+                    value.display(&resolutions)
+                }
+                Err(exception) => exception.report(&session.source_map),
+            }
+        })
+    }
+
+    #[test]
+    fn evaluates_functions_and_data() {
+        let source =
+            "let rec fibonacci n = if n < 2 then n else fibonacci (n - 1) + fibonacci (n - 2)\n\
+                      let x = fibonacci 10\n";
+        assert_eq!("55", run(source, "x"));
+        let source = "let add x y = x + y\nlet inc = add 1\nlet x = (inc 2, inc, [inc 3])\n";
+        assert_eq!("(3, <fun>, [4])", run(source, "x"));
+        let source = "type Option a = None | Some a\n\
+                      type P = { x: int; y: Option int }\n\
+                      let p = { x = 1; y = Some 2 }\n\
+                      let q = { p with x = p.x + 1 }\n";
+        assert_eq!("{ x = 2; y = Some 2 }", run(source, "q"));
+    }
+
+    #[test]
+    fn captures_local_bindings() {
+        let source = "let make n =\n  let rec count k = if k = 0 then [] else (k + n) :: count (k - 1)\n  count\n\
+                      let xs = make 10 3\n";
+        assert_eq!("[13; 12; 11]", run(source, "xs"));
+        let source = "let counter () =\n  let r = ref 0\n  fun () ->\n    r := !r + 1\n    !r\n\
+                      let next = counter ()\nlet a = next ()\nlet b = (next (), a)\n";
+        assert_eq!("(2, 1)", run(source, "b"));
+    }
+
+    #[test]
+    fn runs_loops_and_matches() {
+        let source = "let total =\n  let sum = ref 0\n  for i = 1 to 4 do\n    sum := !sum + i\n  for i = 2 downto 1 do\n    sum := !sum * i\n  let n = ref 3\n  while !n > 0 do\n    n := !n - 1\n  !sum + !n\n";
+        assert_eq!("20", run(source, "total"));
+        let source = "let rec last xs =\n  match xs\n  | [x] -> x\n  | _ :: rest -> last rest\n\
+                      let x = last [1; 2; 3]\n";
+        assert_eq!("3", run(source, "x"));
+    }
+
+    #[test]
+    fn applies_operators_and_sections() {
+        let source = "infixl 1 |>\nlet (|>) x f = f x\n\
+                      let x = [1; 2] |> (fun xs -> 0 :: xs) |> (:: [])\n";
+        assert_eq!("[[0; 1; 2]]", run(source, "x"));
+        let source = "let x = ((-) 10 4, (10 -) 4, (/ 2) 10, (*) 3 4)\n";
+        assert_eq!("(6, 6, 5, 12)", run(source, "x"));
+        let source = "infixr 5 @\n\
+                      let rec (@) xs ys =\n  match xs\n  | [] -> ys\n  | x :: rest -> x :: (rest @ ys)\n\
+                      let x = ([1] @ [2] @ [3], ([0] @) [4], (@ [6]) [5])\n";
+        assert_eq!("([1; 2; 3], [0; 4], [5; 6])", run(source, "x"));
+        let source = "let r = (/ 0) 1\n";
+        assert!(run(source, "r").starts_with("uncaught exception DivisionByZero"));
+    }
+
+    #[test]
+    fn runs_prelude_pipelines() {
+        let source = "let f = (* 2) << (+ 1)\nlet g = (+ 1) >> (* 2) >> (fun n -> n - 3)\n\
+                      let x = (3 |> (+ 1) |> (fun n -> n * 2), f 3, g 3, f <| 1 + 1)\n";
+        assert_eq!("(8, 8, 5, 6)", run(source, "x"));
+    }
+
+    #[test]
+    fn evaluates_strings_and_builtins() {
+        let source = "let s = \"a\\tb\" ^ string_of_int 12\nlet x = (s, string_length s, compare [1; 2] [1; 3])\n";
+        assert_eq!("(\"a\\tb12\", 5, -1)", run(source, "x"));
+        let source = "let x = int_of_string \"1x\"\n";
+        assert!(run(source, "x").starts_with("uncaught exception Failure \"int_of_string\""));
+        let source = "let x = compare (fun x -> x) (fun x -> x)\n";
+        assert!(run(source, "x").starts_with("uncaught exception InvalidArgument"));
+    }
+
+    #[test]
+    fn formats_strings() {
+        let source = "let name = \"b\"\nlet f = sprintf \"%s = %d (%b), 100%%\"\n\
+                      let x = (f name 1 true, sprintf \"plain\", $\"{{{name}}} = {1 + 2}\\n\", $\"\")\n";
+        assert_eq!(
+            "(\"b = 1 (true), 100%\", \"plain\", \"{b} = 3\\n\", \"\")",
+            run(source, "x")
+        );
+        let source = "type P = { x: int }\nlet p = { x = 4 }\nlet s = $\"x: {let q = { p with x = 5 } in q.x}\"\n";
+        assert_eq!("\"x: 5\"", run(source, "s"));
+    }
+
+    /// Runs the tests of the standard library modules, each of which binds
+    /// `result` to `"ok"` or raises `Failure` with the name of the failed
+    /// check.
+    #[test]
+    fn runs_stdlib_tests() {
+        for (name, source) in test_util::STDLIB_TESTS.iter() {
+            assert_eq!("\"ok\"", run(source, "result"), "stdlib test {}", name);
+        }
+
+        let (source, path) = test_util::io_test("brink_io_test.txt");
+        assert_eq!("\"ok\"", run(&source, "result"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn handles_exceptions() {
+        let source = "exception NotFound int\n\
+                      let find x = if x > 2 then raise (NotFound x) else x\n\
+                      let r = try find 3 with | NotFound n -> n * 10\n";
+        assert_eq!("30", run(source, "r"));
+        let source = "let r =\n  try\n    1 / 0\n  with\n  | DivisionByZero -> -1\n";
+        assert_eq!("-1", run(source, "r"));
+        // Unmatched exceptions propagate to the enclosing handlers.
+        let source = "exception A\nexception B\n\
+                      let r = try (try raise B with | A -> 1) with | B -> 2\n";
+        assert_eq!("2", run(source, "r"));
+    }
+
+    #[test]
+    fn runs_tail_calls_in_constant_space() {
+        let source = "let rec loop n acc = if n = 0 then acc else @tailcall loop (n - 1) (acc + 1)\n\
+                      let rec even n =\n  let odd m = if m = 0 then false else even (m - 1)\n  match n\n  | 0 -> true\n  | _ -> odd (n - 1)\n\
+                      let r = (loop 1000000 0, even 1000001)\n";
+        assert_eq!("(1000000, false)", run(source, "r"));
+        // The calls with work left after them still nest, up to the depth
+        // the stack holds.
+        let source =
+            "let rec map f xs =\n  match xs\n  | [] -> []\n  | x :: rest -> f x :: map f rest\n\
+                      let rec build n acc = if n = 0 then acc else build (n - 1) (n :: acc)\n\
+                      let r = map (fun x -> x - 1) (build 20000 []) = 0 :: build 19999 []\n";
+        assert_eq!("true", run(source, "r"));
+        let source = "let rec count n = if n = 0 then 0 else 1 + count (n - 1)\n\
+                      let r = (try count 1000000 with | StackOverflow -> -1, count 100000)\n";
+        assert!(run(source, "r").starts_with("uncaught exception StackOverflow"));
+    }
+
+    #[test]
+    fn runs_mutually_recursive_groups_in_constant_space() {
+        assert_eq!(
+            "\"true true 1000000\"",
+            run(&test_util::mutually_recursive_groups(1000000), "r")
+        );
+    }
+
+    #[test]
+    fn reports_uncaught_exception_with_trace() {
+        let source = "exception Invalid int\n\
+                      let check x =\n  if x < 0 then raise (Invalid x)\n  x\n\
+                      let run x = check (x - 5) + 1\n\
+                      let y = run 3\n";
+        assert_eq!(
+            "uncaught exception Invalid (-2)\n  at check (main.bk:3:17)\n  at run (main.bk:5:13)\n  at Main (main.bk:6:9)",
+            run(source, "y")
+        );
+        let source = "let f x =\n  match x\n  | 0 -> 1\nlet y = f 1\n";
+        assert_eq!(
+            "uncaught exception MatchFailure\n  at f (main.bk:2:3)\n  at Main (main.bk:4:9)",
+            run(source, "y")
+        );
+        // The raise in tail position runs in the frame of its caller.
+        let source = "exception Boom int\n\
+                      let inner x =\n  raise (Boom x)\n\
+                      let outer y = 1 + inner (y * 2)\n\
+                      let z = outer 4\n";
+        assert_eq!(
+            "uncaught exception Boom 8\n  at inner (main.bk:3:3)\n  at outer (main.bk:4:19)\n  at Main (main.bk:5:9)",
+            run(source, "z")
+        );
+    }
+}
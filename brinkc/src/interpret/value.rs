@@ -0,0 +1,288 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use crate::{
+    ast::{self, node_id::NodeId},
+    resolve::{Builtin, DefId, DefKind, Resolutions},
+};
+
+/// A runtime value. The values are immutable, except for the contents of
+/// the references, and cheap to clone.
+#[derive(Clone, Debug)]
+pub enum Value<'a> {
+    Int(i64),
+    Bool(bool),
+    String(Rc<str>),
+    Unit,
+    Tuple(Rc<Vec<Value<'a>>>),
+    /// The empty list.
+    Nil,
+    /// The head and the tail of a non-empty list.
+    Cons(Rc<(Value<'a>, Value<'a>)>),
+    Array(Rc<Vec<Value<'a>>>),
+    Ref(Rc<RefCell<Value<'a>>>),
+    /// A constructor or an exception applied to all of its arguments.
+    Variant(Tag, Rc<Vec<Value<'a>>>),
+    /// A value of the record type, with the field values in the order of
+    /// the declaration.
+    Record(DefId, Rc<Vec<Value<'a>>>),
+    Function(Rc<Function<'a>>),
+}
+
+/// Identifies the constructor of a variant value.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tag {
+    /// A constructor of a variant type or a user-defined exception.
+    Def(DefId),
+    /// A built-in exception.
+    Builtin(Builtin),
+}
+
+/// A possibly partially applied function. It is called once it is given
+/// `arity` arguments.
+#[derive(Clone, Debug)]
+pub struct Function<'a> {
+    pub kind: FunctionKind<'a>,
+    pub arity: usize,
+    pub arguments: Vec<Value<'a>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum FunctionKind<'a> {
+    Closure(Rc<Closure<'a>>),
+    Constructor(Tag),
+    Builtin(Builtin),
+    /// A built-in binary operator used as a function, e.g. `(+)`.
+    Operator(ast::BinaryOpKind),
+    /// The function of two arguments taking them in the reverse order. A
+    /// right section, e.g. `(/ 2)`, is the flipped operator applied to the
+    /// right operand.
+    Flipped(Value<'a>),
+    /// `sprintf` applied to the format, taking the arguments of its
+    /// conversions.
+    Format(Rc<str>),
+}
+
+/// A let-bound function or a lambda with the values of the local bindings
+/// it captures.
+#[derive(Clone, Debug)]
+pub struct Closure<'a> {
+    /// The name shown in the stack traces.
+    pub name: Rc<str>,
+    pub parameters: &'a [ast::Pattern],
+    pub body: &'a ast::LetBody,
+    pub captured: Rc<[(NodeId, Value<'a>)]>,
+    /// The recursive local functions, which are bound on every call instead
+    /// of being captured: the function itself, or every function of its
+    /// `let rec … and …` group. Their own `recursive` lists are empty.
+    pub recursive: Rc<[(NodeId, Rc<Closure<'a>>)]>,
+}
+
+impl<'a> Value<'a> {
+    pub fn list(elements: Vec<Value<'a>>) -> Value<'a> {
+        elements
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |tail, head| Value::Cons(Rc::new((head, tail))))
+    }
+
+    /// Compares the values structurally. The functions are only equal to
+    /// themselves, and the references are equal if their contents are.
+    pub fn equals(&self, other: &Value<'a>) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Unit, Value::Unit) | (Value::Nil, Value::Nil) => true,
+            (Value::Tuple(a), Value::Tuple(b))
+            | (Value::Array(a), Value::Array(b))
+            | (Value::Record(_, a), Value::Record(_, b)) => elements_equal(a, b),
+            (Value::Cons(a), Value::Cons(b)) => a.0.equals(&b.0) && a.1.equals(&b.1),
+            (Value::Ref(a), Value::Ref(b)) => a.borrow().equals(&b.borrow()),
+            (Value::Variant(a, a_arguments), Value::Variant(b, b_arguments)) => {
+                a == b && elements_equal(a_arguments, b_arguments)
+            }
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Orders the values structurally, the way `compare` does. The list
+    /// elements and the tuple, array and record fields are compared from
+    /// the first one, and the constructors of a type in the order of their
+    /// declaration. Returns `None` if the values contain a function.
+    pub fn compare(&self, other: &Value<'a>) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Unit, Value::Unit) | (Value::Nil, Value::Nil) => Some(Ordering::Equal),
+            (Value::Nil, Value::Cons(_)) => Some(Ordering::Less),
+            (Value::Cons(_), Value::Nil) => Some(Ordering::Greater),
+            (Value::Cons(a), Value::Cons(b)) => match a.0.compare(&b.0)? {
+                Ordering::Equal => a.1.compare(&b.1),
+                ordering => Some(ordering),
+            },
+            (Value::Tuple(a), Value::Tuple(b))
+            | (Value::Array(a), Value::Array(b))
+            | (Value::Record(_, a), Value::Record(_, b)) => compare_elements(a, b),
+            (Value::Ref(a), Value::Ref(b)) => a.borrow().compare(&b.borrow()),
+            (Value::Variant(a, a_arguments), Value::Variant(b, b_arguments)) => {
+                match tag_rank(*a).cmp(&tag_rank(*b)) {
+                    Ordering::Equal => compare_elements(a_arguments, b_arguments),
+                    ordering => Some(ordering),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Formats the value of a primitive type without quotes, as `sprintf`
+    /// and the interpolated strings do.
+    pub fn format_plain(&self) -> String {
+        match self {
+            Value::Int(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            Value::String(value) => value.to_string(),
+            value => unreachable!("formatting of {:?}", value),
+        }
+    }
+
+    /// Formats the value the way it would be written in the source code.
+    /// The functions are shown as `<fun>`.
+    pub fn display(&self, resolutions: &Resolutions) -> String {
+        let mut output = String::new();
+        self.write(resolutions, false, &mut output);
+        output
+    }
+
+    fn write(&self, resolutions: &Resolutions, nested: bool, output: &mut String) {
+        match self {
+            Value::Int(value) if nested && *value < 0 => output.push_str(&format!("({})", value)),
+            Value::Int(value) => output.push_str(&value.to_string()),
+            Value::Bool(value) => output.push_str(&value.to_string()),
+            Value::String(value) => write_string(value, output),
+            Value::Unit => output.push_str("()"),
+            Value::Tuple(elements) => {
+                output.push('(');
+                write_separated(elements, ", ", resolutions, output);
+                output.push(')');
+            }
+            Value::Nil | Value::Cons(_) => {
+                let mut elements = Vec::new();
+                let mut current = self;
+                while let Value::Cons(cell) = current {
+                    elements.push(cell.0.clone());
+                    current = &cell.1;
+                }
+                output.push('[');
+                write_separated(&elements, "; ", resolutions, output);
+                output.push(']');
+            }
+            Value::Array(elements) => {
+                output.push_str("[|");
+                write_separated(elements, "; ", resolutions, output);
+                output.push_str("|]");
+            }
+            Value::Ref(value) => {
+                if nested {
+                    output.push('(');
+                }
+                output.push_str("ref ");
+                value.borrow().write(resolutions, true, output);
+                if nested {
+                    output.push(')');
+                }
+            }
+            Value::Variant(tag, arguments) => {
+                let parenthesize = nested && !arguments.is_empty();
+                if parenthesize {
+                    output.push('(');
+                }
+                output.push_str(match tag {
+                    Tag::Def(def) => &resolutions.def(*def).name,
+                    Tag::Builtin(builtin) => builtin.name(),
+                });
+                for argument in arguments.iter() {
+                    output.push(' ');
+                    argument.write(resolutions, true, output);
+                }
+                if parenthesize {
+                    output.push(')');
+                }
+            }
+            Value::Record(owner, fields) => {
+                // The fields are defined in the order of the declaration.
+                let names = resolutions
+                    .defs
+                    .iter()
+                    .filter(|def| def.kind == DefKind::Field(*owner))
+                    .map(|def| &def.name);
+                output.push_str("{ ");
+                for (i, (name, value)) in names.zip(fields.iter()).enumerate() {
+                    if i > 0 {
+                        output.push_str("; ");
+                    }
+                    output.push_str(name);
+                    output.push_str(" = ");
+                    value.write(resolutions, false, output);
+                }
+                output.push_str(" }");
+            }
+            Value::Function(_) => output.push_str("<fun>"),
+        }
+    }
+}
+
+fn elements_equal<'a>(a: &[Value<'a>], b: &[Value<'a>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals(b))
+}
+
+/// Compares the sequences lexicographically; the shorter prefix is less.
+fn compare_elements<'a>(a: &[Value<'a>], b: &[Value<'a>]) -> Option<Ordering> {
+    for (a, b) in a.iter().zip(b) {
+        match a.compare(b)? {
+            Ordering::Equal => {}
+            ordering => return Some(ordering),
+        }
+    }
+    Some(a.len().cmp(&b.len()))
+}
+
+/// Orders the constructors of a type by their definitions, which are
+/// created in the order of the declaration.
+fn tag_rank(tag: Tag) -> (usize, usize) {
+    match tag {
+        Tag::Def(def) => (0, def.as_usize()),
+        Tag::Builtin(builtin) => (1, builtin as usize),
+    }
+}
+
+/// Writes the string as a literal, quoted and escaped.
+fn write_string(value: &str, output: &mut String) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            '\r' => output.push_str("\\r"),
+            '\\' => output.push_str("\\\\"),
+            '"' => output.push_str("\\\""),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+fn write_separated(
+    values: &[Value<'_>],
+    separator: &str,
+    resolutions: &Resolutions,
+    output: &mut String,
+) {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            output.push_str(separator);
+        }
+        value.write(resolutions, false, output);
+    }
+}
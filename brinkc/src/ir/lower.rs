@@ -0,0 +1,1578 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{self, node_id::NodeId},
+    frontend::token,
+    resolve::{Builtin, DefId, DefKind, ModuleGraph, PrimTy, Res, Resolutions},
+    source_file::{SourceMap, SourceSpan},
+    typeck::{
+        format::{self, Conversion, FormatPiece},
+        ty::{Scheme, Ty, TyVid},
+        AdtKind, TypeckResults,
+    },
+};
+
+use super::{
+    all_cons, con_field_tys, tail::mark_tail_calls, Con, Expr, ExprKind, Global, Literal, PrimOp,
+    Program, TailCall, Test, VarId,
+};
+
+/// Lowers the type checked program to the core IR. The program must be free
+/// of errors.
+pub fn lower(
+    source_map: &SourceMap,
+    graph: &ModuleGraph,
+    resolutions: &Resolutions,
+    typeck_results: &TypeckResults,
+) -> Program {
+    let mut lowerer = Lowerer {
+        source_map,
+        resolutions,
+        typeck_results,
+        program: Program::default(),
+        globals: HashMap::new(),
+        locals: HashMap::new(),
+    };
+    // The functions can refer to the bindings defined after them, so all
+    // the globals are declared first.
+    for module in &graph.modules {
+        lowerer.declare_globals(&module.program.body);
+    }
+    for module in &graph.modules {
+        lowerer.lower_module_items(&module.program.body);
+    }
+    let mut program = lowerer.program;
+    mark_tail_calls(&mut program);
+    program
+}
+
+struct Lowerer<'a> {
+    source_map: &'a SourceMap,
+    resolutions: &'a Resolutions,
+    typeck_results: &'a TypeckResults,
+    program: Program,
+    globals: HashMap<DefId, VarId>,
+    /// The variables of the local bindings, by the node of the bound
+    /// identifier. The bindings in the non-first alternatives of or-patterns
+    /// share the variables of the first.
+    locals: HashMap<NodeId, VarId>,
+}
+
+/// What a match evaluates to when no arm matches the value.
+#[derive(Copy, Clone)]
+enum Failure {
+    /// Raises `MatchFailure` at the span.
+    MatchFailure(SourceSpan),
+    /// Raises the exception in the variable again, for the `try` handlers.
+    Reraise(VarId),
+}
+
+/// A pattern with the bound variables and the constructors resolved.
+#[derive(Clone, Debug)]
+enum Pat {
+    Any,
+    Bind(VarId),
+    Literal(Literal),
+    Con(Con, Vec<Pat>),
+    Or(Vec<Pat>),
+}
+
+impl Pat {
+    /// Checks whether matching the pattern needs a test of the value.
+    fn is_test(&self) -> bool {
+        match self {
+            Pat::Literal(_) => true,
+            Pat::Con(con, _) => !con.is_irrefutable(),
+            Pat::Any | Pat::Bind(_) | Pat::Or(_) => false,
+        }
+    }
+
+    fn test(&self) -> Test {
+        match self {
+            Pat::Literal(literal) => Test::Literal(literal.clone()),
+            Pat::Con(con, _) => Test::Con(*con),
+            pat => unreachable!("{:?} is not a test", pat),
+        }
+    }
+}
+
+/// Identifies a value a match inspects: a scrutinee, or a field of another
+/// occurrence.
+type OccId = usize;
+
+struct Occurrence {
+    parent: Option<(OccId, Con, usize)>,
+    ty: Ty,
+}
+
+/// A row of the pattern matrix: the patterns the occurrences have to match
+/// for the arm to be selected, and the variables bound so far.
+#[derive(Clone)]
+struct Row {
+    patterns: Vec<(OccId, Pat)>,
+    bindings: Vec<(VarId, OccId)>,
+    arm: usize,
+}
+
+enum Decision {
+    Fail,
+    Leaf {
+        arm: usize,
+        bindings: Vec<(VarId, OccId)>,
+    },
+    Switch {
+        occurrence: OccId,
+        cases: Vec<(Test, Decision)>,
+        default: Option<Box<Decision>>,
+    },
+}
+
+/// The state of compiling a single match to a decision tree.
+struct MatchCompiler {
+    occurrences: Vec<Occurrence>,
+    children: HashMap<(OccId, Con, usize), OccId>,
+}
+
+/// How the decision tree reaches the body of an arm.
+enum ArmBody {
+    /// The arm is reached from a single leaf, which contains the body.
+    Inline(Option<Expr>),
+    /// The arm is reached from several leaves, which call the function of
+    /// the arm variables, or of `()` if there are none.
+    Join(VarId, Vec<VarId>),
+    Unreachable,
+}
+
+impl<'a> Lowerer<'a> {
+    fn declare_globals(&mut self, items: &[ast::Item]) {
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => self.declare_global(let_binding),
+                ast::ItemKind::LetRec(bindings) => {
+                    for let_binding in bindings {
+                        self.declare_global(let_binding);
+                    }
+                }
+                ast::ItemKind::Module(module) => self.declare_globals(&module.body.items),
+                _ => {}
+            }
+        }
+    }
+
+    fn declare_global(&mut self, let_binding: &ast::LetBinding) {
+        if let Some(def) = self.resolutions.def_of_node.get(&let_binding.id) {
+            let scheme = &self.typeck_results.def_schemes[def];
+            let var = self.declare(&let_binding.identifier, Some(scheme));
+            self.globals.insert(*def, var);
+        }
+    }
+
+    fn lower_module_items(&mut self, items: &[ast::Item]) {
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => self.lower_global(let_binding),
+                // The globals are declared beforehand, so the functions of
+                // the group refer to each other like to any global.
+                ast::ItemKind::LetRec(bindings) => {
+                    for let_binding in bindings {
+                        self.lower_global(let_binding);
+                    }
+                }
+                ast::ItemKind::Module(module) => self.lower_module_items(&module.body.items),
+                ast::ItemKind::Expr(expr) => {
+                    let value = self.lower_expr(expr);
+                    let var = self.new_var("_", value.ty.clone(), expr.span);
+                    self.program.globals.push(Global { var, value });
+                }
+                ast::ItemKind::Type(_)
+                | ast::ItemKind::Open(_)
+                | ast::ItemKind::Import(_)
+                | ast::ItemKind::Exception(_)
+                | ast::ItemKind::Fixity(_) => {}
+            }
+        }
+    }
+
+    fn lower_global(&mut self, let_binding: &ast::LetBinding) {
+        if let Some(def) = self.resolutions.def_of_node.get(&let_binding.id) {
+            let var = self.globals[def];
+            let value = self.lower_let_binding(let_binding);
+            self.program.globals.push(Global { var, value });
+        }
+    }
+
+    /// Creates the variable of the identifier, generalized as the scheme.
+    fn declare(&mut self, identifier: &ast::Literal, scheme: Option<&Scheme>) -> VarId {
+        let ty = self.node_ty(identifier.id);
+        let generics = scheme.map_or_else(Vec::new, |scheme| quantified_vars(scheme, &ty));
+        let name = self.text(identifier.span).into();
+        let var = self.program.new_var(name, ty, identifier.span);
+        self.program.vars[var.as_usize()].generics = generics;
+        self.locals.insert(identifier.id, var);
+        var
+    }
+
+    fn new_var(&mut self, name: &str, ty: Ty, span: SourceSpan) -> VarId {
+        self.program.new_var(name.into(), ty, span)
+    }
+
+    /// Lowers the value bound by the let binding: a function if it has
+    /// parameters.
+    fn lower_let_binding(&mut self, let_binding: &ast::LetBinding) -> Expr {
+        if let_binding.parameters.is_empty() {
+            return self.lower_let_body(&let_binding.body);
+        }
+        let ty = self.node_ty(let_binding.identifier.id);
+        self.lower_function(
+            let_binding.span,
+            &let_binding.parameters,
+            &let_binding.body,
+            ty,
+        )
+    }
+
+    /// Lowers the local let binding, or the recursive group, scoped to the
+    /// rest of the block.
+    fn lower_local_let_bindings(
+        &mut self,
+        bindings: &[ast::LetBinding],
+        rest: impl FnOnce(&mut Self) -> Expr,
+    ) -> Expr {
+        let vars = bindings
+            .iter()
+            .map(|let_binding| {
+                let scheme = self
+                    .typeck_results
+                    .local_schemes
+                    .get(&let_binding.identifier.id);
+                self.declare(&let_binding.identifier, scheme)
+            })
+            .collect::<Vec<_>>();
+        let values = bindings
+            .iter()
+            .map(|let_binding| self.lower_let_binding(let_binding))
+            .collect::<Vec<_>>();
+        let body = rest(self);
+        let span = bindings[0].span.to(body.span);
+        let ty = body.ty.clone();
+        let kind = if bindings[0].is_recursive {
+            ExprKind::LetRec(vars.into_iter().zip(values).collect(), Box::new(body))
+        } else {
+            let value = values.into_iter().next().unwrap();
+            ExprKind::Let(vars[0], Box::new(value), Box::new(body))
+        };
+        Expr { ty, span, kind }
+    }
+
+    fn lower_let_body(&mut self, body: &ast::LetBody) -> Expr {
+        match body {
+            ast::LetBody::Block(block) => self.lower_items(&block.items, block.span),
+            ast::LetBody::Expr(expr) => self.lower_expr(expr),
+        }
+    }
+
+    /// Lowers the items of a block to nested lets. The values of the
+    /// expressions before the last item are bound to unused variables.
+    fn lower_items(&mut self, items: &[ast::Item], span: SourceSpan) -> Expr {
+        let (item, rest) = match items.split_first() {
+            Some(split) => split,
+            None => return literal_expr(Literal::Unit, span),
+        };
+        match &item.kind {
+            ast::ItemKind::LetBinding(let_binding) => self
+                .lower_local_let_bindings(std::slice::from_ref(let_binding), |lowerer| {
+                    lowerer.lower_items(rest, span)
+                }),
+            ast::ItemKind::LetRec(bindings) => {
+                self.lower_local_let_bindings(bindings, |lowerer| lowerer.lower_items(rest, span))
+            }
+            ast::ItemKind::Expr(expr) if rest.is_empty() => self.lower_expr(expr),
+            ast::ItemKind::Expr(expr) => {
+                let value = self.lower_expr(expr);
+                let var = self.new_var("_", value.ty.clone(), expr.span);
+                let body = self.lower_items(rest, span);
+                let_expr(var, value, body)
+            }
+            // Rejected by the resolver.
+            _ => self.lower_items(rest, span),
+        }
+    }
+
+    /// Lowers the function of the parameter patterns. The parameters which
+    /// are not variables are matched in the body.
+    fn lower_function(
+        &mut self,
+        span: SourceSpan,
+        parameters: &[ast::Pattern],
+        body: &ast::LetBody,
+        ty: Ty,
+    ) -> Expr {
+        let mut vars = Vec::new();
+        let mut matched = Vec::new();
+        for parameter in parameters {
+            match &strip_pattern(parameter).kind {
+                ast::PatternKind::Binding(identifier) => vars.push(self.declare(identifier, None)),
+                _ => {
+                    let ty = self.node_ty(parameter.id);
+                    let var = self.new_var("_", ty, parameter.span);
+                    vars.push(var);
+                    matched.push((var, parameter));
+                }
+            }
+        }
+
+        let body_ty = result_ty(&ty, parameters.len());
+        let body = if matched.is_empty() {
+            self.lower_let_body(body)
+        } else {
+            let (scrutinees, patterns): (Vec<_>, Vec<_>) = matched.into_iter().unzip();
+            let failure = Failure::MatchFailure(patterns[0].span);
+            self.lower_match(&scrutinees, vec![(patterns, body)], body_ty, failure)
+        };
+        Expr {
+            ty,
+            span,
+            kind: ExprKind::Lambda(vars, Box::new(body)),
+        }
+    }
+
+    fn lower_expr(&mut self, expr: &ast::Expr) -> Expr {
+        let ty = self.node_ty(expr.id);
+        let span = expr.span;
+        let kind = match &expr.kind {
+            ast::ExprKind::Literal(literal) => ExprKind::Literal(self.literal(literal)),
+            ast::ExprKind::Path(path) => return self.lower_path(path, ty, span),
+            ast::ExprKind::Application(callee, arguments) => {
+                return self.lower_application(expr, callee, arguments)
+            }
+            ast::ExprKind::TailCall(call) => {
+                let mut call = self.lower_expr(call);
+                if let ExprKind::Apply(_, _, tail_call) = &mut call.kind {
+                    tail_call.required = true;
+                }
+                return call;
+            }
+            ast::ExprKind::Paren(inner) | ast::ExprKind::Typed(inner, _) => {
+                return self.lower_expr(inner)
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                let mut pieces = Vec::new();
+                for part in parts {
+                    match part {
+                        ast::InterpolationPart::Text(span) => {
+                            let text = token::unescape_interpolated_text(self.text(*span));
+                            let text = text.unwrap_or_default();
+                            if !text.is_empty() {
+                                pieces.push(literal_expr(Literal::String(text.into()), *span));
+                            }
+                        }
+                        ast::InterpolationPart::Hole(hole) => {
+                            let value = self.lower_expr(hole);
+                            pieces.push(to_string(value));
+                        }
+                    }
+                }
+                return concat(pieces, span);
+            }
+            ast::ExprKind::Tuple(elements) => {
+                ExprKind::Construct(Con::Tuple(elements.len()), self.lower_exprs(elements))
+            }
+            ast::ExprKind::List(elements) => {
+                let elements = self.lower_exprs(elements);
+                return elements.into_iter().rev().fold(
+                    construct(Con::Nil, Vec::new(), ty.clone(), span),
+                    |tail, head| {
+                        let span = head.span.to(span);
+                        construct(Con::Cons, vec![head, tail], ty.clone(), span)
+                    },
+                );
+            }
+            ast::ExprKind::Array(elements) => {
+                ExprKind::Construct(Con::Array(elements.len()), self.lower_exprs(elements))
+            }
+            ast::ExprKind::Record(fields) => return self.lower_record(ty, span, None, fields),
+            ast::ExprKind::RecordUpdate(record, fields) => {
+                return self.lower_record(ty, span, Some(record), fields)
+            }
+            ast::ExprKind::FieldAccess(record, field) => {
+                let (owner, index) = self.field_index(field);
+                let record = self.lower_expr(record);
+                ExprKind::Field(Box::new(record), Con::Record(owner), index)
+            }
+            ast::ExprKind::Match(scrutinee, arms) => {
+                let value = self.lower_expr(scrutinee);
+                let (var, binding) = self.bind_value(value);
+                let arms = arms
+                    .iter()
+                    .map(|arm| (vec![&arm.pattern], &arm.body))
+                    .collect();
+                let tree = self.lower_match(&[var], arms, ty, Failure::MatchFailure(span));
+                return match binding {
+                    Some(value) => let_expr(var, value, tree),
+                    None => tree,
+                };
+            }
+            ast::ExprKind::Try(body, arms) => {
+                let body = self.lower_let_body(body);
+                let exception = self.new_var("_", Ty::Prim(PrimTy::Exn), span);
+                let arms = arms
+                    .iter()
+                    .map(|arm| (vec![&arm.pattern], &arm.body))
+                    .collect();
+                let handler =
+                    self.lower_match(&[exception], arms, ty.clone(), Failure::Reraise(exception));
+                ExprKind::Try(Box::new(body), exception, Box::new(handler))
+            }
+            ast::ExprKind::Lambda(parameters, body) => {
+                return self.lower_function(span, parameters, body, ty)
+            }
+            ast::ExprKind::If(condition, then_branch, else_branch) => {
+                let condition = self.lower_expr(condition);
+                let then_branch = self.lower_let_body(then_branch);
+                let else_branch = match else_branch {
+                    Some(else_branch) => self.lower_let_body(else_branch),
+                    None => literal_expr(Literal::Unit, span),
+                };
+                ExprKind::If(
+                    Box::new(condition),
+                    Box::new(then_branch),
+                    Box::new(else_branch),
+                )
+            }
+            ast::ExprKind::While(condition, body) => ExprKind::While(
+                Box::new(self.lower_expr(condition)),
+                Box::new(self.lower_let_body(body)),
+            ),
+            ast::ExprKind::For(for_loop) => {
+                let start = self.lower_expr(&for_loop.start);
+                let end = self.lower_expr(&for_loop.end);
+                let var = self.declare(&for_loop.binding, None);
+                let body = self.lower_let_body(&for_loop.body);
+                ExprKind::For(
+                    var,
+                    Box::new(start),
+                    for_loop.direction,
+                    Box::new(end),
+                    Box::new(body),
+                )
+            }
+            ast::ExprKind::Let(let_binding, body) => {
+                return self
+                    .lower_local_let_bindings(std::slice::from_ref(let_binding), |lowerer| {
+                        lowerer.lower_let_body(body)
+                    })
+            }
+            ast::ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.lower_expr(lhs);
+                let rhs = self.lower_expr(rhs);
+                return binary(op.kind, lhs, rhs, ty, span);
+            }
+            ast::ExprKind::Infix(operator, lhs, rhs) => {
+                let lhs = self.lower_expr(lhs);
+                let rhs = self.lower_expr(rhs);
+                let operator_ty =
+                    Ty::function(lhs.ty.clone(), Ty::function(rhs.ty.clone(), ty.clone()));
+                let operator = self.lower_path(operator, operator_ty, span);
+                ExprKind::Apply(Box::new(operator), vec![lhs, rhs], TailCall::default())
+            }
+            ast::ExprKind::Section(operator, lhs, rhs) => {
+                return self.lower_section(operator, lhs.as_deref(), rhs.as_deref(), ty, span)
+            }
+            ast::ExprKind::Unary(op, operand) => {
+                let op = match op.kind {
+                    ast::UnaryOpKind::Negate => PrimOp::Negate,
+                    ast::UnaryOpKind::Deref => PrimOp::Deref,
+                };
+                ExprKind::Prim(op, vec![self.lower_expr(operand)])
+            }
+        };
+        Expr { ty, span, kind }
+    }
+
+    fn lower_exprs(&mut self, exprs: &[ast::Expr]) -> Vec<Expr> {
+        exprs.iter().map(|expr| self.lower_expr(expr)).collect()
+    }
+
+    /// Lowers the path used at the type. The constructors and the builtins
+    /// which are not applied become functions building or calling them.
+    fn lower_path(&mut self, path: &ast::Path, ty: Ty, span: SourceSpan) -> Expr {
+        let kind = match self.resolutions.paths[&path.id] {
+            Res::Local(node) => ExprKind::Var(self.locals[&node]),
+            Res::Def(def) => match self.resolutions.def(def).kind {
+                DefKind::Constructor(_) | DefKind::Exception => {
+                    let arity = parameter_count(&ty);
+                    return self.eta_expand(ty, arity, span, |fields, ty| {
+                        construct(Con::Constructor(def), fields, ty, span)
+                    });
+                }
+                _ => ExprKind::Var(self.globals[&def]),
+            },
+            Res::Builtin(builtin) if builtin.is_exception() => {
+                return self.eta_expand(ty, builtin.arity(), span, |fields, ty| {
+                    construct(Con::Exception(builtin), fields, ty, span)
+                });
+            }
+            Res::Builtin(builtin) => {
+                return self.eta_expand(ty, builtin.arity(), span, |arguments, ty| {
+                    prim(PrimOp::Builtin(builtin), arguments, ty, span)
+                });
+            }
+            res => unreachable!("value path resolved to {:?}", res),
+        };
+        Expr { ty, span, kind }
+    }
+
+    /// Builds the function of the first parameters of the type, whose body
+    /// is built from the parameters.
+    fn eta_expand(
+        &mut self,
+        ty: Ty,
+        arity: usize,
+        span: SourceSpan,
+        build: impl FnOnce(Vec<Expr>, Ty) -> Expr,
+    ) -> Expr {
+        if arity == 0 {
+            return build(Vec::new(), ty);
+        }
+        let mut parameters = Vec::new();
+        let mut result = &ty;
+        for _ in 0..arity {
+            match result {
+                Ty::Function(parameter, next) => {
+                    parameters.push(self.new_var("_", parameter.as_ref().clone(), span));
+                    result = next;
+                }
+                ty => unreachable!("{:?} is not a function of {} parameters", ty, arity),
+            }
+        }
+        let arguments = parameters
+            .iter()
+            .map(|parameter| self.var_expr(*parameter, span))
+            .collect();
+        let body = build(arguments, result.clone());
+        Expr {
+            ty,
+            span,
+            kind: ExprKind::Lambda(parameters, Box::new(body)),
+        }
+    }
+
+    /// Lowers the application. The constructors and the builtins applied to
+    /// enough arguments are built and called directly.
+    fn lower_application(
+        &mut self,
+        expr: &ast::Expr,
+        callee: &ast::Expr,
+        arguments: &[ast::Expr],
+    ) -> Expr {
+        let ty = self.node_ty(expr.id);
+        let span = expr.span;
+        let callee_ty = self.node_ty(callee.id);
+        let mut head = callee;
+        while let ast::ExprKind::Paren(inner) = &head.kind {
+            head = inner;
+        }
+        let res = match &head.kind {
+            ast::ExprKind::Path(path) => Some(self.resolutions.paths[&path.id]),
+            _ => None,
+        };
+
+        let direct = match res {
+            Some(Res::Builtin(Builtin::Sprintf)) => {
+                let format = match &arguments[0].kind {
+                    ast::ExprKind::Literal(literal) => self.literal(literal),
+                    kind => unreachable!("sprintf applied to {:?}", kind),
+                };
+                let function = self.lower_format(format, result_ty(&callee_ty, 1), span);
+                return self.apply(function, &arguments[1..], ty, span);
+            }
+            Some(Res::Builtin(builtin)) if builtin.is_exception() => {
+                Some((Err(Con::Exception(builtin)), builtin.arity()))
+            }
+            Some(Res::Builtin(builtin)) => Some((Ok(PrimOp::Builtin(builtin)), builtin.arity())),
+            Some(Res::Def(def)) => match self.resolutions.def(def).kind {
+                DefKind::Constructor(_) | DefKind::Exception => {
+                    Some((Err(Con::Constructor(def)), parameter_count(&callee_ty)))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let (op_or_con, arity) = match direct {
+            Some((op_or_con, arity)) if arity > 0 && arguments.len() >= arity => (op_or_con, arity),
+            _ => {
+                let callee = self.lower_expr(callee);
+                return self.apply(callee, arguments, ty, span);
+            }
+        };
+
+        let (saturated, rest) = arguments.split_at(arity);
+        let saturated = self.lower_exprs(saturated);
+        let call_ty = result_ty(&callee_ty, arity);
+        let call_span = callee
+            .span
+            .to(saturated.last().map_or(callee.span, |e| e.span));
+        let call = match op_or_con {
+            Ok(op) => prim(op, saturated, call_ty, call_span),
+            Err(con) => construct(con, saturated, call_ty, call_span),
+        };
+        self.apply(call, rest, ty, span)
+    }
+
+    /// Applies the function to the lowered arguments, if there are any.
+    fn apply(&mut self, function: Expr, arguments: &[ast::Expr], ty: Ty, span: SourceSpan) -> Expr {
+        if arguments.is_empty() {
+            return function;
+        }
+        let arguments = self.lower_exprs(arguments);
+        Expr {
+            ty,
+            span,
+            kind: ExprKind::Apply(Box::new(function), arguments, TailCall::default()),
+        }
+    }
+
+    /// Lowers `sprintf` applied to the format to the function of the
+    /// converted values, concatenating them with the text.
+    fn lower_format(&mut self, format: Literal, ty: Ty, span: SourceSpan) -> Expr {
+        let format = match format {
+            Literal::String(format) => format,
+            literal => unreachable!("sprintf applied to {:?}", literal),
+        };
+        // The formats are checked by the type checker.
+        let pieces = format::parse_format(&format).unwrap_or_default();
+        let arity = pieces
+            .iter()
+            .filter(|piece| matches!(piece, FormatPiece::Conversion(_)))
+            .count();
+        self.eta_expand(ty, arity, span, |arguments, _| {
+            let mut arguments = arguments.into_iter();
+            let mut strings = Vec::new();
+            let mut text = String::new();
+            for piece in pieces {
+                match piece {
+                    FormatPiece::Text(piece) => text.push_str(piece),
+                    FormatPiece::Conversion(conversion) => {
+                        if !text.is_empty() {
+                            let text = std::mem::take(&mut text);
+                            strings.push(literal_expr(Literal::String(text.into()), span));
+                        }
+                        let argument = arguments.next().unwrap();
+                        strings.push(match conversion {
+                            Conversion::Int | Conversion::Bool => to_string(argument),
+                            Conversion::String => argument,
+                        });
+                    }
+                }
+            }
+            if !text.is_empty() {
+                strings.push(literal_expr(Literal::String(text.into()), span));
+            }
+            concat(strings, span)
+        })
+    }
+
+    /// Lowers the record construction, or the update of the record. The
+    /// fields are evaluated in the source order.
+    fn lower_record(
+        &mut self,
+        ty: Ty,
+        span: SourceSpan,
+        record: Option<&ast::Expr>,
+        fields: &[ast::FieldExpr],
+    ) -> Expr {
+        let mut lets = Vec::new();
+        let base = record.map(|record| {
+            let value = self.lower_expr(record);
+            let (var, binding) = self.bind_value(value);
+            lets.extend(binding.map(|value| (var, value)));
+            var
+        });
+
+        let field_tys = match &ty {
+            Ty::Adt(owner, _) => con_field_tys(
+                self.resolutions,
+                self.typeck_results,
+                Con::Record(*owner),
+                &ty,
+            ),
+            _ => None,
+        }
+        .unwrap_or_else(|| unreachable!("record of type {:?}", ty));
+        let indices = fields
+            .iter()
+            .map(|field| self.field_index(&field.identifier))
+            .collect::<Vec<_>>();
+        let owner = indices[0].0;
+        let in_order = indices.windows(2).all(|pair| pair[0].1 < pair[1].1);
+
+        let mut values = vec![None; field_tys.len()];
+        for (field, (_, index)) in fields.iter().zip(indices) {
+            let value = self.lower_expr(&field.expr);
+            values[index] = Some(if in_order {
+                value
+            } else {
+                let name = self.text(field.identifier.span);
+                let var = self.new_var(name, value.ty.clone(), field.span);
+                lets.push((var, value));
+                self.var_expr(var, field.span)
+            });
+        }
+        let values = values
+            .into_iter()
+            .zip(field_tys)
+            .enumerate()
+            .map(|(index, (value, field_ty))| {
+                value.unwrap_or_else(|| Expr {
+                    ty: field_ty,
+                    span,
+                    kind: ExprKind::Field(
+                        Box::new(self.var_expr(base.unwrap(), span)),
+                        Con::Record(owner),
+                        index,
+                    ),
+                })
+            })
+            .collect();
+        let record = construct(Con::Record(owner), values, ty, span);
+        wrap_lets(lets, record)
+    }
+
+    /// Lowers the operator section to the function of the missing operands.
+    /// The given operand is evaluated when the section is.
+    fn lower_section(
+        &mut self,
+        operator: &ast::SectionOperator,
+        lhs: Option<&ast::Expr>,
+        rhs: Option<&ast::Expr>,
+        ty: Ty,
+        span: SourceSpan,
+    ) -> Expr {
+        let lhs = lhs.map(|lhs| self.lower_expr(lhs));
+        let rhs = rhs.map(|rhs| self.lower_expr(rhs));
+        if let ast::SectionOperator::User(path) = operator {
+            match (lhs, rhs) {
+                (None, None) => return self.lower_path(path, ty, span),
+                (Some(lhs), None) => {
+                    let operator =
+                        self.lower_path(path, Ty::function(lhs.ty.clone(), ty.clone()), span);
+                    return Expr {
+                        ty,
+                        span,
+                        kind: ExprKind::Apply(Box::new(operator), vec![lhs], TailCall::default()),
+                    };
+                }
+                (lhs, rhs) => {
+                    let (lhs_ty, result) = match &ty {
+                        Ty::Function(parameter, result) => (parameter.as_ref(), result.as_ref()),
+                        ty => unreachable!("section of type {:?}", ty),
+                    };
+                    let rhs_ty = rhs
+                        .as_ref()
+                        .map_or_else(|| lhs_ty.clone(), |rhs| rhs.ty.clone());
+                    let operator_ty =
+                        Ty::function(lhs_ty.clone(), Ty::function(rhs_ty, result.clone()));
+                    let operator = self.lower_path(path, operator_ty, span);
+                    return self.section(lhs, rhs, ty, span, |lhs, rhs, ty| Expr {
+                        ty,
+                        span,
+                        kind: ExprKind::Apply(
+                            Box::new(operator),
+                            vec![lhs, rhs],
+                            TailCall::default(),
+                        ),
+                    });
+                }
+            }
+        }
+
+        let kind = match operator {
+            ast::SectionOperator::Builtin(op) => op.kind,
+            ast::SectionOperator::User(_) => unreachable!(),
+        };
+        self.section(lhs, rhs, ty, span, |lhs, rhs, ty| {
+            binary(kind, lhs, rhs, ty, span)
+        })
+    }
+
+    /// Builds the function of the missing operands, binding the given ones
+    /// to variables first.
+    fn section(
+        &mut self,
+        lhs: Option<Expr>,
+        rhs: Option<Expr>,
+        ty: Ty,
+        span: SourceSpan,
+        build: impl FnOnce(Expr, Expr, Ty) -> Expr,
+    ) -> Expr {
+        let mut lets = Vec::new();
+        let mut operand = |lowerer: &mut Self, operand: Option<Expr>| {
+            operand.map(|value| {
+                let var = lowerer.new_var("_", value.ty.clone(), value.span);
+                lets.push((var, value));
+                lowerer.var_expr(var, span)
+            })
+        };
+        let lhs = operand(self, lhs);
+        let rhs = operand(self, rhs);
+        let arity = usize::from(lhs.is_none()) + usize::from(rhs.is_none());
+        let function = self.eta_expand(ty, arity, span, |mut parameters, ty| {
+            let rhs = rhs.unwrap_or_else(|| parameters.pop().unwrap());
+            let lhs = lhs.unwrap_or_else(|| parameters.pop().unwrap());
+            build(lhs, rhs, ty)
+        });
+        wrap_lets(lets, function)
+    }
+
+    /// Binds the value to a variable, unless it is a monomorphic variable
+    /// already. Returns the variable, and the value to bind to it.
+    fn bind_value(&mut self, value: Expr) -> (VarId, Option<Expr>) {
+        match value.kind {
+            ExprKind::Var(var) if self.program.var(var).generics.is_empty() => (var, None),
+            _ => {
+                let var = self.new_var("_", value.ty.clone(), value.span);
+                (var, Some(value))
+            }
+        }
+    }
+
+    /// Compiles the match of the scrutinees against the rows of patterns to
+    /// a decision tree. The arms reached from several leaves become local
+    /// functions of their variables.
+    fn lower_match(
+        &mut self,
+        scrutinees: &[VarId],
+        arms: Vec<(Vec<&ast::Pattern>, &ast::LetBody)>,
+        ty: Ty,
+        failure: Failure,
+    ) -> Expr {
+        let mut compiler = MatchCompiler {
+            occurrences: Vec::new(),
+            children: HashMap::new(),
+        };
+        let mut materialized = HashMap::new();
+        for scrutinee in scrutinees {
+            let ty = self.program.var(*scrutinee).ty.clone();
+            materialized.insert(compiler.occurrences.len(), *scrutinee);
+            compiler.occurrences.push(Occurrence { parent: None, ty });
+        }
+
+        let mut rows = Vec::new();
+        let mut arm_vars = Vec::new();
+        for (arm, (patterns, _)) in arms.iter().enumerate() {
+            let mut vars = Vec::new();
+            let patterns = patterns
+                .iter()
+                .enumerate()
+                .map(|(occurrence, pattern)| (occurrence, self.pattern(pattern, &mut vars)))
+                .collect();
+            let row = Row {
+                patterns,
+                bindings: Vec::new(),
+                arm,
+            };
+            compiler.expand(self, row, &mut rows);
+            arm_vars.push(vars);
+        }
+        let decision = compiler.compile(self, rows);
+
+        let mut leaves = vec![0; arms.len()];
+        decision.count_leaves(&mut leaves);
+        let mut joins = Vec::new();
+        let mut bodies = Vec::new();
+        for ((_, body), (vars, leaves)) in arms.into_iter().zip(arm_vars.into_iter().zip(leaves)) {
+            if leaves == 0 {
+                bodies.push(ArmBody::Unreachable);
+                continue;
+            }
+            let body = self.lower_let_body(body);
+            if leaves == 1 {
+                bodies.push(ArmBody::Inline(Some(body)));
+                continue;
+            }
+            let span = body.span;
+            let parameters = if vars.is_empty() {
+                vec![self.new_var("_", Ty::Prim(PrimTy::Unit), span)]
+            } else {
+                vars.clone()
+            };
+            let function_ty = parameters
+                .iter()
+                .rev()
+                .fold(ty.clone(), |result, parameter| {
+                    Ty::function(self.program.var(*parameter).ty.clone(), result)
+                });
+            let join = self.new_var("_", function_ty.clone(), span);
+            joins.push((
+                join,
+                Expr {
+                    ty: function_ty,
+                    span,
+                    kind: ExprKind::Lambda(parameters, Box::new(body)),
+                },
+            ));
+            bodies.push(ArmBody::Join(join, vars));
+        }
+
+        let tree = self.emit_decision(
+            &compiler,
+            decision,
+            &mut materialized,
+            &mut bodies,
+            &ty,
+            failure,
+        );
+        wrap_lets(joins, tree)
+    }
+
+    /// Converts the pattern, creating the variables it binds.
+    fn pattern(&mut self, pattern: &ast::Pattern, vars: &mut Vec<VarId>) -> Pat {
+        match &pattern.kind {
+            ast::PatternKind::Wildcard => Pat::Any,
+            ast::PatternKind::Binding(identifier) => self.pattern_var(identifier, vars),
+            ast::PatternKind::Literal(literal) => match self.literal(literal) {
+                Literal::Unit => Pat::Any,
+                literal => Pat::Literal(literal),
+            },
+            ast::PatternKind::Constructor(path, arguments) => {
+                let con = match self.resolutions.paths[&path.id] {
+                    Res::Def(def) => Con::Constructor(def),
+                    Res::Builtin(builtin) => Con::Exception(builtin),
+                    res => unreachable!("constructor pattern resolved to {:?}", res),
+                };
+                let arguments = arguments.iter().map(|a| self.pattern(a, vars)).collect();
+                Pat::Con(con, arguments)
+            }
+            ast::PatternKind::Record(fields) => {
+                let ty = self.node_ty(pattern.id);
+                let count = match &ty {
+                    Ty::Adt(owner, _) => con_field_tys(
+                        self.resolutions,
+                        self.typeck_results,
+                        Con::Record(*owner),
+                        &ty,
+                    )
+                    .map_or(0, |fields| fields.len()),
+                    _ => 0,
+                };
+                let mut patterns = vec![Pat::Any; count];
+                let mut owner = None;
+                for field in fields {
+                    let (field_owner, index) = self.field_index(&field.identifier);
+                    owner = Some(field_owner);
+                    patterns[index] = match &field.pattern {
+                        Some(pattern) => self.pattern(pattern, vars),
+                        None => self.pattern_var(&field.identifier, vars),
+                    };
+                }
+                match owner {
+                    Some(owner) => Pat::Con(Con::Record(owner), patterns),
+                    None => Pat::Any,
+                }
+            }
+            ast::PatternKind::Or(alternatives) => Pat::Or(
+                alternatives
+                    .iter()
+                    .map(|alternative| self.pattern(alternative, vars))
+                    .collect(),
+            ),
+            ast::PatternKind::Paren(pattern) | ast::PatternKind::Typed(pattern, _) => {
+                self.pattern(pattern, vars)
+            }
+            ast::PatternKind::Tuple(elements) => Pat::Con(
+                Con::Tuple(elements.len()),
+                elements.iter().map(|e| self.pattern(e, vars)).collect(),
+            ),
+            ast::PatternKind::List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.pattern(e, vars))
+                    .collect::<Vec<_>>();
+                elements
+                    .into_iter()
+                    .rev()
+                    .fold(Pat::Con(Con::Nil, Vec::new()), |tail, head| {
+                        Pat::Con(Con::Cons, vec![head, tail])
+                    })
+            }
+            ast::PatternKind::Array(elements) => Pat::Con(
+                Con::Array(elements.len()),
+                elements.iter().map(|e| self.pattern(e, vars)).collect(),
+            ),
+            ast::PatternKind::Cons(head, tail) => {
+                let head = self.pattern(head, vars);
+                Pat::Con(Con::Cons, vec![head, self.pattern(tail, vars)])
+            }
+        }
+    }
+
+    /// Gets the variable bound by the identifier in a pattern, creating it
+    /// on its first occurrence.
+    fn pattern_var(&mut self, identifier: &ast::Literal, vars: &mut Vec<VarId>) -> Pat {
+        let node = *self
+            .resolutions
+            .or_bindings
+            .get(&identifier.id)
+            .unwrap_or(&identifier.id);
+        let var = match self.locals.get(&node) {
+            Some(var) => *var,
+            None => {
+                let ty = self.node_ty(node);
+                let name = self.text(identifier.span).into();
+                let var = self.program.new_var(name, ty, identifier.span);
+                self.locals.insert(node, var);
+                var
+            }
+        };
+        if !vars.contains(&var) {
+            vars.push(var);
+        }
+        Pat::Bind(var)
+    }
+
+    /// Emits the decision tree. The occurrences are read into variables
+    /// when they are first needed on the path from the root.
+    fn emit_decision(
+        &mut self,
+        compiler: &MatchCompiler,
+        decision: Decision,
+        materialized: &mut HashMap<OccId, VarId>,
+        bodies: &mut [ArmBody],
+        ty: &Ty,
+        failure: Failure,
+    ) -> Expr {
+        let mut lets = Vec::new();
+        let tree = match decision {
+            Decision::Fail => {
+                let (exception, span) = match failure {
+                    Failure::MatchFailure(span) => (
+                        construct(
+                            Con::Exception(Builtin::MatchFailure),
+                            Vec::new(),
+                            Ty::Prim(PrimTy::Exn),
+                            span,
+                        ),
+                        span,
+                    ),
+                    Failure::Reraise(var) => {
+                        let span = self.program.var(var).span;
+                        (self.var_expr(var, span), span)
+                    }
+                };
+                prim(
+                    PrimOp::Builtin(Builtin::Raise),
+                    vec![exception],
+                    ty.clone(),
+                    span,
+                )
+            }
+            Decision::Leaf { arm, bindings } => match &mut bodies[arm] {
+                ArmBody::Inline(body) => {
+                    for (var, occurrence) in bindings {
+                        let bound = self.materialize(
+                            compiler,
+                            occurrence,
+                            materialized,
+                            &mut lets,
+                            Some(var),
+                        );
+                        if bound != var {
+                            let span = self.program.var(var).span;
+                            lets.push((var, self.var_expr(bound, span)));
+                        }
+                    }
+                    body.take().unwrap()
+                }
+                ArmBody::Join(join, vars) => {
+                    let (join, vars) = (*join, vars.clone());
+                    let span = self.program.var(join).span;
+                    let arguments = if vars.is_empty() {
+                        vec![literal_expr(Literal::Unit, span)]
+                    } else {
+                        vars.iter()
+                            .map(|var| {
+                                let occurrence = bindings
+                                    .iter()
+                                    .find(|(bound, _)| bound == var)
+                                    .map(|(_, occurrence)| *occurrence)
+                                    .unwrap();
+                                let value = self.materialize(
+                                    compiler,
+                                    occurrence,
+                                    materialized,
+                                    &mut lets,
+                                    None,
+                                );
+                                self.var_expr(value, span)
+                            })
+                            .collect()
+                    };
+                    Expr {
+                        ty: ty.clone(),
+                        span,
+                        kind: ExprKind::Apply(
+                            Box::new(self.var_expr(join, span)),
+                            arguments,
+                            TailCall::default(),
+                        ),
+                    }
+                }
+                ArmBody::Unreachable => unreachable!("leaf of an unreachable arm"),
+            },
+            Decision::Switch {
+                occurrence,
+                cases,
+                default,
+            } => {
+                let var = self.materialize(compiler, occurrence, materialized, &mut lets, None);
+                let span = self.program.var(var).span;
+                let cases = cases
+                    .into_iter()
+                    .map(|(test, decision)| {
+                        let mut materialized = materialized.clone();
+                        let body = self.emit_decision(
+                            compiler,
+                            decision,
+                            &mut materialized,
+                            bodies,
+                            ty,
+                            failure,
+                        );
+                        (test, body)
+                    })
+                    .collect();
+                let default = default.map(|decision| {
+                    let mut materialized = materialized.clone();
+                    Box::new(self.emit_decision(
+                        compiler,
+                        *decision,
+                        &mut materialized,
+                        bodies,
+                        ty,
+                        failure,
+                    ))
+                });
+                Expr {
+                    ty: ty.clone(),
+                    span,
+                    kind: ExprKind::Switch(var, cases, default),
+                }
+            }
+        };
+        wrap_lets(lets, tree)
+    }
+
+    /// Gets the variable holding the value of the occurrence, reading it
+    /// from its parent into the preferred variable or a new one if needed.
+    fn materialize(
+        &mut self,
+        compiler: &MatchCompiler,
+        occurrence: OccId,
+        materialized: &mut HashMap<OccId, VarId>,
+        lets: &mut Vec<(VarId, Expr)>,
+        preferred: Option<VarId>,
+    ) -> VarId {
+        if let Some(var) = materialized.get(&occurrence) {
+            return *var;
+        }
+        let (parent, con, index) = compiler.occurrences[occurrence].parent.unwrap();
+        let parent = self.materialize(compiler, parent, materialized, lets, None);
+        let span = self.program.var(parent).span;
+        let ty = compiler.occurrences[occurrence].ty.clone();
+        let var = preferred.unwrap_or_else(|| self.new_var("_", ty.clone(), span));
+        let value = Expr {
+            ty,
+            span,
+            kind: ExprKind::Field(Box::new(self.var_expr(parent, span)), con, index),
+        };
+        lets.push((var, value));
+        materialized.insert(occurrence, var);
+        var
+    }
+
+    fn var_expr(&self, var: VarId, span: SourceSpan) -> Expr {
+        Expr {
+            ty: self.program.var(var).ty.clone(),
+            span,
+            kind: ExprKind::Var(var),
+        }
+    }
+
+    fn literal(&self, literal: &ast::Literal) -> Literal {
+        let text = self.text(literal.span);
+        match literal.kind {
+            // The literals out of range saturate.
+            ast::LiteralKind::Integer => Literal::Int(text.parse().unwrap_or(i64::MAX)),
+            ast::LiteralKind::Bool => Literal::Bool(text == "true"),
+            ast::LiteralKind::Unit => Literal::Unit,
+            ast::LiteralKind::String => {
+                let contents = &text[1..text.len() - 1];
+                Literal::String(token::unescape_string(contents).unwrap_or_default().into())
+            }
+            ast::LiteralKind::Identifier | ast::LiteralKind::TypeVariable => {
+                unreachable!("identifier lowered as a literal")
+            }
+        }
+    }
+
+    /// Gets the record type of the field and the index of the field in it.
+    fn field_index(&self, field: &ast::Literal) -> (DefId, usize) {
+        let def = self.resolutions.fields[&field.id];
+        let owner = match self.resolutions.def(def).kind {
+            DefKind::Field(owner) => owner,
+            kind => unreachable!("field resolved to {:?}", kind),
+        };
+        match &self.typeck_results.adts[&owner].kind {
+            AdtKind::Record(fields) => (owner, fields.iter().position(|f| f.def == def).unwrap()),
+            AdtKind::Variant(_) => unreachable!("field of a variant type"),
+        }
+    }
+
+    fn node_ty(&self, node: NodeId) -> Ty {
+        self.typeck_results.node_types[&node].clone()
+    }
+
+    fn text(&self, span: SourceSpan) -> &'a str {
+        self.source_map.span_to_snippet(span)
+    }
+}
+
+impl MatchCompiler {
+    /// Gets the occurrence of the field of the parent built by the
+    /// constructor.
+    fn child(&mut self, lowerer: &Lowerer, parent: OccId, con: Con, index: usize) -> OccId {
+        if let Some(child) = self.children.get(&(parent, con, index)) {
+            return *child;
+        }
+        let tys = con_field_tys(
+            lowerer.resolutions,
+            lowerer.typeck_results,
+            con,
+            &self.occurrences[parent].ty,
+        )
+        .unwrap_or_else(|| unreachable!("{:?} does not build the occurrence", con));
+        let child = self.occurrences.len();
+        self.occurrences.push(Occurrence {
+            parent: Some((parent, con, index)),
+            ty: tys[index].clone(),
+        });
+        self.children.insert((parent, con, index), child);
+        child
+    }
+
+    /// Simplifies the patterns of the row which do not test the value: binds
+    /// the variables, matches the fields of the tuples and the records, and
+    /// splits the row at the or-patterns.
+    fn expand(&mut self, lowerer: &Lowerer, mut row: Row, rows: &mut Vec<Row>) {
+        let position = match row.patterns.iter().position(|(_, pat)| !pat.is_test()) {
+            Some(position) => position,
+            None => return rows.push(row),
+        };
+        let (occurrence, pat) = row.patterns.remove(position);
+        match pat {
+            Pat::Any => self.expand(lowerer, row, rows),
+            Pat::Bind(var) => {
+                row.bindings.push((var, occurrence));
+                self.expand(lowerer, row, rows)
+            }
+            Pat::Con(con, fields) => {
+                let fields = self.fields(lowerer, occurrence, con, fields);
+                row.patterns.splice(position..position, fields);
+                self.expand(lowerer, row, rows)
+            }
+            Pat::Or(alternatives) => {
+                for alternative in alternatives {
+                    let mut row = row.clone();
+                    row.patterns.insert(position, (occurrence, alternative));
+                    self.expand(lowerer, row, rows);
+                }
+            }
+            Pat::Literal(_) => unreachable!(),
+        }
+    }
+
+    fn fields(
+        &mut self,
+        lowerer: &Lowerer,
+        occurrence: OccId,
+        con: Con,
+        fields: Vec<Pat>,
+    ) -> Vec<(OccId, Pat)> {
+        fields
+            .into_iter()
+            .enumerate()
+            .map(|(index, field)| (self.child(lowerer, occurrence, con, index), field))
+            .collect()
+    }
+
+    /// Compiles the rows to a decision tree selecting the arm of the first
+    /// matching row. Tests the occurrence of the first pattern of the first
+    /// row, in each case keeping the rows compatible with the test.
+    fn compile(&mut self, lowerer: &Lowerer, rows: Vec<Row>) -> Decision {
+        let first = match rows.first() {
+            Some(first) => first,
+            None => return Decision::Fail,
+        };
+        let occurrence = match first.patterns.first() {
+            Some((occurrence, _)) => *occurrence,
+            None => {
+                return Decision::Leaf {
+                    arm: first.arm,
+                    bindings: first.bindings.clone(),
+                }
+            }
+        };
+
+        let mut tests = Vec::new();
+        for row in &rows {
+            if let Some((_, pat)) = row.patterns.iter().find(|(o, _)| *o == occurrence) {
+                let test = pat.test();
+                if !tests.contains(&test) {
+                    tests.push(test);
+                }
+            }
+        }
+        let ty = self.occurrences[occurrence].ty.clone();
+        let exhaustive = match &ty {
+            Ty::Prim(PrimTy::Bool) => {
+                tests.contains(&Test::Literal(Literal::Bool(true)))
+                    && tests.contains(&Test::Literal(Literal::Bool(false)))
+            }
+            ty => all_cons(lowerer.typeck_results, ty)
+                .is_some_and(|cons| cons.into_iter().all(|con| tests.contains(&Test::Con(con)))),
+        };
+
+        let cases = tests
+            .into_iter()
+            .map(|test| {
+                let mut specialized = Vec::new();
+                for row in &rows {
+                    self.specialize(lowerer, row, occurrence, &test, &mut specialized);
+                }
+                let decision = self.compile(lowerer, specialized);
+                (test, decision)
+            })
+            .collect();
+        let default = if exhaustive {
+            None
+        } else {
+            let rows = rows
+                .into_iter()
+                .filter(|row| row.patterns.iter().all(|(o, _)| *o != occurrence))
+                .collect();
+            Some(Box::new(self.compile(lowerer, rows)))
+        };
+        Decision::Switch {
+            occurrence,
+            cases,
+            default,
+        }
+    }
+
+    /// Adds the row if it is compatible with the test of the occurrence,
+    /// replacing the tested pattern with the patterns of its fields.
+    fn specialize(
+        &mut self,
+        lowerer: &Lowerer,
+        row: &Row,
+        occurrence: OccId,
+        test: &Test,
+        rows: &mut Vec<Row>,
+    ) {
+        let position = match row.patterns.iter().position(|(o, _)| *o == occurrence) {
+            Some(position) => position,
+            None => return rows.push(row.clone()),
+        };
+        if row.patterns[position].1.test() != *test {
+            return;
+        }
+        let mut row = row.clone();
+        let (_, pat) = row.patterns.remove(position);
+        if let Pat::Con(con, fields) = pat {
+            let fields = self.fields(lowerer, occurrence, con, fields);
+            row.patterns.splice(position..position, fields);
+        }
+        self.expand(lowerer, row, rows);
+    }
+}
+
+impl Decision {
+    fn count_leaves(&self, leaves: &mut [usize]) {
+        match self {
+            Decision::Fail => {}
+            Decision::Leaf { arm, .. } => leaves[*arm] += 1,
+            Decision::Switch { cases, default, .. } => {
+                for (_, decision) in cases {
+                    decision.count_leaves(leaves);
+                }
+                if let Some(default) = default {
+                    default.count_leaves(leaves);
+                }
+            }
+        }
+    }
+}
+
+/// Gets the type variables the scheme is generalized over, by matching it
+/// against the type of the binding.
+fn quantified_vars(scheme: &Scheme, ty: &Ty) -> Vec<TyVid> {
+    fn collect(generic: &Ty, ty: &Ty, vars: &mut [Option<TyVid>]) {
+        match (generic, ty) {
+            (Ty::Generic(index), Ty::Var(vid)) => vars[*index as usize] = Some(*vid),
+            (Ty::Adt(_, generics), Ty::Adt(_, tys)) | (Ty::Tuple(generics), Ty::Tuple(tys)) => {
+                for (generic, ty) in generics.iter().zip(tys) {
+                    collect(generic, ty, vars);
+                }
+            }
+            (Ty::List(generic), Ty::List(ty))
+            | (Ty::Array(generic), Ty::Array(ty))
+            | (Ty::Ref(generic), Ty::Ref(ty)) => collect(generic, ty, vars),
+            (Ty::Function(generic_parameter, generic_result), Ty::Function(parameter, result)) => {
+                collect(generic_parameter, parameter, vars);
+                collect(generic_result, result, vars);
+            }
+            _ => {}
+        }
+    }
+
+    let mut vars = vec![None; scheme.generics as usize];
+    collect(&scheme.ty, ty, &mut vars);
+    vars.into_iter().flatten().collect()
+}
+
+/// Skips the parentheses and the type annotations around the pattern.
+fn strip_pattern(pattern: &ast::Pattern) -> &ast::Pattern {
+    match &pattern.kind {
+        ast::PatternKind::Paren(inner) | ast::PatternKind::Typed(inner, _) => strip_pattern(inner),
+        _ => pattern,
+    }
+}
+
+fn parameter_count(ty: &Ty) -> usize {
+    match ty {
+        Ty::Function(_, result) => 1 + parameter_count(result),
+        _ => 0,
+    }
+}
+
+/// Gets the result of the function type applied to the arguments.
+fn result_ty(ty: &Ty, arguments: usize) -> Ty {
+    match ty {
+        Ty::Function(_, result) if arguments > 0 => result_ty(result, arguments - 1),
+        ty => ty.clone(),
+    }
+}
+
+fn literal_expr(literal: Literal, span: SourceSpan) -> Expr {
+    Expr {
+        ty: literal.ty(),
+        span,
+        kind: ExprKind::Literal(literal),
+    }
+}
+
+fn prim(op: PrimOp, arguments: Vec<Expr>, ty: Ty, span: SourceSpan) -> Expr {
+    Expr {
+        ty,
+        span,
+        kind: ExprKind::Prim(op, arguments),
+    }
+}
+
+fn construct(con: Con, fields: Vec<Expr>, ty: Ty, span: SourceSpan) -> Expr {
+    Expr {
+        ty,
+        span,
+        kind: ExprKind::Construct(con, fields),
+    }
+}
+
+fn let_expr(var: VarId, value: Expr, body: Expr) -> Expr {
+    Expr {
+        ty: body.ty.clone(),
+        span: value.span.to(body.span),
+        kind: ExprKind::Let(var, Box::new(value), Box::new(body)),
+    }
+}
+
+/// Binds the values to the variables in order around the body.
+fn wrap_lets(lets: Vec<(VarId, Expr)>, body: Expr) -> Expr {
+    lets.into_iter()
+        .rev()
+        .fold(body, |body, (var, value)| let_expr(var, value, body))
+}
+
+/// Lowers the built-in binary operator applied to the operands. The logical
+/// operators evaluate the right operand only if needed.
+fn binary(kind: ast::BinaryOpKind, lhs: Expr, rhs: Expr, ty: Ty, span: SourceSpan) -> Expr {
+    use ast::BinaryOpKind::*;
+    let op = match kind {
+        And | Or => {
+            let short_circuit = literal_expr(Literal::Bool(kind == Or), span);
+            let (then_branch, else_branch) = match kind {
+                And => (rhs, short_circuit),
+                _ => (short_circuit, rhs),
+            };
+            return Expr {
+                ty,
+                span,
+                kind: ExprKind::If(Box::new(lhs), Box::new(then_branch), Box::new(else_branch)),
+            };
+        }
+        Cons => return construct(Con::Cons, vec![lhs, rhs], ty, span),
+        Add => PrimOp::Add,
+        Subtract => PrimOp::Subtract,
+        Multiply => PrimOp::Multiply,
+        Divide => PrimOp::Divide,
+        Equal => PrimOp::Equal,
+        NotEqual => PrimOp::NotEqual,
+        Less => PrimOp::Less,
+        LessEqual => PrimOp::LessEqual,
+        Greater => PrimOp::Greater,
+        GreaterEqual => PrimOp::GreaterEqual,
+        Assign => PrimOp::Assign,
+    };
+    prim(op, vec![lhs, rhs], ty, span)
+}
+
+/// Converts the `int`, `bool` or `string` value to its text.
+fn to_string(value: Expr) -> Expr {
+    let span = value.span;
+    let string = Ty::Prim(PrimTy::String);
+    match value.ty {
+        Ty::Prim(PrimTy::Int) => prim(
+            PrimOp::Builtin(Builtin::StringOfInt),
+            vec![value],
+            string,
+            span,
+        ),
+        Ty::Prim(PrimTy::Bool) => Expr {
+            ty: string,
+            span,
+            kind: ExprKind::If(
+                Box::new(value),
+                Box::new(literal_expr(Literal::String("true".into()), span)),
+                Box::new(literal_expr(Literal::String("false".into()), span)),
+            ),
+        },
+        _ => value,
+    }
+}
+
+/// Concatenates the strings from left to right.
+fn concat(strings: Vec<Expr>, span: SourceSpan) -> Expr {
+    let mut strings = strings.into_iter();
+    let first = match strings.next() {
+        Some(first) => first,
+        None => return literal_expr(Literal::String("".into()), span),
+    };
+    strings.fold(first, |result, string| {
+        let span = result.span.to(string.span);
+        prim(
+            PrimOp::Builtin(Builtin::StringConcat),
+            vec![result, string],
+            Ty::Prim(PrimTy::String),
+            span,
+        )
+    })
+}
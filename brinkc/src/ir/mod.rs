@@ -0,0 +1,565 @@
+//! The core intermediate representation: a small, explicitly typed language
+//! the type checked syntax tree is desugared into. The pattern matching is
+//! compiled to decision trees, the operators to calls of the primitives and
+//! the blocks to nested lets, and every variable is bound exactly once in
+//! the whole program.
+
+use std::{fmt, rc::Rc};
+
+use crate::{
+    ast::ForDirection,
+    resolve::{Builtin, DefId, DefKind, PrimTy, Resolutions},
+    source_file::SourceSpan,
+    typeck::{
+        ty::{Ty, TyVid},
+        AdtKind, TypeckResults,
+    },
+};
+
+mod lower;
+mod pretty;
+mod tail;
+mod validate;
+
+pub use lower::lower;
+pub use pretty::print_program;
+pub use validate::validate;
+
+/// Identifies a variable, unique in the whole program.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct VarId(u32);
+
+impl VarId {
+    pub fn new(index: usize) -> VarId {
+        VarId(index as u32)
+    }
+
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug)]
+pub struct VarInfo {
+    /// The name of the source variable, or `_` for the ones introduced by
+    /// the lowering.
+    pub name: Rc<str>,
+    /// The type of the bound value. A polymorphic variable has the type
+    /// variables it is generalized over listed in `generics`, and every use
+    /// of it has an instance of the type.
+    pub ty: Ty,
+    pub generics: Vec<TyVid>,
+    pub span: SourceSpan,
+}
+
+/// The lowered program: the module-level bindings of all the modules, in
+/// the order they are evaluated. The functions of the bindings can refer to
+/// the bindings defined later.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub vars: Vec<VarInfo>,
+    pub globals: Vec<Global>,
+}
+
+impl Program {
+    pub fn var(&self, id: VarId) -> &VarInfo {
+        &self.vars[id.as_usize()]
+    }
+
+    pub fn new_var(&mut self, name: Rc<str>, ty: Ty, span: SourceSpan) -> VarId {
+        let id = VarId(self.vars.len() as u32);
+        self.vars.push(VarInfo {
+            name,
+            ty,
+            generics: Vec::new(),
+            span,
+        });
+        id
+    }
+}
+
+/// A module-level binding. The top-level expressions are bound to the
+/// variables named `_`.
+#[derive(Debug)]
+pub struct Global {
+    pub var: VarId,
+    pub value: Expr,
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub ty: Ty,
+    pub span: SourceSpan,
+    pub kind: ExprKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+    Var(VarId),
+    Literal(Literal),
+    /// A curried function of the parameters.
+    Lambda(Vec<VarId>, Box<Expr>),
+    /// Applies the function to the arguments one by one; the function may
+    /// take fewer or more parameters than there are arguments.
+    Apply(Box<Expr>, Vec<Expr>, TailCall),
+    /// A saturated call of the primitive operation.
+    Prim(PrimOp, Vec<Expr>),
+    Let(VarId, Box<Expr>, Box<Expr>),
+    /// Mutually recursive functions; the values are lambdas.
+    LetRec(Vec<(VarId, Expr)>, Box<Expr>),
+    /// Builds the value of the constructor from the fields.
+    Construct(Con, Vec<Expr>),
+    /// Reads the field of the value, which is known to be built by the
+    /// constructor: either it is the only one of the type, or the value is
+    /// a variable tested by an enclosing `Switch`.
+    Field(Box<Expr>, Con, usize),
+    /// Evaluates the first case whose test matches the value of the
+    /// variable, or the default. Without the default, the tests cover all
+    /// the values of the type.
+    Switch(VarId, Vec<(Test, Expr)>, Option<Box<Expr>>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    While(Box<Expr>, Box<Expr>),
+    /// A loop binding the variable to the integers from the start to the
+    /// end, both inclusive and evaluated once.
+    For(VarId, Box<Expr>, ForDirection, Box<Expr>, Box<Expr>),
+    /// Evaluates the body, and the handler with the variable bound to the
+    /// exception if one is raised.
+    Try(Box<Expr>, VarId, Box<Expr>),
+}
+
+/// Whether an application is a tail call, the last thing done by the
+/// enclosing function.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TailCall {
+    /// The application is in tail position, as marked by
+    /// `mark_tail_calls`.
+    pub is_tail: bool,
+    /// The application is annotated with `@tailcall`, so it has to stay in
+    /// tail position.
+    pub required: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Bool(bool),
+    String(Rc<str>),
+    Unit,
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Int(value) => write!(f, "{}", value),
+            Literal::Bool(value) => write!(f, "{}", value),
+            Literal::String(value) => write!(f, "{:?}", value),
+            Literal::Unit => write!(f, "()"),
+        }
+    }
+}
+
+impl Literal {
+    pub fn ty(&self) -> Ty {
+        Ty::Prim(match self {
+            Literal::Int(_) => PrimTy::Int,
+            Literal::Bool(_) => PrimTy::Bool,
+            Literal::String(_) => PrimTy::String,
+            Literal::Unit => PrimTy::Unit,
+        })
+    }
+}
+
+/// A way of building a value out of fields.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Con {
+    Tuple(usize),
+    /// The record type.
+    Record(DefId),
+    /// A variant constructor or a user-defined exception.
+    Constructor(DefId),
+    /// A built-in exception.
+    Exception(Builtin),
+    Nil,
+    Cons,
+    /// The array of the length.
+    Array(usize),
+}
+
+impl Con {
+    /// Checks whether the constructor is the only one of its type, so its
+    /// fields can be read without testing the value first.
+    pub fn is_irrefutable(self) -> bool {
+        matches!(self, Con::Tuple(_) | Con::Record(_))
+    }
+
+    /// Gets the name of the constructor in the tests of a `Switch`.
+    pub fn name(self, resolutions: &Resolutions) -> String {
+        match self {
+            Con::Tuple(n) => format!("({})", ",".repeat(n - 1)),
+            Con::Record(def) | Con::Constructor(def) => resolutions.def(def).name.to_string(),
+            Con::Exception(builtin) => builtin.name().to_string(),
+            Con::Nil => "[]".to_string(),
+            Con::Cons => "::".to_string(),
+            Con::Array(n) => format!("[|{}|]", n),
+        }
+    }
+
+    /// Gets the name of the field in the field reads, e.g. `0` for the
+    /// first element of a tuple and `Some#0` for the argument of `Some`.
+    pub fn field_name(
+        self,
+        index: usize,
+        resolutions: &Resolutions,
+        typeck_results: &TypeckResults,
+    ) -> String {
+        match self {
+            Con::Tuple(_) => index.to_string(),
+            Con::Record(def) => match &typeck_results.adts[&def].kind {
+                AdtKind::Record(fields) => resolutions.def(fields[index].def).name.to_string(),
+                AdtKind::Variant(_) => unreachable!("field of a variant type"),
+            },
+            Con::Constructor(def) => format!("{}#{}", resolutions.def(def).name, index),
+            Con::Exception(builtin) => format!("{}#{}", builtin.name(), index),
+            Con::Cons if index == 0 => "head".to_string(),
+            Con::Cons => "tail".to_string(),
+            Con::Nil => format!("[]#{}", index),
+            Con::Array(_) => format!("[{}]", index),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Test {
+    Con(Con),
+    Literal(Literal),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PrimOp {
+    Add,
+    Subtract,
+    Multiply,
+    /// Raises `DivisionByZero` if the divisor is zero.
+    Divide,
+    Negate,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// The structural equality.
+    Equal,
+    NotEqual,
+    Deref,
+    Assign,
+    /// A built-in function other than `sprintf`, which is lowered to the
+    /// conversions and the concatenations.
+    Builtin(Builtin),
+}
+
+impl PrimOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            PrimOp::Add => "add",
+            PrimOp::Subtract => "sub",
+            PrimOp::Multiply => "mul",
+            PrimOp::Divide => "div",
+            PrimOp::Negate => "negate",
+            PrimOp::Less => "less",
+            PrimOp::LessEqual => "less_equal",
+            PrimOp::Greater => "greater",
+            PrimOp::GreaterEqual => "greater_equal",
+            PrimOp::Equal => "equal",
+            PrimOp::NotEqual => "not_equal",
+            PrimOp::Deref => "deref",
+            PrimOp::Assign => "assign",
+            PrimOp::Builtin(builtin) => builtin.name(),
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            PrimOp::Negate | PrimOp::Deref => 1,
+            PrimOp::Builtin(builtin) => builtin.arity(),
+            _ => 2,
+        }
+    }
+}
+
+/// Gets the types of the fields of the constructor building a value of the
+/// type, or `None` if the constructor does not build values of the type.
+pub fn con_field_tys(
+    resolutions: &Resolutions,
+    typeck_results: &TypeckResults,
+    con: Con,
+    ty: &Ty,
+) -> Option<Vec<Ty>> {
+    match (con, ty) {
+        (Con::Tuple(n), Ty::Tuple(elements)) if elements.len() == n => Some(elements.clone()),
+        (Con::Record(def), Ty::Adt(owner, arguments)) if def == *owner => {
+            match &typeck_results.adts.get(&def)?.kind {
+                AdtKind::Record(fields) => {
+                    Some(fields.iter().map(|f| f.ty.subst(arguments)).collect())
+                }
+                AdtKind::Variant(_) => None,
+            }
+        }
+        (Con::Constructor(def), Ty::Adt(owner, arguments)) => {
+            match resolutions.def(def).kind {
+                DefKind::Constructor(adt) if adt == *owner => {}
+                _ => return None,
+            }
+            match &typeck_results.adts.get(owner)?.kind {
+                AdtKind::Variant(variants) => {
+                    let variant = variants.iter().find(|v| v.def == def)?;
+                    Some(
+                        variant
+                            .arguments
+                            .iter()
+                            .map(|a| a.subst(arguments))
+                            .collect(),
+                    )
+                }
+                AdtKind::Record(_) => None,
+            }
+        }
+        (Con::Constructor(def), Ty::Prim(PrimTy::Exn)) => {
+            if resolutions.def(def).kind != DefKind::Exception {
+                return None;
+            }
+            let mut ty = &typeck_results.def_schemes.get(&def)?.ty;
+            let mut fields = Vec::new();
+            while let Ty::Function(parameter, result) = ty {
+                fields.push(parameter.as_ref().clone());
+                ty = result;
+            }
+            Some(fields)
+        }
+        (Con::Exception(builtin), Ty::Prim(PrimTy::Exn)) if builtin.is_exception() => {
+            Some(vec![Ty::Prim(PrimTy::String); builtin.arity()])
+        }
+        (Con::Nil, Ty::List(_)) => Some(Vec::new()),
+        (Con::Cons, Ty::List(element)) => Some(vec![element.as_ref().clone(), ty.clone()]),
+        (Con::Array(n), Ty::Array(element)) => Some(vec![element.as_ref().clone(); n]),
+        _ => None,
+    }
+}
+
+/// Gets all the constructors of the type, if there are finitely many.
+pub fn all_cons(typeck_results: &TypeckResults, ty: &Ty) -> Option<Vec<Con>> {
+    match ty {
+        Ty::Tuple(elements) => Some(vec![Con::Tuple(elements.len())]),
+        Ty::Adt(def, _) => Some(match &typeck_results.adts.get(def)?.kind {
+            AdtKind::Record(_) => vec![Con::Record(*def)],
+            AdtKind::Variant(variants) => {
+                variants.iter().map(|v| Con::Constructor(v.def)).collect()
+            }
+        }),
+        Ty::List(_) => Some(vec![Con::Nil, Con::Cons]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_util;
+
+    /// Lowers and validates the program, returning the printed IR.
+    fn lower_source(source: &str) -> String {
+        let test_util::Checked {
+            session,
+            graph,
+            resolutions,
+            results,
+        } = test_util::check(source);
+        let program = lower(&session.source_map, &graph, &resolutions, &results);
+        if let Err(errors) = validate(&program, &resolutions, &results) {
+            panic!(
+                "{:#?}\n{}",
+                errors,
+                print_program(&program, &resolutions, &results)
+            );
+        }
+        print_program(&program, &resolutions, &results)
+    }
+
+    /// Gets the printed IR of the last global of the name, with the
+    /// variables renumbered from zero in the order they appear.
+    fn lower_global(source: &str, name: &str) -> String {
+        let printed = lower_source(source);
+        let header = format!("let {}/", name);
+        let start = printed.rfind(&header).unwrap();
+        let end = printed[start..]
+            .find("\n\n")
+            .map_or(printed.len(), |end| start + end);
+        renumber(printed[start..end].trim_end())
+    }
+
+    fn renumber(printed: &str) -> String {
+        let mut ids = HashMap::new();
+        let mut output = String::new();
+        let mut chars = printed.chars().peekable();
+        while let Some(c) = chars.next() {
+            output.push(c);
+            if c != '/' || !chars.peek().is_some_and(char::is_ascii_digit) {
+                continue;
+            }
+            let mut id = String::new();
+            while let Some(digit) = chars.peek().copied().filter(char::is_ascii_digit) {
+                id.push(digit);
+                chars.next();
+            }
+            let next = ids.len();
+            output.push_str(&ids.entry(id).or_insert(next).to_string());
+        }
+        output
+    }
+
+    #[test]
+    fn lowers_operators_and_blocks() {
+        let source = "let f x =\n  let y = x * 2\n  print_string \"a\"\n  -y + 1 < 3 && true\n";
+        assert_eq!(
+            "let f/0 : int -> bool =\n  \
+               fun (x/1 : int) ->\n    \
+                 let y/2 : int = %mul(x/1, 2)\n    \
+                 let _/3 : unit = %print_string(\"a\")\n    \
+                 if %less(%add(%negate(y/2), 1), 3) then\n      \
+                   true\n    \
+                 else\n      \
+                   false",
+            lower_global(source, "f")
+        );
+    }
+
+    #[test]
+    fn compiles_matches_to_decision_trees() {
+        let source = "let f xs =\n  match xs\n  | [] -> 0\n  | [x] -> x\n  | x :: _ -> -x\n";
+        assert_eq!(
+            "let f/0 : list int -> int =\n  \
+               fun (xs/1 : list int) ->\n    \
+                 switch xs/1\n    \
+                 | [] ->\n      \
+                   0\n    \
+                 | :: ->\n      \
+                   let _/2 : list int = xs/1.tail\n      \
+                   switch _/2\n      \
+                   | [] ->\n        \
+                     let x/3 : int = xs/1.head\n        \
+                     x/3\n      \
+                   | _ ->\n        \
+                     let x/4 : int = xs/1.head\n        \
+                     %negate(x/4)",
+            lower_global(source, "f")
+        );
+    }
+
+    #[test]
+    fn shares_arms_reached_from_several_leaves() {
+        let source = "type T = A | B | C int\nlet f t =\n  match t\n  | A | C 1 -> 1\n  | _ -> 2\n";
+        assert_eq!(
+            "let f/0 : T -> int =\n  \
+               fun (t/1 : T) ->\n    \
+                 let _/2 : unit -> int = fun (_/3 : unit) -> 1\n    \
+                 let _/4 : unit -> int = fun (_/5 : unit) -> 2\n    \
+                 switch t/1\n    \
+                 | A ->\n      \
+                   tail _/2 ()\n    \
+                 | C ->\n      \
+                   let _/6 : int = t/1.C#0\n      \
+                   switch _/6\n      \
+                   | 1 ->\n        \
+                     tail _/2 ()\n      \
+                   | _ ->\n        \
+                     tail _/4 ()\n    \
+                 | _ ->\n      \
+                   tail _/4 ()",
+            lower_global(source, "f")
+        );
+    }
+
+    #[test]
+    fn keeps_local_bindings_polymorphic() {
+        assert_eq!(
+            "let pair/0 : int * bool =\n  \
+               let id/1 : 'a -> 'a = fun (x/2 : 'a) -> x/2\n  \
+               (id/1 1, id/1 true)",
+            lower_global("let pair =\n  let id x = x\n  (id 1, id true)\n", "pair")
+        );
+    }
+
+    #[test]
+    fn lowers_strings_and_sections() {
+        let source = "let f (n: int) = $\"n = {n}!\"\nlet g = sprintf \"%s!\"\nlet h = (1 -)\n";
+        assert_eq!(
+            "let f/0 : int -> string =\n  \
+               fun (n/1 : int) -> %string_concat(%string_concat(\"n = \", %string_of_int(n/1)), \"!\")",
+            lower_global(source, "f")
+        );
+        assert_eq!(
+            "let g/0 : string -> string =\n  \
+               fun (_/1 : string) -> %string_concat(_/1, \"!\")",
+            lower_global(source, "g")
+        );
+        assert_eq!(
+            "let h/0 : int -> int =\n  \
+               let _/1 : int = 1\n  \
+               fun (_/2 : int) -> %sub(_/1, _/2)",
+            lower_global(source, "h")
+        );
+    }
+
+    #[test]
+    fn lowers_standard_library_and_tests() {
+        let tests = [
+            include_str!("../../lib/tests/list.bk"),
+            include_str!("../../lib/tests/map.bk"),
+            include_str!("../../lib/tests/option.bk"),
+            include_str!("../../lib/tests/result.bk"),
+            include_str!("../../lib/tests/set.bk"),
+            include_str!("../../lib/tests/string.bk"),
+            include_str!("../../lib/tests/io.bk"),
+        ];
+        for source in &tests {
+            lower_source(source);
+        }
+        lower_source(
+            "exception Error string int\n\
+             type Point = { x: int; y: int }\n\
+             let f p =\n  match p\n  | { x = 0; y } | { y; x = 1 } -> y\n  | { x } -> x\n\
+             let g p = { p with y = 1; x = p.y }\n\
+             let h () =\n  try raise (Error \"a\" 1)\n  with\n  | Error _ n -> n\n  | Failure _ -> 0\n\
+             let sum a =\n  let total = ref 0\n  for i = 0 to 1 do\n    total := !total + i\n  match a\n  | [| x; y |] -> x + y + !total\n  | _ -> 0\n",
+        );
+    }
+
+    #[test]
+    fn reports_invalid_programs() {
+        let test_util::Checked {
+            session,
+            graph,
+            resolutions,
+            results,
+        } = test_util::check("let f x = x + 1\n");
+        let mut program = lower(&session.source_map, &graph, &resolutions, &results);
+        assert!(validate(&program, &resolutions, &results).is_ok());
+
+        let global = program.globals.last_mut().unwrap();
+        if let ExprKind::Lambda(_, body) = &mut global.value.kind {
+            body.ty = Ty::Prim(PrimTy::Bool);
+        }
+        let errors = validate(&program, &resolutions, &results).unwrap_err();
+        let messages = errors
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "mismatched types: expected `bool`, found `int`",
+                "mismatched types: expected `int -> int`, found `int -> bool`",
+            ],
+            messages
+        );
+    }
+}
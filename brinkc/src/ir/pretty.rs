@@ -0,0 +1,297 @@
+use crate::{
+    resolve::Resolutions,
+    typeck::{ty::TyPrinter, TypeckResults},
+};
+
+use super::{Con, Expr, ExprKind, Literal, Program, Test, VarId};
+
+/// Formats the program for reading. The variables are shown with their
+/// unique ids, e.g. `x/1`, and the binders with their types. The type
+/// variables are named per global.
+pub fn print_program(
+    program: &Program,
+    resolutions: &Resolutions,
+    typeck_results: &TypeckResults,
+) -> String {
+    let mut printer = Printer {
+        program,
+        resolutions,
+        typeck_results,
+        ty_printer: TyPrinter::new(resolutions),
+        output: String::new(),
+    };
+    for (i, global) in program.globals.iter().enumerate() {
+        if i > 0 {
+            printer.output.push_str("\n\n");
+        }
+        printer.ty_printer = TyPrinter::new(resolutions);
+        printer.output.push_str("let ");
+        printer.binder(global.var);
+        printer.output.push_str(" =");
+        printer.block(&global.value, 2);
+    }
+    printer.output.push('\n');
+    printer.output
+}
+
+struct Printer<'a> {
+    program: &'a Program,
+    resolutions: &'a Resolutions,
+    typeck_results: &'a TypeckResults,
+    ty_printer: TyPrinter<'a>,
+    output: String,
+}
+
+impl<'a> Printer<'a> {
+    fn var(&mut self, var: VarId) {
+        let name = &self.program.var(var).name;
+        self.output
+            .push_str(&format!("{}/{}", name, var.as_usize()));
+    }
+
+    fn binder(&mut self, var: VarId) {
+        self.var(var);
+        let ty = self.ty_printer.print(&self.program.var(var).ty);
+        self.output.push_str(" : ");
+        self.output.push_str(&ty);
+    }
+
+    /// Prints the expression on a new line at the indentation.
+    fn block(&mut self, expr: &Expr, indent: usize) {
+        self.newline(indent);
+        self.expr(expr, indent);
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.output.push('\n');
+        self.output.extend(std::iter::repeat_n(' ', indent));
+    }
+
+    /// Prints the expression at the current position. The expressions which
+    /// span several lines continue at the indentation.
+    fn expr(&mut self, expr: &Expr, indent: usize) {
+        match &expr.kind {
+            ExprKind::Var(var) => self.var(*var),
+            ExprKind::Literal(literal) => self.literal(literal),
+            ExprKind::Lambda(parameters, body) => {
+                self.output.push_str("fun");
+                for parameter in parameters {
+                    self.output.push_str(" (");
+                    self.binder(*parameter);
+                    self.output.push(')');
+                }
+                self.output.push_str(" ->");
+                if is_inline(body) {
+                    self.output.push(' ');
+                    self.expr(body, indent);
+                } else {
+                    self.block(body, indent + 2);
+                }
+            }
+            ExprKind::Apply(function, arguments, tail_call) => {
+                if tail_call.is_tail {
+                    self.output.push_str("tail ");
+                }
+                self.operand(function, indent);
+                for argument in arguments {
+                    self.output.push(' ');
+                    self.operand(argument, indent);
+                }
+            }
+            ExprKind::Prim(op, arguments) => {
+                self.output.push('%');
+                self.output.push_str(op.name());
+                self.arguments(arguments, indent);
+            }
+            ExprKind::Let(var, value, body) => {
+                self.output.push_str("let ");
+                self.binding(*var, value, indent);
+                self.block(body, indent);
+            }
+            ExprKind::LetRec(bindings, body) => {
+                for (i, (var, value)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        self.newline(indent);
+                    }
+                    self.output
+                        .push_str(if i == 0 { "let rec " } else { "and " });
+                    self.binding(*var, value, indent);
+                }
+                self.block(body, indent);
+            }
+            ExprKind::Construct(con, fields) => self.construct(*con, fields, indent),
+            ExprKind::Field(value, con, index) => {
+                self.operand(value, indent);
+                self.output.push('.');
+                let field = con.field_name(*index, self.resolutions, self.typeck_results);
+                self.output.push_str(&field);
+            }
+            ExprKind::Switch(var, cases, default) => {
+                self.output.push_str("switch ");
+                self.var(*var);
+                for (test, body) in cases {
+                    self.newline(indent);
+                    self.output.push_str("| ");
+                    self.test(test);
+                    self.output.push_str(" ->");
+                    self.block(body, indent + 2);
+                }
+                if let Some(default) = default {
+                    self.newline(indent);
+                    self.output.push_str("| _ ->");
+                    self.block(default, indent + 2);
+                }
+            }
+            ExprKind::If(condition, then_branch, else_branch) => {
+                self.output.push_str("if ");
+                self.operand(condition, indent);
+                self.output.push_str(" then");
+                self.block(then_branch, indent + 2);
+                self.newline(indent);
+                self.output.push_str("else");
+                self.block(else_branch, indent + 2);
+            }
+            ExprKind::While(condition, body) => {
+                self.output.push_str("while ");
+                self.operand(condition, indent);
+                self.output.push_str(" do");
+                self.block(body, indent + 2);
+            }
+            ExprKind::For(var, start, direction, end, body) => {
+                self.output.push_str("for ");
+                self.binder(*var);
+                self.output.push_str(" = ");
+                self.operand(start, indent);
+                self.output.push_str(match direction {
+                    crate::ast::ForDirection::Up => " to ",
+                    crate::ast::ForDirection::Down => " downto ",
+                });
+                self.operand(end, indent);
+                self.output.push_str(" do");
+                self.block(body, indent + 2);
+            }
+            ExprKind::Try(body, var, handler) => {
+                self.output.push_str("try");
+                self.block(body, indent + 2);
+                self.newline(indent);
+                self.output.push_str("with ");
+                self.binder(*var);
+                self.output.push_str(" ->");
+                self.block(handler, indent + 2);
+            }
+        }
+    }
+
+    /// Prints `x/1 : ty = value`, the value on the following lines if it
+    /// does not fit on one.
+    fn binding(&mut self, var: VarId, value: &Expr, indent: usize) {
+        self.binder(var);
+        self.output.push_str(" =");
+        if is_inline(value) {
+            self.output.push(' ');
+            self.expr(value, indent);
+        } else {
+            self.block(value, indent + 2);
+        }
+    }
+
+    /// Prints the expression in a position where the applications and the
+    /// functions have to be parenthesized.
+    fn operand(&mut self, expr: &Expr, indent: usize) {
+        if !is_inline(expr) {
+            self.output.push('(');
+            self.block(expr, indent + 2);
+            self.output.push(')');
+        } else if matches!(expr.kind, ExprKind::Apply(..) | ExprKind::Lambda(..)) {
+            self.output.push('(');
+            self.expr(expr, indent);
+            self.output.push(')');
+        } else {
+            self.expr(expr, indent);
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expr], indent: usize) {
+        self.output.push('(');
+        self.separated(arguments, ", ", indent);
+        self.output.push(')');
+    }
+
+    fn separated(&mut self, exprs: &[Expr], separator: &str, indent: usize) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.output.push_str(separator);
+            }
+            if is_inline(expr) {
+                self.expr(expr, indent);
+            } else {
+                self.operand(expr, indent);
+            }
+        }
+    }
+
+    fn construct(&mut self, con: Con, fields: &[Expr], indent: usize) {
+        match con {
+            Con::Tuple(_) => self.arguments(fields, indent),
+            Con::Record(_) => {
+                self.output.push_str("{ ");
+                self.separated(fields, "; ", indent);
+                self.output.push_str(" }");
+            }
+            Con::Constructor(_) | Con::Exception(_) => {
+                self.con(con);
+                if !fields.is_empty() {
+                    self.arguments(fields, indent);
+                }
+            }
+            Con::Nil => self.output.push_str("[]"),
+            Con::Cons => {
+                self.output.push('(');
+                self.separated(fields, " :: ", indent);
+                self.output.push(')');
+            }
+            Con::Array(_) => {
+                self.output.push_str("[|");
+                self.separated(fields, "; ", indent);
+                self.output.push_str("|]");
+            }
+        }
+    }
+
+    fn con(&mut self, con: Con) {
+        self.output.push_str(&con.name(self.resolutions));
+    }
+
+    fn test(&mut self, test: &Test) {
+        match test {
+            Test::Con(con) => self.con(*con),
+            Test::Literal(literal) => self.literal(literal),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        self.output.push_str(&literal.to_string());
+    }
+}
+
+/// Checks whether the expression is printed on a single line.
+fn is_inline(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Var(_) | ExprKind::Literal(_) => true,
+        ExprKind::Lambda(_, body) => is_inline(body),
+        ExprKind::Apply(function, arguments, _) => {
+            is_inline(function) && arguments.iter().all(is_inline)
+        }
+        ExprKind::Prim(_, arguments) | ExprKind::Construct(_, arguments) => {
+            arguments.iter().all(is_inline)
+        }
+        ExprKind::Field(value, _, _) => is_inline(value),
+        ExprKind::Let(..)
+        | ExprKind::LetRec(..)
+        | ExprKind::Switch(..)
+        | ExprKind::If(..)
+        | ExprKind::While(..)
+        | ExprKind::For(..)
+        | ExprKind::Try(..) => false,
+    }
+}
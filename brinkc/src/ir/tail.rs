@@ -0,0 +1,69 @@
+use super::{Expr, ExprKind, Program};
+
+/// Marks the applications in tail position, whose values the enclosing
+/// functions return without doing anything else. The backends reuse the
+/// frames of the callers for them, so the recursion through tail calls,
+/// including the mutual one, runs in constant space.
+pub fn mark_tail_calls(program: &mut Program) {
+    for global in &mut program.globals {
+        mark(&mut global.value, false);
+    }
+}
+
+fn mark(expr: &mut Expr, tail: bool) {
+    match &mut expr.kind {
+        ExprKind::Var(_) | ExprKind::Literal(_) => {}
+        ExprKind::Lambda(_, body) => mark(body, true),
+        ExprKind::Apply(function, arguments, call) => {
+            call.is_tail = tail;
+            mark(function, false);
+            for argument in arguments {
+                mark(argument, false);
+            }
+        }
+        ExprKind::Prim(_, arguments) | ExprKind::Construct(_, arguments) => {
+            for argument in arguments {
+                mark(argument, false);
+            }
+        }
+        ExprKind::Let(_, value, body) => {
+            mark(value, false);
+            mark(body, tail);
+        }
+        ExprKind::LetRec(bindings, body) => {
+            for (_, value) in bindings {
+                mark(value, false);
+            }
+            mark(body, tail);
+        }
+        ExprKind::Field(value, _, _) => mark(value, false),
+        ExprKind::Switch(_, cases, default) => {
+            for (_, case) in cases {
+                mark(case, tail);
+            }
+            if let Some(default) = default {
+                mark(default, tail);
+            }
+        }
+        ExprKind::If(condition, then_branch, else_branch) => {
+            mark(condition, false);
+            mark(then_branch, tail);
+            mark(else_branch, tail);
+        }
+        ExprKind::While(condition, body) => {
+            mark(condition, false);
+            mark(body, false);
+        }
+        ExprKind::For(_, start, _, end, body) => {
+            mark(start, false);
+            mark(end, false);
+            mark(body, false);
+        }
+        // Returning from the body removes the handler, so it is the body
+        // of the handler which is in tail position.
+        ExprKind::Try(body, _, handler) => {
+            mark(body, false);
+            mark(handler, tail);
+        }
+    }
+}
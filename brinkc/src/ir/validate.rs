@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use crate::{
+    resolve::{Builtin, PrimTy, Resolutions},
+    source_file::SourceSpan,
+    typeck::{
+        ty::{Ty, TyPrinter, TyVid},
+        TypeckResults,
+    },
+};
+
+use super::{all_cons, con_field_tys, Con, Expr, ExprKind, Literal, PrimOp, Program, Test, VarId};
+
+/// An inconsistency in the IR, which is a bug of the pass producing it.
+#[derive(Debug)]
+pub struct ValidationError {
+    /// The global the error is in, e.g. `main/3`.
+    pub global: String,
+    pub span: SourceSpan,
+    pub message: String,
+}
+
+/// Checks that the program is well-formed: every variable is bound once and
+/// used in its scope, the fields of the variants are read only after testing
+/// the constructor, and the type of every expression agrees with the types
+/// of its parts. Meant to run after each pass.
+pub fn validate(
+    program: &Program,
+    resolutions: &Resolutions,
+    typeck_results: &TypeckResults,
+) -> Result<(), Vec<ValidationError>> {
+    let mut validator = Validator {
+        program,
+        resolutions,
+        typeck_results,
+        bound: vec![false; program.vars.len()],
+        in_scope: vec![false; program.vars.len()],
+        known_cons: HashMap::new(),
+        global: String::new(),
+        errors: Vec::new(),
+    };
+    for global in &program.globals {
+        validator.bind(global.var, global.value.span);
+    }
+    for global in &program.globals {
+        let var = program.var(global.var);
+        validator.global = format!("{}/{}", var.name, global.var.as_usize());
+        validator.check(&global.value);
+        validator.expect_ty(global.value.span, &var.ty, &global.value.ty);
+    }
+
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.errors)
+    }
+}
+
+struct Validator<'a> {
+    program: &'a Program,
+    resolutions: &'a Resolutions,
+    typeck_results: &'a TypeckResults,
+    /// Whether the variables were bound already, anywhere in the program.
+    bound: Vec<bool>,
+    in_scope: Vec<bool>,
+    /// The constructors of the variables tested by the enclosing switches.
+    known_cons: HashMap<VarId, Con>,
+    global: String,
+    errors: Vec<ValidationError>,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&mut self, expr: &Expr) {
+        let found = match &expr.kind {
+            ExprKind::Var(var) => {
+                self.check_use(*var, expr);
+                None
+            }
+            ExprKind::Literal(literal) => Some(literal.ty()),
+            ExprKind::Lambda(parameters, body) => {
+                for parameter in parameters {
+                    self.bind(*parameter, expr.span);
+                }
+                self.check(body);
+                for parameter in parameters {
+                    self.unbind(*parameter);
+                }
+                Some(
+                    parameters
+                        .iter()
+                        .rev()
+                        .fold(body.ty.clone(), |result, parameter| {
+                            Ty::function(self.program.var(*parameter).ty.clone(), result)
+                        }),
+                )
+            }
+            ExprKind::Apply(function, arguments, tail_call) => {
+                if tail_call.required && !tail_call.is_tail {
+                    self.error(expr.span, "`@tailcall` application not in tail position");
+                }
+                self.check(function);
+                let mut ty = function.ty.clone();
+                for argument in arguments {
+                    self.check(argument);
+                    ty = match ty {
+                        Ty::Function(parameter, result) => {
+                            self.expect_ty(argument.span, &parameter, &argument.ty);
+                            *result
+                        }
+                        ty => {
+                            let message = format!("`{}` applied to an argument", self.print(&ty));
+                            self.error(expr.span, message);
+                            return;
+                        }
+                    };
+                }
+                Some(ty)
+            }
+            ExprKind::Prim(op, arguments) => {
+                for argument in arguments {
+                    self.check(argument);
+                }
+                self.check_prim(*op, arguments, expr)
+            }
+            ExprKind::Let(var, value, body) => {
+                self.check(value);
+                self.expect_ty(value.span, &self.program.var(*var).ty, &value.ty);
+                self.bind(*var, expr.span);
+                self.check(body);
+                self.unbind(*var);
+                Some(body.ty.clone())
+            }
+            ExprKind::LetRec(bindings, body) => {
+                for (var, _) in bindings {
+                    self.bind(*var, expr.span);
+                }
+                for (var, value) in bindings {
+                    if !matches!(value.kind, ExprKind::Lambda(..)) {
+                        self.error(
+                            value.span,
+                            "a recursive binding of a value which is not a function",
+                        );
+                    }
+                    self.check(value);
+                    self.expect_ty(value.span, &self.program.var(*var).ty, &value.ty);
+                }
+                self.check(body);
+                for (var, _) in bindings {
+                    self.unbind(*var);
+                }
+                Some(body.ty.clone())
+            }
+            ExprKind::Construct(con, fields) => {
+                for field in fields {
+                    self.check(field);
+                }
+                match self.field_tys(*con, &expr.ty, expr.span) {
+                    Some(tys) if tys.len() == fields.len() => {
+                        for (ty, field) in tys.iter().zip(fields) {
+                            self.expect_ty(field.span, ty, &field.ty);
+                        }
+                    }
+                    Some(tys) => {
+                        let message = format!(
+                            "`{:?}` built of {} fields instead of {}",
+                            con,
+                            fields.len(),
+                            tys.len()
+                        );
+                        self.error(expr.span, message);
+                    }
+                    None => {}
+                }
+                None
+            }
+            ExprKind::Field(value, con, index) => {
+                self.check(value);
+                if !con.is_irrefutable() {
+                    let known = match value.kind {
+                        ExprKind::Var(var) => self.known_cons.get(&var) == Some(con),
+                        _ => false,
+                    };
+                    if !known {
+                        let message = format!(
+                            "a field of `{:?}` read without testing the constructor",
+                            con
+                        );
+                        self.error(expr.span, message);
+                    }
+                }
+                match self.field_tys(*con, &value.ty, expr.span) {
+                    Some(tys) if *index < tys.len() => Some(tys[*index].clone()),
+                    Some(_) => {
+                        self.error(expr.span, format!("`{:?}` has no field {}", con, index));
+                        None
+                    }
+                    None => None,
+                }
+            }
+            ExprKind::Switch(var, cases, default) => {
+                self.check_in_scope(*var, expr.span);
+                let ty = self.program.var(*var).ty.clone();
+                let mut tests = Vec::new();
+                for (test, body) in cases {
+                    if tests.contains(&test) {
+                        self.error(body.span, "the test is repeated in the switch");
+                    }
+                    tests.push(test);
+                    let previous = match test {
+                        Test::Con(con) => {
+                            let _ = self.field_tys(*con, &ty, body.span);
+                            self.known_cons.insert(*var, *con)
+                        }
+                        Test::Literal(literal) => {
+                            self.expect_ty(body.span, &ty, &literal.ty());
+                            self.known_cons.remove(var)
+                        }
+                    };
+                    self.check(body);
+                    match previous {
+                        Some(previous) => self.known_cons.insert(*var, previous),
+                        None => self.known_cons.remove(var),
+                    };
+                    self.expect_ty(body.span, &expr.ty, &body.ty);
+                }
+                match default {
+                    Some(default) => {
+                        self.check(default);
+                        self.expect_ty(default.span, &expr.ty, &default.ty);
+                    }
+                    None if !self.is_exhaustive(&ty, &tests) => {
+                        let message = format!(
+                            "the switch without a default does not cover all the values of `{}`",
+                            self.print(&ty)
+                        );
+                        self.error(expr.span, message);
+                    }
+                    None => {}
+                }
+                None
+            }
+            ExprKind::If(condition, then_branch, else_branch) => {
+                self.check(condition);
+                self.expect_ty(condition.span, &Ty::Prim(PrimTy::Bool), &condition.ty);
+                self.check(then_branch);
+                self.check(else_branch);
+                self.expect_ty(else_branch.span, &then_branch.ty, &else_branch.ty);
+                Some(then_branch.ty.clone())
+            }
+            ExprKind::While(condition, body) => {
+                self.check(condition);
+                self.expect_ty(condition.span, &Ty::Prim(PrimTy::Bool), &condition.ty);
+                self.check(body);
+                self.expect_ty(body.span, &Ty::Prim(PrimTy::Unit), &body.ty);
+                Some(Ty::Prim(PrimTy::Unit))
+            }
+            ExprKind::For(var, start, _, end, body) => {
+                let int = Ty::Prim(PrimTy::Int);
+                for bound in &[start, end] {
+                    self.check(bound);
+                    self.expect_ty(bound.span, &int, &bound.ty);
+                }
+                self.expect_ty(expr.span, &int, &self.program.var(*var).ty);
+                self.bind(*var, expr.span);
+                self.check(body);
+                self.unbind(*var);
+                self.expect_ty(body.span, &Ty::Prim(PrimTy::Unit), &body.ty);
+                Some(Ty::Prim(PrimTy::Unit))
+            }
+            ExprKind::Try(body, var, handler) => {
+                self.check(body);
+                let exn = Ty::Prim(PrimTy::Exn);
+                self.expect_ty(expr.span, &exn, &self.program.var(*var).ty);
+                self.bind(*var, expr.span);
+                self.check(handler);
+                self.unbind(*var);
+                self.expect_ty(handler.span, &body.ty, &handler.ty);
+                Some(body.ty.clone())
+            }
+        };
+        if let Some(found) = found {
+            self.expect_ty(expr.span, &expr.ty, &found);
+        }
+    }
+
+    /// Checks that the variable is in scope, and used at an instance of its
+    /// type.
+    fn check_use(&mut self, var: VarId, expr: &Expr) {
+        if !self.check_in_scope(var, expr.span) {
+            return;
+        }
+        let info = self.program.var(var);
+        if !is_instance(&info.ty, &expr.ty, &info.generics, &mut HashMap::new()) {
+            let message = format!(
+                "`{}` of type `{}` used at type `{}`",
+                self.var_name(var),
+                self.print(&info.ty),
+                self.print(&expr.ty)
+            );
+            self.error(expr.span, message);
+        }
+    }
+
+    fn check_in_scope(&mut self, var: VarId, span: SourceSpan) -> bool {
+        let in_scope = self.in_scope[var.as_usize()];
+        if !in_scope {
+            let message = format!("`{}` is used out of its scope", self.var_name(var));
+            self.error(span, message);
+        }
+        in_scope
+    }
+
+    /// Gets the type of the primitive operation applied to the arguments.
+    fn check_prim(&mut self, op: PrimOp, arguments: &[Expr], expr: &Expr) -> Option<Ty> {
+        if arguments.len() != op.arity() {
+            let message = format!(
+                "`{:?}` applied to {} arguments instead of {}",
+                op,
+                arguments.len(),
+                op.arity()
+            );
+            self.error(expr.span, message);
+            return None;
+        }
+        let int = || Ty::Prim(PrimTy::Int);
+        let bool = || Ty::Prim(PrimTy::Bool);
+        let string = || Ty::Prim(PrimTy::String);
+        let unit = || Ty::Prim(PrimTy::Unit);
+        let first = arguments[0].ty.clone();
+        let (parameters, result) = match op {
+            PrimOp::Add | PrimOp::Subtract | PrimOp::Multiply | PrimOp::Divide => {
+                (vec![int(), int()], int())
+            }
+            PrimOp::Negate => (vec![int()], int()),
+            PrimOp::Less | PrimOp::LessEqual | PrimOp::Greater | PrimOp::GreaterEqual => {
+                (vec![int(), int()], bool())
+            }
+            PrimOp::Equal | PrimOp::NotEqual => (vec![first.clone(), first], bool()),
+            PrimOp::Deref => match &first {
+                Ty::Ref(value) => (vec![first.clone()], value.as_ref().clone()),
+                _ => (vec![Ty::Ref(Box::new(expr.ty.clone()))], expr.ty.clone()),
+            },
+            PrimOp::Assign => match &first {
+                Ty::Ref(value) => (vec![first.clone(), value.as_ref().clone()], unit()),
+                _ => {
+                    let value = arguments[1].ty.clone();
+                    (vec![Ty::Ref(Box::new(value.clone())), value], unit())
+                }
+            },
+            PrimOp::Builtin(builtin) => match builtin {
+                Builtin::Ref => (vec![first.clone()], Ty::Ref(Box::new(first))),
+                Builtin::Raise => (vec![Ty::Prim(PrimTy::Exn)], expr.ty.clone()),
+                Builtin::Compare => (vec![first.clone(), first], int()),
+                Builtin::StringLength => (vec![string()], int()),
+                Builtin::StringGet => (vec![string(), int()], int()),
+                Builtin::StringSub => (vec![string(), int(), int()], string()),
+                Builtin::StringConcat => (vec![string(), string()], string()),
+                Builtin::StringConcatList => (vec![Ty::List(Box::new(string()))], string()),
+                Builtin::StringOfCharCode | Builtin::StringOfInt => (vec![int()], string()),
+                Builtin::IntOfString => (vec![string()], int()),
+                Builtin::PrintString | Builtin::EprintString => (vec![string()], unit()),
+                Builtin::ReadLine => (vec![unit()], string()),
+                Builtin::ReadFile => (vec![string()], string()),
+                Builtin::WriteFile => (vec![string(), string()], unit()),
+                Builtin::Sprintf
+                | Builtin::DivisionByZero
+                | Builtin::MatchFailure
+                | Builtin::StackOverflow
+                | Builtin::Failure
+                | Builtin::InvalidArgument
+                | Builtin::NotFound
+                | Builtin::EndOfFile => {
+                    let message = format!("`{}` is not a primitive operation", builtin.name());
+                    self.error(expr.span, message);
+                    return None;
+                }
+            },
+        };
+        for (parameter, argument) in parameters.iter().zip(arguments) {
+            self.expect_ty(argument.span, parameter, &argument.ty);
+        }
+        Some(result)
+    }
+
+    fn field_tys(&mut self, con: Con, ty: &Ty, span: SourceSpan) -> Option<Vec<Ty>> {
+        let tys = con_field_tys(self.resolutions, self.typeck_results, con, ty);
+        if tys.is_none() {
+            let message = format!(
+                "`{:?}` does not build values of type `{}`",
+                con,
+                self.print(ty)
+            );
+            self.error(span, message);
+        }
+        tys
+    }
+
+    fn is_exhaustive(&self, ty: &Ty, tests: &[&Test]) -> bool {
+        match ty {
+            Ty::Prim(PrimTy::Bool) => [true, false]
+                .iter()
+                .all(|value| tests.contains(&&Test::Literal(Literal::Bool(*value)))),
+            ty => all_cons(self.typeck_results, ty)
+                .is_some_and(|cons| cons.into_iter().all(|con| tests.contains(&&Test::Con(con)))),
+        }
+    }
+
+    fn bind(&mut self, var: VarId, span: SourceSpan) {
+        if self.bound[var.as_usize()] {
+            let message = format!("`{}` is bound more than once", self.var_name(var));
+            self.error(span, message);
+        }
+        self.bound[var.as_usize()] = true;
+        self.in_scope[var.as_usize()] = true;
+    }
+
+    fn unbind(&mut self, var: VarId) {
+        self.in_scope[var.as_usize()] = false;
+    }
+
+    fn expect_ty(&mut self, span: SourceSpan, expected: &Ty, found: &Ty) {
+        if expected != found {
+            let mut printer = TyPrinter::new(self.resolutions);
+            let message = format!(
+                "mismatched types: expected `{}`, found `{}`",
+                printer.print(expected),
+                printer.print(found)
+            );
+            self.error(span, message);
+        }
+    }
+
+    fn error(&mut self, span: SourceSpan, message: impl Into<String>) {
+        self.errors.push(ValidationError {
+            global: self.global.clone(),
+            span,
+            message: message.into(),
+        });
+    }
+
+    fn var_name(&self, var: VarId) -> String {
+        format!("{}/{}", self.program.var(var).name, var.as_usize())
+    }
+
+    fn print(&self, ty: &Ty) -> String {
+        TyPrinter::new(self.resolutions).print(ty)
+    }
+}
+
+/// Checks whether the type is an instance of the type generalized over the
+/// variables, extending the substitution of the variables.
+fn is_instance(generic: &Ty, ty: &Ty, generics: &[TyVid], subst: &mut HashMap<TyVid, Ty>) -> bool {
+    match (generic, ty) {
+        (Ty::Var(vid), ty) if generics.contains(vid) => match subst.get(vid) {
+            Some(bound) => bound == ty,
+            None => {
+                subst.insert(*vid, ty.clone());
+                true
+            }
+        },
+        (Ty::Adt(generic_def, generics_args), Ty::Adt(def, args)) => {
+            generic_def == def
+                && generics_args.len() == args.len()
+                && generics_args
+                    .iter()
+                    .zip(args)
+                    .all(|(g, t)| is_instance(g, t, generics, subst))
+        }
+        (Ty::Tuple(generic_elements), Ty::Tuple(elements)) => {
+            generic_elements.len() == elements.len()
+                && generic_elements
+                    .iter()
+                    .zip(elements)
+                    .all(|(g, t)| is_instance(g, t, generics, subst))
+        }
+        (Ty::List(generic), Ty::List(ty))
+        | (Ty::Array(generic), Ty::Array(ty))
+        | (Ty::Ref(generic), Ty::Ref(ty)) => is_instance(generic, ty, generics, subst),
+        (Ty::Function(generic_parameter, generic_result), Ty::Function(parameter, result)) => {
+            is_instance(generic_parameter, parameter, generics, subst)
+                && is_instance(generic_result, result, generics, subst)
+        }
+        (generic, ty) => generic == ty,
+    }
+}
@@ -0,0 +1,33 @@
+#![feature(peekable_next_if)]
+
+//! The compiler of the Brink language. The `brinkc` binary drives the stages
+//! from the command line, and the language server reuses the frontend and the
+//! query database.
+
+pub mod anf;
+pub mod ast;
+pub mod bytecode;
+pub mod codegen;
+pub mod fmt;
+pub mod frontend;
+pub mod highlight;
+pub mod interpret;
+pub mod ir;
+pub mod opt;
+pub mod query;
+pub mod resolve;
+pub mod source_file;
+pub mod typeck;
+pub mod vm;
+
+#[cfg(test)]
+mod test_util;
+
+/// The stack size of the threads the interpreter and the virtual machine
+/// run the programs on, as their calls nest on the native stack.
+pub const INTERPRETER_STACK_SIZE: usize = 1 << 30;
+
+/// The maximum number of active calls of the interpreter and the virtual
+/// machine, above which `StackOverflow` is raised. The calls in tail
+/// position do not count, since they replace their callers.
+pub const MAX_CALL_DEPTH: usize = 100_000;
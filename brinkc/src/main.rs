@@ -1,59 +1,616 @@
-#![feature(peekable_next_if)]
+use std::{io::Write, path::Path, rc::Rc, time::Instant};
 
-use std::time::Instant;
+use brinkc::{
+    anf, bytecode, codegen,
+    fmt::{self, FormatError},
+    frontend::{self, parse_session::ParseSession},
+    highlight, interpret, ir, opt, query, resolve,
+    resolve::ModuleSource,
+    source_file::SourceMap,
+    typeck, vm, INTERPRETER_STACK_SIZE,
+};
 
-mod ast;
-mod frontend;
-mod source_file;
+/// The intermediate stages the compiler can print instead of running the
+/// program.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Emit {
+    /// The core IR, `--emit=ir`.
+    Ir,
+    /// The optimized mid-level IR, `--emit=anf`.
+    Anf,
+    /// The C source of the program and the runtime, `--emit=c`.
+    C,
+    /// The bytecode of the virtual machine, `--emit=bytecode`, written to
+    /// a `.bkc` file.
+    Bytecode,
+}
 
-use frontend::{
-    find_mixed_and_invalid_indentations, lexer::Lexer, parse_session::ParseSession, parser::Parser,
-};
-use source_file::SourceFile;
+/// The machine `brinkc build` compiles the program for, chosen by
+/// `--target`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Target {
+    /// The host, with the native backend.
+    Native,
+    /// WebAssembly, `--target=wasm32`, written to a `.wasm` module.
+    Wasm32,
+}
 
-fn main() {
-    println!("brink compiler v{}", env!("CARGO_PKG_VERSION"));
-    println!();
+/// What the compiler does with the program, chosen by the subcommand.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Command {
+    /// Runs the program with the interpreter, or emits the stage of
+    /// `--emit`.
+    Interpret,
+    /// Builds the executable or the module of the target, `brinkc build`,
+    /// from `-o` or named after the input file.
+    Build(String, Target),
+    /// Runs the program with the virtual machine, `brinkc run`.
+    Run,
+    /// Prints the bytecode of the program, `brinkc disasm`.
+    Disasm,
+    /// Formats the file in place, `brinkc fmt`, or only checks that it is
+    /// formatted with `--check`.
+    Format(fmt::Config, bool),
+    /// Prints the file highlighted by the lexer, `brinkc highlight`, as an
+    /// HTML page with `--html` and with the terminal colors otherwise.
+    Highlight(bool),
+    /// Prints the TextMate grammar of the language, `brinkc grammar`.
+    Grammar,
+}
 
-    let start_time = Instant::now();
+struct Options {
+    input: String,
+    command: Command,
+    emit: Option<Emit>,
+    /// The file to write the emitted stage to, from `-o`, instead of the
+    /// standard output.
+    output: Option<String>,
+    /// The optimization passes, from `-C opt-level` and `--passes`.
+    pipeline: Vec<opt::Pass>,
+    /// Whether the native code is optimized, with an optimization level
+    /// above zero.
+    optimize: bool,
+}
 
-    let args = std::env::args().collect::<Vec<String>>();
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut input = None;
+        let mut emit = None;
+        let mut opt_level = 0;
+        let mut passes = None;
+        let mut output = None;
+        let mut target = None;
+        let mut format_config = fmt::Config::default();
+        let mut check = false;
+        let mut html = false;
+        let mut args = args.iter().peekable();
+        let subcommand = args
+            .next_if(|arg| {
+                ["build", "run", "disasm", "fmt", "highlight", "grammar"].contains(&arg.as_str())
+            })
+            .map(String::as_str);
+        while let Some(arg) = args.next() {
+            if let Some(path) = arg.strip_prefix("-o") {
+                output = Some(match path {
+                    "" => args.next().ok_or("missing output file after `-o`")?.clone(),
+                    path => path.to_string(),
+                });
+            } else if let Some(stage) = arg.strip_prefix("--emit=") {
+                emit = Some(match stage {
+                    "ir" => Emit::Ir,
+                    "anf" => Emit::Anf,
+                    "c" => Emit::C,
+                    "bytecode" => Emit::Bytecode,
+                    _ => return Err(format!("unknown stage `{}` to emit", stage)),
+                });
+            } else if let Some(name) = arg.strip_prefix("--target=") {
+                target = Some(match name {
+                    "wasm32" => Target::Wasm32,
+                    _ => return Err(format!("unknown target `{}`", name)),
+                });
+            } else if subcommand == Some("fmt") && arg == "--check" {
+                check = true;
+            } else if subcommand == Some("highlight") && arg == "--html" {
+                html = true;
+            } else if let Some(width) = arg
+                .strip_prefix("--width=")
+                .filter(|_| subcommand == Some("fmt"))
+            {
+                format_config.width = width
+                    .parse()
+                    .map_err(|_| format!("invalid line width `{}`", width))?;
+            } else if let Some(indent) = arg
+                .strip_prefix("--indent=")
+                .filter(|_| subcommand == Some("fmt"))
+            {
+                format_config.indent = match indent.parse() {
+                    Ok(indent) if indent > 0 => indent,
+                    _ => return Err(format!("invalid indentation `{}`", indent)),
+                };
+            } else if let Some(list) = arg.strip_prefix("--passes=") {
+                passes = Some(list.to_string());
+            } else if let Some(codegen) = arg.strip_prefix("-C") {
+                let codegen = match codegen {
+                    "" => args.next().ok_or("missing codegen option after `-C`")?,
+                    codegen => codegen,
+                };
+                let level = codegen
+                    .strip_prefix("opt-level=")
+                    .ok_or_else(|| format!("unknown codegen option `{}`", codegen))?;
+                opt_level = match level.parse() {
+                    Ok(level) if level <= opt::MAX_OPT_LEVEL => level,
+                    _ => {
+                        return Err(format!(
+                            "invalid optimization level `{}`, expected 0 to {}",
+                            level,
+                            opt::MAX_OPT_LEVEL
+                        ))
+                    }
+                };
+            } else if arg.starts_with('-') {
+                return Err(format!("unknown option `{}`", arg));
+            } else if input.replace(arg.clone()).is_some() {
+                return Err("more than one input file given".to_string());
+            }
+        }
+        // The grammar is the same for every file.
+        if subcommand == Some("grammar") {
+            if let Some(input) = input {
+                return Err(format!(
+                    "`brinkc grammar` takes no input file, but got `{}`",
+                    input
+                ));
+            }
+            input = Some(String::new());
+        }
+        let input = input.ok_or_else(|| {
+            "usage: brinkc [--emit=ir|anf|c|bytecode] [-o OUTPUT] [-C opt-level=N] [--passes=LIST] <file>\n       \
+             brinkc build [--target=wasm32] [-o OUTPUT] [-C opt-level=N] [--passes=LIST] <file>\n       \
+             brinkc run|disasm [-C opt-level=N] [--passes=LIST] <file.bk|file.bkc>\n       \
+             brinkc fmt [--check] [--width=N] [--indent=N] <file>\n       \
+             brinkc highlight [--html] [-o OUTPUT] <file>\n       \
+             brinkc grammar [-o OUTPUT]"
+                .to_string()
+        })?;
+        let stem = || {
+            Path::new(&input)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        };
+        let command = match subcommand {
+            Some("build") => {
+                let target = target.take().unwrap_or(Target::Native);
+                let output = output.take().unwrap_or_else(|| match target {
+                    Target::Native => stem().unwrap_or_else(|| "a.out".to_string()),
+                    Target::Wasm32 => format!("{}.wasm", stem().unwrap_or_else(|| "a".to_string())),
+                });
+                Command::Build(output, target)
+            }
+            Some("run") => Command::Run,
+            Some("disasm") => Command::Disasm,
+            Some("fmt") => Command::Format(format_config, check),
+            Some("highlight") => Command::Highlight(html),
+            Some("grammar") => Command::Grammar,
+            _ => Command::Interpret,
+        };
+        if command != Command::Interpret && emit.is_some() {
+            return Err(format!(
+                "`--emit` cannot be used with `brinkc {}`",
+                subcommand.unwrap()
+            ));
+        }
+        if target.is_some() {
+            return Err("`--target` can only be used with `brinkc build`".to_string());
+        }
+        if emit.is_none()
+            && output.is_some()
+            && !matches!(command, Command::Highlight(_) | Command::Grammar)
+        {
+            return Err("`-o` can only be used with `--emit` or `brinkc build`".to_string());
+        }
+        if matches!(command, Command::Format(..)) && (output.is_some() || passes.is_some()) {
+            return Err("`brinkc fmt` only takes `--check`, `--width` and `--indent`".to_string());
+        }
+        if is_bytecode_file(&input) && !matches!(command, Command::Run | Command::Disasm) {
+            return Err(format!(
+                "`{}` is a bytecode file, which only `brinkc run` and `brinkc disasm` take",
+                input
+            ));
+        }
+        // The bytecode is cached in a file rather than printed.
+        if emit == Some(Emit::Bytecode) && output.is_none() {
+            output = Some(format!("{}.bkc", stem().unwrap_or_else(|| "a".to_string())));
+        }
+        let pipeline = match passes {
+            Some(passes) => opt::parse_pipeline(opt_level, &passes)?,
+            None => opt::default_pipeline(opt_level),
+        };
+        Ok(Options {
+            input,
+            command,
+            emit,
+            output,
+            pipeline,
+            optimize: opt_level > 0,
+        })
+    }
+
+    /// Whether the standard output carries what the command emits, e.g. in
+    /// `brinkc --emit=c fib.bk > fib.c`, rather than the program's output.
+    fn writes_to_stdout(&self) -> bool {
+        match self.command {
+            Command::Interpret => self.emit.is_some() && self.output.is_none(),
+            Command::Disasm => true,
+            Command::Highlight(_) | Command::Grammar => self.output.is_none(),
+            _ => false,
+        }
+    }
+}
+
+/// Prints the banner and the summary of the compilation, to the standard
+/// error when the standard output carries the emitted code.
+struct Status {
+    start_time: Instant,
+    to_stderr: bool,
+}
 
-    let source_file_path = &args[1];
-    let source_file = match SourceFile::read(source_file_path.clone()) {
-        Ok(f) => f,
+impl Status {
+    fn print(&self, line: &str) {
+        if self.to_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+fn main() {
+    let start_time = Instant::now();
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
         Err(e) => {
             eprintln!("{}", e);
             std::process::exit(1);
         }
     };
+    let status = Status {
+        start_time,
+        to_stderr: options.writes_to_stdout(),
+    };
+    status.print(&format!("brink compiler v{}\n", env!("CARGO_PKG_VERSION")));
+
+    match &options.command {
+        Command::Format(config, check) => {
+            format_file(&options.input, config, *check, &status);
+            return;
+        }
+        Command::Highlight(html) => {
+            let mut source_map = SourceMap::new();
+            let file = match source_map.load_file(options.input.clone()) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            let source_file = source_map.file(file);
+            let highlighted = if *html {
+                highlight::to_html(source_file)
+            } else {
+                highlight::to_ansi(source_file)
+            };
+            write_output(options.output.as_deref(), highlighted.as_bytes());
+            return;
+        }
+        Command::Grammar => {
+            let grammar = highlight::textmate_grammar();
+            write_output(options.output.as_deref(), grammar.as_bytes());
+            return;
+        }
+        _ => {}
+    }
 
-    let tokens = Lexer::tokenize(&source_file);
+    if is_bytecode_file(&options.input) {
+        let module = std::fs::read(&options.input)
+            .map_err(|e| format!("could not read `{}`: {}", options.input, e))
+            .and_then(|bytes| {
+                bytecode::deserialize(&bytes)
+                    .map_err(|e| format!("invalid bytecode file `{}`: {}", options.input, e))
+            });
+        match module {
+            Ok(module) if options.command == Command::Run => run_bytecode(module),
+            Ok(module) => print!("{}", bytecode::disassemble(&module)),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let mut parse_session = ParseSession::new(source_file);
+    // The stages are queries of the database, each stopping the compilation
+    // if it reports errors.
+    let database = query::Database::new(ParseSession::new(SourceMap::new()), &options.input);
+    let module_graph = match database.checked_graph() {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    database.emit(database.graph_diagnostics());
+    if database.session().has_errors() {
+        terminate_compilation(&status, &database.session(), 1);
+    }
 
-    find_mixed_and_invalid_indentations(&mut parse_session, &tokens);
-    if parse_session.has_errors() {
-        terminate_compilation(start_time, &parse_session, 1);
+    let resolutions = database.resolutions();
+    database.emit(database.resolve_diagnostics());
+    if database.session().has_errors() {
+        terminate_compilation(&status, &database.session(), 1);
     }
 
-    let abstract_syntax_tree = Parser::parse(&parse_session, tokens);
+    let typeck_results = database.typeck();
+    database.emit(database.typeck_diagnostics());
+    if database.session().has_errors() {
+        terminate_compilation(&status, &database.session(), 1);
+    }
+    let parse_session = database.into_session();
+    let typeck_results = Rc::try_unwrap(typeck_results).unwrap_or_else(|rc| (*rc).clone());
+
+    if options.emit.is_some() || options.command != Command::Interpret {
+        let program = ir::lower(
+            &parse_session.source_map,
+            &module_graph,
+            &resolutions,
+            &typeck_results,
+        );
+        if let Err(errors) = ir::validate(&program, &resolutions, &typeck_results) {
+            for error in errors {
+                eprintln!(
+                    "internal compiler error: invalid IR in `{}` at {}: {}",
+                    error.global,
+                    parse_session.source_map.span_to_location(error.span),
+                    error.message
+                );
+            }
+            std::process::exit(3);
+        }
+        if options.emit == Some(Emit::Ir) {
+            let printed = ir::print_program(&program, &resolutions, &typeck_results);
+            write_output(options.output.as_deref(), printed.as_bytes());
+            print_summary(&status, &parse_session);
+            return;
+        }
+
+        let mut program = anf::convert(&program);
+        let result = anf::check(&program)
+            .map_err(|errors| ("the conversion", errors))
+            .and_then(|()| {
+                opt::optimize(&mut program, &options.pipeline)
+                    .map_err(|error| (error.pass.name(), error.errors))
+            });
+        if let Err((pass, errors)) = result {
+            for error in errors {
+                eprintln!(
+                    "internal compiler error: invalid mid-level IR after `{}` in `{}` at {}: {}",
+                    pass,
+                    error.global,
+                    parse_session.source_map.span_to_location(error.span),
+                    error.message
+                );
+            }
+            std::process::exit(3);
+        }
+        let compile_bytecode =
+            || bytecode::compile(&program, &resolutions, &parse_session.source_map);
+        match &options.command {
+            Command::Build(output, Target::Wasm32) => {
+                write_output(Some(output), &codegen::emit_wasm(&program, &resolutions));
+            }
+            Command::Build(output, Target::Native) => {
+                if let Err(e) = build(&program, &resolutions, options.optimize, Path::new(output)) {
+                    eprintln!("error: {}", e);
+                    terminate_compilation(&status, &parse_session, 1);
+                }
+            }
+            Command::Run => {
+                let module = compile_bytecode();
+                print_summary(&status, &parse_session);
+                run_bytecode(module);
+                return;
+            }
+            Command::Disasm => print!("{}", bytecode::disassemble(&compile_bytecode())),
+            Command::Format(..) | Command::Highlight(_) | Command::Grammar => {
+                unreachable!("the command does not compile the program")
+            }
+            Command::Interpret => {
+                let emitted = match options.emit {
+                    Some(Emit::C) => codegen::emit_c(&program, &resolutions).into_bytes(),
+                    Some(Emit::Bytecode) => bytecode::serialize(&compile_bytecode()),
+                    _ => anf::print_program(&program, &resolutions, &typeck_results).into_bytes(),
+                };
+                write_output(options.output.as_deref(), &emitted);
+            }
+        }
+        print_summary(&status, &parse_session);
+        return;
+    }
 
     #[cfg(debug_assertions)]
     {
-        println!("{:#?}", abstract_syntax_tree);
+        for module in &module_graph.modules {
+            println!("{:#?}", module.program);
+        }
+        println!("{:#?}", resolutions.paths);
         println!();
+        for (def, scheme) in &typeck_results.def_schemes {
+            let mut printer = typeck::ty::TyPrinter::new(&resolutions);
+            println!(
+                "{} : {}",
+                resolutions.def(*def).name,
+                printer.print(&scheme.ty)
+            );
+        }
+        println!();
+    }
+
+    print_summary(&status, &parse_session);
+
+    // The interpreter recurses on every call, so it needs a deep stack.
+    let result = std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || {
+            interpret::interpret(
+                &parse_session.source_map,
+                &module_graph,
+                &resolutions,
+                &typeck_results,
+            )
+            .err()
+            .map(|exception| exception.report(&parse_session.source_map))
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    if let Some(report) = result {
+        eprintln!("{}", report);
+        std::process::exit(2);
+    }
+}
+
+/// Formats the file in place, or with `--check` reports the first line
+/// which is not formatted and fails.
+fn format_file(input: &str, config: &fmt::Config, check: bool, status: &Status) {
+    let mut session = ParseSession::new(SourceMap::new());
+    let prelude = ModuleSource::Prelude;
+    let file = session.source_map.add_file(
+        prelude.path().display().to_string(),
+        prelude.embedded_source().unwrap().to_string(),
+    );
+    let _ = frontend::parse_prelude(&mut session, file);
+    let file = match session.source_map.load_file(input.to_string()) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let formatted = match fmt::format(&mut session, file, config) {
+        Ok(formatted) => formatted,
+        Err(FormatError::Syntax) => {
+            terminate_compilation(status, &session, 1);
+            return;
+        }
+        Err(FormatError::Unstable(message)) => {
+            eprintln!(
+                "internal compiler error: formatting `{}` would change the program: {}",
+                input, message
+            );
+            std::process::exit(3);
+        }
+    };
+    let source = &session.source_map.file(file).source_code;
+    if formatted != *source {
+        if check {
+            let mut lines = source.lines().zip(formatted.lines());
+            let line = lines
+                .position(|(line, formatted)| line != formatted)
+                .unwrap_or_else(|| source.lines().count().min(formatted.lines().count()));
+            eprintln!(
+                "error: `{}` is not formatted, from line {}",
+                input,
+                line + 1
+            );
+            eprintln!("- {}", source.lines().nth(line).unwrap_or(""));
+            eprintln!("+ {}", formatted.lines().nth(line).unwrap_or(""));
+            terminate_compilation(status, &session, 1);
+        }
+        write_output(Some(input), formatted.as_bytes());
+    }
+    print_summary(status, &session);
+}
+
+/// Writes the emitted stage to the file, or to the standard output.
+fn write_output(path: Option<&str>, contents: &[u8]) {
+    match path {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, contents) {
+                eprintln!("error: could not write `{}`: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            let _ = std::io::stdout().write_all(contents);
+        }
+    }
+}
+
+fn is_bytecode_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension == "bkc")
+}
+
+/// Runs the bytecode with the virtual machine, which recurses when it
+/// compares or shows the values, so it also runs on a deep stack.
+fn run_bytecode(module: bytecode::Module) {
+    let result = std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || vm::run(&module).err().map(|exception| exception.report()))
+        .unwrap()
+        .join()
+        .unwrap();
+    if let Some(report) = result {
+        eprintln!("{}", report);
+        std::process::exit(2);
     }
+}
+
+/// Compiles the program to native code and links it into the executable.
+#[cfg(feature = "cranelift")]
+fn build(
+    program: &anf::Program,
+    resolutions: &resolve::Resolutions,
+    optimize: bool,
+    output: &Path,
+) -> Result<(), String> {
+    let object = codegen::compile(program, resolutions, optimize)?;
+    let directory = std::env::temp_dir().join(format!("brink-{}", std::process::id()));
+    std::fs::create_dir_all(&directory)
+        .map_err(|e| format!("could not create `{}`: {}", directory.display(), e))?;
+    let path = directory.join("main.o");
+    let result = std::fs::write(&path, object)
+        .map_err(|e| format!("could not write `{}`: {}", path.display(), e))
+        .and_then(|()| codegen::link(&[path], output));
+    let _ = std::fs::remove_dir_all(&directory);
+    result
+}
 
-    terminate_compilation(start_time, &parse_session, 0);
+#[cfg(not(feature = "cranelift"))]
+fn build(
+    _program: &anf::Program,
+    _resolutions: &resolve::Resolutions,
+    _optimize: bool,
+    _output: &Path,
+) -> Result<(), String> {
+    Err(
+        "`brinkc build` needs the native backend, rebuild brinkc with `--features cranelift`"
+            .to_string(),
+    )
 }
 
-fn terminate_compilation(start_time: Instant, session: &ParseSession, exit_code: i32) {
-    println!(
+fn terminate_compilation(status: &Status, session: &ParseSession, exit_code: i32) {
+    print_summary(status, session);
+    std::process::exit(exit_code);
+}
+
+fn print_summary(status: &Status, session: &ParseSession) {
+    status.print(&format!(
         "compilation finished with {} errors and {} warnings in {:.6}s",
         session.error_count(),
         session.warning_count(),
-        start_time.elapsed().as_secs_f32()
-    );
-    std::process::exit(exit_code);
+        status.start_time.elapsed().as_secs_f32()
+    ));
 }
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use crate::{
+    anf::{self, Atom, Block, Function, GlobalValue, Program, Stmt, Value},
+    ir::VarId,
+};
+
+use super::Subst;
+
+/// Substitutes the variables bound to operands and reduces the
+/// applications of the functions used nowhere else. Such a function is
+/// moved to the call instead of being copied, so its variables stay bound
+/// once.
+pub fn run(program: &mut Program) {
+    let counts = anf::use_counts(program);
+    let mut reducer = Reducer {
+        counts,
+        calls: HashMap::new(),
+        subst: Subst::default(),
+        pending: HashMap::new(),
+    };
+    for global in &program.globals {
+        let mut count_calls = |block: &Block| reducer.count_calls(block);
+        match &global.value {
+            GlobalValue::Function(function) => count_calls(&function.body),
+            GlobalValue::Block(block) => count_calls(block),
+        }
+    }
+    for global in &mut program.globals {
+        match &mut global.value {
+            GlobalValue::Function(function) => reducer.function(function),
+            GlobalValue::Block(block) => reducer.block(block),
+        }
+    }
+}
+
+struct Reducer {
+    counts: Vec<usize>,
+    /// The numbers of the arguments the variables are called with, if they
+    /// are called.
+    calls: HashMap<VarId, usize>,
+    subst: Subst,
+    /// The functions whose only use is a call yet to be reached.
+    pending: HashMap<VarId, Function>,
+}
+
+impl Reducer {
+    fn count_calls(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(_, value) => {
+                    match value {
+                        Value::Apply(Atom::Var(function), arguments) => {
+                            self.calls.insert(*function, arguments.len());
+                        }
+                        Value::Call(function, arguments) => {
+                            self.calls.insert(*function, arguments.len());
+                        }
+                        _ => {}
+                    }
+                    for block in value.blocks() {
+                        self.count_calls(block);
+                    }
+                }
+                Stmt::LetRec(functions) => {
+                    for (_, function) in functions {
+                        self.count_calls(&function.body);
+                    }
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, function: &mut Function) {
+        self.block(&mut function.body);
+    }
+
+    fn block(&mut self, block: &mut Block) {
+        let mut stmts = Vec::new();
+        for stmt in std::mem::take(&mut block.stmts) {
+            self.stmt(stmt, &mut stmts);
+        }
+        block.stmts = stmts;
+        self.subst.atom(&mut block.result);
+    }
+
+    fn stmt(&mut self, stmt: Stmt, stmts: &mut Vec<Stmt>) {
+        let (var, mut value) = match stmt {
+            Stmt::Let(var, value) => (var, value),
+            Stmt::LetRec(mut functions) => {
+                for (_, function) in &mut functions {
+                    self.function(function);
+                }
+                stmts.push(Stmt::LetRec(functions));
+                return;
+            }
+        };
+        self.subst.operands(&mut value);
+        match value {
+            Value::Atom(atom) => {
+                self.subst.insert(var, atom);
+                return;
+            }
+            Value::Lambda(function)
+                if self.counts[var.as_usize()] == 1
+                    && self.calls.get(&var) == Some(&function.parameters.len()) =>
+            {
+                self.pending.insert(var, function);
+                return;
+            }
+            Value::Apply(Atom::Var(function), arguments) | Value::Call(function, arguments)
+                if self.pending.contains_key(&function) =>
+            {
+                let function = self.pending.remove(&function).unwrap();
+                for (parameter, argument) in function.parameters.iter().zip(arguments) {
+                    self.subst.insert(*parameter, argument);
+                }
+                for stmt in function.body.stmts {
+                    self.stmt(stmt, stmts);
+                }
+                let mut result = function.body.result;
+                self.subst.atom(&mut result);
+                self.subst.insert(var, result);
+                return;
+            }
+            _ => {}
+        }
+        for block in value.blocks_mut() {
+            self.block(block);
+        }
+        stmts.push(Stmt::Let(var, value));
+    }
+}
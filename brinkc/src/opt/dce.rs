@@ -0,0 +1,166 @@
+use crate::{
+    anf::{self, Atom, Block, Function, GlobalValue, Program, Stmt, Value},
+    ir::{Literal, PrimOp, VarId},
+    resolve::Builtin,
+};
+
+/// Removes the bindings of the values without effects whose variables are
+/// not used, the recursive functions only used by themselves, and the
+/// global functions and values no longer used.
+pub fn run(program: &mut Program) {
+    let mut eliminator = Eliminator {
+        counts: anf::use_counts(program),
+    };
+    for global in &mut program.globals {
+        match &mut global.value {
+            GlobalValue::Function(function) => eliminator.block(&mut function.body),
+            GlobalValue::Block(block) => eliminator.block(block),
+        }
+    }
+    // The functions can use the globals defined after them, so the globals
+    // are removed until none is left to remove.
+    loop {
+        let count = program.globals.len();
+        let mut globals = std::mem::take(&mut program.globals);
+        globals.retain(|global| {
+            let dead = match &global.value {
+                GlobalValue::Function(function) => {
+                    eliminator.uses_from_outside(&[(global.var, function)]) == 0
+                }
+                GlobalValue::Block(block) => {
+                    eliminator.counts[global.var.as_usize()] == 0 && is_pure_block(block)
+                }
+            };
+            if dead {
+                match &global.value {
+                    GlobalValue::Function(function) => eliminator.release_block(&function.body),
+                    GlobalValue::Block(block) => eliminator.release_block(block),
+                }
+            }
+            !dead
+        });
+        program.globals = globals;
+        if program.globals.len() == count {
+            break;
+        }
+    }
+}
+
+struct Eliminator {
+    counts: Vec<usize>,
+}
+
+impl Eliminator {
+    /// Removes the dead bindings of the block from the last one, so the
+    /// uses by the removed bindings are not counted for the earlier ones.
+    fn block(&mut self, block: &mut Block) {
+        let mut stmts = Vec::new();
+        for stmt in std::mem::take(&mut block.stmts).into_iter().rev() {
+            match stmt {
+                Stmt::Let(var, mut value) => {
+                    if self.counts[var.as_usize()] == 0 && is_pure(&value) {
+                        value.for_each_use(&mut |var| self.counts[var.as_usize()] -= 1);
+                        continue;
+                    }
+                    for block in value.blocks_mut() {
+                        self.block(block);
+                    }
+                    stmts.push(Stmt::Let(var, value));
+                }
+                Stmt::LetRec(mut functions) => {
+                    let group = functions
+                        .iter()
+                        .map(|(var, function)| (*var, function))
+                        .collect::<Vec<_>>();
+                    if self.uses_from_outside(&group) == 0 {
+                        for (_, function) in &functions {
+                            self.release_block(&function.body);
+                        }
+                        continue;
+                    }
+                    for (_, function) in &mut functions {
+                        self.block(&mut function.body);
+                    }
+                    stmts.push(Stmt::LetRec(functions));
+                }
+            }
+        }
+        stmts.reverse();
+        block.stmts = stmts;
+    }
+
+    /// Counts the uses of the recursive functions other than by themselves.
+    fn uses_from_outside(&self, group: &[(VarId, &Function)]) -> usize {
+        let mut uses = group
+            .iter()
+            .map(|(var, _)| self.counts[var.as_usize()])
+            .sum::<usize>();
+        for (_, function) in group {
+            function.body.for_each_use(&mut |var| {
+                if group.iter().any(|(member, _)| *member == var) {
+                    uses -= 1;
+                }
+            });
+        }
+        uses
+    }
+
+    fn release_block(&mut self, block: &Block) {
+        block.for_each_use(&mut |var| self.counts[var.as_usize()] -= 1);
+    }
+}
+
+/// Checks whether the value can be computed or not without a difference:
+/// it cannot raise an exception, loop forever or have an effect. The
+/// allocations are not effects.
+fn is_pure(value: &Value) -> bool {
+    match value {
+        Value::Atom(_)
+        | Value::Lambda(_)
+        | Value::Box(_)
+        | Value::Unbox(_)
+        | Value::Construct(..)
+        | Value::Field(..) => true,
+        Value::Apply(..) | Value::Call(..) | Value::While(..) | Value::For(..) => false,
+        Value::Prim(op, arguments) => match op {
+            PrimOp::Add
+            | PrimOp::Subtract
+            | PrimOp::Multiply
+            | PrimOp::Negate
+            | PrimOp::Less
+            | PrimOp::LessEqual
+            | PrimOp::Greater
+            | PrimOp::GreaterEqual
+            | PrimOp::Equal
+            | PrimOp::NotEqual
+            | PrimOp::Deref => true,
+            PrimOp::Divide => {
+                !matches!(arguments[1], Atom::Var(_) | Atom::Literal(Literal::Int(0)))
+            }
+            PrimOp::Assign => false,
+            PrimOp::Builtin(builtin) => matches!(
+                builtin,
+                Builtin::Ref
+                    | Builtin::StringConcat
+                    | Builtin::StringConcatList
+                    | Builtin::StringLength
+                    | Builtin::StringOfInt
+            ),
+        },
+        Value::If(_, then_block, else_block) => {
+            is_pure_block(then_block) && is_pure_block(else_block)
+        }
+        Value::Switch(_, cases, default) => {
+            cases.iter().all(|(_, block)| is_pure_block(block)) && default.iter().all(is_pure_block)
+        }
+        // The handler runs only if the body raises an exception.
+        Value::Try(body, ..) => is_pure_block(body),
+    }
+}
+
+fn is_pure_block(block: &Block) -> bool {
+    block.stmts.iter().all(|stmt| match stmt {
+        Stmt::Let(_, value) => is_pure(value),
+        Stmt::LetRec(_) => true,
+    })
+}
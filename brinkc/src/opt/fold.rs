@@ -0,0 +1,221 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    anf::{Atom, Block, GlobalValue, Program, Stmt, Value},
+    ir::{Con, Literal, PrimOp, Test, VarId},
+    resolve::Builtin,
+};
+
+use super::Subst;
+
+/// Folds the operations on constants, the reads of the fields of the
+/// values built in sight, and the branches on known values. The variables
+/// bound to the results are substituted.
+pub fn run(program: &mut Program) {
+    let mut folder = Folder {
+        subst: Subst::default(),
+        known: HashMap::new(),
+    };
+    for global in &mut program.globals {
+        match &mut global.value {
+            GlobalValue::Function(function) => folder.block(&mut function.body),
+            GlobalValue::Block(block) => folder.block(block),
+        }
+    }
+}
+
+/// What is known of the value of a variable.
+#[derive(Clone)]
+enum Known {
+    Construct(Con, Vec<Atom>),
+    /// A boxed integer constant.
+    Int(i64),
+}
+
+struct Folder {
+    subst: Subst,
+    known: HashMap<VarId, Known>,
+}
+
+impl Folder {
+    fn block(&mut self, block: &mut Block) {
+        let mut stmts = Vec::new();
+        for stmt in std::mem::take(&mut block.stmts) {
+            self.stmt(stmt, &mut stmts);
+        }
+        block.stmts = stmts;
+        self.subst.atom(&mut block.result);
+    }
+
+    fn stmt(&mut self, stmt: Stmt, stmts: &mut Vec<Stmt>) {
+        let (var, mut value) = match stmt {
+            Stmt::Let(var, value) => (var, value),
+            Stmt::LetRec(mut functions) => {
+                for (_, function) in &mut functions {
+                    self.block(&mut function.body);
+                }
+                stmts.push(Stmt::LetRec(functions));
+                return;
+            }
+        };
+        self.subst.operands(&mut value);
+        if let Some(block) = self.branch(&mut value) {
+            for stmt in block.stmts {
+                self.stmt(stmt, stmts);
+            }
+            let mut result = block.result;
+            self.subst.atom(&mut result);
+            self.subst.insert(var, result);
+            return;
+        }
+        if let Some(folded) = self.fold(&value) {
+            value = folded;
+        }
+        match &value {
+            Value::Atom(atom) => {
+                self.subst.insert(var, atom.clone());
+                return;
+            }
+            Value::Construct(con, fields) => {
+                self.known
+                    .insert(var, Known::Construct(*con, fields.clone()));
+            }
+            Value::Box(Atom::Literal(Literal::Int(n))) => {
+                self.known.insert(var, Known::Int(*n));
+            }
+            _ => {}
+        }
+        for block in value.blocks_mut() {
+            self.block(block);
+        }
+        stmts.push(Stmt::Let(var, value));
+    }
+
+    fn int(&self, atom: &Atom) -> Option<i64> {
+        match atom {
+            Atom::Literal(Literal::Int(n)) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Gets the constant the operand is known to be, the integers boxed or
+    /// not.
+    fn constant(&self, atom: &Atom) -> Option<Literal> {
+        match atom {
+            Atom::Literal(literal) => Some(literal.clone()),
+            Atom::Var(var) => match self.known.get(var)? {
+                Known::Int(n) => Some(Literal::Int(*n)),
+                Known::Construct(..) => None,
+            },
+        }
+    }
+
+    /// Takes the block the branching value evaluates if it is known.
+    fn branch(&mut self, value: &mut Value) -> Option<Block> {
+        match value {
+            Value::If(Atom::Literal(Literal::Bool(condition)), then_block, else_block) => {
+                Some(std::mem::replace(
+                    if *condition { then_block } else { else_block },
+                    empty_block(),
+                ))
+            }
+            Value::Switch(scrutinee, cases, default) => {
+                let case = match (&*scrutinee, self.constant(scrutinee)) {
+                    (_, Some(literal)) => cases
+                        .iter()
+                        .position(|(test, _)| *test == Test::Literal(literal.clone())),
+                    (Atom::Var(var), None) => match self.known.get(var) {
+                        Some(Known::Construct(con, _)) => {
+                            cases.iter().position(|(test, _)| *test == Test::Con(*con))
+                        }
+                        _ => return None,
+                    },
+                    (Atom::Literal(_), None) => unreachable!("literal without a constant"),
+                };
+                match case {
+                    Some(i) => Some(cases.swap_remove(i).1),
+                    None => default.take(),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Folds the value if its result is known.
+    fn fold(&self, value: &Value) -> Option<Value> {
+        let literal = |literal| Some(Value::Atom(Atom::Literal(literal)));
+        let int = |n| literal(Literal::Int(n));
+        let bool = |b| literal(Literal::Bool(b));
+        match value {
+            Value::Prim(op, arguments) => match (op, arguments.as_slice()) {
+                (PrimOp::Negate, [a]) => int(self.int(a)?.wrapping_neg()),
+                (PrimOp::Add, [a, b]) => match (self.int(a), self.int(b)) {
+                    (Some(a), Some(b)) => int(a.wrapping_add(b)),
+                    (Some(0), None) => Some(Value::Atom(b.clone())),
+                    (None, Some(0)) => Some(Value::Atom(a.clone())),
+                    _ => None,
+                },
+                (PrimOp::Subtract, [a, b]) => match (self.int(a), self.int(b)) {
+                    (Some(a), Some(b)) => int(a.wrapping_sub(b)),
+                    (None, Some(0)) => Some(Value::Atom(a.clone())),
+                    _ => None,
+                },
+                (PrimOp::Multiply, [a, b]) => match (self.int(a), self.int(b)) {
+                    (Some(a), Some(b)) => int(a.wrapping_mul(b)),
+                    (Some(1), None) => Some(Value::Atom(b.clone())),
+                    (None, Some(1)) => Some(Value::Atom(a.clone())),
+                    _ => None,
+                },
+                // The division by zero raises an exception, which is left
+                // to the runtime.
+                (PrimOp::Divide, [a, b]) => match (self.int(a)?, self.int(b)?) {
+                    (_, 0) => None,
+                    (a, b) => int(a.wrapping_div(b)),
+                },
+                (PrimOp::Less, [a, b]) => bool(self.int(a)? < self.int(b)?),
+                (PrimOp::LessEqual, [a, b]) => bool(self.int(a)? <= self.int(b)?),
+                (PrimOp::Greater, [a, b]) => bool(self.int(a)? > self.int(b)?),
+                (PrimOp::GreaterEqual, [a, b]) => bool(self.int(a)? >= self.int(b)?),
+                (PrimOp::Equal, [a, b]) => bool(self.constant(a)? == self.constant(b)?),
+                (PrimOp::NotEqual, [a, b]) => bool(self.constant(a)? != self.constant(b)?),
+                (PrimOp::Builtin(Builtin::StringConcat), [a, b]) => {
+                    match (self.constant(a)?, self.constant(b)?) {
+                        (Literal::String(a), Literal::String(b)) => {
+                            literal(Literal::String(Rc::from(format!("{}{}", a, b))))
+                        }
+                        _ => None,
+                    }
+                }
+                (PrimOp::Builtin(Builtin::StringOfInt), [a]) => match self.constant(a)? {
+                    Literal::Int(n) => literal(Literal::String(Rc::from(n.to_string()))),
+                    _ => None,
+                },
+                (PrimOp::Builtin(Builtin::StringLength), [a]) => match self.constant(a)? {
+                    Literal::String(s) => {
+                        Some(Value::Box(Atom::Literal(Literal::Int(s.len() as i64))))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            Value::Unbox(Atom::Var(var)) => match self.known.get(var)? {
+                Known::Int(n) => int(*n),
+                Known::Construct(..) => None,
+            },
+            Value::Field(Atom::Var(var), con, index) => match self.known.get(var)? {
+                Known::Construct(known, fields) if known == con => {
+                    Some(Value::Atom(fields[*index].clone()))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+fn empty_block() -> Block {
+    Block {
+        stmts: Vec::new(),
+        result: Atom::Literal(Literal::Unit),
+    }
+}
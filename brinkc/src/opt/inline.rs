@@ -0,0 +1,129 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    anf::{self, Atom, Block, Function, GlobalValue, Program, Stmt, Value},
+    ir::VarId,
+};
+
+/// The size of the largest function inlined, in statements.
+const INLINE_THRESHOLD: usize = 12;
+
+/// Inlines the calls of the small functions bound by the non-recursive
+/// bindings, global or local, which are given at least as many arguments
+/// as they take. The copies are not inlined into again.
+pub fn run(program: &mut Program) {
+    let recursive = recursive_globals(program);
+    let mut inliner = Inliner {
+        functions: program
+            .globals
+            .iter()
+            .filter_map(|global| match &global.value {
+                GlobalValue::Function(function)
+                    if !recursive.contains(&global.var)
+                        && function.body.size() <= INLINE_THRESHOLD =>
+                {
+                    Some((global.var, function.clone()))
+                }
+                _ => None,
+            })
+            .collect(),
+    };
+    let mut globals = std::mem::take(&mut program.globals);
+    for global in &mut globals {
+        match &mut global.value {
+            GlobalValue::Function(function) => inliner.block(program, &mut function.body),
+            GlobalValue::Block(block) => inliner.block(program, block),
+        }
+    }
+    program.globals = globals;
+}
+
+/// Finds the global functions which can call themselves through the other
+/// globals.
+fn recursive_globals(program: &Program) -> HashSet<VarId> {
+    let references = program
+        .globals
+        .iter()
+        .map(|global| {
+            let mut references = Vec::new();
+            let mut reference = |var: VarId| references.push(var);
+            match &global.value {
+                GlobalValue::Function(function) => function.body.for_each_use(&mut reference),
+                GlobalValue::Block(block) => block.for_each_use(&mut reference),
+            }
+            (global.var, references)
+        })
+        .collect::<HashMap<_, _>>();
+    let mut recursive = HashSet::new();
+    for global in &program.globals {
+        let mut reached = HashSet::new();
+        let mut stack = references[&global.var].clone();
+        while let Some(var) = stack.pop() {
+            if var == global.var {
+                recursive.insert(var);
+                break;
+            }
+            if reached.insert(var) {
+                if let Some(references) = references.get(&var) {
+                    stack.extend(references);
+                }
+            }
+        }
+    }
+    recursive
+}
+
+struct Inliner {
+    /// The functions to inline, by the variables bound to them.
+    functions: HashMap<VarId, Function>,
+}
+
+impl Inliner {
+    fn block(&mut self, program: &mut Program, block: &mut Block) {
+        let mut stmts = Vec::new();
+        for stmt in std::mem::take(&mut block.stmts) {
+            let (var, mut value) = match stmt {
+                Stmt::Let(var, value) => (var, value),
+                Stmt::LetRec(mut functions) => {
+                    for (_, function) in &mut functions {
+                        self.block(program, &mut function.body);
+                    }
+                    stmts.push(Stmt::LetRec(functions));
+                    continue;
+                }
+            };
+            let call = match &value {
+                Value::Apply(Atom::Var(function), arguments) => Some((*function, arguments)),
+                Value::Call(function, arguments) => Some((*function, arguments)),
+                _ => None,
+            };
+            if let Some((function, arguments)) = call {
+                if let Some(inlined) = self.functions.get(&function) {
+                    let arity = inlined.parameters.len();
+                    if arguments.len() >= arity {
+                        let body = anf::copy_body(program, inlined, &arguments[..arity]);
+                        let rest = arguments[arity..].to_vec();
+                        stmts.extend(body.stmts);
+                        let value = if rest.is_empty() {
+                            Value::Atom(body.result)
+                        } else {
+                            Value::Apply(body.result, rest)
+                        };
+                        stmts.push(Stmt::Let(var, value));
+                        continue;
+                    }
+                }
+            }
+            for block in value.blocks_mut() {
+                self.block(program, block);
+            }
+            if let Value::Lambda(function) = &value {
+                if function.body.size() <= INLINE_THRESHOLD {
+                    self.functions.insert(var, function.clone());
+                }
+            }
+            stmts.push(Stmt::Let(var, value));
+        }
+        block.stmts = stmts;
+    }
+}
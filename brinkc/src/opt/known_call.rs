@@ -0,0 +1,90 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    anf::{Atom, Block, GlobalValue, Program, Repr, Stmt, Value},
+    ir::VarId,
+    typeck::ty::Ty,
+};
+
+/// Turns the applications of the variables bound to functions into direct
+/// calls when there are enough arguments. The extra arguments are applied
+/// to the result of the call.
+pub fn run(program: &mut Program) {
+    let mut arities = program
+        .globals
+        .iter()
+        .filter_map(|global| match &global.value {
+            GlobalValue::Function(function) => Some((global.var, function.parameters.len())),
+            GlobalValue::Block(_) => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let mut globals = std::mem::take(&mut program.globals);
+    for global in &mut globals {
+        match &mut global.value {
+            GlobalValue::Function(function) => {
+                rewrite_calls(program, &mut arities, &mut function.body)
+            }
+            GlobalValue::Block(block) => rewrite_calls(program, &mut arities, block),
+        }
+    }
+    program.globals = globals;
+}
+
+fn rewrite_calls(program: &mut Program, arities: &mut HashMap<VarId, usize>, block: &mut Block) {
+    let mut stmts = Vec::new();
+    for stmt in std::mem::take(&mut block.stmts) {
+        let (var, mut value) = match stmt {
+            Stmt::Let(var, value) => (var, value),
+            Stmt::LetRec(mut functions) => {
+                for (var, function) in &functions {
+                    arities.insert(*var, function.parameters.len());
+                }
+                for (_, function) in &mut functions {
+                    rewrite_calls(program, arities, &mut function.body);
+                }
+                stmts.push(Stmt::LetRec(functions));
+                continue;
+            }
+        };
+        for nested in value.blocks_mut() {
+            rewrite_calls(program, arities, nested);
+        }
+        match value {
+            Value::Lambda(ref function) => {
+                arities.insert(var, function.parameters.len());
+            }
+            Value::Apply(Atom::Var(function), ref mut arguments) => {
+                if let Some(&arity) = arities.get(&function) {
+                    if arguments.len() == arity {
+                        value = Value::Call(function, std::mem::take(arguments));
+                    } else if arguments.len() > arity {
+                        let rest = arguments.split_off(arity);
+                        let info = program.var(function);
+                        let ty = result_ty(&info.ty, arity);
+                        let span = program.var(var).span;
+                        let result = program.new_var(Rc::from("_"), ty, Repr::Value, span);
+                        let call = Value::Call(function, std::mem::take(arguments));
+                        stmts.push(Stmt::Let(result, call));
+                        value = Value::Apply(Atom::Var(result), rest);
+                    }
+                }
+            }
+            _ => {}
+        }
+        stmts.push(Stmt::Let(var, value));
+    }
+    block.stmts = stmts;
+}
+
+/// Gets the type of the result of the function type applied to the number
+/// of arguments.
+fn result_ty(ty: &Ty, arguments: usize) -> Ty {
+    let mut ty = ty;
+    for _ in 0..arguments {
+        match ty {
+            Ty::Function(_, result) => ty = result,
+            _ => break,
+        }
+    }
+    ty.clone()
+}
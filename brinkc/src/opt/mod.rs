@@ -0,0 +1,384 @@
+//! The optimizer of the mid-level IR: a pipeline of passes, each of which
+//! can be enabled on its own, and after each of which the program is
+//! checked. A miscompilation can be found by bisecting the pipeline with
+//! `--passes`.
+
+use std::collections::HashMap;
+
+use crate::{
+    anf::{self, Atom, CheckError, Program, Value},
+    ir::VarId,
+};
+
+mod beta;
+mod dce;
+mod fold;
+mod inline;
+mod known_call;
+mod unbox;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Pass {
+    /// Substitutes the variables bound to operands, and applies the
+    /// functions bound to variables used only in one call.
+    Beta,
+    /// Inlines the calls of the small functions which are not recursive.
+    Inline,
+    /// Evaluates the operations on constants and the branches on known
+    /// values.
+    ConstFold,
+    /// Turns the applications of the known functions to the arguments they
+    /// take into direct calls.
+    KnownCall,
+    /// Avoids boxing the integers which are unboxed again.
+    Unbox,
+    /// Removes the bindings without uses and effects.
+    Dce,
+}
+
+const PASSES: &[Pass] = &[
+    Pass::Beta,
+    Pass::Inline,
+    Pass::ConstFold,
+    Pass::KnownCall,
+    Pass::Unbox,
+    Pass::Dce,
+];
+
+impl Pass {
+    pub fn from_name(name: &str) -> Option<Pass> {
+        PASSES.iter().copied().find(|pass| pass.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Beta => "beta",
+            Pass::Inline => "inline",
+            Pass::ConstFold => "const-fold",
+            Pass::KnownCall => "known-call",
+            Pass::Unbox => "unbox",
+            Pass::Dce => "dce",
+        }
+    }
+
+    fn run(self, program: &mut Program) {
+        match self {
+            Pass::Beta => beta::run(program),
+            Pass::Inline => inline::run(program),
+            Pass::ConstFold => fold::run(program),
+            Pass::KnownCall => known_call::run(program),
+            Pass::Unbox => unbox::run(program),
+            Pass::Dce => dce::run(program),
+        }
+    }
+}
+
+/// The highest optimization level, `-C opt-level=2`.
+pub const MAX_OPT_LEVEL: u8 = 2;
+
+/// Gets the passes run at the optimization level, in order. The cleanup
+/// passes run again after the ones leaving work for them.
+pub fn default_pipeline(opt_level: u8) -> Vec<Pass> {
+    use Pass::*;
+    match opt_level {
+        0 => Vec::new(),
+        1 => vec![Beta, ConstFold, Unbox, Dce],
+        _ => vec![
+            Beta, Inline, Beta, ConstFold, KnownCall, Unbox, Beta, ConstFold, Dce,
+        ],
+    }
+}
+
+/// Builds the pipeline from the comma-separated list of `--passes`. The
+/// names run in the order listed, replacing the pipeline of the
+/// optimization level, and the names prefixed with `-` are removed from
+/// it, e.g. `--passes=-inline` for the default pipeline without inlining.
+pub fn parse_pipeline(opt_level: u8, passes: &str) -> Result<Vec<Pass>, String> {
+    let mut listed = Vec::new();
+    let mut removed = Vec::new();
+    for name in passes.split(',').filter(|name| !name.is_empty()) {
+        let (list, name) = match name.strip_prefix('-') {
+            Some(name) => (&mut removed, name),
+            None => (&mut listed, name),
+        };
+        match Pass::from_name(name) {
+            Some(pass) => list.push(pass),
+            None => {
+                let names = PASSES.iter().map(|pass| pass.name()).collect::<Vec<_>>();
+                return Err(format!(
+                    "unknown pass `{}`, expected one of {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        }
+    }
+    let mut pipeline = if listed.is_empty() {
+        default_pipeline(opt_level)
+    } else {
+        listed
+    };
+    pipeline.retain(|pass| !removed.contains(pass));
+    Ok(pipeline)
+}
+
+/// The errors found in the program after a pass, which is a bug of the
+/// pass.
+#[derive(Debug)]
+pub struct PassError {
+    pub pass: Pass,
+    pub errors: Vec<CheckError>,
+}
+
+/// Runs the passes in order on the checked program, checking it after each
+/// of them.
+pub fn optimize(program: &mut Program, pipeline: &[Pass]) -> Result<(), PassError> {
+    for pass in pipeline {
+        pass.run(program);
+        anf::check(program).map_err(|errors| PassError {
+            pass: *pass,
+            errors,
+        })?;
+    }
+    Ok(())
+}
+
+/// The variables replaced by the operands they are bound to. The operands
+/// are substituted before they are inserted, so a single lookup is enough.
+#[derive(Default)]
+struct Subst(HashMap<VarId, Atom>);
+
+impl Subst {
+    fn insert(&mut self, var: VarId, atom: Atom) {
+        self.0.insert(var, atom);
+    }
+
+    fn atom(&self, atom: &mut Atom) {
+        if let Atom::Var(var) = atom {
+            if let Some(substituted) = self.0.get(var) {
+                *atom = substituted.clone();
+            }
+        }
+    }
+
+    /// Substitutes the operands of the value, not the ones of its nested
+    /// blocks.
+    fn operands(&self, value: &mut Value) {
+        value.operands_mut(&mut |atom| self.atom(atom));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{anf::Repr, ir, resolve, test_util};
+
+    /// Converts the program to the mid-level IR and runs the passes on it,
+    /// returning the printed program.
+    fn optimize_source(source: &str, pipeline: &[Pass]) -> String {
+        let test_util::Checked {
+            session,
+            graph,
+            resolutions,
+            results,
+        } = test_util::check(source);
+        let program = ir::lower(&session.source_map, &graph, &resolutions, &results);
+        let mut program = anf::convert(&program);
+        if let Err(errors) = anf::check(&program) {
+            panic!("{:#?}", errors);
+        }
+        if let Err(error) = optimize(&mut program, pipeline) {
+            panic!(
+                "{:#?}\n{}",
+                error,
+                anf::print_program(&program, &resolutions, &results)
+            );
+        }
+        anf::print_program(&program, &resolutions, &results)
+    }
+
+    /// Gets the printed global of the name, with the variables renumbered
+    /// from zero in the order they appear, or `None` if it was removed.
+    fn optimize_global(source: &str, pipeline: &[Pass], name: &str) -> Option<String> {
+        let printed = optimize_source(source, pipeline);
+        let header = format!("let {}/", name);
+        let start = printed.rfind(&header)?;
+        let end = printed[start..]
+            .find("\n\n")
+            .map_or(printed.len(), |end| start + end);
+        Some(renumber(printed[start..end].trim_end()))
+    }
+
+    fn renumber(printed: &str) -> String {
+        let mut ids = HashMap::new();
+        let mut output = String::new();
+        let mut chars = printed.chars().peekable();
+        while let Some(c) = chars.next() {
+            output.push(c);
+            if c != '/' || !chars.peek().is_some_and(char::is_ascii_digit) {
+                continue;
+            }
+            let mut id = String::new();
+            while let Some(digit) = chars.peek().copied().filter(char::is_ascii_digit) {
+                id.push(digit);
+                chars.next();
+            }
+            let next = ids.len();
+            output.push_str(&ids.entry(id).or_insert(next).to_string());
+        }
+        output
+    }
+
+    const SOURCE: &str = "let add x y = x + y\n\
+                          let three () = 1 + 2\n\
+                          let rec sum n = if n = 0 then 0 else n + sum (n - 1)\n\
+                          let twice f x = f (f x)\n\
+                          print_string (string_of_int (twice (add 1) (sum (three ()))))\n";
+
+    #[test]
+    fn parses_pipelines() {
+        use Pass::*;
+        assert_eq!(Ok(default_pipeline(2)), parse_pipeline(2, ""));
+        assert_eq!(
+            Ok(vec![Inline, Dce, Inline]),
+            parse_pipeline(0, "inline,dce,inline")
+        );
+        assert_eq!(Ok(vec![Beta, ConstFold, Dce]), parse_pipeline(1, "-unbox"));
+        assert_eq!(Ok(vec![Beta]), parse_pipeline(2, "beta,unbox,-unbox"));
+        assert_eq!(
+            Err(
+                "unknown pass `cse`, expected one of beta, inline, const-fold, \
+                 known-call, unbox, dce"
+                    .to_string()
+            ),
+            parse_pipeline(2, "beta,-cse")
+        );
+    }
+
+    #[test]
+    fn converts_integers_boxed() {
+        assert_eq!(
+            Some(
+                "let add/0 : int -> int -> int =\n  \
+                   fun (x/1 : int) (y/2 : int) ->\n    \
+                     let _/3 : #int = %unbox(x/1)\n    \
+                     let _/4 : #int = %unbox(y/2)\n    \
+                     let _/5 : #int = %add(_/3, _/4)\n    \
+                     let _/6 : int = %box(_/5)\n    \
+                     _/6"
+                .to_string()
+            ),
+            optimize_global(SOURCE, &[], "add")
+        );
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(
+            Some(
+                "let three/0 : unit -> int =\n  \
+                   fun (_/1 : unit) ->\n    \
+                     let _/2 : int = %box(3)\n    \
+                     _/2"
+                .to_string()
+            ),
+            optimize_global(SOURCE, &[Pass::ConstFold, Pass::Dce], "three")
+        );
+        let source = "let f x =\n  match (1, x)\n  | (1, y) -> y + 0\n  | _ -> 2\n\
+                      print_string (string_of_int (f (3 * 4)))\n";
+        assert_eq!(
+            Some(
+                "let f/0 : int -> int =\n  \
+                   fun (x/1 : int) -> x/1"
+                    .to_string()
+            ),
+            optimize_global(source, &default_pipeline(1), "f")
+        );
+    }
+
+    #[test]
+    fn unboxes_integers_across_joins() {
+        assert_eq!(
+            Some(
+                "let sum/0 : int -> int =\n  \
+                   fun (n/1 : int) ->\n    \
+                     let _/2 : #int = %unbox(n/1)\n    \
+                     let _/3 : bool = %equal(_/2, 0)\n    \
+                     let _/4 : #int =\n      \
+                       if _/3 then\n        \
+                         0\n      \
+                       else\n        \
+                         let _/5 : #int = %sub(_/2, 1)\n        \
+                         let _/6 : int = %box(_/5)\n        \
+                         let _/7 : int = call sum/0(_/6)\n        \
+                         let _/8 : #int = %unbox(_/7)\n        \
+                         let _/9 : #int = %add(_/2, _/8)\n        \
+                         _/9\n    \
+                     let _/10 : int = %box(_/4)\n    \
+                     _/10"
+                    .to_string()
+            ),
+            optimize_global(SOURCE, &[Pass::KnownCall, Pass::Unbox, Pass::Dce], "sum")
+        );
+    }
+
+    #[test]
+    fn inlines_and_removes_small_functions() {
+        assert!(optimize_global(SOURCE, &default_pipeline(1), "twice").is_some());
+        assert_eq!(None, optimize_global(SOURCE, &default_pipeline(2), "twice"));
+        assert_eq!(None, optimize_global(SOURCE, &default_pipeline(2), "three"));
+        // The functions are kept without the dead code elimination.
+        assert!(optimize_global(SOURCE, &[Pass::Inline], "twice").is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        let mut program = anf::Program::default();
+        let span = crate::source_file::SourceSpan { start: 0, end: 0 };
+        let int = crate::typeck::ty::Ty::Prim(resolve::PrimTy::Int);
+        let x = program.new_var("x".into(), int.clone(), Repr::Value, span);
+        let y = program.new_var("y".into(), int, Repr::Int, span);
+        program.globals.push(anf::Global {
+            var: x,
+            value: anf::GlobalValue::Block(anf::Block {
+                stmts: vec![anf::Stmt::Let(y, Value::Unbox(Atom::Var(y)))],
+                result: Atom::Var(x),
+            }),
+        });
+        let messages = anf::check(&program)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.message)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "`y/1` is used out of its scope".to_string(),
+                "mismatched representations: expected Value, found Int".to_string(),
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn optimizes_standard_library_tests() {
+        let tests = [
+            include_str!("../../lib/tests/list.bk"),
+            include_str!("../../lib/tests/map.bk"),
+            include_str!("../../lib/tests/option.bk"),
+            include_str!("../../lib/tests/result.bk"),
+            include_str!("../../lib/tests/set.bk"),
+            include_str!("../../lib/tests/string.bk"),
+            include_str!("../../lib/tests/io.bk"),
+        ];
+        for source in &tests {
+            for level in 0..=MAX_OPT_LEVEL {
+                optimize_source(source, &default_pipeline(level));
+            }
+            for pass in PASSES {
+                optimize_source(source, &[*pass]);
+            }
+        }
+    }
+}
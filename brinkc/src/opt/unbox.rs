@@ -0,0 +1,148 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    anf::{Atom, Block, GlobalValue, Program, Repr, Stmt, Value},
+    ir::{Literal, PrimOp, Test, VarId},
+    resolve::PrimTy,
+    typeck::ty::Ty,
+};
+
+use super::Subst;
+
+/// Removes the boxing of the integers around the operations taking them
+/// unboxed: the unboxed values of the boxed ones are used instead of
+/// unboxing them, a value is only unboxed once in a scope, and the
+/// equalities and switches on the boxed integers take them unboxed. The
+/// branches whose results are all boxed return them unboxed, the join
+/// boxing them once. The boxes left without uses are removed by `dce`.
+pub fn run(program: &mut Program) {
+    let mut unboxer = Unboxer {
+        subst: Subst::default(),
+        boxed: HashMap::new(),
+        unboxed: HashMap::new(),
+        unboxed_in_scope: Vec::new(),
+        reboxed: HashMap::new(),
+    };
+    let mut globals = std::mem::take(&mut program.globals);
+    for global in &mut globals {
+        match &mut global.value {
+            GlobalValue::Function(function) => unboxer.block(program, &mut function.body),
+            GlobalValue::Block(block) => unboxer.block(program, block),
+        }
+    }
+    program.globals = globals;
+}
+
+struct Unboxer {
+    subst: Subst,
+    /// The unboxed integers of the variables bound to boxes.
+    boxed: HashMap<VarId, Atom>,
+    /// The variables bound to the unboxed values of the variables in the
+    /// scope, and the variables added to it in the enclosing blocks.
+    unboxed: HashMap<VarId, VarId>,
+    unboxed_in_scope: Vec<VarId>,
+    /// The boxed variables the unboxed values were taken out of.
+    reboxed: HashMap<VarId, VarId>,
+}
+
+impl Unboxer {
+    fn block(&mut self, program: &mut Program, block: &mut Block) {
+        let scope = self.unboxed_in_scope.len();
+        let mut stmts = Vec::new();
+        for stmt in std::mem::take(&mut block.stmts) {
+            self.stmt(program, stmt, &mut stmts);
+        }
+        block.stmts = stmts;
+        self.subst.atom(&mut block.result);
+        for boxed in self.unboxed_in_scope.drain(scope..) {
+            self.unboxed.remove(&boxed);
+        }
+    }
+
+    fn stmt(&mut self, program: &mut Program, stmt: Stmt, stmts: &mut Vec<Stmt>) {
+        let (var, mut value) = match stmt {
+            Stmt::Let(var, value) => (var, value),
+            Stmt::LetRec(mut functions) => {
+                for (_, function) in &mut functions {
+                    self.block(program, &mut function.body);
+                }
+                stmts.push(Stmt::LetRec(functions));
+                return;
+            }
+        };
+        self.subst.operands(&mut value);
+        for block in value.blocks_mut() {
+            self.block(program, block);
+        }
+        match &mut value {
+            Value::Unbox(Atom::Var(boxed)) => match self.unboxed_atom(&Atom::Var(*boxed)) {
+                Some(unboxed) => {
+                    self.subst.insert(var, unboxed);
+                    return;
+                }
+                None => {
+                    self.unboxed.insert(*boxed, var);
+                    self.unboxed_in_scope.push(*boxed);
+                    self.reboxed.insert(var, *boxed);
+                }
+            },
+            Value::Box(Atom::Var(unboxed)) if self.reboxed.contains_key(unboxed) => {
+                let boxed = self.reboxed[unboxed];
+                self.subst.insert(var, Atom::Var(boxed));
+                return;
+            }
+            Value::Prim(PrimOp::Equal, arguments) | Value::Prim(PrimOp::NotEqual, arguments) => {
+                let unboxed = arguments
+                    .iter()
+                    .map(|argument| self.unboxed_atom(argument))
+                    .collect::<Option<Vec<_>>>();
+                if let Some(unboxed) = unboxed {
+                    *arguments = unboxed;
+                }
+            }
+            Value::Switch(scrutinee, cases, _) => {
+                let tests_ints = cases
+                    .iter()
+                    .all(|(test, _)| matches!(test, Test::Literal(Literal::Int(_))));
+                if let (true, Some(unboxed)) = (tests_ints, self.unboxed_atom(scrutinee)) {
+                    *scrutinee = unboxed;
+                }
+            }
+            _ => {}
+        }
+        if value.is_join() && program.var(var).repr == Repr::Value {
+            let results = value
+                .join_blocks_mut()
+                .into_iter()
+                .map(|block| self.unboxed_atom(&block.result))
+                .collect::<Option<Vec<_>>>();
+            if let Some(results) = results {
+                for (block, result) in value.join_blocks_mut().into_iter().zip(results) {
+                    block.result = result;
+                }
+                let info = program.var(var);
+                let (name, span) = (Rc::clone(&info.name), info.span);
+                let join = program.new_var(name, Ty::Prim(PrimTy::Int), Repr::Int, span);
+                stmts.push(Stmt::Let(join, value));
+                value = Value::Box(Atom::Var(join));
+            }
+        }
+        if let Value::Box(unboxed) = &value {
+            self.boxed.insert(var, unboxed.clone());
+        }
+        stmts.push(Stmt::Let(var, value));
+    }
+
+    /// Gets the unboxed integer of the operand, if it is a box in sight or
+    /// it is unboxed in the scope.
+    fn unboxed_atom(&self, atom: &Atom) -> Option<Atom> {
+        match atom {
+            Atom::Var(var) => self
+                .boxed
+                .get(var)
+                .cloned()
+                .or_else(|| self.unboxed.get(var).map(|unboxed| Atom::Var(*unboxed))),
+            Atom::Literal(_) => None,
+        }
+    }
+}
@@ -0,0 +1,640 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{self, node_id::NodeId},
+    frontend::parse_session::Diagnostic,
+    resolve::{DefId, DefKind, FileModule, ModuleId, ModuleSource, Res, Resolutions},
+    source_file::SourceSpan,
+    typeck::{
+        self,
+        ty::{Scheme, Ty, TyVid},
+        TypeDecls, TypeckResults,
+    },
+};
+
+use super::{Database, LoadedGraph, QueryKey, Resolved};
+
+/// Identifies a top-level let binding or expression by its module and its
+/// position among the items of the module, which, unlike its node id, stays
+/// the same when the items before it are edited.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ItemId {
+    pub module: ModuleId,
+    pub index: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ItemEntry {
+    pub item: ItemId,
+    /// The definitions of the let binding or of the recursive group.
+    pub defs: Vec<DefId>,
+}
+
+/// The items checked by the `infer` query, in the order of the program.
+#[derive(Debug, PartialEq)]
+pub struct ProgramItems {
+    pub items: Vec<ItemEntry>,
+    by_def: HashMap<DefId, usize>,
+}
+
+/// The checked type declarations, with the names and the kinds of all the
+/// definitions, which the items are checked with. The diagnostics are
+/// left out of the comparison, as no query depends on them.
+pub struct TypeDeclsResult {
+    pub type_decls: TypeDecls,
+    defs: Vec<(String, DefKind)>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl PartialEq for TypeDeclsResult {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_decls == other.type_decls && self.defs == other.defs
+    }
+}
+
+/// Everything the type inference of an item depends on, besides the types
+/// of the definitions it refers to. The node ids are relative to the node
+/// id of the item, so an item which is not edited has the same input when
+/// the items before it change.
+pub struct ItemInput {
+    text: String,
+    /// The fixity declarations of the prelude and of the file of the item,
+    /// which determine how it is parsed.
+    fixities: Vec<String>,
+    defs: Vec<DefId>,
+    paths: Vec<(usize, Res)>,
+    fields: Vec<(usize, DefId)>,
+    or_bindings: Vec<(usize, usize)>,
+    /// The value definitions the item refers to, except its own.
+    pub references: Vec<DefId>,
+    /// The node id and the start of the item in the current revision, which
+    /// are not compared.
+    pub node: NodeId,
+    pub start: usize,
+    graph: Rc<LoadedGraph>,
+    resolved: Rc<Resolved>,
+}
+
+impl PartialEq for ItemInput {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+            && self.fixities == other.fixities
+            && self.defs == other.defs
+            && self.paths == other.paths
+            && self.fields == other.fields
+            && self.or_bindings == other.or_bindings
+            && self.references == other.references
+    }
+}
+
+/// The types inferred for an item, with the node ids relative to the node
+/// id of the item, and the diagnostic spans relative to its start.
+#[derive(Debug, PartialEq)]
+pub struct InferredItem {
+    /// The schemes of the definitions of the item.
+    pub schemes: Vec<(DefId, Scheme)>,
+    /// Whether the schemes have type variables which are not generalized,
+    /// so the items using the definitions may determine them.
+    pub is_weak: bool,
+    node_types: HashMap<usize, Ty>,
+    local_schemes: HashMap<usize, Scheme>,
+    /// The schemes of the weak definitions as the item determines them.
+    refined: Vec<(DefId, Scheme)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Database {
+    /// Checks the type declarations of the whole program.
+    pub(super) fn type_decls(&self) -> Rc<TypeDeclsResult> {
+        self.type_decls.get(self, &(), QueryKey::TypeDecls, |db| {
+            let loaded = db.module_graph();
+            let resolved = db.last_resolved();
+            let resolutions = &resolved.partial.resolutions;
+            let mut session = db.session.borrow_mut();
+            session.begin_capture();
+            let type_decls = match &loaded.graph {
+                Ok(graph) => typeck::check_type_decls(&mut session, graph, resolutions),
+                Err(_) => TypeDecls::default(),
+            };
+            let diagnostics = session.end_capture();
+            Rc::new(TypeDeclsResult {
+                type_decls,
+                defs: resolutions
+                    .defs
+                    .iter()
+                    .map(|def| (def.name.clone(), def.kind))
+                    .collect(),
+                diagnostics,
+            })
+        })
+    }
+
+    /// Lists the top-level let bindings and expressions, including the ones
+    /// of the nested modules.
+    pub(super) fn items(&self) -> Rc<ProgramItems> {
+        self.items.get(self, &(), QueryKey::Items, |db| {
+            let loaded = db.module_graph();
+            let resolved = db.last_resolved();
+            let resolutions = &resolved.partial.resolutions;
+            let mut items = Vec::new();
+            if let Ok(graph) = &loaded.graph {
+                for module in &graph.modules {
+                    let id = resolutions
+                        .modules
+                        .iter()
+                        .position(|data| data.parent.is_none() && data.file == module.file);
+                    if let Some(id) = id {
+                        collect_items(
+                            resolutions,
+                            &module.program.body,
+                            ModuleId::new(id),
+                            &mut items,
+                        );
+                    }
+                }
+            }
+            let by_def = items
+                .iter()
+                .enumerate()
+                .flat_map(|(index, entry)| entry.defs.iter().map(move |def| (*def, index)))
+                .collect();
+            Rc::new(ProgramItems { items, by_def })
+        })
+    }
+
+    /// Takes the item out of the current parse and resolutions.
+    pub(super) fn item_input(&self, item: ItemId) -> Rc<ItemInput> {
+        self.item_inputs
+            .get(self, &item, QueryKey::ItemInput(item), |db| {
+                let graph = db.module_graph();
+                let resolved = db.last_resolved();
+                let resolutions = &resolved.partial.resolutions;
+                let modules = &graph.graph.as_ref().unwrap().modules;
+                let ast_item = find_item(modules, resolutions, item).unwrap();
+
+                let session = db.session.borrow();
+                let source_map = &session.source_map;
+                let file = resolutions.module(item.module).file;
+                let mut fixities = Vec::new();
+                for module in modules {
+                    if module.source == ModuleSource::Prelude || module.file == file {
+                        collect_fixities(&module.program.body, &mut fixities);
+                    }
+                }
+
+                let node = ast_item.id.as_usize();
+                let mut paths = Vec::new();
+                let mut fields = Vec::new();
+                let mut or_bindings = Vec::new();
+                let mut references = Vec::new();
+                let defs = item_defs(resolutions, ast_item);
+                let relative = |id: NodeId| node - id.as_usize();
+                for id in ast_item.first_id.as_usize()..=node {
+                    let id = NodeId::from_usize(id);
+                    if let Some(res) = resolutions.paths.get(&id) {
+                        let res = match *res {
+                            Res::Local(binding) => {
+                                Res::Local(NodeId::from_usize(relative(binding)))
+                            }
+                            Res::TyParam(parameter) => {
+                                Res::TyParam(NodeId::from_usize(relative(parameter)))
+                            }
+                            Res::Def(target) => {
+                                if resolutions.def(target).kind == DefKind::Value
+                                    && !defs.contains(&target)
+                                    && !references.contains(&target)
+                                {
+                                    references.push(target);
+                                }
+                                Res::Def(target)
+                            }
+                            res => res,
+                        };
+                        paths.push((relative(id), res));
+                    }
+                    if let Some(field) = resolutions.fields.get(&id) {
+                        fields.push((relative(id), *field));
+                    }
+                    if let Some(first) = resolutions.or_bindings.get(&id) {
+                        or_bindings.push((relative(id), relative(*first)));
+                    }
+                }
+
+                Rc::new(ItemInput {
+                    text: source_map.span_to_snippet(ast_item.span).to_string(),
+                    fixities: fixities
+                        .into_iter()
+                        .map(|span| source_map.span_to_snippet(span).to_string())
+                        .collect(),
+                    defs,
+                    paths,
+                    fields,
+                    or_bindings,
+                    references,
+                    node: ast_item.id,
+                    start: ast_item.span.start,
+                    graph: graph.clone(),
+                    resolved: resolved.clone(),
+                })
+            })
+    }
+
+    /// Infers the types of the item, given the types of the definitions it
+    /// refers to. The items referring to the same weak definitions are
+    /// inferred together, as the later ones see the types the earlier ones
+    /// determined.
+    pub fn infer(&self, item: ItemId) -> Rc<InferredItem> {
+        self.inferred.get(self, &item, QueryKey::Infer(item), |db| {
+            let input = db.item_input(item);
+            let type_decls = db.type_decls();
+            let mut schemes = HashMap::new();
+            let mut weak = Vec::new();
+            for &def in &input.references {
+                match db.infer_def(def) {
+                    Some(inferred) if inferred.is_weak => weak.push(def),
+                    Some(inferred) => {
+                        if let Some(scheme) = inferred.scheme(def) {
+                            schemes.insert(def, scheme.clone());
+                        }
+                    }
+                    None => {}
+                }
+            }
+            let context = match weak.is_empty() {
+                true => Vec::new(),
+                false => db.weak_context(item, &mut weak, &mut schemes),
+            };
+
+            // The input is up to date, so its module graph and resolutions
+            // are the current ones.
+            let modules = &input.graph.graph.as_ref().unwrap().modules;
+            let resolutions = &input.resolved.partial.resolutions;
+            let context = context
+                .into_iter()
+                .map(|context_item| find_item(modules, resolutions, context_item).unwrap())
+                .collect::<Vec<_>>();
+            let ast_item = find_item(modules, resolutions, item).unwrap();
+
+            let mut session = db.session.borrow_mut();
+            session.begin_capture();
+            let results = typeck::check_item(
+                &mut session,
+                resolutions,
+                &type_decls.type_decls,
+                schemes,
+                &context,
+                ast_item,
+            );
+            let diagnostics = session.end_capture();
+            Rc::new(InferredItem::new(&input, results, &weak, diagnostics))
+        })
+    }
+
+    fn infer_def(&self, def: DefId) -> Option<Rc<InferredItem>> {
+        let items = self.items();
+        let index = *items.by_def.get(&def)?;
+        Some(self.infer(items.items[index].item))
+    }
+
+    /// Collects the items before the item which may determine the types of
+    /// the weak definitions: the ones defining and referring to them. The
+    /// weak definitions referred to by these items are added to them, and
+    /// the types of the other definitions they refer to to the schemes.
+    fn weak_context(
+        &self,
+        item: ItemId,
+        weak: &mut Vec<DefId>,
+        schemes: &mut HashMap<DefId, Scheme>,
+    ) -> Vec<ItemId> {
+        let items = self.items();
+        let position = items
+            .items
+            .iter()
+            .position(|entry| entry.item == item)
+            .unwrap();
+        let mut included = vec![false; position];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, entry) in items.items[..position].iter().enumerate() {
+                let input = self.item_input(entry.item);
+                let defines_weak = entry.defs.iter().any(|def| weak.contains(def));
+                if included[index]
+                    || !defines_weak && !input.references.iter().any(|def| weak.contains(def))
+                {
+                    continue;
+                }
+                included[index] = true;
+                changed = true;
+                for &def in &input.references {
+                    let is_weak = self.infer_def(def).is_some_and(|inferred| inferred.is_weak);
+                    if is_weak && !weak.contains(&def) {
+                        weak.push(def);
+                    }
+                }
+            }
+        }
+
+        let context = items.items[..position]
+            .iter()
+            .zip(included)
+            .filter(|(_, included)| *included)
+            .map(|(entry, _)| entry.item)
+            .collect::<Vec<_>>();
+        for context_item in &context {
+            for &def in &self.item_input(*context_item).references {
+                if weak.contains(&def) || schemes.contains_key(&def) {
+                    continue;
+                }
+                if let Some(scheme) = self.infer_def(def).and_then(|i| i.scheme(def).cloned()) {
+                    schemes.insert(def, scheme);
+                }
+            }
+        }
+        context
+    }
+
+    /// Combines the types inferred for the items into the results of the
+    /// whole program.
+    pub fn typeck(&self) -> Rc<TypeckResults> {
+        self.typeck.get(self, &(), QueryKey::Typeck, |db| {
+            // The node ids of the items change with the resolutions, even
+            // when their inputs do not.
+            let _ = db.last_resolved();
+            let type_decls = db.type_decls();
+            let mut results = TypeckResults {
+                adts: type_decls.type_decls.adts.clone(),
+                def_schemes: type_decls.type_decls.schemes.clone(),
+                ..TypeckResults::default()
+            };
+            for entry in &db.items().items {
+                let node = db.item_input(entry.item).node.as_usize();
+                let inferred = db.infer(entry.item);
+                for (offset, ty) in &inferred.node_types {
+                    results
+                        .node_types
+                        .insert(NodeId::from_usize(node - offset), ty.clone());
+                }
+                for (offset, scheme) in &inferred.local_schemes {
+                    results
+                        .local_schemes
+                        .insert(NodeId::from_usize(node - offset), scheme.clone());
+                }
+                for (def, scheme) in &inferred.schemes {
+                    results.def_schemes.insert(*def, scheme.clone());
+                }
+                for (def, scheme) in &inferred.refined {
+                    results.def_schemes.insert(*def, scheme.clone());
+                }
+            }
+            // The nodes of the weak definitions get the types which the
+            // items using them determined, like their schemes.
+            for entry in &db.items().items {
+                let inferred = db.infer(entry.item);
+                if !inferred.is_weak {
+                    continue;
+                }
+                let mut bindings = HashMap::new();
+                for (def, scheme) in &inferred.schemes {
+                    bind_vars(&scheme.ty, &results.def_schemes[def].ty, &mut bindings);
+                }
+                if bindings.is_empty() {
+                    continue;
+                }
+                let node = db.item_input(entry.item).node.as_usize();
+                for (offset, ty) in &inferred.node_types {
+                    results.node_types.insert(
+                        NodeId::from_usize(node - offset),
+                        replace_vars(ty, &bindings),
+                    );
+                }
+                for (offset, scheme) in &inferred.local_schemes {
+                    let scheme = Scheme {
+                        generics: scheme.generics,
+                        ty: replace_vars(&scheme.ty, &bindings),
+                    };
+                    results
+                        .local_schemes
+                        .insert(NodeId::from_usize(node - offset), scheme);
+                }
+            }
+            Rc::new(results)
+        })
+    }
+
+    /// Gets the diagnostics of the type checking: the ones of the type
+    /// declarations, then the ones of the items in order.
+    pub fn typeck_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.type_decls().diagnostics.clone();
+        for entry in &self.items().items {
+            let start = self.item_input(entry.item).start;
+            for diagnostic in &self.infer(entry.item).diagnostics {
+                let span = diagnostic.span;
+                diagnostics.push(Diagnostic {
+                    span: SourceSpan::new(start + span.start, start + span.end),
+                    ..diagnostic.clone()
+                });
+            }
+        }
+        diagnostics
+    }
+
+    fn last_resolved(&self) -> Rc<Resolved> {
+        match self.modules().len() {
+            0 => Rc::new(Resolved {
+                partial: Default::default(),
+                diagnostics: Vec::new(),
+            }),
+            len => self.resolve(len - 1),
+        }
+    }
+}
+
+impl InferredItem {
+    fn new(
+        input: &ItemInput,
+        results: TypeckResults,
+        weak: &[DefId],
+        diagnostics: Vec<Diagnostic>,
+    ) -> Self {
+        let node = input.node.as_usize();
+        let schemes = input
+            .defs
+            .iter()
+            .filter_map(|def| Some((*def, results.def_schemes.get(def)?.clone())))
+            .collect::<Vec<_>>();
+        let refined = weak
+            .iter()
+            .filter_map(|def| Some((*def, results.def_schemes.get(def)?.clone())))
+            .collect();
+        InferredItem {
+            is_weak: schemes.iter().any(|(_, scheme)| has_vars(&scheme.ty)),
+            schemes,
+            node_types: results
+                .node_types
+                .into_iter()
+                .map(|(id, ty)| (node - id.as_usize(), ty))
+                .collect(),
+            local_schemes: results
+                .local_schemes
+                .into_iter()
+                .map(|(id, scheme)| (node - id.as_usize(), scheme))
+                .collect(),
+            refined,
+            diagnostics: diagnostics
+                .into_iter()
+                .map(|diagnostic| Diagnostic {
+                    span: SourceSpan::new(
+                        diagnostic.span.start.saturating_sub(input.start),
+                        diagnostic.span.end.saturating_sub(input.start),
+                    ),
+                    ..diagnostic
+                })
+                .collect(),
+        }
+    }
+
+    /// Gets the scheme of one of the definitions of the item.
+    pub fn scheme(&self, def: DefId) -> Option<&Scheme> {
+        self.schemes
+            .iter()
+            .find_map(|(other, scheme)| (*other == def).then_some(scheme))
+    }
+}
+
+fn has_vars(ty: &Ty) -> bool {
+    match ty {
+        Ty::Var(_) => true,
+        Ty::Prim(_) | Ty::Generic(_) | Ty::Error => false,
+        Ty::Adt(_, tys) | Ty::Tuple(tys) => tys.iter().any(has_vars),
+        Ty::List(element) | Ty::Array(element) | Ty::Ref(element) => has_vars(element),
+        Ty::Function(parameter, result) => has_vars(parameter) || has_vars(result),
+    }
+}
+
+/// Binds the type variables of the type to the types without variables
+/// at the same places in the refined type.
+fn bind_vars(ty: &Ty, refined: &Ty, bindings: &mut HashMap<TyVid, Ty>) {
+    match (ty, refined) {
+        (Ty::Var(var), refined) if !has_vars(refined) => {
+            bindings.insert(*var, refined.clone());
+        }
+        (Ty::Adt(_, tys), Ty::Adt(_, refined)) | (Ty::Tuple(tys), Ty::Tuple(refined)) => {
+            for (ty, refined) in tys.iter().zip(refined) {
+                bind_vars(ty, refined, bindings);
+            }
+        }
+        (Ty::List(ty), Ty::List(refined))
+        | (Ty::Array(ty), Ty::Array(refined))
+        | (Ty::Ref(ty), Ty::Ref(refined)) => bind_vars(ty, refined, bindings),
+        (Ty::Function(parameter, result), Ty::Function(refined_parameter, refined_result)) => {
+            bind_vars(parameter, refined_parameter, bindings);
+            bind_vars(result, refined_result, bindings);
+        }
+        _ => {}
+    }
+}
+
+fn replace_vars(ty: &Ty, bindings: &HashMap<TyVid, Ty>) -> Ty {
+    let replace = |tys: &[Ty]| tys.iter().map(|ty| replace_vars(ty, bindings)).collect();
+    match ty {
+        Ty::Var(var) => bindings.get(var).cloned().unwrap_or_else(|| ty.clone()),
+        Ty::Prim(_) | Ty::Generic(_) | Ty::Error => ty.clone(),
+        Ty::Adt(def, tys) => Ty::Adt(*def, replace(tys)),
+        Ty::Tuple(tys) => Ty::Tuple(replace(tys)),
+        Ty::List(element) => Ty::List(Box::new(replace_vars(element, bindings))),
+        Ty::Array(element) => Ty::Array(Box::new(replace_vars(element, bindings))),
+        Ty::Ref(element) => Ty::Ref(Box::new(replace_vars(element, bindings))),
+        Ty::Function(parameter, result) => Ty::function(
+            replace_vars(parameter, bindings),
+            replace_vars(result, bindings),
+        ),
+    }
+}
+
+/// Gets the definitions of the let binding or of the recursive group.
+fn item_defs(resolutions: &Resolutions, item: &ast::Item) -> Vec<DefId> {
+    let bindings = match &item.kind {
+        ast::ItemKind::LetBinding(let_binding) => std::slice::from_ref(let_binding),
+        ast::ItemKind::LetRec(bindings) => bindings,
+        _ => &[],
+    };
+    bindings
+        .iter()
+        .filter_map(|let_binding| resolutions.def_of_node.get(&let_binding.id).copied())
+        .collect()
+}
+
+fn collect_items(
+    resolutions: &Resolutions,
+    items: &[ast::Item],
+    module: ModuleId,
+    entries: &mut Vec<ItemEntry>,
+) {
+    for (index, item) in items.iter().enumerate() {
+        let item_id = ItemId { module, index };
+        match &item.kind {
+            ast::ItemKind::LetBinding(_) | ast::ItemKind::LetRec(_) | ast::ItemKind::Expr(_) => {
+                entries.push(ItemEntry {
+                    item: item_id,
+                    defs: item_defs(resolutions, item),
+                })
+            }
+            ast::ItemKind::Module(decl) => {
+                let nested = resolutions.modules.iter().position(|data| {
+                    data.parent == Some(module) && data.span == decl.identifier.span
+                });
+                if let Some(nested) = nested {
+                    collect_items(
+                        resolutions,
+                        &decl.body.items,
+                        ModuleId::new(nested),
+                        entries,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_fixities(items: &[ast::Item], fixities: &mut Vec<SourceSpan>) {
+    for item in items {
+        match &item.kind {
+            ast::ItemKind::Fixity(_) => fixities.push(item.span),
+            ast::ItemKind::Module(decl) => collect_fixities(&decl.body.items, fixities),
+            _ => {}
+        }
+    }
+}
+
+/// Finds the items of the module in the module graph.
+fn module_items<'g>(
+    modules: &'g [FileModule],
+    resolutions: &Resolutions,
+    module: ModuleId,
+) -> Option<&'g [ast::Item]> {
+    let data = resolutions.module(module);
+    match data.parent {
+        None => modules
+            .iter()
+            .find(|file_module| file_module.file == data.file)
+            .map(|file_module| &file_module.program.body[..]),
+        Some(parent) => module_items(modules, resolutions, parent)?
+            .iter()
+            .find_map(|item| match &item.kind {
+                ast::ItemKind::Module(decl) if decl.identifier.span == data.span => {
+                    Some(&decl.body.items[..])
+                }
+                _ => None,
+            }),
+    }
+}
+
+fn find_item<'g>(
+    modules: &'g [FileModule],
+    resolutions: &Resolutions,
+    item: ItemId,
+) -> Option<&'g ast::Item> {
+    module_items(modules, resolutions, item.module)?.get(item.index)
+}
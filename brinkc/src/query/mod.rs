@@ -0,0 +1,639 @@
+//! The demand-driven, incremental compiler pipeline. Every stage is a query
+//! whose value is memoized along with the queries it read, so after an input
+//! changes, only the queries depending on the change are computed again.
+//!
+//! A recomputed value equal to the previous one does not invalidate the
+//! queries depending on it. The parsed files and the resolutions are always
+//! new, as their node ids are, but the inputs of the type inference of each
+//! top-level item are made independent of the node ids and of the positions,
+//! so editing a function only checks the function and the items whose types
+//! depend on its type again.
+
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::{
+    ast,
+    frontend::{
+        self,
+        parse_session::{Diagnostic, ParseSession},
+        parser::{Fixity, Parser},
+        tokens::Tokens,
+    },
+    resolve::{
+        self,
+        module_graph::{FileLoader, ParsedFile},
+        FileModule, ModuleGraph, ModuleSource, PartialResolutions, Resolutions,
+    },
+    source_file::{FileId, SourceSpan},
+    typeck::TypeckResults,
+};
+
+mod items;
+mod storage;
+
+pub use items::ItemId;
+use items::{InferredItem, ItemInput, ProgramItems, TypeDeclsResult};
+use storage::{QueryTable, Revision, Runtime};
+
+/// Identifies a query and its key, as a dependency of other queries.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum QueryKey {
+    SourceText(ModuleSource),
+    SourceFile(ModuleSource),
+    Tokens(ModuleSource),
+    Parse(ModuleSource),
+    ModuleGraph,
+    Modules,
+    /// The resolution of the file module at the index in the module graph.
+    Resolve(usize),
+    TypeDecls,
+    Items,
+    ItemInput(ItemId),
+    Infer(ItemId),
+    Typeck,
+    /// Stands for the queries read while checking whether a value is up to
+    /// date, which are not dependencies of the query being computed.
+    Untracked,
+}
+
+/// The tokens of a file, which are missing if its indentation is malformed.
+pub struct TokenizedFile {
+    pub file: FileId,
+    pub tokens: Option<Tokens>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A parsed file with the diagnostics of the lexer and the parser.
+pub struct ParsedSource {
+    pub file: FileId,
+    pub program: Option<Arc<ast::Program>>,
+    /// The fixities declared in the file, which only matter for the prelude.
+    pub fixities: HashMap<String, Fixity>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The module graph of the root file, with the diagnostics of all its files.
+pub struct LoadedGraph {
+    pub graph: Result<ModuleGraph, String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The resolutions of the file modules up to one of them, with the
+/// diagnostics of that module.
+pub struct Resolved {
+    pub partial: PartialResolutions,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// The files are parsed again only when their text changes, and the new
+// results have new node ids, so they are compared by identity.
+macro_rules! compare_by_identity {
+    ($($ty:ty),*) => {
+        $(impl PartialEq for $ty {
+            fn eq(&self, other: &Self) -> bool {
+                std::ptr::eq(self, other)
+            }
+        })*
+    };
+}
+
+compare_by_identity!(TokenizedFile, ParsedSource, LoadedGraph, Resolved);
+
+/// Reads the text of a file from disk, or from the editor's buffers.
+type ReadFile = Box<dyn Fn(&Path) -> Option<String>>;
+
+/// The text of a file, `None` if it cannot be read, with the revision it
+/// was last set in.
+type SourceText = (Option<Rc<str>>, Revision);
+
+/// Holds the inputs of the compilation and the memoized queries computed
+/// from them. The files are read on demand, unless their text is set.
+pub struct Database {
+    session: RefCell<ParseSession>,
+    root: PathBuf,
+    read_file: ReadFile,
+    runtime: Runtime,
+    /// The texts of the files read from disk or set, with the revisions
+    /// they were last set in.
+    source_texts: RefCell<HashMap<PathBuf, SourceText>>,
+    source_files: QueryTable<ModuleSource, Option<FileId>>,
+    tokens: QueryTable<ModuleSource, Option<Rc<TokenizedFile>>>,
+    parses: QueryTable<ModuleSource, Option<Rc<ParsedSource>>>,
+    module_graph: QueryTable<(), Rc<LoadedGraph>>,
+    modules: QueryTable<(), Rc<Vec<(String, ModuleSource)>>>,
+    resolutions: QueryTable<usize, Rc<Resolved>>,
+    type_decls: QueryTable<(), Rc<TypeDeclsResult>>,
+    items: QueryTable<(), Rc<ProgramItems>>,
+    item_inputs: QueryTable<ItemId, Rc<ItemInput>>,
+    inferred: QueryTable<ItemId, Rc<InferredItem>>,
+    typeck: QueryTable<(), Rc<TypeckResults>>,
+}
+
+impl Database {
+    /// Creates the database compiling the root file, reading the files from
+    /// disk.
+    pub fn new(session: ParseSession, root: impl Into<PathBuf>) -> Self {
+        Self::with_reader(session, root, |path| std::fs::read_to_string(path).ok())
+    }
+
+    pub fn with_reader(
+        session: ParseSession,
+        root: impl Into<PathBuf>,
+        read_file: impl Fn(&Path) -> Option<String> + 'static,
+    ) -> Self {
+        Self {
+            session: RefCell::new(session),
+            root: root.into(),
+            read_file: Box::new(read_file),
+            runtime: Runtime::new(),
+            source_texts: RefCell::new(HashMap::new()),
+            source_files: QueryTable::new(),
+            tokens: QueryTable::new(),
+            parses: QueryTable::new(),
+            module_graph: QueryTable::new(),
+            modules: QueryTable::new(),
+            resolutions: QueryTable::new(),
+            type_decls: QueryTable::new(),
+            items: QueryTable::new(),
+            item_inputs: QueryTable::new(),
+            inferred: QueryTable::new(),
+            typeck: QueryTable::new(),
+        }
+    }
+
+    pub fn session(&self) -> Ref<'_, ParseSession> {
+        self.session.borrow()
+    }
+
+    pub fn into_session(self) -> ParseSession {
+        self.session.into_inner()
+    }
+
+    /// Reports the diagnostics through the session.
+    pub fn emit(&self, diagnostics: Vec<Diagnostic>) {
+        let mut session = self.session.borrow_mut();
+        for diagnostic in diagnostics {
+            session.emit(diagnostic);
+        }
+    }
+
+    /// Sets the text of the file, overriding its contents on disk. `None`
+    /// makes the file missing.
+    pub fn set_source_text(&mut self, path: impl Into<PathBuf>, text: Option<String>) {
+        let path = path.into();
+        let text = text.map(Rc::from);
+        let mut source_texts = self.source_texts.borrow_mut();
+        if let Some((current, _)) = source_texts.get(&path) {
+            if *current == text {
+                return;
+            }
+        }
+        let revision = self.runtime.bump_revision();
+        source_texts.insert(path, (text, revision));
+    }
+
+    /// Brings the query up to date and gets the revision its value last
+    /// changed in.
+    fn refresh(&self, query: &QueryKey) -> Revision {
+        self.runtime.untracked(|| match query {
+            QueryKey::SourceText(source) => {
+                let _ = self.source_text(source);
+                match source {
+                    ModuleSource::File(path) => self.source_texts.borrow()[path].1,
+                    _ => Revision::FIRST,
+                }
+            }
+            QueryKey::SourceFile(source) => {
+                let _ = self.source_file(source);
+                self.source_files.changed_at(source)
+            }
+            QueryKey::Tokens(source) => {
+                let _ = self.tokens(source);
+                self.tokens.changed_at(source)
+            }
+            QueryKey::Parse(source) => {
+                let _ = self.parse(source);
+                self.parses.changed_at(source)
+            }
+            QueryKey::ModuleGraph => {
+                let _ = self.module_graph();
+                self.module_graph.changed_at(&())
+            }
+            QueryKey::Modules => {
+                let _ = self.modules();
+                self.modules.changed_at(&())
+            }
+            QueryKey::Resolve(index) => {
+                let _ = self.resolve(*index);
+                self.resolutions.changed_at(index)
+            }
+            QueryKey::TypeDecls => {
+                let _ = self.type_decls();
+                self.type_decls.changed_at(&())
+            }
+            QueryKey::Items => {
+                let _ = self.items();
+                self.items.changed_at(&())
+            }
+            QueryKey::ItemInput(item) => {
+                let _ = self.item_input(*item);
+                self.item_inputs.changed_at(item)
+            }
+            QueryKey::Infer(item) => {
+                let _ = self.infer(*item);
+                self.inferred.changed_at(item)
+            }
+            QueryKey::Typeck => {
+                let _ = self.typeck();
+                self.typeck.changed_at(&())
+            }
+            QueryKey::Untracked => unreachable!(),
+        })
+    }
+
+    /// The input query: the text of the file, or `None` if there is no such
+    /// file.
+    pub fn source_text(&self, source: &ModuleSource) -> Option<Rc<str>> {
+        self.runtime.record(QueryKey::SourceText(source.clone()));
+        let path = match source {
+            ModuleSource::File(path) => path,
+            _ => return source.embedded_source().map(Rc::from),
+        };
+        let mut source_texts = self.source_texts.borrow_mut();
+        let (text, _) = source_texts.entry(path.clone()).or_insert_with(|| {
+            let text = (self.read_file)(path).map(Rc::from);
+            (text, self.runtime.revision())
+        });
+        text.clone()
+    }
+
+    /// Adds the text of the file to the source map. Every version of the
+    /// file gets new positions, so the spans into the other files stay
+    /// valid, and the text of the previous version is dropped, as all the
+    /// queries using it are computed again.
+    pub fn source_file(&self, source: &ModuleSource) -> Option<FileId> {
+        self.source_files
+            .get(self, source, QueryKey::SourceFile(source.clone()), |db| {
+                let text = db.source_text(source);
+                let mut session = db.session.borrow_mut();
+                if let Some(previous) = db.source_files.peek(source).flatten() {
+                    session.source_map.drop_file(previous);
+                }
+                Some(
+                    session
+                        .source_map
+                        .add_file(source.path().display().to_string(), text?.to_string()),
+                )
+            })
+    }
+
+    /// Lexes the file and checks its indentation.
+    pub fn tokens(&self, source: &ModuleSource) -> Option<Rc<TokenizedFile>> {
+        self.tokens
+            .get(self, source, QueryKey::Tokens(source.clone()), |db| {
+                let file = db.source_file(source)?;
+                let mut session = db.session.borrow_mut();
+                session.begin_capture();
+                let tokens = frontend::tokenize_file(&mut session, file);
+                let diagnostics = session.end_capture();
+                Some(Rc::new(TokenizedFile {
+                    file,
+                    tokens,
+                    diagnostics,
+                }))
+            })
+    }
+
+    /// Parses the file. The files other than the prelude are parsed with
+    /// the fixities declared in the prelude.
+    pub fn parse(&self, source: &ModuleSource) -> Option<Rc<ParsedSource>> {
+        self.parses
+            .get(self, source, QueryKey::Parse(source.clone()), |db| {
+                let tokenized = db.tokens(source)?;
+                let prelude_fixities = match source {
+                    ModuleSource::Prelude => HashMap::new(),
+                    _ => db
+                        .parse(&ModuleSource::Prelude)
+                        .map(|prelude| prelude.fixities.clone())
+                        .unwrap_or_default(),
+                };
+                let mut diagnostics = tokenized.diagnostics.clone();
+                let tokens = match &tokenized.tokens {
+                    Some(tokens) => tokens.clone(),
+                    None => {
+                        return Some(Rc::new(ParsedSource {
+                            file: tokenized.file,
+                            program: None,
+                            fixities: HashMap::new(),
+                            diagnostics,
+                        }))
+                    }
+                };
+
+                let mut session = db.session.borrow_mut();
+                session.begin_capture();
+                let (program, fixities) = match source {
+                    ModuleSource::Prelude => {
+                        let program = Parser::parse_prelude(&mut session, tokenized.file, tokens);
+                        (program, std::mem::take(&mut session.prelude_fixities))
+                    }
+                    _ => {
+                        session.prelude_fixities = prelude_fixities;
+                        let program = Parser::parse(&mut session, tokenized.file, tokens);
+                        (program, HashMap::new())
+                    }
+                };
+                diagnostics.extend(session.end_capture());
+                Some(Rc::new(ParsedSource {
+                    file: tokenized.file,
+                    program: Some(Arc::new(program)),
+                    fixities,
+                    diagnostics,
+                }))
+            })
+    }
+
+    /// Loads the module graph of the root file through the `parse` query.
+    pub fn module_graph(&self) -> Rc<LoadedGraph> {
+        self.module_graph
+            .get(self, &(), QueryKey::ModuleGraph, |db| {
+                let mut loader = DatabaseLoader {
+                    db,
+                    diagnostics: Vec::new(),
+                };
+                let graph = ModuleGraph::load_from(&mut loader, &db.root);
+                Rc::new(LoadedGraph {
+                    graph,
+                    diagnostics: loader.diagnostics,
+                })
+            })
+    }
+
+    /// The names and the sources of the file modules in the order of the
+    /// module graph. Unlike the graph, it only changes when the imports do.
+    pub fn modules(&self) -> Rc<Vec<(String, ModuleSource)>> {
+        self.modules.get(self, &(), QueryKey::Modules, |db| {
+            let modules = match &db.module_graph().graph {
+                Ok(graph) => graph
+                    .modules
+                    .iter()
+                    .map(|module| (module.name.clone(), module.source.clone()))
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            Rc::new(modules)
+        })
+    }
+
+    /// Resolves the file module at the index in the module graph on top of
+    /// the resolutions of the modules before it.
+    pub fn resolve(&self, index: usize) -> Rc<Resolved> {
+        self.resolutions
+            .get(self, &index, QueryKey::Resolve(index), |db| {
+                let mut partial = match index {
+                    0 => PartialResolutions::default(),
+                    _ => db.resolve(index - 1).partial.clone(),
+                };
+                let (name, source) = db.modules()[index].clone();
+                let parsed = db.parse(&source).unwrap();
+                let module = FileModule {
+                    name,
+                    source,
+                    file: parsed.file,
+                    program: parsed.program.clone().unwrap(),
+                };
+
+                let mut session = db.session.borrow_mut();
+                session.begin_capture();
+                resolve::resolve_file_module(&mut session, &mut partial, &module);
+                let diagnostics = session.end_capture();
+                Rc::new(Resolved {
+                    partial,
+                    diagnostics,
+                })
+            })
+    }
+
+    /// Gets the module graph, or the error if the root file cannot be read.
+    pub fn checked_graph(&self) -> Result<ModuleGraph, String> {
+        self.module_graph().graph.clone()
+    }
+
+    /// Gets the diagnostics of loading and parsing the files.
+    pub fn graph_diagnostics(&self) -> Vec<Diagnostic> {
+        self.module_graph().diagnostics.clone()
+    }
+
+    /// Gets the resolutions of the whole module graph.
+    pub fn resolutions(&self) -> Resolutions {
+        match self.modules().len() {
+            0 => Resolutions::default(),
+            len => self.resolve(len - 1).partial.resolutions.clone(),
+        }
+    }
+
+    /// Gets the diagnostics of the name resolution, by module.
+    pub fn resolve_diagnostics(&self) -> Vec<Diagnostic> {
+        (0..self.modules().len())
+            .flat_map(|index| self.resolve(index).diagnostics.clone())
+            .collect()
+    }
+}
+
+/// Loads the module graph through the database, collecting the diagnostics
+/// of the parsed files in the order they are loaded.
+struct DatabaseLoader<'a> {
+    db: &'a Database,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl FileLoader for DatabaseLoader<'_> {
+    fn parse(&mut self, source: &ModuleSource) -> Option<ParsedFile> {
+        let parsed = self.db.parse(source)?;
+        self.diagnostics.extend(parsed.diagnostics.iter().cloned());
+        Some((parsed.file, parsed.program.clone()))
+    }
+
+    fn snippet(&self, span: SourceSpan) -> String {
+        let session = self.db.session.borrow();
+        session.source_map.span_to_snippet(span).to_string()
+    }
+
+    fn error(&mut self, span: SourceSpan, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: frontend::parse_session::Severity::Error,
+            span,
+            message,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, source_file::SourceMap, typeck};
+
+    fn database(files: &[(&str, &str)]) -> Database {
+        let mut db =
+            Database::with_reader(ParseSession::new(SourceMap::new()), "src/main.bk", |_| None);
+        for (path, text) in files {
+            db.set_source_text(*path, Some(text.to_string()));
+        }
+        db
+    }
+
+    /// Gets the names of the items inferred since the last call.
+    fn inferred(db: &Database) -> Vec<String> {
+        let executed = db.runtime.executed.take();
+        let items = db.items();
+        let resolutions = db.resolutions();
+        let names = executed
+            .iter()
+            .filter_map(|query| match query {
+                QueryKey::Infer(item) => items.items.iter().find(|entry| entry.item == *item),
+                _ => None,
+            })
+            .map(|entry| match &entry.defs[..] {
+                [] => "_".to_string(),
+                defs => defs
+                    .iter()
+                    .map(|def| resolutions.def(*def).name.clone())
+                    .collect::<Vec<_>>()
+                    .join(" and "),
+            })
+            .collect();
+        db.runtime.executed.take();
+        names
+    }
+
+    fn error_count(db: &Database) -> u32 {
+        db.emit(db.graph_diagnostics());
+        db.emit(db.resolve_diagnostics());
+        db.typeck();
+        db.emit(db.typeck_diagnostics());
+        db.session().error_count()
+    }
+
+    #[test]
+    fn reuses_memoized_queries() {
+        let db = database(&[("src/main.bk", "let f x = x + 1\nlet y = f 2\n")]);
+        db.typeck();
+        assert!(inferred(&db).ends_with(&["f".to_string(), "y".to_string()]));
+
+        db.typeck();
+        assert!(db.runtime.executed.borrow().is_empty());
+    }
+
+    #[test]
+    fn rechecks_only_the_edited_item() {
+        let mut db = database(&[(
+            "src/main.bk",
+            "let f x = x + 1\nlet g x = x * 2\nlet y = g 3\n",
+        )]);
+        let before = db.typeck();
+        inferred(&db);
+
+        db.set_source_text(
+            "src/main.bk",
+            Some("let f x = x + 10\nlet g x = x * 2\nlet y = g 3\n".to_string()),
+        );
+        let after = db.typeck();
+        assert_eq!(inferred(&db), ["f"]);
+        assert_eq!(before.def_schemes, after.def_schemes);
+    }
+
+    #[test]
+    fn rechecks_the_items_using_a_changed_type() {
+        let mut db = database(&[(
+            "src/main.bk",
+            "let f x = x + 1\nlet g x = x * 2\nlet y = f 3\n",
+        )]);
+        db.typeck();
+        inferred(&db);
+
+        db.set_source_text(
+            "src/main.bk",
+            Some("let f x = x = 1\nlet g x = x * 2\nlet y = f 3\n".to_string()),
+        );
+        db.typeck();
+        assert!(inferred(&db).ends_with(&["f".to_string(), "y".to_string()]));
+    }
+
+    #[test]
+    fn matches_the_batch_type_checker() {
+        let source = "let r = ref []\nlet a = r := [1]\nlet b = r := [true]\nlet c = 1 + \"s\"\n";
+        let db = database(&[("src/main.bk", source)]);
+        assert_eq!(error_count(&db), 2);
+
+        let mut session = ParseSession::new(SourceMap::new());
+        let graph =
+            ModuleGraph::load_with(&mut session, "src/main.bk", &|_| Some(source.to_string()))
+                .unwrap();
+        let resolutions = crate::resolve::resolve(&mut session, &graph);
+        let results = typeck::typeck(&mut session, &graph, &resolutions);
+        assert_eq!(session.error_count(), 2);
+        assert_eq!(results.def_schemes, db.typeck().def_schemes);
+    }
+
+    #[test]
+    fn fills_the_weak_types_of_the_definitions() {
+        let source = "let r = ref []\n\
+                      let rec build n acc = if n = 0 then acc else build (n - 1) (n :: acc)\n\
+                      let go () = r := build 20000 []\n";
+        let db = database(&[("src/main.bk", source)]);
+        assert_eq!(error_count(&db), 0);
+
+        let graph = db.checked_graph().unwrap();
+        let resolutions = db.resolutions();
+        let results = db.typeck();
+        let session = db.session();
+        let program = ir::lower(&session.source_map, &graph, &resolutions, &results);
+        if let Err(errors) = ir::validate(&program, &resolutions, &results) {
+            panic!("{}", errors[0].message);
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_last_version_of_a_file() {
+        let mut db = database(&[("src/main.bk", "let y = 1\n")]);
+        for n in 2..10 {
+            db.set_source_text("src/main.bk", Some(format!("let y = {}\n", n)));
+            assert_eq!(error_count(&db), 0);
+        }
+
+        let session = db.session();
+        let texts = session
+            .source_map
+            .files()
+            .iter()
+            .filter(|file| file.file_path == "src/main.bk" && !file.source_code.is_empty())
+            .map(|file| file.source_code.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["let y = 9\n"]);
+    }
+
+    #[test]
+    fn picks_up_an_added_file() {
+        let mut db = database(&[("src/main.bk", "import Util\nlet y = Util.twice 2\n")]);
+        assert_eq!(error_count(&db), 3);
+
+        db.set_source_text("src/util.bk", Some("pub let twice x = x * 2\n".to_string()));
+        let results = db.typeck();
+        let resolutions = db.resolutions();
+        let y = resolutions
+            .defs
+            .iter()
+            .position(|def| def.name == "y")
+            .unwrap();
+        assert_eq!(
+            results.def_schemes[&resolve::DefId::new(y)].ty,
+            typeck::ty::Ty::Prim(resolve::PrimTy::Int)
+        );
+    }
+}
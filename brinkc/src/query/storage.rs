@@ -0,0 +1,174 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    hash::Hash,
+};
+
+use super::{Database, QueryKey};
+
+/// Counts the changes of the inputs of the database.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Revision(u64);
+
+impl Revision {
+    /// The revision the database starts in, which the embedded files are
+    /// never changed after.
+    pub const FIRST: Revision = Revision(1);
+}
+
+/// The memoized value of a query with the queries it was computed from.
+struct Memo<V> {
+    value: V,
+    /// The revision the value last changed in. A recomputed value equal to
+    /// the previous one keeps it, so the queries depending on the value are
+    /// not recomputed.
+    changed_at: Revision,
+    /// The last revision the value was known to be up to date in.
+    verified_at: Revision,
+    dependencies: Vec<QueryKey>,
+}
+
+/// The memoized values of a single query, by its key.
+pub struct QueryTable<K, V> {
+    memos: RefCell<HashMap<K, Memo<V>>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone + PartialEq> QueryTable<K, V> {
+    pub fn new() -> Self {
+        Self {
+            memos: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Gets the value of the query, computing it unless the memoized value
+    /// is up to date. It is if none of the queries it was computed from
+    /// has changed since it was last verified, which is checked by bringing
+    /// them up to date first.
+    pub fn get(
+        &self,
+        db: &Database,
+        key: &K,
+        query: QueryKey,
+        compute: impl FnOnce(&Database) -> V,
+    ) -> V {
+        let revision = db.runtime.revision();
+        let memo = self
+            .memos
+            .borrow()
+            .get(key)
+            .map(|memo| (memo.verified_at, memo.dependencies.clone()));
+        if let Some((verified_at, dependencies)) = memo {
+            if verified_at == revision
+                || dependencies
+                    .iter()
+                    .all(|dependency| db.refresh(dependency) <= verified_at)
+            {
+                let mut memos = self.memos.borrow_mut();
+                let memo = memos.get_mut(key).unwrap();
+                memo.verified_at = revision;
+                db.runtime.record(query);
+                return memo.value.clone();
+            }
+        }
+
+        db.runtime.enter(query.clone());
+        let value = compute(db);
+        let dependencies = db.runtime.exit();
+        let mut memos = self.memos.borrow_mut();
+        let changed_at = match memos.get(key) {
+            Some(memo) if memo.value == value => memo.changed_at,
+            _ => revision,
+        };
+        memos.insert(
+            key.clone(),
+            Memo {
+                value: value.clone(),
+                changed_at,
+                verified_at: revision,
+                dependencies,
+            },
+        );
+        db.runtime.record(query);
+        value
+    }
+
+    /// Gets the memoized value, whether or not it is up to date, without
+    /// recording the query.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.memos.borrow().get(key).map(|memo| memo.value.clone())
+    }
+
+    /// Gets the revision the memoized value last changed in.
+    pub fn changed_at(&self, key: &K) -> Revision {
+        self.memos.borrow()[key].changed_at
+    }
+}
+
+/// Tracks the revision and the dependencies of the queries being computed.
+pub struct Runtime {
+    revision: Cell<Revision>,
+    /// The queries being computed with the queries they read so far,
+    /// innermost last.
+    stack: RefCell<Vec<(QueryKey, Vec<QueryKey>)>>,
+    /// The queries computed rather than reused, in order.
+    #[cfg(test)]
+    pub executed: RefCell<Vec<QueryKey>>,
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Self {
+            revision: Cell::new(Revision::FIRST),
+            stack: RefCell::new(Vec::new()),
+            #[cfg(test)]
+            executed: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn revision(&self) -> Revision {
+        self.revision.get()
+    }
+
+    /// Starts a new revision after an input has changed.
+    pub fn bump_revision(&self) -> Revision {
+        let revision = Revision(self.revision.get().0 + 1);
+        self.revision.set(revision);
+        revision
+    }
+
+    /// Records that the query being computed reads the other query.
+    pub fn record(&self, query: QueryKey) {
+        if let Some((_, dependencies)) = self.stack.borrow_mut().last_mut() {
+            if !dependencies.contains(&query) {
+                dependencies.push(query);
+            }
+        }
+    }
+
+    /// Starts computing the query. The queries cannot depend on themselves.
+    pub fn enter(&self, query: QueryKey) {
+        let mut stack = self.stack.borrow_mut();
+        if stack.iter().any(|(active, _)| *active == query) {
+            panic!("the query {:?} depends on itself", query);
+        }
+        #[cfg(test)]
+        self.executed.borrow_mut().push(query.clone());
+        stack.push((query, Vec::new()));
+    }
+
+    /// Finishes computing the innermost query, returning its dependencies.
+    pub fn exit(&self) -> Vec<QueryKey> {
+        self.stack.borrow_mut().pop().unwrap().1
+    }
+
+    /// Runs the function without recording the queries it reads in the
+    /// query being computed.
+    pub fn untracked<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.stack
+            .borrow_mut()
+            .push((QueryKey::Untracked, Vec::new()));
+        let result = f();
+        self.stack.borrow_mut().pop();
+        result
+    }
+}
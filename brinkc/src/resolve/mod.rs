@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{node_id::NodeId, Visibility},
+    frontend::parser::Fixity,
+    source_file::{FileId, SourceSpan},
+};
+
+pub mod module_graph;
+mod resolver;
+
+pub use module_graph::{FileModule, ModuleGraph, ModuleSource, PRELUDE_NAME};
+pub use resolver::{resolve, resolve_file_module, PartialResolutions};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ModuleId(u32);
+
+impl ModuleId {
+    pub fn new(index: usize) -> ModuleId {
+        ModuleId(index as u32)
+    }
+
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Identifies a module-level definition.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct DefId(u32);
+
+impl DefId {
+    pub fn new(index: usize) -> DefId {
+        DefId(index as u32)
+    }
+
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DefKind {
+    Value,
+    Type,
+    /// A constructor of the variant type.
+    Constructor(DefId),
+    /// A field of the record type.
+    Field(DefId),
+    /// An exception, which is a constructor of the `exn` type.
+    Exception,
+}
+
+/// The types built into the language.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PrimTy {
+    Int,
+    Bool,
+    String,
+    Unit,
+    /// The immutable singly linked list, `list a`.
+    List,
+    /// The fixed-size array, `array a`.
+    Array,
+    /// The mutable cell, `ref a`.
+    Ref,
+    /// The type of the exceptions.
+    Exn,
+}
+
+impl PrimTy {
+    pub fn from_name(name: &str) -> Option<PrimTy> {
+        match name {
+            "int" => Some(PrimTy::Int),
+            "bool" => Some(PrimTy::Bool),
+            "string" => Some(PrimTy::String),
+            "unit" => Some(PrimTy::Unit),
+            "list" => Some(PrimTy::List),
+            "array" => Some(PrimTy::Array),
+            "ref" => Some(PrimTy::Ref),
+            "exn" => Some(PrimTy::Exn),
+            _ => None,
+        }
+    }
+
+    /// Gets the number of the type arguments the type takes.
+    pub fn parameters(self) -> usize {
+        match self {
+            PrimTy::List | PrimTy::Array | PrimTy::Ref => 1,
+            PrimTy::Int | PrimTy::Bool | PrimTy::String | PrimTy::Unit | PrimTy::Exn => 0,
+        }
+    }
+}
+
+/// The values built into the language. They can be shadowed by the user
+/// definitions. Most of them are the primitives the standard library is
+/// built on, and are meant to be used through it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Builtin {
+    /// Creates a new reference, `ref : 'a -> ref 'a`.
+    Ref,
+    /// Raises the exception, `raise : exn -> 'a`.
+    Raise,
+    /// Compares the values structurally, `compare : 'a -> 'a -> int`. The
+    /// result is negative, zero or positive.
+    Compare,
+    /// `string_length : string -> int`, in bytes.
+    StringLength,
+    /// `string_get : string -> int -> int`, the byte at the index.
+    StringGet,
+    /// `string_sub : string -> int -> int -> string`, the substring at the
+    /// byte index of the byte length.
+    StringSub,
+    /// `string_concat : string -> string -> string`.
+    StringConcat,
+    /// `string_concat_list : string list -> string`, the strings of the
+    /// list one after another, built in one pass.
+    StringConcatList,
+    /// `string_of_char_code : int -> string`, the character of the code
+    /// point.
+    StringOfCharCode,
+    /// `string_of_int : int -> string`.
+    StringOfInt,
+    /// Formats the arguments, e.g. `sprintf "%s = %d" name value`. Its type
+    /// is derived from the format, which has to be a string literal.
+    Sprintf,
+    /// `int_of_string : string -> int`, raising `Failure` if the string is
+    /// not a decimal integer.
+    IntOfString,
+    /// `print_string : string -> unit`, to the standard output.
+    PrintString,
+    /// `eprint_string : string -> unit`, to the standard error.
+    EprintString,
+    /// `read_line : unit -> string`, from the standard input without the
+    /// line ending, raising `EndOfFile` at its end.
+    ReadLine,
+    /// `read_file : string -> string`, raising `Failure` on an error.
+    ReadFile,
+    /// `write_file : string -> string -> unit`, raising `Failure` on an
+    /// error.
+    WriteFile,
+    /// The exception raised by the division by zero.
+    DivisionByZero,
+    /// The exception raised when no match arm matches the value.
+    MatchFailure,
+    /// The exception raised when the calls are nested too deeply.
+    StackOverflow,
+    /// The exception raised by a failed operation, with a message.
+    Failure,
+    /// The exception raised for an invalid argument, e.g. an index out of
+    /// bounds, with the name of the function.
+    InvalidArgument,
+    /// The exception raised when a searched element is missing.
+    NotFound,
+    /// The exception raised when reading past the end of the input.
+    EndOfFile,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin::Ref,
+    Builtin::Raise,
+    Builtin::Compare,
+    Builtin::StringLength,
+    Builtin::StringGet,
+    Builtin::StringSub,
+    Builtin::StringConcat,
+    Builtin::StringConcatList,
+    Builtin::StringOfCharCode,
+    Builtin::StringOfInt,
+    Builtin::Sprintf,
+    Builtin::IntOfString,
+    Builtin::PrintString,
+    Builtin::EprintString,
+    Builtin::ReadLine,
+    Builtin::ReadFile,
+    Builtin::WriteFile,
+    Builtin::DivisionByZero,
+    Builtin::MatchFailure,
+    Builtin::StackOverflow,
+    Builtin::Failure,
+    Builtin::InvalidArgument,
+    Builtin::NotFound,
+    Builtin::EndOfFile,
+];
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        BUILTINS
+            .iter()
+            .copied()
+            .find(|builtin| builtin.name() == name)
+    }
+
+    /// Gets the builtin of the discriminant, e.g. read from a bytecode file.
+    pub fn from_index(index: usize) -> Option<Builtin> {
+        BUILTINS
+            .iter()
+            .copied()
+            .find(|builtin| *builtin as usize == index)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Ref => "ref",
+            Builtin::Raise => "raise",
+            Builtin::Compare => "compare",
+            Builtin::StringLength => "string_length",
+            Builtin::StringGet => "string_get",
+            Builtin::StringSub => "string_sub",
+            Builtin::StringConcat => "string_concat",
+            Builtin::StringConcatList => "string_concat_list",
+            Builtin::StringOfCharCode => "string_of_char_code",
+            Builtin::StringOfInt => "string_of_int",
+            Builtin::Sprintf => "sprintf",
+            Builtin::IntOfString => "int_of_string",
+            Builtin::PrintString => "print_string",
+            Builtin::EprintString => "eprint_string",
+            Builtin::ReadLine => "read_line",
+            Builtin::ReadFile => "read_file",
+            Builtin::WriteFile => "write_file",
+            Builtin::DivisionByZero => "DivisionByZero",
+            Builtin::MatchFailure => "MatchFailure",
+            Builtin::StackOverflow => "StackOverflow",
+            Builtin::Failure => "Failure",
+            Builtin::InvalidArgument => "InvalidArgument",
+            Builtin::NotFound => "NotFound",
+            Builtin::EndOfFile => "EndOfFile",
+        }
+    }
+
+    /// Gets the number of the arguments the builtin takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::DivisionByZero
+            | Builtin::MatchFailure
+            | Builtin::StackOverflow
+            | Builtin::NotFound
+            | Builtin::EndOfFile => 0,
+            Builtin::Ref
+            | Builtin::Raise
+            | Builtin::StringLength
+            | Builtin::StringConcatList
+            | Builtin::StringOfCharCode
+            | Builtin::StringOfInt
+            | Builtin::Sprintf
+            | Builtin::IntOfString
+            | Builtin::PrintString
+            | Builtin::EprintString
+            | Builtin::ReadLine
+            | Builtin::ReadFile
+            | Builtin::Failure
+            | Builtin::InvalidArgument => 1,
+            Builtin::Compare | Builtin::StringGet | Builtin::StringConcat | Builtin::WriteFile => 2,
+            Builtin::StringSub => 3,
+        }
+    }
+
+    /// Checks whether the builtin is an exception constructor.
+    pub fn is_exception(self) -> bool {
+        self.name().starts_with(char::is_uppercase)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefKind,
+    pub visibility: Visibility,
+    /// The module the definition belongs to.
+    pub module: ModuleId,
+    /// The AST node introducing the definition, e.g. a `LetBinding`.
+    pub node: NodeId,
+    /// Span of the defined identifier.
+    pub span: SourceSpan,
+}
+
+#[derive(Clone, Debug)]
+pub struct ModuleData {
+    pub name: String,
+    pub parent: Option<ModuleId>,
+    pub file: FileId,
+    pub visibility: Visibility,
+    /// Span of the module name, or the file start for the file modules.
+    pub span: SourceSpan,
+    /// The values and constructors defined in the module, by name. Shadowed
+    /// values are not reachable by name anymore.
+    pub values: HashMap<String, DefId>,
+    pub types: HashMap<String, DefId>,
+    /// The record fields, by name. A field shadows the fields of the same
+    /// name declared before in other record types.
+    pub fields: HashMap<String, DefId>,
+    /// The nested and imported modules, by name.
+    pub modules: HashMap<String, ModuleId>,
+}
+
+/// What a path refers to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Res {
+    Def(DefId),
+    /// A local binding, identified by the node of the bound identifier.
+    Local(NodeId),
+    Module(ModuleId),
+    PrimTy(PrimTy),
+    Builtin(Builtin),
+    /// A type parameter, identified by the node of the parameter identifier.
+    TyParam(NodeId),
+}
+
+/// The result of the name resolution: the module tree with all definitions,
+/// and a side table mapping every resolved path to its target.
+#[derive(Clone, Debug, Default)]
+pub struct Resolutions {
+    pub modules: Vec<ModuleData>,
+    pub defs: Vec<Definition>,
+    /// Keyed by the `Path` node ids.
+    pub paths: HashMap<NodeId, Res>,
+    /// Maps the nodes introducing definitions, e.g. `LetBinding`s, to the
+    /// definitions.
+    pub def_of_node: HashMap<NodeId, DefId>,
+    /// Maps the field name literals in record expressions, patterns and
+    /// field accesses to the field definitions.
+    pub fields: HashMap<NodeId, DefId>,
+    /// Maps the bindings in the non-first alternatives of or-patterns to the
+    /// corresponding bindings in the first alternative, which are the ones
+    /// the paths resolve to.
+    pub or_bindings: HashMap<NodeId, NodeId>,
+    /// The local bindings captured by each closure, in order of the first
+    /// use. Keyed by the lambda expression ids and the ids of the local
+    /// `LetBinding`s with parameters. A recursive local function does not
+    /// capture itself.
+    pub free_variables: HashMap<NodeId, Vec<NodeId>>,
+    /// The module implicitly opened in all the other file modules.
+    pub prelude: Option<ModuleId>,
+    /// The fixities declared in each file, which the operators defined in
+    /// the file must be parsed with in the other files too.
+    pub fixities: HashMap<FileId, HashMap<String, Fixity>>,
+}
+
+impl Resolutions {
+    pub fn module(&self, id: ModuleId) -> &ModuleData {
+        &self.modules[id.as_usize()]
+    }
+
+    pub fn def(&self, id: DefId) -> &Definition {
+        &self.defs[id.as_usize()]
+    }
+
+    /// Checks whether the definition is the one of the name in the prelude.
+    pub fn is_prelude_def(&self, id: DefId, name: &str) -> bool {
+        let def = self.def(id);
+        Some(def.module) == self.prelude && def.name == name
+    }
+
+    /// Formats the fully qualified name of the module, e.g. `Main.Inner`.
+    pub fn module_path(&self, id: ModuleId) -> String {
+        let module = self.module(id);
+        match module.parent {
+            Some(parent) => format!("{}.{}", self.module_path(parent), module.name),
+            None => module.name.clone(),
+        }
+    }
+
+    /// Checks whether the item with the visibility, declared in the module,
+    /// can be referred to from the other module. Private items are visible
+    /// in their module and all the modules nested in it.
+    pub fn is_accessible(&self, visibility: Visibility, owner: ModuleId, from: ModuleId) -> bool {
+        if visibility == Visibility::Public {
+            return true;
+        }
+        let mut current = Some(from);
+        while let Some(module) = current {
+            if module == owner {
+                return true;
+            }
+            current = self.module(module).parent;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{frontend::parse_session::ParseSession, source_file::SourceMap};
+
+    fn load_files(files: &[(&str, &str)]) -> (ParseSession, ModuleGraph) {
+        let files = files
+            .iter()
+            .map(|(path, source)| (Path::new(path).to_path_buf(), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut session = ParseSession::new(SourceMap::new());
+        let graph = ModuleGraph::load_with(&mut session, "src/main.bk", &|p| files.get(p).cloned())
+            .unwrap();
+        (session, graph)
+    }
+
+    fn resolve_files(files: &[(&str, &str)]) -> (ParseSession, Resolutions) {
+        let (mut session, graph) = load_files(files);
+        let resolutions = resolve(&mut session, &graph);
+        (session, resolutions)
+    }
+
+    #[test]
+    fn resolves_qualified_path_into_imported_file() {
+        let (session, resolutions) = resolve_files(&[
+            ("src/main.bk", "import List\nlet x = List.map 1\n"),
+            ("src/list.bk", "pub let map x = x\n"),
+        ]);
+
+        assert!(!session.has_errors());
+        let map = resolutions
+            .defs
+            .iter()
+            .position(|d| d.name == "map")
+            .unwrap();
+        assert!(resolutions
+            .paths
+            .values()
+            .any(|res| *res == Res::Def(DefId(map as u32))));
+    }
+
+    #[test]
+    fn opens_nested_module() {
+        let (session, _) = resolve_files(&[(
+            "src/main.bk",
+            "module Inner =\n  pub let y = 1\nopen Inner\nlet x = y\n",
+        )]);
+
+        assert!(!session.has_errors());
+    }
+
+    #[test]
+    fn reports_private_definition() {
+        let (session, _) = resolve_files(&[
+            ("src/main.bk", "import Util\nlet x = Util.secret\n"),
+            ("src/util.bk", "let secret = 1\n"),
+        ]);
+
+        assert_eq!(1, session.error_count());
+    }
+
+    #[test]
+    fn allows_private_definition_in_nested_module() {
+        let (session, _) = resolve_files(&[(
+            "src/main.bk",
+            "let secret = 1\nmodule Inner =\n  let x = secret\n",
+        )]);
+
+        assert!(!session.has_errors());
+    }
+
+    #[test]
+    fn reports_unresolved_name() {
+        let (session, _) = resolve_files(&[("src/main.bk", "let f x = x y\n")]);
+
+        assert_eq!(1, session.error_count());
+    }
+
+    #[test]
+    fn resolves_variant_and_record_types() {
+        let (session, _) = resolve_files(&[(
+            "src/main.bk",
+            "type Option a = None | Some a\n\
+             type Point =\n  { x: int\n    y: Option int }\n\
+             let p = { x = 1; y = Some 2 }\n\
+             let q = { p with x = p.x }\n\
+             let f o =\n  match o\n  | { x; y = Some _ } | { x; y = None } -> x\n  | _ -> 0\n",
+        )]);
+
+        assert!(!session.has_errors());
+    }
+
+    #[test]
+    fn reports_inconsistent_or_pattern() {
+        let (session, _) = resolve_files(&[(
+            "src/main.bk",
+            "type T = A int | B int\nlet f t =\n  match t\n  | A x | B y -> 1\n",
+        )]);
+
+        assert_eq!(1, session.error_count());
+    }
+
+    #[test]
+    fn collects_free_variables_of_closures() {
+        let (session, resolutions) = resolve_files(&[(
+            "src/main.bk",
+            "let f a b =\n  let g x = fun y -> x + y + a\n  let rec loop n = loop (n + b)\n  g\n",
+        )]);
+
+        assert!(!session.has_errors());
+        let mut counts = resolutions
+            .free_variables
+            .values()
+            .map(|v| v.len())
+            .collect::<Vec<_>>();
+        counts.sort_unstable();
+        // `loop` captures `b`, `g` captures `a`, the lambda `x` and `a`.
+        assert_eq!(vec![1, 1, 2], counts);
+    }
+
+    #[test]
+    fn checks_tail_calls_are_in_tail_position() {
+        let (session, _) = resolve_files(&[(
+            "src/main.bk",
+            "let rec f n =\n  if n = 0 then 0\n  else\n    let m = n - 1\n    match m\n    | 1 -> @tailcall f 0\n    | _ -> (@tailcall f m : int)\n\
+             let g n = try f n with | _ -> @tailcall f 0\n\
+             let h = fun n -> @tailcall f n\n",
+        )]);
+        assert!(!session.has_errors());
+
+        let (session, _) = resolve_files(&[(
+            "src/main.bk",
+            "let rec f n =\n  let x = @tailcall f n\n  @tailcall f (n - 1) + 1\n\
+             let g n = try @tailcall f n with | _ -> 0\n\
+             let h = @tailcall f 1\n",
+        )]);
+        assert_eq!(4, session.error_count());
+    }
+
+    #[test]
+    fn checks_fixity_of_imported_operators() {
+        let ops = ("src/ops.bk", "infixr 2 <+>\npub let (<+>) a b = a - b\n");
+        let (session, _) =
+            resolve_files(&[("src/main.bk", "open Ops\nlet x = 10 <+> 5 <+> 2\n"), ops]);
+        assert_eq!(2, session.error_count());
+
+        let (session, _) = resolve_files(&[
+            (
+                "src/main.bk",
+                "open Ops\ninfixr 2 <+>\nlet x = 10 <+> 5 <+> 2 |> fun x -> x\n",
+            ),
+            ops,
+        ]);
+        assert!(!session.has_errors());
+    }
+
+    #[test]
+    fn detects_import_cycle() {
+        let (session, _) = load_files(&[
+            ("src/main.bk", "import A\n"),
+            ("src/a.bk", "import B\n"),
+            ("src/b.bk", "import A\n"),
+        ]);
+
+        assert_eq!(1, session.error_count());
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    ast,
+    frontend::{self, parse_session::ParseSession},
+    source_file::{FileId, SourceSpan},
+};
+
+/// The name of the module implicitly opened in every other module.
+pub const PRELUDE_NAME: &str = "Prelude";
+
+/// The source code of the prelude, embedded in the compiler.
+const PRELUDE_SOURCE: &str = include_str!("../../lib/prelude.bk");
+
+/// The standard library modules embedded in the compiler, with their file
+/// names and source code. They are used when the directory of the root file
+/// does not define a module of the same name.
+const STDLIB_MODULES: &[(&str, &str, &str)] = &[
+    ("IO", "io.bk", include_str!("../../lib/io.bk")),
+    ("List", "list.bk", include_str!("../../lib/list.bk")),
+    ("Map", "map.bk", include_str!("../../lib/map.bk")),
+    ("Option", "option.bk", include_str!("../../lib/option.bk")),
+    ("Result", "result.bk", include_str!("../../lib/result.bk")),
+    ("Set", "set.bk", include_str!("../../lib/set.bk")),
+    ("String", "string.bk", include_str!("../../lib/string.bk")),
+];
+
+/// Where the source code of a file module comes from.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ModuleSource {
+    /// The prelude, embedded in the compiler.
+    Prelude,
+    /// A standard library module embedded in the compiler, by file name.
+    Std(&'static str),
+    File(PathBuf),
+}
+
+impl ModuleSource {
+    /// Gets the path the file is presented with in the diagnostics.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            ModuleSource::Prelude => PathBuf::from("prelude.bk"),
+            ModuleSource::Std(file_name) => Path::new("std").join(file_name),
+            ModuleSource::File(path) => path.clone(),
+        }
+    }
+
+    /// Gets the source code embedded in the compiler, if the file is not
+    /// read from disk.
+    pub fn embedded_source(&self) -> Option<&'static str> {
+        match self {
+            ModuleSource::Prelude => Some(PRELUDE_SOURCE),
+            ModuleSource::Std(file_name) => STDLIB_MODULES
+                .iter()
+                .find(|(_, name, _)| name == file_name)
+                .map(|(_, _, source_code)| *source_code),
+            ModuleSource::File(_) => None,
+        }
+    }
+}
+
+/// A module defined by a single source file.
+#[derive(Clone)]
+pub struct FileModule {
+    /// Name of the module, derived from the file name: `list_utils.bk`
+    /// defines the `List_utils` module.
+    pub name: String,
+    pub source: ModuleSource,
+    pub file: FileId,
+    /// Shared with the query database, which keeps the parsed files.
+    pub program: Arc<ast::Program>,
+}
+
+/// All the source files taking part in the compilation, discovered by
+/// following the `import` and `open` items starting from the root file.
+#[derive(Clone)]
+pub struct ModuleGraph {
+    /// The file modules, sorted so every module comes after all the modules
+    /// it depends on. The prelude is always the first one, and the root
+    /// module the last one.
+    pub modules: Vec<FileModule>,
+}
+
+/// A parsed file, without the program if its indentation is malformed.
+pub type ParsedFile = (FileId, Option<Arc<ast::Program>>);
+
+/// Reads and parses the files for the module graph, either directly into
+/// the session or through the query database.
+pub trait FileLoader {
+    /// Parses the file, returning `None` if it does not exist.
+    fn parse(&mut self, source: &ModuleSource) -> Option<ParsedFile>;
+
+    fn snippet(&self, span: SourceSpan) -> String;
+
+    fn error(&mut self, span: SourceSpan, message: String);
+}
+
+/// Loads the files into the session, reading them with the function.
+struct SessionLoader<'a> {
+    session: &'a mut ParseSession,
+    read_file: &'a dyn Fn(&Path) -> Option<String>,
+}
+
+impl FileLoader for SessionLoader<'_> {
+    fn parse(&mut self, source: &ModuleSource) -> Option<ParsedFile> {
+        let source_code = match source.embedded_source() {
+            Some(source_code) => source_code.to_string(),
+            None => (self.read_file)(&source.path())?,
+        };
+        let file = self
+            .session
+            .source_map
+            .add_file(source.path().display().to_string(), source_code);
+        let program = match source {
+            ModuleSource::Prelude => frontend::parse_prelude(self.session, file),
+            _ => frontend::parse_file(self.session, file),
+        };
+        Some((file, program.map(Arc::new)))
+    }
+
+    fn snippet(&self, span: SourceSpan) -> String {
+        self.session.source_map.span_to_snippet(span).to_string()
+    }
+
+    fn error(&mut self, span: SourceSpan, message: String) {
+        self.session.error(span, message);
+    }
+}
+
+enum VisitState {
+    InProgress,
+    Done,
+}
+
+struct Loader<'a> {
+    files: &'a mut dyn FileLoader,
+    directory: PathBuf,
+    states: HashMap<String, VisitState>,
+    /// Names of the modules currently being loaded, outermost first.
+    stack: Vec<String>,
+    modules: Vec<FileModule>,
+}
+
+impl ModuleGraph {
+    /// Loads the root file and all of its dependencies from disk. The files
+    /// are looked up in the directory of the root file.
+    pub fn load(session: &mut ParseSession, root_path: &str) -> Result<ModuleGraph, String> {
+        Self::load_with(session, root_path, &|path| {
+            std::fs::read_to_string(path).ok()
+        })
+    }
+
+    pub fn load_with(
+        session: &mut ParseSession,
+        root_path: &str,
+        read_file: &dyn Fn(&Path) -> Option<String>,
+    ) -> Result<ModuleGraph, String> {
+        Self::load_from(
+            &mut SessionLoader { session, read_file },
+            Path::new(root_path),
+        )
+    }
+
+    pub fn load_from(files: &mut dyn FileLoader, root_path: &Path) -> Result<ModuleGraph, String> {
+        let mut loader = Loader {
+            files,
+            directory: root_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            states: HashMap::new(),
+            stack: Vec::new(),
+            modules: Vec::new(),
+        };
+        loader.load_prelude();
+        let root = ModuleSource::File(root_path.to_path_buf());
+        match loader.files.parse(&root) {
+            Some(parsed) => loader.load_module(module_name(root_path), root, parsed),
+            None => {
+                return Err(format!(
+                    "error: failed to open the source file \"{}\"",
+                    root_path.display()
+                ))
+            }
+        }
+
+        Ok(ModuleGraph {
+            modules: loader.modules,
+        })
+    }
+
+    pub fn root(&self) -> &FileModule {
+        self.modules.last().unwrap()
+    }
+}
+
+impl<'a> Loader<'a> {
+    /// Loads the prelude through the same frontend as the other files. It
+    /// cannot import other modules.
+    fn load_prelude(&mut self) {
+        if let Some((file, Some(program))) = self.files.parse(&ModuleSource::Prelude) {
+            self.modules.push(FileModule {
+                name: PRELUDE_NAME.to_string(),
+                source: ModuleSource::Prelude,
+                file,
+                program,
+            });
+        }
+        self.states
+            .insert(PRELUDE_NAME.to_string(), VisitState::Done);
+    }
+
+    fn load_module(&mut self, name: String, source: ModuleSource, (file, program): ParsedFile) {
+        let program = match program {
+            Some(program) => program,
+            None => {
+                self.states.insert(name, VisitState::Done);
+                return;
+            }
+        };
+
+        self.states.insert(name.clone(), VisitState::InProgress);
+        self.stack.push(name.clone());
+
+        let mut dependencies = Vec::new();
+        let mut nested_modules = Vec::new();
+        collect_dependencies(&program.body, &mut dependencies, &mut nested_modules);
+        let nested_modules = nested_modules
+            .into_iter()
+            .map(|span| self.files.snippet(span))
+            .collect::<Vec<_>>();
+        for (kind, span) in dependencies {
+            let dependency = self.files.snippet(span);
+            if !nested_modules.contains(&dependency) {
+                self.visit_dependency(dependency, kind, span);
+            }
+        }
+
+        self.stack.pop();
+        self.states.insert(name.clone(), VisitState::Done);
+        self.modules.push(FileModule {
+            name,
+            source,
+            file,
+            program,
+        });
+    }
+
+    fn visit_dependency(&mut self, name: String, kind: DependencyKind, span: SourceSpan) {
+        match self.states.get(&name) {
+            Some(VisitState::Done) => {}
+            Some(VisitState::InProgress) => {
+                let cycle_start = self.stack.iter().position(|m| *m == name).unwrap();
+                let mut cycle = self.stack[cycle_start..].to_vec();
+                cycle.push(name);
+                self.files.error(
+                    span,
+                    format!("import cycle detected: {}", cycle.join(" -> ")),
+                );
+            }
+            None => match self.find_module_file(&name) {
+                Some((source, parsed)) => self.load_module(name, source, parsed),
+                // An opened module does not have to be a file, it may be
+                // declared in the current file. It is up to the resolver
+                // to report it if it is not.
+                None if kind == DependencyKind::Open => {}
+                None => self.files.error(
+                    span,
+                    format!(
+                        "cannot find the file of module `{}` in \"{}\"",
+                        name,
+                        self.directory.display()
+                    ),
+                ),
+            },
+        }
+    }
+
+    /// Looks for the file defining the module, trying both the exact and
+    /// the lowercase first letter file names, then the standard library.
+    fn find_module_file(&mut self, name: &str) -> Option<(ModuleSource, ParsedFile)> {
+        let mut chars = name.chars();
+        let first = chars.next()?;
+        let lowercase = first.to_lowercase().chain(chars).collect::<String>();
+
+        for file_name in &[format!("{}.bk", lowercase), format!("{}.bk", name)] {
+            let source = ModuleSource::File(self.directory.join(file_name));
+            if let Some(parsed) = self.files.parse(&source) {
+                return Some((source, parsed));
+            }
+        }
+        let (_, file_name, _) = STDLIB_MODULES
+            .iter()
+            .find(|(module, _, _)| *module == name)?;
+        let source = ModuleSource::Std(file_name);
+        let parsed = self.files.parse(&source)?;
+        Some((source, parsed))
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum DependencyKind {
+    Import,
+    Open,
+}
+
+/// Collects the first segments of all the imported and opened module paths,
+/// as only the outermost module of a path may be a file, and the names of
+/// all the modules nested in the file.
+fn collect_dependencies(
+    items: &[ast::Item],
+    dependencies: &mut Vec<(DependencyKind, SourceSpan)>,
+    nested_modules: &mut Vec<SourceSpan>,
+) {
+    for item in items {
+        match &item.kind {
+            ast::ItemKind::Import(path) => {
+                dependencies.push((DependencyKind::Import, path.segments[0].span))
+            }
+            ast::ItemKind::Open(path) => {
+                dependencies.push((DependencyKind::Open, path.segments[0].span))
+            }
+            ast::ItemKind::Module(module) => {
+                nested_modules.push(module.identifier.span);
+                collect_dependencies(&module.body.items, dependencies, nested_modules)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn module_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut chars = stem.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => stem,
+    }
+}
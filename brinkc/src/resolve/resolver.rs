@@ -0,0 +1,1115 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{self, node_id::NodeId, Visibility},
+    frontend::{
+        parse_session::ParseSession,
+        parser::{Fixity, DEFAULT_FIXITY},
+    },
+    source_file::{FileId, SourceSpan},
+};
+
+use super::{
+    Builtin, DefId, DefKind, Definition, FileModule, ModuleData, ModuleGraph, ModuleId, PrimTy,
+    Res, Resolutions, PRELUDE_NAME,
+};
+
+/// Resolves all the paths in the module graph to the definitions and local
+/// bindings they refer to. Unresolved paths are reported and left out of
+/// the side table.
+pub fn resolve(session: &mut ParseSession, graph: &ModuleGraph) -> Resolutions {
+    let mut partial = PartialResolutions::default();
+    for module in &graph.modules {
+        resolve_file_module(session, &mut partial, module);
+    }
+    partial.resolutions
+}
+
+/// The resolutions of the file modules resolved so far, which the next
+/// file modules are resolved on top of.
+#[derive(Clone, Debug, Default)]
+pub struct PartialResolutions {
+    pub resolutions: Resolutions,
+    file_modules: HashMap<String, ModuleId>,
+    imports: Vec<HashMap<String, ModuleId>>,
+    opens: Vec<Vec<ModuleId>>,
+}
+
+/// Resolves the file module after all the modules it depends on, adding
+/// its definitions to the partial resolutions.
+pub fn resolve_file_module(
+    session: &mut ParseSession,
+    partial: &mut PartialResolutions,
+    module: &FileModule,
+) {
+    let mut resolver = Resolver {
+        session,
+        resolutions: std::mem::take(&mut partial.resolutions),
+        file_modules: std::mem::take(&mut partial.file_modules),
+        imports: std::mem::take(&mut partial.imports),
+        opens: std::mem::take(&mut partial.opens),
+        scopes: Vec::new(),
+        closures: Vec::new(),
+        type_parameters: HashMap::new(),
+        in_type_decl: false,
+        current_module: ModuleId(0),
+        tail: false,
+    };
+
+    let start = resolver.session.source_map.file(module.file).start_pos;
+    let id = resolver.add_module(
+        module.name.clone(),
+        None,
+        module.file,
+        Visibility::Public,
+        SourceSpan::new(start, start),
+    );
+    resolver.file_modules.insert(module.name.clone(), id);
+    if module.name == PRELUDE_NAME {
+        resolver.resolutions.prelude = Some(id);
+    } else if let Some(prelude) = resolver.resolutions.prelude {
+        // The prelude is opened before the `open` items, so the modules
+        // they open shadow it.
+        resolver.opens[id.as_usize()].push(prelude);
+        resolver.imports[id.as_usize()].insert(PRELUDE_NAME.to_string(), prelude);
+    }
+    resolver.current_module = id;
+    resolver.resolve_module_items(&module.program.body);
+
+    partial.resolutions = resolver.resolutions;
+    partial.file_modules = resolver.file_modules;
+    partial.imports = resolver.imports;
+    partial.opens = resolver.opens;
+}
+
+struct Resolver<'a> {
+    session: &'a mut ParseSession,
+    resolutions: Resolutions,
+    /// The modules defined by the source files, by name.
+    file_modules: HashMap<String, ModuleId>,
+    /// The file modules imported into each module. Imports are private
+    /// to the importing module and the modules nested in it.
+    imports: Vec<HashMap<String, ModuleId>>,
+    /// The modules opened in each module, in order of the `open` items.
+    opens: Vec<Vec<ModuleId>>,
+    /// The local bindings, innermost scope last.
+    scopes: Vec<HashMap<String, NodeId>>,
+    /// The closures being resolved, innermost last.
+    closures: Vec<Closure>,
+    /// The type parameters of the type declaration being resolved.
+    type_parameters: HashMap<String, NodeId>,
+    in_type_decl: bool,
+    current_module: ModuleId,
+    /// Whether the expression resolved next is in tail position, so its
+    /// value is returned from the enclosing function, and `@tailcall` is
+    /// allowed there.
+    tail: bool,
+}
+
+struct Closure {
+    node: NodeId,
+    /// The number of the scopes outside of the closure.
+    outer_scopes: usize,
+    /// The binding of the recursive local function itself.
+    self_binding: Option<NodeId>,
+    free_variables: Vec<NodeId>,
+}
+
+impl<'a> Resolver<'a> {
+    fn add_module(
+        &mut self,
+        name: String,
+        parent: Option<ModuleId>,
+        file: FileId,
+        visibility: Visibility,
+        span: SourceSpan,
+    ) -> ModuleId {
+        let id = ModuleId(self.resolutions.modules.len() as u32);
+        self.resolutions.modules.push(ModuleData {
+            name,
+            parent,
+            file,
+            visibility,
+            span,
+            values: HashMap::new(),
+            types: HashMap::new(),
+            fields: HashMap::new(),
+            modules: HashMap::new(),
+        });
+        self.imports.push(HashMap::new());
+        self.opens.push(Vec::new());
+        id
+    }
+
+    fn add_def(
+        &mut self,
+        name: String,
+        kind: DefKind,
+        visibility: Visibility,
+        node: NodeId,
+        span: SourceSpan,
+    ) -> DefId {
+        let id = DefId(self.resolutions.defs.len() as u32);
+        self.resolutions.defs.push(Definition {
+            name: name.clone(),
+            kind,
+            visibility,
+            module: self.current_module,
+            node,
+            span,
+        });
+        self.resolutions.def_of_node.insert(node, id);
+        let module = &mut self.resolutions.modules[self.current_module.as_usize()];
+        match kind {
+            DefKind::Value | DefKind::Constructor(_) | DefKind::Exception => {
+                module.values.insert(name, id)
+            }
+            DefKind::Type => module.types.insert(name, id),
+            DefKind::Field(_) => module.fields.insert(name, id),
+        };
+        id
+    }
+
+    fn resolve_module_items(&mut self, items: &[ast::Item]) {
+        // The types are defined first, so the types can refer to the ones
+        // declared after them, e.g. in mutually recursive types.
+        for item in items {
+            if let ast::ItemKind::Type(type_decl) = &item.kind {
+                let name = self.text(&type_decl.identifier);
+                self.add_def(
+                    name,
+                    DefKind::Type,
+                    item.visibility,
+                    type_decl.id,
+                    type_decl.identifier.span,
+                );
+            }
+        }
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    // A recursive binding is defined before its body, so the
+                    // body can refer to it.
+                    if !let_binding.is_recursive {
+                        self.resolve_let_binding_body(let_binding);
+                    }
+                    let name = self.text(&let_binding.identifier);
+                    self.add_def(
+                        name,
+                        DefKind::Value,
+                        item.visibility,
+                        let_binding.id,
+                        let_binding.identifier.span,
+                    );
+                    if let_binding.is_recursive {
+                        self.resolve_let_binding_body(let_binding);
+                    }
+                }
+                ast::ItemKind::LetRec(bindings) => {
+                    self.check_let_rec_names(bindings);
+                    for let_binding in bindings {
+                        let name = self.text(&let_binding.identifier);
+                        self.add_def(
+                            name,
+                            DefKind::Value,
+                            item.visibility,
+                            let_binding.id,
+                            let_binding.identifier.span,
+                        );
+                    }
+                    for let_binding in bindings {
+                        self.resolve_let_binding_body(let_binding);
+                    }
+                }
+                ast::ItemKind::Type(type_decl) => {
+                    self.resolve_type_decl(item.visibility, type_decl)
+                }
+                ast::ItemKind::Module(module) => self.resolve_module_decl(item.visibility, module),
+                ast::ItemKind::Open(path) => {
+                    if let Some(module) = self.resolve_module_path(&path.segments, true) {
+                        self.resolutions.paths.insert(path.id, Res::Module(module));
+                        self.opens[self.current_module.as_usize()].push(module);
+                        // Opening a file module imports it as well.
+                        if self.resolutions.module(module).parent.is_none() {
+                            let name = self.resolutions.module(module).name.clone();
+                            self.imports[self.current_module.as_usize()].insert(name, module);
+                        }
+                    }
+                }
+                ast::ItemKind::Import(path) => self.resolve_import(item.visibility, path),
+                ast::ItemKind::Exception(exception) => {
+                    // The arguments cannot refer to type variables.
+                    self.in_type_decl = true;
+                    for argument in &exception.arguments {
+                        self.resolve_ty(argument);
+                    }
+                    self.in_type_decl = false;
+                    let name = self.text(&exception.identifier);
+                    self.add_def(
+                        name,
+                        DefKind::Exception,
+                        item.visibility,
+                        exception.id,
+                        exception.identifier.span,
+                    );
+                }
+                // The fixities only affect the parsing, but are kept to
+                // check the uses of the operators in the other files.
+                ast::ItemKind::Fixity(decl) => {
+                    let fixity = Fixity::of_decl(decl, &self.session.source_map);
+                    let file = self.resolutions.module(self.current_module).file;
+                    for operator in &decl.operators {
+                        let name = self.text(operator);
+                        self.resolutions
+                            .fixities
+                            .entry(file)
+                            .or_default()
+                            .insert(name, fixity);
+                    }
+                }
+                ast::ItemKind::Expr(expr) => self.resolve_expr(expr),
+            }
+        }
+    }
+
+    /// Resolves the type defined at the start of its module, then defines
+    /// its constructors or fields. The constructors and fields share the
+    /// type's visibility.
+    fn resolve_type_decl(&mut self, visibility: Visibility, type_decl: &ast::TypeDecl) {
+        let name = self.text(&type_decl.identifier);
+        self.in_type_decl = true;
+        let ty = self.resolutions.def_of_node[&type_decl.id];
+        // A type declared again shadows the earlier one from there on.
+        self.resolutions.modules[self.current_module.as_usize()]
+            .types
+            .insert(name, ty);
+
+        for parameter in &type_decl.parameters {
+            let name = self.type_parameter_name(parameter);
+            if self
+                .type_parameters
+                .insert(name.clone(), parameter.id)
+                .is_some()
+            {
+                self.session.error(
+                    parameter.span,
+                    format!("type parameter `{}` is declared more than once", name),
+                );
+            }
+        }
+
+        match &type_decl.kind {
+            ast::TypeDeclKind::Variant(constructors) => {
+                for constructor in constructors {
+                    for argument in &constructor.arguments {
+                        self.resolve_ty(argument);
+                    }
+                    let name = self.text(&constructor.identifier);
+                    self.add_def(
+                        name,
+                        DefKind::Constructor(ty),
+                        visibility,
+                        constructor.id,
+                        constructor.identifier.span,
+                    );
+                }
+            }
+            ast::TypeDeclKind::Record(fields) => {
+                for field in fields {
+                    self.resolve_ty(&field.ty);
+                    let name = self.text(&field.identifier);
+                    self.add_def(
+                        name,
+                        DefKind::Field(ty),
+                        visibility,
+                        field.id,
+                        field.identifier.span,
+                    );
+                }
+            }
+            ast::TypeDeclKind::Alias(aliased) => self.resolve_ty(aliased),
+        }
+
+        self.type_parameters.clear();
+        self.in_type_decl = false;
+    }
+
+    fn resolve_ty(&mut self, ty: &ast::Ty) {
+        match &ty.kind {
+            ast::TyKind::Path(path, arguments) => {
+                self.resolve_type_path(path);
+                for argument in arguments {
+                    self.resolve_ty(argument);
+                }
+            }
+            ast::TyKind::Var(variable) => {
+                // Outside of type declarations, the type variables are
+                // implicitly declared by the annotations using them.
+                if self.in_type_decl {
+                    let name = self.type_parameter_name(variable);
+                    if !self.type_parameters.contains_key(&name) {
+                        self.session.error(
+                            variable.span,
+                            format!("cannot find type variable `'{}` in this scope", name),
+                        );
+                    }
+                }
+            }
+            ast::TyKind::Arrow(parameter, result) => {
+                self.resolve_ty(parameter);
+                self.resolve_ty(result);
+            }
+            ast::TyKind::Tuple(elements) => {
+                for element in elements {
+                    self.resolve_ty(element);
+                }
+            }
+            ast::TyKind::Paren(ty) => self.resolve_ty(ty),
+        }
+    }
+
+    /// Gets the name of the type parameter without the leading apostrophe,
+    /// so `type Option a` and `type Option 'a` declare the same parameter.
+    fn type_parameter_name(&self, parameter: &ast::Literal) -> String {
+        self.text(parameter).trim_start_matches('\'').to_string()
+    }
+
+    fn resolve_type_path(&mut self, path: &ast::Path) {
+        let (last, prefix) = path.segments.split_last().unwrap();
+        let name = self.text(last);
+
+        let res = if prefix.is_empty() {
+            if let Some(parameter) = self.type_parameters.get(&name) {
+                Res::TyParam(*parameter)
+            } else if let Some(def) = self.lookup_in_modules(&name, |m| &m.types) {
+                Res::Def(def)
+            } else if let Some(prim_ty) = PrimTy::from_name(&name) {
+                Res::PrimTy(prim_ty)
+            } else {
+                self.session.error(
+                    last.span,
+                    format!("cannot find type `{}` in this scope", name),
+                );
+                return;
+            }
+        } else {
+            let module = match self.resolve_module_path(prefix, false) {
+                Some(module) => module,
+                None => return,
+            };
+            match self.resolutions.module(module).types.get(&name) {
+                Some(def) => {
+                    let def = *def;
+                    self.check_def_accessible(def, last.span);
+                    Res::Def(def)
+                }
+                None => {
+                    let message = format!(
+                        "cannot find type `{}` in module `{}`",
+                        name,
+                        self.resolutions.module_path(module)
+                    );
+                    self.session.error(last.span, message);
+                    return;
+                }
+            }
+        };
+
+        self.resolutions.paths.insert(path.id, res);
+    }
+
+    fn resolve_module_decl(&mut self, visibility: Visibility, module: &ast::ModuleDecl) {
+        let name = self.text(&module.identifier);
+        let parent = self.current_module;
+        let file = self.resolutions.module(parent).file;
+        let id = self.add_module(
+            name.clone(),
+            Some(parent),
+            file,
+            visibility,
+            module.identifier.span,
+        );
+        self.resolutions.modules[parent.as_usize()]
+            .modules
+            .insert(name, id);
+
+        self.current_module = id;
+        self.resolve_module_items(&module.body.items);
+        self.current_module = parent;
+    }
+
+    fn resolve_import(&mut self, visibility: Visibility, path: &ast::Path) {
+        if visibility == Visibility::Public {
+            self.session
+                .error(path.span, "imports are private and cannot be re-exported");
+        }
+        if let Some(module) = self.resolve_module_path(&path.segments, true) {
+            self.resolutions.paths.insert(path.id, Res::Module(module));
+            let name = self.text(path.segments.last().unwrap());
+            self.imports[self.current_module.as_usize()].insert(name, module);
+        }
+    }
+
+    fn resolve_let_binding_body(&mut self, let_binding: &ast::LetBinding) {
+        // Only the functions can refer to themselves, as they do not use
+        // their own value before they are called.
+        let is_lambda = matches!(
+            &let_binding.body,
+            ast::LetBody::Expr(ast::Expr {
+                kind: ast::ExprKind::Lambda(..),
+                ..
+            })
+        );
+        if let_binding.is_recursive && let_binding.parameters.is_empty() && !is_lambda {
+            self.session.error(
+                let_binding.identifier.span,
+                "only functions can be recursive",
+            );
+        }
+        // The module-level functions cannot capture anything.
+        let is_closure = !self.scopes.is_empty() && !let_binding.parameters.is_empty();
+        if is_closure {
+            let self_binding = let_binding
+                .is_recursive
+                .then_some(let_binding.identifier.id);
+            self.enter_closure(let_binding.id, self_binding);
+        }
+        let mut scope = HashMap::new();
+        for parameter in &let_binding.parameters {
+            self.resolve_pattern(parameter, &mut scope);
+        }
+        if let Some(return_ty) = &let_binding.return_ty {
+            self.resolve_ty(return_ty);
+        }
+        self.scopes.push(scope);
+        self.tail = !let_binding.parameters.is_empty();
+        self.resolve_let_body(&let_binding.body);
+        self.scopes.pop();
+        if is_closure {
+            self.exit_closure();
+        }
+    }
+
+    /// Resolves the let binding scoped to a block or a let expression,
+    /// adding the bound identifier to the innermost scope.
+    fn resolve_local_let_binding(&mut self, let_binding: &ast::LetBinding) {
+        let name = self.text(&let_binding.identifier);
+        if let_binding.is_recursive {
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(name, let_binding.identifier.id);
+            self.resolve_let_binding_body(let_binding);
+        } else {
+            self.resolve_let_binding_body(let_binding);
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(name, let_binding.identifier.id);
+        }
+    }
+
+    /// Resolves the recursive group scoped to a block. The functions of the
+    /// group are in scope in all of their bodies.
+    fn resolve_local_let_rec(&mut self, bindings: &[ast::LetBinding]) {
+        self.check_let_rec_names(bindings);
+        for let_binding in bindings {
+            let name = self.text(&let_binding.identifier);
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(name, let_binding.identifier.id);
+        }
+        for let_binding in bindings {
+            self.resolve_let_binding_body(let_binding);
+        }
+    }
+
+    fn check_let_rec_names(&mut self, bindings: &[ast::LetBinding]) {
+        for (i, let_binding) in bindings.iter().enumerate() {
+            let name = self.text(&let_binding.identifier);
+            if bindings[..i]
+                .iter()
+                .any(|other| self.text(&other.identifier) == name)
+            {
+                self.session.error(
+                    let_binding.identifier.span,
+                    format!("`{}` is defined more than once in the group", name),
+                );
+            }
+        }
+    }
+
+    fn resolve_let_body(&mut self, body: &ast::LetBody) {
+        match body {
+            ast::LetBody::Block(block) => self.resolve_block(block),
+            ast::LetBody::Expr(expr) => self.resolve_expr(expr),
+        }
+    }
+
+    fn enter_closure(&mut self, node: NodeId, self_binding: Option<NodeId>) {
+        self.closures.push(Closure {
+            node,
+            outer_scopes: self.scopes.len(),
+            self_binding,
+            free_variables: Vec::new(),
+        });
+    }
+
+    fn exit_closure(&mut self) {
+        let closure = self.closures.pop().unwrap();
+        self.resolutions
+            .free_variables
+            .insert(closure.node, closure.free_variables);
+    }
+
+    /// Records the local binding from the scope as captured by the closures
+    /// it is referred to from.
+    fn capture(&mut self, node: NodeId, scope: usize) {
+        for closure in self.closures.iter_mut().rev() {
+            if closure.outer_scopes <= scope {
+                break;
+            }
+            if closure.self_binding != Some(node) && !closure.free_variables.contains(&node) {
+                closure.free_variables.push(node);
+            }
+        }
+    }
+
+    fn resolve_block(&mut self, block: &ast::Block) {
+        let tail = std::mem::take(&mut self.tail);
+        self.scopes.push(HashMap::new());
+        for (i, item) in block.items.iter().enumerate() {
+            if item.visibility == Visibility::Public {
+                self.session
+                    .error(item.span, "local definitions cannot be public");
+            }
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    self.resolve_local_let_binding(let_binding)
+                }
+                ast::ItemKind::LetRec(bindings) => self.resolve_local_let_rec(bindings),
+                ast::ItemKind::Expr(expr) => {
+                    self.tail = tail && i == block.items.len() - 1;
+                    self.resolve_expr(expr);
+                }
+                ast::ItemKind::Type(_) => {
+                    self.session
+                        .error(item.span, "types can only be declared at the module level");
+                }
+                ast::ItemKind::Exception(_) => {
+                    self.session.error(
+                        item.span,
+                        "exceptions can only be declared at the module level",
+                    );
+                }
+                ast::ItemKind::Fixity(_) => {
+                    self.session.error(
+                        item.span,
+                        "fixities can only be declared at the module level",
+                    );
+                }
+                ast::ItemKind::Module(_) | ast::ItemKind::Open(_) | ast::ItemKind::Import(_) => {
+                    self.session.error(
+                        item.span,
+                        "modules can only be declared, opened and imported at the module level",
+                    );
+                }
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_expr(&mut self, expr: &ast::Expr) {
+        // The subexpressions are only in tail position where stated below.
+        let tail = std::mem::take(&mut self.tail);
+        match &expr.kind {
+            ast::ExprKind::Literal(_) => {}
+            ast::ExprKind::Path(path) => self.resolve_value_path(path),
+            ast::ExprKind::Application(callee, arguments) => {
+                self.resolve_expr(callee);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+            ast::ExprKind::TailCall(call) => {
+                if !tail {
+                    self.session.error(
+                        expr.span,
+                        "the call annotated with `@tailcall` is not in tail position",
+                    );
+                }
+                self.resolve_expr(call);
+            }
+            ast::ExprKind::Paren(expr) => {
+                self.tail = tail;
+                self.resolve_expr(expr);
+            }
+            ast::ExprKind::Tuple(elements)
+            | ast::ExprKind::List(elements)
+            | ast::ExprKind::Array(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            ast::ExprKind::Record(fields) => self.resolve_field_exprs(fields),
+            ast::ExprKind::RecordUpdate(record, fields) => {
+                self.resolve_expr(record);
+                self.resolve_field_exprs(fields);
+            }
+            ast::ExprKind::FieldAccess(record, field) => {
+                self.resolve_expr(record);
+                self.resolve_field(field);
+            }
+            ast::ExprKind::Match(scrutinee, arms) => {
+                self.resolve_expr(scrutinee);
+                self.resolve_match_arms(arms, tail);
+            }
+            ast::ExprKind::If(condition, then_branch, else_branch) => {
+                self.resolve_expr(condition);
+                self.tail = tail;
+                self.resolve_let_body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.tail = tail;
+                    self.resolve_let_body(else_branch);
+                }
+            }
+            ast::ExprKind::Try(body, arms) => {
+                // Only the handlers are in tail position.
+                self.resolve_let_body(body);
+                self.resolve_match_arms(arms, tail);
+            }
+            ast::ExprKind::While(condition, body) => {
+                self.resolve_expr(condition);
+                self.resolve_let_body(body);
+            }
+            ast::ExprKind::For(for_loop) => {
+                self.resolve_expr(&for_loop.start);
+                self.resolve_expr(&for_loop.end);
+                let mut scope = HashMap::new();
+                self.bind(&for_loop.binding, &mut scope);
+                self.scopes.push(scope);
+                self.resolve_let_body(&for_loop.body);
+                self.scopes.pop();
+            }
+            ast::ExprKind::Lambda(parameters, body) => {
+                self.enter_closure(expr.id, None);
+                let mut scope = HashMap::new();
+                for parameter in parameters {
+                    self.resolve_pattern(parameter, &mut scope);
+                }
+                self.scopes.push(scope);
+                self.tail = true;
+                self.resolve_let_body(body);
+                self.scopes.pop();
+                self.exit_closure();
+            }
+            ast::ExprKind::Let(let_binding, body) => {
+                self.scopes.push(HashMap::new());
+                self.resolve_local_let_binding(let_binding);
+                self.tail = tail;
+                self.resolve_let_body(body);
+                self.scopes.pop();
+            }
+            ast::ExprKind::Binary(_, lhs, rhs) => {
+                self.resolve_expr(lhs);
+                self.resolve_expr(rhs);
+            }
+            ast::ExprKind::Infix(operator, lhs, rhs) => {
+                self.resolve_value_path(operator);
+                self.check_operator_fixity(operator);
+                self.resolve_expr(lhs);
+                self.resolve_expr(rhs);
+            }
+            ast::ExprKind::Section(operator, lhs, rhs) => {
+                if let ast::SectionOperator::User(path) = operator {
+                    self.resolve_value_path(path);
+                }
+                for operand in lhs.iter().chain(rhs) {
+                    self.resolve_expr(operand);
+                }
+            }
+            ast::ExprKind::Unary(_, operand) => self.resolve_expr(operand),
+            ast::ExprKind::Typed(expr, ty) => {
+                self.tail = tail;
+                self.resolve_expr(expr);
+                self.resolve_ty(ty);
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    if let ast::InterpolationPart::Hole(hole) = part {
+                        self.resolve_expr(hole);
+                    }
+                }
+            }
+        }
+    }
+
+    fn resolve_match_arms(&mut self, arms: &[ast::MatchArm], tail: bool) {
+        for arm in arms {
+            let mut scope = HashMap::new();
+            self.resolve_pattern(&arm.pattern, &mut scope);
+            self.scopes.push(scope);
+            self.tail = tail;
+            self.resolve_let_body(&arm.body);
+            self.scopes.pop();
+        }
+    }
+
+    fn resolve_field_exprs(&mut self, fields: &[ast::FieldExpr]) {
+        for field in fields {
+            self.resolve_field(&field.identifier);
+            self.resolve_expr(&field.expr);
+        }
+    }
+
+    fn resolve_field(&mut self, field: &ast::Literal) {
+        let name = self.text(field);
+        match self.lookup_in_modules(&name, |m| &m.fields) {
+            Some(def) => {
+                self.resolutions.fields.insert(field.id, def);
+            }
+            None => self.session.error(
+                field.span,
+                format!("cannot find field `{}` in this scope", name),
+            ),
+        }
+    }
+
+    /// Resolves the pattern, collecting the variables it binds into the
+    /// scope. A variable cannot be bound twice by the same pattern.
+    fn resolve_pattern(&mut self, pattern: &ast::Pattern, bindings: &mut HashMap<String, NodeId>) {
+        match &pattern.kind {
+            ast::PatternKind::Wildcard | ast::PatternKind::Literal(_) => {}
+            ast::PatternKind::Binding(identifier) => self.bind(identifier, bindings),
+            ast::PatternKind::Constructor(path, arguments) => {
+                self.resolve_value_path(path);
+                let value = match self.resolutions.paths.get(&path.id) {
+                    Some(Res::Def(def)) => match self.resolutions.def(*def).kind {
+                        DefKind::Constructor(_) | DefKind::Exception => None,
+                        _ => Some(self.resolutions.def(*def).name.clone()),
+                    },
+                    Some(Res::Builtin(builtin)) if !builtin.is_exception() => {
+                        Some(builtin.name().to_string())
+                    }
+                    _ => None,
+                };
+                if let Some(name) = value {
+                    let message = format!("expected a constructor, found value `{}`", name);
+                    self.session.error(path.span, message);
+                }
+                for argument in arguments {
+                    self.resolve_pattern(argument, bindings);
+                }
+            }
+            ast::PatternKind::Record(fields) => {
+                for field in fields {
+                    self.resolve_field(&field.identifier);
+                    match &field.pattern {
+                        Some(pattern) => self.resolve_pattern(pattern, bindings),
+                        None => self.bind(&field.identifier, bindings),
+                    }
+                }
+            }
+            ast::PatternKind::Or(alternatives) => {
+                let (first, rest) = alternatives.split_first().unwrap();
+                let mut first_bindings = HashMap::new();
+                self.resolve_pattern(first, &mut first_bindings);
+                for alternative in rest {
+                    let mut alternative_bindings = HashMap::new();
+                    self.resolve_pattern(alternative, &mut alternative_bindings);
+                    for (name, node) in &alternative_bindings {
+                        if let Some(first) = first_bindings.get(name) {
+                            self.resolutions.or_bindings.insert(*node, *first);
+                        }
+                    }
+                    let unbound = first_bindings
+                        .keys()
+                        .chain(alternative_bindings.keys())
+                        .find(|name| {
+                            !first_bindings.contains_key(*name)
+                                || !alternative_bindings.contains_key(*name)
+                        });
+                    if let Some(name) = unbound {
+                        let message =
+                            format!("variable `{}` is not bound in all alternatives", name);
+                        self.session.error(alternative.span, message);
+                    }
+                }
+                for (name, node) in first_bindings {
+                    self.bind_node(name, node, pattern.span, bindings);
+                }
+            }
+            ast::PatternKind::Paren(pattern) => self.resolve_pattern(pattern, bindings),
+            ast::PatternKind::Tuple(elements)
+            | ast::PatternKind::List(elements)
+            | ast::PatternKind::Array(elements) => {
+                for element in elements {
+                    self.resolve_pattern(element, bindings);
+                }
+            }
+            ast::PatternKind::Cons(head, tail) => {
+                self.resolve_pattern(head, bindings);
+                self.resolve_pattern(tail, bindings);
+            }
+            ast::PatternKind::Typed(pattern, ty) => {
+                self.resolve_pattern(pattern, bindings);
+                self.resolve_ty(ty);
+            }
+        }
+    }
+
+    fn bind(&mut self, identifier: &ast::Literal, bindings: &mut HashMap<String, NodeId>) {
+        let name = self.text(identifier);
+        self.bind_node(name, identifier.id, identifier.span, bindings);
+    }
+
+    fn bind_node(
+        &mut self,
+        name: String,
+        node: NodeId,
+        span: SourceSpan,
+        bindings: &mut HashMap<String, NodeId>,
+    ) {
+        if bindings.insert(name.clone(), node).is_some() {
+            self.session.error(
+                span,
+                format!("variable `{}` is bound more than once in the pattern", name),
+            );
+        }
+    }
+
+    fn resolve_value_path(&mut self, path: &ast::Path) {
+        let (last, prefix) = path.segments.split_last().unwrap();
+        let name = self.text(last);
+
+        let res = if prefix.is_empty() {
+            match self.lookup_value(&name) {
+                Some(Res::Local(node)) => {
+                    let scope = self
+                        .scopes
+                        .iter()
+                        .rposition(|s| s.get(&name) == Some(&node));
+                    self.capture(node, scope.unwrap());
+                    Res::Local(node)
+                }
+                Some(res) => res,
+                None => {
+                    self.session.error(
+                        last.span,
+                        format!("cannot find value `{}` in this scope", name),
+                    );
+                    return;
+                }
+            }
+        } else {
+            let module = match self.resolve_module_path(prefix, false) {
+                Some(module) => module,
+                None => return,
+            };
+            match self.resolutions.module(module).values.get(&name) {
+                Some(def) => {
+                    let def = *def;
+                    self.check_def_accessible(def, last.span);
+                    Res::Def(def)
+                }
+                None => {
+                    let message = format!(
+                        "cannot find value `{}` in module `{}`",
+                        name,
+                        self.resolutions.module_path(module)
+                    );
+                    self.session.error(last.span, message);
+                    return;
+                }
+            }
+        };
+
+        self.resolutions.paths.insert(path.id, res);
+    }
+
+    /// Checks that the operator defined in another file was parsed with the
+    /// fixity declared there. The fixities declared so far in this file, and
+    /// in the prelude, are the ones the parser used.
+    fn check_operator_fixity(&mut self, operator: &ast::Path) {
+        let def = match self.resolutions.paths.get(&operator.id) {
+            Some(Res::Def(def)) => self.resolutions.def(*def),
+            _ => return,
+        };
+        let file = self.resolutions.module(self.current_module).file;
+        let def_file = self.resolutions.module(def.module).file;
+        if def_file == file {
+            return;
+        }
+        let fixity_in = |file: FileId| self.resolutions.fixities.get(&file)?.get(&def.name);
+        let declared = fixity_in(def_file).copied().unwrap_or(DEFAULT_FIXITY);
+        let prelude = self
+            .resolutions
+            .prelude
+            .map(|id| self.resolutions.module(id).file);
+        let parsed = fixity_in(file)
+            .or_else(|| fixity_in(prelude?))
+            .copied()
+            .unwrap_or(DEFAULT_FIXITY);
+        if declared != parsed {
+            let message = format!(
+                "the operator `{}` of module `{}` is `{}`, but is parsed with `{}` here; \
+                 declare `{} {}` in this file too",
+                def.name,
+                self.resolutions.module_path(def.module),
+                declared,
+                parsed,
+                declared,
+                def.name
+            );
+            self.session.error(operator.span, message);
+        }
+    }
+
+    /// Resolves the path to a module. The file modules which are not
+    /// imported can only be referred to by `open` and `import` items.
+    fn resolve_module_path(
+        &mut self,
+        segments: &[ast::Literal],
+        allow_files: bool,
+    ) -> Option<ModuleId> {
+        let (first, rest) = segments.split_first().unwrap();
+        let name = self.text(first);
+        let mut module = match self.lookup_module(&name) {
+            Some(module) => module,
+            None => match self.file_modules.get(&name) {
+                Some(module) if allow_files => *module,
+                _ => {
+                    self.session.error(
+                        first.span,
+                        format!("cannot find module `{}` in this scope", name),
+                    );
+                    return None;
+                }
+            },
+        };
+
+        for segment in rest {
+            let name = self.text(segment);
+            match self.resolutions.module(module).modules.get(&name) {
+                Some(nested) => {
+                    let nested = *nested;
+                    let data = self.resolutions.module(nested);
+                    if !self
+                        .resolutions
+                        .is_accessible(data.visibility, module, self.current_module)
+                    {
+                        let message = format!(
+                            "module `{}` is private",
+                            self.resolutions.module_path(nested)
+                        );
+                        self.session.error(segment.span, message);
+                    }
+                    module = nested;
+                }
+                None => {
+                    let message = format!(
+                        "cannot find module `{}` in module `{}`",
+                        name,
+                        self.resolutions.module_path(module)
+                    );
+                    self.session.error(segment.span, message);
+                    return None;
+                }
+            }
+        }
+
+        Some(module)
+    }
+
+    fn check_def_accessible(&mut self, def: DefId, span: SourceSpan) {
+        let definition = self.resolutions.def(def);
+        if !self.resolutions.is_accessible(
+            definition.visibility,
+            definition.module,
+            self.current_module,
+        ) {
+            let message = format!(
+                "`{}` is private to module `{}`",
+                definition.name,
+                self.resolutions.module_path(definition.module)
+            );
+            self.session.error(span, message);
+        }
+    }
+
+    /// Looks the value up in the local scopes, then in the current module
+    /// and the modules opened in it, then in the enclosing modules.
+    fn lookup_value(&self, name: &str) -> Option<Res> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(node) = scope.get(name) {
+                return Some(Res::Local(*node));
+            }
+        }
+
+        self.lookup_in_modules(name, |m| &m.values)
+            .map(Res::Def)
+            .or_else(|| Builtin::from_name(name).map(Res::Builtin))
+    }
+
+    /// Looks the definition up in the namespace of the current module and
+    /// the modules opened in it, then of the enclosing modules.
+    fn lookup_in_modules(
+        &self,
+        name: &str,
+        namespace: impl Fn(&ModuleData) -> &HashMap<String, DefId>,
+    ) -> Option<DefId> {
+        let mut current = Some(self.current_module);
+        while let Some(module) = current {
+            if let Some(def) = namespace(self.resolutions.module(module)).get(name) {
+                return Some(*def);
+            }
+            for opened in self.opens[module.as_usize()].iter().rev() {
+                if let Some(def) = namespace(self.resolutions.module(*opened)).get(name) {
+                    let definition = self.resolutions.def(*def);
+                    if self.resolutions.is_accessible(
+                        definition.visibility,
+                        definition.module,
+                        self.current_module,
+                    ) {
+                        return Some(*def);
+                    }
+                }
+            }
+            current = self.resolutions.module(module).parent;
+        }
+
+        None
+    }
+
+    /// Looks the module up in the nested, imported and opened modules of the
+    /// current module, then of the enclosing modules.
+    fn lookup_module(&self, name: &str) -> Option<ModuleId> {
+        let mut current = Some(self.current_module);
+        while let Some(module) = current {
+            if let Some(nested) = self.resolutions.module(module).modules.get(name) {
+                return Some(*nested);
+            }
+            if let Some(imported) = self.imports[module.as_usize()].get(name) {
+                return Some(*imported);
+            }
+            for opened in self.opens[module.as_usize()].iter().rev() {
+                if let Some(nested) = self.resolutions.module(*opened).modules.get(name) {
+                    if self.resolutions.module(*nested).visibility == Visibility::Public {
+                        return Some(*nested);
+                    }
+                }
+            }
+            current = self.resolutions.module(module).parent;
+        }
+
+        None
+    }
+
+    fn text(&self, literal: &ast::Literal) -> String {
+        self.session
+            .source_map
+            .span_to_snippet(literal.span)
+            .to_string()
+    }
+}
@@ -1,38 +1,74 @@
 mod analyze_file;
+pub mod source_map;
 
 use analyze_file::*;
 
+pub use source_map::{FileId, SourceMap};
+
 pub struct SourceFile {
+    pub id: FileId,
     /// Path to the source file, as given to the compiler via the command line.
     pub file_path: String,
     /// The complete source code readen from the source file.
     pub source_code: String,
     /// Kind of indentation used in the source code.
     pub indent_kind: IndentKind,
+    /// Global position of the first byte of the file in the `SourceMap`.
+    pub start_pos: usize,
+    /// Global positions of the first bytes of all the lines.
+    line_starts: Vec<usize>,
 }
 
 impl SourceFile {
-    pub fn read(file_path: String) -> Result<Self, String> {
-        let source_code = match std::fs::read_to_string(&file_path) {
-            Ok(s) => s,
-            Err(_) => {
-                eprintln!("error: failed to open the source file \"{}\"", &file_path);
-                std::process::exit(1);
-            }
-        };
-
+    pub fn new(id: FileId, file_path: String, source_code: String, start_pos: usize) -> Self {
         let indent_kind = detect_indent_kind(&source_code);
 
-        Ok(Self {
+        let mut line_starts = vec![start_pos];
+        for (i, c) in source_code.char_indices() {
+            if c == '\n' {
+                line_starts.push(start_pos + i + 1);
+            }
+        }
+
+        Self {
+            id,
             file_path,
             source_code,
             indent_kind,
-        })
+            start_pos,
+            line_starts,
+        }
+    }
+
+    /// Global position just past the last byte of the file.
+    pub fn end_pos(&self) -> usize {
+        self.start_pos + self.source_code.len()
     }
 
-    #[allow(dead_code)]
     pub fn read_span(&self, span: SourceSpan) -> &str {
-        &self.source_code[span.start..span.end]
+        &self.source_code[span.start - self.start_pos..span.end - self.start_pos]
+    }
+
+    /// Gets the one-based line and column numbers of the global position.
+    pub fn line_column(&self, pos: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&pos) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let line_start = self.line_starts[line] - self.start_pos;
+        let offset = pos - self.start_pos;
+        let column = self.source_code[line_start..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+
+    /// Gets the text of the line with the given one-based number.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1] - self.start_pos;
+        let end = match self.line_starts.get(line) {
+            Some(next) => next - self.start_pos,
+            None => self.source_code.len(),
+        };
+        self.source_code[start..end].trim_end_matches(&['\r', '\n'][..])
     }
 }
 
@@ -65,4 +101,22 @@ impl SourceSpan {
     pub fn len(self) -> usize {
         self.end - self.start
     }
+
+    pub fn is_empty(self) -> bool {
+        self.start == self.end
+    }
+
+    /// Checks whether the position is in the span or right after it, as a
+    /// cursor at the end of an identifier is still on it.
+    pub fn touches(self, pos: usize) -> bool {
+        self.start <= pos && pos <= self.end
+    }
+
+    /// Creates a span covering both this and the other span.
+    pub fn to(self, other: SourceSpan) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
 }
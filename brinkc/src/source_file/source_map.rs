@@ -0,0 +1,181 @@
+use super::{SourceFile, SourceSpan};
+
+/// Identifies a single source file loaded into the `SourceMap`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FileId(u32);
+
+impl FileId {
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Position of a byte in a source file, as presented to the user.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SourcePosition {
+    pub file: FileId,
+    /// One-based line number.
+    pub line: usize,
+    /// One-based column number, counted in characters.
+    pub column: usize,
+}
+
+/// Owns all source files taking part in a compilation.
+///
+/// Every file is assigned a base offset, so the byte positions in the files
+/// do not overlap and a `SourceSpan` is unique across the whole compilation.
+/// There is a gap of one byte between consecutive files, so the end-of-file
+/// position of a file never coincides with the start of the next one.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// Reads the source file from disk and adds it to the map.
+    pub fn load_file(&mut self, file_path: String) -> Result<FileId, String> {
+        match std::fs::read_to_string(&file_path) {
+            Ok(source_code) => Ok(self.add_file(file_path, source_code)),
+            Err(_) => Err(format!(
+                "error: failed to open the source file \"{}\"",
+                file_path
+            )),
+        }
+    }
+
+    /// Adds the source code to the map as if it was read from the given path.
+    pub fn add_file(&mut self, file_path: String, source_code: String) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        let start_pos = match self.files.last() {
+            Some(file) => file.end_pos() + 1,
+            None => 0,
+        };
+        self.files
+            .push(SourceFile::new(id, file_path, source_code, start_pos));
+        id
+    }
+
+    /// Drops the text of the file, e.g. after a new version of it is added.
+    /// The file keeps its positions, so the positions of the other files
+    /// stay valid, but the spans into the file cannot be read anymore.
+    pub fn drop_file(&mut self, id: FileId) {
+        let file = &mut self.files[id.as_usize()];
+        file.source_code = String::new();
+        file.line_starts = vec![file.start_pos];
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.as_usize()]
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Finds the file containing the given global byte position.
+    pub fn lookup_file(&self, pos: usize) -> &SourceFile {
+        let index = match self.files.binary_search_by(|f| f.start_pos.cmp(&pos)) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        &self.files[index]
+    }
+
+    pub fn lookup_position(&self, pos: usize) -> SourcePosition {
+        let file = self.lookup_file(pos);
+        let (line, column) = file.line_column(pos);
+        SourcePosition {
+            file: file.id,
+            line,
+            column,
+        }
+    }
+
+    /// Formats the start of the span as `path:line:column`.
+    pub fn span_to_location(&self, span: SourceSpan) -> String {
+        let position = self.lookup_position(span.start);
+        format!(
+            "{}:{}:{}",
+            self.file(position.file).file_path,
+            position.line,
+            position.column
+        )
+    }
+
+    pub fn span_to_snippet(&self, span: SourceSpan) -> &str {
+        self.lookup_file(span.start).read_span(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_map() -> SourceMap {
+        let mut source_map = SourceMap::new();
+        source_map.add_file("a.bk".to_string(), "let x = 1\nlet y = 2\n".to_string());
+        source_map.add_file("b.bk".to_string(), "let z =\n  3".to_string());
+        source_map
+    }
+
+    #[test]
+    fn assigns_disjoint_base_offsets() {
+        let source_map = source_map();
+        let a = &source_map.files()[0];
+        let b = &source_map.files()[1];
+
+        assert_eq!(0, a.start_pos);
+        assert_eq!(a.end_pos() + 1, b.start_pos);
+    }
+
+    #[test]
+    fn looks_up_file_by_position() {
+        let source_map = source_map();
+        let b_start = source_map.files()[1].start_pos;
+
+        assert_eq!("a.bk", source_map.lookup_file(0).file_path);
+        assert_eq!("a.bk", source_map.lookup_file(b_start - 1).file_path);
+        assert_eq!("b.bk", source_map.lookup_file(b_start).file_path);
+    }
+
+    #[test]
+    fn looks_up_line_and_column() {
+        let source_map = source_map();
+        let b_start = source_map.files()[1].start_pos;
+
+        let position = source_map.lookup_position(14);
+        assert_eq!((2, 5), (position.line, position.column));
+
+        let position = source_map.lookup_position(b_start + 10);
+        assert_eq!(source_map.files()[1].id, position.file);
+        assert_eq!((2, 3), (position.line, position.column));
+    }
+
+    #[test]
+    fn keeps_positions_of_dropped_files() {
+        let mut source_map = source_map();
+        let c = source_map.add_file("a.bk".to_string(), "let x = 10\n".to_string());
+        source_map.drop_file(FileId(0));
+
+        let c_start = source_map.file(c).start_pos;
+        assert!(source_map.file(FileId(0)).source_code.is_empty());
+        assert_eq!("b.bk", source_map.lookup_file(c_start - 1).file_path);
+        assert_eq!(
+            "10",
+            source_map.span_to_snippet(SourceSpan::from_length(c_start + 8, 2))
+        );
+    }
+
+    #[test]
+    fn reads_snippet_of_global_span() {
+        let source_map = source_map();
+        let b_start = source_map.files()[1].start_pos;
+
+        let span = SourceSpan::from_length(b_start + 4, 1);
+        assert_eq!("z", source_map.span_to_snippet(span));
+    }
+}
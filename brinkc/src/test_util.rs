@@ -0,0 +1,135 @@
+//! The setup shared by the tests of the stages after the parser, and the
+//! programs all the backends run.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    anf,
+    frontend::parse_session::ParseSession,
+    interpret, ir, opt,
+    resolve::{self, ModuleGraph, Resolutions},
+    source_file::SourceMap,
+    typeck::{self, TypeckResults},
+};
+
+/// A program loaded from the single file `main.bk`, with the standard
+/// library, then resolved and type checked.
+pub struct Checked {
+    pub session: ParseSession,
+    pub graph: ModuleGraph,
+    pub resolutions: Resolutions,
+    pub results: TypeckResults,
+}
+
+/// Loads, resolves and type checks the source, leaving the errors in the
+/// session.
+pub fn load(source: &str) -> Checked {
+    let files = vec![(Path::new("main.bk").to_path_buf(), source.to_string())]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut session = ParseSession::new(SourceMap::new());
+    let graph =
+        ModuleGraph::load_with(&mut session, "main.bk", &|p| files.get(p).cloned()).unwrap();
+    let resolutions = resolve::resolve(&mut session, &graph);
+    let results = typeck::typeck(&mut session, &graph, &resolutions);
+    Checked {
+        session,
+        graph,
+        resolutions,
+        results,
+    }
+}
+
+/// Loads, resolves and type checks the source, which has no errors.
+pub fn check(source: &str) -> Checked {
+    let checked = load(source);
+    assert!(!checked.session.has_errors());
+    checked
+}
+
+impl Checked {
+    /// Lowers the program to the mid-level IR, optimized at the level like
+    /// the compiler does.
+    pub fn optimize(&self, opt_level: u8) -> anf::Program {
+        let program = ir::lower(
+            &self.session.source_map,
+            &self.graph,
+            &self.resolutions,
+            &self.results,
+        );
+        let mut program = anf::convert(&program);
+        opt::optimize(&mut program, &opt::default_pipeline(opt_level)).unwrap();
+        program
+    }
+
+    /// Gets the exception the interpreter does not handle, which the
+    /// compiled programs report too.
+    pub fn uncaught_exception(&self) -> Option<String> {
+        on_deep_stack(|| {
+            interpret::interpret(
+                &self.session.source_map,
+                &self.graph,
+                &self.resolutions,
+                &self.results,
+            )
+            .err()
+            .map(|exception| exception.exception)
+        })
+    }
+}
+
+/// Runs the function on a thread with the stack the interpreter and the
+/// virtual machine run the programs on.
+pub fn on_deep_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(crate::INTERPRETER_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
+
+/// The tests of the standard library modules, each binding `result` to
+/// `"ok"` once its checks pass.
+pub const STDLIB_TESTS: [(&str, &str); 6] = [
+    ("list", include_str!("../lib/tests/list.bk")),
+    ("map", include_str!("../lib/tests/map.bk")),
+    ("option", include_str!("../lib/tests/option.bk")),
+    ("result", include_str!("../lib/tests/result.bk")),
+    ("set", include_str!("../lib/tests/set.bk")),
+    ("string", include_str!("../lib/tests/string.bk")),
+];
+
+/// The test of the `IO` module, which writes and reads the file of the
+/// name in the temporary directory, returned for the caller to remove.
+pub fn io_test(file_name: &str) -> (String, PathBuf) {
+    let path = std::env::temp_dir().join(file_name);
+    let literal = path.to_string_lossy().replace('\\', "\\\\");
+    let source = include_str!("../lib/tests/io.bk").replace("{path}", &literal);
+    (source, path)
+}
+
+/// Nests the number of calls through a recursive group at the top level
+/// and through one local to a function, unless the calls in tail position
+/// run in constant space. Binds `r` to `"true true <calls>"`.
+pub fn mutually_recursive_groups(calls: u32) -> String {
+    format!(
+        "let rec even n = if n = 0 then true else odd (n - 1)\n\
+         and odd n = if n = 0 then false else even (n - 1)\n\
+         let count limit =\n  let rec ping n = if n = limit then n else pong (n + 1)\n  and pong n = if n = limit then n else ping (n + 1)\n  ping 0\n\
+         let r = sprintf \"%b %b %d\" (even {0}) (odd {1}) (count {0})\n",
+        calls,
+        calls + 1
+    )
+}
+
+/// Appends printing the string bound to the name, for the backends which
+/// only show the output of the programs.
+pub fn printing(source: &str, name: &str) -> String {
+    format!("{}\nlet printed = print_string {}\n", source, name)
+}
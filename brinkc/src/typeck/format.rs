@@ -0,0 +1,105 @@
+use crate::resolve::PrimTy;
+
+/// A conversion of an argument in a `sprintf` format string, e.g. `%d`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Conversion {
+    /// An integer in decimal, `%d`.
+    Int,
+    /// A string as is, `%s`.
+    String,
+    /// `true` or `false`, `%b`.
+    Bool,
+}
+
+impl Conversion {
+    /// Gets the type of the argument the conversion formats.
+    pub fn ty(self) -> PrimTy {
+        match self {
+            Conversion::Int => PrimTy::Int,
+            Conversion::String => PrimTy::String,
+            Conversion::Bool => PrimTy::Bool,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum FormatPiece<'a> {
+    Text(&'a str),
+    Conversion(Conversion),
+}
+
+/// An invalid conversion, with its byte offset and length in the format.
+#[derive(Debug, Eq, PartialEq)]
+pub struct FormatError {
+    pub offset: usize,
+    pub length: usize,
+    pub message: String,
+}
+
+/// Splits the format string into the text and the conversions, the `%%`
+/// being the text `%`.
+pub fn parse_format(format: &str) -> Result<Vec<FormatPiece<'_>>, FormatError> {
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut chars = format.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        let conversion = match chars.next().map(|(_, c)| c) {
+            Some('d') => Conversion::Int,
+            Some('s') => Conversion::String,
+            Some('b') => Conversion::Bool,
+            Some('%') => {
+                pieces.push(FormatPiece::Text(&format[text_start..i + 1]));
+                text_start = i + 2;
+                continue;
+            }
+            Some(c) => {
+                return Err(FormatError {
+                    offset: i,
+                    length: 1 + c.len_utf8(),
+                    message: format!("unknown conversion `%{}` in the format", c),
+                })
+            }
+            None => {
+                return Err(FormatError {
+                    offset: i,
+                    length: 1,
+                    message: "incomplete conversion at the end of the format".to_string(),
+                })
+            }
+        };
+        if text_start < i {
+            pieces.push(FormatPiece::Text(&format[text_start..i]));
+        }
+        pieces.push(FormatPiece::Conversion(conversion));
+        text_start = i + 2;
+    }
+    if text_start < format.len() {
+        pieces.push(FormatPiece::Text(&format[text_start..]));
+    }
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_format() {
+        use FormatPiece::*;
+        assert_eq!(
+            Ok(vec![
+                Text("x = "),
+                Conversion(super::Conversion::Int),
+                Text(", 100%"),
+                Conversion(super::Conversion::String),
+            ]),
+            parse_format("x = %d, 100%%%s")
+        );
+        assert_eq!(Ok(vec![]), parse_format(""));
+        assert_eq!(4, parse_format("abc %x").unwrap_err().offset);
+        assert_eq!(1, parse_format("a%").unwrap_err().length);
+    }
+}
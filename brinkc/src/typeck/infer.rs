@@ -0,0 +1,1606 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{self, node_id::NodeId},
+    frontend::parse_session::ParseSession,
+    resolve::{Builtin, DefId, DefKind, ModuleGraph, PrimTy, Res, Resolutions},
+    source_file::SourceSpan,
+};
+
+use super::{
+    format::{self, FormatPiece},
+    ty::{Scheme, Ty, TyPrinter, TyVid},
+    AdtDef, AdtKind, FieldDef, TypeDecls, TypeckResults, VariantDef,
+};
+
+/// Infers the types of all the definitions and expressions in the module
+/// graph with the Hindley-Milner algorithm. The let bindings are generalized
+/// using the levels of the type variables.
+pub fn typeck(
+    session: &mut ParseSession,
+    graph: &ModuleGraph,
+    resolutions: &Resolutions,
+) -> TypeckResults {
+    let mut checker = TypeChecker::new(session, resolutions);
+    for module in &graph.modules {
+        collect_type_decls(&module.program.body, resolutions, &mut checker.type_decls);
+    }
+
+    for module in &graph.modules {
+        checker.check_type_decls(&module.program.body);
+    }
+    for module in &graph.modules {
+        checker.check_module_items(&module.program.body);
+    }
+
+    checker.finish()
+}
+
+/// Checks the type declarations and the exceptions of the module graph,
+/// leaving out the let bindings, which `check_item` checks against them.
+pub fn check_type_decls(
+    session: &mut ParseSession,
+    graph: &ModuleGraph,
+    resolutions: &Resolutions,
+) -> TypeDecls {
+    let mut checker = TypeChecker::new(session, resolutions);
+    for module in &graph.modules {
+        collect_type_decls(&module.program.body, resolutions, &mut checker.type_decls);
+    }
+    for module in &graph.modules {
+        checker.check_type_decls(&module.program.body);
+    }
+
+    let aliases = std::mem::take(&mut checker.aliases);
+    let results = checker.finish();
+    TypeDecls {
+        adts: results.adts,
+        aliases,
+        schemes: results.def_schemes,
+    }
+}
+
+/// Checks a top-level let binding or expression on its own. The
+/// definitions it refers to have the given schemes, except for the ones
+/// defined by the context items, which are checked before it in the same
+/// inference, so their type variables which are not generalized are shared.
+/// The results of the context items are left out.
+pub fn check_item(
+    session: &mut ParseSession,
+    resolutions: &Resolutions,
+    type_decls: &TypeDecls,
+    schemes: HashMap<DefId, Scheme>,
+    context: &[&ast::Item],
+    item: &ast::Item,
+) -> TypeckResults {
+    let mut checker = TypeChecker::new(session, resolutions);
+    checker.aliases = type_decls.aliases.clone();
+    checker.results.adts = type_decls.adts.clone();
+    checker.results.def_schemes = type_decls.schemes.clone();
+    checker.results.def_schemes.extend(schemes);
+
+    // Their diagnostics are reported when they are checked on their own.
+    checker.session.begin_capture();
+    for context_item in context {
+        checker.check_module_item(context_item);
+    }
+    let _ = checker.session.end_capture();
+    let context_nodes = checker
+        .results
+        .node_types
+        .keys()
+        .chain(checker.results.local_schemes.keys())
+        .copied()
+        .collect::<HashSet<_>>();
+
+    checker.check_module_item(item);
+    let mut results = checker.finish();
+    results.adts.clear();
+    results
+        .node_types
+        .retain(|node, _| !context_nodes.contains(node));
+    results
+        .local_schemes
+        .retain(|node, _| !context_nodes.contains(node));
+    results
+}
+
+fn collect_type_decls<'a>(
+    items: &'a [ast::Item],
+    resolutions: &Resolutions,
+    type_decls: &mut HashMap<DefId, &'a ast::TypeDecl>,
+) {
+    for item in items {
+        match &item.kind {
+            ast::ItemKind::Type(type_decl) => {
+                if let Some(def) = resolutions.def_of_node.get(&type_decl.id) {
+                    type_decls.insert(*def, type_decl);
+                }
+            }
+            ast::ItemKind::Module(module) => {
+                collect_type_decls(&module.body.items, resolutions, type_decls)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone)]
+enum VarValue {
+    Unbound { level: u32 },
+    Bound(Ty),
+}
+
+enum UnifyError {
+    Mismatch,
+    /// The variable occurs in the type it would be bound to.
+    InfiniteType(TyVid, Ty),
+}
+
+struct TypeChecker<'a, 's> {
+    session: &'s mut ParseSession,
+    resolutions: &'a Resolutions,
+    type_decls: HashMap<DefId, &'a ast::TypeDecl>,
+    results: TypeckResults,
+    variables: Vec<VarValue>,
+    /// The nesting depth of the let bindings being inferred. The variables
+    /// created deeper than the binding are generalized.
+    level: u32,
+    /// The types of the local bindings, by the node of the bound identifier.
+    locals: HashMap<NodeId, Scheme>,
+    /// The parameters of the type declaration being lowered, by name.
+    decl_parameters: Option<HashMap<String, u32>>,
+    /// The expanded bodies of the type aliases with their parameter counts.
+    aliases: HashMap<DefId, (usize, Ty)>,
+    aliases_in_progress: HashSet<DefId>,
+    /// The type variables of the annotations in the current top-level item.
+    type_variables: HashMap<String, Ty>,
+}
+
+impl<'a, 's> TypeChecker<'a, 's> {
+    fn new(session: &'s mut ParseSession, resolutions: &'a Resolutions) -> Self {
+        Self {
+            session,
+            resolutions,
+            type_decls: HashMap::new(),
+            results: TypeckResults::default(),
+            variables: Vec::new(),
+            level: 0,
+            locals: HashMap::new(),
+            decl_parameters: None,
+            aliases: HashMap::new(),
+            aliases_in_progress: HashSet::new(),
+            type_variables: HashMap::new(),
+        }
+    }
+
+    fn check_type_decls(&mut self, items: &'a [ast::Item]) {
+        for item in items {
+            match &item.kind {
+                ast::ItemKind::Type(type_decl) => self.check_type_decl(type_decl),
+                ast::ItemKind::Module(module) => self.check_type_decls(&module.body.items),
+                ast::ItemKind::Exception(exception) => self.check_exception(exception),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_type_decl(&mut self, type_decl: &'a ast::TypeDecl) {
+        let def = match self.resolutions.def_of_node.get(&type_decl.id) {
+            Some(def) => *def,
+            None => return,
+        };
+
+        if let ast::TypeDeclKind::Alias(_) = &type_decl.kind {
+            self.expand_alias(def);
+            return;
+        }
+
+        let outer = self.enter_type_decl(type_decl);
+        let parameters = type_decl.parameters.len();
+        let kind = match &type_decl.kind {
+            ast::TypeDeclKind::Variant(constructors) => {
+                let mut variants = Vec::new();
+                for constructor in constructors {
+                    let arguments = constructor
+                        .arguments
+                        .iter()
+                        .map(|argument| self.lower_ty(argument))
+                        .collect::<Vec<_>>();
+                    if let Some(constructor) = self.resolutions.def_of_node.get(&constructor.id) {
+                        variants.push(VariantDef {
+                            def: *constructor,
+                            arguments,
+                        });
+                    }
+                }
+                AdtKind::Variant(variants)
+            }
+            ast::TypeDeclKind::Record(fields) => {
+                let mut field_defs = Vec::new();
+                for field in fields {
+                    let ty = self.lower_ty(&field.ty);
+                    if let Some(field) = self.resolutions.def_of_node.get(&field.id) {
+                        field_defs.push(FieldDef { def: *field, ty });
+                    }
+                }
+                AdtKind::Record(field_defs)
+            }
+            ast::TypeDeclKind::Alias(_) => unreachable!(),
+        };
+        self.decl_parameters = outer;
+
+        // The constructors are curried functions from their arguments.
+        let self_ty = Ty::Adt(def, (0..parameters as u32).map(Ty::Generic).collect());
+        if let AdtKind::Variant(variants) = &kind {
+            for variant in variants {
+                let ty = variant
+                    .arguments
+                    .iter()
+                    .rev()
+                    .fold(self_ty.clone(), |result, argument| {
+                        Ty::function(argument.clone(), result)
+                    });
+                let scheme = Scheme {
+                    generics: parameters as u32,
+                    ty,
+                };
+                self.results.def_schemes.insert(variant.def, scheme);
+            }
+        }
+        self.results.adts.insert(def, AdtDef { parameters, kind });
+    }
+
+    /// Defines the exception as a constructor of the `exn` type. Its
+    /// arguments cannot have type variables.
+    fn check_exception(&mut self, exception: &ast::Constructor) {
+        let def = match self.resolutions.def_of_node.get(&exception.id) {
+            Some(def) => *def,
+            None => return,
+        };
+        let outer = self.decl_parameters.replace(HashMap::new());
+        let ty = exception
+            .arguments
+            .iter()
+            .rev()
+            .fold(Ty::Prim(PrimTy::Exn), |result, argument| {
+                Ty::function(self.lower_ty(argument), result)
+            });
+        self.decl_parameters = outer;
+        self.results
+            .def_schemes
+            .insert(def, Scheme::monomorphic(ty));
+    }
+
+    fn enter_type_decl(&mut self, type_decl: &ast::TypeDecl) -> Option<HashMap<String, u32>> {
+        let parameters = type_decl
+            .parameters
+            .iter()
+            .enumerate()
+            .map(|(i, parameter)| {
+                let name = self.text(parameter.span);
+                (name.trim_start_matches('\'').to_string(), i as u32)
+            })
+            .collect();
+        self.decl_parameters.replace(parameters)
+    }
+
+    /// Lowers the body of the type alias, expanding the aliases it refers
+    /// to. The expansion is done once and cached.
+    fn expand_alias(&mut self, def: DefId) -> Option<(usize, Ty)> {
+        if let Some(alias) = self.aliases.get(&def) {
+            return Some(alias.clone());
+        }
+        let type_decl = *self.type_decls.get(&def)?;
+        let aliased = match &type_decl.kind {
+            ast::TypeDeclKind::Alias(aliased) => aliased,
+            _ => return None,
+        };
+        if !self.aliases_in_progress.insert(def) {
+            self.session.error(
+                type_decl.identifier.span,
+                format!(
+                    "type alias `{}` refers to itself",
+                    self.resolutions.def(def).name
+                ),
+            );
+            self.aliases
+                .insert(def, (type_decl.parameters.len(), Ty::Error));
+            return Some((type_decl.parameters.len(), Ty::Error));
+        }
+
+        let outer = self.enter_type_decl(type_decl);
+        let ty = self.lower_ty(aliased);
+        self.decl_parameters = outer;
+        self.aliases_in_progress.remove(&def);
+
+        let alias = (type_decl.parameters.len(), ty);
+        self.aliases.entry(def).or_insert(alias.clone());
+        Some(alias)
+    }
+
+    /// Converts the type written in the source code to the internal type.
+    /// In the type declarations, the parameters are lowered to generics;
+    /// elsewhere, each type variable is a fresh inference variable shared by
+    /// all the annotations of the top-level item.
+    fn lower_ty(&mut self, ty: &ast::Ty) -> Ty {
+        match &ty.kind {
+            ast::TyKind::Path(path, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.lower_ty(argument))
+                    .collect::<Vec<_>>();
+                self.lower_type_path(path, arguments)
+            }
+            ast::TyKind::Var(variable) => {
+                let name = self.text(variable.span);
+                let name = name.trim_start_matches('\'');
+                if let Some(parameters) = &self.decl_parameters {
+                    // Undeclared variables are reported by the resolver.
+                    return match parameters.get(name) {
+                        Some(index) => Ty::Generic(*index),
+                        None => Ty::Error,
+                    };
+                }
+                if let Some(ty) = self.type_variables.get(name) {
+                    return ty.clone();
+                }
+                // The variables are created at the level of the top-level
+                // binding, so they are generalized with it.
+                let ty = self.new_var_at(1.min(self.level));
+                self.type_variables.insert(name.to_string(), ty.clone());
+                ty
+            }
+            ast::TyKind::Arrow(parameter, result) => {
+                Ty::function(self.lower_ty(parameter), self.lower_ty(result))
+            }
+            ast::TyKind::Tuple(elements) => Ty::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.lower_ty(element))
+                    .collect(),
+            ),
+            ast::TyKind::Paren(ty) => self.lower_ty(ty),
+        }
+    }
+
+    fn lower_type_path(&mut self, path: &ast::Path, arguments: Vec<Ty>) -> Ty {
+        let mut is_alias = false;
+        let (parameters, ty) = match self.resolutions.paths.get(&path.id) {
+            Some(Res::PrimTy(prim_ty)) => (prim_ty.parameters(), Ty::Prim(*prim_ty)),
+            Some(Res::TyParam(_)) => {
+                let name = self.text(path.span);
+                let index = self
+                    .decl_parameters
+                    .as_ref()
+                    .and_then(|parameters| parameters.get(&name).copied());
+                (0, index.map_or(Ty::Error, Ty::Generic))
+            }
+            Some(Res::Def(def)) => match self.type_decls.get(def) {
+                Some(type_decl) => match &type_decl.kind {
+                    ast::TypeDeclKind::Alias(_) => match self.expand_alias(*def) {
+                        Some(alias) => {
+                            is_alias = true;
+                            alias
+                        }
+                        None => return Ty::Error,
+                    },
+                    _ => (type_decl.parameters.len(), Ty::Adt(*def, Vec::new())),
+                },
+                // Checking a single item, the declarations are already
+                // checked.
+                None => match (self.aliases.get(def), self.results.adts.get(def)) {
+                    (Some(alias), _) => {
+                        is_alias = true;
+                        alias.clone()
+                    }
+                    (None, Some(adt)) => (adt.parameters, Ty::Adt(*def, Vec::new())),
+                    (None, None) => return Ty::Error,
+                },
+            },
+            _ => return Ty::Error,
+        };
+
+        if parameters != arguments.len() {
+            let message = format!(
+                "type `{}` expects {} type argument{}, but {} {} given",
+                self.text(path.span),
+                parameters,
+                if parameters == 1 { "" } else { "s" },
+                arguments.len(),
+                if arguments.len() == 1 { "was" } else { "were" },
+            );
+            self.session.error(path.span, message);
+            return Ty::Error;
+        }
+
+        match ty {
+            // The generics in the alias body are its parameters.
+            ty if is_alias => ty.subst(&arguments),
+            Ty::Adt(def, _) => Ty::Adt(def, arguments),
+            Ty::Prim(PrimTy::List) => Ty::List(Box::new(arguments.into_iter().next().unwrap())),
+            Ty::Prim(PrimTy::Array) => Ty::Array(Box::new(arguments.into_iter().next().unwrap())),
+            Ty::Prim(PrimTy::Ref) => Ty::Ref(Box::new(arguments.into_iter().next().unwrap())),
+            ty => ty,
+        }
+    }
+
+    fn check_module_items(&mut self, items: &'a [ast::Item]) {
+        for item in items {
+            self.check_module_item(item);
+        }
+    }
+
+    fn check_module_item(&mut self, item: &'a ast::Item) {
+        match &item.kind {
+            ast::ItemKind::LetBinding(let_binding) => {
+                self.check_let_bindings(std::slice::from_ref(let_binding))
+            }
+            ast::ItemKind::LetRec(bindings) => self.check_let_bindings(bindings),
+            ast::ItemKind::Module(module) => self.check_module_items(&module.body.items),
+            ast::ItemKind::Expr(expr) => {
+                self.type_variables.clear();
+                let _ = self.infer_expr(expr);
+            }
+            ast::ItemKind::Type(_)
+            | ast::ItemKind::Open(_)
+            | ast::ItemKind::Import(_)
+            | ast::ItemKind::Exception(_)
+            | ast::ItemKind::Fixity(_) => {}
+        }
+    }
+
+    /// Infers the schemes of the module-level binding or recursive group.
+    fn check_let_bindings(&mut self, bindings: &[ast::LetBinding]) {
+        self.type_variables.clear();
+        let schemes = self.infer_let_bindings(bindings, |checker, let_binding, ty| {
+            if let Some(def) = checker.resolutions.def_of_node.get(&let_binding.id) {
+                checker.results.def_schemes.insert(*def, ty);
+            }
+        });
+        for (let_binding, scheme) in bindings.iter().zip(schemes) {
+            if let Some(def) = self.resolutions.def_of_node.get(&let_binding.id) {
+                self.results.def_schemes.insert(*def, scheme);
+            }
+        }
+    }
+
+    /// Infers the generalized types of the let binding, or of the bindings
+    /// of a recursive group. A recursive binding is monomorphic in the
+    /// bodies of its group; the type it has there is passed to `define`
+    /// before the bodies are inferred.
+    fn infer_let_bindings(
+        &mut self,
+        bindings: &[ast::LetBinding],
+        mut define: impl FnMut(&mut Self, &ast::LetBinding, Scheme),
+    ) -> Vec<Scheme> {
+        self.level += 1;
+        let tys = bindings.iter().map(|_| self.new_var()).collect::<Vec<_>>();
+        for (let_binding, ty) in bindings.iter().zip(&tys) {
+            if let_binding.is_recursive {
+                define(self, let_binding, Scheme::monomorphic(ty.clone()));
+            }
+        }
+
+        for (let_binding, ty) in bindings.iter().zip(&tys) {
+            let parameters = let_binding
+                .parameters
+                .iter()
+                .map(|parameter| {
+                    let ty = self.new_var();
+                    self.check_pattern(parameter, &ty);
+                    ty
+                })
+                .collect::<Vec<_>>();
+            let body_ty = self.infer_let_body(&let_binding.body);
+            if let Some(return_ty) = &let_binding.return_ty {
+                let expected = self.lower_ty(return_ty);
+                self.unify(let_body_span(&let_binding.body), &expected, &body_ty);
+            }
+            let function_ty = parameters
+                .into_iter()
+                .rev()
+                .fold(body_ty, |result, parameter| Ty::function(parameter, result));
+            self.unify(let_binding.identifier.span, ty, &function_ty);
+        }
+        self.level -= 1;
+
+        bindings
+            .iter()
+            .zip(tys)
+            .map(|(let_binding, ty)| {
+                // The value restriction: a binding whose evaluation may
+                // create a reference, e.g. `let r = ref []`, is not
+                // generalized, as the reference would be shared by all the
+                // instances.
+                let scheme =
+                    if !let_binding.parameters.is_empty() || self.is_value(&let_binding.body) {
+                        self.generalize(&ty)
+                    } else {
+                        let level = self.level;
+                        self.lower_levels(&ty, level);
+                        Scheme::monomorphic(ty.clone())
+                    };
+                self.results
+                    .node_types
+                    .insert(let_binding.identifier.id, ty);
+                scheme
+            })
+            .collect()
+    }
+
+    fn infer_let_body(&mut self, body: &ast::LetBody) -> Ty {
+        match body {
+            ast::LetBody::Block(block) => self.infer_block(block),
+            ast::LetBody::Expr(expr) => self.infer_expr(expr),
+        }
+    }
+
+    /// Infers the type of the block, which is the type of its last item if
+    /// it is an expression, and `unit` otherwise. The values of the other
+    /// expressions are discarded, which is reported unless they are `()`.
+    fn infer_block(&mut self, block: &ast::Block) -> Ty {
+        let result = block.result().map(|expr| expr.id);
+        for item in &block.items {
+            match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    self.infer_local_let_bindings(std::slice::from_ref(let_binding))
+                }
+                ast::ItemKind::LetRec(bindings) => self.infer_local_let_bindings(bindings),
+                ast::ItemKind::Expr(expr) => {
+                    let ty = self.infer_expr(expr);
+                    if Some(expr.id) != result {
+                        self.check_discarded(expr.span, &ty);
+                    }
+                }
+                // Reported by the resolver.
+                _ => {}
+            }
+        }
+        match block.result() {
+            Some(expr) => self.results.node_types[&expr.id].clone(),
+            None => Ty::Prim(PrimTy::Unit),
+        }
+    }
+
+    fn check_discarded(&mut self, span: SourceSpan, ty: &Ty) {
+        match self.resolve(ty) {
+            Ty::Prim(PrimTy::Unit) | Ty::Var(_) | Ty::Error => {}
+            ty => {
+                let message = format!(
+                    "the value of this expression of type `{}` is discarded",
+                    self.print(&ty)
+                );
+                self.session.warning(span, message);
+            }
+        }
+    }
+
+    fn infer_local_let_bindings(&mut self, bindings: &[ast::LetBinding]) {
+        let schemes = self.infer_let_bindings(bindings, |checker, let_binding, scheme| {
+            checker.locals.insert(let_binding.identifier.id, scheme);
+        });
+        for (let_binding, scheme) in bindings.iter().zip(schemes) {
+            let node = let_binding.identifier.id;
+            self.results.local_schemes.insert(node, scheme.clone());
+            self.locals.insert(node, scheme);
+        }
+    }
+
+    fn infer_expr(&mut self, expr: &ast::Expr) -> Ty {
+        let ty = match &expr.kind {
+            ast::ExprKind::Literal(literal) => literal_ty(literal),
+            ast::ExprKind::Path(path)
+                if self.resolutions.paths.get(&path.id)
+                    == Some(&Res::Builtin(Builtin::Sprintf)) =>
+            {
+                self.session.error(
+                    path.span,
+                    "`sprintf` has to be applied to a format string literal",
+                );
+                Ty::Error
+            }
+            ast::ExprKind::Path(path) => self.infer_path(path),
+            ast::ExprKind::Application(callee, arguments) => {
+                let mut ty = match self.sprintf_ty(callee, &arguments[0]) {
+                    Some(ty) => ty,
+                    None => self.infer_expr(callee),
+                };
+                for (i, argument) in arguments.iter().enumerate() {
+                    let argument_ty = self.infer_expr(argument);
+                    ty = match self.shallow_resolve(&ty) {
+                        Ty::Function(parameter, result) => {
+                            self.unify(argument.span, &parameter, &argument_ty);
+                            *result
+                        }
+                        Ty::Var(_) => {
+                            let result = self.new_var();
+                            let function_ty = Ty::function(argument_ty, result.clone());
+                            self.unify(callee.span, &ty, &function_ty);
+                            result
+                        }
+                        Ty::Error => Ty::Error,
+                        ty => {
+                            let message = format!(
+                                "this expression has type `{}` and cannot be applied to {} argument{}",
+                                self.print(&ty),
+                                arguments.len() - i,
+                                if arguments.len() - i == 1 { "" } else { "s" }
+                            );
+                            let span = if i == 0 {
+                                callee.span
+                            } else {
+                                callee.span.to(arguments[i - 1].span)
+                            };
+                            self.session.error(span, message);
+                            Ty::Error
+                        }
+                    };
+                }
+                ty
+            }
+            ast::ExprKind::TailCall(call) | ast::ExprKind::Paren(call) => self.infer_expr(call),
+            ast::ExprKind::Tuple(elements) => Ty::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.infer_expr(element))
+                    .collect(),
+            ),
+            ast::ExprKind::List(elements) => Ty::List(Box::new(self.infer_elements(elements))),
+            ast::ExprKind::Array(elements) => Ty::Array(Box::new(self.infer_elements(elements))),
+            ast::ExprKind::Record(fields) => self.infer_record(expr.span, None, fields),
+            ast::ExprKind::RecordUpdate(record, fields) => {
+                self.infer_record(expr.span, Some(record), fields)
+            }
+            ast::ExprKind::FieldAccess(record, field) => {
+                let record_ty = self.infer_expr(record);
+                match self.instantiate_field(field) {
+                    Some((owner_ty, field_ty)) => {
+                        self.unify(record.span, &owner_ty, &record_ty);
+                        field_ty
+                    }
+                    None => Ty::Error,
+                }
+            }
+            ast::ExprKind::Match(scrutinee, arms) => {
+                let scrutinee_ty = self.infer_expr(scrutinee);
+                let ty = self.new_var();
+                for arm in arms {
+                    self.check_pattern(&arm.pattern, &scrutinee_ty);
+                    let arm_ty = self.infer_let_body(&arm.body);
+                    self.unify(let_body_span(&arm.body), &ty, &arm_ty);
+                }
+                ty
+            }
+            ast::ExprKind::Try(body, arms) => {
+                let ty = self.infer_let_body(body);
+                for arm in arms {
+                    self.check_pattern(&arm.pattern, &Ty::Prim(PrimTy::Exn));
+                    let arm_ty = self.infer_let_body(&arm.body);
+                    self.unify(let_body_span(&arm.body), &ty, &arm_ty);
+                }
+                ty
+            }
+            ast::ExprKind::If(condition, then_branch, else_branch) => {
+                let condition_ty = self.infer_expr(condition);
+                self.unify(condition.span, &Ty::Prim(PrimTy::Bool), &condition_ty);
+                let then_ty = self.infer_let_body(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let else_ty = self.infer_let_body(else_branch);
+                        self.unify(let_body_span(else_branch), &then_ty, &else_ty);
+                        then_ty
+                    }
+                    // Without the `else` branch, the value of the `then`
+                    // branch would be lost.
+                    None => {
+                        let unit = Ty::Prim(PrimTy::Unit);
+                        self.unify(let_body_span(then_branch), &unit, &then_ty);
+                        unit
+                    }
+                }
+            }
+            ast::ExprKind::While(condition, body) => {
+                let condition_ty = self.infer_expr(condition);
+                self.unify(condition.span, &Ty::Prim(PrimTy::Bool), &condition_ty);
+                self.check_loop_body(body);
+                Ty::Prim(PrimTy::Unit)
+            }
+            ast::ExprKind::For(for_loop) => {
+                let int = Ty::Prim(PrimTy::Int);
+                for bound in &[&for_loop.start, &for_loop.end] {
+                    let bound_ty = self.infer_expr(bound);
+                    self.unify(bound.span, &int, &bound_ty);
+                }
+                self.bind(&for_loop.binding, &int);
+                self.check_loop_body(&for_loop.body);
+                Ty::Prim(PrimTy::Unit)
+            }
+            ast::ExprKind::Lambda(parameters, body) => {
+                let parameters = parameters
+                    .iter()
+                    .map(|parameter| {
+                        let ty = self.new_var();
+                        self.check_pattern(parameter, &ty);
+                        ty
+                    })
+                    .collect::<Vec<_>>();
+                let body_ty = self.infer_let_body(body);
+                parameters
+                    .into_iter()
+                    .rev()
+                    .fold(body_ty, |result, parameter| Ty::function(parameter, result))
+            }
+            ast::ExprKind::Let(let_binding, body) => {
+                self.infer_local_let_bindings(std::slice::from_ref(let_binding));
+                self.infer_let_body(body)
+            }
+            ast::ExprKind::Binary(op, lhs, rhs) => {
+                let tys = self.binary_op_tys(op.kind);
+                self.infer_operands(tys, Some(lhs), Some(rhs))
+            }
+            ast::ExprKind::Infix(operator, lhs, rhs) => {
+                match self.resolutions.paths.get(&operator.id) {
+                    Some(Res::Def(def)) if self.resolutions.is_prelude_def(*def, "|>") => {
+                        self.infer_pipeline(rhs, lhs)
+                    }
+                    Some(Res::Def(def)) if self.resolutions.is_prelude_def(*def, "<|") => {
+                        self.infer_pipeline(lhs, rhs)
+                    }
+                    _ => {
+                        let tys = self.user_operator_tys(operator);
+                        self.infer_operands(tys, Some(lhs), Some(rhs))
+                    }
+                }
+            }
+            ast::ExprKind::Section(operator, lhs, rhs) => {
+                let tys = match operator {
+                    ast::SectionOperator::Builtin(op) => self.binary_op_tys(op.kind),
+                    ast::SectionOperator::User(path) => self.user_operator_tys(path),
+                };
+                let (lhs_ty, rhs_ty, _) = tys.clone();
+                let result = self.infer_operands(tys, lhs.as_deref(), rhs.as_deref());
+                // The missing operands become the parameters.
+                let parameters = match (lhs, rhs) {
+                    (None, None) => vec![lhs_ty, rhs_ty],
+                    (Some(_), None) => vec![rhs_ty],
+                    (None, Some(_)) => vec![lhs_ty],
+                    (Some(_), Some(_)) => vec![],
+                };
+                parameters
+                    .into_iter()
+                    .rev()
+                    .fold(result, |result, parameter| Ty::function(parameter, result))
+            }
+            ast::ExprKind::Unary(op, operand) => match op.kind {
+                ast::UnaryOpKind::Negate => {
+                    let int = Ty::Prim(PrimTy::Int);
+                    let operand_ty = self.infer_expr(operand);
+                    self.unify(operand.span, &int, &operand_ty);
+                    int
+                }
+                ast::UnaryOpKind::Deref => {
+                    let value = self.new_var();
+                    let reference = Ty::Ref(Box::new(value.clone()));
+                    let operand_ty = self.infer_expr(operand);
+                    self.unify(operand.span, &reference, &operand_ty);
+                    value
+                }
+            },
+            ast::ExprKind::Typed(inner, ty) => {
+                let expected = self.lower_ty(ty);
+                let found = self.infer_expr(inner);
+                self.unify(inner.span, &expected, &found);
+                expected
+            }
+            ast::ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    if let ast::InterpolationPart::Hole(hole) = part {
+                        let ty = self.infer_expr(hole);
+                        self.check_interpolated(hole.span, &ty);
+                    }
+                }
+                Ty::Prim(PrimTy::String)
+            }
+        };
+        self.results.node_types.insert(expr.id, ty.clone());
+        ty
+    }
+
+    /// Gets the types of the operands and the result of the built-in binary
+    /// operator.
+    fn binary_op_tys(&mut self, kind: ast::BinaryOpKind) -> (Ty, Ty, Ty) {
+        use ast::BinaryOpKind::*;
+        let int = Ty::Prim(PrimTy::Int);
+        let bool = Ty::Prim(PrimTy::Bool);
+        match kind {
+            Cons => {
+                let element = self.new_var();
+                let list = Ty::List(Box::new(element.clone()));
+                (element, list.clone(), list)
+            }
+            Assign => {
+                let value = self.new_var();
+                let reference = Ty::Ref(Box::new(value.clone()));
+                (reference, value, Ty::Prim(PrimTy::Unit))
+            }
+            Add | Subtract | Multiply | Divide => (int.clone(), int.clone(), int),
+            Less | LessEqual | Greater | GreaterEqual => (int.clone(), int, bool),
+            And | Or => (bool.clone(), bool.clone(), bool),
+            // The equality is structural, so it works for any type.
+            Equal | NotEqual => {
+                let operand = self.new_var();
+                (operand.clone(), operand, bool)
+            }
+        }
+    }
+
+    /// Derives the type of `sprintf` applied to the format string literal
+    /// from its conversions, e.g. `string -> int -> string` for `"%d"`.
+    /// Returns `None` if the callee is not `sprintf` or the format is not a
+    /// literal.
+    fn sprintf_ty(&mut self, callee: &ast::Expr, format: &ast::Expr) -> Option<Ty> {
+        let is_sprintf = matches!(
+            &callee.kind,
+            ast::ExprKind::Path(path)
+                if self.resolutions.paths.get(&path.id) == Some(&Res::Builtin(Builtin::Sprintf))
+        );
+        let literal = match &format.kind {
+            ast::ExprKind::Literal(literal) if is_sprintf => literal,
+            _ => return None,
+        };
+        if !matches!(literal.kind, ast::LiteralKind::String) {
+            return None;
+        }
+
+        // The conversions are found in the source code of the literal, so
+        // the errors point into it; the escape sequences never contain `%`.
+        let contents = SourceSpan::new(literal.span.start + 1, literal.span.end - 1);
+        let string = Ty::Prim(PrimTy::String);
+        let result = match format::parse_format(self.session.source_map.span_to_snippet(contents)) {
+            Ok(pieces) => pieces
+                .iter()
+                .rev()
+                .fold(string.clone(), |result, piece| match piece {
+                    FormatPiece::Conversion(conversion) => {
+                        Ty::function(Ty::Prim(conversion.ty()), result)
+                    }
+                    FormatPiece::Text(_) => result,
+                }),
+            Err(error) => {
+                let span = SourceSpan::from_length(contents.start + error.offset, error.length);
+                self.session.error(span, error.message);
+                Ty::Error
+            }
+        };
+        let ty = Ty::function(string, result);
+        self.results.node_types.insert(callee.id, ty.clone());
+        Some(ty)
+    }
+
+    /// Checks that the value of the type can be interpolated into a string.
+    /// Without other constraints, the interpolated values are strings.
+    fn check_interpolated(&mut self, span: SourceSpan, ty: &Ty) {
+        match self.shallow_resolve(ty) {
+            Ty::Prim(PrimTy::Int | PrimTy::Bool | PrimTy::String) | Ty::Error => {}
+            Ty::Var(_) => self.unify(span, &Ty::Prim(PrimTy::String), ty),
+            ty => {
+                let message = format!(
+                    "cannot interpolate a value of type `{}`, only `int`, `bool` and `string` values",
+                    self.print(&ty)
+                );
+                self.session.error(span, message);
+            }
+        }
+    }
+
+    /// Gets the types of the operands and the result of the user-defined
+    /// operator, which is a function of two arguments.
+    fn user_operator_tys(&mut self, operator: &ast::Path) -> (Ty, Ty, Ty) {
+        let operator_ty = self.infer_path(operator);
+        let (lhs, rhs, result) = (self.new_var(), self.new_var(), self.new_var());
+        let expected = Ty::function(lhs.clone(), Ty::function(rhs.clone(), result.clone()));
+        self.unify(operator.span, &expected, &operator_ty);
+        (lhs, rhs, result)
+    }
+
+    /// Infers the pipeline of the prelude, `x |> f` or `f <| x`, as the
+    /// application of the function to the argument. A function expecting
+    /// more arguments before the piped one is reported as such, rather than
+    /// as a mismatch of its first parameter.
+    fn infer_pipeline(&mut self, function: &ast::Expr, argument: &ast::Expr) -> Ty {
+        let (function_ty, argument_ty) = if function.span.start < argument.span.start {
+            let function_ty = self.infer_expr(function);
+            (function_ty, self.infer_expr(argument))
+        } else {
+            let argument_ty = self.infer_expr(argument);
+            (self.infer_expr(function), argument_ty)
+        };
+
+        match self.shallow_resolve(&function_ty) {
+            Ty::Function(parameter, result) => {
+                let parameters = self.parameter_tys(&function_ty);
+                let expects_more = parameters.len() > 1
+                    && !self.can_unify(&parameter, &argument_ty)
+                    && self.can_unify(parameters.last().unwrap(), &argument_ty);
+                if expects_more {
+                    let message = format!(
+                        "this function expects {} arguments but the pipeline supplies 1",
+                        parameters.len()
+                    );
+                    self.session.error(function.span, message);
+                    return Ty::Error;
+                }
+                self.unify(argument.span, &parameter, &argument_ty);
+                *result
+            }
+            Ty::Var(_) => {
+                let result = self.new_var();
+                let expected = Ty::function(argument_ty, result.clone());
+                self.unify(function.span, &expected, &function_ty);
+                result
+            }
+            Ty::Error => Ty::Error,
+            ty => {
+                let message = format!(
+                    "this expression has type `{}` and cannot be applied in a pipeline",
+                    self.print(&ty)
+                );
+                self.session.error(function.span, message);
+                Ty::Error
+            }
+        }
+    }
+
+    /// Gets the types of the parameters of the function type, following the
+    /// arrows as far as they are known.
+    fn parameter_tys(&self, ty: &Ty) -> Vec<Ty> {
+        let mut parameters = Vec::new();
+        let mut ty = self.shallow_resolve(ty);
+        while let Ty::Function(parameter, result) = ty {
+            parameters.push(*parameter);
+            ty = self.shallow_resolve(&result);
+        }
+        parameters
+    }
+
+    /// Checks whether the types unify, without binding any variables.
+    fn can_unify(&mut self, a: &Ty, b: &Ty) -> bool {
+        let snapshot = self.variables.clone();
+        let result = self.unify_tys(a, b).is_ok();
+        self.variables = snapshot;
+        result
+    }
+
+    /// Checks the given operands against the operator types, returning the
+    /// type of the result.
+    fn infer_operands(
+        &mut self,
+        (lhs_ty, rhs_ty, result): (Ty, Ty, Ty),
+        lhs: Option<&ast::Expr>,
+        rhs: Option<&ast::Expr>,
+    ) -> Ty {
+        for (expected, operand) in [(lhs_ty, lhs), (rhs_ty, rhs)] {
+            if let Some(operand) = operand {
+                let found = self.infer_expr(operand);
+                self.unify(operand.span, &expected, &found);
+            }
+        }
+        result
+    }
+
+    /// Checks that the loop body is `()`, as its value would be lost.
+    fn check_loop_body(&mut self, body: &ast::LetBody) {
+        let body_ty = self.infer_let_body(body);
+        self.unify(let_body_span(body), &Ty::Prim(PrimTy::Unit), &body_ty);
+    }
+
+    /// Infers the common type of the list or array elements.
+    fn infer_elements(&mut self, elements: &[ast::Expr]) -> Ty {
+        let ty = self.new_var();
+        for element in elements {
+            let element_ty = self.infer_expr(element);
+            self.unify(element.span, &ty, &element_ty);
+        }
+        ty
+    }
+
+    fn infer_path(&mut self, path: &ast::Path) -> Ty {
+        match self.resolutions.paths.get(&path.id) {
+            Some(Res::Def(def)) => match self.results.def_schemes.get(def) {
+                Some(scheme) => {
+                    let scheme = scheme.clone();
+                    self.instantiate(&scheme)
+                }
+                None => Ty::Error,
+            },
+            Some(Res::Local(node)) => match self.locals.get(node) {
+                Some(scheme) => {
+                    let scheme = scheme.clone();
+                    self.instantiate(&scheme)
+                }
+                None => Ty::Error,
+            },
+            Some(Res::Builtin(builtin)) => self.builtin_ty(*builtin),
+            _ => Ty::Error,
+        }
+    }
+
+    fn builtin_ty(&mut self, builtin: Builtin) -> Ty {
+        let int = || Ty::Prim(PrimTy::Int);
+        let string = || Ty::Prim(PrimTy::String);
+        let unit = || Ty::Prim(PrimTy::Unit);
+        let exn = || Ty::Prim(PrimTy::Exn);
+        match builtin {
+            Builtin::Ref => {
+                let value = self.new_var();
+                Ty::function(value.clone(), Ty::Ref(Box::new(value)))
+            }
+            Builtin::Raise => Ty::function(exn(), self.new_var()),
+            Builtin::Compare => {
+                let value = self.new_var();
+                Ty::function(value.clone(), Ty::function(value, int()))
+            }
+            Builtin::StringLength => Ty::function(string(), int()),
+            Builtin::StringGet => Ty::function(string(), Ty::function(int(), int())),
+            Builtin::StringSub => {
+                Ty::function(string(), Ty::function(int(), Ty::function(int(), string())))
+            }
+            Builtin::StringConcat => Ty::function(string(), Ty::function(string(), string())),
+            Builtin::StringConcatList => Ty::function(Ty::List(Box::new(string())), string()),
+            Builtin::StringOfCharCode | Builtin::StringOfInt => Ty::function(int(), string()),
+            // The type depends on the format, see `sprintf_ty`.
+            Builtin::Sprintf => Ty::Error,
+            Builtin::IntOfString => Ty::function(string(), int()),
+            Builtin::PrintString | Builtin::EprintString => Ty::function(string(), unit()),
+            Builtin::ReadLine => Ty::function(unit(), string()),
+            Builtin::ReadFile => Ty::function(string(), string()),
+            Builtin::WriteFile => Ty::function(string(), Ty::function(string(), unit())),
+            Builtin::Failure | Builtin::InvalidArgument => Ty::function(string(), exn()),
+            Builtin::DivisionByZero
+            | Builtin::MatchFailure
+            | Builtin::StackOverflow
+            | Builtin::NotFound
+            | Builtin::EndOfFile => exn(),
+        }
+    }
+
+    /// Infers the type of the record construction or update. The record type
+    /// is determined by the first field.
+    fn infer_record(
+        &mut self,
+        span: SourceSpan,
+        record: Option<&ast::Expr>,
+        fields: &[ast::FieldExpr],
+    ) -> Ty {
+        let owner = fields
+            .iter()
+            .find_map(|field| self.field_owner(&field.identifier));
+        let (owner, owner_ty, arguments) = match owner {
+            Some(owner) => {
+                let parameters = self.results.adts[&owner].parameters;
+                let arguments = (0..parameters).map(|_| self.new_var()).collect::<Vec<_>>();
+                (owner, Ty::Adt(owner, arguments.clone()), arguments)
+            }
+            None => {
+                for field in fields {
+                    let _ = self.infer_expr(&field.expr);
+                }
+                return Ty::Error;
+            }
+        };
+
+        if let Some(record) = record {
+            let record_ty = self.infer_expr(record);
+            self.unify(record.span, &owner_ty, &record_ty);
+        }
+
+        let mut given = HashSet::new();
+        for field in fields {
+            let ty = self.infer_expr(&field.expr);
+            let def = match self.resolutions.fields.get(&field.identifier.id) {
+                Some(def) => *def,
+                None => continue,
+            };
+            if self.field_owner(&field.identifier) != Some(owner) {
+                let message = format!(
+                    "field `{}` does not belong to the record type `{}`",
+                    self.resolutions.def(def).name,
+                    self.resolutions.def(owner).name
+                );
+                self.session.error(field.identifier.span, message);
+                continue;
+            }
+            if !given.insert(def) {
+                let message = format!(
+                    "field `{}` is given more than once",
+                    self.resolutions.def(def).name
+                );
+                self.session.error(field.identifier.span, message);
+                continue;
+            }
+            let field_ty = self.field_ty(owner, def).subst(&arguments);
+            self.unify(field.expr.span, &field_ty, &ty);
+        }
+
+        if record.is_none() {
+            let missing = match &self.results.adts[&owner].kind {
+                AdtKind::Record(field_defs) => field_defs
+                    .iter()
+                    .filter(|field| !given.contains(&field.def))
+                    .map(|field| format!("`{}`", self.resolutions.def(field.def).name))
+                    .collect::<Vec<_>>(),
+                AdtKind::Variant(_) => Vec::new(),
+            };
+            if !missing.is_empty() {
+                let message = format!(
+                    "missing field{} {} in the record of type `{}`",
+                    if missing.len() == 1 { "" } else { "s" },
+                    missing.join(", "),
+                    self.resolutions.def(owner).name
+                );
+                self.session.error(span, message);
+            }
+        }
+
+        owner_ty
+    }
+
+    fn field_owner(&self, field: &ast::Literal) -> Option<DefId> {
+        let def = self.resolutions.fields.get(&field.id)?;
+        match self.resolutions.def(*def).kind {
+            DefKind::Field(owner) if self.results.adts.contains_key(&owner) => Some(owner),
+            _ => None,
+        }
+    }
+
+    fn field_ty(&self, owner: DefId, field: DefId) -> Ty {
+        match &self.results.adts[&owner].kind {
+            AdtKind::Record(fields) => fields
+                .iter()
+                .find(|f| f.def == field)
+                .map_or(Ty::Error, |f| f.ty.clone()),
+            AdtKind::Variant(_) => Ty::Error,
+        }
+    }
+
+    /// Instantiates the record type owning the field and the field type
+    /// with fresh type variables.
+    fn instantiate_field(&mut self, field: &ast::Literal) -> Option<(Ty, Ty)> {
+        let owner = self.field_owner(field)?;
+        let def = self.resolutions.fields[&field.id];
+        let parameters = self.results.adts[&owner].parameters;
+        let arguments = (0..parameters).map(|_| self.new_var()).collect::<Vec<_>>();
+        let field_ty = self.field_ty(owner, def).subst(&arguments);
+        Some((Ty::Adt(owner, arguments), field_ty))
+    }
+
+    /// Checks that the pattern matches the values of the expected type,
+    /// assigning the types to the variables it binds.
+    fn check_pattern(&mut self, pattern: &ast::Pattern, expected: &Ty) {
+        match &pattern.kind {
+            ast::PatternKind::Wildcard => {}
+            ast::PatternKind::Binding(identifier) => self.bind(identifier, expected),
+            ast::PatternKind::Literal(literal) => {
+                self.unify(pattern.span, expected, &literal_ty(literal));
+            }
+            ast::PatternKind::Constructor(path, arguments) => {
+                let ty = self.infer_path(path);
+                let mut ty = self.resolve(&ty);
+                let mut parameters = Vec::new();
+                while let Ty::Function(parameter, result) = ty {
+                    parameters.push(*parameter);
+                    ty = *result;
+                }
+                if ty != Ty::Error && parameters.len() != arguments.len() {
+                    let message = format!(
+                        "constructor `{}` expects {} argument{}, but the pattern has {}",
+                        self.text(path.span),
+                        parameters.len(),
+                        if parameters.len() == 1 { "" } else { "s" },
+                        arguments.len()
+                    );
+                    self.session.error(pattern.span, message);
+                    ty = Ty::Error;
+                }
+                self.unify(pattern.span, expected, &ty);
+                for (i, argument) in arguments.iter().enumerate() {
+                    let parameter = parameters.get(i).cloned().unwrap_or(Ty::Error);
+                    self.check_pattern(argument, &parameter);
+                }
+            }
+            ast::PatternKind::Record(fields) => {
+                for field in fields {
+                    let field_ty = match self.instantiate_field(&field.identifier) {
+                        Some((owner_ty, field_ty)) => {
+                            self.unify(pattern.span, expected, &owner_ty);
+                            field_ty
+                        }
+                        None => Ty::Error,
+                    };
+                    match &field.pattern {
+                        Some(pattern) => self.check_pattern(pattern, &field_ty),
+                        None => self.bind(&field.identifier, &field_ty),
+                    }
+                }
+            }
+            ast::PatternKind::Or(alternatives) => {
+                for alternative in alternatives {
+                    self.check_pattern(alternative, expected);
+                }
+            }
+            ast::PatternKind::Paren(pattern) => self.check_pattern(pattern, expected),
+            ast::PatternKind::Tuple(elements) => {
+                let tys = elements.iter().map(|_| self.new_var()).collect::<Vec<_>>();
+                self.unify(pattern.span, expected, &Ty::Tuple(tys.clone()));
+                for (element, ty) in elements.iter().zip(&tys) {
+                    self.check_pattern(element, ty);
+                }
+            }
+            ast::PatternKind::List(elements) | ast::PatternKind::Array(elements) => {
+                let element_ty = self.new_var();
+                let ty = match &pattern.kind {
+                    ast::PatternKind::List(_) => Ty::List(Box::new(element_ty.clone())),
+                    _ => Ty::Array(Box::new(element_ty.clone())),
+                };
+                self.unify(pattern.span, expected, &ty);
+                for element in elements {
+                    self.check_pattern(element, &element_ty);
+                }
+            }
+            ast::PatternKind::Cons(head, tail) => {
+                let element_ty = self.new_var();
+                let list = Ty::List(Box::new(element_ty.clone()));
+                self.unify(pattern.span, expected, &list);
+                self.check_pattern(head, &element_ty);
+                self.check_pattern(tail, &list);
+            }
+            ast::PatternKind::Typed(inner, ty) => {
+                let annotated = self.lower_ty(ty);
+                self.unify(pattern.span, &annotated, expected);
+                self.check_pattern(inner, &annotated);
+            }
+        }
+        self.results.node_types.insert(pattern.id, expected.clone());
+    }
+
+    /// Binds the variable to the type. The variables bound by the non-first
+    /// alternatives of or-patterns must have the same types as in the first.
+    fn bind(&mut self, identifier: &ast::Literal, ty: &Ty) {
+        if let Some(first) = self.resolutions.or_bindings.get(&identifier.id) {
+            if let Some(scheme) = self.locals.get(first) {
+                let first_ty = scheme.ty.clone();
+                self.unify(identifier.span, &first_ty, ty);
+            }
+        }
+        self.locals
+            .insert(identifier.id, Scheme::monomorphic(ty.clone()));
+        self.results.node_types.insert(identifier.id, ty.clone());
+    }
+
+    fn new_var(&mut self) -> Ty {
+        self.new_var_at(self.level)
+    }
+
+    fn new_var_at(&mut self, level: u32) -> Ty {
+        let vid = TyVid::new(self.variables.len());
+        self.variables.push(VarValue::Unbound { level });
+        Ty::Var(vid)
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Ty {
+        if scheme.generics == 0 {
+            return scheme.ty.clone();
+        }
+        let arguments = (0..scheme.generics)
+            .map(|_| self.new_var())
+            .collect::<Vec<_>>();
+        scheme.ty.subst(&arguments)
+    }
+
+    /// Turns the variables created deeper than the current level into the
+    /// generic parameters of the scheme.
+    fn generalize(&mut self, ty: &Ty) -> Scheme {
+        let mut generics = HashMap::new();
+        let ty = self.generalize_ty(ty, &mut generics);
+        Scheme {
+            generics: generics.len() as u32,
+            ty,
+        }
+    }
+
+    fn generalize_ty(&mut self, ty: &Ty, generics: &mut HashMap<TyVid, u32>) -> Ty {
+        match self.shallow_resolve(ty) {
+            Ty::Var(vid) => match self.variables[vid.as_usize()] {
+                VarValue::Unbound { level } if level > self.level => {
+                    let count = generics.len() as u32;
+                    Ty::Generic(*generics.entry(vid).or_insert(count))
+                }
+                _ => Ty::Var(vid),
+            },
+            ty @ (Ty::Prim(_) | Ty::Generic(_) | Ty::Error) => ty,
+            Ty::Adt(def, arguments) => Ty::Adt(
+                def,
+                arguments
+                    .iter()
+                    .map(|argument| self.generalize_ty(argument, generics))
+                    .collect(),
+            ),
+            Ty::Tuple(elements) => Ty::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.generalize_ty(element, generics))
+                    .collect(),
+            ),
+            Ty::List(element) => Ty::List(Box::new(self.generalize_ty(&element, generics))),
+            Ty::Array(element) => Ty::Array(Box::new(self.generalize_ty(&element, generics))),
+            Ty::Ref(element) => Ty::Ref(Box::new(self.generalize_ty(&element, generics))),
+            Ty::Function(parameter, result) => Ty::function(
+                self.generalize_ty(&parameter, generics),
+                self.generalize_ty(&result, generics),
+            ),
+        }
+    }
+
+    /// Lowers the levels of the variables in the type to the level, so they
+    /// are not generalized by the enclosing bindings.
+    fn lower_levels(&mut self, ty: &Ty, level: u32) {
+        match self.shallow_resolve(ty) {
+            Ty::Var(vid) => {
+                if let VarValue::Unbound { level: var_level } = &mut self.variables[vid.as_usize()]
+                {
+                    *var_level = (*var_level).min(level);
+                }
+            }
+            Ty::Prim(_) | Ty::Generic(_) | Ty::Error => {}
+            Ty::Adt(_, tys) | Ty::Tuple(tys) => {
+                for ty in &tys {
+                    self.lower_levels(ty, level);
+                }
+            }
+            Ty::List(element) | Ty::Array(element) | Ty::Ref(element) => {
+                self.lower_levels(&element, level)
+            }
+            Ty::Function(parameter, result) => {
+                self.lower_levels(&parameter, level);
+                self.lower_levels(&result, level);
+            }
+        }
+    }
+
+    /// Follows the bound variables until a type which is not a bound
+    /// variable is found.
+    fn shallow_resolve(&self, ty: &Ty) -> Ty {
+        let mut ty = ty;
+        while let Ty::Var(vid) = ty {
+            match &self.variables[vid.as_usize()] {
+                VarValue::Bound(bound) => ty = bound,
+                VarValue::Unbound { .. } => break,
+            }
+        }
+        ty.clone()
+    }
+
+    /// Substitutes all the bound variables in the type.
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match self.shallow_resolve(ty) {
+            ty @ (Ty::Var(_) | Ty::Prim(_) | Ty::Generic(_) | Ty::Error) => ty,
+            Ty::Adt(def, arguments) => {
+                Ty::Adt(def, arguments.iter().map(|a| self.resolve(a)).collect())
+            }
+            Ty::Tuple(elements) => Ty::Tuple(elements.iter().map(|e| self.resolve(e)).collect()),
+            Ty::List(element) => Ty::List(Box::new(self.resolve(&element))),
+            Ty::Array(element) => Ty::Array(Box::new(self.resolve(&element))),
+            Ty::Ref(element) => Ty::Ref(Box::new(self.resolve(&element))),
+            Ty::Function(parameter, result) => {
+                Ty::function(self.resolve(&parameter), self.resolve(&result))
+            }
+        }
+    }
+
+    /// Unifies the type found at the span with the expected one, reporting
+    /// the mismatch.
+    fn unify(&mut self, span: SourceSpan, expected: &Ty, found: &Ty) {
+        match self.unify_tys(expected, found) {
+            Ok(()) => {}
+            Err(UnifyError::Mismatch) => {
+                let mut printer = TyPrinter::new(self.resolutions);
+                let message = format!(
+                    "mismatched types: expected `{}`, found `{}`",
+                    printer.print(&self.resolve(expected)),
+                    printer.print(&self.resolve(found))
+                );
+                self.session.error(span, message);
+            }
+            Err(UnifyError::InfiniteType(vid, ty)) => {
+                let mut printer = TyPrinter::new(self.resolutions);
+                let message = format!(
+                    "cannot construct the infinite type `{}` = `{}`",
+                    printer.print(&Ty::Var(vid)),
+                    printer.print(&self.resolve(&ty))
+                );
+                self.session.error(span, message);
+            }
+        }
+    }
+
+    fn unify_tys(&mut self, a: &Ty, b: &Ty) -> Result<(), UnifyError> {
+        match (self.shallow_resolve(a), self.shallow_resolve(b)) {
+            (Ty::Error, _) | (_, Ty::Error) => Ok(()),
+            (Ty::Var(a), Ty::Var(b)) if a == b => Ok(()),
+            (Ty::Var(vid), ty) | (ty, Ty::Var(vid)) => self.bind_var(vid, ty),
+            (Ty::Prim(a), Ty::Prim(b)) if a == b => Ok(()),
+            (Ty::Generic(a), Ty::Generic(b)) if a == b => Ok(()),
+            (Ty::Adt(a, a_arguments), Ty::Adt(b, b_arguments)) if a == b => {
+                for (a, b) in a_arguments.iter().zip(&b_arguments) {
+                    self.unify_tys(a, b)?;
+                }
+                Ok(())
+            }
+            (Ty::Tuple(a), Ty::Tuple(b)) if a.len() == b.len() => {
+                for (a, b) in a.iter().zip(&b) {
+                    self.unify_tys(a, b)?;
+                }
+                Ok(())
+            }
+            (Ty::List(a), Ty::List(b))
+            | (Ty::Array(a), Ty::Array(b))
+            | (Ty::Ref(a), Ty::Ref(b)) => self.unify_tys(&a, &b),
+            (Ty::Function(a_parameter, a_result), Ty::Function(b_parameter, b_result)) => {
+                self.unify_tys(&a_parameter, &b_parameter)?;
+                self.unify_tys(&a_result, &b_result)
+            }
+            _ => Err(UnifyError::Mismatch),
+        }
+    }
+
+    fn bind_var(&mut self, vid: TyVid, ty: Ty) -> Result<(), UnifyError> {
+        let level = match self.variables[vid.as_usize()] {
+            VarValue::Unbound { level } => level,
+            VarValue::Bound(_) => unreachable!(),
+        };
+        if self.occurs(vid, level, &ty) {
+            return Err(UnifyError::InfiniteType(vid, ty));
+        }
+        self.variables[vid.as_usize()] = VarValue::Bound(ty);
+        Ok(())
+    }
+
+    /// Checks whether the variable occurs in the type, lowering the levels
+    /// of the variables in the type to the level of the variable, so they
+    /// are not generalized if it is not.
+    fn occurs(&mut self, vid: TyVid, level: u32, ty: &Ty) -> bool {
+        match self.shallow_resolve(ty) {
+            Ty::Var(other) => {
+                if let VarValue::Unbound { level: other_level } =
+                    &mut self.variables[other.as_usize()]
+                {
+                    *other_level = (*other_level).min(level);
+                }
+                other == vid
+            }
+            Ty::Prim(_) | Ty::Generic(_) | Ty::Error => false,
+            Ty::Adt(_, tys) | Ty::Tuple(tys) => tys.iter().any(|ty| self.occurs(vid, level, ty)),
+            Ty::List(element) | Ty::Array(element) | Ty::Ref(element) => {
+                self.occurs(vid, level, &element)
+            }
+            Ty::Function(parameter, result) => {
+                self.occurs(vid, level, &parameter) || self.occurs(vid, level, &result)
+            }
+        }
+    }
+
+    /// Checks whether the body is a syntactic value, whose evaluation cannot
+    /// create a reference: a constant, a variable, a function or a constructor
+    /// applied to values.
+    fn is_value(&self, body: &ast::LetBody) -> bool {
+        match body {
+            ast::LetBody::Block(block) => block.items.iter().all(|item| match &item.kind {
+                ast::ItemKind::LetBinding(let_binding) => {
+                    !let_binding.parameters.is_empty() || self.is_value(&let_binding.body)
+                }
+                ast::ItemKind::Expr(expr) => self.is_value_expr(expr),
+                _ => true,
+            }),
+            ast::LetBody::Expr(expr) => self.is_value_expr(expr),
+        }
+    }
+
+    fn is_value_expr(&self, expr: &ast::Expr) -> bool {
+        match &expr.kind {
+            ast::ExprKind::Literal(_) | ast::ExprKind::Path(_) | ast::ExprKind::Lambda(..) => true,
+            ast::ExprKind::Paren(inner) | ast::ExprKind::Typed(inner, _) => {
+                self.is_value_expr(inner)
+            }
+            ast::ExprKind::Tuple(elements) | ast::ExprKind::List(elements) => {
+                elements.iter().all(|e| self.is_value_expr(e))
+            }
+            ast::ExprKind::Record(fields) => {
+                fields.iter().all(|field| self.is_value_expr(&field.expr))
+            }
+            ast::ExprKind::Binary(op, lhs, rhs) if op.kind == ast::BinaryOpKind::Cons => {
+                self.is_value_expr(lhs) && self.is_value_expr(rhs)
+            }
+            // A section is a function of the remaining operand.
+            ast::ExprKind::Section(_, lhs, rhs) => {
+                lhs.iter().chain(rhs).all(|e| self.is_value_expr(e))
+            }
+            // Only the constructors are known not to have effects.
+            ast::ExprKind::Application(callee, arguments) => {
+                self.is_constructor(callee) && arguments.iter().all(|e| self.is_value_expr(e))
+            }
+            ast::ExprKind::Let(let_binding, body) => {
+                (!let_binding.parameters.is_empty() || self.is_value(&let_binding.body))
+                    && self.is_value(body)
+            }
+            _ => false,
+        }
+    }
+
+    fn is_constructor(&self, expr: &ast::Expr) -> bool {
+        let path = match &expr.kind {
+            ast::ExprKind::Path(path) => path,
+            _ => return false,
+        };
+        match self.resolutions.paths.get(&path.id) {
+            Some(Res::Def(def)) => matches!(
+                self.resolutions.def(*def).kind,
+                DefKind::Constructor(_) | DefKind::Exception
+            ),
+            Some(Res::Builtin(builtin)) => builtin.is_exception(),
+            _ => false,
+        }
+    }
+
+    fn print(&self, ty: &Ty) -> String {
+        TyPrinter::new(self.resolutions).print(&self.resolve(ty))
+    }
+
+    fn text(&self, span: SourceSpan) -> String {
+        self.session.source_map.span_to_snippet(span).to_string()
+    }
+
+    /// Substitutes the inferred types into the results.
+    fn finish(mut self) -> TypeckResults {
+        let node_types = std::mem::take(&mut self.results.node_types);
+        self.results.node_types = node_types
+            .into_iter()
+            .map(|(node, ty)| (node, self.resolve(&ty)))
+            .collect();
+        let def_schemes = std::mem::take(&mut self.results.def_schemes);
+        self.results.def_schemes = def_schemes
+            .into_iter()
+            .map(|(def, scheme)| {
+                let ty = self.resolve(&scheme.ty);
+                (def, Scheme { ty, ..scheme })
+            })
+            .collect();
+        let local_schemes = std::mem::take(&mut self.results.local_schemes);
+        self.results.local_schemes = local_schemes
+            .into_iter()
+            .map(|(node, scheme)| {
+                let ty = self.resolve(&scheme.ty);
+                (node, Scheme { ty, ..scheme })
+            })
+            .collect();
+        self.results
+    }
+}
+
+fn literal_ty(literal: &ast::Literal) -> Ty {
+    match literal.kind {
+        ast::LiteralKind::Integer => Ty::Prim(PrimTy::Int),
+        ast::LiteralKind::String => Ty::Prim(PrimTy::String),
+        ast::LiteralKind::Bool => Ty::Prim(PrimTy::Bool),
+        ast::LiteralKind::Unit => Ty::Prim(PrimTy::Unit),
+        ast::LiteralKind::Identifier | ast::LiteralKind::TypeVariable => Ty::Error,
+    }
+}
+
+/// Gets the span of the expression giving the value of the body.
+fn let_body_span(body: &ast::LetBody) -> SourceSpan {
+    match body {
+        ast::LetBody::Block(block) => block.result().map_or(block.span, |expr| expr.span),
+        ast::LetBody::Expr(expr) => expr.span,
+    }
+}
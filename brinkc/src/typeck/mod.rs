@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+
+use crate::{ast::node_id::NodeId, resolve::DefId};
+
+pub mod format;
+mod infer;
+pub mod ty;
+
+pub use infer::{check_item, check_type_decls, typeck};
+use ty::{Scheme, Ty};
+
+/// A variant or record type declaration, with its parameters lowered to
+/// `Ty::Generic`s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdtDef {
+    pub parameters: usize,
+    pub kind: AdtKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdtKind {
+    Variant(Vec<VariantDef>),
+    Record(Vec<FieldDef>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantDef {
+    pub def: DefId,
+    pub arguments: Vec<Ty>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldDef {
+    pub def: DefId,
+    pub ty: Ty,
+}
+
+/// The type declarations of the whole program, which the top-level items
+/// are checked against one at a time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TypeDecls {
+    pub adts: HashMap<DefId, AdtDef>,
+    /// The expanded type aliases with their parameter counts.
+    pub aliases: HashMap<DefId, (usize, Ty)>,
+    /// The type schemes of the constructors and the exceptions.
+    pub schemes: HashMap<DefId, Scheme>,
+}
+
+/// The result of the type inference.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TypeckResults {
+    /// The types of the expressions, patterns and let-bound identifiers.
+    pub node_types: HashMap<NodeId, Ty>,
+    /// The type schemes of the module-level values and constructors.
+    pub def_schemes: HashMap<DefId, Scheme>,
+    /// The type schemes of the local let bindings, keyed by the nodes of the
+    /// bound identifiers.
+    pub local_schemes: HashMap<NodeId, Scheme>,
+    /// The variant and record types. The aliases are expanded during the
+    /// type checking, so they do not appear here.
+    pub adts: HashMap<DefId, AdtDef>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Type checks the source and prints the type of the definition.
+    fn type_of(source: &str, name: &str) -> String {
+        let test_util::Checked {
+            resolutions,
+            results,
+            ..
+        } = test_util::check(source);
+        let def = resolutions
+            .defs
+            .iter()
+            .rposition(|d| d.name == name)
+            .unwrap();
+        let (_, scheme) = results
+            .def_schemes
+            .iter()
+            .find(|(d, _)| d.as_usize() == def)
+            .unwrap();
+        ty::TyPrinter::new(&resolutions).print(&scheme.ty)
+    }
+
+    fn error_count(source: &str) -> u32 {
+        test_util::load(source).session.error_count()
+    }
+
+    #[test]
+    fn infers_polymorphic_function() {
+        assert_eq!("'a -> 'a", type_of("let id x = x\n", "id"));
+        assert_eq!(
+            "('a -> 'b) -> ('b -> 'c) -> 'a -> 'c",
+            type_of("let compose f g x = g (f x)\n", "compose")
+        );
+    }
+
+    #[test]
+    fn generalizes_let_bindings() {
+        let source = "let id x = x\nlet pair = id 1 = 1 && id true\n";
+        assert_eq!("bool", type_of(source, "pair"));
+    }
+
+    #[test]
+    fn infers_recursive_function() {
+        let source = "let rec fibonacci n =\n  match n\n  | 0 | 1 -> 1\n  | n -> fibonacci (n - 1) + fibonacci (n - 2)\n";
+        assert_eq!("int -> int", type_of(source, "fibonacci"));
+    }
+
+    #[test]
+    fn infers_recursive_groups() {
+        let source =
+            "let rec length xs =\n  match xs\n  | [] -> 0\n  | _ :: rest -> 1 + skip rest\n\
+                      and skip xs =\n  match xs\n  | [] -> 0\n  | _ :: rest -> length rest\n";
+        assert_eq!("list 'a -> int", type_of(source, "length"));
+        assert_eq!("list 'a -> int", type_of(source, "skip"));
+        let source = "let r =\n  let rec even n = if n = 0 then true else odd (n - 1)\n  and odd n = if n = 0 then false else even (n - 1)\n  (even 4, odd 4)\n";
+        assert_eq!("bool * bool", type_of(source, "r"));
+        // The bindings of the group are monomorphic in its bodies.
+        assert_eq!(1, error_count("let rec f x = g 1 + g true\nand g y = 0\n"));
+        assert_eq!(1, error_count("let rec f x = 1\nand f y = 2\n"));
+    }
+
+    #[test]
+    fn infers_variant_and_record_types() {
+        let source = "type Option a = None | Some a\n\
+                      type Point = { x: int; y: int }\n\
+                      let get default o =\n  match o\n  | None -> default\n  | Some x -> x\n\
+                      let move p = { p with x = p.x + 1 }\n";
+        assert_eq!("'a -> Option 'a -> 'a", type_of(source, "get"));
+        assert_eq!("Point -> Point", type_of(source, "move"));
+    }
+
+    #[test]
+    fn infers_types_declared_later() {
+        let source = "type Expr = Lit int | Block Stmt\n\
+                      type Stmt = Expr Expr | Seq Stmts\n\
+                      type Stmts = list Stmt\n\
+                      let block = Block (Seq [Expr (Lit 1)])\n";
+        assert_eq!("Expr", type_of(source, "block"));
+        assert_eq!(1, error_count("type A = B\ntype B = A\n"));
+    }
+
+    #[test]
+    fn infers_tuple_list_and_array_types() {
+        assert_eq!("int * bool", type_of("let x = (1, true)\n", "x"));
+        assert_eq!("list int", type_of("let x = 1 :: [2; 3]\n", "x"));
+        assert_eq!(
+            "array (list bool)",
+            type_of("let x = [| []; [true] |]\n", "x")
+        );
+        assert_eq!(1, error_count("let x = [1; true]\n"));
+    }
+
+    #[test]
+    fn infers_tuple_and_list_patterns() {
+        let source = "let rec sum xs =\n  match xs\n  | [] -> 0\n  | [x] -> x\n  | (x, _) :: rest -> x + sum rest\n";
+        assert_eq!(1, error_count(source));
+        let source = "let rec unzip xs =\n  match xs\n  | [] -> ([], [])\n  | (a, b) :: rest ->\n    match unzip rest\n    | (l, r) -> (a :: l, b :: r)\n";
+        assert_eq!(
+            "list ('a * 'b) -> list 'a * list 'b",
+            type_of(source, "unzip")
+        );
+        assert_eq!(
+            "array int -> int",
+            type_of(
+                "let first a =\n  match a\n  | [| x; _ |] -> x\n  | _ -> 0\n",
+                "first"
+            )
+        );
+    }
+
+    #[test]
+    fn parses_multi_line_literals() {
+        let source = "let xs = [\n  1\n  2;\n    3\n]\nlet p = (\n  xs,\n  [|\n    true\n  |]\n)\n";
+        assert_eq!("list int * array bool", type_of(source, "p"));
+    }
+
+    #[test]
+    fn infers_lambdas_and_let_expressions() {
+        assert_eq!(
+            "int -> int -> int",
+            type_of("let add = fun x y -> x + y\n", "add")
+        );
+        let source =
+            "let apply f x = f x\nlet y = apply (fun x ->\n    let z = x * 2 in\n    z + 1) 1\n";
+        assert_eq!("int", type_of(source, "y"));
+        // The lambda parameters are monomorphic.
+        let source = "let pair = (fun id -> (id 1, id true)) (fun x -> x)\n";
+        assert_eq!(1, error_count(source));
+        let source = "let pair =\n  let id x = x in\n  (id 1, id true)\n";
+        assert_eq!("int * bool", type_of(source, "pair"));
+    }
+
+    #[test]
+    fn infers_conditionals() {
+        let source = "let sign n =\n  if n < 0 then -1\n  elif n = 0 then 0\n  else if n < 10 then 1\n  else\n    let big = 2\n    big\n";
+        assert_eq!("int -> int", type_of(source, "sign"));
+        let source = "let f c =\n  if c then\n    ()\n  (if c then 1 else 2, [if c then\n    true\n  else false])\n";
+        assert_eq!("bool -> int * list bool", type_of(source, "f"));
+        assert_eq!(1, error_count("let f c = if c then 1 else true\n"));
+        assert_eq!(1, error_count("let f c = if c then 1\n"));
+        assert_eq!(1, error_count("let f c = if 1 then () else ()\n"));
+    }
+
+    #[test]
+    fn block_value_is_its_last_item() {
+        assert_eq!("int", type_of("let x =\n  let y = true\n  y\n  1\n", "x"));
+        assert_eq!("unit", type_of("let x =\n  1\n  let y = 2\n", "x"));
+    }
+
+    #[test]
+    fn infers_references_and_loops() {
+        let source = "let sum n =\n  let total = ref 0\n  for i = 1 to n do\n    total := !total + i\n  !total\n";
+        assert_eq!("int -> int", type_of(source, "sum"));
+        let source = "let count (r: ref int) =\n  while !r > 0 do r := !r - 1\n  for i = 10 downto 1 do\n    ()\n";
+        assert_eq!("ref int -> unit", type_of(source, "count"));
+        assert_eq!(1, error_count("let r = ref 1\nlet x = r := true\n"));
+        assert_eq!(1, error_count("let f n = while n do 1\n"));
+    }
+
+    #[test]
+    fn restricts_generalization_to_values() {
+        let source = "let r = ref []\nlet a = r := [1]\nlet b = r := [true]\n";
+        assert_eq!(1, error_count(source));
+        let source =
+            "let f =\n  let r = ref [] in\n  fun x -> r := [x]\nlet a = f 1\nlet b = f true\n";
+        assert_eq!(1, error_count(source));
+        // A constructor applied to values is still generalized.
+        let source = "let f x = ref x\nlet xs = [f]\nlet a = (1 :: [], f 1, f true)\n";
+        assert_eq!("list ('a -> ref 'a)", type_of(source, "xs"));
+    }
+
+    #[test]
+    fn warns_about_discarded_values() {
+        let checked = test_util::check("let f x =\n  x + 1\n  ()\n  x\n");
+        assert_eq!(1, checked.session.warning_count());
+    }
+
+    #[test]
+    fn infers_exceptions() {
+        let source = "exception NotFound int\n\
+                      let find x =\n  try\n    if x > 0 then raise (NotFound x)\n    x\n  with\n  | NotFound n -> n\n  | DivisionByZero -> 0\n";
+        assert_eq!("int -> int", type_of(source, "find"));
+        assert_eq!("exn -> 'a", type_of("let fail e = raise e\n", "fail"));
+        assert_eq!(
+            1,
+            error_count("exception E int\nlet x = try 1 with | E -> 2\n")
+        );
+        assert_eq!(
+            1,
+            error_count("exception E\nlet x = try 1 with | E -> true\n")
+        );
+        assert_eq!(1, error_count("exception E 'a\n"));
+    }
+
+    #[test]
+    fn infers_operators_and_sections() {
+        let source = "let (|>) x f = f x\nlet y = 1 |> (+ 1) |> (=) 2\n";
+        assert_eq!("'a -> ('a -> 'b) -> 'b", type_of(source, "|>"));
+        assert_eq!("bool", type_of(source, "y"));
+        assert_eq!("int -> int", type_of("let f = (10 -)\n", "f"));
+        assert_eq!("list int -> list int", type_of("let f = (1 ::)\n", "f"));
+        let source = "let (<+>) xs ys = xs\nlet f = (<+> [1])\nlet g = (<+>)\n";
+        assert_eq!("'a -> 'a", type_of(source, "f"));
+        assert_eq!("'a -> 'b -> 'a", type_of(source, "g"));
+        assert_eq!(1, error_count("let x = 1 |> 2\n"));
+        assert_eq!(1, error_count("let (|>) x = x\nlet y = 1 |> 2\n"));
+    }
+
+    #[test]
+    fn parses_fixity_declarations() {
+        // Without a declaration, the operator binds tighter than `+`.
+        let source = "let (++) a b = a * b\nlet x = (1 + 2 ++ 3, 1 ++ 2 + 3)\n";
+        assert_eq!("int * int", type_of(source, "x"));
+        let source = "infixr 0 $\nlet ($) f x = f x\nlet not b = if b then false else true\n\
+                      let x = not $ not $ 1 = 1\n";
+        assert_eq!("bool", type_of(source, "x"));
+        assert_eq!(
+            1,
+            error_count("infix 4 ==\nlet (==) a b = a = b\nlet x = 1 == 2 == 3\n")
+        );
+        assert_eq!(1, error_count("infixl 10 |>\n"));
+        assert_eq!(1, error_count("infixl 1 +\n"));
+        assert_eq!(1, error_count("let (+) a b = a\n"));
+    }
+
+    #[test]
+    fn infers_prelude_pipelines() {
+        let source = "let add x y = x + y\nlet x = 1 |> add 2 |> (=) 3\n";
+        assert_eq!("bool", type_of(source, "x"));
+        assert_eq!("int", type_of("let x = (+) 1 <| 2 * 3\n", "x"));
+        let source = "let twice f = f >> f\nlet x = twice (fun x -> x * 2) 1\n";
+        assert_eq!("('a -> 'a) -> 'a -> 'a", type_of(source, "twice"));
+        assert_eq!("int", type_of(source, "x"));
+        assert_eq!(1, error_count("let apply f x = f x\nlet y = 1 |> apply\n"));
+        assert_eq!(1, error_count("let apply f x = f x\nlet y = apply <| 1\n"));
+        assert_eq!(1, error_count("let y = 1 |> 2\n"));
+        assert_eq!(1, error_count("let y = true |> (+) 1\n"));
+    }
+
+    #[test]
+    fn checks_annotations() {
+        let source = "let f (x: int) (g: int -> 'a) : 'a = g x\n";
+        assert_eq!("int -> (int -> 'a) -> 'a", type_of(source, "f"));
+        assert_eq!(
+            "int * bool -> int",
+            type_of("type P = int * bool\nlet f (p: P) : int = 1\n", "f")
+        );
+        assert_eq!(1, error_count("let f (x: int) : bool = x\n"));
+        assert_eq!(1, error_count("let x = (true : int)\n"));
+    }
+
+    #[test]
+    fn annotated_type_variables_are_shared() {
+        assert_eq!(
+            1,
+            error_count("let f (x: 'a) (y: 'a) = x\nlet z = f 1 true\n")
+        );
+    }
+
+    #[test]
+    fn reports_mismatched_types() {
+        assert_eq!(1, error_count("let x = 1 + true\n"));
+        assert_eq!(1, error_count("let x = 1 2\n"));
+        assert_eq!(1, error_count("let f x = x x\n"));
+    }
+
+    #[test]
+    fn reports_wrong_type_arguments() {
+        assert_eq!(
+            1,
+            error_count("type Option a = None | Some a\nlet f (x: Option) = x\n")
+        );
+        assert_eq!(1, error_count("type A = A\n"));
+    }
+
+    #[test]
+    fn derives_types_of_formats() {
+        assert_eq!(
+            "int -> string -> string",
+            type_of("let f = sprintf \"x = %d, name = %s\"\n", "f")
+        );
+        assert_eq!(
+            "int -> bool -> string",
+            type_of("let f n b = $\"{n + 1}: {not b} {\"s\"}\"\n", "f")
+        );
+        assert_eq!("string -> string", type_of("let f x = $\"{x}\"\n", "f"));
+        assert_eq!(1, error_count("let s = sprintf \"%d\" true\n"));
+        assert_eq!(1, error_count("let s = sprintf \"%x\" 1\n"));
+        assert_eq!(1, error_count("let f format = sprintf format\n"));
+        assert_eq!(1, error_count("let s = $\"{[1]}\"\n"));
+    }
+
+    #[test]
+    fn reports_missing_record_fields() {
+        assert_eq!(
+            1,
+            error_count("type P = { x: int; y: int }\nlet p = { x = 1 }\n")
+        );
+    }
+}
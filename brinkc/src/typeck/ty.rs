@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use crate::resolve::{DefId, PrimTy, Resolutions};
+
+/// Identifies a type variable created during the inference.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TyVid(u32);
+
+impl TyVid {
+    pub fn new(index: usize) -> Self {
+        Self(index as u32)
+    }
+
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Ty {
+    /// A type variable, which is either still unknown or generalized.
+    Var(TyVid),
+    Prim(PrimTy),
+    /// A variant or record type applied to the type arguments.
+    Adt(DefId, Vec<Ty>),
+    Tuple(Vec<Ty>),
+    List(Box<Ty>),
+    Array(Box<Ty>),
+    Ref(Box<Ty>),
+    Function(Box<Ty>, Box<Ty>),
+    /// The type parameter of a type scheme or a type declaration, by index.
+    Generic(u32),
+    /// The type of an erroneous expression, which unifies with any type so
+    /// an error is reported only once.
+    Error,
+}
+
+impl Ty {
+    pub fn function(parameter: Ty, result: Ty) -> Ty {
+        Ty::Function(Box::new(parameter), Box::new(result))
+    }
+
+    /// Substitutes the generic parameters with the arguments.
+    pub fn subst(&self, arguments: &[Ty]) -> Ty {
+        match self {
+            Ty::Generic(index) => arguments[*index as usize].clone(),
+            Ty::Var(_) | Ty::Prim(_) | Ty::Error => self.clone(),
+            Ty::Adt(def, tys) => Ty::Adt(*def, tys.iter().map(|t| t.subst(arguments)).collect()),
+            Ty::Tuple(tys) => Ty::Tuple(tys.iter().map(|t| t.subst(arguments)).collect()),
+            Ty::List(element) => Ty::List(Box::new(element.subst(arguments))),
+            Ty::Array(element) => Ty::Array(Box::new(element.subst(arguments))),
+            Ty::Ref(element) => Ty::Ref(Box::new(element.subst(arguments))),
+            Ty::Function(parameter, result) => {
+                Ty::function(parameter.subst(arguments), result.subst(arguments))
+            }
+        }
+    }
+}
+
+/// A possibly polymorphic type of a definition, e.g. `'a -> 'a`, whose
+/// generic parameters are instantiated with fresh type variables on use.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Scheme {
+    pub generics: u32,
+    pub ty: Ty,
+}
+
+impl Scheme {
+    pub fn monomorphic(ty: Ty) -> Self {
+        Self { generics: 0, ty }
+    }
+}
+
+/// Formats the types the way they are written in the source code. The type
+/// variables are named `'a`, `'b`, ... in the order of appearance, and the
+/// names are shared by all the types printed with the same printer.
+pub struct TyPrinter<'a> {
+    resolutions: &'a Resolutions,
+    names: HashMap<TyVid, String>,
+}
+
+impl<'a> TyPrinter<'a> {
+    pub fn new(resolutions: &'a Resolutions) -> Self {
+        Self {
+            resolutions,
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn print(&mut self, ty: &Ty) -> String {
+        let mut output = String::new();
+        self.print_ty(ty, Precedence::Arrow, &mut output);
+        output
+    }
+
+    fn print_ty(&mut self, ty: &Ty, precedence: Precedence, output: &mut String) {
+        let own_precedence = match ty {
+            Ty::Function(..) => Precedence::Arrow,
+            Ty::Tuple(_) => Precedence::Tuple,
+            Ty::Adt(_, arguments) if !arguments.is_empty() => Precedence::Application,
+            Ty::List(_) | Ty::Array(_) | Ty::Ref(_) => Precedence::Application,
+            _ => Precedence::Atom,
+        };
+        if own_precedence < precedence {
+            output.push('(');
+            self.print_ty(ty, Precedence::Arrow, output);
+            output.push(')');
+            return;
+        }
+
+        match ty {
+            Ty::Var(vid) => {
+                let count = self.names.len();
+                let name = self
+                    .names
+                    .entry(*vid)
+                    .or_insert_with(|| variable_name(count as u32));
+                output.push_str(name);
+            }
+            Ty::Prim(prim_ty) => output.push_str(match prim_ty {
+                PrimTy::Int => "int",
+                PrimTy::Bool => "bool",
+                PrimTy::String => "string",
+                PrimTy::Unit => "unit",
+                PrimTy::List => "list",
+                PrimTy::Array => "array",
+                PrimTy::Ref => "ref",
+                PrimTy::Exn => "exn",
+            }),
+            Ty::Adt(def, arguments) => {
+                output.push_str(&self.resolutions.def(*def).name);
+                for argument in arguments {
+                    output.push(' ');
+                    self.print_ty(argument, Precedence::Atom, output);
+                }
+            }
+            Ty::List(element) => {
+                output.push_str("list ");
+                self.print_ty(element, Precedence::Atom, output);
+            }
+            Ty::Array(element) => {
+                output.push_str("array ");
+                self.print_ty(element, Precedence::Atom, output);
+            }
+            Ty::Ref(element) => {
+                output.push_str("ref ");
+                self.print_ty(element, Precedence::Atom, output);
+            }
+            Ty::Tuple(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        output.push_str(" * ");
+                    }
+                    self.print_ty(element, Precedence::Application, output);
+                }
+            }
+            Ty::Function(parameter, result) => {
+                self.print_ty(parameter, Precedence::Tuple, output);
+                output.push_str(" -> ");
+                self.print_ty(result, Precedence::Arrow, output);
+            }
+            Ty::Generic(index) => output.push_str(&variable_name(*index)),
+            Ty::Error => output.push('?'),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Precedence {
+    Arrow,
+    Tuple,
+    Application,
+    Atom,
+}
+
+fn variable_name(index: u32) -> String {
+    let letter = (b'a' + (index % 26) as u8) as char;
+    if index < 26 {
+        format!("'{}", letter)
+    } else {
+        format!("'{}{}", letter, index / 26)
+    }
+}
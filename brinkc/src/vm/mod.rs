@@ -0,0 +1,792 @@
+//! The virtual machine running the bytecode of `brinkc run`. It keeps the
+//! local variables and the temporary values of the active functions on one
+//! stack, with a frame of every active call, so the depth of the calls is
+//! not limited by the native stack. The calls in tail position replace the
+//! frame of the caller.
+
+use std::{
+    cell::{OnceCell, RefCell},
+    convert::TryFrom,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use crate::{
+    bytecode::{Constant, Instr, Module},
+    ir::{Con, PrimOp},
+    resolve::Builtin,
+    MAX_CALL_DEPTH,
+};
+
+pub mod value;
+
+use value::{Closure, Partial, Tag, Value};
+
+/// An exception which was raised and not handled by the program.
+#[derive(Debug)]
+pub struct UncaughtException {
+    /// The exception value, e.g. `NotFound 1`.
+    pub exception: String,
+    /// The functions active when the exception was raised, innermost call
+    /// first, with the locations of the instructions they were executing.
+    pub trace: Vec<(String, Option<String>)>,
+}
+
+impl UncaughtException {
+    /// Formats the exception with the stack trace, like the interpreter.
+    pub fn report(&self) -> String {
+        let mut report = format!("uncaught exception {}", self.exception);
+        for (function, location) in &self.trace {
+            match location {
+                Some(location) => report.push_str(&format!("\n  at {} ({})", function, location)),
+                None => report.push_str(&format!("\n  at {}", function)),
+            }
+        }
+        report
+    }
+}
+
+/// Why the program stopped before its main function returned.
+#[derive(Debug)]
+pub enum Failure {
+    Uncaught(UncaughtException),
+    /// The bytecode did what its checks cannot rule out, e.g. took a field
+    /// of an integer, as a corrupted file may.
+    InvalidBytecode(String),
+}
+
+impl Failure {
+    pub fn report(&self) -> String {
+        match self {
+            Failure::Uncaught(exception) => exception.report(),
+            Failure::InvalidBytecode(message) => format!("invalid bytecode: {}", message),
+        }
+    }
+}
+
+/// Runs the main function of the module, returning the values of the
+/// globals.
+pub fn run(module: &Module) -> Result<Vec<Value>, Failure> {
+    let mut machine = Machine {
+        module,
+        constants: module
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Constant::Int(value) => Value::Int(*value),
+                Constant::String(value) => Value::String(value.as_str().into()),
+            })
+            .collect(),
+        globals: vec![Value::Unit; module.globals.len()],
+        stack: Vec::new(),
+        frames: Vec::new(),
+        handlers: Vec::new(),
+    };
+    let main = Rc::new(Closure {
+        function: module.main,
+        captures: OnceCell::from(Vec::new()),
+    });
+    let mut result = machine.enter(main, 0, Vec::new(), false);
+    loop {
+        match result {
+            Err(Trap::Raise(exception)) => {
+                let handler = match machine.handlers.pop() {
+                    Some(handler) => handler,
+                    None => return Err(Failure::Uncaught(machine.uncaught(&exception))),
+                };
+                machine.frames.truncate(handler.frames);
+                machine.stack.truncate(handler.stack);
+                machine.frames.last_mut().unwrap().ip = handler.target as usize;
+                machine.stack.push(exception);
+            }
+            Err(Trap::Invalid(message)) => return Err(Failure::InvalidBytecode(message)),
+            Ok(()) => {}
+        }
+        result = machine.execute();
+        if result.is_ok() {
+            return Ok(machine.globals);
+        }
+    }
+}
+
+struct Machine<'m> {
+    module: &'m Module,
+    constants: Vec<Value>,
+    globals: Vec<Value>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+}
+
+/// An active call of a function.
+struct Frame {
+    closure: Rc<Closure>,
+    /// The index of the next instruction.
+    ip: usize,
+    /// The index of the first local variable on the stack.
+    base: usize,
+    /// The arguments the result of the function is applied to, given to
+    /// the function beyond its arity.
+    pending: Vec<Value>,
+}
+
+/// An exception handler of a `try`, with the depths of the frames and of
+/// the stack it restores.
+struct Handler {
+    frames: usize,
+    stack: usize,
+    target: u32,
+}
+
+/// Stops the execution: an exception was raised, which a handler may
+/// catch, or the bytecode is invalid, which stops the machine.
+enum Trap {
+    Raise(Value),
+    Invalid(String),
+}
+
+/// The result of the execution, which is the trap if one stopped it.
+type Exec = Result<(), Trap>;
+
+fn invalid<T>(message: &str) -> Result<T, Trap> {
+    Err(Trap::Invalid(message.to_string()))
+}
+
+impl<'m> Machine<'m> {
+    /// Executes the instructions until the main function returns or an
+    /// exception is raised.
+    fn execute(&mut self) -> Exec {
+        let module = self.module;
+        while let Some(frame) = self.frames.last_mut() {
+            let function = &module.functions[frame.closure.function as usize];
+            let instr = function.code[frame.ip];
+            frame.ip += 1;
+            let base = frame.base;
+            match instr {
+                Instr::Const(constant) => {
+                    self.stack.push(self.constants[constant as usize].clone())
+                }
+                Instr::Unit => self.stack.push(Value::Unit),
+                Instr::Bool(value) => self.stack.push(Value::Bool(value)),
+                Instr::Local(slot) => self.stack.push(self.stack[base + slot as usize].clone()),
+                Instr::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[base + slot as usize] = value;
+                }
+                Instr::Capture(index) => {
+                    let value = match frame.closure.captures.get() {
+                        Some(captures) => captures[index as usize].clone(),
+                        None => return invalid("capture of an uninitialized closure"),
+                    };
+                    self.stack.push(value);
+                }
+                Instr::Callee => {
+                    let closure = frame.closure.clone();
+                    self.stack.push(Value::Closure(closure));
+                }
+                Instr::Global(global) => self.stack.push(self.globals[global as usize].clone()),
+                Instr::SetGlobal(global) => self.globals[global as usize] = self.pop(),
+                Instr::Closure(function) => {
+                    let captures = self.pop_n(module.functions[function as usize].captures);
+                    self.stack.push(Value::Closure(Rc::new(Closure {
+                        function,
+                        captures: OnceCell::from(captures),
+                    })));
+                }
+                Instr::RecClosure(function) => self.stack.push(Value::Closure(Rc::new(Closure {
+                    function,
+                    captures: OnceCell::new(),
+                }))),
+                Instr::InitClosure(slot) => {
+                    let closure = match &self.stack[base + slot as usize] {
+                        Value::Closure(closure) => closure.clone(),
+                        _ => return invalid("initialization of a non-closure"),
+                    };
+                    let captures = self.pop_n(module.functions[closure.function as usize].captures);
+                    if closure.captures.set(captures).is_err() {
+                        return invalid("initialization of an initialized closure");
+                    }
+                }
+                Instr::Pop => {
+                    self.pop();
+                }
+                Instr::Apply(count) | Instr::TailApply(count) => {
+                    self.apply(count as usize, matches!(instr, Instr::TailApply(_)))?;
+                }
+                Instr::Call(function) | Instr::TailCall(function) => {
+                    let arity = module.functions[function as usize].arity as usize;
+                    let closure = match self.stack.remove(self.stack.len() - arity - 1) {
+                        Value::Closure(closure) if closure.function == function => closure,
+                        _ => return invalid("call of another function"),
+                    };
+                    let tail = matches!(instr, Instr::TailCall(_));
+                    self.enter(closure, arity, Vec::new(), tail)?;
+                }
+                Instr::Return => {
+                    let value = self.pop();
+                    self.return_value(value)?;
+                }
+                Instr::Jump(target) => frame.ip = target as usize,
+                Instr::JumpIfFalse(target) => {
+                    if !self.pop_bool()? {
+                        self.frames.last_mut().unwrap().ip = target as usize;
+                    }
+                }
+                Instr::JumpIfTrue(target) => {
+                    if self.pop_bool()? {
+                        self.frames.last_mut().unwrap().ip = target as usize;
+                    }
+                }
+                Instr::Prim(op) => {
+                    let start = self.stack.len() - op.arity();
+                    let value = prim(op, &self.stack[start..])?;
+                    self.stack.truncate(start);
+                    self.stack.push(value);
+                }
+                Instr::Construct(con, count) => {
+                    let value = self.construct(con, count as usize);
+                    self.stack.push(value);
+                }
+                Instr::Field(index) => {
+                    let index = index as usize;
+                    let value = match self.pop() {
+                        Value::Tuple(fields)
+                        | Value::Array(fields)
+                        | Value::Record(_, fields)
+                        | Value::Variant(_, fields) => match fields.get(index) {
+                            Some(field) => field.clone(),
+                            None => return invalid("field out of the bounds of the value"),
+                        },
+                        Value::Cons(cell) if index == 0 => cell.0.clone(),
+                        Value::Cons(cell) if index == 1 => cell.1.clone(),
+                        _ => return invalid("field of a value without fields"),
+                    };
+                    self.stack.push(value);
+                }
+                Instr::IsCon(con) => {
+                    let value = self.pop();
+                    let matches = match (con, &value) {
+                        (Con::Tuple(_), _) | (Con::Record(_), _) => true,
+                        (Con::Nil, value) => matches!(value, Value::Nil),
+                        (Con::Cons, value) => matches!(value, Value::Cons(_)),
+                        (Con::Array(n), Value::Array(elements)) => elements.len() == n,
+                        (Con::Constructor(def), Value::Variant(tag, _)) => {
+                            *tag == Tag::Def(def.as_usize() as u32)
+                        }
+                        (Con::Exception(builtin), Value::Variant(tag, _)) => {
+                            *tag == Tag::Builtin(builtin)
+                        }
+                        _ => return invalid("constructor test of a value of another type"),
+                    };
+                    self.stack.push(Value::Bool(matches));
+                }
+                Instr::PushHandler(target) => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    stack: self.stack.len(),
+                    target,
+                }),
+                Instr::PopHandler => {
+                    self.handlers.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn pop_n(&mut self, count: u32) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count as usize)
+    }
+
+    /// Builds the value of the fields on the top of the stack, which it
+    /// pops. The checks of the bytecode ensure a list cell gets its head
+    /// and its tail.
+    fn construct(&mut self, con: Con, count: usize) -> Value {
+        let mut fields = self.stack.drain(self.stack.len() - count..);
+        match con {
+            Con::Tuple(_) => Value::Tuple(fields.collect()),
+            Con::Record(def) => Value::Record(def.as_usize() as u32, fields.collect()),
+            Con::Constructor(def) => {
+                Value::Variant(Tag::Def(def.as_usize() as u32), fields.collect())
+            }
+            Con::Exception(builtin) => Value::Variant(Tag::Builtin(builtin), fields.collect()),
+            Con::Nil => Value::Nil,
+            Con::Cons => {
+                let head = fields.next().unwrap();
+                Value::Cons(Rc::new((head, fields.next().unwrap())))
+            }
+            Con::Array(_) => Value::Array(fields.collect()),
+        }
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, Trap> {
+        match self.pop() {
+            Value::Bool(value) => Ok(value),
+            _ => invalid("condition of a non-boolean"),
+        }
+    }
+
+    /// Applies the function below the arguments on the top of the stack to
+    /// them. The arguments may be fewer or more than the function takes.
+    fn apply(&mut self, count: usize, tail: bool) -> Exec {
+        let position = self.stack.len() - count - 1;
+        let closure = match self.stack.remove(position) {
+            Value::Closure(closure) => closure,
+            Value::Partial(partial) => {
+                let given = partial.arguments.iter().cloned();
+                self.stack.splice(position..position, given);
+                partial.closure.clone()
+            }
+            _ => return invalid("application of a non-function"),
+        };
+        let count = self.stack.len() - position;
+        let arity = self.module.functions[closure.function as usize].arity as usize;
+        if count < arity {
+            let arguments = self.stack.split_off(position);
+            let partial = Value::Partial(Rc::new(Partial { closure, arguments }));
+            if tail {
+                return self.return_value(partial);
+            }
+            self.stack.push(partial);
+            return Ok(());
+        }
+        let rest = self.stack.split_off(position + arity);
+        self.enter(closure, arity, rest, tail)
+    }
+
+    /// Starts running the function with the arguments on the top of the
+    /// stack, which become its first local variables, in a new frame or in
+    /// the frame of the caller for the calls in tail position.
+    fn enter(
+        &mut self,
+        closure: Rc<Closure>,
+        arity: usize,
+        mut pending: Vec<Value>,
+        tail: bool,
+    ) -> Exec {
+        let locals = self.module.functions[closure.function as usize].locals as usize;
+        let start = self.stack.len() - arity;
+        let base = if tail {
+            let frame = self.frames.last_mut().unwrap();
+            self.stack.drain(frame.base..start);
+            // The result of the callee is applied to its extra arguments
+            // before the ones of the caller.
+            pending.append(&mut frame.pending);
+            frame.pending = pending;
+            frame.closure = closure;
+            frame.ip = 0;
+            frame.base
+        } else {
+            if self.frames.len() >= MAX_CALL_DEPTH {
+                return Err(raise(Builtin::StackOverflow, None));
+            }
+            self.frames.push(Frame {
+                closure,
+                ip: 0,
+                base: start,
+                pending,
+            });
+            start
+        };
+        self.stack.resize(base + locals, Value::Unit);
+        Ok(())
+    }
+
+    /// Returns the value from the running function to its caller, applying
+    /// it to the pending arguments first.
+    fn return_value(&mut self, value: Value) -> Exec {
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        self.stack.push(value);
+        if frame.pending.is_empty() {
+            return Ok(());
+        }
+        let count = frame.pending.len();
+        self.stack.extend(frame.pending);
+        self.apply(count, false)
+    }
+
+    /// Builds the report of the exception from the active frames.
+    fn uncaught(&self, exception: &Value) -> UncaughtException {
+        let trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = &self.module.functions[frame.closure.function as usize];
+                let location = function
+                    .location(frame.ip - 1)
+                    .map(|location| self.module.locations[location as usize].clone());
+                (function.name.clone(), location)
+            })
+            .collect();
+        UncaughtException {
+            exception: exception.display(self.module),
+            trace,
+        }
+    }
+}
+
+/// Applies the primitive operation to its operands, which stay on the
+/// stack.
+fn prim(op: PrimOp, arguments: &[Value]) -> Result<Value, Trap> {
+    let mut arguments = arguments.iter();
+    let mut next = || arguments.next().unwrap();
+    let int = |value: &Value| match value {
+        Value::Int(value) => Ok(*value),
+        _ => invalid("integer operation on a non-integer"),
+    };
+    let string = |value: &Value| match value {
+        Value::String(value) => Ok(value.clone()),
+        _ => invalid("string operation on a non-string"),
+    };
+    let new_string = |value: &str| Value::String(value.into());
+
+    Ok(match op {
+        PrimOp::Add => Value::Int(int(next())?.wrapping_add(int(next())?)),
+        PrimOp::Subtract => Value::Int(int(next())?.wrapping_sub(int(next())?)),
+        PrimOp::Multiply => Value::Int(int(next())?.wrapping_mul(int(next())?)),
+        PrimOp::Divide => {
+            let (lhs, rhs) = (int(next())?, int(next())?);
+            if rhs == 0 {
+                return Err(raise(Builtin::DivisionByZero, None));
+            }
+            Value::Int(lhs.wrapping_div(rhs))
+        }
+        PrimOp::Negate => Value::Int(int(next())?.wrapping_neg()),
+        PrimOp::Less => Value::Bool(int(next())? < int(next())?),
+        PrimOp::LessEqual => Value::Bool(int(next())? <= int(next())?),
+        PrimOp::Greater => Value::Bool(int(next())? > int(next())?),
+        PrimOp::GreaterEqual => Value::Bool(int(next())? >= int(next())?),
+        PrimOp::Equal => Value::Bool(next().equals(next())),
+        PrimOp::NotEqual => Value::Bool(!next().equals(next())),
+        PrimOp::Deref => match next() {
+            Value::Ref(cell) => cell.borrow().clone(),
+            _ => return invalid("dereference of a non-reference"),
+        },
+        PrimOp::Assign => match next() {
+            Value::Ref(cell) => {
+                *cell.borrow_mut() = next().clone();
+                Value::Unit
+            }
+            _ => return invalid("assignment of a non-reference"),
+        },
+        PrimOp::Builtin(builtin) => match builtin {
+            Builtin::Ref => Value::Ref(Rc::new(RefCell::new(next().clone()))),
+            Builtin::Raise => return Err(Trap::Raise(next().clone())),
+            Builtin::Compare => match next().compare(next()) {
+                Some(ordering) => Value::Int(ordering as i64),
+                None => {
+                    return Err(raise(
+                        Builtin::InvalidArgument,
+                        Some("compare: functional value"),
+                    ))
+                }
+            },
+            Builtin::StringLength => Value::Int(string(next())?.len() as i64),
+            Builtin::StringGet => {
+                let value = string(next())?;
+                let index = int(next())?;
+                match usize::try_from(index)
+                    .ok()
+                    .and_then(|i| value.as_bytes().get(i))
+                {
+                    Some(byte) => Value::Int(i64::from(*byte)),
+                    None => return Err(raise(Builtin::InvalidArgument, Some("string_get"))),
+                }
+            }
+            Builtin::StringSub => {
+                let value = string(next())?;
+                let start = int(next())?;
+                let length = int(next())?;
+                let range = usize::try_from(start)
+                    .ok()
+                    .zip(usize::try_from(length).ok())
+                    .and_then(|(start, length)| value.get(start..start.checked_add(length)?));
+                match range {
+                    Some(sub) => new_string(sub),
+                    None => return Err(raise(Builtin::InvalidArgument, Some("string_sub"))),
+                }
+            }
+            Builtin::StringConcat => {
+                let mut value = string(next())?.to_string();
+                value.push_str(&string(next())?);
+                new_string(&value)
+            }
+            Builtin::StringConcatList => {
+                let mut value = String::new();
+                let mut list = next();
+                while let Value::Cons(cell) = list {
+                    value.push_str(&string(&cell.0)?);
+                    list = &cell.1;
+                }
+                new_string(&value)
+            }
+            Builtin::StringOfCharCode => {
+                match u32::try_from(int(next())?).ok().and_then(char::from_u32) {
+                    Some(c) => new_string(c.encode_utf8(&mut [0; 4])),
+                    None => {
+                        return Err(raise(Builtin::InvalidArgument, Some("string_of_char_code")))
+                    }
+                }
+            }
+            Builtin::StringOfInt => new_string(&int(next())?.to_string()),
+            Builtin::IntOfString => match string(next())?.parse() {
+                Ok(value) => Value::Int(value),
+                Err(_) => return Err(raise(Builtin::Failure, Some("int_of_string"))),
+            },
+            Builtin::PrintString => {
+                print!("{}", string(next())?);
+                let _ = io::stdout().flush();
+                Value::Unit
+            }
+            Builtin::EprintString => {
+                eprint!("{}", string(next())?);
+                Value::Unit
+            }
+            Builtin::ReadLine => {
+                let mut line = String::new();
+                match io::stdin().lock().read_line(&mut line) {
+                    Ok(0) => return Err(raise(Builtin::EndOfFile, None)),
+                    Ok(_) => {
+                        let end = line.trim_end_matches(&['\n', '\r'][..]).len();
+                        line.truncate(end);
+                        new_string(&line)
+                    }
+                    Err(error) => return Err(raise(Builtin::Failure, Some(&error.to_string()))),
+                }
+            }
+            Builtin::ReadFile => {
+                let path = string(next())?;
+                match std::fs::read_to_string(&*path) {
+                    Ok(contents) => new_string(&contents),
+                    Err(error) => {
+                        let message = format!("{}: {}", path, error);
+                        return Err(raise(Builtin::Failure, Some(&message)));
+                    }
+                }
+            }
+            Builtin::WriteFile => {
+                let path = string(next())?;
+                match std::fs::write(&*path, &*string(next())?) {
+                    Ok(()) => Value::Unit,
+                    Err(error) => {
+                        let message = format!("{}: {}", path, error);
+                        return Err(raise(Builtin::Failure, Some(&message)));
+                    }
+                }
+            }
+            builtin => return invalid(&format!("builtin `{}` in the bytecode", builtin.name())),
+        },
+    })
+}
+
+/// Raises a built-in exception, with the message if it takes one.
+fn raise(builtin: Builtin, message: Option<&str>) -> Trap {
+    let arguments = message.map(|message| Value::String(message.into()));
+    Trap::Raise(Value::Variant(
+        Tag::Builtin(builtin),
+        arguments.into_iter().collect::<Vec<_>>().into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bytecode, interpret, test_util};
+
+    /// Compiles the program at the optimization level to bytecode, which is
+    /// serialized and deserialized before it runs, and formats the final
+    /// value of the global or the report of the uncaught exception.
+    fn run_at(source: &str, name: &str, opt_level: u8) -> String {
+        let checked = test_util::check(source);
+        let program = checked.optimize(opt_level);
+        let module = bytecode::compile(&program, &checked.resolutions, &checked.session.source_map);
+        let module = {
+            let deserialized = bytecode::deserialize(&bytecode::serialize(&module)).unwrap();
+            assert_eq!(module, deserialized);
+            deserialized
+        };
+        test_util::on_deep_stack(|| match run(&module) {
+            Ok(globals) => {
+                let global = module.globals.iter().rposition(|global| global == name);
+                globals[global.unwrap()].display(&module)
+            }
+            Err(exception) => exception.report(),
+        })
+    }
+
+    /// Runs the program without and with the optimizations, which give the
+    /// same result. The stack traces differ once the functions are inlined,
+    /// so only the exceptions are compared.
+    fn run_value(source: &str, name: &str) -> String {
+        let unoptimized = run_at(source, name, 0);
+        let optimized = run_at(source, name, 2);
+        assert_eq!(unoptimized.lines().next(), optimized.lines().next());
+        unoptimized
+    }
+
+    /// Gets the report of the exception the interpreter does not handle, on
+    /// a deep stack.
+    fn interpret(source: &str) -> Option<String> {
+        let test_util::Checked {
+            session,
+            graph,
+            resolutions,
+            results,
+        } = test_util::check(source);
+        test_util::on_deep_stack(|| {
+            interpret::interpret(&session.source_map, &graph, &resolutions, &results)
+                .err()
+                .map(|exception| exception.report(&session.source_map))
+        })
+    }
+
+    #[test]
+    fn runs_functions_and_closures() {
+        let source = "let rec fibonacci n = if n < 2 then n else fibonacci (n - 1) + fibonacci (n - 2)\n\
+                      let add x y = x + y\nlet inc = add 1\n\
+                      let make n =\n  let rec count k = if k = 0 then [] else (k + n) :: count (k - 1)\n  count\n\
+                      let twice f x = f (f x)\n\
+                      let compose f g = fun x -> f (g x)\n\
+                      let x = (fibonacci 20, inc 2, twice inc 5, make 10 3, compose inc (add 2) 1, inc)\n";
+        assert_eq!(
+            "(6765, 3, 7, [13; 12; 11], 4, <fun>)",
+            run_value(source, "x")
+        );
+        let source = "let counter () =\n  let r = ref 0\n  fun () ->\n    r := !r + 1\n    !r\n\
+                      let next = counter ()\nlet a = next ()\nlet b = (next (), a)\n";
+        assert_eq!("(2, 1)", run_value(source, "b"));
+        let source = "let adder n =\n  let rec go k acc = if k = 0 then acc else go (k - 1) (acc + n)\n  go\n\
+                      let x = (adder 3 2 0, adder 1 4 5)\n";
+        assert_eq!("(6, 9)", run_value(source, "x"));
+    }
+
+    #[test]
+    fn runs_tail_calls_in_constant_space() {
+        // Far deeper than the call depth limit.
+        let source = "let rec sum i acc = if i = 0 then acc else sum (i - 1) (acc + i)\n\
+                      let rec count n = match n\n  | 0 -> 0\n  | n -> count (n - 1)\n\
+                      let apply f x = f x\n\
+                      let rec bounce n = if n = 0 then 0 else apply bounce (n - 1)\n\
+                      let rec even n =\n  let odd m = if m = 0 then false else @tailcall even (m - 1)\n  n = 0 || odd (n - 1)\n\
+                      let x = (sum 1000000 0, count 500000, bounce 500000, even 1000001)\n";
+        assert_eq!("(500000500000, 0, 0, false)", run_value(source, "x"));
+        let source =
+            "let rec down n = if n = 0 then 0 else 1 + down (n - 1)\nlet x = down 1000000\n";
+        assert!(run_value(source, "x").starts_with("uncaught exception StackOverflow"));
+    }
+
+    #[test]
+    fn runs_mutually_recursive_groups_in_constant_space() {
+        assert_eq!(
+            "\"true true 1000000\"",
+            run_value(&test_util::mutually_recursive_groups(1000000), "r")
+        );
+    }
+
+    #[test]
+    fn runs_loops_matches_and_exceptions() {
+        let source = "type Shape = Circle int | Square int int | Empty\n\
+                      let area s =\n  match s\n  | Circle r -> 3 * r * r\n  | Square w h -> w * h\n  | Empty -> 0\n\
+                      let name s =\n  match s\n  | \"a\" -> 1\n  | _ -> 2\n\
+                      let total =\n  let sum = ref 0\n  for i = 1 to 4 do\n    sum := !sum + i\n  for i = 2 downto 1 do\n    sum := !sum * i\n  let n = ref 3\n  while !n > 0 do\n    n := !n - 1\n  !sum + !n\n\
+                      exception Invalid int\n\
+                      let r = try 1 / 0 with | DivisionByZero -> -1\n\
+                      let s = try raise (Invalid 4) with | Invalid n -> n\n\
+                      let t = try (try raise (Invalid 1) with | DivisionByZero -> 0) with | Invalid n -> n + 1\n\
+                      let x = (area (Circle 2) + area (Square 2 3) + area Empty, name \"a\" + name \"b\", total, r, s, t)\n";
+        assert_eq!("(18, 3, 20, -1, 4, 2)", run_value(source, "x"));
+        let source = "type P = { x: int; y: string }\nlet p = { x = 1; y = \"a\" }\n\
+                      let x = ({ p with x = 2 }, [|1; 2|], compare [1; 2] [1; 3], string_sub \"hello\" 1 3)\n";
+        assert_eq!(
+            "({ x = 2; y = \"a\" }, [|1; 2|], -1, \"ell\")",
+            run_value(source, "x")
+        );
+    }
+
+    #[test]
+    fn reports_uncaught_exceptions_with_traces() {
+        let source = "exception Invalid int\n\
+                      let check x =\n  if x < 0 then raise (Invalid x)\n  x\n\
+                      let run x = check (x - 5) + 1\n\
+                      let y = run 3\n";
+        assert_eq!(
+            "uncaught exception Invalid (-2)\n  at check (main.bk:3:17)\n  at run (main.bk:5:13)\n  at <main> (main.bk:6:9)",
+            run_at(source, "y", 0)
+        );
+        let sources = [
+            "let f x =\n  match x\n  | 0 -> 1\nlet y = f 1\n",
+            "let y = int_of_string \"1x\"\n",
+            "let y = compare (fun x -> x) (fun x -> x)\n",
+            "let y = string_get \"a\" 3\n",
+        ];
+        for source in sources.iter() {
+            let report = run_value(source, "y");
+            let expected = interpret(source).unwrap();
+            assert_eq!(expected.lines().next(), report.lines().next());
+        }
+    }
+
+    #[test]
+    fn stops_on_invalid_bytecode() {
+        let main = |code| bytecode::Module {
+            constants: Vec::new(),
+            functions: vec![bytecode::Function {
+                name: "<main>".to_string(),
+                arity: 0,
+                captures: 0,
+                locals: 0,
+                code,
+                lines: Vec::new(),
+            }],
+            globals: Vec::new(),
+            main: 0,
+            tag_names: Vec::new(),
+            locations: Vec::new(),
+        };
+        let programs = [
+            (
+                vec![
+                    Instr::Unit,
+                    Instr::Unit,
+                    Instr::Construct(Con::Tuple(2), 2),
+                    Instr::Field(56064),
+                    Instr::Return,
+                ],
+                "invalid bytecode: field out of the bounds of the value",
+            ),
+            (
+                vec![
+                    Instr::Bool(true),
+                    Instr::Unit,
+                    Instr::Prim(PrimOp::Add),
+                    Instr::Return,
+                ],
+                "invalid bytecode: integer operation on a non-integer",
+            ),
+        ];
+        for (code, expected) in programs {
+            let module = bytecode::deserialize(&bytecode::serialize(&main(code))).unwrap();
+            assert_eq!(expected, run(&module).unwrap_err().report());
+        }
+    }
+
+    /// Runs the tests of the standard library modules, each of which binds
+    /// `result` to `"ok"` or raises `Failure` with the name of the failed
+    /// check.
+    #[test]
+    fn runs_stdlib_tests() {
+        for (name, source) in test_util::STDLIB_TESTS.iter() {
+            assert_eq!(
+                "\"ok\"",
+                run_value(source, "result"),
+                "stdlib test {}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    cmp::Ordering,
+    rc::Rc,
+};
+
+use crate::{bytecode::Module, resolve::Builtin};
+
+/// A value of the virtual machine. Like the values of the interpreter,
+/// they are immutable, except for the contents of the references, and
+/// cheap to clone.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    String(Rc<str>),
+    Unit,
+    Tuple(Rc<[Value]>),
+    Nil,
+    Cons(Rc<(Value, Value)>),
+    Array(Rc<[Value]>),
+    Ref(Rc<RefCell<Value>>),
+    /// A constructor or an exception applied to all of its arguments.
+    Variant(Tag, Rc<[Value]>),
+    /// A value of the record type, with the record type as the index of
+    /// its definition.
+    Record(u32, Rc<[Value]>),
+    Closure(Rc<Closure>),
+    /// A closure applied to fewer arguments than its arity.
+    Partial(Rc<Partial>),
+}
+
+/// Identifies the constructor of a variant value.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tag {
+    /// A constructor of a variant type or a user-defined exception, as the
+    /// index of its definition.
+    Def(u32),
+    Builtin(Builtin),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    /// The index of the function in the function table.
+    pub function: u32,
+    /// The captured values, stored once the closures of the recursive
+    /// functions capturing each other are all created.
+    pub captures: OnceCell<Vec<Value>>,
+}
+
+#[derive(Debug)]
+pub struct Partial {
+    pub closure: Rc<Closure>,
+    pub arguments: Vec<Value>,
+}
+
+impl Value {
+    /// Compares the values structurally. The functions are only equal to
+    /// themselves, and the references are equal if their contents are.
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Unit, Value::Unit) | (Value::Nil, Value::Nil) => true,
+            (Value::Tuple(a), Value::Tuple(b))
+            | (Value::Array(a), Value::Array(b))
+            | (Value::Record(_, a), Value::Record(_, b)) => elements_equal(a, b),
+            (Value::Cons(a), Value::Cons(b)) => a.0.equals(&b.0) && a.1.equals(&b.1),
+            (Value::Ref(a), Value::Ref(b)) => a.borrow().equals(&b.borrow()),
+            (Value::Variant(a, a_arguments), Value::Variant(b, b_arguments)) => {
+                a == b && elements_equal(a_arguments, b_arguments)
+            }
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Partial(a), Value::Partial(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Orders the values structurally, the way the interpreter does.
+    /// Returns `None` if the values contain a function.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Unit, Value::Unit) | (Value::Nil, Value::Nil) => Some(Ordering::Equal),
+            (Value::Nil, Value::Cons(_)) => Some(Ordering::Less),
+            (Value::Cons(_), Value::Nil) => Some(Ordering::Greater),
+            (Value::Cons(a), Value::Cons(b)) => match a.0.compare(&b.0)? {
+                Ordering::Equal => a.1.compare(&b.1),
+                ordering => Some(ordering),
+            },
+            (Value::Tuple(a), Value::Tuple(b))
+            | (Value::Array(a), Value::Array(b))
+            | (Value::Record(_, a), Value::Record(_, b)) => compare_elements(a, b),
+            (Value::Ref(a), Value::Ref(b)) => a.borrow().compare(&b.borrow()),
+            (Value::Variant(a, a_arguments), Value::Variant(b, b_arguments)) => {
+                match tag_rank(*a).cmp(&tag_rank(*b)) {
+                    Ordering::Equal => compare_elements(a_arguments, b_arguments),
+                    ordering => Some(ordering),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Formats the value the way it would be written in the source code,
+    /// with the names of the constructors and the fields in the module.
+    pub fn display(&self, module: &Module) -> String {
+        let mut output = String::new();
+        self.write(module, false, &mut output);
+        output
+    }
+
+    fn write(&self, module: &Module, nested: bool, output: &mut String) {
+        match self {
+            Value::Int(value) if nested && *value < 0 => output.push_str(&format!("({})", value)),
+            Value::Int(value) => output.push_str(&value.to_string()),
+            Value::Bool(value) => output.push_str(&value.to_string()),
+            Value::String(value) => write_string(value, output),
+            Value::Unit => output.push_str("()"),
+            Value::Tuple(elements) => {
+                output.push('(');
+                write_separated(elements, ", ", module, output);
+                output.push(')');
+            }
+            Value::Nil | Value::Cons(_) => {
+                let mut elements = Vec::new();
+                let mut current = self;
+                while let Value::Cons(cell) = current {
+                    elements.push(cell.0.clone());
+                    current = &cell.1;
+                }
+                output.push('[');
+                write_separated(&elements, "; ", module, output);
+                output.push(']');
+            }
+            Value::Array(elements) => {
+                output.push_str("[|");
+                write_separated(elements, "; ", module, output);
+                output.push_str("|]");
+            }
+            Value::Ref(value) => {
+                if nested {
+                    output.push('(');
+                }
+                output.push_str("ref ");
+                value.borrow().write(module, true, output);
+                if nested {
+                    output.push(')');
+                }
+            }
+            Value::Variant(tag, arguments) => {
+                let parenthesize = nested && !arguments.is_empty();
+                if parenthesize {
+                    output.push('(');
+                }
+                output.push_str(match tag {
+                    Tag::Def(def) => &module.tag_names[*def as usize],
+                    Tag::Builtin(builtin) => builtin.name(),
+                });
+                for argument in arguments.iter() {
+                    output.push(' ');
+                    argument.write(module, true, output);
+                }
+                if parenthesize {
+                    output.push(')');
+                }
+            }
+            Value::Record(owner, fields) => {
+                let names = module.tag_names[*owner as usize].split(' ');
+                output.push_str("{ ");
+                for (i, (name, value)) in names.zip(fields.iter()).enumerate() {
+                    if i > 0 {
+                        output.push_str("; ");
+                    }
+                    output.push_str(name);
+                    output.push_str(" = ");
+                    value.write(module, false, output);
+                }
+                output.push_str(" }");
+            }
+            Value::Closure(_) | Value::Partial(_) => output.push_str("<fun>"),
+        }
+    }
+}
+
+fn elements_equal(a: &[Value], b: &[Value]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals(b))
+}
+
+/// Compares the sequences lexicographically; the shorter prefix is less.
+fn compare_elements(a: &[Value], b: &[Value]) -> Option<Ordering> {
+    for (a, b) in a.iter().zip(b) {
+        match a.compare(b)? {
+            Ordering::Equal => {}
+            ordering => return Some(ordering),
+        }
+    }
+    Some(a.len().cmp(&b.len()))
+}
+
+/// Orders the constructors of a type by their definitions, before the
+/// built-in exceptions.
+fn tag_rank(tag: Tag) -> (usize, usize) {
+    match tag {
+        Tag::Def(def) => (0, def as usize),
+        Tag::Builtin(builtin) => (1, builtin as usize),
+    }
+}
+
+/// Writes the string as a literal, quoted and escaped.
+fn write_string(value: &str, output: &mut String) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            '\r' => output.push_str("\\r"),
+            '\\' => output.push_str("\\\\"),
+            '"' => output.push_str("\\\""),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+fn write_separated(values: &[Value], separator: &str, module: &Module, output: &mut String) {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            output.push_str(separator);
+        }
+        value.write(module, false, output);
+    }
+}